@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::{OrgId, ProjectId, Scope};
+use crate::{IpCidr, OrgId, ProjectId, Scope};
 
 pub type ApiKeyId = Uuid;
 
@@ -20,6 +20,29 @@ pub struct ApiKey {
     pub created_at: DateTime<Utc>,
     pub last_used_at: Option<DateTime<Utc>>,
     pub expires_at: Option<DateTime<Utc>>,
+    /// Only ingest spans, whatever `scopes` says. For keys shipped inside
+    /// edge or agent deployments, where a leaked key mustn't read traces.
+    #[serde(default)]
+    pub ingest_only: bool,
+    /// Networks the key may be used from; empty allows any.
+    #[serde(default)]
+    pub allowed_ips: Vec<IpCidr>,
+}
+
+impl ApiKey {
+    /// The scopes a request made with this key gets. An ingest-only key
+    /// that may write traces gets `Scope::Ingest` alone, which no route but
+    /// ingest accepts.
+    pub fn effective_scopes(&self) -> Vec<Scope> {
+        if !self.ingest_only {
+            return self.scopes.clone();
+        }
+        if self.scopes.iter().any(|s| Scope::ingest().contains(s)) {
+            vec![Scope::Ingest]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 /// Result of generating a new API key
@@ -81,6 +104,8 @@ pub fn generate_api_key(
         created_at: now,
         last_used_at: None,
         expires_at: None,
+        ingest_only: false,
+        allowed_ips: Vec::new(),
     };
 
     (generated, stored)
@@ -157,6 +182,30 @@ mod tests {
         assert!(!verify_api_key("wrong_key", &stored.key_hash));
     }
 
+    #[test]
+    fn test_ingest_only_scopes() {
+        let (_, mut stored) = generate_api_key(
+            Uuid::now_v7(),
+            Uuid::now_v7(),
+            "Edge".to_string(),
+            Scope::default_sdk(),
+        );
+        stored.ingest_only = true;
+        assert_eq!(stored.effective_scopes(), vec![Scope::Ingest]);
+
+        let ctx = crate::AuthContext::from_api_key(
+            stored.org_id,
+            stored.project_id,
+            stored.effective_scopes(),
+        );
+        assert!(ctx.can_ingest());
+        assert!(!ctx.can_write_traces());
+        assert!(!ctx.can_read_traces());
+
+        stored.scopes = Scope::read_only();
+        assert!(stored.effective_scopes().is_empty());
+    }
+
     #[test]
     fn test_sha256_hash_format() {
         let hash = hash_api_key("tw_sk_test123");
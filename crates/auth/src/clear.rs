@@ -0,0 +1,147 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AuthError, OrgId, ProjectId};
+
+/// Audience of clear confirmation tokens, so they can't pass as session,
+/// share, or feedback tokens or the other way around.
+const AUDIENCE: &str = "clear";
+
+/// JWT claims for clear confirmation tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearClaims {
+    pub aud: String,
+    /// Token ID
+    pub jti: String,
+    /// Organization ID
+    pub org: String,
+    /// Project ID
+    pub project: String,
+    /// What the confirmed clear removes, as chosen by the caller
+    pub scope: String,
+    /// Issued at
+    pub iat: i64,
+    /// Expiration
+    pub exp: i64,
+}
+
+/// Parsed clear confirmation token
+#[derive(Debug, Clone)]
+pub struct ClearToken {
+    pub id: Uuid,
+    pub org_id: OrgId,
+    pub project_id: ProjectId,
+    pub scope: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Create a token that confirms clearing `scope` from one project until it
+/// expires.
+pub fn create_clear_token(
+    org_id: OrgId,
+    project_id: ProjectId,
+    scope: &str,
+    ttl: Duration,
+    secret: &[u8],
+) -> Result<(String, ClearToken), AuthError> {
+    let now = Utc::now();
+    let token = ClearToken {
+        id: Uuid::now_v7(),
+        org_id,
+        project_id,
+        scope: scope.to_string(),
+        expires_at: now + ttl,
+    };
+
+    let claims = ClearClaims {
+        aud: AUDIENCE.to_string(),
+        jti: token.id.to_string(),
+        org: org_id.to_string(),
+        project: project_id.to_string(),
+        scope: token.scope.clone(),
+        iat: now.timestamp(),
+        exp: token.expires_at.timestamp(),
+    };
+
+    let encoded = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret),
+    )
+    .map_err(|_| AuthError::InvalidToken)?;
+    Ok((encoded, token))
+}
+
+/// Verify and decode a clear confirmation token
+pub fn verify_clear_token(token: &str, secret: &[u8]) -> Result<ClearToken, AuthError> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    // Confirmation tokens live for minutes; the default leeway would
+    // stretch that noticeably
+    validation.leeway = 0;
+    let token_data = decode::<ClearClaims>(token, &DecodingKey::from_secret(secret), &validation)
+        .map_err(|e| {
+            if e.kind() == &jsonwebtoken::errors::ErrorKind::ExpiredSignature {
+                AuthError::ExpiredToken
+            } else {
+                AuthError::InvalidToken
+            }
+        })?;
+
+    let claims = token_data.claims;
+    let parse = |s: &str| s.parse::<Uuid>().map_err(|_| AuthError::InvalidToken);
+
+    Ok(ClearToken {
+        id: parse(&claims.jti)?,
+        org_id: parse(&claims.org)?,
+        project_id: parse(&claims.project)?,
+        scope: claims.scope,
+        expires_at: DateTime::from_timestamp(claims.exp, 0).ok_or(AuthError::InvalidToken)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::generate_secret;
+    use crate::share::create_share_token;
+
+    #[test]
+    fn test_clear_roundtrip() {
+        let secret = generate_secret();
+        let (org_id, project_id) = (Uuid::now_v7(), Uuid::now_v7());
+
+        let (token, issued) =
+            create_clear_token(org_id, project_id, "spans", Duration::minutes(5), &secret)
+                .unwrap();
+        let parsed = verify_clear_token(&token, &secret).unwrap();
+
+        assert_eq!(parsed.id, issued.id);
+        assert_eq!(parsed.org_id, org_id);
+        assert_eq!(parsed.project_id, project_id);
+        assert_eq!(parsed.scope, "spans");
+        assert!(verify_clear_token(&token, &generate_secret()).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_foreign_tokens_are_rejected() {
+        let secret = generate_secret();
+        let ids = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+
+        let (expired, _) =
+            create_clear_token(ids.0, ids.1, "all", Duration::seconds(-5), &secret).unwrap();
+        assert!(matches!(
+            verify_clear_token(&expired, &secret),
+            Err(AuthError::ExpiredToken)
+        ));
+
+        let (share, _) =
+            create_share_token(ids.0, ids.1, ids.2, Duration::days(1), &secret).unwrap();
+        assert!(matches!(
+            verify_clear_token(&share, &secret),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
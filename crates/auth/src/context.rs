@@ -12,6 +12,9 @@ pub struct AuthContext {
     pub scopes: Vec<Scope>,
     pub is_local_mode: bool,
     pub is_api_key: bool,
+    /// Lookup prefix of the API key used, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_prefix: Option<String>,
 }
 
 impl AuthContext {
@@ -24,6 +27,7 @@ impl AuthContext {
             scopes: Scope::all(),
             is_local_mode: true,
             is_api_key: false,
+            api_key_prefix: None,
         }
     }
 
@@ -36,9 +40,16 @@ impl AuthContext {
             scopes,
             is_local_mode: false,
             is_api_key: true,
+            api_key_prefix: None,
         }
     }
 
+    /// Record which API key authenticated the request.
+    pub fn with_api_key_prefix(mut self, prefix: &str) -> Self {
+        self.api_key_prefix = Some(prefix.to_string());
+        self
+    }
+
     /// Create context from session (dashboard user)
     pub fn from_session(
         org_id: OrgId,
@@ -53,6 +64,7 @@ impl AuthContext {
             scopes,
             is_local_mode: false,
             is_api_key: false,
+            api_key_prefix: None,
         }
     }
 
@@ -71,6 +83,12 @@ impl AuthContext {
         self.has_scope(Scope::TracesWrite)
     }
 
+    /// Check if context can send spans and traces, which ingest-only keys
+    /// can without `TracesWrite`
+    pub fn can_ingest(&self) -> bool {
+        Scope::ingest().into_iter().any(|s| self.has_scope(s))
+    }
+
     /// Check if context can read datasets
     pub fn can_read_datasets(&self) -> bool {
         self.has_scope(Scope::DatasetsRead)
@@ -113,9 +131,21 @@ pub enum AuthError {
     #[error("session expired")]
     ExpiredSession,
 
+    #[error("invalid token")]
+    InvalidToken,
+
+    #[error("token expired")]
+    ExpiredToken,
+
     #[error("insufficient permissions: requires {required:?}")]
     InsufficientScope { required: Scope },
 
+    #[error("not a member of this organization")]
+    NotAMember,
+
+    #[error("API key not allowed from this address")]
+    IpNotAllowed,
+
     #[error("organization not found")]
     OrgNotFound,
 
@@ -132,9 +162,32 @@ impl AuthError {
             AuthError::ExpiredApiKey => 401,
             AuthError::InvalidSession => 401,
             AuthError::ExpiredSession => 401,
+            AuthError::InvalidToken => 401,
+            AuthError::ExpiredToken => 401,
             AuthError::InsufficientScope { .. } => 403,
+            AuthError::NotAMember => 403,
+            AuthError::IpNotAllowed => 403,
             AuthError::OrgNotFound => 404,
             AuthError::UserNotFound => 404,
         }
     }
+
+    /// Machine-readable code for API error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingAuth => "missing_auth",
+            AuthError::InvalidFormat => "invalid_format",
+            AuthError::InvalidApiKey => "invalid_api_key",
+            AuthError::ExpiredApiKey => "expired_api_key",
+            AuthError::InvalidSession => "invalid_session",
+            AuthError::ExpiredSession => "expired_session",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::ExpiredToken => "expired_token",
+            AuthError::InsufficientScope { .. } => "insufficient_scope",
+            AuthError::NotAMember => "not_a_member",
+            AuthError::IpNotAllowed => "ip_not_allowed",
+            AuthError::OrgNotFound => "org_not_found",
+            AuthError::UserNotFound => "user_not_found",
+        }
+    }
 }
@@ -0,0 +1,140 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AuthError, OrgId, ProjectId};
+
+/// Audience of feedback tokens, so they can't pass as session tokens or
+/// the other way around.
+const AUDIENCE: &str = "feedback";
+
+const FEEDBACK_DURATION_DAYS: i64 = 30;
+
+/// JWT claims for feedback tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackClaims {
+    pub aud: String,
+    /// Token ID; feedback sent with the same token replaces earlier feedback
+    pub jti: String,
+    /// Organization ID
+    pub org: String,
+    /// Project ID
+    pub project: String,
+    /// Span the feedback is about
+    pub span: String,
+    /// Trace of that span
+    pub trace: String,
+    /// Issued at
+    pub iat: i64,
+    /// Expiration
+    pub exp: i64,
+}
+
+/// Parsed feedback token
+#[derive(Debug, Clone)]
+pub struct FeedbackToken {
+    pub id: Uuid,
+    pub org_id: OrgId,
+    pub project_id: ProjectId,
+    pub span_id: Uuid,
+    pub trace_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Create a token that lets an end user send feedback on one span without
+/// an API key.
+pub fn create_feedback_token(
+    org_id: OrgId,
+    project_id: ProjectId,
+    span_id: Uuid,
+    trace_id: Uuid,
+    secret: &[u8],
+) -> Result<String, AuthError> {
+    let now = Utc::now();
+    let exp = now + Duration::days(FEEDBACK_DURATION_DAYS);
+
+    let claims = FeedbackClaims {
+        aud: AUDIENCE.to_string(),
+        jti: Uuid::now_v7().to_string(),
+        org: org_id.to_string(),
+        project: project_id.to_string(),
+        span: span_id.to_string(),
+        trace: trace_id.to_string(),
+        iat: now.timestamp(),
+        exp: exp.timestamp(),
+    };
+
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret),
+    )
+    .map_err(|_| AuthError::InvalidToken)
+}
+
+/// Verify and decode a feedback token
+pub fn verify_feedback_token(token: &str, secret: &[u8]) -> Result<FeedbackToken, AuthError> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    let token_data = decode::<FeedbackClaims>(token, &DecodingKey::from_secret(secret), &validation)
+        .map_err(|e| {
+            if e.kind() == &jsonwebtoken::errors::ErrorKind::ExpiredSignature {
+                AuthError::ExpiredToken
+            } else {
+                AuthError::InvalidToken
+            }
+        })?;
+
+    let claims = token_data.claims;
+    let parse = |s: &str| s.parse::<Uuid>().map_err(|_| AuthError::InvalidToken);
+
+    Ok(FeedbackToken {
+        id: parse(&claims.jti)?,
+        org_id: parse(&claims.org)?,
+        project_id: parse(&claims.project)?,
+        span_id: parse(&claims.span)?,
+        trace_id: parse(&claims.trace)?,
+        expires_at: DateTime::from_timestamp(claims.exp, 0).ok_or(AuthError::InvalidToken)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{create_session, generate_secret, verify_session};
+
+    #[test]
+    fn test_feedback_roundtrip() {
+        let secret = generate_secret();
+        let (org_id, project_id) = (Uuid::now_v7(), Uuid::now_v7());
+        let (span_id, trace_id) = (Uuid::now_v7(), Uuid::now_v7());
+
+        let token = create_feedback_token(org_id, project_id, span_id, trace_id, &secret).unwrap();
+        let parsed = verify_feedback_token(&token, &secret).unwrap();
+
+        assert_eq!(parsed.org_id, org_id);
+        assert_eq!(parsed.project_id, project_id);
+        assert_eq!(parsed.span_id, span_id);
+        assert_eq!(parsed.trace_id, trace_id);
+        assert!(matches!(
+            verify_feedback_token(&token, &generate_secret()),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn test_tokens_are_not_interchangeable() {
+        let secret = generate_secret();
+        let ids = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+
+        let feedback = create_feedback_token(ids.0, ids.1, ids.2, ids.3, &secret).unwrap();
+        assert!(verify_session(&feedback, &secret).is_err());
+
+        let session = create_session(ids.0, ids.1, ids.2, vec![], &secret).unwrap();
+        assert!(matches!(
+            verify_feedback_token(&session, &secret),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
@@ -3,18 +3,26 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 pub mod api_key;
+pub mod cidr;
+pub mod clear;
 pub mod context;
 pub mod email;
+pub mod feedback;
 pub mod middleware;
 pub mod session;
+pub mod share;
 pub mod store;
 
 // Re-exports
 pub use api_key::{ApiKey, ApiKeyId, generate_api_key, hash_api_key, verify_api_key};
+pub use cidr::{InvalidCidr, IpCidr};
+pub use clear::{ClearToken, create_clear_token, verify_clear_token};
 pub use context::{AuthContext, AuthError};
 pub use email::{Email, EmailError, EmailSender, NoopEmailSender, ResendSender};
-pub use middleware::{Auth, AuthConfig, ApiKeyLookup};
+pub use feedback::{FeedbackToken, create_feedback_token, verify_feedback_token};
+pub use middleware::{Auth, AuthConfig, ApiKeyGrant, ApiKeyLookup};
 pub use session::{SessionToken, create_session, verify_session};
+pub use share::{ShareToken, create_share_token, verify_share_token};
 pub use store::{AuthStore, AuthStoreError};
 
 // Re-export Project (defined in this file, no need for `use`)
@@ -116,7 +124,10 @@ pub struct User {
     pub name: Option<String>,
     #[serde(skip_serializing)]
     pub password_hash: Option<String>,
+    /// The org the user signed up into, where sessions start. Other orgs
+    /// they belong to are in their memberships.
     pub org_id: OrgId,
+    /// Role in `org_id`.
     pub role: Role,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -152,6 +163,29 @@ impl User {
     }
 }
 
+// --- Membership ---
+
+/// A user's place in an org. Users belong to their home org
+/// (`User::org_id`) and to any org whose invite they accept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub user_id: UserId,
+    pub org_id: OrgId,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Membership {
+    pub fn new(user_id: UserId, org_id: OrgId, role: Role) -> Self {
+        Self {
+            user_id,
+            org_id,
+            role,
+            created_at: Utc::now(),
+        }
+    }
+}
+
 // --- Role ---
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -175,6 +209,15 @@ impl Role {
     pub fn can_manage_org(&self) -> bool {
         matches!(self, Role::Owner)
     }
+
+    /// Scopes of sessions for a user with this role.
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Role::Owner | Role::Admin => Scope::all(),
+            Role::Member => Scope::default_sdk(),
+            Role::ReadOnly => Scope::read_only(),
+        }
+    }
 }
 
 // --- Scope ---
@@ -188,6 +231,9 @@ pub enum Scope {
     DatasetsWrite,
     AnalyticsRead,
     Admin,
+    /// Send spans and traces and nothing else. Held by ingest-only keys in
+    /// place of `TracesWrite`, so only the ingest routes accept them.
+    Ingest,
 }
 
 impl Scope {
@@ -219,6 +265,11 @@ impl Scope {
             Scope::AnalyticsRead,
         ]
     }
+
+    /// Scopes that let a caller send spans and traces.
+    pub fn ingest() -> Vec<Scope> {
+        vec![Scope::TracesWrite, Scope::Ingest]
+    }
 }
 
 // --- Plan ---
@@ -261,6 +312,16 @@ impl Plan {
         }
     }
 
+    /// How long audit log events are kept.
+    pub fn audit_log_days(&self) -> u32 {
+        match self {
+            Plan::Free => 30,
+            Plan::Pro => 90,
+            Plan::Team => 365,
+            Plan::Enterprise => 730,
+        }
+    }
+
     pub fn max_api_keys(&self) -> usize {
         match self {
             Plan::Free => 1,
@@ -269,6 +330,24 @@ impl Plan {
             Plan::Enterprise => usize::MAX,
         }
     }
+
+    pub fn includes(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::SemanticSearch | Feature::ConcurrencyAnalytics => *self != Plan::Free,
+        }
+    }
+}
+
+/// Capabilities gated by plan rather than by a numeric limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    SemanticSearch,
+    ConcurrencyAnalytics,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 2] = [Feature::SemanticSearch, Feature::ConcurrencyAnalytics];
 }
 
 // --- Invite ---
@@ -1,13 +1,14 @@
 use axum::{
     body::Body,
-    extract::State,
-    http::{header, Request, StatusCode},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use crate::{verify_api_key, AuthContext, AuthError, Scope};
+use crate::{verify_api_key, AuthContext, AuthError, IpCidr, Scope};
 
 /// Configuration for auth middleware
 #[derive(Clone)]
@@ -16,6 +17,8 @@ pub struct AuthConfig {
     pub local_mode: bool,
     /// JWT secret for session verification
     pub jwt_secret: Vec<u8>,
+    /// Proxies whose `X-Forwarded-For` is believed; empty trusts none.
+    pub trusted_proxies: Vec<IpCidr>,
 }
 
 impl Default for AuthConfig {
@@ -23,6 +26,7 @@ impl Default for AuthConfig {
         Self {
             local_mode: true,
             jwt_secret: vec![],
+            trusted_proxies: Vec::new(),
         }
     }
 }
@@ -36,6 +40,53 @@ impl AuthConfig {
         Self {
             local_mode: false,
             jwt_secret,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpCidr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+}
+
+/// An API key found by its prefix, before the full key is checked.
+#[derive(Debug, Clone)]
+pub struct ApiKeyGrant {
+    pub org_id: crate::OrgId,
+    pub project_id: crate::ProjectId,
+    pub key_hash: String,
+    /// Scopes after the key's restrictions, e.g. ingest-only, are applied.
+    pub scopes: Vec<Scope>,
+    /// Networks the key may be used from; empty allows any.
+    pub allowed_ips: Vec<IpCidr>,
+}
+
+impl ApiKeyGrant {
+    pub fn new(
+        org_id: crate::OrgId,
+        project_id: crate::ProjectId,
+        key_hash: String,
+        scopes: Vec<Scope>,
+    ) -> Self {
+        Self {
+            org_id,
+            project_id,
+            key_hash,
+            scopes,
+            allowed_ips: Vec::new(),
+        }
+    }
+
+    /// Check the client's address against the key's allowlist. A key with
+    /// an allowlist is refused when the address is unknown.
+    pub fn check_ip(&self, client_ip: Option<IpAddr>) -> Result<(), AuthError> {
+        if self.allowed_ips.is_empty() {
+            return Ok(());
+        }
+        match client_ip {
+            Some(ip) if self.allowed_ips.iter().any(|net| net.contains(ip)) => Ok(()),
+            _ => Err(AuthError::IpNotAllowed),
         }
     }
 }
@@ -43,8 +94,40 @@ impl AuthConfig {
 /// Trait for looking up API keys - implement this on your app state
 #[async_trait::async_trait]
 pub trait ApiKeyLookup: Send + Sync {
-    /// Returns (org_id, project_id, key_hash, scopes) for a given key prefix.
-    async fn lookup_api_key(&self, prefix: &str) -> Option<(crate::OrgId, crate::ProjectId, String, Vec<Scope>)>;
+    /// Returns the key stored under a given key prefix.
+    async fn lookup_api_key(&self, prefix: &str) -> Option<ApiKeyGrant>;
+}
+
+/// The client's address: the peer the connection came from, unless that
+/// peer is one of `trusted_proxies`. Then `X-Forwarded-For` is read from
+/// the right, skipping trusted hops, and the first other hop is the client.
+/// Anything left of that hop was written by the client and is ignored.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trusted_proxies: &[IpCidr],
+) -> Option<IpAddr> {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut client = peer?.ip();
+    if !trusted(client) {
+        return Some(client);
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    for hop in hops.into_iter().rev() {
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+        if !trusted(client) {
+            break;
+        }
+    }
+    Some(client)
 }
 
 /// Auth middleware that extracts AuthContext from request
@@ -64,7 +147,11 @@ where
     }
 
     // Try to extract auth from headers
-    let auth_result = extract_auth(&request, &config, lookup.as_ref()).await;
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0);
+    let auth_result = authenticate(request.headers(), None, peer, &config, lookup.as_ref()).await;
 
     match auth_result {
         Ok(ctx) => {
@@ -93,43 +180,47 @@ where
     AuthError::MissingAuth.into_response()
 }
 
-async fn extract_auth(
-    request: &Request<Body>,
+/// Resolve the caller from a bearer API key (`tw_sk_...`) or session
+/// token, a `session` cookie, or failing those `query_token`. An API key's
+/// allowlist is checked against the address `client_ip` finds from `peer`.
+pub async fn authenticate(
+    headers: &HeaderMap,
+    query_token: Option<String>,
+    peer: Option<SocketAddr>,
     config: &AuthConfig,
     lookup: &dyn ApiKeyLookup,
 ) -> Result<AuthContext, AuthError> {
-    // Check Authorization header
-    if let Some(auth_header) = request.headers().get(header::AUTHORIZATION) {
-        let auth_str = auth_header
-            .to_str()
-            .map_err(|_| AuthError::InvalidFormat)?;
-
-        // Bearer token (API key or JWT)
-        if let Some(token) = auth_str.strip_prefix("Bearer ") {
-            // API key format: tw_sk_...
-            if token.starts_with("tw_sk_") {
-                return validate_api_key(token, lookup).await;
-            }
-            // JWT session token
-            return validate_session(token, config);
-        }
-
-        return Err(AuthError::InvalidFormat);
+    if config.local_mode {
+        return Ok(AuthContext::local());
     }
 
-    // Check session cookie
-    if let Some(cookie) = request.headers().get(header::COOKIE) {
-        let cookie_str = cookie.to_str().map_err(|_| AuthError::InvalidFormat)?;
-        if let Some(session) = extract_session_cookie(cookie_str) {
-            return validate_session(&session, config);
-        }
-    }
+    let token = match headers.get(header::AUTHORIZATION) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .map(str::to_string)
+            .ok_or(AuthError::InvalidFormat)?,
+        None => headers
+            .get(header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(extract_session_cookie)
+            .or(query_token)
+            .ok_or(AuthError::MissingAuth)?,
+    };
 
-    Err(AuthError::MissingAuth)
+    // API key format: tw_sk_...
+    if token.starts_with("tw_sk_") {
+        let ip = client_ip(headers, peer, &config.trusted_proxies);
+        return validate_api_key(&token, ip, lookup).await;
+    }
+    // JWT session token
+    validate_session(&token, config)
 }
 
 async fn validate_api_key(
     key: &str,
+    client_ip: Option<IpAddr>,
     lookup: &dyn ApiKeyLookup,
 ) -> Result<AuthContext, AuthError> {
     // Extract prefix for lookup
@@ -140,17 +231,19 @@ async fn validate_api_key(
     };
 
     // Look up key by prefix
-    let (org_id, project_id, key_hash, scopes) = lookup
+    let grant = lookup
         .lookup_api_key(prefix)
         .await
         .ok_or(AuthError::InvalidApiKey)?;
 
     // Verify key hash
-    if !verify_api_key(key, &key_hash) {
+    if !verify_api_key(key, &grant.key_hash) {
         return Err(AuthError::InvalidApiKey);
     }
+    grant.check_ip(client_ip)?;
 
-    Ok(AuthContext::from_api_key(org_id, project_id, scopes))
+    Ok(AuthContext::from_api_key(grant.org_id, grant.project_id, grant.scopes)
+        .with_api_key_prefix(prefix))
 }
 
 fn validate_session(token: &str, config: &AuthConfig) -> Result<AuthContext, AuthError> {
@@ -182,17 +275,31 @@ impl IntoResponse for AuthError {
             | AuthError::InvalidApiKey
             | AuthError::ExpiredApiKey
             | AuthError::InvalidSession
-            | AuthError::ExpiredSession => StatusCode::UNAUTHORIZED,
-            AuthError::InsufficientScope { .. } => StatusCode::FORBIDDEN,
+            | AuthError::ExpiredSession
+            | AuthError::InvalidToken
+            | AuthError::ExpiredToken => StatusCode::UNAUTHORIZED,
+            AuthError::InsufficientScope { .. }
+            | AuthError::NotAMember
+            | AuthError::IpNotAllowed => StatusCode::FORBIDDEN,
             AuthError::OrgNotFound | AuthError::UserNotFound => StatusCode::NOT_FOUND,
         };
 
+        // RFC 7807 problem details, the same shape as the daemon's errors
         let body = serde_json::json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": self.to_string(),
+            "code": self.code(),
             "error": self.to_string(),
-            "code": format!("{:?}", self).to_lowercase().replace(" ", "_"),
         });
 
-        (status, axum::Json(body)).into_response()
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            axum::Json(body),
+        )
+            .into_response()
     }
 }
 
@@ -230,3 +337,47 @@ where
             .ok_or(AuthError::MissingAuth)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_client_ip_trusts_only_configured_proxies() {
+        let proxies: Vec<IpCidr> = vec!["10.0.0.0/8".parse().unwrap()];
+        let client = |headers: &HeaderMap, peer: &str| {
+            let peer = SocketAddr::new(peer.parse().unwrap(), 443);
+            client_ip(headers, Some(peer), &proxies)
+        };
+
+        // A spoofed header from a client that isn't a proxy is ignored
+        let spoofed = forwarded("198.51.100.1");
+        assert_eq!(client(&spoofed, "203.0.113.9"), ip("203.0.113.9"));
+        assert_eq!(client_ip(&spoofed, None, &proxies), None);
+
+        // Behind trusted proxies, the right-most untrusted hop is the
+        // client, and whatever the client prepended is ignored
+        let chain = forwarded("198.51.100.1, 203.0.113.9, 10.0.0.2");
+        assert_eq!(client(&chain, "10.0.0.1"), ip("203.0.113.9"));
+        assert_eq!(client(&forwarded("10.0.0.2"), "10.0.0.1"), ip("10.0.0.2"));
+        assert_eq!(client(&HeaderMap::new(), "10.0.0.1"), ip("10.0.0.1"));
+
+        let mut grant = ApiKeyGrant::new(Default::default(), Default::default(), "".into(), vec![]);
+        grant.allowed_ips = vec!["198.51.100.0/24".parse().unwrap()];
+        assert!(matches!(
+            grant.check_ip(client(&spoofed, "203.0.113.9")),
+            Err(AuthError::IpNotAllowed)
+        ));
+        assert!(grant.check_ip(client(&spoofed, "10.0.0.1")).is_ok());
+    }
+}
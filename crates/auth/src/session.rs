@@ -9,7 +9,7 @@ use crate::{AuthError, OrgId, ProjectId, Scope, UserId};
 pub struct SessionClaims {
     /// Subject (user ID)
     pub sub: String,
-    /// Organization ID
+    /// Active organization ID; switching orgs issues a new session
     pub org: String,
     /// Project ID
     pub project: String,
@@ -31,7 +31,7 @@ pub struct SessionToken {
     pub expires_at: DateTime<Utc>,
 }
 
-const SESSION_DURATION_DAYS: i64 = 30;
+pub const SESSION_DURATION_DAYS: i64 = 30;
 
 /// Create a new session token (JWT)
 pub fn create_session(
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AuthError, OrgId, ProjectId};
+
+/// Audience of share tokens, so they can't pass as session or feedback
+/// tokens or the other way around.
+const AUDIENCE: &str = "share";
+
+/// JWT claims for trace share tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareClaims {
+    pub aud: String,
+    /// Token ID
+    pub jti: String,
+    /// Organization ID
+    pub org: String,
+    /// Project ID
+    pub project: String,
+    /// The shared trace
+    pub trace: String,
+    /// Issued at
+    pub iat: i64,
+    /// Expiration
+    pub exp: i64,
+}
+
+/// Parsed share token
+#[derive(Debug, Clone)]
+pub struct ShareToken {
+    pub id: Uuid,
+    pub org_id: OrgId,
+    pub project_id: ProjectId,
+    pub trace_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Create a token that grants read-only access to one trace until it
+/// expires.
+pub fn create_share_token(
+    org_id: OrgId,
+    project_id: ProjectId,
+    trace_id: Uuid,
+    ttl: Duration,
+    secret: &[u8],
+) -> Result<(String, ShareToken), AuthError> {
+    let now = Utc::now();
+    let token = ShareToken {
+        id: Uuid::now_v7(),
+        org_id,
+        project_id,
+        trace_id,
+        expires_at: now + ttl,
+    };
+
+    let claims = ShareClaims {
+        aud: AUDIENCE.to_string(),
+        jti: token.id.to_string(),
+        org: org_id.to_string(),
+        project: project_id.to_string(),
+        trace: trace_id.to_string(),
+        iat: now.timestamp(),
+        exp: token.expires_at.timestamp(),
+    };
+
+    let encoded = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret),
+    )
+    .map_err(|_| AuthError::InvalidToken)?;
+    Ok((encoded, token))
+}
+
+/// Verify and decode a share token
+pub fn verify_share_token(token: &str, secret: &[u8]) -> Result<ShareToken, AuthError> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    let token_data = decode::<ShareClaims>(token, &DecodingKey::from_secret(secret), &validation)
+        .map_err(|e| {
+            if e.kind() == &jsonwebtoken::errors::ErrorKind::ExpiredSignature {
+                AuthError::ExpiredToken
+            } else {
+                AuthError::InvalidToken
+            }
+        })?;
+
+    let claims = token_data.claims;
+    let parse = |s: &str| s.parse::<Uuid>().map_err(|_| AuthError::InvalidToken);
+
+    Ok(ShareToken {
+        id: parse(&claims.jti)?,
+        org_id: parse(&claims.org)?,
+        project_id: parse(&claims.project)?,
+        trace_id: parse(&claims.trace)?,
+        expires_at: DateTime::from_timestamp(claims.exp, 0).ok_or(AuthError::InvalidToken)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feedback::create_feedback_token;
+    use crate::session::{generate_secret, verify_session};
+
+    #[test]
+    fn test_share_roundtrip() {
+        let secret = generate_secret();
+        let (org_id, project_id, trace_id) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+
+        let (token, issued) =
+            create_share_token(org_id, project_id, trace_id, Duration::days(7), &secret).unwrap();
+        let parsed = verify_share_token(&token, &secret).unwrap();
+
+        assert_eq!(parsed.id, issued.id);
+        assert_eq!(parsed.org_id, org_id);
+        assert_eq!(parsed.project_id, project_id);
+        assert_eq!(parsed.trace_id, trace_id);
+        assert!(verify_session(&token, &secret).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_foreign_tokens_are_rejected() {
+        let secret = generate_secret();
+        let ids = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+
+        // Past the default leeway of a minute
+        let (expired, _) =
+            create_share_token(ids.0, ids.1, ids.2, Duration::minutes(-5), &secret).unwrap();
+        assert!(matches!(
+            verify_share_token(&expired, &secret),
+            Err(AuthError::ExpiredToken)
+        ));
+
+        let feedback = create_feedback_token(ids.0, ids.1, ids.2, ids.2, &secret).unwrap();
+        assert!(matches!(
+            verify_share_token(&feedback, &secret),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
@@ -1,11 +1,15 @@
 //! Storage trait for auth-related data.
 //!
 //! This trait abstracts the persistence layer for organizations, users,
-//! API keys, and invites. Implement this on your storage backend.
+//! memberships, API keys, and invites. Implement this on your storage
+//! backend.
 
 use async_trait::async_trait;
 
-use crate::{ApiKey, ApiKeyId, Invite, OrgId, Organization, PasswordResetToken, Project, ProjectId, User, UserId};
+use crate::{
+    ApiKey, ApiKeyId, Invite, Membership, OrgId, Organization, PasswordResetToken, Project,
+    ProjectId, User, UserId,
+};
 
 /// Error type for auth storage operations
 #[derive(Debug, thiserror::Error)]
@@ -54,8 +58,32 @@ pub trait AuthStore: Send + Sync {
 
     async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, AuthStoreError>;
 
+    /// Users with a membership in the org, each with their role there.
     async fn list_users_for_org(&self, org_id: OrgId) -> Result<Vec<User>, AuthStoreError>;
 
+    // --- Membership ---
+
+    /// Add a user to an org, or change their role in it.
+    async fn save_membership(&self, membership: &Membership) -> Result<(), AuthStoreError>;
+
+    async fn get_membership(
+        &self,
+        user_id: UserId,
+        org_id: OrgId,
+    ) -> Result<Option<Membership>, AuthStoreError>;
+
+    /// The orgs a user belongs to, oldest membership first.
+    async fn list_memberships_for_user(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<Membership>, AuthStoreError>;
+
+    async fn delete_membership(
+        &self,
+        user_id: UserId,
+        org_id: OrgId,
+    ) -> Result<bool, AuthStoreError>;
+
     // --- API Key ---
 
     async fn save_api_key(&self, key: &ApiKey) -> Result<(), AuthStoreError>;
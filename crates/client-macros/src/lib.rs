@@ -0,0 +1,90 @@
+//! `#[traceway::trace]`. Use it through the `traceway-client` crate, which
+//! re-exports it and provides the runtime it expands to.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, LitStr, ReturnType, Type};
+
+/// Record each call of an async function as a span.
+///
+/// Spans go to the client registered with `traceway::set_global`; without
+/// one the function runs untraced. Calls made inside an instrumented
+/// function become child spans, and a call with no parent starts a trace.
+/// Functions returning `Result` fail their span on `Err`.
+///
+/// ```ignore
+/// #[traceway::trace]
+/// async fn answer(question: &str) -> Result<String, Error> { ... }
+///
+/// #[traceway::trace(name = "retrieve", kind = "retrieval")]
+/// async fn search(query: &str) -> Vec<Doc> { ... }
+/// ```
+///
+/// `name` defaults to the function's name and `kind` to `"function"`.
+#[proc_macro_attribute]
+pub fn trace(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let mut kind: Option<LitStr> = None;
+    let args = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("kind") {
+            kind = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `name` or `kind`"))
+        }
+    });
+    parse_macro_input!(attr with args);
+
+    let mut func = parse_macro_input!(item as syn::ItemFn);
+    if func.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(
+            func.sig.fn_token,
+            "#[traceway::trace] only supports async functions",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let name =
+        name.unwrap_or_else(|| LitStr::new(&func.sig.ident.to_string(), func.sig.ident.span()));
+    let kind = kind.unwrap_or_else(|| LitStr::new("function", proc_macro2::Span::call_site()));
+    let body = &func.block;
+    // Annotating the block's type keeps `?` and `return` inferring as they
+    // would in the original function. `impl Trait` can't be written there.
+    let inner = match &func.sig.output {
+        ReturnType::Default => {
+            quote!(async move { let __traceway_ret: () = #body; __traceway_ret })
+        }
+        ReturnType::Type(_, ty) if matches!(**ty, Type::ImplTrait(_)) => quote!(async move #body),
+        ReturnType::Type(_, ty) => {
+            quote!(async move { let __traceway_ret: #ty = #body; __traceway_ret })
+        }
+    };
+    let helper = if returns_result(&func.sig.output) {
+        quote!(instrument_result)
+    } else {
+        quote!(instrument)
+    };
+    func.block = parse_quote!({
+        ::traceway::__private::#helper(#name, #kind, #inner).await
+    });
+    quote!(#func).into()
+}
+
+/// Whether the return type is spelled `Result<..>` (any path ending in it).
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|seg| seg.ident == "Result"),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
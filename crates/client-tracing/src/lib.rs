@@ -0,0 +1,411 @@
+//! A `tracing_subscriber` layer that reports `tracing` spans to Traceway.
+//!
+//! Each root span starts a trace and nested spans become its children. A
+//! span is sent when it closes; fields recorded on it along the way decide
+//! how it is stored (see [`fields`]). An `ERROR` event inside a span fails
+//! it.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), traceway::ClientError> {
+//! use tracing_subscriber::prelude::*;
+//!
+//! let client = traceway::TracewayClient::new("http://127.0.0.1:3000")?;
+//! tracing_subscriber::registry()
+//!     .with(traceway_tracing::TracewayLayer::new(client))
+//!     .init();
+//!
+//! let span = tracing::info_span!(
+//!     "chat",
+//!     llm.model = "gpt-4o",
+//!     llm.output_tokens = tracing::field::Empty,
+//! );
+//! span.record("llm.output_tokens", 42);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+use traceway::{SpanHandle, SpanKind, TraceHandle, TracewayClient};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Field names with a meaning beyond being stored as attributes.
+pub mod fields {
+    /// Makes the span an LLM call for this model.
+    pub const LLM_MODEL: &str = "llm.model";
+    pub const LLM_PROVIDER: &str = "llm.provider";
+    pub const LLM_INPUT_TOKENS: &str = "llm.input_tokens";
+    pub const LLM_OUTPUT_TOKENS: &str = "llm.output_tokens";
+    /// Cost in USD. When absent the daemon prices the call.
+    pub const LLM_COST: &str = "llm.cost";
+    /// The span's input; JSON text is stored parsed.
+    pub const INPUT: &str = "input";
+    pub const OUTPUT: &str = "output";
+    /// Fails the span with this message.
+    pub const ERROR: &str = "error";
+    /// Custom kind name for spans that aren't LLM calls.
+    pub const KIND: &str = "traceway.kind";
+    /// Comma-separated tags added to the span's trace.
+    pub const TAGS: &str = "traceway.tags";
+    pub const SESSION_ID: &str = "session.id";
+    pub const USER_ID: &str = "user.id";
+}
+
+/// Kind name for spans without `traceway.kind`.
+const DEFAULT_KIND: &str = "tracing";
+
+/// Targets skipped by default: the HTTP stack the client itself uses,
+/// whose spans would otherwise be reported in a loop.
+const IGNORED_TARGETS: &[&str] = &[
+    "h2",
+    "hyper",
+    "hyper_util",
+    "mio",
+    "reqwest",
+    "rustls",
+    "tokio",
+    "tower",
+    "traceway",
+];
+
+pub struct TracewayLayer {
+    client: TracewayClient,
+    ignored: Vec<String>,
+}
+
+impl TracewayLayer {
+    pub fn new(client: TracewayClient) -> Self {
+        Self {
+            client,
+            ignored: IGNORED_TARGETS.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    /// Skip spans from `target` and its submodules. Their children are
+    /// attached to the nearest reported ancestor.
+    pub fn ignore_target(mut self, target: impl Into<String>) -> Self {
+        self.ignored.push(target.into());
+        self
+    }
+
+    fn ignores(&self, target: &str) -> bool {
+        self.ignored.iter().any(|t| within_target(t, target))
+    }
+}
+
+/// Whether `target` is `parent` or one of its submodules.
+fn within_target(parent: &str, target: &str) -> bool {
+    target
+        .strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Per-span state kept in the registry's extensions.
+struct Data {
+    span: SpanHandle,
+    /// Shared by every span in the trace, which ends once all are closed.
+    trace: Arc<TraceHandle>,
+    fields: Map<String, Value>,
+    /// Message of the first `ERROR` event inside the span.
+    error: Option<String>,
+}
+
+impl<S> Layer<S> for TracewayLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if self.ignores(attrs.metadata().target()) {
+            return;
+        }
+        let mut fields = Map::new();
+        attrs.record(&mut Visitor(&mut fields));
+
+        let name = attrs.metadata().name();
+        // Kind is settled at close, once every field is recorded
+        let kind = custom_kind(DEFAULT_KIND.to_string(), Default::default());
+        let parent = span.scope().skip(1).find_map(|ancestor| {
+            let ext = ancestor.extensions();
+            ext.get::<Data>().map(|d| (d.trace.clone(), d.span.id()))
+        });
+        let (trace, handle) = match parent {
+            Some((trace, parent_id)) => {
+                let handle = self
+                    .client
+                    .start_span(trace.id(), Some(parent_id), name, kind);
+                (trace, handle)
+            }
+            None => {
+                let trace = Arc::new(self.client.start_trace(name));
+                let handle = trace.start_span(name, kind);
+                (trace, handle)
+            }
+        };
+        span.extensions_mut().insert(Data {
+            span: handle,
+            trace,
+            fields,
+            error: None,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut ext = span.extensions_mut();
+        if let Some(data) = ext.get_mut::<Data>() {
+            values.record(&mut Visitor(&mut data.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let mut fields = Map::new();
+        event.record(&mut Visitor(&mut fields));
+        let message = fields
+            .remove("message")
+            .map(text)
+            .unwrap_or_else(|| event.metadata().name().to_string());
+        for span in span.scope() {
+            let mut ext = span.extensions_mut();
+            if let Some(data) = ext.get_mut::<Data>() {
+                data.error.get_or_insert(message);
+                return;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let data = span.extensions_mut().remove::<Data>();
+        if let Some(data) = data {
+            data.finish();
+        }
+    }
+}
+
+impl Data {
+    fn finish(self) {
+        let Data {
+            mut span,
+            trace,
+            fields: mut recorded,
+            error,
+        } = self;
+        if let Some(tags) = recorded.remove(fields::TAGS) {
+            for tag in text(tags)
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+            {
+                trace.tag(tag);
+            }
+        }
+        if let Some(session) = recorded.remove(fields::SESSION_ID) {
+            trace.set_session(text(session));
+        }
+        if let Some(user) = recorded.remove(fields::USER_ID) {
+            trace.set_user(text(user));
+        }
+
+        let mapped = Mapped::from_fields(recorded);
+        span.set_kind(mapped.kind);
+        if let Some(input) = mapped.input {
+            span.set_input(input);
+        }
+        match mapped.error.or(error) {
+            Some(error) => span.fail(error),
+            None => span.complete(mapped.output),
+        }
+    }
+}
+
+/// What a span's fields map to.
+#[derive(Debug)]
+struct Mapped {
+    kind: SpanKind,
+    input: Option<Value>,
+    output: Option<Value>,
+    error: Option<String>,
+}
+
+impl Mapped {
+    fn from_fields(mut recorded: Map<String, Value>) -> Self {
+        let input = recorded.remove(fields::INPUT).map(parse_json);
+        let output = recorded.remove(fields::OUTPUT).map(parse_json);
+        let error = recorded.remove(fields::ERROR).map(text);
+        let kind = match recorded.remove(fields::LLM_MODEL) {
+            Some(model) => SpanKind::LlmCall {
+                model: text(model),
+                provider: recorded.remove(fields::LLM_PROVIDER).map(text),
+                input_tokens: recorded
+                    .remove(fields::LLM_INPUT_TOKENS)
+                    .and_then(|v| v.as_u64()),
+                output_tokens: recorded
+                    .remove(fields::LLM_OUTPUT_TOKENS)
+                    .and_then(|v| v.as_u64()),
+                cost: recorded.remove(fields::LLM_COST).and_then(|v| v.as_f64()),
+                input_preview: input.as_ref().map(|v| text(v.clone())),
+                output_preview: output.as_ref().map(|v| text(v.clone())),
+            },
+            None => {
+                let kind = recorded
+                    .remove(fields::KIND)
+                    .map(text)
+                    .unwrap_or_else(|| DEFAULT_KIND.to_string());
+                custom_kind(kind, recorded.into_iter().collect())
+            }
+        };
+        Self {
+            kind,
+            input,
+            output,
+            error,
+        }
+    }
+}
+
+fn custom_kind(kind: String, attributes: std::collections::HashMap<String, Value>) -> SpanKind {
+    SpanKind::Custom { kind, attributes }
+}
+
+/// A field value as plain text, without JSON quoting for strings.
+fn text(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Strings holding a JSON object or array are stored parsed.
+fn parse_json(value: Value) -> Value {
+    match &value {
+        Value::String(s) if s.starts_with(['{', '[']) => serde_json::from_str(s).unwrap_or(value),
+        _ => value,
+    }
+}
+
+struct Visitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Visitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0
+            .insert(field.name().to_string(), Value::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), Value::from(format!("{value:?}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn map(value: Value) -> Mapped {
+        match value {
+            Value::Object(fields) => Mapped::from_fields(fields),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn llm_fields_make_an_llm_call() {
+        let mapped = map(json!({
+            "llm.model": "gpt-4o",
+            "llm.provider": "openai",
+            "llm.input_tokens": 12,
+            "llm.output_tokens": 3,
+            "input": "[{\"role\":\"user\",\"content\":\"hi\"}]",
+            "output": "hello",
+            "attempt": 2,
+        }));
+        assert_eq!(
+            mapped.input,
+            Some(json!([{"role": "user", "content": "hi"}]))
+        );
+        assert_eq!(mapped.output, Some(json!("hello")));
+        match mapped.kind {
+            SpanKind::LlmCall {
+                model,
+                provider,
+                input_tokens,
+                output_tokens,
+                cost,
+                output_preview,
+                ..
+            } => {
+                assert_eq!(model, "gpt-4o");
+                assert_eq!(provider.as_deref(), Some("openai"));
+                assert_eq!(
+                    (input_tokens, output_tokens, cost),
+                    (Some(12), Some(3), None)
+                );
+                assert_eq!(output_preview.as_deref(), Some("hello"));
+            }
+            other => panic!("expected an LLM call, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn other_fields_become_custom_attributes() {
+        let mapped = map(json!({
+            "traceway.kind": "retrieval",
+            "query": "refund policy",
+            "top_k": 5,
+            "error": "index unavailable",
+        }));
+        assert_eq!(mapped.error.as_deref(), Some("index unavailable"));
+        match mapped.kind {
+            SpanKind::Custom { kind, attributes } => {
+                assert_eq!(kind, "retrieval");
+                assert_eq!(attributes.len(), 2);
+                assert_eq!(attributes["top_k"], json!(5));
+            }
+            other => panic!("expected a custom span, got {other:?}"),
+        }
+
+        assert!(within_target("hyper", "hyper"));
+        assert!(within_target("hyper", "hyper::client::pool"));
+        assert!(!within_target("hyper", "hyperloop"));
+    }
+}
@@ -0,0 +1,78 @@
+//! Runtime support for `#[traceway::trace]`.
+//!
+//! The span of the instrumented call in progress is kept in a task-local,
+//! so nested instrumented calls become its children. A call with no
+//! enclosing span starts a new trace. Work moved onto another task with
+//! `tokio::spawn` starts fresh.
+
+use std::fmt::Display;
+use std::future::Future;
+
+use crate::{SpanId, SpanKind, TraceId};
+
+/// The instrumented span a task is currently inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+/// The innermost `#[traceway::trace]` span on this task.
+pub fn current_span() -> Option<SpanContext> {
+    CURRENT.try_with(|ctx| *ctx).ok()
+}
+
+/// Run `fut` as a span named `name`, completing it when `fut` finishes.
+pub async fn instrument<F: Future>(name: &'static str, kind: &'static str, fut: F) -> F::Output {
+    run(name, kind, fut, |_| None).await
+}
+
+/// Like [`instrument`], but an `Err` fails the span with its message.
+pub async fn instrument_result<F, T, E>(name: &'static str, kind: &'static str, fut: F) -> F::Output
+where
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    run(name, kind, fut, |out| {
+        out.as_ref().err().map(|e| e.to_string())
+    })
+    .await
+}
+
+async fn run<F: Future>(
+    name: &'static str,
+    kind: &'static str,
+    fut: F,
+    error: impl FnOnce(&F::Output) -> Option<String>,
+) -> F::Output {
+    let Some(client) = crate::global() else {
+        return fut.await;
+    };
+    let kind = SpanKind::Custom {
+        kind: kind.to_string(),
+        attributes: Default::default(),
+    };
+    // Declared first so the trace ends after its root span
+    let trace;
+    let span = match current_span() {
+        Some(parent) => client.start_span(parent.trace_id, Some(parent.span_id), name, kind),
+        None => {
+            trace = client.start_trace(name);
+            trace.start_span(name, kind)
+        }
+    };
+    let ctx = SpanContext {
+        trace_id: span.trace_id(),
+        span_id: span.id(),
+    };
+    let out = CURRENT.scope(ctx, fut).await;
+    match error(&out) {
+        Some(error) => span.fail(error),
+        None => span.complete(None),
+    }
+    out
+}
@@ -0,0 +1,567 @@
+//! Rust client for the Traceway API.
+//!
+//! Spans are recorded locally and handed to a background task, which sends
+//! them in batches and retries when the daemon is unreachable or busy.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), traceway::ClientError> {
+//! use traceway::{SpanKind, TracewayClient};
+//!
+//! let client = TracewayClient::builder("http://127.0.0.1:3000")
+//!     .api_key("tw_...")
+//!     .build()?;
+//! let trace = client.start_trace("checkout");
+//! trace.tag("prod");
+//! let kind = SpanKind::Custom {
+//!     kind: "step".to_string(),
+//!     attributes: Default::default(),
+//! };
+//! let span = trace.start_span("charge card", kind);
+//! span.complete(Some(serde_json::json!({"ok": true})));
+//! trace.end();
+//! client.flush().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `#[traceway::trace]` instruments async functions through the client
+//! registered with [`set_global`]; see [`trace`](macro@trace).
+
+// Lets the macro's `::traceway` paths resolve inside this crate's tests
+extern crate self as traceway;
+
+mod instrument;
+mod sender;
+
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+pub use instrument::{current_span, SpanContext};
+pub use trace::git::GitInfo;
+pub use trace::{SpanId, SpanKind, SpanStatus, TraceId};
+pub use traceway_client_macros::trace;
+
+use sender::{Message, SpanRecord, TraceRecord};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::instrument::{instrument, instrument_result};
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("invalid client settings: {0}")]
+    Config(String),
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{status}: {body}")]
+    Status {
+        status: reqwest::StatusCode,
+        /// The `code` of the daemon's problem details, such as
+        /// `span_already_terminal`.
+        code: Option<String>,
+        body: String,
+    },
+    #[error("the background sender has stopped")]
+    Closed,
+}
+
+pub struct ClientBuilder {
+    endpoint: String,
+    api_key: Option<String>,
+    session_id: Option<String>,
+    git: Option<GitInfo>,
+    batch_size: usize,
+    flush_interval: Duration,
+    max_retries: u32,
+    queue_capacity: usize,
+    timeout: Duration,
+}
+
+impl ClientBuilder {
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+    /// Default session for every trace this client sends, passed as the
+    /// `x-traceway-session-id` header. `TraceHandle::set_session` overrides it.
+    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+    /// Commit, branch, and repository recorded on every trace. Detected
+    /// from the working directory's checkout when unset; pass
+    /// `GitInfo::default()` to record none.
+    pub fn git(mut self, git: GitInfo) -> Self {
+        self.git = Some(git);
+        self
+    }
+    /// Spans per request; the API accepts at most 1000.
+    pub fn batch_size(mut self, n: usize) -> Self {
+        self.batch_size = n.clamp(1, 1000);
+        self
+    }
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+    pub fn max_retries(mut self, n: u32) -> Self {
+        self.max_retries = n;
+        self
+    }
+    /// Records buffered before new ones are dropped.
+    pub fn queue_capacity(mut self, n: usize) -> Self {
+        self.queue_capacity = n.max(1);
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Build the client and start its sender. Must be called inside a
+    /// Tokio runtime.
+    pub fn build(self) -> Result<TracewayClient, ClientError> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(key) = &self.api_key {
+            let value = format!("Bearer {key}")
+                .parse()
+                .map_err(|_| ClientError::Config("API key is not a valid header value".into()))?;
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        if let Some(session_id) = &self.session_id {
+            let value = session_id.parse().map_err(|_| {
+                ClientError::Config("session id is not a valid header value".into())
+            })?;
+            headers.insert("x-traceway-session-id", value);
+        }
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(self.timeout)
+            .build()?;
+        let (tx, rx) = mpsc::channel(self.queue_capacity);
+        tokio::spawn(sender::run(
+            rx,
+            sender::Sender {
+                http,
+                base: format!("{}/api", self.endpoint.trim_end_matches('/')),
+                batch_size: self.batch_size,
+                max_retries: self.max_retries,
+            },
+            self.flush_interval,
+        ));
+        let git = self.git.unwrap_or_else(GitInfo::from_current_dir);
+        Ok(TracewayClient {
+            tx,
+            git: Arc::new(git),
+        })
+    }
+}
+
+/// Handle to a Traceway daemon. Cheap to clone; clones share one sender,
+/// which stops once every clone and handle is dropped.
+#[derive(Debug, Clone)]
+pub struct TracewayClient {
+    tx: mpsc::Sender<Message>,
+    git: Arc<GitInfo>,
+}
+
+impl TracewayClient {
+    /// `endpoint` is the daemon's base URL, e.g. `http://127.0.0.1:3000`.
+    pub fn builder(endpoint: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            endpoint: endpoint.into(),
+            api_key: None,
+            session_id: None,
+            git: None,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(1),
+            max_retries: 3,
+            queue_capacity: 10_000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn new(endpoint: impl Into<String>) -> Result<Self, ClientError> {
+        Self::builder(endpoint).build()
+    }
+
+    /// Start a trace. It is reported right away, and again when ended.
+    pub fn start_trace(&self, name: impl Into<String>) -> TraceHandle {
+        let record = TraceRecord {
+            id: uuid::Uuid::now_v7(),
+            name: Some(name.into()),
+            tags: Vec::new(),
+            started_at: Utc::now(),
+            ended_at: None,
+            session_id: None,
+            user_id: None,
+            git_commit: self.git.commit.clone(),
+            git_branch: self.git.branch.clone(),
+            repo: self.git.repo.clone(),
+        };
+        self.send(Message::Trace(record.clone()));
+        TraceHandle {
+            client: self.clone(),
+            record: Mutex::new(record),
+        }
+    }
+
+    /// Start a span in an existing trace, under `parent` if given.
+    pub fn start_span(
+        &self,
+        trace_id: TraceId,
+        parent_id: Option<SpanId>,
+        name: impl Into<String>,
+        kind: SpanKind,
+    ) -> SpanHandle {
+        SpanHandle {
+            client: self.clone(),
+            record: Some(SpanRecord {
+                id: uuid::Uuid::now_v7(),
+                trace_id,
+                parent_id,
+                name: name.into(),
+                kind,
+                status: SpanStatus::Running,
+                started_at: Utc::now(),
+                ended_at: None,
+                input: None,
+                output: None,
+            }),
+        }
+    }
+
+    /// Send everything buffered so far and wait for the result.
+    pub async fn flush(&self) -> Result<(), ClientError> {
+        let (done, result) = oneshot::channel();
+        self.tx
+            .send(Message::Flush(done))
+            .await
+            .map_err(|_| ClientError::Closed)?;
+        result.await.map_err(|_| ClientError::Closed)?
+    }
+
+    fn send(&self, message: Message) {
+        if let Err(e) = self.tx.try_send(message) {
+            tracing::warn!("traceway: dropping record: {e}");
+        }
+    }
+}
+
+/// A trace being recorded. Ended when dropped if [`end`](Self::end) was
+/// not called.
+#[derive(Debug)]
+pub struct TraceHandle {
+    client: TracewayClient,
+    record: Mutex<TraceRecord>,
+}
+
+impl TraceHandle {
+    pub fn id(&self) -> TraceId {
+        self.lock().id
+    }
+
+    pub fn tag(&self, tag: impl Into<String>) {
+        let tag = tag.into();
+        let mut record = self.lock();
+        if !record.tags.contains(&tag) {
+            record.tags.push(tag);
+        }
+    }
+
+    pub fn set_session(&self, session_id: impl Into<String>) {
+        self.lock().session_id = Some(session_id.into());
+    }
+
+    pub fn set_user(&self, user_id: impl Into<String>) {
+        self.lock().user_id = Some(user_id.into());
+    }
+
+    /// Start a top-level span in this trace.
+    pub fn start_span(&self, name: impl Into<String>, kind: SpanKind) -> SpanHandle {
+        self.client.start_span(self.id(), None, name, kind)
+    }
+
+    pub fn end(self) {}
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TraceRecord> {
+        self.record.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for TraceHandle {
+    fn drop(&mut self) {
+        let mut record = self.lock().clone();
+        record.ended_at = Some(Utc::now().max(record.started_at));
+        self.client.send(Message::Trace(record));
+    }
+}
+
+/// A running span. Sent once completed or failed; dropping it unfinished
+/// records a failure.
+#[derive(Debug)]
+pub struct SpanHandle {
+    client: TracewayClient,
+    record: Option<SpanRecord>,
+}
+
+impl SpanHandle {
+    pub fn id(&self) -> SpanId {
+        self.record().id
+    }
+
+    pub fn trace_id(&self) -> TraceId {
+        self.record().trace_id
+    }
+
+    pub fn set_input(&mut self, input: Value) {
+        self.record_mut().input = Some(input);
+    }
+
+    /// Replace the kind, e.g. to add token counts once a model call returns.
+    pub fn set_kind(&mut self, kind: SpanKind) {
+        self.record_mut().kind = kind;
+    }
+
+    pub fn start_child(&self, name: impl Into<String>, kind: SpanKind) -> SpanHandle {
+        self.client
+            .start_span(self.trace_id(), Some(self.id()), name, kind)
+    }
+
+    pub fn complete(mut self, output: Option<Value>) {
+        self.finish(SpanStatus::Completed, output);
+    }
+
+    pub fn fail(mut self, error: impl Into<String>) {
+        let error = error.into();
+        self.finish(SpanStatus::Failed { error }, None);
+    }
+
+    fn finish(&mut self, status: SpanStatus, output: Option<Value>) {
+        if let Some(mut record) = self.record.take() {
+            record.status = status;
+            record.ended_at = Some(Utc::now().max(record.started_at));
+            record.output = output;
+            self.client.send(Message::Span(record));
+        }
+    }
+
+    fn record(&self) -> &SpanRecord {
+        self.record
+            .as_ref()
+            .expect("span record is present until finished")
+    }
+
+    fn record_mut(&mut self) -> &mut SpanRecord {
+        self.record
+            .as_mut()
+            .expect("span record is present until finished")
+    }
+}
+
+impl Drop for SpanHandle {
+    fn drop(&mut self) {
+        self.finish(
+            SpanStatus::Failed {
+                error: "span dropped before it finished".to_string(),
+            },
+            None,
+        );
+    }
+}
+
+static GLOBAL: OnceLock<TracewayClient> = OnceLock::new();
+
+/// Register the client `#[traceway::trace]` reports to. Returns `false` if
+/// one was already set.
+pub fn set_global(client: TracewayClient) -> bool {
+    GLOBAL.set(client).is_ok()
+}
+
+pub fn global() -> Option<&'static TracewayClient> {
+    GLOBAL.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::routing::{post, put};
+    use axum::Json;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Received {
+        traces: Arc<Mutex<Vec<(String, Value)>>>,
+        spans: Arc<Mutex<Vec<Value>>>,
+        /// Span batches to reject with a 503 before accepting.
+        unavailable: Arc<AtomicUsize>,
+        batches: Arc<AtomicUsize>,
+    }
+
+    async fn put_trace(
+        State(r): State<Received>,
+        Path(id): Path<String>,
+        Json(body): Json<Value>,
+    ) -> StatusCode {
+        r.traces.lock().unwrap().push((id, body));
+        StatusCode::OK
+    }
+
+    async fn post_spans(State(r): State<Received>, Json(batch): Json<Vec<Value>>) -> StatusCode {
+        r.batches.fetch_add(1, Ordering::SeqCst);
+        let reject = r
+            .unavailable
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if reject {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        r.spans.lock().unwrap().extend(batch);
+        StatusCode::OK
+    }
+
+    async fn serve(received: Received) -> String {
+        let app = axum::Router::new()
+            .route("/api/traces/:id", put(put_trace))
+            .route("/api/spans/batch", post(post_spans))
+            .with_state(received);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    fn step() -> SpanKind {
+        SpanKind::Custom {
+            kind: "step".to_string(),
+            attributes: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_trace_and_batched_spans() {
+        let received = Received::default();
+        let git = GitInfo {
+            commit: Some("4f2a9c1".into()),
+            branch: Some("main".into()),
+            repo: None,
+        };
+        let client = TracewayClient::builder(serve(received.clone()).await)
+            .git(git)
+            .build()
+            .unwrap();
+
+        let trace = client.start_trace("checkout");
+        trace.tag("prod");
+        trace.tag("prod");
+        let root = trace.start_span("root", step());
+        let child = root.start_child("charge", step());
+        let (trace_id, root_id) = (trace.id(), root.id());
+        child.fail("card declined");
+        root.complete(Some(serde_json::json!({"ok": false})));
+        trace.end();
+        client.flush().await.unwrap();
+
+        let traces = received.traces.lock().unwrap();
+        assert_eq!(traces.len(), 1, "start and end are coalesced");
+        assert_eq!(traces[0].0, trace_id.to_string());
+        assert_eq!(traces[0].1["tags"], serde_json::json!(["prod"]));
+        assert!(traces[0].1["ended_at"].is_string());
+        assert_eq!(traces[0].1["git_commit"], "4f2a9c1");
+        assert_eq!(traces[0].1["git_branch"], "main");
+        assert!(traces[0].1.get("repo").is_none());
+
+        let spans = received.spans.lock().unwrap();
+        assert_eq!(received.batches.load(Ordering::SeqCst), 1);
+        assert_eq!(spans[0]["name"], "charge");
+        assert_eq!(spans[0]["parent_id"], root_id.to_string());
+        assert_eq!(spans[0]["status"]["failed"]["error"], "card declined");
+        assert_eq!(spans[1]["status"], "completed");
+        assert_eq!(spans[1]["output"]["ok"], false);
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable_daemon() {
+        let received = Received::default();
+        received.unavailable.store(1, Ordering::SeqCst);
+        let endpoint = serve(received.clone()).await;
+
+        let client = TracewayClient::new(&endpoint).unwrap();
+        client
+            .start_span(uuid::Uuid::now_v7(), None, "a", step())
+            .complete(None);
+        client.flush().await.unwrap();
+        assert_eq!(received.batches.load(Ordering::SeqCst), 2);
+        assert_eq!(received.spans.lock().unwrap().len(), 1);
+
+        received.unavailable.store(1, Ordering::SeqCst);
+        let client = TracewayClient::builder(&endpoint)
+            .max_retries(0)
+            .build()
+            .unwrap();
+        client
+            .start_span(uuid::Uuid::now_v7(), None, "b", step())
+            .complete(None);
+        assert!(matches!(
+            client.flush().await,
+            Err(ClientError::Status { status, .. }) if status == StatusCode::SERVICE_UNAVAILABLE
+        ));
+    }
+
+    #[derive(Debug)]
+    struct Declined;
+
+    impl std::fmt::Display for Declined {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("declined")
+        }
+    }
+
+    #[traceway::trace(kind = "step")]
+    async fn charge(amount: u64) -> Result<u64, Declined> {
+        if amount > 100 {
+            return Err(Declined);
+        }
+        Ok(amount)
+    }
+
+    #[traceway::trace(name = "checkout")]
+    async fn checkout() -> Option<SpanContext> {
+        let _ = charge(500).await;
+        current_span()
+    }
+
+    #[tokio::test]
+    async fn attribute_nests_spans() {
+        let received = Received::default();
+        assert!(set_global(
+            TracewayClient::new(serve(received.clone()).await).unwrap()
+        ));
+
+        let ctx = checkout().await.expect("inside the checkout span");
+        assert_eq!(current_span(), None);
+        global().unwrap().flush().await.unwrap();
+
+        let spans = received.spans.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0]["name"], "charge");
+        assert_eq!(spans[0]["kind"]["kind"], "step");
+        assert_eq!(spans[0]["parent_id"], ctx.span_id.to_string());
+        assert_eq!(spans[0]["status"]["failed"]["error"], "declined");
+        assert_eq!(spans[1]["id"], ctx.span_id.to_string());
+        assert_eq!(spans[1]["status"], "completed");
+        let traces = received.traces.lock().unwrap();
+        assert_eq!(traces[0].0, ctx.trace_id.to_string());
+        assert_eq!(traces[0].1["name"], "checkout");
+    }
+}
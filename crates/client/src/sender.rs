@@ -0,0 +1,175 @@
+//! The background task that ships recorded spans and traces.
+//!
+//! Records queue up until `batch_size` spans are waiting, the flush
+//! interval passes, or a flush is requested. Traces go out first, one
+//! `PUT /api/traces/:id` each, then spans through `POST /api/spans/batch`.
+//! Connection errors, 429s, and 5xxs are retried with backoff; anything
+//! still failing is logged and dropped so the queue cannot grow unbounded.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use trace::{SpanId, SpanKind, SpanStatus, TraceId};
+
+use crate::ClientError;
+
+/// First wait between retries; doubles each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// A span in the shape `POST /api/spans/batch` accepts.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SpanRecord {
+    pub id: SpanId,
+    pub trace_id: TraceId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<SpanId>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub status: SpanStatus,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+}
+
+/// A trace in the shape `PUT /api/traces/:id` accepts.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TraceRecord {
+    #[serde(skip)]
+    pub id: TraceId,
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Message {
+    Span(SpanRecord),
+    Trace(TraceRecord),
+    Flush(oneshot::Sender<Result<(), ClientError>>),
+}
+
+pub(crate) struct Sender {
+    pub http: reqwest::Client,
+    /// The daemon's `/api` URL.
+    pub base: String,
+    pub batch_size: usize,
+    pub max_retries: u32,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Latest record per trace; a trace started and ended between flushes
+    /// is sent once.
+    traces: HashMap<TraceId, TraceRecord>,
+    spans: Vec<SpanRecord>,
+}
+
+pub(crate) async fn run(mut rx: mpsc::Receiver<Message>, sender: Sender, interval: Duration) {
+    let mut pending = Pending::default();
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(Message::Span(span)) => {
+                    pending.spans.push(span);
+                    if pending.spans.len() >= sender.batch_size {
+                        let _ = sender.flush(&mut pending).await;
+                    }
+                }
+                Some(Message::Trace(trace)) => {
+                    pending.traces.insert(trace.id, trace);
+                }
+                Some(Message::Flush(done)) => {
+                    let _ = done.send(sender.flush(&mut pending).await);
+                }
+                None => {
+                    let _ = sender.flush(&mut pending).await;
+                    return;
+                }
+            },
+            _ = tick.tick() => {
+                let _ = sender.flush(&mut pending).await;
+            }
+        }
+    }
+}
+
+impl Sender {
+    /// Send and clear everything pending. Returns the last failure.
+    async fn flush(&self, pending: &mut Pending) -> Result<(), ClientError> {
+        let mut result = Ok(());
+        for (id, trace) in pending.traces.drain() {
+            let url = format!("{}/traces/{id}", self.base);
+            if let Err(e) = self.send(|| self.http.put(&url).json(&trace)).await {
+                tracing::warn!(trace_id = %id, "traceway: dropping trace: {e}");
+                result = Err(e);
+            }
+        }
+        let spans = std::mem::take(&mut pending.spans);
+        for batch in spans.chunks(self.batch_size) {
+            let url = format!("{}/spans/batch", self.base);
+            if let Err(e) = self.send(|| self.http.post(&url).json(batch)).await {
+                tracing::warn!(count = batch.len(), "traceway: dropping spans: {e}");
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    async fn send(&self, request: impl Fn() -> reqwest::RequestBuilder) -> Result<(), ClientError> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let error = match request().send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(()),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    let code = serde_json::from_str::<serde_json::Value>(&body)
+                        .ok()
+                        .and_then(|v| Some(v.get("code")?.as_str()?.to_string()));
+                    ClientError::Status { status, code, body }
+                }
+                Err(e) => ClientError::Http(e),
+            };
+            if attempt >= self.max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+            attempt += 1;
+            tracing::debug!(attempt, "traceway: retrying after {error}");
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+fn is_retryable(error: &ClientError) -> bool {
+    match error {
+        ClientError::Http(e) => e.is_connect() || e.is_timeout(),
+        ClientError::Status { status, .. } => {
+            status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }
+        ClientError::Config(_) | ClientError::Closed => false,
+    }
+}
@@ -0,0 +1,68 @@
+//! `traceway admin`: maintenance commands run against storage directly,
+//! without a daemon.
+
+use storage_turbopuffer::{TurbopufferBackend, TurbopufferConfig};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Bring Turbopuffer namespaces up to the declared schemas, rewriting
+    /// rows saved before an attribute was declared
+    TpufMigrate(TpufMigrateArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TpufMigrateArgs {
+    /// Only migrate backends whose namespace prefix starts with this,
+    /// e.g. `tw_` for per-project stores [default: every backend]
+    #[arg(long, default_value = "")]
+    prefix: String,
+
+    /// Report how each namespace differs without changing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub async fn run(command: AdminCommand) -> Result<(), String> {
+    match command {
+        AdminCommand::TpufMigrate(args) => tpuf_migrate(args).await,
+    }
+}
+
+async fn tpuf_migrate(args: TpufMigrateArgs) -> Result<(), String> {
+    let config = TurbopufferConfig::from_env().map_err(|e| e.to_string())?;
+    let probe = TurbopufferBackend::new(config.clone()).map_err(|e| e.to_string())?;
+    let prefixes = probe
+        .namespace_prefixes(&args.prefix)
+        .await
+        .map_err(|e| e.to_string())?;
+    if prefixes.is_empty() {
+        println!("no namespaces found");
+        return Ok(());
+    }
+
+    for prefix in prefixes {
+        let backend = TurbopufferBackend::new(config.clone().with_namespace(&prefix))
+            .map_err(|e| e.to_string())?;
+        if args.dry_run {
+            let diffs = backend.verify_schemas().await.map_err(|e| e.to_string())?;
+            for (collection, diff) in diffs.iter().filter(|(_, d)| !d.is_empty()) {
+                println!(
+                    "{prefix}_{collection}: missing {:?}, changed {:?}, conflicting {:?}",
+                    diff.missing, diff.changed, diff.conflicts
+                );
+            }
+            if diffs.iter().all(|(_, d)| d.is_empty()) {
+                println!("{prefix}: up to date");
+            }
+            continue;
+        }
+        let rewritten = backend.migrate().await.map_err(|e| e.to_string())?;
+        if rewritten.is_empty() {
+            println!("{prefix}: up to date");
+        }
+        for (collection, rows) in rewritten {
+            println!("{prefix}_{collection}: rewrote {rows} rows");
+        }
+    }
+    Ok(())
+}
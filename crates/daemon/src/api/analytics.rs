@@ -0,0 +1,408 @@
+//! Span analytics endpoints.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use storage::{
+    analytical, analytics, AnalyticalStore, SpanFilter, StorageBackend, StorageError, TraceFilter,
+};
+use trace::{
+    AnalyticsQuery, AnalyticsResponse, AnalyticsSummary, Cohort, CommitMetrics, CompareQuery,
+    CompareResponse, ConcurrencyQuery, ConcurrencyResponse, GroupByField, Span, TimeseriesQuery,
+    TimeseriesResponse, TraceId,
+};
+use tracing::warn;
+use utoipa::IntoParams;
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::{
+    api_error, project_store, require_scope, ApiError, AppState, SharedStore, MAX_PAGE_LIMIT,
+};
+
+const DEFAULT_TRACE_LIMIT: usize = 20;
+const DEFAULT_COMMIT_LIMIT: usize = 50;
+/// Upper bound on buckets per response, so a 1m interval can't be asked to
+/// cover months.
+const MAX_BUCKETS: usize = 10_000;
+
+async fn load_spans(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+    filter: &SpanFilter,
+) -> Result<Vec<Span>, ApiError> {
+    project_store(ctx, state)
+        .await?
+        .backend()
+        .list_spans(filter)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Tags of every trace in `spans`, when grouping by tag needs them.
+async fn trace_tags(
+    store: &SharedStore,
+    spans: &[Span],
+    group_by: &[GroupByField],
+) -> HashMap<TraceId, Vec<String>> {
+    let mut tags = HashMap::new();
+    if !group_by.contains(&GroupByField::Tag) {
+        return tags;
+    }
+    let ids: HashSet<TraceId> = spans.iter().map(Span::trace_id).collect();
+    for id in ids {
+        if let Some(trace) = store.get_trace_or_load(id).await {
+            tags.insert(id, trace.tags);
+        }
+    }
+    tags
+}
+
+/// Dashboard totals over every span in the project.
+#[utoipa::path(
+    get,
+    path = "/api/analytics/summary",
+    tag = "analytics",
+    responses(
+        (status = 200, body = AnalyticsSummary),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn summary(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let etag = ETag::for_revision(store.revision(), &uri);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let spans = load_spans(&ctx, &state, &SpanFilter::default()).await?;
+    let refs: Vec<&Span> = spans.iter().collect();
+    let traces: HashSet<_> = spans.iter().map(Span::trace_id).collect();
+    Ok(etag.respond(Json(analytics::compute_summary(&refs, traces.len()))))
+}
+
+/// Metrics over the spans matching the query's filter, optionally grouped.
+/// Grouping by `tag` counts a span under every tag on its trace.
+#[utoipa::path(
+    post,
+    path = "/api/analytics",
+    tag = "analytics",
+    request_body = AnalyticsQuery,
+    responses(
+        (status = 200, body = AnalyticsResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn query_analytics(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(query): Json<AnalyticsQuery>,
+) -> Result<Json<AnalyticsResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    run_query(&ctx, &state, &query).await.map(Json)
+}
+
+/// Answer `query` from the analytical store when it can, otherwise by
+/// aggregating the matching spans in memory.
+pub(super) async fn run_query(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+    query: &AnalyticsQuery,
+) -> Result<AnalyticsResponse, ApiError> {
+    let store = project_store(ctx, state).await?;
+    if let Some(olap) = store
+        .analytical()
+        .filter(|_| analytical::supports(&query.metrics, &query.group_by))
+    {
+        match analytical::analytics(olap, query).await {
+            Ok(response) => return Ok(response),
+            Err(e) => warn!("analytical store query failed, aggregating in memory: {e}"),
+        }
+    }
+    let spans = load_spans(ctx, state, &(&query.filter).into()).await?;
+    let refs: Vec<&Span> = spans.iter().collect();
+    let tags = trace_tags(&store, &spans, &query.group_by).await;
+    Ok(analytics::compute_analytics(&refs, &tags, query))
+}
+
+/// Concurrent spans per time bucket, with max concurrency and
+/// parent-to-child queue gaps per trace. `since`/`until` select spans by
+/// start time; spans started earlier are not counted.
+#[utoipa::path(
+    post,
+    path = "/api/analytics/concurrency",
+    tag = "analytics",
+    request_body = ConcurrencyQuery,
+    responses(
+        (status = 200, body = ConcurrencyResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn concurrency(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(query): Json<ConcurrencyQuery>,
+) -> Result<Json<ConcurrencyResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    state.require_feature(auth::Feature::ConcurrencyAnalytics)?;
+    let now = Utc::now();
+    let filter = &query.filter;
+    let until = filter.until.unwrap_or(now);
+    if filter.since.is_some_and(|since| since > until) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "since must be before until",
+        ));
+    }
+
+    let spans = load_spans(&ctx, &state, &filter.into()).await?;
+    let refs: Vec<&Span> = spans.iter().collect();
+    let since = filter
+        .since
+        .or_else(|| refs.iter().map(|s| s.started_at()).min());
+    if since.is_some_and(|s| analytics::bucket_count(query.interval, s, until) > MAX_BUCKETS) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("range covers more than {MAX_BUCKETS} buckets; use a wider interval"),
+        ));
+    }
+    let trace_limit = query
+        .trace_limit
+        .unwrap_or(DEFAULT_TRACE_LIMIT)
+        .min(MAX_PAGE_LIMIT);
+    Ok(Json(analytics::compute_concurrency(
+        &refs,
+        query.interval,
+        since,
+        Some(until),
+        trace_limit,
+        now,
+    )))
+}
+
+/// Metrics per fixed-width bucket, zero-filled, for charting. `since`
+/// defaults to the earliest matching span and `until` to now.
+#[utoipa::path(
+    post,
+    path = "/api/analytics/timeseries",
+    tag = "analytics",
+    request_body = TimeseriesQuery,
+    responses(
+        (status = 200, body = TimeseriesResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn timeseries(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(query): Json<TimeseriesQuery>,
+) -> Result<Json<TimeseriesResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let filter = &query.filter;
+    let until = filter.until.unwrap_or_else(Utc::now);
+    if filter.since.is_some_and(|since| since > until) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "since must be before until",
+        ));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    if let Some(olap) = store
+        .analytical()
+        .filter(|_| analytical::supports(&query.metrics, &query.group_by))
+    {
+        match timeseries_sql(olap, &query, until).await {
+            Ok(Some(response)) => return Ok(Json(response)),
+            Ok(None) => {}
+            Err(e) => warn!("analytical store query failed, aggregating in memory: {e}"),
+        }
+    }
+
+    let spans = load_spans(&ctx, &state, &filter.into()).await?;
+    let refs: Vec<&Span> = spans.iter().collect();
+    let Some(since) = filter
+        .since
+        .or_else(|| refs.iter().map(|s| s.started_at()).min())
+    else {
+        return Ok(Json(TimeseriesResponse {
+            interval: query.interval,
+            buckets: Vec::new(),
+            totals: Vec::new(),
+            series: Vec::new(),
+        }));
+    };
+    if analytics::bucket_count(query.interval, since, until) > MAX_BUCKETS {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("range covers more than {MAX_BUCKETS} buckets; use a wider interval"),
+        ));
+    }
+    let tags = trace_tags(&store, &spans, &query.group_by).await;
+    Ok(Json(analytics::compute_timeseries(
+        &refs, &tags, &query, since, until,
+    )))
+}
+
+/// `timeseries` from the analytical store. `None` when the range is too
+/// wide, so the in-memory path can report it.
+async fn timeseries_sql(
+    olap: &dyn AnalyticalStore,
+    query: &TimeseriesQuery,
+    until: DateTime<Utc>,
+) -> Result<Option<TimeseriesResponse>, StorageError> {
+    let since = match query.filter.since {
+        Some(since) => Some(since),
+        None => olap.earliest(&query.filter).await?,
+    };
+    let Some(since) = since else {
+        return Ok(Some(TimeseriesResponse {
+            interval: query.interval,
+            buckets: Vec::new(),
+            totals: Vec::new(),
+            series: Vec::new(),
+        }));
+    };
+    if analytics::bucket_count(query.interval, since, until) > MAX_BUCKETS {
+        return Ok(None);
+    }
+    analytical::timeseries(olap, query, since, until)
+        .await
+        .map(Some)
+}
+
+/// Spans in `cohort`. Tags live on traces, so a tagged cohort keeps only the
+/// spans whose trace has every tag.
+async fn cohort_spans(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+    cohort: &Cohort,
+) -> Result<Vec<Span>, ApiError> {
+    let mut filter: SpanFilter = (&cohort.filter).into();
+    filter.name_contains = cohort.name_contains.clone();
+    let mut spans = load_spans(ctx, state, &filter).await?;
+    if !cohort.trace_tags.is_empty() {
+        let store = project_store(ctx, state).await?;
+        let traces = store
+            .backend()
+            .list_traces(&TraceFilter {
+                tags: Some(cohort.trace_tags.clone()),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let ids: HashSet<_> = traces.into_iter().map(|t| t.id).collect();
+        spans.retain(|s| ids.contains(&s.trace_id()));
+    }
+    Ok(spans)
+}
+
+/// Side-by-side cost, latency, error, and token metrics for two span
+/// cohorts (e.g. two prompt versions or models), with deltas from `a` to `b`.
+#[utoipa::path(
+    post,
+    path = "/api/analytics/compare",
+    tag = "analytics",
+    request_body = CompareQuery,
+    responses(
+        (status = 200, body = CompareResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn compare(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(query): Json<CompareQuery>,
+) -> Result<Json<CompareResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let (a, b) = tokio::try_join!(
+        cohort_spans(&ctx, &state, &query.a),
+        cohort_spans(&ctx, &state, &query.b),
+    )?;
+    let metrics = |spans: &[Span], cohort: Cohort| {
+        let refs: Vec<&Span> = spans.iter().collect();
+        analytics::compute_cohort(&refs, cohort.label)
+    };
+    Ok(Json(analytics::compare_cohorts(
+        metrics(&a, query.a),
+        metrics(&b, query.b),
+    )))
+}
+
+/// Query parameters for `GET /api/analytics/by-commit`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ByCommitQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub repo: Option<String>,
+    pub git_branch: Option<String>,
+    /// Most recent commits to return.
+    pub limit: Option<usize>,
+}
+
+/// Trace cost, latency, and errors per commit, newest first, each compared
+/// with the commit deployed before it.
+#[utoipa::path(
+    get,
+    path = "/api/analytics/by-commit",
+    tag = "analytics",
+    params(ByCommitQuery),
+    responses(
+        (status = 200, body = Vec<CommitMetrics>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn by_commit(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ByCommitQuery>,
+) -> Result<Json<Vec<CommitMetrics>>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let traces = store
+        .backend()
+        .list_traces(&TraceFilter {
+            since: q.since,
+            until: q.until,
+            repo: q.repo,
+            git_branch: q.git_branch,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let mut commits = analytics::compute_by_commit(&traces);
+    commits.truncate(
+        q.limit
+            .unwrap_or(DEFAULT_COMMIT_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT),
+    );
+    Ok(Json(commits))
+}
+
+/// Refill the analytical store from the project's spans.
+pub async fn rebuild(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = project_store(&ctx, &state).await?;
+    let spans = store.rebuild_analytical().await.map_err(|e| match e {
+        StorageError::Configuration(msg) => api_error(StatusCode::CONFLICT, msg),
+        e => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(Json(serde_json::json!({ "spans": spans })))
+}
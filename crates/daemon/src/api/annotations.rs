@@ -0,0 +1,268 @@
+//! Span annotations: scores, labels, and comments attached to a span's
+//! output by reviewers or by programs.
+//!
+//! Annotations are stored apart from spans, so adding one never rewrites
+//! the span. Span queries select by them with `score` and `label` (the
+//! `score:>0.5 label:hallucination` terms of the query language).
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use storage::AnnotationFilter;
+use trace::{Annotation, AnnotationId, SpanId, TraceId};
+use utoipa::{IntoParams, ToSchema};
+
+use super::error::Problem;
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState, MAX_PAGE_LIMIT};
+
+const MAX_LABEL_LEN: usize = 100;
+
+/// Body for `POST /api/spans/:id/annotate`. At least one field is needed.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct AnnotateRequest {
+    /// Usually in `[0, 1]`.
+    #[serde(default)]
+    pub score: Option<f64>,
+    /// A category such as `hallucination` or `helpful`.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// Query parameters for `GET /api/annotations`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListAnnotationsQuery {
+    #[param(value_type = Option<String>)]
+    pub span_id: Option<SpanId>,
+    #[param(value_type = Option<String>)]
+    pub trace_id: Option<TraceId>,
+    pub label: Option<String>,
+    pub score_min: Option<f64>,
+    pub score_max: Option<f64>,
+    /// `user:<id>`, `api_key:<prefix>`, or `local`.
+    pub created_by: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Attach a score, label, or comment to a span.
+#[utoipa::path(
+    post,
+    path = "/api/spans/{id}/annotate",
+    tag = "spans",
+    params(("id" = String, Path, description = "Span id")),
+    request_body = AnnotateRequest,
+    responses(
+        (status = 201, body = Annotation),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn annotate_span(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SpanId>,
+    Json(req): Json<AnnotateRequest>,
+) -> Result<(StatusCode, Json<Annotation>), ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let label = req.label.map(|l| l.trim().to_string());
+    if req.score.is_none() && label.is_none() && req.comment.is_none() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "an annotation needs a score, label, or comment",
+        ));
+    }
+    if req.score.is_some_and(|s| !s.is_finite()) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "score must be a finite number"));
+    }
+    if let Some(ref label) = label {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN || label.contains(char::is_whitespace)
+        {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                format!("label must be 1 to {MAX_LABEL_LEN} characters without spaces"),
+            ));
+        }
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    let span = store.get_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span not found").with_code("span_not_found")
+    })?;
+    let mut annotation = Annotation::new(id, span.trace_id(), audit::actor(&ctx));
+    annotation.score = req.score;
+    annotation.label = label;
+    annotation.comment = req.comment;
+    store
+        .save_annotation(&annotation)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+/// A span's annotations, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/spans/{id}/annotations",
+    tag = "spans",
+    params(("id" = String, Path, description = "Span id")),
+    responses(
+        (status = 200, body = Vec<Annotation>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn span_annotations(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SpanId>,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    list(
+        &ctx,
+        &state,
+        AnnotationFilter {
+            span_id: Some(id),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Annotations across spans, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/annotations",
+    tag = "spans",
+    params(ListAnnotationsQuery),
+    responses(
+        (status = 200, body = Vec<Annotation>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_annotations(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ListAnnotationsQuery>,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    let filter = AnnotationFilter {
+        span_id: q.span_id,
+        trace_id: q.trace_id,
+        label: q.label,
+        score_min: q.score_min,
+        score_max: q.score_max,
+        created_by: q.created_by,
+        limit: Some(q.limit.unwrap_or(100).min(MAX_PAGE_LIMIT)),
+    };
+    list(&ctx, &state, filter).await
+}
+
+async fn list(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+    filter: AnnotationFilter,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    require_scope(ctx, auth::Scope::TracesRead)?;
+    let store = project_store(ctx, state).await?;
+    let annotations = store
+        .list_annotations(&filter)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(annotations))
+}
+
+/// Remove an annotation.
+#[utoipa::path(
+    delete,
+    path = "/api/annotations/{id}",
+    tag = "spans",
+    params(("id" = String, Path, description = "Annotation id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn delete_annotation(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<AnnotationId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let deleted = store
+        .delete_annotation(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !deleted {
+        return Err(api_error(StatusCode::NOT_FOUND, "annotation not found")
+            .with_code("annotation_not_found"));
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "annotation.delete",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::{SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+    use crate::api::AnyBackend;
+
+    async fn matching(store: &PersistentStore<AnyBackend>, query: &str) -> Vec<SpanId> {
+        let filter = storage::parse_span_query(query).unwrap();
+        let mut ids: Vec<SpanId> = store
+            .query_spans(&filter)
+            .await
+            .unwrap()
+            .items
+            .iter()
+            .map(|s| s.id())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn span_queries_select_by_annotation() {
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = PersistentStore::open(backend).await.unwrap();
+        let trace = Trace::new(None);
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+
+        let mut ids = Vec::new();
+        for annotated in [Some((0.9, "helpful")), Some((0.2, "hallucination")), None] {
+            let kind = SpanKind::Custom {
+                kind: "step".into(),
+                attributes: Default::default(),
+            };
+            let id = store
+                .insert(SpanBuilder::new(trace_id, "call", kind).build())
+                .await
+                .unwrap();
+            if let Some((score, label)) = annotated {
+                let mut annotation = Annotation::new(id, trace_id, "local".into());
+                annotation.score = Some(score);
+                annotation.label = Some(label.into());
+                store.save_annotation(&annotation).await.unwrap();
+            }
+            ids.push(id);
+        }
+
+        assert_eq!(matching(&store, "score:>0.5").await, [ids[0]]);
+        assert_eq!(matching(&store, "score:<0.5").await, [ids[1]]);
+        assert_eq!(matching(&store, "label:hallucination").await, [ids[1]]);
+        assert!(matching(&store, "score:>0.5 label:hallucination").await.is_empty());
+        assert_eq!(matching(&store, "call").await.len(), 3);
+    }
+}
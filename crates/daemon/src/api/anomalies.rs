@@ -0,0 +1,596 @@
+//! Anomaly detection on latency and error rates.
+//!
+//! Every [`CHECK_INTERVAL`], finished spans are grouped by model (or, for
+//! spans without one, by name) and each group's average latency and error
+//! rate over the interval are compared with an exponentially weighted
+//! baseline of the intervals before it. A value more than [`Z_THRESHOLD`]
+//! standard deviations above its baseline emits an `anomaly_detected` event
+//! and is kept in the org's history, served by `GET /api/anomalies`. Spans
+//! are read from the backend. One still running when the interval it
+//! started in is judged is looked up again on later checks and counted in
+//! the interval it is first seen finished in.
+//!
+//! Nothing needs configuring: a series is judged once its baseline has seen
+//! [`WARMUP_INTERVALS`] busy intervals, and alerts once per excursion, not
+//! again until it is back within the threshold.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{SpanFilter, StorageError};
+use tokio::sync::Mutex;
+use trace::{OrgId, Span, SpanId, SpanStatus};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::error::Problem;
+use super::events::EventJournal;
+use super::{
+    api_error, org_store, require_scope, ApiError, AppState, OrgStoreManager, SharedStore,
+    SystemEvent,
+};
+
+/// Settings key an org's anomaly history is saved under.
+pub const ANOMALIES_SETTING: &str = "anomalies";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How long spans get to finish before the interval they started in is
+/// judged, in seconds.
+const SETTLE_SECS: i64 = 60;
+/// Weight of the newest interval in the baseline.
+const ALPHA: f64 = 0.2;
+const Z_THRESHOLD: f64 = 3.0;
+const WARMUP_INTERVALS: u32 = 10;
+/// Intervals with fewer finished spans in a series are skipped, so a
+/// single slow call to a rarely used model isn't an anomaly.
+const MIN_SPANS: usize = 5;
+/// Series tracked per org; new ones beyond this are ignored.
+const MAX_SERIES: usize = 1_000;
+/// Running spans followed per org until they finish; more are left
+/// uncounted.
+const MAX_UNFINISHED: usize = 10_000;
+/// Running spans looked up per backend query.
+const UNFINISHED_BATCH: usize = 500;
+/// Anomalies kept per org, oldest dropped first.
+const MAX_HISTORY: usize = 500;
+const DEFAULT_LIMIT: usize = 100;
+
+/// The spans an anomaly was found in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnomalySubject {
+    Model {
+        model: String,
+    },
+    /// Spans without a model, by name.
+    Span {
+        name: String,
+    },
+}
+
+impl AnomalySubject {
+    fn of(span: &Span) -> Self {
+        match span.kind().model() {
+            Some(model) => AnomalySubject::Model {
+                model: model.to_string(),
+            },
+            None => AnomalySubject::Span {
+                name: span.group_name().to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyMetric {
+    AvgLatencyMs,
+    /// Failed spans over finished spans, from 0 to 1.
+    ErrorRate,
+}
+
+impl AnomalyMetric {
+    const ALL: [AnomalyMetric; 2] = [AnomalyMetric::AvgLatencyMs, AnomalyMetric::ErrorRate];
+
+    /// Smallest standard deviation assumed around a baseline `mean`, so a
+    /// series that has been perfectly flat doesn't flag ordinary noise.
+    fn min_std(self, mean: f64) -> f64 {
+        match self {
+            AnomalyMetric::AvgLatencyMs => (mean * 0.1).max(1.0),
+            AnomalyMetric::ErrorRate => 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Anomaly {
+    #[schema(value_type = String)]
+    pub id: Uuid,
+    pub subject: AnomalySubject,
+    pub metric: AnomalyMetric,
+    /// The metric over the interval.
+    pub value: f64,
+    /// The baseline's mean before the interval.
+    pub expected: f64,
+    /// Standard deviations above the baseline.
+    pub z_score: f64,
+    /// Finished spans in the interval.
+    pub span_count: usize,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Exponentially weighted mean and variance of a metric's past intervals.
+#[derive(Debug, Clone, Default)]
+struct Baseline {
+    mean: f64,
+    var: f64,
+    intervals: u32,
+}
+
+impl Baseline {
+    /// Standard deviations `value` lies from the baseline, once warmed up,
+    /// then fold `value` in.
+    fn observe(&mut self, value: f64, min_std: f64) -> Option<f64> {
+        let z = (self.intervals >= WARMUP_INTERVALS)
+            .then(|| (value - self.mean) / self.var.sqrt().max(min_std));
+        if self.intervals == 0 {
+            self.mean = value;
+        } else {
+            let diff = value - self.mean;
+            self.mean += ALPHA * diff;
+            self.var = (1.0 - ALPHA) * (self.var + ALPHA * diff * diff);
+        }
+        self.intervals += 1;
+        z
+    }
+}
+
+#[derive(Debug, Default)]
+struct Series {
+    baseline: Baseline,
+    /// Alerted and not yet back within the threshold.
+    firing: bool,
+}
+
+/// Finished spans of one subject in one interval.
+#[derive(Debug, Default)]
+struct IntervalStats {
+    spans: usize,
+    errors: usize,
+    latency_sum_ms: f64,
+}
+
+impl IntervalStats {
+    fn value(&self, metric: AnomalyMetric) -> f64 {
+        match metric {
+            AnomalyMetric::AvgLatencyMs => self.latency_sum_ms / self.spans as f64,
+            AnomalyMetric::ErrorRate => self.errors as f64 / self.spans as f64,
+        }
+    }
+}
+
+fn interval_stats<'a>(
+    spans: impl IntoIterator<Item = &'a Span>,
+) -> HashMap<AnomalySubject, IntervalStats> {
+    let mut stats: HashMap<AnomalySubject, IntervalStats> = HashMap::new();
+    for span in spans {
+        let Some(ms) = span.duration_ms() else {
+            continue;
+        };
+        let entry = stats.entry(AnomalySubject::of(span)).or_default();
+        entry.spans += 1;
+        entry.latency_sum_ms += ms as f64;
+        if matches!(span.status(), SpanStatus::Failed { .. }) {
+            entry.errors += 1;
+        }
+    }
+    stats
+}
+
+/// One org's series, and the end of the last interval judged.
+struct OrgSeries {
+    series: HashMap<(AnomalySubject, AnomalyMetric), Series>,
+    checked_until: DateTime<Utc>,
+    /// Spans that were still running when the interval they started in
+    /// was judged.
+    unfinished: HashSet<SpanId>,
+}
+
+impl OrgSeries {
+    fn new(checked_until: DateTime<Utc>) -> Self {
+        Self {
+            series: HashMap::new(),
+            checked_until,
+            unfinished: HashSet::new(),
+        }
+    }
+
+    /// Judge the interval `[start, end)` and fold it into the baselines.
+    fn evaluate(
+        &mut self,
+        stats: HashMap<AnomalySubject, IntervalStats>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<Anomaly> {
+        let now = Utc::now();
+        let mut anomalies = Vec::new();
+        for (subject, interval) in stats {
+            if interval.spans < MIN_SPANS {
+                continue;
+            }
+            for metric in AnomalyMetric::ALL {
+                let key = (subject.clone(), metric);
+                if !self.series.contains_key(&key) && self.series.len() >= MAX_SERIES {
+                    continue;
+                }
+                let series = self.series.entry(key).or_default();
+                let value = interval.value(metric);
+                let expected = series.baseline.mean;
+                let z = series
+                    .baseline
+                    .observe(value, metric.min_std(expected))
+                    .filter(|z| *z >= Z_THRESHOLD);
+                match z {
+                    Some(z_score) if !series.firing => {
+                        series.firing = true;
+                        anomalies.push(Anomaly {
+                            id: Uuid::now_v7(),
+                            subject: subject.clone(),
+                            metric,
+                            value,
+                            expected,
+                            z_score,
+                            span_count: interval.spans,
+                            window_start: start,
+                            window_end: end,
+                            detected_at: now,
+                        });
+                    }
+                    Some(_) => {}
+                    None => series.firing = false,
+                }
+            }
+        }
+        anomalies
+    }
+}
+
+/// Baselines for every org with open stores.
+pub struct AnomalyDetector {
+    org_stores: Arc<OrgStoreManager>,
+    orgs: Mutex<HashMap<OrgId, OrgSeries>>,
+}
+
+impl AnomalyDetector {
+    pub fn new(org_stores: Arc<OrgStoreManager>) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            orgs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Judge the org's spans since the last check, along with running
+    /// spans from earlier intervals that have since finished. The first
+    /// check only marks where the next one starts.
+    async fn check(&self, org_id: OrgId) -> Result<Vec<Anomaly>, StorageError> {
+        let end = Utc::now() - chrono::Duration::seconds(SETTLE_SECS);
+        let (start, unfinished) = {
+            let mut orgs = self.orgs.lock().await;
+            let org = orgs.entry(org_id).or_insert_with(|| OrgSeries::new(end));
+            if org.checked_until >= end {
+                return Ok(Vec::new());
+            }
+            let unfinished: Vec<SpanId> = org.unfinished.iter().copied().collect();
+            (org.checked_until, unfinished)
+        };
+        let filter = SpanFilter {
+            since: Some(start),
+            until: Some(end),
+            skip_payloads: true,
+            ..Default::default()
+        };
+        let mut finished = Vec::new();
+        let mut running = HashSet::new();
+        let mut sort = |span: &Span| match span.duration_ms() {
+            Some(_) => finished.push(span.clone()),
+            None => {
+                running.insert(span.id());
+            }
+        };
+        for store in self.org_stores.cached_stores_for_org(org_id).await {
+            store
+                .scan_spans(&filter, |span| {
+                    if span.started_at() < end {
+                        sort(span);
+                    }
+                })
+                .await?;
+            for ids in unfinished.chunks(UNFINISHED_BATCH) {
+                let filter = SpanFilter {
+                    span_ids: Some(ids.to_vec()),
+                    skip_payloads: true,
+                    ..Default::default()
+                };
+                store.scan_spans(&filter, &mut sort).await?;
+            }
+        }
+        let stats = interval_stats(&finished);
+        let mut orgs = self.orgs.lock().await;
+        let Some(org) = orgs.get_mut(&org_id) else {
+            return Ok(Vec::new());
+        };
+        org.checked_until = end;
+        org.unfinished = running.into_iter().take(MAX_UNFINISHED).collect();
+        Ok(org.evaluate(stats, start, end))
+    }
+
+    /// Add `anomalies` to the org's saved history.
+    async fn record(&self, org_id: OrgId, anomalies: &[Anomaly]) -> Result<(), String> {
+        let store = self.org_stores.get(org_id).await?;
+        let mut history = saved_history(&store).await?;
+        history.extend_from_slice(anomalies);
+        let excess = history.len().saturating_sub(MAX_HISTORY);
+        history.drain(..excess);
+        let value = serde_json::to_value(&history).map_err(|e| e.to_string())?;
+        store
+            .save_setting(ANOMALIES_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The org's anomalies, oldest first.
+async fn saved_history(store: &SharedStore) -> Result<Vec<Anomaly>, String> {
+    match store.get_setting(ANOMALIES_SETTING).await {
+        Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+        Ok(None) => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Spawn the periodic check, which emits `anomaly_detected` events. It
+/// holds the journal weakly and stops once the router that owns it is gone.
+pub fn spawn_anomaly_detector(
+    detector: Arc<AnomalyDetector>,
+    journal: Weak<EventJournal>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            let mut orgs: Vec<OrgId> = detector
+                .org_stores
+                .all_stores()
+                .await
+                .into_iter()
+                .map(|(org_id, _)| org_id)
+                .collect();
+            orgs.sort();
+            orgs.dedup();
+            for org_id in orgs {
+                let anomalies = match detector.check(org_id).await {
+                    Ok(anomalies) if !anomalies.is_empty() => anomalies,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        // The interval is judged on the next check instead
+                        warn!(%org_id, "failed to check for anomalies: {e}");
+                        continue;
+                    }
+                };
+                if let Err(e) = detector.record(org_id, &anomalies).await {
+                    warn!(%org_id, "failed to save anomalies: {e}");
+                }
+                for anomaly in anomalies {
+                    warn!(
+                        %org_id,
+                        subject = ?anomaly.subject,
+                        metric = ?anomaly.metric,
+                        value = anomaly.value,
+                        expected = anomaly.expected,
+                        "anomaly detected"
+                    );
+                    journal.emit(
+                        &org_id.to_string(),
+                        SystemEvent::AnomalyDetected { anomaly },
+                    );
+                }
+            }
+        }
+    })
+}
+
+// --- Handlers ---
+
+/// Query parameters for `GET /api/anomalies`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnomalyQuery {
+    /// Only anomalies detected at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Most recent anomalies to return (default 100).
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AnomalyList {
+    /// Newest first.
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Anomalies detected in the org's latency and error rates. The last 500
+/// are kept.
+#[utoipa::path(
+    get,
+    path = "/api/anomalies",
+    tag = "analytics",
+    params(AnomalyQuery),
+    responses(
+        (status = 200, body = AnomalyList),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_anomalies(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(query): Query<AnomalyQuery>,
+) -> Result<Json<AnomalyList>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let store = org_store(&ctx, &state).await?;
+    let history = saved_history(&store)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let anomalies = history
+        .into_iter()
+        .rev()
+        .filter(|a| query.since.is_none_or(|since| a.detected_at >= since))
+        .take(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_HISTORY))
+        .collect();
+    Ok(Json(AnomalyList { anomalies }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(
+        spans: usize,
+        errors: usize,
+        avg_ms: f64,
+    ) -> HashMap<AnomalySubject, IntervalStats> {
+        let subject = AnomalySubject::Model {
+            model: "gpt-4o".into(),
+        };
+        let stats = IntervalStats {
+            spans,
+            errors,
+            latency_sum_ms: avg_ms * spans as f64,
+        };
+        HashMap::from([(subject, stats)])
+    }
+
+    #[test]
+    fn spikes_alert_once_after_warmup() {
+        let mut org = OrgSeries::new(Utc::now());
+        let now = Utc::now();
+        let mut judge = |stats| org.evaluate(stats, now, now);
+
+        for i in 0..WARMUP_INTERVALS {
+            let jitter = if i % 2 == 0 { 20.0 } else { -20.0 };
+            assert!(judge(interval(20, 0, 1_000.0 + jitter)).is_empty());
+        }
+        // Too few spans to judge
+        assert!(judge(interval(2, 2, 9_000.0)).is_empty());
+
+        let found = judge(interval(20, 10, 4_000.0));
+        let mut metrics: Vec<_> = found.iter().map(|a| a.metric).collect();
+        metrics.sort_by_key(|m| *m as u8);
+        assert_eq!(
+            metrics,
+            [AnomalyMetric::AvgLatencyMs, AnomalyMetric::ErrorRate]
+        );
+        assert!(found.iter().all(|a| a.z_score >= Z_THRESHOLD));
+
+        // Higher still, but already alerted on
+        assert!(judge(interval(20, 20, 40_000.0)).is_empty());
+    }
+
+    #[test]
+    fn spans_without_a_model_group_by_name() {
+        let span = trace::SpanBuilder::new(
+            Uuid::now_v7(),
+            "search",
+            trace::SpanKind::ToolCall {
+                tool_name: "search".into(),
+                arguments: None,
+                result_preview: None,
+            },
+        )
+        .build()
+        .complete(None);
+        let stats = interval_stats([&span]);
+        let subject = AnomalySubject::Span {
+            name: "search".into(),
+        };
+        assert_eq!(stats[&subject].spans, 1);
+    }
+
+    #[tokio::test]
+    async fn spans_finishing_after_their_interval_are_counted() {
+        use storage::PersistentStore;
+        use storage_sqlite::SqliteBackend;
+
+        use crate::api::AnyBackend;
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open_lazy(backend).await.unwrap());
+        let detector = AnomalyDetector::new(Arc::new(OrgStoreManager::single(store.clone())));
+        let org_id = OrgId::nil();
+        let started_at = Utc::now() - chrono::Duration::minutes(5);
+        detector.orgs.lock().await.insert(
+            org_id,
+            OrgSeries::new(started_at - chrono::Duration::minutes(1)),
+        );
+
+        let mut ids = Vec::new();
+        for _ in 0..MIN_SPANS {
+            let span = Span::from_parts(
+                Uuid::now_v7(),
+                Uuid::now_v7(),
+                None,
+                None,
+                "call".into(),
+                trace::SpanKind::LlmCall {
+                    model: "gpt-4o".into(),
+                    provider: None,
+                    input_tokens: None,
+                    output_tokens: None,
+                    cost: None,
+                    input_preview: None,
+                    output_preview: None,
+                },
+                SpanStatus::Running,
+                started_at,
+                None,
+                None,
+                None,
+            );
+            ids.push(store.insert(span).await.unwrap());
+        }
+
+        // Still running when their interval is judged
+        detector.check(org_id).await.unwrap();
+        {
+            let orgs = detector.orgs.lock().await;
+            assert_eq!(orgs[&org_id].unfinished.len(), MIN_SPANS);
+            assert!(orgs[&org_id].series.is_empty());
+        }
+
+        for id in ids {
+            store.complete_span(id, None).await.unwrap();
+        }
+        detector.check(org_id).await.unwrap();
+        let orgs = detector.orgs.lock().await;
+        assert!(orgs[&org_id].unfinished.is_empty());
+        let key = (
+            AnomalySubject::Model {
+                model: "gpt-4o".into(),
+            },
+            AnomalyMetric::AvgLatencyMs,
+        );
+        assert_eq!(orgs[&org_id].series[&key].baseline.intervals, 1);
+    }
+}
@@ -3,25 +3,156 @@
 //! `AnyBackend` wraps the concrete backend implementations (SQLite for local,
 //! Turbopuffer for cloud) behind a single type so that the rest of the codebase
 //! can be monomorphic over `PersistentStore<AnyBackend>`.
+//!
+//! During a move between backends, `AnyBackend::DualWrite` serves reads from
+//! the old backend and writes to both, so the new one stays current after
+//! `traceway migrate` has copied the history.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use storage_sqlite::SqliteBackend;
 use storage_turbopuffer::TurbopufferBackend;
 use trace::{
-    CaptureRule, CaptureRuleId, Datapoint, DatapointId, Dataset, DatasetId, EvalResult,
-    EvalResultId, EvalRun, EvalRunId, FileVersion, ProviderConnection, ProviderConnectionId,
-    QueueItem, QueueItemId, Span, SpanId, Trace, TraceId,
+    Annotation, AnnotationId, AuditEvent, CaptureRule, CaptureRuleId, Datapoint, DatapointId,
+    Dataset, DatasetId, EvalResult, EvalResultId, EvalRun, EvalRunId, FileVersion, Machine,
+    ProviderConnection, ProviderConnectionId, QueueItem, QueueItemId, QueueSubmission, SavedView,
+    SavedViewId, Session, Span, SpanId, SpanKindDefinition, Trace, TraceId, Webhook,
+    WebhookDelivery, WebhookId,
 };
 
 use storage::error::StorageError;
-use storage::filter::{SpanFilter, TraceFilter};
-use storage::StorageBackend;
+use storage::filter::{AnnotationFilter, AuditFilter, SpanFilter, TraceFilter};
+use storage::{ScoredSpan, StorageBackend};
+use tracing::warn;
 
 /// A storage backend that dispatches to either SQLite (local) or Turbopuffer (cloud)
 /// at runtime.
 pub enum AnyBackend {
     Sqlite(SqliteBackend),
     Turbopuffer(TurbopufferBackend),
+    DualWrite(Box<DualWrite>),
+}
+
+/// Reads from `primary`; writes go to `primary`, then to `mirror`. A
+/// failed mirror write is logged and counted (`mirror_failures` in
+/// `/api/health` and on `/metrics`) but doesn't fail the write, so a
+/// cutover never takes the old backend down with it. Run
+/// `traceway migrate --verify` before switching over to catch writes the
+/// mirror missed.
+pub struct DualWrite {
+    primary: AnyBackend,
+    mirror: AnyBackend,
+    mirror_failures: AtomicU64,
+}
+
+impl DualWrite {
+    fn mirror_failed(&self, method: &str, e: &StorageError) {
+        let failures = self.mirror_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            method,
+            mirror = self.mirror.backend_type(),
+            failures,
+            "dual write: mirror write failed: {e}"
+        );
+    }
+}
+
+impl AnyBackend {
+    /// Mirror every write to `mirror` while reading from `self`.
+    pub fn dual_write(self, mirror: AnyBackend) -> Self {
+        AnyBackend::DualWrite(Box::new(DualWrite {
+            primary: self,
+            mirror,
+            mirror_failures: AtomicU64::new(0),
+        }))
+    }
+
+    /// Writes the mirror has failed since opening; `None` unless dual
+    /// writing.
+    pub fn mirror_failures(&self) -> Option<u64> {
+        match self {
+            AnyBackend::DualWrite(d) => Some(d.mirror_failures.load(Ordering::Relaxed)),
+            _ => None,
+        }
+    }
+
+    /// Turbopuffer write-batching counters; `None` for SQLite or when
+    /// batching is disabled.
+    pub fn write_batch_stats(&self) -> Option<storage_turbopuffer::BatchStats> {
+        match self {
+            AnyBackend::Sqlite(_) => None,
+            AnyBackend::Turbopuffer(b) => b.batch_stats(),
+            AnyBackend::DualWrite(d) => d.primary.write_batch_stats(),
+        }
+    }
+
+    /// Turbopuffer request retry counters; `None` for SQLite.
+    pub fn retry_stats(&self) -> Option<storage_turbopuffer::RetryStats> {
+        match self {
+            AnyBackend::Sqlite(_) => None,
+            AnyBackend::Turbopuffer(b) => Some(b.retry_stats()),
+            AnyBackend::DualWrite(d) => d.primary.retry_stats(),
+        }
+    }
+}
+
+/// Where `traceway migrate` and `storage.dual_write` find a backend:
+/// `sqlite:<path>` or `turbopuffer:<namespace prefix>`. Turbopuffer
+/// credentials come from the usual `TURBOPUFFER_*` environment variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendSpec {
+    Sqlite(PathBuf),
+    Turbopuffer(String),
+}
+
+impl FromStr for BackendSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(BackendSpec::Sqlite(path.into())),
+            Some(("turbopuffer", namespace)) if !namespace.is_empty() => {
+                Ok(BackendSpec::Turbopuffer(namespace.to_string()))
+            }
+            _ => Err(format!(
+                "invalid backend {s:?}: expected sqlite:<path> or turbopuffer:<namespace>"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BackendSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendSpec::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+            BackendSpec::Turbopuffer(namespace) => write!(f, "turbopuffer:{namespace}"),
+        }
+    }
+}
+
+impl BackendSpec {
+    pub async fn open(&self) -> Result<AnyBackend, String> {
+        match self {
+            BackendSpec::Sqlite(path) => SqliteBackend::open(path)
+                .map(AnyBackend::Sqlite)
+                .map_err(|e| format!("{self}: {e}")),
+            BackendSpec::Turbopuffer(namespace) => {
+                let config = storage_turbopuffer::TurbopufferConfig::from_env()
+                    .map_err(|e| format!("{self}: {e}"))?
+                    .with_namespace(namespace);
+                let backend =
+                    TurbopufferBackend::new(config).map_err(|e| format!("{self}: {e}"))?;
+                if let Err(e) = backend.ensure_schemas().await {
+                    warn!(%namespace, error = %e, "Failed to verify Turbopuffer schemas");
+                }
+                Ok(AnyBackend::Turbopuffer(backend))
+            }
+        }
+    }
 }
 
 macro_rules! delegate {
@@ -29,16 +160,48 @@ macro_rules! delegate {
         match $self {
             AnyBackend::Sqlite(b) => b.$method($($arg),*).await,
             AnyBackend::Turbopuffer(b) => b.$method($($arg),*).await,
+            AnyBackend::DualWrite(d) => d.primary.$method($($arg),*).await,
         }
     };
 }
 
+/// Delegate a write, mirroring it when dual writing.
+macro_rules! mirrored_write {
+    ($self:ident, $method:ident $(, $arg:expr)*) => {
+        match $self {
+            AnyBackend::DualWrite(d) => {
+                let result = d.primary.$method($($arg),*).await;
+                if result.is_ok() {
+                    if let Err(e) = d.mirror.$method($($arg),*).await {
+                        d.mirror_failed(stringify!($method), &e);
+                    }
+                }
+                result
+            }
+            _ => delegate!($self, $method $(, $arg)*),
+        }
+    };
+}
+
+/// Delegate a write, recording its latency under the method's name. A
+/// dual write's primary records its own.
+macro_rules! timed_write {
+    ($self:ident, $method:ident $(, $arg:expr)*) => {{
+        let start = std::time::Instant::now();
+        let result = mirrored_write!($self, $method $(, $arg)*);
+        if !matches!($self, AnyBackend::DualWrite(_)) {
+            super::metrics::global().record_storage_write(stringify!($method), start.elapsed());
+        }
+        result
+    }};
+}
+
 #[async_trait]
 impl StorageBackend for AnyBackend {
     // --- Trace operations ---
 
     async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
-        delegate!(self, save_trace, trace)
+        timed_write!(self, save_trace, trace)
     }
 
     async fn get_trace(&self, id: TraceId) -> Result<Option<Trace>, StorageError> {
@@ -49,14 +212,22 @@ impl StorageBackend for AnyBackend {
         delegate!(self, list_traces, filter)
     }
 
+    async fn list_sessions(&self, filter: &TraceFilter) -> Result<Vec<Session>, StorageError> {
+        delegate!(self, list_sessions, filter)
+    }
+
     async fn delete_trace(&self, id: TraceId) -> Result<bool, StorageError> {
-        delegate!(self, delete_trace, id)
+        mirrored_write!(self, delete_trace, id)
+    }
+
+    async fn delete_traces_by_filter(&self, filter: &TraceFilter) -> Result<usize, StorageError> {
+        mirrored_write!(self, delete_traces_by_filter, filter)
     }
 
     // --- Span operations ---
 
     async fn save_span(&self, span: &Span) -> Result<(), StorageError> {
-        delegate!(self, save_span, span)
+        timed_write!(self, save_span, span)
     }
 
     async fn get_span(&self, id: SpanId) -> Result<Option<Span>, StorageError> {
@@ -68,21 +239,25 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_span(&self, id: SpanId) -> Result<bool, StorageError> {
-        delegate!(self, delete_span, id)
+        mirrored_write!(self, delete_span, id)
     }
 
     async fn delete_trace_spans(&self, trace_id: TraceId) -> Result<usize, StorageError> {
-        delegate!(self, delete_trace_spans, trace_id)
+        mirrored_write!(self, delete_trace_spans, trace_id)
+    }
+
+    async fn delete_spans_by_filter(&self, filter: &SpanFilter) -> Result<usize, StorageError> {
+        mirrored_write!(self, delete_spans_by_filter, filter)
     }
 
     async fn clear_spans(&self) -> Result<(), StorageError> {
-        delegate!(self, clear_spans)
+        mirrored_write!(self, clear_spans)
     }
 
     // --- Dataset operations ---
 
     async fn save_dataset(&self, dataset: &Dataset) -> Result<(), StorageError> {
-        delegate!(self, save_dataset, dataset)
+        mirrored_write!(self, save_dataset, dataset)
     }
 
     async fn get_dataset(&self, id: DatasetId) -> Result<Option<Dataset>, StorageError> {
@@ -94,13 +269,30 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_dataset(&self, id: DatasetId) -> Result<bool, StorageError> {
-        delegate!(self, delete_dataset, id)
+        mirrored_write!(self, delete_dataset, id)
+    }
+
+    async fn update_dataset(&self, dataset: &Dataset, expected: u64) -> Result<bool, StorageError> {
+        match self {
+            // The primary decides; the mirror may lag, so it takes the
+            // winning write as is.
+            AnyBackend::DualWrite(d) => {
+                let updated = d.primary.update_dataset(dataset, expected).await?;
+                if updated {
+                    if let Err(e) = d.mirror.save_dataset(dataset).await {
+                        d.mirror_failed("update_dataset", &e);
+                    }
+                }
+                Ok(updated)
+            }
+            _ => delegate!(self, update_dataset, dataset, expected),
+        }
     }
 
     // --- Datapoint operations ---
 
     async fn save_datapoint(&self, dp: &Datapoint) -> Result<(), StorageError> {
-        delegate!(self, save_datapoint, dp)
+        mirrored_write!(self, save_datapoint, dp)
     }
 
     async fn get_datapoint(&self, id: DatapointId) -> Result<Option<Datapoint>, StorageError> {
@@ -112,20 +304,35 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_datapoint(&self, id: DatapointId) -> Result<bool, StorageError> {
-        delegate!(self, delete_datapoint, id)
+        mirrored_write!(self, delete_datapoint, id)
+    }
+
+    async fn update_datapoint(&self, dp: &Datapoint, expected: u64) -> Result<bool, StorageError> {
+        match self {
+            AnyBackend::DualWrite(d) => {
+                let updated = d.primary.update_datapoint(dp, expected).await?;
+                if updated {
+                    if let Err(e) = d.mirror.save_datapoint(dp).await {
+                        d.mirror_failed("update_datapoint", &e);
+                    }
+                }
+                Ok(updated)
+            }
+            _ => delegate!(self, update_datapoint, dp, expected),
+        }
     }
 
     async fn delete_dataset_datapoints(
         &self,
         dataset_id: DatasetId,
     ) -> Result<usize, StorageError> {
-        delegate!(self, delete_dataset_datapoints, dataset_id)
+        mirrored_write!(self, delete_dataset_datapoints, dataset_id)
     }
 
     // --- Queue operations ---
 
     async fn save_queue_item(&self, item: &QueueItem) -> Result<(), StorageError> {
-        delegate!(self, save_queue_item, item)
+        mirrored_write!(self, save_queue_item, item)
     }
 
     async fn get_queue_item(&self, id: QueueItemId) -> Result<Option<QueueItem>, StorageError> {
@@ -140,13 +347,27 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_queue_item(&self, id: QueueItemId) -> Result<bool, StorageError> {
-        delegate!(self, delete_queue_item, id)
+        mirrored_write!(self, delete_queue_item, id)
+    }
+
+    async fn save_queue_submission(
+        &self,
+        submission: &QueueSubmission,
+    ) -> Result<(), StorageError> {
+        mirrored_write!(self, save_queue_submission, submission)
+    }
+
+    async fn list_queue_submissions(
+        &self,
+        queue_item_id: QueueItemId,
+    ) -> Result<Vec<QueueSubmission>, StorageError> {
+        delegate!(self, list_queue_submissions, queue_item_id)
     }
 
     // --- Eval Run operations ---
 
     async fn save_eval_run(&self, run: &EvalRun) -> Result<(), StorageError> {
-        delegate!(self, save_eval_run, run)
+        mirrored_write!(self, save_eval_run, run)
     }
 
     async fn get_eval_run(&self, id: EvalRunId) -> Result<Option<EvalRun>, StorageError> {
@@ -158,13 +379,13 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_eval_run(&self, id: EvalRunId) -> Result<bool, StorageError> {
-        delegate!(self, delete_eval_run, id)
+        mirrored_write!(self, delete_eval_run, id)
     }
 
     // --- Eval Result operations ---
 
     async fn save_eval_result(&self, result: &EvalResult) -> Result<(), StorageError> {
-        delegate!(self, save_eval_result, result)
+        mirrored_write!(self, save_eval_result, result)
     }
 
     async fn get_eval_result(&self, id: EvalResultId) -> Result<Option<EvalResult>, StorageError> {
@@ -176,34 +397,46 @@ impl StorageBackend for AnyBackend {
     }
 
     async fn delete_eval_run_results(&self, run_id: EvalRunId) -> Result<usize, StorageError> {
-        delegate!(self, delete_eval_run_results, run_id)
+        mirrored_write!(self, delete_eval_run_results, run_id)
     }
 
     // --- Capture Rule operations ---
 
     async fn save_capture_rule(&self, rule: &CaptureRule) -> Result<(), StorageError> {
-        delegate!(self, save_capture_rule, rule)
+        mirrored_write!(self, save_capture_rule, rule)
     }
 
-    async fn get_capture_rule(&self, id: CaptureRuleId) -> Result<Option<CaptureRule>, StorageError> {
+    async fn get_capture_rule(
+        &self,
+        id: CaptureRuleId,
+    ) -> Result<Option<CaptureRule>, StorageError> {
         delegate!(self, get_capture_rule, id)
     }
 
-    async fn list_capture_rules(&self, dataset_id: DatasetId) -> Result<Vec<CaptureRule>, StorageError> {
+    async fn list_capture_rules(
+        &self,
+        dataset_id: DatasetId,
+    ) -> Result<Vec<CaptureRule>, StorageError> {
         delegate!(self, list_capture_rules, dataset_id)
     }
 
     async fn delete_capture_rule(&self, id: CaptureRuleId) -> Result<bool, StorageError> {
-        delegate!(self, delete_capture_rule, id)
+        mirrored_write!(self, delete_capture_rule, id)
     }
 
     // --- Provider Connection operations ---
 
-    async fn save_provider_connection(&self, conn: &ProviderConnection) -> Result<(), StorageError> {
-        delegate!(self, save_provider_connection, conn)
+    async fn save_provider_connection(
+        &self,
+        conn: &ProviderConnection,
+    ) -> Result<(), StorageError> {
+        mirrored_write!(self, save_provider_connection, conn)
     }
 
-    async fn get_provider_connection(&self, id: ProviderConnectionId) -> Result<Option<ProviderConnection>, StorageError> {
+    async fn get_provider_connection(
+        &self,
+        id: ProviderConnectionId,
+    ) -> Result<Option<ProviderConnection>, StorageError> {
         delegate!(self, get_provider_connection, id)
     }
 
@@ -211,36 +444,184 @@ impl StorageBackend for AnyBackend {
         delegate!(self, list_provider_connections)
     }
 
-    async fn delete_provider_connection(&self, id: ProviderConnectionId) -> Result<bool, StorageError> {
-        delegate!(self, delete_provider_connection, id)
+    async fn delete_provider_connection(
+        &self,
+        id: ProviderConnectionId,
+    ) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_provider_connection, id)
+    }
+
+    // --- Span Kind Definition operations ---
+
+    async fn save_span_kind(&self, def: &SpanKindDefinition) -> Result<(), StorageError> {
+        mirrored_write!(self, save_span_kind, def)
+    }
+
+    async fn list_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError> {
+        delegate!(self, list_span_kinds)
+    }
+
+    async fn delete_span_kind(&self, name: &str) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_span_kind, name)
+    }
+
+    // --- Machine registry ---
+
+    async fn save_machine(&self, machine: &Machine) -> Result<(), StorageError> {
+        mirrored_write!(self, save_machine, machine)
+    }
+
+    async fn list_machines(&self) -> Result<Vec<Machine>, StorageError> {
+        delegate!(self, list_machines)
+    }
+
+    async fn delete_machine(&self, id: &str) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_machine, id)
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<serde_json::Value>, StorageError> {
+        delegate!(self, get_setting, key)
+    }
+
+    async fn save_setting(&self, key: &str, value: &serde_json::Value) -> Result<(), StorageError> {
+        mirrored_write!(self, save_setting, key, value)
+    }
+
+    async fn delete_settings(&self, keys: &[String]) -> Result<(), StorageError> {
+        mirrored_write!(self, delete_settings, keys)
+    }
+
+    async fn update_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, StorageError> {
+        match self {
+            // As with datasets, the primary decides
+            AnyBackend::DualWrite(d) => {
+                let updated = d.primary.update_setting(key, value, expected).await?;
+                if updated {
+                    if let Err(e) = d.mirror.save_setting(key, value).await {
+                        d.mirror_failed("update_setting", &e);
+                    }
+                }
+                Ok(updated)
+            }
+            _ => delegate!(self, update_setting, key, value, expected),
+        }
+    }
+
+    async fn save_webhook(&self, webhook: &Webhook) -> Result<(), StorageError> {
+        mirrored_write!(self, save_webhook, webhook)
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, StorageError> {
+        delegate!(self, list_webhooks)
+    }
+
+    async fn delete_webhook(&self, id: WebhookId) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_webhook, id)
+    }
+
+    async fn save_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), StorageError> {
+        mirrored_write!(self, save_webhook_delivery, delivery)
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: WebhookId,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, StorageError> {
+        delegate!(self, list_webhook_deliveries, webhook_id, limit)
+    }
+
+    async fn save_audit_event(&self, event: &AuditEvent) -> Result<(), StorageError> {
+        mirrored_write!(self, save_audit_event, event)
+    }
+
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, StorageError> {
+        delegate!(self, list_audit_events, filter)
+    }
+
+    async fn delete_audit_events_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError> {
+        mirrored_write!(self, delete_audit_events_before, cutoff)
+    }
+
+    async fn save_annotation(&self, annotation: &Annotation) -> Result<(), StorageError> {
+        mirrored_write!(self, save_annotation, annotation)
+    }
+
+    async fn list_annotations(
+        &self,
+        filter: &AnnotationFilter,
+    ) -> Result<Vec<Annotation>, StorageError> {
+        delegate!(self, list_annotations, filter)
+    }
+
+    async fn delete_annotation(&self, id: AnnotationId) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_annotation, id)
+    }
+
+    async fn save_view(&self, view: &SavedView) -> Result<(), StorageError> {
+        mirrored_write!(self, save_view, view)
+    }
+
+    async fn list_views(&self) -> Result<Vec<SavedView>, StorageError> {
+        delegate!(self, list_views)
+    }
+
+    async fn delete_view(&self, id: SavedViewId) -> Result<bool, StorageError> {
+        mirrored_write!(self, delete_view, id)
     }
 
     // --- File operations ---
 
     async fn save_file_version(&self, version: &FileVersion) -> Result<(), StorageError> {
-        delegate!(self, save_file_version, version)
+        mirrored_write!(self, save_file_version, version)
     }
 
     async fn list_file_versions(&self) -> Result<Vec<FileVersion>, StorageError> {
         delegate!(self, list_file_versions)
     }
 
+    async fn delete_file_versions_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError> {
+        mirrored_write!(self, delete_file_versions_before, cutoff)
+    }
+
     async fn save_file_content(&self, hash: &str, content: &[u8]) -> Result<(), StorageError> {
-        delegate!(self, save_file_content, hash, content)
+        mirrored_write!(self, save_file_content, hash, content)
     }
 
     async fn load_file_content(&self, hash: &str) -> Result<Vec<u8>, StorageError> {
         delegate!(self, load_file_content, hash)
     }
 
+    async fn list_file_contents(&self) -> Result<Vec<(String, u64)>, StorageError> {
+        delegate!(self, list_file_contents)
+    }
+
+    async fn delete_file_contents(&self, hashes: &[String]) -> Result<usize, StorageError> {
+        mirrored_write!(self, delete_file_contents, hashes)
+    }
+
     // --- Batch operations ---
 
     async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError> {
-        delegate!(self, save_spans_batch, spans)
+        timed_write!(self, save_spans_batch, spans)
     }
 
     async fn save_datapoints_batch(&self, datapoints: &[Datapoint]) -> Result<(), StorageError> {
-        delegate!(self, save_datapoints_batch, datapoints)
+        mirrored_write!(self, save_datapoints_batch, datapoints)
     }
 
     // --- Load-all operations ---
@@ -307,12 +688,38 @@ impl StorageBackend for AnyBackend {
         delegate!(self, load_all_provider_connections)
     }
 
+    async fn load_all_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError> {
+        delegate!(self, load_all_span_kinds)
+    }
+
+    // --- Search ---
+
+    async fn semantic_search(
+        &self,
+        query: &str,
+        filter: &SpanFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredSpan>, StorageError> {
+        delegate!(self, semantic_search, query, filter, limit)
+    }
+
+    // --- Durability ---
+
+    async fn flush(&self) -> Result<(), StorageError> {
+        mirrored_write!(self, flush)
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        delegate!(self, ping)
+    }
+
     // --- Metadata ---
 
     fn backend_type(&self) -> &'static str {
         match self {
             AnyBackend::Sqlite(b) => b.backend_type(),
             AnyBackend::Turbopuffer(b) => b.backend_type(),
+            AnyBackend::DualWrite(d) => d.primary.backend_type(),
         }
     }
 }
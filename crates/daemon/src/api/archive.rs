@@ -0,0 +1,443 @@
+//! Archiving old spans to compressed segments.
+//!
+//! A background task moves spans older than `archive.days` out of each
+//! open store into gzipped JSONL segments, kept in a local directory or an
+//! S3 bucket (see `storage::archive`). Reads that miss the database fall
+//! back to the segments, so archived spans stay visible in trace views and
+//! time-bounded queries.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use storage::{ArchiveReport, LocalSegments, SegmentInfo, SegmentStore, StorageError};
+use tracing::{error, info};
+
+use super::OrgStoreManager;
+use super::{
+    api_error, audit, events::EventJournal, project_store, require_scope, ApiError, AppState,
+};
+use crate::config::{ArchiveConfig, S3Config};
+
+/// Segments in an S3 bucket, or an S3-compatible store, addressed
+/// path-style and signed with AWS Signature Version 4.
+pub struct S3Segments {
+    client: reqwest::Client,
+    config: S3Config,
+    endpoint: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Segments {
+    /// Credentials come from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+    /// and, for temporary credentials, `AWS_SESSION_TOKEN`.
+    pub fn from_env(config: S3Config) -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("{name} is not set"));
+        let endpoint = config
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            access_key: var("AWS_ACCESS_KEY_ID")?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            config,
+        })
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, StorageError> {
+        let path = format!(
+            "/{}/{}",
+            self.config.bucket,
+            uri_encode(&format!("{}{}", self.config.prefix, key))
+        );
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, host)| host);
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let mut headers = vec![
+            ("host", host.to_string()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{value}\n"))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request =
+            format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+        let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &date, &self.config.region, "s3");
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.endpoint, path))
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key
+                ),
+            )
+            .body(body);
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        request
+            .send()
+            .await
+            .map_err(|e| StorageError::Network(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl SegmentStore for S3Segments {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let response = self.send(reqwest::Method::PUT, key, data).await?;
+        if !response.status().is_success() {
+            return Err(StorageError::Backend(format!(
+                "S3 PUT {key} returned {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        let response = self.send(reqwest::Method::GET, key, Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .bytes()
+                .await
+                .map(|b| Some(b.to_vec()))
+                .map_err(|e| StorageError::Network(e.to_string())),
+            status => Err(StorageError::Backend(format!(
+                "S3 GET {key} returned {status}"
+            ))),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let response = self.send(reqwest::Method::DELETE, key, Vec::new()).await?;
+        match response.status() {
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            status => Err(StorageError::Backend(format!(
+                "S3 DELETE {key} returned {status}"
+            ))),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{secret}").as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+/// Percent-encode a key for a SigV4 canonical URI, keeping `/`.
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Where the local store's segments are kept: S3 when configured, else
+/// `dir`.
+pub fn segment_store(
+    config: &ArchiveConfig,
+    dir: PathBuf,
+) -> Result<Arc<dyn SegmentStore>, String> {
+    Ok(match &config.s3 {
+        Some(s3) => Arc::new(S3Segments::from_env(s3.clone())?),
+        None => Arc::new(LocalSegments::new(dir)),
+    })
+}
+
+/// Archive old spans in every open store once.
+pub async fn archive_all(org_stores: &OrgStoreManager, days: u32) {
+    let cutoff = Utc::now() - chrono::Duration::days(i64::from(days));
+    for (org_id, store) in org_stores.all_stores().await {
+        match store.archive_before(cutoff, false).await {
+            Ok(r) if r.spans == 0 => {}
+            Ok(r) => info!(
+                %org_id,
+                days,
+                spans = r.spans,
+                segments = r.segments.len(),
+                "archive: moved old spans to segments"
+            ),
+            // Stores opened without an archive
+            Err(StorageError::Configuration(_)) => {}
+            Err(e) => error!(%org_id, "archive: failed: {e}"),
+        }
+    }
+}
+
+/// Spawn the periodic archival. It holds the journal weakly and stops once
+/// the router that owns it is gone.
+pub fn spawn_archive_task(
+    org_stores: Arc<OrgStoreManager>,
+    journal: Weak<EventJournal>,
+    config: ArchiveConfig,
+) -> tokio::task::JoinHandle<()> {
+    let period = Duration::from_secs(config.interval_secs.max(60));
+    info!(
+        days = config.days,
+        interval_secs = period.as_secs(),
+        "starting archive task"
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if journal.upgrade().is_none() {
+                return;
+            }
+            archive_all(&org_stores, config.days).await;
+        }
+    })
+}
+
+/// Query parameters for `POST /api/admin/archive`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ArchiveQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Archive spans older than this instead of `archive.days`.
+    pub days: Option<u32>,
+}
+
+/// Archive the caller's project's old spans now.
+pub async fn archive(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ArchiveQuery>,
+) -> Result<Json<ArchiveReport>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let days = q.days.unwrap_or(state.archive.days);
+    if days == 0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "days must be at least 1",
+        ));
+    }
+    let store = project_store(&ctx, &state).await?;
+    let cutoff = Utc::now() - chrono::Duration::days(i64::from(days));
+    let report = store
+        .archive_before(cutoff, q.dry_run)
+        .await
+        .map_err(|e| match e {
+            StorageError::Configuration(msg) => api_error(StatusCode::CONFLICT, msg),
+            e => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+        })?;
+    if !q.dry_run && report.spans > 0 {
+        audit::record(
+            &state,
+            &ctx,
+            "data.archive",
+            None,
+            serde_json::json!({
+                "days": days,
+                "spans": report.spans,
+                "segments": report.segments.len(),
+            }),
+        )
+        .await;
+    }
+    Ok(Json(report))
+}
+
+pub async fn list_segments(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SegmentInfo>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = project_store(&ctx, &state).await?;
+    let segments = store
+        .archive_segments()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{ClearScope, PersistentStore, SpanFilter, StorageBackend};
+    use storage_sqlite::SqliteBackend;
+    use trace::{SpanBuilder, SpanKind, TraceId};
+
+    use super::super::AnyBackend;
+    use super::*;
+
+    async fn open(dir: &std::path::Path) -> PersistentStore<AnyBackend> {
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(&dir.join("traces.db")).unwrap());
+        PersistentStore::open(backend)
+            .await
+            .unwrap()
+            .with_archive(Arc::new(LocalSegments::new(dir.join("archive"))))
+    }
+
+    #[tokio::test]
+    async fn archived_spans_stay_readable() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let trace_id = TraceId::new_v4();
+
+        let store = open(&dir).await;
+        let done = store
+            .insert(SpanBuilder::new(trace_id, "done", kind.clone()).build())
+            .await
+            .unwrap();
+        store.complete_span(done, None).await.unwrap();
+        let running = store
+            .insert(SpanBuilder::new(TraceId::new_v4(), "running", kind).build())
+            .await
+            .unwrap();
+        let cutoff = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(store.archive_before(cutoff, true).await.unwrap().spans, 1);
+        let report = store.archive_before(cutoff, false).await.unwrap();
+        assert_eq!(report.spans, 1);
+        assert_eq!(report.segments.len(), 1);
+        drop(store);
+
+        let store = open(&dir).await;
+        assert_eq!(store.get_or_load(done).await.unwrap().name(), "done");
+        assert_eq!(store.spans_for_trace_or_load(trace_id).await, vec![done]);
+        assert!(store.backend().get_span(running).await.unwrap().is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn deleted_and_cleared_traces_leave_the_archive() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let (deleted, kept) = (TraceId::new_v4(), TraceId::new_v4());
+
+        let store = open(&dir).await;
+        let mut ids = Vec::new();
+        for trace_id in [deleted, kept] {
+            let span = SpanBuilder::new(trace_id, "done", kind.clone()).build();
+            let id = store.insert(span).await.unwrap();
+            store.complete_span(id, None).await.unwrap();
+            ids.push(id);
+        }
+        let cutoff = Utc::now() + chrono::Duration::minutes(1);
+        store.archive_before(cutoff, false).await.unwrap();
+
+        store.delete_trace(deleted).await.unwrap();
+        assert!(store.spans_for_trace_or_load(deleted).await.is_empty());
+        assert!(store.get_or_load(ids[0]).await.is_none());
+        let in_trace = SpanFilter {
+            trace_id: Some(deleted),
+            ..Default::default()
+        };
+        assert!(store.query_spans(&in_trace).await.unwrap().items.is_empty());
+        // The other trace's spans stay archived
+        let segments = store.archive_segments().await.unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].trace_ids, [kept]);
+        assert_eq!(segments[0].span_count, 1);
+        assert_eq!(store.spans_for_trace_or_load(kept).await, vec![ids[1]]);
+
+        store.clear(ClearScope::All).await.unwrap();
+        assert!(store.archive_segments().await.unwrap().is_empty());
+        let segment_files = LocalSegments::new(dir.join("archive"));
+        assert!(segment_files.get(&segments[0].key).await.unwrap().is_none());
+        drop(store);
+
+        let store = open(&dir).await;
+        assert!(store.spans_for_trace_or_load(kept).await.is_empty());
+        let recent = SpanFilter {
+            since: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert!(store.query_spans(&recent).await.unwrap().items.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn uri_encode_keeps_path_separators() {
+        assert_eq!(
+            uri_encode("spans/2024/01/02/a b.jsonl.gz"),
+            "spans/2024/01/02/a%20b.jsonl.gz"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Org audit log.
+//!
+//! Handlers for destructive and admin operations call [`record`] once the
+//! operation has succeeded. Events are kept in the org-level store; a
+//! failure to record one is logged rather than failing the request. The
+//! retention task prunes events older than the org plan's audit window.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use storage::AuditFilter;
+use trace::AuditEvent;
+use tracing::warn;
+
+use super::{api_error, org_store, require_scope, ApiError, AppState, MAX_PAGE_LIMIT};
+
+/// Who `ctx` is, as recorded in `AuditEvent::actor`.
+pub fn actor(ctx: &auth::AuthContext) -> String {
+    if let Some(user_id) = ctx.user_id {
+        format!("user:{user_id}")
+    } else if let Some(prefix) = &ctx.api_key_prefix {
+        format!("api_key:{prefix}")
+    } else if ctx.is_local_mode {
+        "local".to_string()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// Append an event to the caller's org audit log.
+pub async fn record(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    action: &str,
+    target: Option<String>,
+    details: serde_json::Value,
+) {
+    let event = AuditEvent::new(actor(ctx), action, target, details);
+    let result = match state.store_for_org(ctx.org_id).await {
+        Ok(store) => store
+            .save_audit_event(&event)
+            .await
+            .map_err(|e| e.to_string()),
+        Err((_, e)) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!(org_id = %ctx.org_id, action, "audit: failed to record event: {e}");
+    }
+}
+
+/// Query parameters for `GET /api/org/audit-log`.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    /// An exact action, or a prefix ending in `.` such as `webhook.`.
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+/// The org's audit log, newest first.
+pub async fn list_audit_log(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = org_store(&ctx, &state).await?;
+    let filter = AuditFilter {
+        actor: q.actor,
+        action: q.action,
+        since: q.since,
+        until: q.until,
+        limit: Some(q.limit.unwrap_or(100).min(MAX_PAGE_LIMIT)),
+    };
+    let events = store
+        .list_audit_events(&filter)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(events))
+}
@@ -6,9 +6,22 @@
 //! - Query parameter auth extraction for SSE endpoints
 
 use async_trait::async_trait;
-use auth::{ApiKeyLookup, AuthConfig, OrgId, ProjectId, Scope};
+use std::net::SocketAddr;
+
+use auth::{ApiKeyGrant, ApiKeyLookup, AuthContext, AuthError, OrgId, ProjectId, Scope};
+#[cfg(feature = "cloud")]
+use auth::{AuthConfig, IpCidr};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
 use tracing::{debug, info};
 
+use super::AppState;
+
 /// API key record stored in memory
 #[derive(Clone)]
 pub struct ApiKeyRecord {
@@ -95,11 +108,10 @@ impl EnvApiKeyLookup {
 
 #[async_trait]
 impl ApiKeyLookup for EnvApiKeyLookup {
-    async fn lookup_api_key(&self, prefix: &str) -> Option<(OrgId, ProjectId, String, Vec<Scope>)> {
-        self.keys
-            .iter()
-            .find(|k| k.prefix == prefix)
-            .map(|k| (k.org_id, k.project_id, k.key_hash.clone(), k.scopes.clone()))
+    async fn lookup_api_key(&self, prefix: &str) -> Option<ApiKeyGrant> {
+        self.keys.iter().find(|k| k.prefix == prefix).map(|k| {
+            ApiKeyGrant::new(k.org_id, k.project_id, k.key_hash.clone(), k.scopes.clone())
+        })
     }
 }
 
@@ -108,15 +120,15 @@ pub struct NoopApiKeyLookup;
 
 #[async_trait]
 impl ApiKeyLookup for NoopApiKeyLookup {
-    async fn lookup_api_key(&self, _prefix: &str) -> Option<(OrgId, ProjectId, String, Vec<Scope>)> {
+    async fn lookup_api_key(&self, _prefix: &str) -> Option<ApiKeyGrant> {
         None
     }
 }
 
 /// Database-backed API key lookup using `AuthStore`.
 ///
-/// Delegates to `AuthStore::lookup_api_key_by_prefix` and returns the
-/// (org_id, key_hash, scopes) tuple the middleware expects.
+/// Delegates to `AuthStore::lookup_api_key_by_prefix` and returns the key
+/// with its ingest-only and network restrictions applied.
 pub struct StoreApiKeyLookup {
     store: std::sync::Arc<dyn auth::AuthStore>,
 }
@@ -129,7 +141,7 @@ impl StoreApiKeyLookup {
 
 #[async_trait]
 impl ApiKeyLookup for StoreApiKeyLookup {
-    async fn lookup_api_key(&self, prefix: &str) -> Option<(OrgId, ProjectId, String, Vec<Scope>)> {
+    async fn lookup_api_key(&self, prefix: &str) -> Option<ApiKeyGrant> {
         match self.store.lookup_api_key_by_prefix(prefix).await {
             Ok(Some(key)) => {
                 // Check expiry
@@ -145,7 +157,14 @@ impl ApiKeyLookup for StoreApiKeyLookup {
                 tokio::spawn(async move {
                     let _ = store.update_api_key_last_used(key_id).await;
                 });
-                Some((key.org_id, key.project_id, key.key_hash, key.scopes))
+                let scopes = key.effective_scopes();
+                Some(ApiKeyGrant {
+                    org_id: key.org_id,
+                    project_id: key.project_id,
+                    key_hash: key.key_hash,
+                    scopes,
+                    allowed_ips: key.allowed_ips,
+                })
             }
             Ok(None) => None,
             Err(e) => {
@@ -173,7 +192,7 @@ impl CompositeApiKeyLookup {
 
 #[async_trait]
 impl ApiKeyLookup for CompositeApiKeyLookup {
-    async fn lookup_api_key(&self, prefix: &str) -> Option<(OrgId, ProjectId, String, Vec<Scope>)> {
+    async fn lookup_api_key(&self, prefix: &str) -> Option<ApiKeyGrant> {
         // Try DB first, then env
         if let Some(result) = self.store_lookup.lookup_api_key(prefix).await {
             return Some(result);
@@ -183,6 +202,7 @@ impl ApiKeyLookup for CompositeApiKeyLookup {
 }
 
 /// Create auth config from environment
+#[cfg(feature = "cloud")]
 pub fn auth_config_from_env() -> AuthConfig {
     let is_cloud = std::env::var("TRACEWAY_CLOUD").is_ok()
         || std::env::var("STORAGE_BACKEND").is_ok();
@@ -201,12 +221,33 @@ pub fn auth_config_from_env() -> AuthConfig {
                     .as_nanos() as u64);
                 format!("auto_{:x}", h.finish())
             });
-        AuthConfig::cloud(secret.into_bytes())
+        AuthConfig::cloud(secret.into_bytes()).with_trusted_proxies(trusted_proxies_from_env())
     } else {
         AuthConfig::local()
     }
 }
 
+/// Networks of the load balancers in front of the daemon, from the
+/// comma-separated TRACEWAY_TRUSTED_PROXIES. Only their `X-Forwarded-For`
+/// is used to find a client's address.
+#[cfg(feature = "cloud")]
+fn trusted_proxies_from_env() -> Vec<IpCidr> {
+    let Ok(value) = std::env::var("TRACEWAY_TRUSTED_PROXIES") else {
+        return Vec::new();
+    };
+    value
+        .split(',')
+        .filter(|net| !net.trim().is_empty())
+        .filter_map(|net| match net.parse() {
+            Ok(net) => Some(net),
+            Err(e) => {
+                tracing::warn!("Ignoring TRACEWAY_TRUSTED_PROXIES entry: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
 /// Extract auth token from query parameters (for SSE which can't use headers)
 pub fn extract_token_from_query(query: &str) -> Option<String> {
     for param in query.split('&') {
@@ -223,3 +264,91 @@ pub fn extract_token_from_query(query: &str) -> Option<String> {
     }
     None
 }
+
+/// Middleware that resolves the caller's `AuthContext` and attaches it to the
+/// request so handlers can use the `auth::Auth` extractor. Unlike the auth
+/// crate's own middleware it also takes a `?token=` query parameter, which
+/// SSE clients use because they can't set headers. With an auth store, a
+/// session's user must still belong to its org.
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let query_token = request.uri().query().and_then(extract_token_from_query);
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0);
+    let authenticated = request
+        .extensions_mut()
+        .remove::<super::rate_limit::Authenticated>();
+    let resolved = match authenticated {
+        Some(super::rate_limit::Authenticated(ctx)) => narrow_to_membership(&state, ctx).await,
+        None => resolve_auth(&state, request.headers(), query_token, peer).await,
+    };
+    match resolved {
+        Ok(ctx) => {
+            request.extensions_mut().insert(ctx);
+            next.run(request).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The caller as `auth::middleware::authenticate` finds them, with a
+/// session's scopes narrowed by the user's membership when there is an
+/// auth store.
+pub(super) async fn resolve_auth(
+    state: &AppState,
+    headers: &HeaderMap,
+    query_token: Option<String>,
+    peer: Option<SocketAddr>,
+) -> Result<AuthContext, AuthError> {
+    let ctx = auth::middleware::authenticate(
+        headers,
+        query_token,
+        peer,
+        &state.auth_config,
+        state.api_key_lookup.as_ref(),
+    )
+    .await?;
+    narrow_to_membership(state, ctx).await
+}
+
+/// Narrow a session's scopes by the user's membership when there is an
+/// auth store.
+async fn narrow_to_membership(
+    state: &AppState,
+    mut ctx: AuthContext,
+) -> Result<AuthContext, AuthError> {
+    if let (Some(store), Some(user_id)) = (&state.auth_store, ctx.user_id) {
+        ctx.scopes = member_scopes(store.as_ref(), user_id, &ctx).await?;
+    }
+    Ok(ctx)
+}
+
+/// The session's scopes that the user's current role in its org allows.
+/// A session stops working in an org the user has left, and a changed role
+/// applies without signing in again.
+async fn member_scopes(
+    store: &dyn auth::AuthStore,
+    user_id: auth::UserId,
+    session: &AuthContext,
+) -> Result<Vec<Scope>, AuthError> {
+    let membership = store
+        .get_membership(user_id, session.org_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("membership lookup failed: {}", e);
+            AuthError::InvalidSession
+        })?
+        .ok_or(AuthError::NotAMember)?;
+    let allowed = membership.role.scopes();
+    Ok(session
+        .scopes
+        .iter()
+        .copied()
+        .filter(|s| allowed.contains(s))
+        .collect())
+}
@@ -0,0 +1,486 @@
+//! Usage metering for billing.
+//!
+//! Ingest counts the spans it stores per org in memory. Every
+//! [`FLUSH_INTERVAL`] the counts are added to the org's usage ledger, one
+//! entry per calendar month (UTC) saved in the org's settings. When
+//! `POLAR_ACCESS_TOKEN` is set, spans the ledger hasn't reported yet are
+//! sent to Polar's event ingestion API, with the org id as the external
+//! customer id, so usage-based prices can bill them. Each range of spans
+//! is claimed in the ledger before it is reported and sent under an id
+//! derived from the org, month and range, so Polar drops a report that is
+//! retried, whether after a failure or by another instance. Without Polar,
+//! the ledger is the record invoices are made from.
+//!
+//! Ledger updates are compare-and-swap writes, so instances sharing an org
+//! store don't lose each other's counts. Counts not yet flushed are lost if
+//! the daemon stops.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use trace::OrgId;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{api_error, require_scope, ApiError, AppState, OrgStoreManager};
+
+/// Settings key an org's usage ledger is saved under.
+pub const USAGE_SETTING: &str = "usage_ledger";
+
+/// How often counts are written to ledgers and reported to Polar.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Months of usage a ledger keeps.
+const LEDGER_MONTHS: usize = 24;
+
+const DEFAULT_POLAR_URL: &str = "https://api.polar.sh";
+const DEFAULT_POLAR_EVENT: &str = "traceway.spans";
+
+/// Spans stored for an org in one calendar month.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UsagePeriod {
+    /// The month, as `YYYY-MM`.
+    pub period: String,
+    pub spans: u64,
+    /// How many of `spans` have been reported to Polar.
+    #[serde(default)]
+    pub reported_spans: u64,
+    /// While spans are being reported, the count they bring
+    /// `reported_spans` up to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reporting_to: Option<u64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn period_of(at: DateTime<Utc>) -> String {
+    format!("{:04}-{:02}", at.year(), at.month())
+}
+
+/// Add `spans` to the ledger's entry for `now`'s month, keeping the ledger
+/// newest first and at most [`LEDGER_MONTHS`] long.
+fn add_spans(ledger: &mut Vec<UsagePeriod>, spans: u64, now: DateTime<Utc>) {
+    let period = period_of(now);
+    match ledger.iter_mut().find(|p| p.period == period) {
+        Some(entry) => {
+            entry.spans += spans;
+            entry.updated_at = now;
+        }
+        None => ledger.push(UsagePeriod {
+            period,
+            spans,
+            reported_spans: 0,
+            reporting_to: None,
+            updated_at: now,
+        }),
+    }
+    ledger.sort_by(|a, b| b.period.cmp(&a.period));
+    ledger.truncate(LEDGER_MONTHS);
+}
+
+/// Reports span counts to Polar as meter events.
+struct PolarClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+    event_name: String,
+}
+
+impl PolarClient {
+    /// Configured by `POLAR_ACCESS_TOKEN`, plus `POLAR_API_URL` and
+    /// `POLAR_USAGE_EVENT` to override the API and event name.
+    fn from_env() -> Option<Self> {
+        let token = std::env::var("POLAR_ACCESS_TOKEN")
+            .ok()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())?;
+        let base_url = std::env::var("POLAR_API_URL")
+            .unwrap_or_else(|_| DEFAULT_POLAR_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let event_name =
+            std::env::var("POLAR_USAGE_EVENT").unwrap_or_else(|_| DEFAULT_POLAR_EVENT.to_string());
+        Some(Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            base_url,
+            token,
+            event_name,
+        })
+    }
+
+    /// Report the org's spans in `period` past the first `from`, up to
+    /// `to`. The event id depends only on those, so Polar ignores a repeat.
+    async fn report(&self, org_id: OrgId, period: &str, from: u64, to: u64) -> Result<(), String> {
+        let body = serde_json::json!({
+            "events": [{
+                "name": self.event_name,
+                "external_id": format!("{org_id}:{period}:{from}-{to}"),
+                "external_customer_id": org_id.to_string(),
+                "metadata": { "spans": to - from, "period": period },
+            }],
+        });
+        let response = self
+            .client
+            .post(format!("{}/v1/events/ingest", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Polar answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Per-org span counts waiting to be written to ledgers.
+pub struct UsageMeter {
+    org_stores: Arc<OrgStoreManager>,
+    pending: std::sync::Mutex<HashMap<OrgId, u64>>,
+    /// Orgs whose last report to Polar failed.
+    unreported: std::sync::Mutex<HashSet<OrgId>>,
+    polar: Option<PolarClient>,
+    /// Serializes ledger updates.
+    flushing: Mutex<()>,
+}
+
+impl UsageMeter {
+    pub fn new(org_stores: Arc<OrgStoreManager>) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            pending: Default::default(),
+            unreported: Default::default(),
+            polar: PolarClient::from_env(),
+            flushing: Mutex::new(()),
+        })
+    }
+
+    /// Count spans stored for the org.
+    pub fn record(&self, org_id: OrgId, spans: usize) {
+        if spans > 0 {
+            *self.pending.lock().unwrap().entry(org_id).or_default() += spans as u64;
+        }
+    }
+
+    /// Whether usage is reported to Polar.
+    pub fn metered(&self) -> bool {
+        self.polar.is_some()
+    }
+
+    /// The org's ledger, and the setting it was read from.
+    async fn ledger(
+        &self,
+        org_id: OrgId,
+    ) -> Result<(Vec<UsagePeriod>, Option<serde_json::Value>), String> {
+        let store = self.org_stores.get(org_id).await?;
+        let stored = store
+            .get_setting(USAGE_SETTING)
+            .await
+            .map_err(|e| e.to_string())?;
+        let ledger = match &stored {
+            Some(value) => serde_json::from_value(value.clone()).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        Ok((ledger, stored))
+    }
+
+    /// Apply `update` to the org's ledger and save it, unless another
+    /// instance saved the ledger in between, in which case `update` is
+    /// applied again to the ledger as that instance left it.
+    async fn update_ledger(
+        &self,
+        org_id: OrgId,
+        mut update: impl FnMut(&mut Vec<UsagePeriod>),
+    ) -> Result<Vec<UsagePeriod>, String> {
+        let store = self.org_stores.get(org_id).await?;
+        loop {
+            let (mut ledger, stored) = self.ledger(org_id).await?;
+            update(&mut ledger);
+            let value = serde_json::to_value(&ledger).map_err(|e| e.to_string())?;
+            let saved = store
+                .update_setting(USAGE_SETTING, &value, stored.as_ref())
+                .await
+                .map_err(|e| e.to_string())?;
+            if saved {
+                return Ok(ledger);
+            }
+        }
+    }
+
+    /// The org's ledger, newest first, with counts not yet flushed added.
+    pub async fn usage(&self, org_id: OrgId) -> Result<Vec<UsagePeriod>, String> {
+        let (mut ledger, _) = self.ledger(org_id).await?;
+        let pending = self.pending.lock().unwrap().get(&org_id).copied();
+        if let Some(spans) = pending {
+            add_spans(&mut ledger, spans, Utc::now());
+        }
+        Ok(ledger)
+    }
+
+    /// Write pending counts to ledgers and report unreported spans.
+    async fn flush(&self) {
+        let _guard = self.flushing.lock().await;
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let retries = std::mem::take(&mut *self.unreported.lock().unwrap());
+        let orgs: HashSet<OrgId> = pending.keys().copied().chain(retries).collect();
+        for org_id in orgs {
+            let spans = pending.get(&org_id).copied().unwrap_or(0);
+            if let Err(e) = self.flush_org(org_id, spans).await {
+                warn!(%org_id, "billing: failed to update usage ledger: {e}");
+                // Counted again on the next flush
+                self.record(org_id, spans as usize);
+            }
+        }
+    }
+
+    async fn flush_org(&self, org_id: OrgId, spans: u64) -> Result<(), String> {
+        let now = Utc::now();
+        let ledger = if spans > 0 {
+            self.update_ledger(org_id, |ledger| add_spans(ledger, spans, now))
+                .await?
+        } else {
+            self.ledger(org_id).await?.0
+        };
+
+        let Some(polar) = &self.polar else {
+            return Ok(());
+        };
+        for entry in ledger.iter().filter(|e| e.spans > e.reported_spans) {
+            if let Err(e) = self.report_period(polar, org_id, &entry.period).await {
+                warn!(%org_id, period = %entry.period, "billing: failed to report usage to Polar: {e}");
+                self.unreported.lock().unwrap().insert(org_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Report the org's unreported spans in `period`. The range is claimed
+    /// in the ledger first, and a claim left by a failed report, here or
+    /// on another instance, is reported as is, so each span is reported in
+    /// exactly one range.
+    async fn report_period(
+        &self,
+        polar: &PolarClient,
+        org_id: OrgId,
+        period: &str,
+    ) -> Result<(), String> {
+        let ledger = self
+            .update_ledger(org_id, |ledger| {
+                if let Some(entry) = ledger.iter_mut().find(|e| e.period == period) {
+                    if entry.reporting_to.is_none() && entry.spans > entry.reported_spans {
+                        entry.reporting_to = Some(entry.spans);
+                    }
+                }
+            })
+            .await?;
+        let Some((from, to)) = ledger
+            .iter()
+            .find(|e| e.period == period)
+            .and_then(|e| Some((e.reported_spans, e.reporting_to?)))
+        else {
+            return Ok(());
+        };
+
+        polar.report(org_id, period, from, to).await?;
+        self.update_ledger(org_id, |ledger| {
+            if let Some(entry) = ledger.iter_mut().find(|e| e.period == period) {
+                if entry.reporting_to == Some(to) {
+                    entry.reported_spans = to;
+                    entry.reporting_to = None;
+                }
+            }
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Flush usage every [`FLUSH_INTERVAL`] until the meter is dropped.
+pub fn spawn_usage_flusher(meter: Weak<UsageMeter>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(meter) = meter.upgrade() else {
+                return;
+            };
+            meter.flush().await;
+        }
+    })
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BillingUsage {
+    /// The org's plan, when it can be looked up.
+    #[schema(value_type = Option<String>)]
+    pub plan: Option<auth::Plan>,
+    /// Spans the plan includes each month.
+    pub spans_per_month: Option<u64>,
+    pub current: UsagePeriod,
+    /// Earlier months, newest first.
+    pub history: Vec<UsagePeriod>,
+    /// Whether usage is reported to Polar for usage-based billing.
+    pub metered: bool,
+}
+
+/// The org's span usage this month and in earlier months.
+#[utoipa::path(
+    get,
+    path = "/api/org/billing/usage",
+    tag = "billing",
+    responses(
+        (status = 200, body = BillingUsage),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_usage(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<BillingUsage>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let now = Utc::now();
+    let mut history = state
+        .usage
+        .usage(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let period = period_of(now);
+    let current = match history.iter().position(|p| p.period == period) {
+        Some(i) => history.remove(i),
+        None => UsagePeriod {
+            period,
+            spans: 0,
+            reported_spans: 0,
+            reporting_to: None,
+            updated_at: now,
+        },
+    };
+    let plan = org_plan(&state, ctx.org_id).await?;
+    Ok(Json(BillingUsage {
+        plan,
+        spans_per_month: plan.map(|p| p.spans_per_month()),
+        current,
+        history,
+        metered: state.usage.metered(),
+    }))
+}
+
+async fn org_plan(state: &AppState, org_id: OrgId) -> Result<Option<auth::Plan>, ApiError> {
+    if let Some(sim) = &state.plan_sim {
+        return Ok(Some(sim.plan()));
+    }
+    let Some(store) = &state.auth_store else {
+        return Ok(None);
+    };
+    let org = store
+        .get_org(org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(org.map(|o| o.plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn ledger_keeps_one_entry_per_month_newest_first() {
+        let march = Utc.with_ymd_and_hms(2026, 3, 31, 23, 0, 0).unwrap();
+        let april = Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap();
+        let mut ledger = Vec::new();
+        add_spans(&mut ledger, 10, march);
+        add_spans(&mut ledger, 5, march);
+        ledger[0].reported_spans = 15;
+        add_spans(&mut ledger, 7, april);
+
+        let periods: Vec<_> = ledger.iter().map(|p| (p.period.as_str(), p.spans)).collect();
+        assert_eq!(periods, [("2026-04", 7), ("2026-03", 15)]);
+        assert_eq!(ledger[1].reported_spans, 15);
+
+        for month in 1..=12 {
+            for year in [2024, 2025] {
+                add_spans(&mut ledger, 1, Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap());
+            }
+        }
+        assert_eq!(ledger.len(), LEDGER_MONTHS);
+        assert_eq!(ledger[0].period, "2026-04");
+    }
+
+    #[tokio::test]
+    async fn spans_are_reported_once_across_instances() {
+        use axum::routing::post;
+        use storage::PersistentStore;
+        use storage_sqlite::SqliteBackend;
+
+        use crate::api::AnyBackend;
+
+        // Polar that records the id of every event and fails the first
+        // request
+        let events: Arc<std::sync::Mutex<Vec<String>>> = Default::default();
+        let seen = events.clone();
+        let polar = axum::Router::new().route(
+            "/v1/events/ingest",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let seen = seen.clone();
+                async move {
+                    let mut seen = seen.lock().unwrap();
+                    let id = &body["events"][0]["external_id"];
+                    seen.push(id.as_str().unwrap().to_string());
+                    if seen.len() == 1 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, polar).await });
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let org_stores = Arc::new(OrgStoreManager::single(store));
+        let instance = || UsageMeter {
+            org_stores: org_stores.clone(),
+            pending: Default::default(),
+            unreported: Default::default(),
+            polar: Some(PolarClient {
+                client: reqwest::Client::new(),
+                base_url: base_url.clone(),
+                token: "token".into(),
+                event_name: DEFAULT_POLAR_EVENT.into(),
+            }),
+            flushing: Mutex::new(()),
+        };
+        let (a, b) = (instance(), instance());
+        let org_id = OrgId::nil();
+        let period = period_of(Utc::now());
+
+        // The first report fails, leaving its range claimed
+        a.record(org_id, 10);
+        a.flush().await;
+        // Another instance reports the claimed range before its own spans
+        b.record(org_id, 5);
+        b.flush().await;
+        a.flush().await;
+
+        let first = format!("{org_id}:{period}:0-10");
+        let second = format!("{org_id}:{period}:10-15");
+        assert_eq!(*events.lock().unwrap(), [first.clone(), first, second]);
+        let ledger = a.usage(org_id).await.unwrap();
+        assert_eq!(ledger[0].spans, 15);
+        assert_eq!(ledger[0].reported_spans, 15);
+        assert_eq!(ledger[0].reporting_to, None);
+    }
+}
@@ -0,0 +1,692 @@
+//! Monthly cost budgets.
+//!
+//! A budget caps the cost of spans in its scope (the whole org, traces
+//! with a tag, or one model) over each calendar month, UTC. Budgets are
+//! saved in the org's settings. The tracker recomputes spend from the spans
+//! in the storage backend every [`REFRESH_INTERVAL`], and the proxy adds the
+//! cost of each call it completes in between.
+//!
+//! The first time in a month that spend reaches a limit, a `budget_alert`
+//! event is emitted. Once spend reaches a budget's hard limit, the proxy
+//! answers calls in its scope with 429 until the month ends.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{SpanFilter, StorageError};
+use tokio::sync::{Mutex, RwLock};
+use trace::{OrgId, TraceId};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::events::EventJournal;
+use super::{
+    api_error, audit, require_scope, ApiError, AppState, OrgStoreManager, SharedStore, SystemEvent,
+};
+
+/// Settings key an org's budgets are saved under.
+pub const BUDGETS_SETTING: &str = "budgets";
+
+/// How often spend is recomputed from the backend and alerts are sent.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+pub type BudgetId = Uuid;
+
+/// The spans a budget counts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BudgetScope {
+    Org,
+    /// Spans of traces with this tag.
+    Tag {
+        tag: String,
+    },
+    Model {
+        model: String,
+    },
+}
+
+impl BudgetScope {
+    fn matches(&self, model: Option<&str>, tags: &[String]) -> bool {
+        match self {
+            BudgetScope::Org => true,
+            BudgetScope::Tag { tag } => tags.contains(tag),
+            BudgetScope::Model { model: m } => model == Some(m.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLimit {
+    /// Alert only.
+    Soft,
+    /// Alert, and reject proxied calls.
+    Hard,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Budget {
+    pub id: BudgetId,
+    pub name: String,
+    pub scope: BudgetScope,
+    /// Monthly spend in USD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_limit: Option<f64>,
+    /// Monthly spend in USD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_limit: Option<f64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Budget {
+    fn limit(&self, which: BudgetLimit) -> Option<f64> {
+        match which {
+            BudgetLimit::Soft => self.soft_limit,
+            BudgetLimit::Hard => self.hard_limit,
+        }
+    }
+
+    /// The highest limit `spent` has reached.
+    fn reached(&self, spent: f64) -> Option<BudgetLimit> {
+        [BudgetLimit::Hard, BudgetLimit::Soft]
+            .into_iter()
+            .find(|&which| self.limit(which).is_some_and(|limit| spent >= limit))
+    }
+}
+
+/// Body for `POST /api/budgets`.
+#[derive(Debug, Deserialize)]
+pub struct CreateBudget {
+    pub name: String,
+    pub scope: BudgetScope,
+    pub soft_limit: Option<f64>,
+    pub hard_limit: Option<f64>,
+}
+
+impl CreateBudget {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".into());
+        }
+        match &self.scope {
+            BudgetScope::Tag { tag: value } | BudgetScope::Model { model: value }
+                if value.is_empty() =>
+            {
+                return Err("scope needs a tag or model".into());
+            }
+            _ => {}
+        }
+        if self.soft_limit.is_none() && self.hard_limit.is_none() {
+            return Err("set soft_limit, hard_limit, or both".into());
+        }
+        for limit in [self.soft_limit, self.hard_limit].into_iter().flatten() {
+            if !limit.is_finite() || limit <= 0.0 {
+                return Err("limits must be positive".into());
+            }
+        }
+        if let (Some(soft), Some(hard)) = (self.soft_limit, self.hard_limit) {
+            if soft > hard {
+                return Err("soft_limit must not exceed hard_limit".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A budget with its spend this month.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    #[serde(flatten)]
+    pub budget: Budget,
+    pub spent: f64,
+    /// The highest limit reached this month.
+    pub exceeded: Option<BudgetLimit>,
+    pub period_start: DateTime<Utc>,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// Start of the calendar month containing `at`.
+fn period_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(at.year(), at.month(), 1, 0, 0, 0)
+        .single()
+        .expect("the first of a month is a valid UTC time")
+}
+
+/// Start of the month after the one starting at `start`.
+fn period_end(start: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match start.month() {
+        12 => (start.year() + 1, 1),
+        month => (start.year(), month + 1),
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .expect("the first of a month is a valid UTC time")
+}
+
+/// What each budget's scope spent in `stores` since `since`, read from the
+/// backends so spans the caches don't hold are counted.
+async fn compute_spend(
+    stores: &[SharedStore],
+    budgets: &[Budget],
+    since: DateTime<Utc>,
+) -> Result<HashMap<BudgetId, f64>, StorageError> {
+    let mut spent: HashMap<BudgetId, f64> = budgets.iter().map(|b| (b.id, 0.0)).collect();
+    if budgets.is_empty() {
+        return Ok(spent);
+    }
+    let by_tag = budgets
+        .iter()
+        .any(|b| matches!(b.scope, BudgetScope::Tag { .. }));
+    let filter = SpanFilter {
+        since: Some(since),
+        ..Default::default()
+    };
+    for store in stores {
+        // Summed per model, and per trace when tags matter, so each trace's
+        // tags are looked up once
+        let mut costs: HashMap<(Option<TraceId>, Option<String>), f64> = HashMap::new();
+        store
+            .scan_spans(&filter, |span| {
+                if let Some(cost) = span.kind().cost().filter(|c| *c > 0.0) {
+                    let trace_id = by_tag.then(|| span.trace_id());
+                    let model = span.kind().model().map(str::to_string);
+                    *costs.entry((trace_id, model)).or_default() += cost;
+                }
+            })
+            .await?;
+        for ((trace_id, model), cost) in costs {
+            let tags = match trace_id {
+                Some(id) => store
+                    .get_trace_or_load(id)
+                    .await
+                    .map(|t| t.tags)
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            };
+            for budget in budgets
+                .iter()
+                .filter(|b| b.scope.matches(model.as_deref(), &tags))
+            {
+                *spent.entry(budget.id).or_default() += cost;
+            }
+        }
+    }
+    Ok(spent)
+}
+
+struct OrgBudgets {
+    budgets: Vec<Budget>,
+    period_start: DateTime<Utc>,
+    spent: HashMap<BudgetId, f64>,
+    /// Limits already alerted on this period.
+    alerted: HashSet<(BudgetId, BudgetLimit)>,
+}
+
+impl OrgBudgets {
+    fn new(budgets: Vec<Budget>) -> Self {
+        Self {
+            budgets,
+            period_start: period_start(Utc::now()),
+            spent: HashMap::new(),
+            alerted: HashSet::new(),
+        }
+    }
+
+    fn status(&self, budget: &Budget) -> BudgetStatus {
+        let spent = self.spent.get(&budget.id).copied().unwrap_or(0.0);
+        BudgetStatus {
+            budget: budget.clone(),
+            spent,
+            exceeded: budget.reached(spent),
+            period_start: self.period_start,
+            resets_at: period_end(self.period_start),
+        }
+    }
+}
+
+/// Each org's budgets and month-to-date spend.
+pub struct BudgetTracker {
+    org_stores: Arc<OrgStoreManager>,
+    orgs: RwLock<HashMap<OrgId, OrgBudgets>>,
+    /// Serializes changes to saved budgets.
+    edits: Mutex<()>,
+}
+
+impl BudgetTracker {
+    pub fn new(org_stores: Arc<OrgStoreManager>) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            orgs: RwLock::new(HashMap::new()),
+            edits: Mutex::new(()),
+        })
+    }
+
+    async fn saved_budgets(&self, org_id: OrgId) -> Result<Vec<Budget>, String> {
+        let store = self.org_stores.get(org_id).await?;
+        match store.get_setting(BUDGETS_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Load the org's budgets and their spend if not yet loaded.
+    async fn ensure_loaded(&self, org_id: OrgId) -> Result<(), String> {
+        if self.orgs.read().await.contains_key(&org_id) {
+            return Ok(());
+        }
+        let budgets = self.saved_budgets(org_id).await?;
+        self.orgs
+            .write()
+            .await
+            .entry(org_id)
+            .or_insert_with(|| OrgBudgets::new(budgets));
+        self.refresh(org_id).await;
+        Ok(())
+    }
+
+    /// Recompute the org's spend this month from its open stores.
+    async fn refresh(&self, org_id: OrgId) {
+        let Some(budgets) = self
+            .orgs
+            .read()
+            .await
+            .get(&org_id)
+            .map(|org| org.budgets.clone())
+        else {
+            return;
+        };
+        let start = period_start(Utc::now());
+        let stores = self.org_stores.cached_stores_for_org(org_id).await;
+        let spent = match compute_spend(&stores, &budgets, start).await {
+            Ok(spent) => spent,
+            Err(e) => {
+                warn!(%org_id, "failed to compute budget spend: {e}");
+                return;
+            }
+        };
+        if let Some(org) = self.orgs.write().await.get_mut(&org_id) {
+            if org.period_start != start {
+                org.period_start = start;
+                org.alerted.clear();
+            }
+            org.spent = spent;
+        }
+    }
+
+    /// Limits the org's spend reached that haven't been alerted on this month.
+    async fn take_alerts(&self, org_id: OrgId) -> Vec<(BudgetStatus, BudgetLimit)> {
+        let mut orgs = self.orgs.write().await;
+        let Some(org) = orgs.get_mut(&org_id) else {
+            return Vec::new();
+        };
+        let mut alerts = Vec::new();
+        for budget in &org.budgets {
+            let status = org.status(budget);
+            for which in [BudgetLimit::Soft, BudgetLimit::Hard] {
+                let reached = budget.limit(which).is_some_and(|l| status.spent >= l);
+                if reached && org.alerted.insert((budget.id, which)) {
+                    alerts.push((status.clone(), which));
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Save `budgets` as the org's budgets and recompute their spend.
+    async fn replace(&self, org_id: OrgId, budgets: Vec<Budget>) -> Result<(), String> {
+        let store = self.org_stores.get(org_id).await?;
+        let value = serde_json::to_value(&budgets).map_err(|e| e.to_string())?;
+        store
+            .save_setting(BUDGETS_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.orgs
+            .write()
+            .await
+            .entry(org_id)
+            .or_insert_with(|| OrgBudgets::new(Vec::new()))
+            .budgets = budgets;
+        self.refresh(org_id).await;
+        Ok(())
+    }
+
+    pub async fn statuses(&self, org_id: OrgId) -> Result<Vec<BudgetStatus>, String> {
+        self.ensure_loaded(org_id).await?;
+        let orgs = self.orgs.read().await;
+        Ok(orgs
+            .get(&org_id)
+            .map(|org| org.budgets.iter().map(|b| org.status(b)).collect())
+            .unwrap_or_default())
+    }
+
+    /// A budget covering a call to `model` in a trace tagged `tags` whose
+    /// hard limit is spent, if any.
+    pub async fn exceeded(
+        &self,
+        org_id: OrgId,
+        model: &str,
+        tags: &[String],
+    ) -> Option<BudgetStatus> {
+        if let Err(e) = self.ensure_loaded(org_id).await {
+            warn!(%org_id, "failed to load budgets: {e}");
+            return None;
+        }
+        let orgs = self.orgs.read().await;
+        let org = orgs.get(&org_id)?;
+        // Last month's spend until the next refresh
+        if org.period_start != period_start(Utc::now()) {
+            return None;
+        }
+        org.budgets
+            .iter()
+            .filter(|b| b.scope.matches(Some(model), tags))
+            .map(|b| org.status(b))
+            .find(|s| s.exceeded == Some(BudgetLimit::Hard))
+    }
+
+    /// Add the cost of a call completed since the last refresh.
+    pub async fn record(&self, org_id: OrgId, model: &str, tags: &[String], cost: f64) {
+        let mut orgs = self.orgs.write().await;
+        let Some(org) = orgs.get_mut(&org_id) else {
+            return;
+        };
+        let matching: Vec<BudgetId> = org
+            .budgets
+            .iter()
+            .filter(|b| b.scope.matches(Some(model), tags))
+            .map(|b| b.id)
+            .collect();
+        for id in matching {
+            *org.spent.entry(id).or_default() += cost;
+        }
+    }
+}
+
+/// Spawn the periodic refresh, which emits `budget_alert` events. It holds
+/// the journal weakly and stops once the router that owns it is gone.
+pub fn spawn_budget_refresher(
+    tracker: Arc<BudgetTracker>,
+    journal: Weak<EventJournal>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            for (org_id, _) in tracker.org_stores.all_stores().await {
+                if let Err(e) = tracker.ensure_loaded(org_id).await {
+                    warn!(%org_id, "failed to load budgets: {e}");
+                    continue;
+                }
+                tracker.refresh(org_id).await;
+                for (status, limit) in tracker.take_alerts(org_id).await {
+                    warn!(
+                        %org_id,
+                        budget = %status.budget.name,
+                        spent = status.spent,
+                        ?limit,
+                        "budget limit reached"
+                    );
+                    journal.emit(
+                        &org_id.to_string(),
+                        SystemEvent::BudgetAlert {
+                            budget: status.budget,
+                            limit,
+                            spent: status.spent,
+                        },
+                    );
+                }
+            }
+        }
+    })
+}
+
+// --- Handlers ---
+
+pub async fn create_budget(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<CreateBudget>,
+) -> Result<(StatusCode, Json<BudgetStatus>), ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    req.validate()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let budget = Budget {
+        id: Uuid::now_v7(),
+        name: req.name,
+        scope: req.scope,
+        soft_limit: req.soft_limit,
+        hard_limit: req.hard_limit,
+        created_at: Utc::now(),
+    };
+    let tracker = &state.budgets;
+    {
+        let _edit = tracker.edits.lock().await;
+        let mut budgets = tracker
+            .saved_budgets(ctx.org_id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        budgets.push(budget.clone());
+        tracker
+            .replace(ctx.org_id, budgets)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "budget.create",
+        Some(budget.id.to_string()),
+        serde_json::to_value(&budget).unwrap_or_default(),
+    )
+    .await;
+    let status = tracker
+        .statuses(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .into_iter()
+        .find(|s| s.budget.id == budget.id)
+        .ok_or_else(|| api_error(StatusCode::INTERNAL_SERVER_ERROR, "budget not saved"))?;
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+/// The org's budgets with their spend this month.
+pub async fn list_budgets(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BudgetStatus>>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let statuses = state
+        .budgets
+        .statuses(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(statuses))
+}
+
+pub async fn delete_budget(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<BudgetId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let tracker = &state.budgets;
+    {
+        let _edit = tracker.edits.lock().await;
+        let mut budgets = tracker
+            .saved_budgets(ctx.org_id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let before = budgets.len();
+        budgets.retain(|b| b.id != id);
+        if budgets.len() == before {
+            return Err(
+                api_error(StatusCode::NOT_FOUND, "budget not found").with_code("budget_not_found")
+            );
+        }
+        tracker
+            .replace(ctx.org_id, budgets)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "budget.delete",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::{SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+    use crate::api::AnyBackend;
+
+    fn budget(scope: BudgetScope, soft_limit: Option<f64>, hard_limit: Option<f64>) -> Budget {
+        Budget {
+            id: Uuid::now_v7(),
+            name: "test".into(),
+            scope,
+            soft_limit,
+            hard_limit,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn periods_are_calendar_months() {
+        let at = Utc.with_ymd_and_hms(2025, 12, 17, 9, 30, 0).unwrap();
+        let start = period_start(at);
+        assert_eq!(start, Utc.with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(
+            period_end(start),
+            Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn spend_is_tracked_per_scope() {
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let trace = Trace::new(None).with_tags(vec!["team-a".into()]);
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+        let kind = SpanKind::LlmCall {
+            model: "gpt-4o".into(),
+            provider: None,
+            input_tokens: None,
+            output_tokens: None,
+            cost: Some(2.0),
+            input_preview: None,
+            output_preview: None,
+        };
+        store
+            .insert(SpanBuilder::new(trace_id, "call", kind).build())
+            .await
+            .unwrap();
+
+        let tracker = BudgetTracker::new(Arc::new(OrgStoreManager::single(store)));
+        let org_id = Uuid::nil();
+        let team = budget(
+            BudgetScope::Tag {
+                tag: "team-a".into(),
+            },
+            Some(1.0),
+            Some(3.0),
+        );
+        let other_model = budget(
+            BudgetScope::Model {
+                model: "claude".into(),
+            },
+            None,
+            Some(1.0),
+        );
+        tracker
+            .replace(org_id, vec![team.clone(), other_model])
+            .await
+            .unwrap();
+
+        let statuses = tracker.statuses(org_id).await.unwrap();
+        assert_eq!(statuses[0].spent, 2.0);
+        assert_eq!(statuses[0].exceeded, Some(BudgetLimit::Soft));
+        assert_eq!(statuses[1].spent, 0.0);
+        let tags = vec!["team-a".to_string()];
+        assert!(tracker.exceeded(org_id, "gpt-4o", &tags).await.is_none());
+
+        tracker.record(org_id, "gpt-4o", &tags, 1.5).await;
+        let over = tracker.exceeded(org_id, "gpt-4o", &tags).await.unwrap();
+        assert_eq!(over.budget.id, team.id);
+        assert!(tracker.exceeded(org_id, "gpt-4o", &[]).await.is_none());
+
+        let alerts = tracker.take_alerts(org_id).await;
+        assert_eq!(alerts.len(), 2);
+        // Each limit alerts once a month
+        assert!(tracker.take_alerts(org_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn spend_counts_spans_outside_the_cache() {
+        let dir = std::env::temp_dir().join(format!("budgets-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+        let kind = SpanKind::LlmCall {
+            model: "gpt-4o".into(),
+            provider: None,
+            input_tokens: None,
+            output_tokens: None,
+            cost: Some(0.5),
+            input_preview: None,
+            output_preview: None,
+        };
+
+        let writer = PersistentStore::open(AnyBackend::Sqlite(SqliteBackend::open(&db).unwrap()))
+            .await
+            .unwrap();
+        let tagged = Trace::new(None).with_tags(vec!["team-a".into()]);
+        writer.save_trace(tagged.clone()).await.unwrap();
+        for trace_id in [tagged.id, tagged.id, TraceId::new_v4()] {
+            let span = SpanBuilder::new(trace_id, "call", kind.clone()).build();
+            writer.insert(span).await.unwrap();
+        }
+        drop(writer);
+
+        // Nothing is cached until read
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(&db).unwrap());
+        let store: SharedStore = Arc::new(PersistentStore::open_lazy(backend).await.unwrap());
+        let org = budget(BudgetScope::Org, None, None);
+        let team = budget(
+            BudgetScope::Tag {
+                tag: "team-a".into(),
+            },
+            None,
+            None,
+        );
+        let since = period_start(Utc::now());
+        let spent = compute_spend(&[store], &[org.clone(), team.clone()], since)
+            .await
+            .unwrap();
+        assert_eq!(spent[&org.id], 1.5);
+        assert_eq!(spent[&team.id], 1.0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
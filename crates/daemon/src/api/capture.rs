@@ -4,13 +4,10 @@
 //! Matching rules (subject to sampling) create new datapoints in the target dataset.
 
 use std::collections::HashMap;
-use std::sync::Arc;
-
-use tokio::sync::broadcast;
 
 use trace::{CaptureRule, Datapoint, DatapointKind, DatapointSource, Span};
 
-use super::events::EventLog;
+use super::events::EventJournal;
 use super::org_store::SharedStore;
 use super::SystemEvent;
 
@@ -22,19 +19,14 @@ use super::SystemEvent;
 pub async fn process_capture_rules(
     store: &SharedStore,
     span: &Span,
-    events_tx: &broadcast::Sender<SystemEvent>,
-    event_log: &Arc<dyn EventLog>,
+    journal: &EventJournal,
     org_id: &str,
 ) {
-    // Collect matching rules under a read lock
-    let matching_rules: Vec<CaptureRule> = {
-        let r = store.read().await;
-        r.all_enabled_capture_rules()
-            .into_iter()
-            .filter(|rule| rule.matches_span(span))
-            .cloned()
-            .collect()
-    };
+    let matching_rules: Vec<CaptureRule> = store
+        .all_enabled_capture_rules()
+        .into_iter()
+        .filter(|rule| rule.matches_span(span))
+        .collect();
 
     if matching_rules.is_empty() {
         return;
@@ -47,11 +39,7 @@ pub async fn process_capture_rules(
         }
 
         // Check that the target dataset exists
-        let dataset_exists = {
-            let r = store.read().await;
-            r.contains_dataset(rule.dataset_id)
-        };
-        if !dataset_exists {
+        if !store.contains_dataset(rule.dataset_id) {
             tracing::warn!(
                 rule_id = %rule.id,
                 dataset_id = %rule.dataset_id,
@@ -73,20 +61,16 @@ pub async fn process_capture_rules(
             .with_source_span(span.id());
 
         // Save datapoint and update captured count
-        {
-            let mut w = store.write().await;
-            if let Err(e) = w.save_datapoint(dp.clone()).await {
-                tracing::error!(rule_id = %rule.id, "capture: failed to save datapoint: {e}");
-                continue;
-            }
+        if let Err(e) = store.save_datapoint(dp.clone()).await {
+            tracing::error!(rule_id = %rule.id, "capture: failed to save datapoint: {e}");
+            continue;
+        }
 
-            // Increment the rule's captured_count
-            if let Some(current_rule) = w.get_capture_rule(rule.id).cloned() {
-                let mut updated = current_rule;
-                updated.captured_count += 1;
-                if let Err(e) = w.save_capture_rule(updated).await {
-                    tracing::error!(rule_id = %rule.id, "capture: failed to update capture rule count: {e}");
-                }
+        // Increment the rule's captured_count
+        if let Some(mut updated) = store.get_capture_rule(rule.id) {
+            updated.captured_count += 1;
+            if let Err(e) = store.save_capture_rule(updated).await {
+                tracing::error!(rule_id = %rule.id, "capture: failed to update capture rule count: {e}");
             }
         }
 
@@ -98,16 +82,19 @@ pub async fn process_capture_rules(
             "capture rule fired, created datapoint"
         );
 
-        // Emit events (broadcast + durable log)
-        let evt1 = SystemEvent::DatapointCreated { datapoint: dp.clone() };
-        let _ = events_tx.send(evt1.clone());
-        if let Err(e) = event_log.append(org_id, &evt1).await {
-            tracing::warn!("failed to log DatapointCreated event: {e}");
-        }
-        let evt2 = SystemEvent::CaptureRuleFired { rule_id: rule.id, datapoint: dp };
-        let _ = events_tx.send(evt2.clone());
-        if let Err(e) = event_log.append(org_id, &evt2).await {
-            tracing::warn!("failed to log CaptureRuleFired event: {e}");
-        }
+        // Emit events (broadcast + journal)
+        journal.emit(
+            org_id,
+            SystemEvent::DatapointCreated {
+                datapoint: dp.clone(),
+            },
+        );
+        journal.emit(
+            org_id,
+            SystemEvent::CaptureRuleFired {
+                rule_id: rule.id,
+                datapoint: dp,
+            },
+        );
     }
 }
@@ -0,0 +1,169 @@
+//! Scoped clearing of trace data.
+//!
+//! Clearing is a two-step operation. A request without `confirm` returns a
+//! preview of what would be removed plus a short-lived token; repeating the
+//! same request with that token performs the clear. Tokens are signed JWTs
+//! bound to the project and scope they were issued for, so any instance can
+//! check them. They are not single use: until a token expires, repeating the
+//! confirmed request clears again, which the admin could do anyway with a
+//! fresh preview.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{ClearReport, ClearScope};
+
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState, SystemEvent};
+
+const TOKEN_TTL_SECS: i64 = 300;
+
+/// How a token names the scope it confirms.
+fn scope_claim(scope: ClearScope) -> String {
+    match scope {
+        ClearScope::Spans => "spans".to_string(),
+        ClearScope::TracesBefore(before) => format!(
+            "traces_before:{}",
+            before.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        ),
+        ClearScope::All => "all".to_string(),
+    }
+}
+
+/// Whether `token` is a live confirmation for clearing `scope` from the
+/// caller's project.
+fn confirms(token: &str, ctx: &auth::AuthContext, scope: ClearScope, secret: &[u8]) -> bool {
+    auth::verify_clear_token(token, secret).is_ok_and(|t| {
+        t.org_id == ctx.org_id && t.project_id == ctx.project_id && t.scope == scope_claim(scope)
+    })
+}
+
+/// Query parameters for `DELETE /api/admin/clear`.
+#[derive(Debug, Deserialize)]
+pub struct ClearQuery {
+    /// "spans", "traces" (requires `before`), or "all".
+    pub scope: String,
+    pub before: Option<DateTime<Utc>>,
+    /// Token from a previous preview of the same request.
+    pub confirm: Option<String>,
+}
+
+impl ClearQuery {
+    fn clear_scope(&self) -> Result<ClearScope, String> {
+        match (self.scope.as_str(), self.before) {
+            ("spans", None) => Ok(ClearScope::Spans),
+            ("traces", Some(before)) => Ok(ClearScope::TracesBefore(before)),
+            ("traces", None) => Err("scope=traces requires before".to_string()),
+            ("all", None) => Ok(ClearScope::All),
+            ("spans" | "all", Some(_)) => {
+                Err(format!("before is not valid with scope={}", self.scope))
+            }
+            (other, _) => Err(format!(
+                "unknown scope '{other}' (expected spans, traces, or all)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ClearResponse {
+    ConfirmationRequired {
+        preview: ClearReport,
+        confirm_token: String,
+        expires_in_secs: u64,
+    },
+    Cleared {
+        report: ClearReport,
+    },
+}
+
+/// Clear trace data from the caller's project.
+pub async fn clear(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ClearQuery>,
+) -> Result<Json<ClearResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let scope = q
+        .clear_scope()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let store = project_store(&ctx, &state).await?;
+
+    let Some(token) = q.confirm else {
+        let preview = store
+            .clear_preview(scope)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let (confirm_token, _) = auth::create_clear_token(
+            ctx.org_id,
+            ctx.project_id,
+            &scope_claim(scope),
+            chrono::Duration::seconds(TOKEN_TTL_SECS),
+            &state.auth_config.jwt_secret,
+        )?;
+        return Ok(Json(ClearResponse::ConfirmationRequired {
+            preview,
+            confirm_token,
+            expires_in_secs: TOKEN_TTL_SECS as u64,
+        }));
+    };
+
+    if !confirms(&token, &ctx, scope, &state.auth_config.jwt_secret) {
+        return Err(api_error(
+            StatusCode::PRECONDITION_FAILED,
+            "confirmation token is invalid, expired, or for a different request",
+        )
+        .with_code("invalid_confirmation_token"));
+    }
+    let report = store
+        .clear(scope)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    state.emit_event(SystemEvent::Cleared, &ctx.org_id.to_string());
+    audit::record(
+        &state,
+        &ctx,
+        "data.clear",
+        None,
+        serde_json::json!({ "scope": q.scope, "before": q.before, "report": report }),
+    )
+    .await;
+    Ok(Json(ClearResponse::Cleared { report }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn tokens_confirm_only_the_request_they_were_issued_for() {
+        let secret = b"shared by every instance";
+        let ctx = auth::AuthContext::from_api_key(Uuid::now_v7(), Uuid::now_v7(), Vec::new());
+        let before = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let scope = ClearScope::TracesBefore(before);
+        let (token, _) = auth::create_clear_token(
+            ctx.org_id,
+            ctx.project_id,
+            &scope_claim(scope),
+            chrono::Duration::seconds(TOKEN_TTL_SECS),
+            secret,
+        )
+        .unwrap();
+
+        assert!(confirms(&token, &ctx, scope, secret));
+        assert!(!confirms(&token, &ctx, ClearScope::All, secret));
+        let later = ClearScope::TracesBefore(before + chrono::Duration::seconds(1));
+        assert!(!confirms(&token, &ctx, later, secret));
+        let other = auth::AuthContext::from_api_key(ctx.org_id, Uuid::now_v7(), Vec::new());
+        assert!(!confirms(&token, &other, scope, secret));
+        assert!(!confirms(&token, &ctx, scope, b"another deployment"));
+        assert!(!confirms("not-a-token", &ctx, scope, secret));
+    }
+}
@@ -0,0 +1,120 @@
+//! CORS for the API.
+//!
+//! In cloud mode with a separate frontend origin, we need explicit origins
+//! and credentials support. `ALLOWED_ORIGINS` is comma-separated. In local
+//! mode (no env var), allow any origin without credentials.
+
+use axum::http::{header, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// The CORS layer for `allowed_origins`, the value of `ALLOWED_ORIGINS`.
+pub(super) fn layer(allowed_origins: Option<&str>) -> CorsLayer {
+    let Some(origins) = allowed_origins else {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any)
+            .expose_headers(Any);
+    };
+    let origins: Vec<HeaderValue> = origins
+        .split(',')
+        .filter_map(|s| {
+            let trimmed = s.trim();
+            match trimmed.parse::<HeaderValue>() {
+                Ok(v) => {
+                    tracing::info!(origin = trimmed, "CORS: allowing origin");
+                    Some(v)
+                }
+                Err(e) => {
+                    tracing::warn!(origin = trimmed, error = %e, "CORS: failed to parse origin, skipping");
+                    None
+                }
+            }
+        })
+        .collect();
+    if origins.is_empty() {
+        tracing::warn!("CORS: ALLOWED_ORIGINS set but no valid origins parsed, falling back to permissive");
+    }
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+            Method::PATCH,
+        ])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::IF_NONE_MATCH,
+            header::IF_MATCH,
+            header::ORIGIN,
+            header::COOKIE,
+        ])
+        // The UI reads ETags to send them back in If-Match
+        .expose_headers([header::ETAG])
+        .allow_credentials(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const ORIGIN: &str = "https://app.example.com";
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/api/datasets/x",
+                get(|| async { ([(header::ETAG, "\"v1\"")], "{}") }),
+            )
+            .layer(layer(Some(ORIGIN)))
+    }
+
+    #[tokio::test]
+    async fn preflight_allows_conditional_writes() {
+        let resp = app()
+            .oneshot(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/api/datasets/x")
+                    .header(header::ORIGIN, ORIGIN)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+                    .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "if-match")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let headers = resp.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap();
+        assert!(allowed.split(',').any(|h| h.trim() == "if-match"));
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap();
+        assert!(methods.split(',').any(|m| m.trim() == "PATCH"));
+    }
+
+    #[tokio::test]
+    async fn responses_expose_the_etag() {
+        let resp = app()
+            .oneshot(
+                Request::get("/api/datasets/x")
+                    .header(header::ORIGIN, ORIGIN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let exposed = resp.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.split(',').any(|h| h.trim() == "etag"));
+    }
+}
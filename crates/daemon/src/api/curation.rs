@@ -0,0 +1,505 @@
+//! Curation rules: scheduled export of production spans into datasets.
+//!
+//! A rule pairs a span query in the search bar's language (e.g.
+//! `status:failed model:gpt-4o since:1d`) with a target dataset and,
+//! optionally, a score ceiling: only spans an eval scored below it match.
+//! Rules are saved in the project store's settings. The scheduler checks
+//! every minute and runs each enabled rule once per `interval_minutes`;
+//! each run exports matching finished spans that aren't in the dataset
+//! yet, so eval sets grow from production failures on their own.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use trace::{Datapoint, DatapointKind, DatapointSource, DatasetId};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::events::EventJournal;
+use super::{
+    api_error, audit, project_store, require_scope, ApiError, AppState, OrgStoreManager,
+    SharedStore, SystemEvent,
+};
+
+/// Settings key a project's curation rules are saved under.
+pub const CURATION_SETTING: &str = "curation_rules";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Spans a run reads before applying the score ceiling and skipping ones
+/// already exported.
+const MAX_SCANNED: usize = 5_000;
+const MAX_PER_RUN: usize = 1_000;
+
+pub type CurationRuleId = Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurationRule {
+    pub id: CurationRuleId,
+    pub name: String,
+    pub dataset_id: DatasetId,
+    /// Span query; relative times like `since:1d` resolve at each run.
+    pub query: String,
+    /// Only export spans an eval result scored below this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<f64>,
+    pub interval_minutes: u32,
+    /// Datapoints a single run creates at most.
+    pub max_per_run: usize,
+    pub enabled: bool,
+    pub exported_count: u64,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl CurationRule {
+    fn due(&self, now: DateTime<Utc>) -> bool {
+        let interval = chrono::Duration::minutes(i64::from(self.interval_minutes));
+        self.enabled && self.last_run_at.is_none_or(|at| now - at >= interval)
+    }
+}
+
+/// Body for `POST /api/curation/rules`.
+#[derive(Debug, Deserialize)]
+pub struct CreateCurationRule {
+    pub name: String,
+    pub dataset_id: DatasetId,
+    pub query: String,
+    #[serde(default)]
+    pub max_score: Option<f64>,
+    #[serde(default = "default_interval")]
+    pub interval_minutes: u32,
+    #[serde(default = "default_max_per_run")]
+    pub max_per_run: usize,
+}
+
+fn default_interval() -> u32 {
+    60
+}
+
+fn default_max_per_run() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurationRunResult {
+    pub rule: CurationRule,
+    pub exported: usize,
+}
+
+/// Saved curation rules and the lock that serializes changes to them.
+pub struct Curation {
+    org_stores: Arc<OrgStoreManager>,
+    edits: Mutex<()>,
+}
+
+impl Curation {
+    pub fn new(org_stores: Arc<OrgStoreManager>) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            edits: Mutex::new(()),
+        })
+    }
+
+    async fn rules(store: &SharedStore) -> Result<Vec<CurationRule>, String> {
+        match store.get_setting(CURATION_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn save(store: &SharedStore, rules: &[CurationRule]) -> Result<(), String> {
+        let value = serde_json::to_value(rules).map_err(|e| e.to_string())?;
+        store
+            .save_setting(CURATION_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Run `rule`, recording the outcome on it and announcing the new
+    /// datapoints. Returns how many were exported.
+    async fn run(
+        store: &SharedStore,
+        journal: &EventJournal,
+        org_id: &str,
+        rule: &mut CurationRule,
+    ) -> usize {
+        rule.last_run_at = Some(Utc::now());
+        match export_matching(store, rule).await {
+            Ok(datapoints) => {
+                rule.last_error = None;
+                rule.exported_count += datapoints.len() as u64;
+                let exported = datapoints.len();
+                for datapoint in datapoints {
+                    journal.emit(org_id, SystemEvent::DatapointCreated { datapoint });
+                }
+                exported
+            }
+            Err(e) => {
+                warn!(rule_id = %rule.id, "curation rule failed: {e}");
+                rule.last_error = Some(e);
+                0
+            }
+        }
+    }
+
+    /// Run the store's due rules. Returns how many datapoints they exported.
+    async fn run_due(
+        &self,
+        store: &SharedStore,
+        journal: &EventJournal,
+        org_id: &str,
+    ) -> Result<usize, String> {
+        let _edit = self.edits.lock().await;
+        let mut rules = Self::rules(store).await?;
+        let now = Utc::now();
+        let mut ran = false;
+        let mut exported = 0;
+        for rule in rules.iter_mut().filter(|r| r.due(now)) {
+            exported += Self::run(store, journal, org_id, rule).await;
+            ran = true;
+        }
+        if ran {
+            Self::save(store, &rules).await?;
+        }
+        Ok(exported)
+    }
+}
+
+/// Save a datapoint in the rule's dataset for every finished span matching
+/// it that isn't there yet, up to `max_per_run`.
+pub async fn export_matching(
+    store: &SharedStore,
+    rule: &CurationRule,
+) -> Result<Vec<Datapoint>, String> {
+    store
+        .get_dataset_or_load(rule.dataset_id)
+        .await
+        .ok_or("target dataset not found")?;
+    let mut filter = storage::parse_span_query(&rule.query).map_err(|e| e.to_string())?;
+    filter.limit = Some(MAX_SCANNED);
+    let spans = store
+        .query_spans(&filter)
+        .await
+        .map_err(|e| e.to_string())?
+        .items;
+
+    store.sync_datapoints_for_dataset(rule.dataset_id).await;
+    let exported: HashSet<_> = store
+        .datapoints_for_dataset(rule.dataset_id)
+        .into_iter()
+        .filter_map(|dp| dp.source_span_id)
+        .collect();
+
+    let mut created = Vec::new();
+    for span in spans {
+        if created.len() >= rule.max_per_run {
+            break;
+        }
+        if span.ended_at().is_none() || exported.contains(&span.id()) {
+            continue;
+        }
+        if let Some(max) = rule.max_score {
+            let lowest = store
+                .eval_results_for_span(span.id())
+                .iter()
+                .filter_map(|r| r.score)
+                .min_by(f64::total_cmp);
+            if !lowest.is_some_and(|score| score < max) {
+                continue;
+            }
+        }
+        let kind = DatapointKind::Generic {
+            input: span.input().cloned().unwrap_or(serde_json::Value::Null),
+            expected_output: span.output().cloned(),
+            actual_output: None,
+            score: None,
+            metadata: HashMap::from([("curation_rule".to_string(), rule.id.to_string().into())]),
+        };
+        let dp = Datapoint::new(rule.dataset_id, kind, DatapointSource::SpanExport)
+            .with_source_span(span.id());
+        store
+            .save_datapoint(dp.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        created.push(dp);
+    }
+    Ok(created)
+}
+
+/// Spawn the scheduler. It holds the journal weakly and stops once the
+/// router that owns it is gone.
+pub fn spawn_curation_scheduler(
+    curation: Arc<Curation>,
+    journal: Weak<EventJournal>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            for (org_id, store) in curation.org_stores.all_stores().await {
+                match curation.run_due(&store, &journal, &org_id.to_string()).await {
+                    Ok(0) => {}
+                    Ok(exported) => info!(%org_id, exported, "curation rules exported spans"),
+                    Err(e) => warn!(%org_id, "failed to run curation rules: {e}"),
+                }
+            }
+        }
+    })
+}
+
+// --- Handlers ---
+
+pub async fn create_rule(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<CreateCurationRule>,
+) -> Result<(StatusCode, Json<CurationRule>), ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    if req.name.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "name must not be empty"));
+    }
+    storage::parse_span_query(&req.query)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid query: {e}")))?;
+    if req.max_score.is_some_and(|s| !s.is_finite()) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "max_score must be a number"));
+    }
+    if req.interval_minutes == 0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "interval_minutes must be at least 1",
+        ));
+    }
+    if !(1..=MAX_PER_RUN).contains(&req.max_per_run) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("max_per_run must be between 1 and {MAX_PER_RUN}"),
+        ));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    store.get_dataset_or_load(req.dataset_id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+    })?;
+    let rule = CurationRule {
+        id: Uuid::now_v7(),
+        name: req.name,
+        dataset_id: req.dataset_id,
+        query: req.query,
+        max_score: req.max_score,
+        interval_minutes: req.interval_minutes,
+        max_per_run: req.max_per_run,
+        enabled: true,
+        exported_count: 0,
+        created_at: Utc::now(),
+        last_run_at: None,
+        last_error: None,
+    };
+    {
+        let _edit = state.curation.edits.lock().await;
+        let mut rules = Curation::rules(&store)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        rules.push(rule.clone());
+        Curation::save(&store, &rules)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "curation_rule.create",
+        Some(rule.id.to_string()),
+        serde_json::to_value(&rule).unwrap_or_default(),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn list_rules(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<CurationRule>>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let rules = Curation::rules(&store)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(rules))
+}
+
+pub async fn delete_rule(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<CurationRuleId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    {
+        let _edit = state.curation.edits.lock().await;
+        let mut rules = Curation::rules(&store)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let before = rules.len();
+        rules.retain(|r| r.id != id);
+        if rules.len() == before {
+            return Err(rule_not_found());
+        }
+        Curation::save(&store, &rules)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "curation_rule.delete",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a rule now, whether or not it's due or enabled.
+pub async fn run_rule(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<CurationRuleId>,
+) -> Result<Json<CurationRunResult>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let _edit = state.curation.edits.lock().await;
+    let mut rules = Curation::rules(&store)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let rule = rules
+        .iter_mut()
+        .find(|r| r.id == id)
+        .ok_or_else(rule_not_found)?;
+    let exported = Curation::run(&store, &state.journal, &ctx.org_id.to_string(), rule).await;
+    let rule = rule.clone();
+    Curation::save(&store, &rules)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(CurationRunResult { rule, exported }))
+}
+
+fn rule_not_found() -> ApiError {
+    api_error(StatusCode::NOT_FOUND, "curation rule not found").with_code("curation_rule_not_found")
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::{Dataset, EvalResult, EvalRun, ScoringStrategy, SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+    use crate::api::AnyBackend;
+
+    fn rule(dataset_id: DatasetId, query: &str, max_score: Option<f64>) -> CurationRule {
+        CurationRule {
+            id: Uuid::now_v7(),
+            name: "failures".into(),
+            dataset_id,
+            query: query.into(),
+            max_score,
+            interval_minutes: 60,
+            max_per_run: 100,
+            enabled: true,
+            exported_count: 0,
+            created_at: Utc::now(),
+            last_run_at: None,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn rule_is_due_once_per_interval() {
+        let mut r = rule(Uuid::now_v7(), "status:failed", None);
+        let now = Utc::now();
+        assert!(r.due(now));
+        r.last_run_at = Some(now - chrono::Duration::minutes(59));
+        assert!(!r.due(now));
+        r.last_run_at = Some(now - chrono::Duration::minutes(60));
+        assert!(r.due(now));
+        r.enabled = false;
+        assert!(!r.due(now));
+    }
+
+    #[tokio::test]
+    async fn exports_matching_spans_once() {
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let dataset = Dataset::new("failures", None);
+        store.save_dataset(dataset.clone()).await.unwrap();
+        let trace = Trace::new(None);
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+
+        let mut ids = Vec::new();
+        for fail in [true, true, false] {
+            let kind = SpanKind::Custom {
+                kind: "step".into(),
+                attributes: Default::default(),
+            };
+            let span = SpanBuilder::new(trace_id, "call", kind)
+                .input(serde_json::json!({"q": "hi"}))
+                .build();
+            let id = store.insert(span).await.unwrap();
+            if fail {
+                store.fail_span(id, "boom").await.unwrap();
+            } else {
+                store.complete_span(id, None).await.unwrap();
+            }
+            ids.push(id);
+        }
+        // Results reference a run and datapoint, which SQLite enforces
+        let run = EvalRun::new(
+            dataset.id,
+            None,
+            serde_json::from_value(serde_json::json!({ "model": "" })).unwrap(),
+            ScoringStrategy::Scorers,
+        );
+        store.save_eval_run(run.clone()).await.unwrap();
+        let kind = DatapointKind::Generic {
+            input: serde_json::Value::Null,
+            expected_output: None,
+            actual_output: None,
+            score: None,
+            metadata: HashMap::new(),
+        };
+        let dp = Datapoint::new(dataset.id, kind, DatapointSource::Manual);
+        store.save_datapoint(dp.clone()).await.unwrap();
+        let mut scored = EvalResult::new(run.id, dp.id);
+        scored.span_id = Some(ids[0]);
+        scored.score = Some(0.2);
+        store.save_eval_result(scored).await.unwrap();
+
+        let low = rule(dataset.id, "status:failed", Some(0.3));
+        let first = export_matching(&store, &low).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].source_span_id, Some(ids[0]));
+
+        let failed = rule(dataset.id, "status:failed", None);
+        let second = export_matching(&store, &failed).await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].source_span_id, Some(ids[1]));
+        assert!(export_matching(&store, &failed).await.unwrap().is_empty());
+    }
+}
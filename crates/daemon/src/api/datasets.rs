@@ -0,0 +1,408 @@
+//! Dataset splitting and sampling.
+//!
+//! Both endpoints copy datapoints from a source dataset into newly created
+//! datasets; the source is left untouched. Stratifying by a metadata field
+//! keeps each value's share of the datapoints the same in every output.
+//! Passing a `seed` makes the selection reproducible.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use trace::{Datapoint, DatapointKind, Dataset, DatasetId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{
+    api_error, project_store, require_scope, ApiError, AppState, SharedStore, SystemEvent,
+};
+
+/// Body for `POST /api/datasets/:id/split`. Ratios are relative weights and
+/// need not sum to 1; a zero ratio skips that split.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SplitRequest {
+    #[serde(default = "default_train")]
+    pub train: f64,
+    #[serde(default = "default_holdout")]
+    pub validation: f64,
+    #[serde(default = "default_holdout")]
+    pub test: f64,
+    /// Metadata field to stratify by.
+    #[serde(default)]
+    pub stratify_by: Option<String>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+fn default_train() -> f64 {
+    0.8
+}
+
+fn default_holdout() -> f64 {
+    0.1
+}
+
+/// Body for `POST /api/datasets/:id/sample`. Exactly one of `size` and
+/// `fraction` is required.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SampleRequest {
+    #[serde(default)]
+    pub size: Option<usize>,
+    #[serde(default)]
+    pub fraction: Option<f64>,
+    #[serde(default)]
+    pub stratify_by: Option<String>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Name of the new dataset. Defaults to `"<source> (sample)"`.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedDataset {
+    pub dataset: Dataset,
+    pub datapoint_count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SplitResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub train: Option<CreatedDataset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<CreatedDataset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test: Option<CreatedDataset>,
+}
+
+/// Split a dataset's datapoints into new train, validation, and test datasets.
+#[utoipa::path(
+    post,
+    path = "/api/datasets/{id}/split",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    request_body = SplitRequest,
+    responses(
+        (status = 200, body = SplitResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn split_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    Json(req): Json<SplitRequest>,
+) -> Result<Json<SplitResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let ratios = [req.train, req.validation, req.test];
+    if ratios.iter().any(|r| !r.is_finite() || *r < 0.0) || ratios.iter().sum::<f64>() <= 0.0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "ratios must be non-negative and not all zero",
+        ));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    let (source, datapoints) = load_source(&store, id).await?;
+
+    let mut rng = rng(req.seed);
+    let parts = split(datapoints, &ratios, req.stratify_by.as_deref(), &mut rng);
+
+    let org_id = ctx.org_id.to_string();
+    let mut created = Vec::with_capacity(parts.len());
+    for ((label, ratio), part) in ["train", "validation", "test"]
+        .into_iter()
+        .zip(ratios)
+        .zip(parts)
+    {
+        if ratio == 0.0 {
+            created.push(None);
+            continue;
+        }
+        let name = format!("{} ({label})", source.name);
+        created.push(Some(
+            copy_into_new(&state, &store, &source, name, part, &org_id).await?,
+        ));
+    }
+
+    let mut created = created.into_iter();
+    Ok(Json(SplitResponse {
+        train: created.next().flatten(),
+        validation: created.next().flatten(),
+        test: created.next().flatten(),
+    }))
+}
+
+/// Copy a random sample of a dataset's datapoints into a new dataset.
+#[utoipa::path(
+    post,
+    path = "/api/datasets/{id}/sample",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    request_body = SampleRequest,
+    responses(
+        (status = 200, body = CreatedDataset),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn sample_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    Json(req): Json<SampleRequest>,
+) -> Result<Json<CreatedDataset>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    if let Some(f) = req.fraction {
+        if !(f > 0.0 && f <= 1.0) {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "fraction must be in (0, 1]",
+            ));
+        }
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    let (source, datapoints) = load_source(&store, id).await?;
+
+    let size = match (req.size, req.fraction) {
+        (Some(size), None) => size,
+        (None, Some(f)) => (datapoints.len() as f64 * f).round() as usize,
+        _ => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "exactly one of size and fraction is required",
+            ))
+        }
+    };
+    if size == 0 || size > datapoints.len() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "sample size must be between 1 and {} (the dataset's datapoint count)",
+                datapoints.len()
+            ),
+        ));
+    }
+
+    let mut rng = rng(req.seed);
+    let picked = sample(datapoints, size, req.stratify_by.as_deref(), &mut rng);
+    let name = req
+        .name
+        .unwrap_or_else(|| format!("{} (sample)", source.name));
+    let org_id = ctx.org_id.to_string();
+    let created = copy_into_new(&state, &store, &source, name, picked, &org_id).await?;
+    Ok(Json(created))
+}
+
+async fn load_source(
+    store: &SharedStore,
+    id: DatasetId,
+) -> Result<(Dataset, Vec<Datapoint>), ApiError> {
+    let source = store.get_dataset_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+    })?;
+    store.sync_datapoints_for_dataset(id).await;
+    let mut datapoints = store.datapoints_for_dataset(id);
+    if datapoints.is_empty() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "dataset has no datapoints",
+        ));
+    }
+    // Cache order is recency; sort so a seed picks the same datapoints.
+    datapoints.sort_by_key(|dp| dp.id);
+    Ok((source, datapoints))
+}
+
+/// Create a dataset named `name` holding copies of `datapoints`.
+async fn copy_into_new(
+    state: &AppState,
+    store: &SharedStore,
+    source: &Dataset,
+    name: String,
+    datapoints: Vec<Datapoint>,
+    org_id: &str,
+) -> Result<CreatedDataset, ApiError> {
+    let mut dataset = Dataset::new(name, source.description.clone());
+    dataset.org_id = source.org_id;
+    store
+        .save_dataset(dataset.clone())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let datapoint_count = datapoints.len();
+    for dp in datapoints {
+        let mut copy = Datapoint::new(dataset.id, dp.kind, dp.source);
+        copy.source_span_id = dp.source_span_id;
+        store
+            .save_datapoint(copy)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+
+    state.emit_event(
+        SystemEvent::DatasetCreated {
+            dataset: dataset.clone(),
+        },
+        org_id,
+    );
+    Ok(CreatedDataset {
+        dataset,
+        datapoint_count,
+    })
+}
+
+fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Group datapoints by the value of a metadata field, or into one group
+/// when not stratifying. Datapoints missing the field form their own group.
+fn strata(datapoints: Vec<Datapoint>, field: Option<&str>) -> Vec<Vec<Datapoint>> {
+    let Some(field) = field else {
+        return vec![datapoints];
+    };
+    let mut groups: BTreeMap<String, Vec<Datapoint>> = BTreeMap::new();
+    for dp in datapoints {
+        let metadata = match &dp.kind {
+            DatapointKind::LlmConversation { metadata, .. } => metadata,
+            DatapointKind::Generic { metadata, .. } => metadata,
+        };
+        let key = metadata
+            .get(field)
+            .filter(|v| !v.is_null())
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        groups.entry(key).or_default().push(dp);
+    }
+    groups.into_values().collect()
+}
+
+/// Divide `total` into integer parts proportional to `weights`, giving
+/// leftover units to the largest remainders.
+fn apportion(total: usize, weights: &[f64]) -> Vec<usize> {
+    let sum: f64 = weights.iter().sum();
+    if sum <= 0.0 {
+        return vec![0; weights.len()];
+    }
+    let exact: Vec<f64> = weights.iter().map(|w| total as f64 * w / sum).collect();
+    let mut counts: Vec<usize> = exact.iter().map(|e| e.floor() as usize).collect();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder
+        .sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    let leftover = total - counts.iter().sum::<usize>();
+    for &i in by_remainder.iter().take(leftover) {
+        counts[i] += 1;
+    }
+    counts
+}
+
+fn split(
+    datapoints: Vec<Datapoint>,
+    ratios: &[f64],
+    stratify_by: Option<&str>,
+    rng: &mut StdRng,
+) -> Vec<Vec<Datapoint>> {
+    let mut parts: Vec<Vec<Datapoint>> = vec![Vec::new(); ratios.len()];
+    for mut group in strata(datapoints, stratify_by) {
+        group.shuffle(rng);
+        let counts = apportion(group.len(), ratios);
+        let mut rest = group.into_iter();
+        for (part, count) in parts.iter_mut().zip(counts) {
+            part.extend(rest.by_ref().take(count));
+        }
+    }
+    parts
+}
+
+fn sample(
+    datapoints: Vec<Datapoint>,
+    size: usize,
+    stratify_by: Option<&str>,
+    rng: &mut StdRng,
+) -> Vec<Datapoint> {
+    let groups = strata(datapoints, stratify_by);
+    let sizes: Vec<f64> = groups.iter().map(|g| g.len() as f64).collect();
+    let counts = apportion(size, &sizes);
+    let mut picked = Vec::with_capacity(size);
+    for (mut group, count) in groups.into_iter().zip(counts) {
+        group.shuffle(rng);
+        group.truncate(count);
+        picked.append(&mut group);
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use trace::DatapointSource;
+    use uuid::Uuid;
+
+    fn datapoints(labels: &[(&str, usize)]) -> Vec<Datapoint> {
+        let dataset_id = Uuid::now_v7();
+        labels
+            .iter()
+            .flat_map(|&(label, n)| std::iter::repeat_n(label, n))
+            .map(|label| {
+                let kind = DatapointKind::Generic {
+                    input: serde_json::Value::Null,
+                    expected_output: None,
+                    actual_output: None,
+                    score: None,
+                    metadata: HashMap::from([("label".to_string(), label.into())]),
+                };
+                Datapoint::new(dataset_id, kind, DatapointSource::Manual)
+            })
+            .collect()
+    }
+
+    fn count_label(dps: &[Datapoint], label: &str) -> usize {
+        dps.iter()
+            .filter(|dp| match &dp.kind {
+                DatapointKind::Generic { metadata, .. } => metadata["label"] == label,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn apportion_uses_largest_remainders() {
+        assert_eq!(apportion(10, &[0.8, 0.1, 0.1]), [8, 1, 1]);
+        assert_eq!(apportion(7, &[1.0, 1.0, 1.0]), [3, 2, 2]);
+        assert_eq!(apportion(5, &[1.0, 0.0]), [5, 0]);
+        assert_eq!(apportion(3, &[0.0, 0.0]), [0, 0]);
+    }
+
+    #[test]
+    fn stratified_split_keeps_label_shares() {
+        let dps = datapoints(&[("a", 80), ("b", 20)]);
+        let mut rng = StdRng::seed_from_u64(7);
+        let parts = split(dps, &[0.5, 0.25, 0.25], Some("label"), &mut rng);
+        assert_eq!(parts.iter().map(Vec::len).collect::<Vec<_>>(), [50, 25, 25]);
+        assert_eq!(count_label(&parts[0], "a"), 40);
+        assert_eq!(count_label(&parts[1], "b"), 5);
+    }
+
+    #[test]
+    fn seeded_sample_is_reproducible() {
+        let dps = datapoints(&[("a", 30), ("b", 10)]);
+        let first = sample(dps.clone(), 8, Some("label"), &mut StdRng::seed_from_u64(1));
+        let second = sample(dps, 8, Some("label"), &mut StdRng::seed_from_u64(1));
+        assert_eq!(count_label(&first, "a"), 6);
+        assert_eq!(count_label(&first, "b"), 2);
+        let ids = |v: &[Datapoint]| v.iter().map(|dp| dp.id).collect::<Vec<_>>();
+        assert_eq!(ids(&first), ids(&second));
+    }
+}
@@ -0,0 +1,340 @@
+//! Near-duplicate detection within a dataset.
+//!
+//! `POST /api/datasets/:id/dedupe` compares datapoints by the text of their
+//! input and expected output, either as embeddings (cosine similarity) or,
+//! without an embedding provider, as MinHash signatures of word shingles
+//! (estimated Jaccard similarity). Pairs at or above the threshold are
+//! clustered transitively; each cluster keeps its oldest datapoint and can
+//! have the rest deleted.
+
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use storage_turbopuffer::{Embedder, EmbeddingConfig};
+use trace::{Datapoint, DatapointId, DatapointKind, DatasetId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::scorers::{cosine, output_text};
+use super::{api_error, project_store, require_scope, ApiError, AppState};
+
+/// Pairwise comparison is quadratic; larger datasets should be split first.
+const MAX_DATAPOINTS: usize = 5_000;
+/// Concurrent embedding requests.
+const EMBED_CONCURRENCY: usize = 8;
+const MINHASH_PERMUTATIONS: usize = 128;
+const SHINGLE_WORDS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeMethod {
+    Embedding,
+    Minhash,
+}
+
+/// Body for `POST /api/datasets/:id/dedupe`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DedupeRequest {
+    /// Similarity in `(0, 1]` at which two datapoints count as duplicates.
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+    /// Defaults to `embedding` when an embedding provider is configured,
+    /// else `minhash`.
+    #[serde(default)]
+    pub method: Option<DedupeMethod>,
+    /// Delete every datapoint of each group except the one kept.
+    #[serde(default)]
+    pub delete: bool,
+}
+
+fn default_threshold() -> f64 {
+    0.9
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Duplicate {
+    #[schema(value_type = String)]
+    pub id: DatapointId,
+    /// Similarity to the kept datapoint.
+    pub similarity: f64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicateGroup {
+    /// The group's oldest datapoint.
+    #[schema(value_type = String)]
+    pub keep: DatapointId,
+    pub duplicates: Vec<Duplicate>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DedupeResponse {
+    pub method: DedupeMethod,
+    pub groups: Vec<DuplicateGroup>,
+    /// Datapoints deleted; zero unless `delete` was set.
+    pub deleted: usize,
+}
+
+/// Find groups of near-duplicate datapoints in a dataset, optionally
+/// deleting all but the oldest of each.
+#[utoipa::path(
+    post,
+    path = "/api/datasets/{id}/dedupe",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    request_body = DedupeRequest,
+    responses(
+        (status = 200, body = DedupeResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn dedupe_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    Json(req): Json<DedupeRequest>,
+) -> Result<Json<DedupeResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    if !(req.threshold > 0.0 && req.threshold <= 1.0) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "threshold must be in (0, 1]",
+        ));
+    }
+    let embedding = EmbeddingConfig::from_env()
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let method = match (req.method, &embedding) {
+        (Some(DedupeMethod::Embedding), None) => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "embedding dedupe needs an embedding provider (TRACEWAY_EMBEDDING_PROVIDER)",
+            ))
+        }
+        (Some(method), _) => method,
+        (None, Some(_)) => DedupeMethod::Embedding,
+        (None, None) => DedupeMethod::Minhash,
+    };
+
+    let store = project_store(&ctx, &state).await?;
+    store.get_dataset_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+    })?;
+    store.sync_datapoints_for_dataset(id).await;
+    let mut datapoints = store.datapoints_for_dataset(id);
+    if datapoints.len() > MAX_DATAPOINTS {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("dedupe supports up to {MAX_DATAPOINTS} datapoints; split the dataset first"),
+        ));
+    }
+    // Ids are time-ordered, so the first of a group is the oldest
+    datapoints.sort_by_key(|dp| dp.id);
+    let ids: Vec<DatapointId> = datapoints.iter().map(|dp| dp.id).collect();
+    let texts: Vec<String> = datapoints.iter().map(datapoint_text).collect();
+
+    let similarity: Box<dyn Fn(usize, usize) -> f64 + Send> = match (method, embedding) {
+        (DedupeMethod::Embedding, Some(config)) => {
+            let max_chars = config.max_input_chars;
+            let embedder = Embedder::new(reqwest::Client::new(), config);
+            let inputs: Vec<String> = texts
+                .iter()
+                .map(|text| text.chars().take(max_chars).collect())
+                .collect();
+            let vectors: Vec<Vec<f32>> = stream::iter(inputs)
+                .map(|text| {
+                    let embedder = &embedder;
+                    async move { embedder.embed(&text).await }
+                })
+                .buffered(EMBED_CONCURRENCY)
+                .try_collect()
+                .await
+                .map_err(|e| api_error(StatusCode::BAD_GATEWAY, format!("embedding failed: {e}")))?;
+            Box::new(move |a, b| cosine(&vectors[a], &vectors[b]))
+        }
+        _ => {
+            let signatures: Vec<_> = texts.iter().map(|t| minhash(t)).collect();
+            Box::new(move |a, b| jaccard_estimate(&signatures[a], &signatures[b]))
+        }
+    };
+    let threshold = req.threshold;
+    let clusters = tokio::task::spawn_blocking(move || cluster(ids.len(), threshold, similarity))
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut deleted = 0;
+    if req.delete {
+        for group in &clusters {
+            for &(i, _) in &group.1 {
+                if store
+                    .delete_datapoint(datapoints[i].id)
+                    .await
+                    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                {
+                    deleted += 1;
+                }
+            }
+        }
+    }
+
+    let groups = clusters
+        .into_iter()
+        .map(|(keep, duplicates)| DuplicateGroup {
+            keep: datapoints[keep].id,
+            duplicates: duplicates
+                .into_iter()
+                .map(|(i, similarity)| Duplicate {
+                    id: datapoints[i].id,
+                    similarity,
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(Json(DedupeResponse {
+        method,
+        groups,
+        deleted,
+    }))
+}
+
+/// The text datapoints are compared by: the conversation or input, then
+/// the expected output.
+fn datapoint_text(dp: &Datapoint) -> String {
+    match &dp.kind {
+        DatapointKind::LlmConversation {
+            messages, expected, ..
+        } => messages
+            .iter()
+            .chain(expected)
+            .map(|m| format!("{}: {}", m.role, m.content))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        DatapointKind::Generic {
+            input,
+            expected_output,
+            ..
+        } => {
+            let mut text = output_text(input);
+            if let Some(expected) = expected_output {
+                text.push('\n');
+                text.push_str(&output_text(expected));
+            }
+            text
+        }
+    }
+}
+
+/// Group indices `0..n` whose pairwise similarity reaches `threshold`,
+/// transitively. Returns `(kept, [(duplicate, similarity to kept)])` for
+/// each group of two or more, where `kept` is the group's lowest index.
+fn cluster(
+    n: usize,
+    threshold: f64,
+    similarity: impl Fn(usize, usize) -> f64,
+) -> Vec<(usize, Vec<(usize, f64)>)> {
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for a in 0..n {
+        for b in a + 1..n {
+            if similarity(a, b) >= threshold {
+                let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+                // The lower index stays root, so it's the one kept
+                parent[ra.max(rb)] = ra.min(rb);
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<(usize, f64)>> = BTreeMap::new();
+    for i in 0..n {
+        let r = root(&mut parent, i);
+        if r != i {
+            groups.entry(r).or_default().push((i, similarity(r, i)));
+        }
+    }
+    groups.into_iter().collect()
+}
+
+/// Lowercased word shingles of `text`, or the whole text when it's
+/// shorter than one shingle.
+fn shingles(text: &str) -> HashSet<String> {
+    let words: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+    if words.len() < SHINGLE_WORDS {
+        return HashSet::from([words.join(" ")]);
+    }
+    words.windows(SHINGLE_WORDS).map(|w| w.join(" ")).collect()
+}
+
+fn minhash(text: &str) -> Vec<u64> {
+    let hashes: Vec<u64> = shingles(text)
+        .iter()
+        .map(|s| {
+            let mut hasher = DefaultHasher::new();
+            s.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect();
+    (0..MINHASH_PERMUTATIONS as u64)
+        .map(|seed| {
+            hashes
+                .iter()
+                .map(|&h| splitmix64(h ^ seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)))
+                .min()
+                .unwrap_or(u64::MAX)
+        })
+        .collect()
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// The share of permutations whose minimum agrees, which estimates the
+/// Jaccard similarity of the shingle sets.
+fn jaccard_estimate(a: &[u64], b: &[u64]) -> f64 {
+    let same = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    same as f64 / a.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minhash_tracks_jaccard_similarity() {
+        let base = "the quick brown fox jumps over the lazy dog near the river bank today";
+        let near = "the quick brown fox jumps over the lazy dog near the river bank tonight";
+        let other = "an entirely different sentence about billing invoices and refunds";
+        let (a, b, c) = (minhash(base), minhash(near), minhash(other));
+        assert_eq!(jaccard_estimate(&a, &minhash(base)), 1.0);
+        assert!(jaccard_estimate(&a, &b) > 0.7);
+        assert!(jaccard_estimate(&a, &c) < 0.1);
+    }
+
+    #[test]
+    fn clusters_transitively_and_keeps_the_lowest_index() {
+        // 0~1 and 1~2 are similar, 0 and 2 are not; 3 stands alone
+        let sim = |a: usize, b: usize| match (a.min(b), a.max(b)) {
+            (0, 1) | (1, 2) => 0.95,
+            (0, 2) => 0.85,
+            _ => 0.1,
+        };
+        let groups = cluster(4, 0.9, sim);
+        assert_eq!(groups, [(0, vec![(1, 0.95), (2, 0.85)])]);
+        assert!(cluster(4, 0.99, sim).is_empty());
+    }
+}
@@ -0,0 +1,513 @@
+//! Versioned dataset and datapoint edits.
+//!
+//! Every dataset and datapoint carries a version that each update bumps.
+//! Reads return it as a strong `ETag`; updates honour `If-Match` and fail
+//! with `412 Precondition Failed` when the record has changed since the
+//! client read it. The write itself is a compare-and-swap in the storage
+//! backend, so instances sharing a backend can't overwrite each other's
+//! edits either.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use storage::Versioned;
+use trace::{Datapoint, DatapointId, DatapointKind, Dataset, DatasetId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::{
+    api_error, audit, project_store, require_scope, ApiError, AppState, SharedStore, SystemEvent,
+};
+
+/// Most datapoints one batch update may touch.
+const MAX_BATCH_UPDATES: usize = 1000;
+
+/// Body for `PATCH /api/datasets/:id`. Omitted fields are left as they are.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateDatasetRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// An empty string clears the description.
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Body for `PATCH /api/datapoints/:id`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateDatapointRequest {
+    pub kind: DatapointKind,
+}
+
+/// One entry of a batch update: the new content of a datapoint, and the
+/// version it was read at.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DatapointUpdate {
+    #[schema(value_type = String)]
+    pub id: DatapointId,
+    pub version: u64,
+    pub kind: DatapointKind,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchUpdateRequest {
+    pub updates: Vec<DatapointUpdate>,
+}
+
+/// A batch entry that wasn't applied because the datapoint had moved past
+/// the version the client sent.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DatapointConflict {
+    pub requested_version: u64,
+    /// The datapoint as it now is.
+    pub current: Datapoint,
+}
+
+/// Result of a batch update. Each entry is applied or refused on its own.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchUpdateResponse {
+    pub updated: Vec<Datapoint>,
+    pub conflicts: Vec<DatapointConflict>,
+    /// Ids that aren't datapoints of this dataset.
+    #[schema(value_type = Vec<String>)]
+    pub not_found: Vec<DatapointId>,
+}
+
+fn version_conflict(what: &str) -> ApiError {
+    api_error(
+        StatusCode::PRECONDITION_FAILED,
+        format!("{what} has changed since it was read; fetch it again and retry"),
+    )
+    .with_code("version_conflict")
+}
+
+fn dataset_not_found() -> ApiError {
+    api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+}
+
+fn datapoint_not_found() -> ApiError {
+    api_error(StatusCode::NOT_FOUND, "datapoint not found").with_code("datapoint_not_found")
+}
+
+/// A datapoint whose dataset is outside the trash, read from the backend
+/// so its version is current.
+/// A datapoint and its dataset as the backend has them, not the cache, so
+/// a version checked against `If-Match` is the one `update_datapoint`
+/// compares with, and a dataset trashed elsewhere hides its datapoints.
+async fn load_datapoint(store: &SharedStore, id: DatapointId) -> Result<Datapoint, ApiError> {
+    let internal = |e: storage::StorageError| api_error(StatusCode::INTERNAL_SERVER_ERROR, e);
+    let dp = store
+        .reload_datapoint(id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(datapoint_not_found)?;
+    if store.reload_dataset(dp.dataset_id).await.map_err(internal)?.is_none() {
+        return Err(datapoint_not_found());
+    }
+    Ok(dp)
+}
+
+/// A dataset, tagged with its version.
+#[utoipa::path(
+    get,
+    path = "/api/datasets/{id}",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    responses(
+        (status = 200, body = Dataset, headers(("ETag" = String, description = "The dataset's version"))),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let dataset = project_store(&ctx, &state)
+        .await?
+        .reload_dataset(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(dataset_not_found)?;
+    let etag = ETag::for_version(dataset.version);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    Ok(etag.respond(Json(dataset)))
+}
+
+/// Rename a dataset or change its description. Send the `ETag` from the
+/// last read as `If-Match` to refuse the update if someone else changed
+/// the dataset in between.
+#[utoipa::path(
+    patch,
+    path = "/api/datasets/{id}",
+    tag = "datasets",
+    params(
+        ("id" = String, Path, description = "Dataset id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the update is conditioned on"),
+    ),
+    request_body = UpdateDatasetRequest,
+    responses(
+        (status = 200, body = Dataset, headers(("ETag" = String, description = "The dataset's new version"))),
+        (status = 412, description = "The dataset has changed since the `If-Match` tag", body = Problem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn update_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    headers: HeaderMap,
+    Json(req): Json<UpdateDatasetRequest>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    if req.name.as_deref().is_some_and(|n| n.trim().is_empty()) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "name must not be empty"));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    let mut dataset = store
+        .reload_dataset(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(dataset_not_found)?;
+    if !ETag::for_version(dataset.version).permits_write(&headers) {
+        return Err(version_conflict("dataset"));
+    }
+    let before = serde_json::json!({ "name": dataset.name, "description": dataset.description });
+    if let Some(name) = req.name {
+        dataset.name = name.trim().to_string();
+    }
+    if let Some(description) = req.description {
+        dataset.description = Some(description).filter(|d| !d.is_empty());
+    }
+
+    let dataset = match store
+        .update_dataset(dataset)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+    {
+        Versioned::Updated(dataset) => dataset,
+        Versioned::Conflict(_) => return Err(version_conflict("dataset")),
+        Versioned::NotFound => return Err(dataset_not_found()),
+    };
+    audit::record(
+        &state,
+        &ctx,
+        "dataset.update",
+        Some(id.to_string()),
+        serde_json::json!({
+            "before": before,
+            "after": { "name": dataset.name, "description": dataset.description },
+            "version": dataset.version,
+        }),
+    )
+    .await;
+    state.emit_event(
+        SystemEvent::DatasetUpdated {
+            dataset: dataset.clone(),
+        },
+        &ctx.org_id.to_string(),
+    );
+    Ok(ETag::for_version(dataset.version).respond(Json(dataset)))
+}
+
+/// A datapoint, tagged with its version.
+#[utoipa::path(
+    get,
+    path = "/api/datapoints/{id}",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Datapoint id")),
+    responses(
+        (status = 200, body = Datapoint, headers(("ETag" = String, description = "The datapoint's version"))),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_datapoint(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatapointId>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let dp = load_datapoint(&store, id).await?;
+    let etag = ETag::for_version(dp.version);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    Ok(etag.respond(Json(dp)))
+}
+
+/// Replace a datapoint's content, conditioned on `If-Match` like dataset
+/// updates.
+#[utoipa::path(
+    patch,
+    path = "/api/datapoints/{id}",
+    tag = "datasets",
+    params(
+        ("id" = String, Path, description = "Datapoint id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the update is conditioned on"),
+    ),
+    request_body = UpdateDatapointRequest,
+    responses(
+        (status = 200, body = Datapoint, headers(("ETag" = String, description = "The datapoint's new version"))),
+        (status = 412, description = "The datapoint has changed since the `If-Match` tag", body = Problem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn update_datapoint(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatapointId>,
+    headers: HeaderMap,
+    Json(req): Json<UpdateDatapointRequest>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let mut dp = load_datapoint(&store, id).await?;
+    if !ETag::for_version(dp.version).permits_write(&headers) {
+        return Err(version_conflict("datapoint"));
+    }
+    dp.kind = req.kind;
+
+    let dp = match store
+        .update_datapoint(dp)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+    {
+        Versioned::Updated(dp) => dp,
+        Versioned::Conflict(_) => return Err(version_conflict("datapoint")),
+        Versioned::NotFound => return Err(datapoint_not_found()),
+    };
+    audit::record(
+        &state,
+        &ctx,
+        "datapoint.update",
+        Some(id.to_string()),
+        serde_json::json!({ "dataset_id": dp.dataset_id, "version": dp.version }),
+    )
+    .await;
+    state.emit_event(
+        SystemEvent::DatapointUpdated {
+            datapoint: dp.clone(),
+        },
+        &ctx.org_id.to_string(),
+    );
+    Ok(ETag::for_version(dp.version).respond(Json(dp)))
+}
+
+/// Update many of a dataset's datapoints at once. Each entry names the
+/// version it was read at and is applied only if the datapoint is still
+/// there; the rest come back as conflicts with the current copy.
+#[utoipa::path(
+    patch,
+    path = "/api/datasets/{id}/datapoints",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    request_body = BatchUpdateRequest,
+    responses(
+        (status = 200, body = BatchUpdateResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn update_datapoints(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    Json(req): Json<BatchUpdateRequest>,
+) -> Result<Json<BatchUpdateResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    if req.updates.len() > MAX_BATCH_UPDATES {
+        return Err(api_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("at most {MAX_BATCH_UPDATES} updates per batch"),
+        ));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    let dataset = store
+        .reload_dataset(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if dataset.is_none() {
+        return Err(dataset_not_found());
+    }
+    store.sync_datapoints_for_dataset(id).await;
+
+    let mut response = BatchUpdateResponse {
+        updated: Vec::new(),
+        conflicts: Vec::new(),
+        not_found: Vec::new(),
+    };
+    for update in req.updates {
+        let Some(mut dp) = store
+            .get_datapoint(update.id)
+            .filter(|dp| dp.dataset_id == id)
+        else {
+            response.not_found.push(update.id);
+            continue;
+        };
+        // The backend checks the version the client sent, not the cached one.
+        dp.version = update.version;
+        dp.kind = update.kind;
+        match store
+            .update_datapoint(dp)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        {
+            Versioned::Updated(dp) => response.updated.push(dp),
+            Versioned::Conflict(current) => response.conflicts.push(DatapointConflict {
+                requested_version: update.version,
+                current,
+            }),
+            Versioned::NotFound => response.not_found.push(update.id),
+        }
+    }
+
+    audit::record(
+        &state,
+        &ctx,
+        "datapoint.batch_update",
+        Some(id.to_string()),
+        serde_json::json!({
+            "updated": response.updated.iter().map(|dp| dp.id).collect::<Vec<_>>(),
+            "conflicts": response.conflicts.len(),
+            "not_found": response.not_found.len(),
+        }),
+    )
+    .await;
+    let org_id = ctx.org_id.to_string();
+    for dp in &response.updated {
+        state.emit_event(
+            SystemEvent::DatapointUpdated {
+                datapoint: dp.clone(),
+            },
+            &org_id,
+        );
+    }
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::DatapointSource;
+    use uuid::Uuid;
+
+    use super::super::AnyBackend;
+    use super::*;
+
+    async fn open(path: &std::path::Path) -> PersistentStore<AnyBackend> {
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(path).unwrap());
+        PersistentStore::open(backend).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn stale_updates_from_another_instance_conflict() {
+        let dir = std::env::temp_dir().join(format!("edits-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let first = open(&db).await;
+        let dataset = Dataset::new("golden", None);
+        first.save_dataset(dataset.clone()).await.unwrap();
+        let kind = DatapointKind::Generic {
+            input: "q".into(),
+            expected_output: None,
+            actual_output: None,
+            score: None,
+            metadata: HashMap::new(),
+        };
+        let dp = Datapoint::new(dataset.id, kind, DatapointSource::Manual);
+        first.save_datapoint(dp.clone()).await.unwrap();
+
+        // Both instances have version 0 cached
+        let second = open(&db).await;
+        let mut renamed = second.get_dataset(dataset.id).unwrap();
+        renamed.name = "golden v2".into();
+        let Versioned::Updated(renamed) = second.update_dataset(renamed).await.unwrap() else {
+            panic!("first update should apply");
+        };
+        assert_eq!(renamed.version, 1);
+
+        let mut stale = first.get_dataset(dataset.id).unwrap();
+        stale.description = Some("lost".into());
+        let Versioned::Conflict(current) = first.update_dataset(stale).await.unwrap() else {
+            panic!("stale update should conflict");
+        };
+        assert_eq!(current.name, "golden v2");
+        assert_eq!(first.get_dataset(dataset.id).unwrap().version, 1);
+
+        let mut edited = dp.clone();
+        edited.kind = DatapointKind::Generic {
+            input: "q2".into(),
+            expected_output: None,
+            actual_output: None,
+            score: None,
+            metadata: HashMap::new(),
+        };
+        assert!(matches!(
+            second.update_datapoint(edited.clone()).await.unwrap(),
+            Versioned::Updated(_)
+        ));
+        assert!(matches!(
+            first.update_datapoint(edited).await.unwrap(),
+            Versioned::Conflict(current) if current.version == 1
+        ));
+
+        first.delete_dataset(dataset.id).await.unwrap();
+        assert!(matches!(
+            second.update_dataset(current).await.unwrap(),
+            Versioned::NotFound
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn datapoints_are_checked_against_the_backend() {
+        let dir = std::env::temp_dir().join(format!("edits-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let first: SharedStore = std::sync::Arc::new(open(&db).await);
+        let dataset = Dataset::new("golden", None);
+        first.save_dataset(dataset.clone()).await.unwrap();
+        let kind = DatapointKind::Generic {
+            input: "q".into(),
+            expected_output: None,
+            actual_output: None,
+            score: None,
+            metadata: HashMap::new(),
+        };
+        let dp = Datapoint::new(dataset.id, kind, DatapointSource::Manual);
+        first.save_datapoint(dp.clone()).await.unwrap();
+        assert_eq!(load_datapoint(&first, dp.id).await.unwrap().version, 0);
+
+        // Another instance edits the datapoint, then trashes its dataset
+        let second = open(&db).await;
+        assert!(matches!(
+            second.update_datapoint(dp.clone()).await.unwrap(),
+            Versioned::Updated(_)
+        ));
+        assert_eq!(load_datapoint(&first, dp.id).await.unwrap().version, 1);
+        second.trash_dataset(dataset.id).await.unwrap();
+        assert!(first.get_dataset(dataset.id).is_some());
+        assert!(load_datapoint(&first, dp.id).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
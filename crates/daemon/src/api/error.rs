@@ -0,0 +1,183 @@
+//! API errors as RFC 7807 problem details.
+//!
+//! Every handler error is an [`ApiError`], rendered as
+//! `application/problem+json`. `code` is a stable identifier for clients to
+//! branch on, such as `dataset_not_found`; without a more specific one it is
+//! derived from the status (`not_found`, `bad_request`, ...). `detail` is
+//! for people and may change. The body also repeats `detail` as `error`,
+//! the field of the older `{"error": "..."}` responses.
+
+use std::borrow::Cow;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{ToResponse, ToSchema};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Body of every error response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema, ToResponse)]
+#[response(
+    description = "Problem details",
+    content_type = "application/problem+json"
+)]
+pub struct Problem {
+    /// Always `about:blank`; `code` identifies the problem.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Reason phrase of `status`.
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Machine-readable problem code.
+    pub code: String,
+    /// Same as `detail`.
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    code: Cow<'static, str>,
+    detail: String,
+}
+
+impl ApiError {
+    /// Error with the generic code for `status`.
+    pub fn new(status: StatusCode, detail: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            code: status_code(status).into(),
+            detail: detail.to_string(),
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code.into();
+        self
+    }
+
+    pub fn problem(&self) -> Problem {
+        Problem {
+            problem_type: "about:blank".to_string(),
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or("Error")
+                .to_string(),
+            status: self.status.as_u16(),
+            detail: self.detail.clone(),
+            code: self.code.to_string(),
+            error: self.detail.clone(),
+        }
+    }
+}
+
+impl From<auth::AuthError> for ApiError {
+    fn from(e: auth::AuthError) -> Self {
+        let status = StatusCode::from_u16(e.status_code()).unwrap_or(StatusCode::UNAUTHORIZED);
+        Self::new(status, &e).with_code(e.code())
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.status, self.code, self.detail)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.problem())).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+}
+
+/// Rewrite error responses that didn't come from an [`ApiError`], such as
+/// extractor rejections and 405s, as problem details. Responses that are
+/// already JSON are left alone.
+pub async fn problem_fallback(response: Response) -> Response {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json") || v.starts_with(PROBLEM_JSON));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+    let body = axum::body::to_bytes(response.into_body(), MAX_FALLBACK_DETAIL)
+        .await
+        .unwrap_or_default();
+    let detail = String::from_utf8_lossy(&body);
+    let detail = match detail.trim() {
+        "" => status.canonical_reason().unwrap_or("error"),
+        text => text,
+    };
+    ApiError::new(status, detail).into_response()
+}
+
+/// Longest plain-text error body carried over as `detail`.
+const MAX_FALLBACK_DETAIL: usize = 64 * 1024;
+
+/// Generic code for `status`: its reason phrase in snake case.
+fn status_code(status: StatusCode) -> String {
+    match status.canonical_reason() {
+        Some(reason) => reason
+            .chars()
+            .filter_map(|c| match c {
+                ' ' | '-' => Some('_'),
+                '\'' => None,
+                c => Some(c.to_ascii_lowercase()),
+            })
+            .collect(),
+        None => "error".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_problem_json() {
+        let err = ApiError::new(StatusCode::NOT_FOUND, "dataset not found");
+        assert_eq!(err.problem().code, "not_found");
+        let err = err.with_code("dataset_not_found");
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+
+        let problem = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "slow down").problem();
+        assert_eq!(problem.code, "too_many_requests");
+        assert_eq!(problem.title, "Too Many Requests");
+        assert_eq!(problem.status, 429);
+        assert_eq!(problem.error, problem.detail);
+        let body = serde_json::to_value(&problem).unwrap();
+        assert_eq!(body["type"], "about:blank");
+    }
+
+    #[tokio::test]
+    async fn fallback_wraps_plain_text_errors() {
+        let rejection = (StatusCode::UNPROCESSABLE_ENTITY, "missing field `name`").into_response();
+        let response = problem_fallback(rejection).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let problem: Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.code, "unprocessable_entity");
+        assert_eq!(problem.detail, "missing field `name`");
+
+        let ok = problem_fallback("fine".into_response()).await;
+        assert_eq!(ok.status(), StatusCode::OK);
+    }
+}
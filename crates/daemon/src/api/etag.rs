@@ -0,0 +1,153 @@
+//! Conditional GETs for polled read endpoints.
+//!
+//! An [`ETag`] combines the project store's revision, which changes on every
+//! span and trace write, with the request's path and query. A request whose
+//! `If-None-Match` names the current tag gets `304 Not Modified` without the
+//! store being queried.
+//!
+//! Editable records are tagged with their version instead, and writes to
+//! them honour `If-Match`, so a client can't overwrite an edit it hasn't
+//! seen.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+
+/// Polled responses may be cached but must be revalidated.
+const REVALIDATE: &str = "private, no-cache";
+/// For content addressed by its hash.
+const IMMUTABLE: &str = "private, max-age=31536000, immutable";
+
+pub struct ETag {
+    value: HeaderValue,
+    cache_control: &'static str,
+}
+
+impl ETag {
+    /// Tag for `uri` at `revision` of the project's store.
+    pub fn for_revision(revision: u64, uri: &Uri) -> Self {
+        let mut hasher = DefaultHasher::new();
+        uri.path().hash(&mut hasher);
+        uri.query().hash(&mut hasher);
+        Self {
+            value: HeaderValue::from_str(&format!("W/\"{revision:x}-{:x}\"", hasher.finish()))
+                .expect("hex digits are a valid header value"),
+            cache_control: REVALIDATE,
+        }
+    }
+
+    /// Strong tag for version `version` of a single record.
+    pub fn for_version(version: u64) -> Self {
+        Self {
+            value: HeaderValue::from_str(&format!("\"v{version}\""))
+                .expect("digits are a valid header value"),
+            cache_control: REVALIDATE,
+        }
+    }
+
+    /// Tag for content that never changes, such as a content-addressed
+    /// blob. `None` when `hash` can't be quoted in a header.
+    pub fn immutable(hash: &str) -> Option<Self> {
+        Some(Self {
+            value: HeaderValue::from_str(&format!("\"{hash}\"")).ok()?,
+            cache_control: IMMUTABLE,
+        })
+    }
+
+    /// Whether the request's `If-None-Match` names this tag, compared
+    /// weakly as RFC 9110 requires.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        let ours = opaque(self.value.to_str().unwrap_or_default());
+        headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .any(|tag| tag == "*" || opaque(tag) == ours)
+    }
+
+    /// Whether the request's `If-Match` lets a write replace the record
+    /// this tags: it is absent, `*`, or names this tag. Compared strongly,
+    /// so a weak tag never matches.
+    pub fn permits_write(&self, headers: &HeaderMap) -> bool {
+        let ours = self.value.to_str().unwrap_or_default();
+        let mut values = headers
+            .get_all(header::IF_MATCH)
+            .iter()
+            .map(|v| v.to_str().unwrap_or_default())
+            .peekable();
+        if values.peek().is_none() {
+            return true;
+        }
+        values
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == ours)
+    }
+
+    pub fn not_modified(self) -> Response {
+        self.respond(StatusCode::NOT_MODIFIED)
+    }
+
+    /// `body` with this tag and its `Cache-Control`.
+    pub fn respond(self, body: impl IntoResponse) -> Response {
+        let mut response = body.into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, self.value);
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(self.cache_control),
+        );
+        response
+    }
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_none_match(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn tags_change_with_revision_and_query() {
+        let uri: Uri = "/traces?limit=10".parse().unwrap();
+        let tag = ETag::for_revision(7, &uri);
+        let current = tag.value.to_str().unwrap().to_string();
+        assert!(tag.matches(&if_none_match(&current)));
+        assert!(tag.matches(&if_none_match(&format!("\"x\", {}", &current[2..]))));
+        assert!(tag.matches(&if_none_match("*")));
+        assert!(!tag.matches(&HeaderMap::new()));
+
+        assert!(!ETag::for_revision(8, &uri).matches(&if_none_match(&current)));
+        let other: Uri = "/traces?limit=20".parse().unwrap();
+        assert!(!ETag::for_revision(7, &other).matches(&if_none_match(&current)));
+    }
+
+    #[test]
+    fn if_match_compares_versions_strongly() {
+        let if_match = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        let tag = ETag::for_version(3);
+        assert!(tag.permits_write(&HeaderMap::new()));
+        assert!(tag.permits_write(&if_match("\"v3\"")));
+        assert!(tag.permits_write(&if_match("\"v2\", \"v3\"")));
+        assert!(tag.permits_write(&if_match("*")));
+        assert!(!tag.permits_write(&if_match("\"v2\"")));
+        assert!(!tag.permits_write(&if_match("W/\"v3\"")));
+    }
+}
@@ -92,18 +92,24 @@ impl EventLog for SqliteEventLog {
         .map_err(|e| EventLogError::Storage(format!("spawn_blocking join error: {e}")))?
     }
 
-    async fn read_after(&self, after_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>, EventLogError> {
+    async fn read_after(
+        &self,
+        org_id: &str,
+        after_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>, EventLogError> {
         let conn = self.conn.clone();
+        let org_id = org_id.to_string();
         tokio::task::spawn_blocking(move || {
             let conn = conn.blocking_lock();
             let mut stmt = conn
                 .prepare(
-                    "SELECT sequence, event_data, org_id, created_at FROM event_log WHERE sequence > ?1 ORDER BY sequence ASC LIMIT ?2",
+                    "SELECT sequence, event_data, org_id, created_at FROM event_log WHERE org_id = ?1 AND sequence > ?2 ORDER BY sequence ASC LIMIT ?3",
                 )
                 .map_err(|e| EventLogError::Storage(e.to_string()))?;
 
             let rows = stmt
-                .query_map(params![after_sequence as i64, limit as i64], |row| {
+                .query_map(params![org_id, after_sequence as i64, limit as i64], |row| {
                     let sequence: i64 = row.get(0)?;
                     let event_data: String = row.get(1)?;
                     let org_id: String = row.get(2)?;
@@ -155,12 +161,30 @@ impl EventLog for SqliteEventLog {
     }
 
     async fn latest_sequence(&self) -> Result<u64, EventLogError> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.blocking_lock();
+            // sqlite_sequence keeps the high-water mark even after trimming
+            let seq: i64 = conn
+                .query_row(
+                    "SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'event_log'), 0)",
+                    [],
+                    |row| row.get(0),
+                )
+                .map_err(|e| EventLogError::Storage(e.to_string()))?;
+            Ok(seq as u64)
+        })
+        .await
+        .map_err(|e| EventLogError::Storage(format!("spawn_blocking join error: {e}")))?
+    }
+
+    async fn earliest_sequence(&self) -> Result<u64, EventLogError> {
         let conn = self.conn.clone();
         tokio::task::spawn_blocking(move || {
             let conn = conn.blocking_lock();
             let seq: i64 = conn
                 .query_row(
-                    "SELECT COALESCE(MAX(sequence), 0) FROM event_log",
+                    "SELECT COALESCE(MIN(sequence), 0) FROM event_log",
                     [],
                     |row| row.get(0),
                 )
@@ -173,24 +197,29 @@ impl EventLog for SqliteEventLog {
 }
 
 /// Extract the serde tag name for a SystemEvent variant.
-fn event_type_name(event: &SystemEvent) -> &'static str {
+pub fn event_type_name(event: &SystemEvent) -> &'static str {
     match event {
         SystemEvent::SpanCreated { .. } => "span_created",
         SystemEvent::SpanCompleted { .. } => "span_completed",
         SystemEvent::SpanFailed { .. } => "span_failed",
+        SystemEvent::SpanStreaming { .. } => "span_streaming",
         SystemEvent::TraceCreated { .. } => "trace_created",
         SystemEvent::TraceCompleted { .. } => "trace_completed",
         SystemEvent::FileVersionCreated { .. } => "file_version_created",
         SystemEvent::SpanDeleted { .. } => "span_deleted",
         SystemEvent::TraceDeleted { .. } => "trace_deleted",
         SystemEvent::DatasetCreated { .. } => "dataset_created",
+        SystemEvent::DatasetUpdated { .. } => "dataset_updated",
         SystemEvent::DatasetDeleted { .. } => "dataset_deleted",
         SystemEvent::DatapointCreated { .. } => "datapoint_created",
+        SystemEvent::DatapointUpdated { .. } => "datapoint_updated",
         SystemEvent::QueueItemUpdated { .. } => "queue_item_updated",
         SystemEvent::EvalRunCreated { .. } => "eval_run_created",
         SystemEvent::EvalRunUpdated { .. } => "eval_run_updated",
         SystemEvent::EvalRunCompleted { .. } => "eval_run_completed",
         SystemEvent::CaptureRuleFired { .. } => "capture_rule_fired",
+        SystemEvent::BudgetAlert { .. } => "budget_alert",
+        SystemEvent::AnomalyDetected { .. } => "anomaly_detected",
         SystemEvent::Cleared => "cleared",
     }
 }
@@ -0,0 +1,456 @@
+//! Resumable event delivery.
+//!
+//! Every journaled event carries a sequence number. Consumers pass the last
+//! sequence they saw (`after`, or a `Last-Event-ID` header) to resume without
+//! losing or repeating events. When the events they missed are no longer in
+//! the journal, a gap is reported first so they can refetch state instead.
+//!
+//! Every endpoint takes optional filters, applied server-side; filtered-out
+//! events still advance the resume point.
+
+use std::collections::HashSet;
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use storage::{SpanFilter, StorageError};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use trace::{SpanId, TraceId};
+
+use super::event_log::event_type_name;
+use super::events::{EventGap, EventJournal, StoredEvent};
+use super::{api_error, require_scope, ApiError, AppState, SystemEvent, MAX_PAGE_LIMIT};
+
+/// Events read from the journal per query while replaying.
+const REPLAY_BATCH: usize = 500;
+const DEFAULT_POLL_LIMIT: usize = 100;
+
+/// Query parameters for `GET /api/events` and `GET /api/events/poll`.
+#[derive(Debug, Default, Deserialize)]
+pub struct EventsQuery {
+    /// Resume after this sequence. For SSE, `Last-Event-ID` is used when
+    /// omitted.
+    pub after: Option<u64>,
+    /// Poll only.
+    pub limit: Option<usize>,
+    /// Only events about this trace or its spans.
+    pub trace_id: Option<TraceId>,
+    /// Only span events of this kind, e.g. `llm_call`.
+    pub kind: Option<String>,
+    /// Only span events matching this span query, e.g. `model:gpt-4o`.
+    pub q: Option<String>,
+    /// Comma-separated event types, e.g. `span_completed,span_failed`.
+    pub types: Option<String>,
+}
+
+/// Which events a consumer wants. With a trace id or span predicate set,
+/// only span and trace events can match; `cleared` always does. Streaming
+/// deltas and deletions follow the running spans that matched.
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    types: Option<HashSet<String>>,
+    trace_id: Option<TraceId>,
+    spans: Option<SpanFilter>,
+    /// Matched spans still running.
+    followed: HashSet<SpanId>,
+}
+
+impl EventFilter {
+    pub fn from_query(q: &EventsQuery) -> Result<Self, StorageError> {
+        let mut spans = q.q.as_deref().map(storage::parse_span_query).transpose()?;
+        // Spans are annotated after the fact, so live events never match
+        if spans.as_ref().is_some_and(SpanFilter::has_annotation_predicates) {
+            return Err(StorageError::InvalidInput(
+                "score and label can't filter live events".into(),
+            ));
+        }
+        if let Some(kind) = &q.kind {
+            spans.get_or_insert_with(SpanFilter::default).kind = Some(kind.clone());
+        }
+        let types = q.types.as_deref().map(|types| {
+            types
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+        Ok(Self {
+            types,
+            trace_id: q.trace_id,
+            spans,
+            followed: HashSet::new(),
+        })
+    }
+
+    pub fn matches(&mut self, event: &SystemEvent) -> bool {
+        if let Some(types) = &self.types {
+            if !types.contains(event_type_name(event)) {
+                return false;
+            }
+        }
+        if self.trace_id.is_none() && self.spans.is_none() {
+            return true;
+        }
+        match event {
+            SystemEvent::SpanCreated { span }
+            | SystemEvent::SpanCompleted { span }
+            | SystemEvent::SpanFailed { span } => {
+                let matched = self.trace_id.is_none_or(|id| span.trace_id() == id)
+                    && self.spans.as_ref().is_none_or(|f| f.matches(span));
+                if matched && !span.status().is_terminal() {
+                    self.followed.insert(span.id());
+                } else {
+                    self.followed.remove(&span.id());
+                }
+                matched
+            }
+            SystemEvent::SpanStreaming { span_id, .. } => self.followed.contains(span_id),
+            SystemEvent::SpanDeleted { span_id } => self.followed.remove(span_id),
+            SystemEvent::TraceCreated { trace } | SystemEvent::TraceCompleted { trace } => {
+                self.spans.is_none() && self.trace_id == Some(trace.id)
+            }
+            SystemEvent::TraceDeleted { trace_id } => {
+                self.spans.is_none() && self.trace_id == Some(*trace_id)
+            }
+            SystemEvent::Cleared => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventsPage {
+    pub events: Vec<StoredEvent>,
+    /// Pass as `after` on the next poll.
+    pub next_after: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap: Option<EventGap>,
+}
+
+/// Return journaled events after `after` that match the query's filters.
+/// `limit` bounds the events read, so a page may hold fewer.
+pub async fn poll_events(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<EventsQuery>,
+) -> Result<Json<EventsPage>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let mut filter =
+        EventFilter::from_query(&q).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let after = q.after.unwrap_or(0);
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_POLL_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let gap = state
+        .journal
+        .gap_since(after)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let events = state
+        .journal
+        .log()
+        .read_after(&ctx.org_id.to_string(), after, limit)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let next_after = events.last().map_or(after, |e| e.sequence);
+    Ok(Json(EventsPage {
+        events: events
+            .into_iter()
+            .filter(|e| filter.matches(&e.event))
+            .collect(),
+        next_after,
+        gap,
+    }))
+}
+
+/// One item of a live event stream, in delivery order.
+#[derive(Debug, Serialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+pub enum StreamItem {
+    /// A journaled event; `id` is its sequence.
+    Event { id: u64, event: SystemEvent },
+    /// An unsequenced `SpanStreaming` delta.
+    Delta { event: SystemEvent },
+    /// Events after the resume point that are no longer retained.
+    Gap(EventGap),
+}
+
+impl StreamItem {
+    fn into_sse(self) -> Event {
+        match self {
+            StreamItem::Event { id, event } => Event::default().id(id.to_string()).json_data(event),
+            StreamItem::Delta { event } => Event::default().json_data(event),
+            StreamItem::Gap(gap) => Event::default().event("gap").json_data(gap),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Where a consumer resumes: `after`, else the `Last-Event-ID` header.
+pub fn resume_point(q: &EventsQuery, headers: &HeaderMap) -> u64 {
+    q.after
+        .or_else(|| {
+            headers
+                .get("last-event-id")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+/// Start feeding `org_id`'s events after `after` to a new consumer:
+/// journaled ones first, then live ones. In local mode, `SpanStreaming`
+/// deltas are interleaved. The feed stops when the receiver is dropped.
+pub fn open_stream(
+    state: &AppState,
+    org_id: String,
+    after: u64,
+    filter: EventFilter,
+) -> mpsc::Receiver<StreamItem> {
+    // Subscribe before replaying so nothing emitted in between is missed;
+    // live events already replayed are skipped by sequence.
+    let live = state.journal.subscribe();
+    let raw = state
+        .auth_config
+        .local_mode
+        .then(|| state.journal.subscribe_raw());
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(deliver(
+        state.journal.clone(),
+        org_id,
+        after,
+        live,
+        raw,
+        filter,
+        tx,
+    ));
+    rx
+}
+
+/// Stream events over SSE. Each journaled event's SSE id is its sequence;
+/// streaming deltas have none.
+pub async fn stream_events(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let filter = EventFilter::from_query(&q).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let after = resume_point(&q, &headers);
+    let rx = open_stream(&state, ctx.org_id.to_string(), after, filter);
+    let stream = ReceiverStream::new(rx).map(|item| Ok(item.into_sse()));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Feed one consumer until it goes away.
+async fn deliver(
+    journal: std::sync::Arc<EventJournal>,
+    org_id: String,
+    mut last: u64,
+    mut live: broadcast::Receiver<StoredEvent>,
+    mut raw: Option<broadcast::Receiver<SystemEvent>>,
+    mut filter: EventFilter,
+    tx: mpsc::Sender<StreamItem>,
+) {
+    let _subscriber = super::metrics::global().sse_connect();
+    if catch_up(&journal, &org_id, &mut last, &mut filter, &tx)
+        .await
+        .is_err()
+    {
+        return;
+    }
+    loop {
+        tokio::select! {
+            received = live.recv() => match received {
+                Ok(stored) => {
+                    if stored.org_id != org_id || stored.sequence <= last {
+                        continue;
+                    }
+                    last = stored.sequence;
+                    if !filter.matches(&stored.event) {
+                        continue;
+                    }
+                    if tx.send(sequenced(stored)).await.is_err() {
+                        return;
+                    }
+                }
+                // Fell behind the live channel; the journal still has them
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if catch_up(&journal, &org_id, &mut last, &mut filter, &tx).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            Some(event) = recv_streaming(&mut raw) => {
+                if !filter.matches(&event) {
+                    continue;
+                }
+                if tx.send(StreamItem::Delta { event }).await.is_err() {
+                    return;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// Send a gap notice if needed, then every journaled event after `last`.
+/// Errs once the consumer has gone away.
+async fn catch_up(
+    journal: &EventJournal,
+    org_id: &str,
+    last: &mut u64,
+    filter: &mut EventFilter,
+    tx: &mpsc::Sender<StreamItem>,
+) -> Result<(), ()> {
+    match journal.gap_since(*last).await {
+        Ok(Some(gap)) => tx.send(StreamItem::Gap(gap)).await.map_err(|_| ())?,
+        Ok(None) => {}
+        Err(e) => tracing::warn!("events: failed to check journal bounds: {e}"),
+    }
+    loop {
+        let batch = match journal.log().read_after(org_id, *last, REPLAY_BATCH).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                tracing::warn!("events: failed to replay journal: {e}");
+                return Ok(());
+            }
+        };
+        let done = batch.len() < REPLAY_BATCH;
+        for stored in batch {
+            *last = stored.sequence;
+            if filter.matches(&stored.event) {
+                tx.send(sequenced(stored)).await.map_err(|_| ())?;
+            }
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+fn sequenced(stored: StoredEvent) -> StreamItem {
+    StreamItem::Event {
+        id: stored.sequence,
+        event: stored.event,
+    }
+}
+
+/// Next `SpanStreaming` delta from the raw bus. Pending forever without one.
+async fn recv_streaming(raw: &mut Option<broadcast::Receiver<SystemEvent>>) -> Option<SystemEvent> {
+    let Some(rx) = raw else {
+        return std::future::pending().await;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(event @ SystemEvent::SpanStreaming { .. }) => return Some(event),
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trace::{Span, SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+
+    fn llm_call(trace_id: TraceId) -> Span {
+        let kind = SpanKind::LlmCall {
+            model: "gpt-4o".into(),
+            provider: None,
+            input_tokens: None,
+            output_tokens: None,
+            cost: None,
+            input_preview: None,
+            output_preview: None,
+        };
+        SpanBuilder::new(trace_id, "chat", kind).build()
+    }
+
+    #[test]
+    fn follows_matching_spans() {
+        let mut filter = EventFilter::from_query(&EventsQuery {
+            kind: Some("llm_call".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let trace = Trace::new(None);
+        let span = llm_call(trace.id);
+        let delta = SystemEvent::SpanStreaming {
+            span_id: span.id(),
+            delta: "hi".into(),
+        };
+
+        assert!(!filter.matches(&SystemEvent::TraceCreated { trace }));
+        assert!(!filter.matches(&delta));
+        assert!(filter.matches(&SystemEvent::SpanCreated { span: span.clone() }));
+        assert!(filter.matches(&delta));
+        assert!(filter.matches(&SystemEvent::SpanCompleted {
+            span: span.complete(None)
+        }));
+        assert!(!filter.matches(&delta));
+        assert!(filter.matches(&SystemEvent::Cleared));
+    }
+
+    #[test]
+    fn filters_by_trace_and_type() {
+        let trace = Trace::new(None);
+        let mut filter = EventFilter::from_query(&EventsQuery {
+            trace_id: Some(trace.id),
+            types: Some("span_failed, trace_completed".into()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(filter.matches(&SystemEvent::TraceCompleted {
+            trace: trace.clone()
+        }));
+        let span = llm_call(trace.id);
+        assert!(!filter.matches(&SystemEvent::SpanCreated { span: span.clone() }));
+        assert!(filter.matches(&SystemEvent::SpanFailed {
+            span: span.fail("boom")
+        }));
+        let other = llm_call(Trace::new(None).id).fail("boom");
+        assert!(!filter.matches(&SystemEvent::SpanFailed { span: other }));
+
+        let bad = EventsQuery {
+            q: Some("duration:>abc".into()),
+            ..Default::default()
+        };
+        assert!(EventFilter::from_query(&bad).is_err());
+    }
+
+    #[test]
+    fn frames_stream_items() {
+        let item = StreamItem::Event {
+            id: 7,
+            event: SystemEvent::Cleared,
+        };
+        assert_eq!(
+            serde_json::to_value(&item).unwrap(),
+            serde_json::json!({"frame": "event", "id": 7, "event": {"type": "cleared"}})
+        );
+        let gap = StreamItem::Gap(EventGap {
+            after: 1,
+            earliest: 5,
+            latest: 9,
+        });
+        assert_eq!(
+            serde_json::to_value(&gap).unwrap()["frame"],
+            serde_json::json!("gap")
+        );
+    }
+}
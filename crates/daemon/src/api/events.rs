@@ -2,14 +2,21 @@
 //!
 //! This module provides both local (in-process) and cloud (Redis Pub/Sub) event bus
 //! implementations for real-time event distribution across multiple server instances.
+//!
+//! With a bus, every instance journals the events emitted on every other
+//! one, so any instance can serve any consumer. Sequences are assigned per
+//! instance: a consumer that resumes on a different instance than it left
+//! may be sent a gap notice.
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
-use tracing::{info, warn};
+use tokio::sync::{broadcast, mpsc};
+use tracing::warn;
+use uuid::Uuid;
 
 use super::SystemEvent;
 
@@ -39,73 +46,323 @@ pub trait EventLog: Send + Sync + 'static {
     /// Append an event to the log. Returns the assigned sequence number.
     async fn append(&self, org_id: &str, event: &SystemEvent) -> Result<u64, EventLogError>;
 
-    /// Read events with sequence > `after_sequence`, up to `limit`.
-    async fn read_after(&self, after_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>, EventLogError>;
+    /// Read `org_id`'s events with sequence > `after_sequence`, up to `limit`.
+    async fn read_after(
+        &self,
+        org_id: &str,
+        after_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>, EventLogError>;
 
     /// Delete events older than `max_age`.
     async fn trim(&self, max_age: Duration) -> Result<usize, EventLogError>;
 
-    /// Get the latest sequence number (0 if empty).
+    /// Get the latest sequence number ever assigned (0 if none).
     async fn latest_sequence(&self) -> Result<u64, EventLogError>;
+
+    /// Sequence of the oldest retained event (0 if empty).
+    async fn earliest_sequence(&self) -> Result<u64, EventLogError>;
+}
+
+/// Bounded in-memory event log holding the most recent `capacity` events.
+/// Used when no durable log is available; sequences restart with the process.
+pub struct MemoryEventLog {
+    capacity: usize,
+    inner: std::sync::Mutex<MemoryLogInner>,
 }
 
-/// No-op event log that discards all events (used when no durable log is configured).
-pub struct NoopEventLog;
+#[derive(Default)]
+struct MemoryLogInner {
+    events: VecDeque<StoredEvent>,
+    latest: u64,
+}
+
+impl MemoryEventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Default::default(),
+        }
+    }
+}
 
 #[async_trait]
-impl EventLog for NoopEventLog {
-    async fn append(&self, _org_id: &str, _event: &SystemEvent) -> Result<u64, EventLogError> {
-        Ok(0)
+impl EventLog for MemoryEventLog {
+    async fn append(&self, org_id: &str, event: &SystemEvent) -> Result<u64, EventLogError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.latest += 1;
+        let sequence = inner.latest;
+        if inner.events.len() == self.capacity {
+            inner.events.pop_front();
+        }
+        inner.events.push_back(StoredEvent {
+            sequence,
+            event: event.clone(),
+            timestamp: Utc::now(),
+            org_id: org_id.to_string(),
+        });
+        Ok(sequence)
     }
-    async fn read_after(&self, _after_sequence: u64, _limit: usize) -> Result<Vec<StoredEvent>, EventLogError> {
-        Ok(vec![])
+
+    async fn read_after(
+        &self,
+        org_id: &str,
+        after_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>, EventLogError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .events
+            .iter()
+            .filter(|e| e.sequence > after_sequence && e.org_id == org_id)
+            .take(limit)
+            .cloned()
+            .collect())
     }
-    async fn trim(&self, _max_age: Duration) -> Result<usize, EventLogError> {
-        Ok(0)
+
+    async fn trim(&self, max_age: Duration) -> Result<usize, EventLogError> {
+        let cutoff = Utc::now()
+            - chrono::Duration::from_std(max_age).unwrap_or_else(|_| chrono::Duration::hours(24));
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.events.len();
+        inner.events.retain(|e| e.timestamp >= cutoff);
+        Ok(before - inner.events.len())
     }
+
     async fn latest_sequence(&self) -> Result<u64, EventLogError> {
-        Ok(0)
+        Ok(self.inner.lock().unwrap().latest)
+    }
+
+    async fn earliest_sequence(&self) -> Result<u64, EventLogError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.events.front().map_or(0, |e| e.sequence))
+    }
+}
+
+/// Events a consumer resuming after `after` can no longer receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EventGap {
+    /// The sequence the consumer resumed from.
+    pub after: u64,
+    /// Oldest sequence still retained (0 if the journal is empty).
+    pub earliest: u64,
+    pub latest: u64,
+}
+
+impl EventGap {
+    /// A gap exists when events after `after` have been trimmed, or when
+    /// `after` is ahead of the journal (it was reset since the consumer last
+    /// connected).
+    pub fn detect(after: u64, earliest: u64, latest: u64) -> Option<Self> {
+        let trimmed = after < latest && (earliest == 0 || after + 1 < earliest);
+        (trimmed || after > latest).then_some(Self {
+            after,
+            earliest,
+            latest,
+        })
     }
 }
 
+/// Orders and fans out system events.
+///
+/// `emit` never blocks. A single task appends each event to the log, which
+/// assigns its sequence, and then broadcasts the sequenced event, so live
+/// subscribers always see sequences in increasing order. Raw events are
+/// also sent on the unsequenced bus for components that predate the journal.
+///
+/// Events emitted here are published on the [`EventBus`] once journaled,
+/// and events published by other instances are journaled here as they
+/// arrive.
+pub struct EventJournal {
+    log: Arc<dyn EventLog>,
+    raw_tx: broadcast::Sender<SystemEvent>,
+    queue: mpsc::UnboundedSender<Queued>,
+    live_tx: broadcast::Sender<StoredEvent>,
+    /// Only events emitted on this instance.
+    local_tx: broadcast::Sender<StoredEvent>,
+}
+
+struct Queued {
+    org_id: String,
+    event: SystemEvent,
+    /// Received from another instance over the bus.
+    remote: bool,
+}
+
+impl EventJournal {
+    /// Start the journal task and its link to `bus`.
+    pub fn spawn(
+        log: Arc<dyn EventLog>,
+        raw_tx: broadcast::Sender<SystemEvent>,
+        bus: Arc<dyn EventBus>,
+    ) -> Arc<Self> {
+        let (queue, mut rx) = mpsc::unbounded_channel::<Queued>();
+        let (live_tx, _) = broadcast::channel(1024);
+        let (local_tx, _) = broadcast::channel(1024);
+        let publisher = spawn_bus_link(bus, queue.clone());
+
+        let task_log = log.clone();
+        let task_live = live_tx.clone();
+        let task_local = local_tx.clone();
+        tokio::spawn(async move {
+            while let Some(Queued {
+                org_id,
+                event,
+                remote,
+            }) = rx.recv().await
+            {
+                let sequence = match task_log.append(&org_id, &event).await {
+                    Ok(sequence) => sequence,
+                    Err(e) => {
+                        warn!("failed to append event to log: {e}");
+                        continue;
+                    }
+                };
+                if !remote {
+                    let _ = publisher.send((org_id.clone(), event.clone()));
+                }
+                let stored = StoredEvent {
+                    sequence,
+                    event,
+                    timestamp: Utc::now(),
+                    org_id,
+                };
+                // No receivers is fine
+                if !remote {
+                    let _ = task_local.send(stored.clone());
+                }
+                let _ = task_live.send(stored);
+            }
+        });
+        Arc::new(Self {
+            log,
+            raw_tx,
+            queue,
+            live_tx,
+            local_tx,
+        })
+    }
+
+    pub fn emit(&self, org_id: &str, event: SystemEvent) {
+        super::metrics::global().record_event(&event);
+        let _ = self.raw_tx.send(event.clone());
+        let _ = self.queue.send(Queued {
+            org_id: org_id.to_string(),
+            event,
+            remote: false,
+        });
+    }
+
+    /// Sequenced events as they are journaled.
+    pub fn subscribe(&self) -> broadcast::Receiver<StoredEvent> {
+        self.live_tx.subscribe()
+    }
+
+    /// Sequenced events emitted on this instance, for side effects that
+    /// must happen once across instances.
+    pub fn subscribe_local(&self) -> broadcast::Receiver<StoredEvent> {
+        self.local_tx.subscribe()
+    }
+
+    /// Unsequenced events, including broadcast-only ones like `SpanStreaming`.
+    pub fn subscribe_raw(&self) -> broadcast::Receiver<SystemEvent> {
+        self.raw_tx.subscribe()
+    }
+
+    pub fn log(&self) -> &Arc<dyn EventLog> {
+        &self.log
+    }
+
+    /// Check whether a consumer resuming after `after` has missed events
+    /// that are no longer retained.
+    pub async fn gap_since(&self, after: u64) -> Result<Option<EventGap>, EventLogError> {
+        let latest = self.log.latest_sequence().await?;
+        let earliest = self.log.earliest_sequence().await?;
+        Ok(EventGap::detect(after, earliest, latest))
+    }
+}
+
+/// Relay journaled local events to `bus`, and queue events other instances
+/// publish. Returns the publisher's queue; events are published in order.
+fn spawn_bus_link(
+    bus: Arc<dyn EventBus>,
+    journal: mpsc::UnboundedSender<Queued>,
+) -> mpsc::UnboundedSender<(String, SystemEvent)> {
+    let origin = Uuid::new_v4();
+    let mut subscriber = bus.subscribe();
+    tokio::spawn(async move {
+        while let Some(message) = subscriber.recv().await {
+            if message.origin == origin {
+                continue;
+            }
+            let queued = Queued {
+                org_id: message.org_id,
+                event: message.event,
+                remote: true,
+            };
+            if journal.send(queued).is_err() {
+                return;
+            }
+        }
+    });
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<(String, SystemEvent)>();
+    tokio::spawn(async move {
+        while let Some((org_id, event)) = rx.recv().await {
+            bus.publish(BusMessage {
+                origin,
+                org_id,
+                event,
+            })
+            .await;
+        }
+    });
+    tx
+}
+
+/// An event as carried between instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusMessage {
+    /// The publishing instance, which skips its own messages.
+    pub origin: Uuid,
+    pub org_id: String,
+    pub event: SystemEvent,
+}
+
 /// Event bus trait for publishing and subscribing to system events
 #[async_trait]
 pub trait EventBus: Send + Sync + 'static {
     /// Publish an event to all subscribers
-    async fn publish(&self, event: SystemEvent);
+    async fn publish(&self, message: BusMessage);
 
     /// Get a subscriber that receives events
     fn subscribe(&self) -> EventSubscriber;
-
-    /// Get the number of active subscribers
-    fn subscriber_count(&self) -> usize;
 }
 
 /// A subscriber that can receive events
 pub struct EventSubscriber {
-    inner: EventSubscriberInner,
-}
-
-enum EventSubscriberInner {
-    Local(broadcast::Receiver<SystemEvent>),
-    #[cfg(feature = "cloud")]
-    Cloud(tokio::sync::mpsc::Receiver<SystemEvent>),
+    rx: broadcast::Receiver<BusMessage>,
 }
 
 impl EventSubscriber {
-    /// Receive the next event (blocking)
-    pub async fn recv(&mut self) -> Option<SystemEvent> {
-        match &mut self.inner {
-            EventSubscriberInner::Local(rx) => rx.recv().await.ok(),
-            #[cfg(feature = "cloud")]
-            EventSubscriberInner::Cloud(rx) => rx.recv().await,
+    /// Receive the next event (blocking). `None` once the bus is gone.
+    pub async fn recv(&mut self) -> Option<BusMessage> {
+        loop {
+            match self.rx.recv().await {
+                Ok(message) => return Some(message),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!(skipped = n, "event bus subscriber lagged");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
         }
     }
 }
 
-/// Local event bus using tokio broadcast channel (single-node only)
+/// Local event bus using tokio broadcast channel (single-node only). The
+/// default bus, and what the Redis bus delivers received messages through.
 pub struct LocalEventBus {
-    tx: broadcast::Sender<SystemEvent>,
+    tx: broadcast::Sender<BusMessage>,
 }
 
 impl LocalEventBus {
@@ -113,11 +370,6 @@ impl LocalEventBus {
         let (tx, _) = broadcast::channel(capacity);
         Self { tx }
     }
-
-    /// Get the underlying broadcast sender (for backward compatibility)
-    pub fn sender(&self) -> broadcast::Sender<SystemEvent> {
-        self.tx.clone()
-    }
 }
 
 impl Default for LocalEventBus {
@@ -128,19 +380,15 @@ impl Default for LocalEventBus {
 
 #[async_trait]
 impl EventBus for LocalEventBus {
-    async fn publish(&self, event: SystemEvent) {
-        let _ = self.tx.send(event);
+    async fn publish(&self, message: BusMessage) {
+        let _ = self.tx.send(message);
     }
 
     fn subscribe(&self) -> EventSubscriber {
         EventSubscriber {
-            inner: EventSubscriberInner::Local(self.tx.subscribe()),
+            rx: self.tx.subscribe(),
         }
     }
-
-    fn subscriber_count(&self) -> usize {
-        self.tx.receiver_count()
-    }
 }
 
 /// Cloud event bus using Redis Pub/Sub for cross-node SSE fanout
@@ -149,8 +397,7 @@ pub mod cloud {
     use super::*;
     use redis::aio::ConnectionManager;
     use redis::AsyncCommands;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use tracing::{debug, error, warn};
+    use tracing::{debug, error, info, warn};
 
     const REDIS_CHANNEL: &str = "traceway:events";
 
@@ -160,10 +407,8 @@ pub mod cloud {
         publisher: ConnectionManager,
         /// Redis client for creating subscriber connections
         client: redis::Client,
-        /// Local broadcast for distributing events to local SSE handlers
-        local_tx: broadcast::Sender<SystemEvent>,
-        /// Counter for subscriber tracking
-        subscriber_count: Arc<AtomicUsize>,
+        /// Delivers received messages to this instance's subscribers
+        local: Arc<LocalEventBus>,
     }
 
     impl RedisEventBus {
@@ -171,13 +416,10 @@ pub mod cloud {
         pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
             let client = redis::Client::open(redis_url)?;
             let publisher = ConnectionManager::new(client.clone()).await?;
-            let (local_tx, _) = broadcast::channel(256);
-
             let bus = Self {
                 publisher,
                 client,
-                local_tx,
-                subscriber_count: Arc::new(AtomicUsize::new(0)),
+                local: Arc::new(LocalEventBus::default()),
             };
 
             // Start the Redis subscription listener
@@ -187,21 +429,14 @@ pub mod cloud {
             Ok(bus)
         }
 
-        /// Create from environment variable REDIS_URL
-        pub async fn from_env() -> Result<Self, redis::RedisError> {
-            let url = std::env::var("REDIS_URL")
-                .unwrap_or_else(|_| "redis://localhost:6379".to_string());
-            Self::new(&url).await
-        }
-
         /// Start the background Redis subscription listener
         async fn start_listener(&self) -> Result<(), redis::RedisError> {
             let client = self.client.clone();
-            let local_tx = self.local_tx.clone();
+            let local = self.local.clone();
 
             tokio::spawn(async move {
                 loop {
-                    match Self::run_subscriber(&client, &local_tx).await {
+                    match Self::run_subscriber(&client, &local).await {
                         Ok(()) => {
                             info!("Redis subscriber exited cleanly");
                             break;
@@ -219,7 +454,7 @@ pub mod cloud {
 
         async fn run_subscriber(
             client: &redis::Client,
-            local_tx: &broadcast::Sender<SystemEvent>,
+            local: &LocalEventBus,
         ) -> Result<(), redis::RedisError> {
             let conn = client.get_async_pubsub().await?;
             let mut pubsub = conn;
@@ -230,10 +465,10 @@ pub mod cloud {
             let mut stream = pubsub.on_message();
             while let Some(msg) = futures::StreamExt::next(&mut stream).await {
                 let payload: String = msg.get_payload()?;
-                match serde_json::from_str::<SystemEvent>(&payload) {
-                    Ok(event) => {
-                        debug!("Received event from Redis: {:?}", event);
-                        let _ = local_tx.send(event);
+                match serde_json::from_str::<BusMessage>(&payload) {
+                    Ok(message) => {
+                        debug!("Received event from Redis: {:?}", message.event);
+                        local.publish(message).await;
                     }
                     Err(e) => {
                         warn!("Failed to deserialize event: {}", e);
@@ -247,8 +482,8 @@ pub mod cloud {
 
     #[async_trait]
     impl EventBus for RedisEventBus {
-        async fn publish(&self, event: SystemEvent) {
-            let payload = match serde_json::to_string(&event) {
+        async fn publish(&self, message: BusMessage) {
+            let payload = match serde_json::to_string(&message) {
                 Ok(p) => p,
                 Err(e) => {
                     error!("Failed to serialize event: {}", e);
@@ -260,21 +495,14 @@ pub mod cloud {
             if let Err(e) = conn.publish::<_, _, ()>(REDIS_CHANNEL, &payload).await {
                 error!("Failed to publish event to Redis: {}", e);
                 // Fall back to local broadcast
-                let _ = self.local_tx.send(event);
+                self.local.publish(message).await;
             } else {
                 debug!("Published event to Redis");
             }
         }
 
         fn subscribe(&self) -> EventSubscriber {
-            self.subscriber_count.fetch_add(1, Ordering::Relaxed);
-            EventSubscriber {
-                inner: EventSubscriberInner::Local(self.local_tx.subscribe()),
-            }
-        }
-
-        fn subscriber_count(&self) -> usize {
-            self.subscriber_count.load(Ordering::Relaxed)
+            self.local.subscribe()
         }
     }
 }
@@ -282,23 +510,64 @@ pub mod cloud {
 #[cfg(feature = "cloud")]
 pub use cloud::RedisEventBus;
 
-/// Create the appropriate event bus based on configuration
-pub async fn create_event_bus() -> Arc<dyn EventBus> {
-    #[cfg(feature = "cloud")]
-    {
-        if std::env::var("REDIS_URL").is_ok() {
-            match RedisEventBus::from_env().await {
-                Ok(bus) => {
-                    info!("Using Redis event bus for cloud deployment");
-                    return Arc::new(bus);
-                }
-                Err(e) => {
-                    warn!("Failed to connect to Redis, falling back to local: {}", e);
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_detection() {
+        // Caught up, or resuming inside the retained window
+        assert_eq!(EventGap::detect(10, 5, 10), None);
+        assert_eq!(EventGap::detect(4, 5, 10), None);
+        assert_eq!(EventGap::detect(0, 1, 10), None);
+        // Events 4..=5 were trimmed
+        assert!(EventGap::detect(3, 6, 10).is_some());
+        // Everything trimmed, or the journal was reset
+        assert!(EventGap::detect(3, 0, 10).is_some());
+        assert!(EventGap::detect(12, 1, 10).is_some());
+    }
+
+    #[tokio::test]
+    async fn memory_log_keeps_recent_events_per_org() {
+        let log = MemoryEventLog::new(3);
+        for org in ["a", "b", "a", "a"] {
+            log.append(org, &SystemEvent::Cleared).await.unwrap();
         }
+        assert_eq!(log.latest_sequence().await.unwrap(), 4);
+        assert_eq!(log.earliest_sequence().await.unwrap(), 2);
+        let seqs: Vec<u64> = log
+            .read_after("a", 0, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| e.sequence)
+            .collect();
+        assert_eq!(seqs, vec![3, 4]);
     }
 
-    info!("Using local event bus");
-    Arc::new(LocalEventBus::default())
+    #[tokio::test]
+    async fn bus_journals_events_from_other_instances() {
+        let bus: Arc<dyn EventBus> = Arc::new(LocalEventBus::default());
+        let instance = || {
+            let log = Arc::new(MemoryEventLog::new(16));
+            EventJournal::spawn(log, broadcast::channel(16).0, bus.clone())
+        };
+        let (a, b) = (instance(), instance());
+        let mut a_local = a.subscribe_local();
+        let mut b_live = b.subscribe();
+        let mut b_local = b.subscribe_local();
+
+        a.emit("org", SystemEvent::Cleared);
+        let wait = Duration::from_secs(1);
+        let stored = tokio::time::timeout(wait, b_live.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((stored.sequence, stored.org_id.as_str()), (1, "org"));
+        assert!(tokio::time::timeout(wait, a_local.recv()).await.is_ok());
+        // Only the emitting instance sees it as local, and journals it once
+        assert!(b_local.try_recv().is_err());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(a.log().latest_sequence().await.unwrap(), 1);
+    }
 }
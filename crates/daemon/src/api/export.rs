@@ -0,0 +1,438 @@
+//! Streaming span and trace export.
+//!
+//! `GET /api/export?format=jsonl|csv&entity=spans|traces` takes the same
+//! filters as the list endpoints and streams every matching row, reading
+//! the store a page at a time. JSONL writes one JSON object per line; CSV
+//! writes a header row and the columns named in `columns` (all of them by
+//! default), with nested values as compact JSON.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Deserialize;
+use storage::{SpanFilter, StorageError, TraceFilter};
+use trace::{Span, SpanStatus, Trace, TraceId};
+use tracing::warn;
+use utoipa::IntoParams;
+
+use super::error::Problem;
+use super::{
+    api_error, project_store, require_scope, traces::split_tags, ApiError, AppState, SharedStore,
+    MAX_PAGE_LIMIT,
+};
+
+pub const SPAN_COLUMNS: &[&str] = &[
+    "id",
+    "trace_id",
+    "parent_id",
+    "name",
+    "kind",
+    "status",
+    "error",
+    "started_at",
+    "ended_at",
+    "duration_ms",
+    "model",
+    "provider",
+    "input_tokens",
+    "output_tokens",
+    "cost",
+    "input",
+    "output",
+];
+
+pub const TRACE_COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "tags",
+    "started_at",
+    "ended_at",
+    "duration_ms",
+    "span_count",
+    "error_count",
+    "input_tokens",
+    "output_tokens",
+    "cost",
+    "session_id",
+    "user_id",
+    "machine_id",
+    "git_commit",
+    "git_branch",
+    "repo",
+];
+
+/// Query parameters for `GET /api/export`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// "jsonl" (default) or "csv".
+    pub format: Option<String>,
+    /// "spans" (default) or "traces".
+    pub entity: Option<String>,
+    /// Comma-separated CSV columns, in order.
+    pub columns: Option<String>,
+    /// Stop after this many rows.
+    pub limit: Option<usize>,
+    /// "started_at" (default), "duration", "cost", or "name"; spans also
+    /// accept "tokens".
+    pub sort: Option<String>,
+    /// "asc" or "desc" (default).
+    pub order: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub name_contains: Option<String>,
+    // Span filters
+    #[param(value_type = Option<String>)]
+    pub trace_id: Option<TraceId>,
+    pub kind: Option<String>,
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<String>,
+    /// Case-insensitive search across name, input, and output.
+    pub q: Option<String>,
+    pub duration_min: Option<i64>,
+    pub duration_max: Option<i64>,
+    pub cost_min: Option<f64>,
+    // Trace filters
+    /// Comma-separated list; traces must have every tag.
+    pub tags: Option<String>,
+    pub machine_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Jsonl,
+    Csv,
+}
+
+enum Source {
+    Spans(Box<SpanFilter>),
+    Traces(Box<TraceFilter>),
+}
+
+struct Export {
+    store: SharedStore,
+    source: Source,
+    format: Format,
+    columns: Vec<&'static str>,
+    cursor: Option<String>,
+    remaining: Option<usize>,
+    header_written: bool,
+    done: bool,
+}
+
+impl Export {
+    /// The next page, encoded, or `None` once everything has been sent.
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, StorageError> {
+        if self.done {
+            return Ok(None);
+        }
+        let limit = self
+            .remaining
+            .map_or(MAX_PAGE_LIMIT, |r| r.min(MAX_PAGE_LIMIT));
+        let mut out = Vec::new();
+        if self.format == Format::Csv && !self.header_written {
+            write_csv_row(&mut out, self.columns.iter().map(|c| c.to_string()));
+            self.header_written = true;
+        }
+        let (rows, next_cursor) = match &self.source {
+            Source::Spans(filter) => {
+                let page = self
+                    .store
+                    .query_spans(&SpanFilter {
+                        cursor: self.cursor.clone(),
+                        limit: Some(limit),
+                        ..(**filter).clone()
+                    })
+                    .await?;
+                for span in &page.items {
+                    match self.format {
+                        Format::Jsonl => write_json_line(&mut out, span)?,
+                        Format::Csv => write_csv_row(
+                            &mut out,
+                            self.columns.iter().map(|c| span_field(span, c)),
+                        ),
+                    }
+                }
+                (page.items.len(), page.next_cursor.filter(|_| page.has_more))
+            }
+            Source::Traces(filter) => {
+                let page = self
+                    .store
+                    .query_traces(&TraceFilter {
+                        cursor: self.cursor.clone(),
+                        limit: Some(limit),
+                        ..(**filter).clone()
+                    })
+                    .await?;
+                for trace in &page.items {
+                    match self.format {
+                        Format::Jsonl => write_json_line(&mut out, trace)?,
+                        Format::Csv => write_csv_row(
+                            &mut out,
+                            self.columns.iter().map(|c| trace_field(trace, c)),
+                        ),
+                    }
+                }
+                (page.items.len(), page.next_cursor.filter(|_| page.has_more))
+            }
+        };
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= rows.min(*remaining);
+        }
+        self.cursor = next_cursor;
+        self.done = self.cursor.is_none() || self.remaining == Some(0);
+        Ok(Some(Bytes::from(out)))
+    }
+}
+
+/// Stream spans or traces matching the query as JSONL or CSV.
+#[utoipa::path(
+    get,
+    path = "/api/export",
+    tag = "export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "One JSON object or CSV row per line", content(("application/x-ndjson"), ("text/csv"))),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn export(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let bad_request = |msg: String| api_error(StatusCode::BAD_REQUEST, msg);
+    let format = match q.format.as_deref().unwrap_or("jsonl") {
+        "jsonl" => Format::Jsonl,
+        "csv" => Format::Csv,
+        other => {
+            return Err(bad_request(format!(
+                "unknown format '{other}' (expected jsonl or csv)"
+            )))
+        }
+    };
+    let entity = q.entity.clone().unwrap_or_else(|| "spans".to_string());
+    let available = match entity.as_str() {
+        "spans" => SPAN_COLUMNS,
+        "traces" => TRACE_COLUMNS,
+        other => {
+            return Err(bad_request(format!(
+                "unknown entity '{other}' (expected spans or traces)"
+            )))
+        }
+    };
+    let columns = parse_columns(q.columns.as_deref(), available).map_err(bad_request)?;
+    let source = if entity == "spans" {
+        Source::Spans(Box::new(SpanFilter {
+            trace_id: q.trace_id,
+            kind: q.kind,
+            model: q.model,
+            provider: q.provider,
+            status: q.status,
+            name_contains: q.name_contains,
+            text_contains: q.q,
+            since: q.since,
+            until: q.until,
+            duration_min: q.duration_min,
+            duration_max: q.duration_max,
+            cost_min: q.cost_min,
+            sort_by: q.sort,
+            sort_order: q.order,
+            ..Default::default()
+        }))
+    } else {
+        Source::Traces(Box::new(TraceFilter {
+            name_contains: q.name_contains,
+            tags: q.tags.as_deref().map(split_tags),
+            since: q.since,
+            until: q.until,
+            machine_id: q.machine_id,
+            sort_by: q.sort,
+            sort_order: q.order,
+            ..Default::default()
+        }))
+    };
+    let store = project_store(&ctx, &state).await?;
+
+    let mut export = Export {
+        store,
+        source,
+        format,
+        columns,
+        cursor: None,
+        remaining: q.limit,
+        header_written: false,
+        done: q.limit == Some(0),
+    };
+    // Read the first page up front so a bad filter is still a 400
+    let first = export.next_chunk().await.map_err(|e| match e {
+        StorageError::Serialization(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    let rest = futures::stream::try_unfold(export, |mut export| async move {
+        match export.next_chunk().await {
+            Ok(chunk) => Ok(chunk.map(|chunk| (chunk, export))),
+            Err(e) => {
+                // Headers are already sent; cutting the stream short is all
+                // that can be done
+                warn!("export: aborting stream: {e}");
+                Err(e)
+            }
+        }
+    });
+    let body = futures::stream::iter(first.map(Ok::<_, StorageError>)).chain(rest);
+
+    let (content_type, extension) = match format {
+        Format::Jsonl => ("application/x-ndjson", "jsonl"),
+        Format::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{entity}.{extension}\""),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+fn parse_columns(
+    columns: Option<&str>,
+    available: &'static [&'static str],
+) -> Result<Vec<&'static str>, String> {
+    let Some(columns) = columns else {
+        return Ok(available.to_vec());
+    };
+    let selected: Vec<&'static str> = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            available.iter().find(|a| **a == c).copied().ok_or_else(|| {
+                format!(
+                    "unknown column '{c}' (expected one of {})",
+                    available.join(", ")
+                )
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    if selected.is_empty() {
+        return Err("columns must name at least one column".to_string());
+    }
+    Ok(selected)
+}
+
+fn write_json_line(out: &mut Vec<u8>, value: &impl serde::Serialize) -> Result<(), StorageError> {
+    serde_json::to_writer(&mut *out, value)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+fn write_csv_row(out: &mut Vec<u8>, fields: impl Iterator<Item = String>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn span_field(span: &Span, column: &str) -> String {
+    let kind = span.kind();
+    match column {
+        "id" => span.id().to_string(),
+        "trace_id" => span.trace_id().to_string(),
+        "parent_id" => opt(span.parent_id()),
+        "name" => span.name().to_string(),
+        "kind" => kind.kind_name().to_string(),
+        "status" => span.status().as_str().to_string(),
+        "error" => match span.status() {
+            SpanStatus::Failed { error } => error.clone(),
+            _ => String::new(),
+        },
+        "started_at" => span.started_at().to_rfc3339(),
+        "ended_at" => opt(span.ended_at().map(|t| t.to_rfc3339())),
+        "duration_ms" => opt(span.duration_ms()),
+        "model" => opt(kind.model()),
+        "provider" => opt(kind.provider()),
+        "input_tokens" => opt(kind.input_tokens()),
+        "output_tokens" => opt(kind.output_tokens()),
+        "cost" => opt(kind.cost()),
+        "input" => opt(span.input()),
+        "output" => opt(span.output()),
+        _ => String::new(),
+    }
+}
+
+fn trace_field(trace: &Trace, column: &str) -> String {
+    match column {
+        "id" => trace.id.to_string(),
+        "name" => opt(trace.name.as_ref()),
+        "tags" => trace.tags.join(";"),
+        "started_at" => trace.started_at.to_rfc3339(),
+        "ended_at" => opt(trace.ended_at.map(|t| t.to_rfc3339())),
+        "duration_ms" => opt(trace
+            .ended_at
+            .map(|end| (end - trace.started_at).num_milliseconds())),
+        "span_count" => trace.stats.span_count.to_string(),
+        "error_count" => trace.stats.error_count.to_string(),
+        "input_tokens" => trace.stats.input_tokens.to_string(),
+        "output_tokens" => trace.stats.output_tokens.to_string(),
+        "cost" => trace.stats.total_cost.to_string(),
+        "session_id" => opt(trace.session_id.as_ref()),
+        "user_id" => opt(trace.user_id.as_ref()),
+        "machine_id" => opt(trace.machine_id.as_ref()),
+        "git_commit" => opt(trace.git_commit.as_ref()),
+        "git_branch" => opt(trace.git_branch.as_ref()),
+        "repo" => opt(trace.repo.as_ref()),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_quote_when_needed() {
+        let mut out = Vec::new();
+        let fields = ["plain", "a,b", "say \"hi\"", "two\nlines", ""];
+        write_csv_row(&mut out, fields.iter().map(|f| f.to_string()));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n"
+        );
+    }
+
+    #[test]
+    fn columns_are_validated_and_ordered() {
+        assert_eq!(parse_columns(None, TRACE_COLUMNS).unwrap(), TRACE_COLUMNS);
+        assert_eq!(
+            parse_columns(Some("cost, name"), SPAN_COLUMNS).unwrap(),
+            ["cost", "name"]
+        );
+        assert!(parse_columns(Some("name,bogus"), SPAN_COLUMNS).is_err());
+        assert!(parse_columns(Some(" , "), SPAN_COLUMNS).is_err());
+    }
+}
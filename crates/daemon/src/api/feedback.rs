@@ -0,0 +1,164 @@
+//! End-user feedback, such as a thumbs up or down sent from a product's
+//! own UI.
+//!
+//! `POST /api/feedback` sits outside the authenticated routes. It takes
+//! either a feedback token, issued with a span by
+//! `POST /api/spans/batch?feedback_tokens=true` and safe to hand to a
+//! browser, or a trace id with the caller's own credentials. A token names
+//! its org, project, and span, so it can only ever annotate that span, and
+//! sending it again replaces the feedback it sent before. The route still
+//! draws from the caller's ingest rate limit.
+//!
+//! Feedback is stored as an annotation labelled [`FEEDBACK_LABEL`].
+
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use trace::{Annotation, SpanId, TraceId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{api_error, audit, auth_keys, project_store, require_scope, ApiError, AppState};
+
+/// Label of annotations recorded from end-user feedback, so they can be
+/// found with `label:user_feedback`.
+pub const FEEDBACK_LABEL: &str = "user_feedback";
+
+const MAX_COMMENT_LEN: usize = 4_000;
+
+/// Body for `POST /api/feedback`. Needs `token` or `trace_id`, and a score
+/// or comment.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct FeedbackRequest {
+    /// A feedback token issued with the span. No other credentials are
+    /// needed.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// With `trace_id`, the request must carry an API key or session.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub trace_id: Option<TraceId>,
+    /// The span within `trace_id`; defaults to the trace's root span.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub span_id: Option<SpanId>,
+    /// `1` for a thumbs up and `0` for a thumbs down, or anything between.
+    #[serde(default)]
+    pub score: Option<f64>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// The end user's id in the calling app, recorded as `end_user:<id>`.
+    #[serde(default)]
+    pub user_id: Option<String>,
+}
+
+/// Record end-user feedback on a span.
+#[utoipa::path(
+    post,
+    path = "/api/feedback",
+    tag = "spans",
+    request_body = FeedbackRequest,
+    security((), ("bearer" = []), ("session" = [])),
+    responses(
+        (status = 201, body = Annotation),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn submit_feedback(
+    State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(req): Json<FeedbackRequest>,
+) -> Result<(StatusCode, Json<Annotation>), ApiError> {
+    if req.score.is_none() && req.comment.is_none() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "feedback needs a score or comment",
+        ));
+    }
+    if req.score.is_some_and(|s| !s.is_finite()) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "score must be a finite number"));
+    }
+    if req.comment.as_ref().is_some_and(|c| c.len() > MAX_COMMENT_LEN) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("comment is limited to {MAX_COMMENT_LEN} bytes"),
+        ));
+    }
+
+    let (org_id, project_id, mut annotation) = match (&req.token, req.trace_id) {
+        (Some(token), _) => {
+            let token = auth::verify_feedback_token(token, &state.auth_config.jwt_secret)?;
+            let created_by =
+                end_user(req.user_id.as_deref()).unwrap_or_else(|| "end_user".into());
+            let mut annotation = Annotation::new(token.span_id, token.trace_id, created_by);
+            // One record per token; sending it again changes the vote
+            annotation.id = token.id;
+            (token.org_id, token.project_id, annotation)
+        }
+        (None, Some(trace_id)) => {
+            let ctx = auth_keys::resolve_auth(&state, &headers, None, peer.map(|c| c.0)).await?;
+            require_scope(&ctx, auth::Scope::TracesWrite)?;
+            let span_id = span_in_trace(&state, &ctx, trace_id, req.span_id).await?;
+            let created_by =
+                end_user(req.user_id.as_deref()).unwrap_or_else(|| audit::actor(&ctx));
+            let annotation = Annotation::new(span_id, trace_id, created_by);
+            (ctx.org_id, ctx.project_id, annotation)
+        }
+        (None, None) => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "feedback needs a token or trace_id",
+            ))
+        }
+    };
+    annotation.score = req.score;
+    annotation.label = Some(FEEDBACK_LABEL.to_string());
+    annotation.comment = req.comment;
+
+    let store = state
+        .store_for_project(org_id, project_id)
+        .await
+        .map_err(|(status, msg)| api_error(status, msg))?;
+    store
+        .save_annotation(&annotation)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+fn end_user(user_id: Option<&str>) -> Option<String> {
+    user_id
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| format!("end_user:{id}"))
+}
+
+/// `span_id` if it belongs to `trace_id`, else the trace's root span.
+async fn span_in_trace(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    trace_id: TraceId,
+    span_id: Option<SpanId>,
+) -> Result<SpanId, ApiError> {
+    let store = project_store(ctx, state).await?;
+    let spans = store
+        .trace_spans(trace_id, true)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let span = match span_id {
+        Some(id) => spans.iter().find(|s| s.id() == id),
+        None => spans
+            .iter()
+            .find(|s| s.parent_id().is_none())
+            .or(spans.first()),
+    };
+    span.map(|s| s.id()).ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span not found in trace").with_code("span_not_found")
+    })
+}
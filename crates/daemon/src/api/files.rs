@@ -0,0 +1,53 @@
+//! Stored file content.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use storage::StorageError;
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::{api_error, project_store, require_scope, ApiError, AppState};
+
+/// Content by its hash. It never changes, so clients may cache it for good.
+#[utoipa::path(
+    get,
+    path = "/api/files/content/{hash}",
+    tag = "files",
+    params(("hash" = String, Path, description = "Hex SHA-256 of the content")),
+    responses(
+        (status = 200, description = "The content", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_content(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let etag = ETag::immutable(&hash)
+        .filter(|_| trace::is_content_hash(&hash))
+        .ok_or_else(|| {
+            api_error(StatusCode::BAD_REQUEST, "invalid content hash")
+                .with_code("invalid_content_hash")
+        })?;
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let store = project_store(&ctx, &state).await?;
+    let content = store.load_file_content(&hash).await.map_err(|e| match e {
+        StorageError::NotFound => {
+            api_error(StatusCode::NOT_FOUND, "content not found").with_code("content_not_found")
+        }
+        e => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(etag.respond((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        content,
+    )))
+}
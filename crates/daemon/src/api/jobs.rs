@@ -0,0 +1,1330 @@
+//! Background job queue.
+//!
+//! Jobs are laid out the way BullMQ lays them out, so Node producers and
+//! dashboards can share a queue with the daemon: each job is a hash at
+//! `bull:<queue>:<id>` with `name`, `data`, `opts`, `timestamp`,
+//! `attemptsMade`, `processedOn`, `finishedOn`, `failedReason`,
+//! `stacktrace`, and `returnvalue`; ids come from `bull:<queue>:id`; and
+//! the job moves between the `wait` and `active` lists and the `delayed`,
+//! `completed`, and `failed` sorted sets. A claimed job is locked by
+//! `bull:<queue>:<id>:lock` until its worker finishes it or the lock
+//! lapses. Delayed jobs are scored by the millisecond they become due.
+//!
+//! Unlike BullMQ, jobs that use up their attempts go to a `dead` sorted
+//! set, the dead-letter queue, where they wait to be requeued. `failed`
+//! keeps jobs that failed without being retried, such as jobs no worker
+//! has a handler for.
+//!
+//! [`MemoryJobQueue`] keeps the same states in process for local mode;
+//! `cloud::RedisJobQueue` shares them through Redis. Jobs are processed by
+//! the workers in [`super::workers`]; the `/api/jobs` handlers below
+//! inspect and manage them. While workers run, the retention task and the
+//! report scheduler queue their work as `prune_retention` and
+//! `send_digest` jobs.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{api_error, audit, require_scope, ApiError, AppState, MAX_PAGE_LIMIT};
+
+/// BullMQ's key prefix.
+#[cfg(feature = "cloud")]
+pub const KEY_PREFIX: &str = "bull";
+
+/// The queue the daemon's workers process.
+#[cfg(feature = "cloud")]
+pub const DEFAULT_QUEUE: &str = "traceway";
+
+/// Failed attempts kept in a job's stacktrace.
+const STACKTRACE_LIMIT: usize = 10;
+
+/// Jobs per state the management handlers look through, newest first.
+const SCAN_LIMIT: usize = 2000;
+
+pub type JobId = String;
+
+/// How a failed job waits before its next attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backoff {
+    /// `delay` ms before every retry.
+    Fixed { delay: u64 },
+    /// `delay` ms, doubled for each attempt already made.
+    Exponential { delay: u64 },
+}
+
+impl Backoff {
+    /// Wait before the retry following attempt `attempts_made` (1-based).
+    pub fn delay_after(&self, attempts_made: u32) -> Duration {
+        let ms = match *self {
+            Backoff::Fixed { delay } => delay,
+            Backoff::Exponential { delay } => {
+                let exp = attempts_made.saturating_sub(1).min(32);
+                delay.saturating_mul(1u64 << exp)
+            }
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+/// BullMQ's `JobsOptions`, as far as the daemon uses them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobOptions {
+    /// Attempts in total, the first included.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<Backoff>,
+    /// Milliseconds to wait before the first attempt.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub delay: u64,
+    #[serde(default)]
+    pub remove_on_complete: bool,
+}
+
+fn default_attempts() -> u32 {
+    1
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            attempts: default_attempts(),
+            backoff: None,
+            delay: 0,
+            remove_on_complete: false,
+        }
+    }
+}
+
+impl JobOptions {
+    /// When to retry a job that just failed attempt `attempts_made`, as a
+    /// delay from now, or `None` once its attempts are used up.
+    pub fn retry_delay(&self, attempts_made: u32) -> Option<Duration> {
+        if attempts_made >= self.attempts.max(1) {
+            return None;
+        }
+        Some(
+            self.backoff
+                .map_or(Duration::ZERO, |b| b.delay_after(attempts_made)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Waiting,
+    Active,
+    /// Not due yet, or waiting out a retry's backoff.
+    Delayed,
+    Completed,
+    /// Failed without being retried.
+    Failed,
+    /// Used up its attempts; in the dead-letter queue.
+    Dead,
+}
+
+impl JobState {
+    pub const ALL: [JobState; 6] = [
+        JobState::Waiting,
+        JobState::Active,
+        JobState::Delayed,
+        JobState::Completed,
+        JobState::Failed,
+        JobState::Dead,
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: JobId,
+    pub name: String,
+    pub data: serde_json::Value,
+    pub opts: JobOptions,
+    /// When the job was added, in ms since the epoch.
+    pub timestamp: i64,
+    pub attempts_made: u32,
+    #[serde(default)]
+    pub processed_on: Option<i64>,
+    #[serde(default)]
+    pub finished_on: Option<i64>,
+    #[serde(default)]
+    pub failed_reason: Option<String>,
+    /// Errors of failed attempts, oldest first.
+    #[serde(default)]
+    pub stacktrace: Vec<String>,
+    #[serde(default)]
+    pub returnvalue: Option<serde_json::Value>,
+}
+
+impl Job {
+    fn new(id: JobId, name: &str, data: serde_json::Value, opts: JobOptions, now: i64) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            data,
+            opts,
+            timestamp: now,
+            attempts_made: 0,
+            processed_on: None,
+            finished_on: None,
+            failed_reason: None,
+            stacktrace: Vec::new(),
+            returnvalue: None,
+        }
+    }
+
+    /// Record a failed attempt's error.
+    fn push_error(&mut self, error: &str) {
+        self.failed_reason = Some(error.to_string());
+        self.stacktrace.push(error.to_string());
+        if self.stacktrace.len() > STACKTRACE_LIMIT {
+            self.stacktrace.remove(0);
+        }
+    }
+
+    /// Reset for a fresh set of attempts. The stacktrace is kept.
+    fn reset(&mut self) {
+        self.attempts_made = 0;
+        self.processed_on = None;
+        self.finished_on = None;
+        self.failed_reason = None;
+    }
+
+    /// The org a job works on, if its data names one.
+    pub fn org_id(&self) -> Option<auth::OrgId> {
+        self.data.get("org_id")?.as_str()?.parse().ok()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[cfg(feature = "cloud")]
+    #[error("job queue error: {0}")]
+    Backend(String),
+    #[error("invalid job {0}: {1}")]
+    Corrupt(JobId, String),
+}
+
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// A queue of jobs claimed by workers.
+///
+/// Finishing calls take the token the job was claimed with and do nothing
+/// (returning `false`) if the lock has since lapsed or passed to another
+/// worker.
+#[async_trait]
+pub trait JobQueue: Send + Sync + 'static {
+    /// Add a job, waiting or delayed per `opts.delay`.
+    async fn add(
+        &self,
+        name: &str,
+        data: serde_json::Value,
+        opts: JobOptions,
+    ) -> Result<Job, JobError>;
+
+    /// Move delayed jobs that are due to waiting, then move the oldest
+    /// waiting job to active, locked by `token` for `lock`.
+    async fn claim(&self, token: &str, lock: Duration) -> Result<Option<Job>, JobError>;
+
+    /// Keep holding a claimed job for another `lock`.
+    async fn extend_lock(&self, id: &str, token: &str, lock: Duration) -> Result<bool, JobError>;
+
+    /// Mark a claimed job completed with the handler's result.
+    async fn complete(
+        &self,
+        id: &str,
+        token: &str,
+        result: serde_json::Value,
+    ) -> Result<bool, JobError>;
+
+    /// Record a failed attempt. A `retryable` failure is delayed for a
+    /// retry per the job's backoff, or goes to the dead-letter queue once
+    /// its attempts are used up; any other failure fails the job.
+    async fn fail(
+        &self,
+        id: &str,
+        token: &str,
+        error: &str,
+        retryable: bool,
+    ) -> Result<bool, JobError>;
+
+    /// Return active jobs whose lock lapsed, e.g. because their worker
+    /// died, to waiting. Returns how many were returned.
+    async fn requeue_stalled(&self) -> Result<usize, JobError>;
+
+    async fn get(&self, id: &str) -> Result<Option<(Job, JobState)>, JobError>;
+
+    /// Up to `limit` jobs in `state`, newest first.
+    async fn list(&self, state: JobState, limit: usize) -> Result<Vec<Job>, JobError>;
+
+    /// Move a failed or dead job back to waiting with a fresh set of
+    /// attempts. Returns `false` if it was in neither state.
+    async fn retry(&self, id: &str) -> Result<bool, JobError>;
+
+    /// Delete a delayed job. Returns `false` if it wasn't delayed.
+    async fn cancel(&self, id: &str) -> Result<bool, JobError>;
+}
+
+struct Lock {
+    token: String,
+    until: i64,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    jobs: HashMap<JobId, Job>,
+    /// Oldest first.
+    wait: VecDeque<JobId>,
+    active: HashMap<JobId, Lock>,
+    /// `(due_ms, id)`.
+    delayed: Vec<(i64, JobId)>,
+    /// `(finished_ms, id)`.
+    completed: Vec<(i64, JobId)>,
+    failed: Vec<(i64, JobId)>,
+    dead: Vec<(i64, JobId)>,
+}
+
+impl MemoryState {
+    fn holds(&self, id: &str, token: &str) -> bool {
+        self.active.get(id).is_some_and(|l| l.token == token)
+    }
+
+    fn state_of(&self, id: &str) -> Option<JobState> {
+        let has = |set: &[(i64, JobId)]| set.iter().any(|(_, j)| j == id);
+        if self.wait.iter().any(|j| j == id) {
+            Some(JobState::Waiting)
+        } else if self.active.contains_key(id) {
+            Some(JobState::Active)
+        } else if has(&self.delayed) {
+            Some(JobState::Delayed)
+        } else if has(&self.completed) {
+            Some(JobState::Completed)
+        } else if has(&self.failed) {
+            Some(JobState::Failed)
+        } else if has(&self.dead) {
+            Some(JobState::Dead)
+        } else {
+            None
+        }
+    }
+
+    /// Ids in `state`, newest first.
+    fn ids(&self, state: JobState) -> Vec<JobId> {
+        let newest_first = |set: &[(i64, JobId)]| {
+            let mut set = set.to_vec();
+            set.sort_by(|a, b| b.cmp(a));
+            set.into_iter().map(|(_, id)| id).collect()
+        };
+        match state {
+            JobState::Waiting => self.wait.iter().rev().cloned().collect(),
+            JobState::Active => {
+                let mut ids: Vec<_> = self.active.keys().cloned().collect();
+                ids.sort_by_key(|id| {
+                    std::cmp::Reverse(self.jobs.get(id).and_then(|j| j.processed_on))
+                });
+                ids
+            }
+            JobState::Delayed => newest_first(&self.delayed),
+            JobState::Completed => newest_first(&self.completed),
+            JobState::Failed => newest_first(&self.failed),
+            JobState::Dead => newest_first(&self.dead),
+        }
+    }
+}
+
+/// In-process job queue for local mode. Jobs don't survive a restart.
+#[derive(Default)]
+pub struct MemoryJobQueue {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryJobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl JobQueue for MemoryJobQueue {
+    async fn add(
+        &self,
+        name: &str,
+        data: serde_json::Value,
+        opts: JobOptions,
+    ) -> Result<Job, JobError> {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        // Time-ordered, so ids break ties between jobs added in the same ms
+        let id = uuid::Uuid::now_v7().to_string();
+        let delay = opts.delay;
+        let job = Job::new(id.clone(), name, data, opts, now);
+        state.jobs.insert(id.clone(), job.clone());
+        if delay > 0 {
+            state.delayed.push((now + delay as i64, id));
+        } else {
+            state.wait.push_back(id);
+        }
+        Ok(job)
+    }
+
+    async fn claim(&self, token: &str, lock: Duration) -> Result<Option<Job>, JobError> {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        let (mut due, delayed): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut state.delayed).into_iter().partition(|(at, _)| *at <= now);
+        state.delayed = delayed;
+        due.sort();
+        state.wait.extend(due.into_iter().map(|(_, id)| id));
+
+        let Some(id) = state.wait.pop_front() else {
+            return Ok(None);
+        };
+        state.active.insert(
+            id.clone(),
+            Lock {
+                token: token.to_string(),
+                until: now + lock.as_millis() as i64,
+            },
+        );
+        let job = state
+            .jobs
+            .get_mut(&id)
+            .ok_or_else(|| JobError::Corrupt(id.clone(), "missing".into()))?;
+        job.processed_on = Some(now);
+        Ok(Some(job.clone()))
+    }
+
+    async fn extend_lock(&self, id: &str, token: &str, lock: Duration) -> Result<bool, JobError> {
+        let mut state = self.state.lock().unwrap();
+        match state.active.get_mut(id).filter(|l| l.token == token) {
+            Some(held) => {
+                held.until = now_ms() + lock.as_millis() as i64;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn complete(
+        &self,
+        id: &str,
+        token: &str,
+        result: serde_json::Value,
+    ) -> Result<bool, JobError> {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        if !state.holds(id, token) {
+            return Ok(false);
+        }
+        state.active.remove(id);
+        let remove = state.jobs.get(id).is_some_and(|j| j.opts.remove_on_complete);
+        if remove {
+            state.jobs.remove(id);
+        } else if let Some(job) = state.jobs.get_mut(id) {
+            job.finished_on = Some(now);
+            job.returnvalue = Some(result);
+            state.completed.push((now, id.to_string()));
+        }
+        Ok(true)
+    }
+
+    async fn fail(
+        &self,
+        id: &str,
+        token: &str,
+        error: &str,
+        retryable: bool,
+    ) -> Result<bool, JobError> {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        if !state.holds(id, token) {
+            return Ok(false);
+        }
+        state.active.remove(id);
+        let Some(job) = state.jobs.get_mut(id) else {
+            return Ok(true);
+        };
+        job.attempts_made += 1;
+        job.push_error(error);
+        if !retryable {
+            job.finished_on = Some(now);
+            state.failed.push((now, id.to_string()));
+            return Ok(true);
+        }
+        match job.opts.retry_delay(job.attempts_made) {
+            Some(delay) => {
+                let due = now + delay.as_millis() as i64;
+                state.delayed.push((due, id.to_string()));
+            }
+            None => {
+                job.finished_on = Some(now);
+                state.dead.push((now, id.to_string()));
+            }
+        }
+        Ok(true)
+    }
+
+    async fn requeue_stalled(&self) -> Result<usize, JobError> {
+        let now = now_ms();
+        let mut state = self.state.lock().unwrap();
+        let stalled: Vec<JobId> = state
+            .active
+            .iter()
+            .filter(|(_, lock)| lock.until <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &stalled {
+            state.active.remove(id);
+            state.wait.push_front(id.clone());
+        }
+        Ok(stalled.len())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Job, JobState)>, JobError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .jobs
+            .get(id)
+            .cloned()
+            .zip(state.state_of(id)))
+    }
+
+    async fn list(&self, job_state: JobState, limit: usize) -> Result<Vec<Job>, JobError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .ids(job_state)
+            .iter()
+            .filter_map(|id| state.jobs.get(id).cloned())
+            .take(limit)
+            .collect())
+    }
+
+    async fn retry(&self, id: &str) -> Result<bool, JobError> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.failed.len() + state.dead.len();
+        state.failed.retain(|(_, j)| j != id);
+        state.dead.retain(|(_, j)| j != id);
+        if state.failed.len() + state.dead.len() == before {
+            return Ok(false);
+        }
+        if let Some(job) = state.jobs.get_mut(id) {
+            job.reset();
+        }
+        state.wait.push_back(id.to_string());
+        Ok(true)
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, JobError> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.delayed.len();
+        state.delayed.retain(|(_, j)| j != id);
+        if state.delayed.len() == before {
+            return Ok(false);
+        }
+        state.jobs.remove(id);
+        Ok(true)
+    }
+}
+
+// --- Handlers ---
+
+/// A job and the state it is in.
+#[derive(Debug, Serialize)]
+pub struct JobView {
+    #[serde(flatten)]
+    pub job: Job,
+    pub state: JobState,
+}
+
+/// A job's failures, oldest first.
+#[derive(Debug, Serialize)]
+pub struct JobStacktrace {
+    pub id: JobId,
+    pub name: String,
+    pub attempts_made: u32,
+    pub failed_reason: Option<String>,
+    pub stacktrace: Vec<String>,
+}
+
+/// Query parameters for `GET /api/jobs` and `GET /api/jobs/dead-letter`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListJobsQuery {
+    /// Every state when unset. Ignored for the dead-letter queue.
+    pub status: Option<JobState>,
+    pub name: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Query parameters for `POST /api/jobs/dead-letter/requeue`.
+#[derive(Debug, Default, Deserialize)]
+pub struct RequeueQuery {
+    /// Only requeue jobs with this name.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RequeueResult {
+    pub requeued: usize,
+}
+
+fn queue_error(e: JobError) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e)
+}
+
+fn not_found(id: &str) -> ApiError {
+    api_error(StatusCode::NOT_FOUND, format!("job {id} not found")).with_code("job_not_found")
+}
+
+/// Jobs are shared across orgs in cloud mode; callers only see jobs whose
+/// data names their org.
+fn visible(ctx: &auth::AuthContext, job: &Job) -> bool {
+    ctx.is_local_mode || job.org_id() == Some(ctx.org_id)
+}
+
+async fn visible_job(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    id: &str,
+) -> Result<(Job, JobState), ApiError> {
+    match state.jobs.get(id).await.map_err(queue_error)? {
+        Some((job, job_state)) if visible(ctx, &job) => Ok((job, job_state)),
+        _ => Err(not_found(id)),
+    }
+}
+
+/// The caller's jobs in `states`, newest first within each state.
+async fn visible_jobs(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    states: &[JobState],
+    name: Option<&str>,
+) -> Result<Vec<JobView>, ApiError> {
+    let mut jobs = Vec::new();
+    for &job_state in states {
+        let listed = state
+            .jobs
+            .list(job_state, SCAN_LIMIT)
+            .await
+            .map_err(queue_error)?;
+        jobs.extend(
+            listed
+                .into_iter()
+                .filter(|job| visible(ctx, job) && name.is_none_or(|n| job.name == n))
+                .map(|job| JobView {
+                    job,
+                    state: job_state,
+                }),
+        );
+    }
+    Ok(jobs)
+}
+
+/// List jobs, optionally by state and name. Only the newest 2000 jobs in
+/// each state are searched.
+pub async fn list_jobs(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ListJobsQuery>,
+) -> Result<Json<Vec<JobView>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let states = q.status.map_or(JobState::ALL.to_vec(), |s| vec![s]);
+    let jobs = visible_jobs(&state, &ctx, &states, q.name.as_deref()).await?;
+    Ok(Json(page(jobs, &q)))
+}
+
+fn page(jobs: Vec<JobView>, q: &ListJobsQuery) -> Vec<JobView> {
+    jobs.into_iter()
+        .skip(q.offset.unwrap_or(0))
+        .take(q.limit.unwrap_or(100).min(MAX_PAGE_LIMIT))
+        .collect()
+}
+
+pub async fn get_job(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobView>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let (job, job_state) = visible_job(&state, &ctx, &id).await?;
+    Ok(Json(JobView {
+        job,
+        state: job_state,
+    }))
+}
+
+/// The errors of a job's failed attempts.
+pub async fn job_stacktrace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobStacktrace>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let (job, _) = visible_job(&state, &ctx, &id).await?;
+    Ok(Json(JobStacktrace {
+        id: job.id,
+        name: job.name,
+        attempts_made: job.attempts_made,
+        failed_reason: job.failed_reason,
+        stacktrace: job.stacktrace,
+    }))
+}
+
+/// Run a failed or dead job again with a fresh set of attempts.
+pub async fn retry_job(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobView>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let (job, job_state) = visible_job(&state, &ctx, &id).await?;
+    if !state.jobs.retry(&id).await.map_err(queue_error)? {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            format!("job {id} is {job_state:?}, only failed and dead jobs can be retried"),
+        )
+        .with_code("job_not_retryable"));
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "job.retry",
+        Some(id.clone()),
+        serde_json::json!({ "name": job.name }),
+    )
+    .await;
+    let (job, job_state) = visible_job(&state, &ctx, &id).await?;
+    Ok(Json(JobView {
+        job,
+        state: job_state,
+    }))
+}
+
+/// Delete a delayed job before it runs.
+pub async fn cancel_job(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let (job, job_state) = visible_job(&state, &ctx, &id).await?;
+    if !state.jobs.cancel(&id).await.map_err(queue_error)? {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            format!("job {id} is {job_state:?}, only delayed jobs can be cancelled"),
+        )
+        .with_code("job_not_cancellable"));
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "job.cancel",
+        Some(id),
+        serde_json::json!({ "name": job.name }),
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Jobs that used up their attempts, newest first.
+pub async fn list_dead_letter(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ListJobsQuery>,
+) -> Result<Json<Vec<JobView>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let jobs = visible_jobs(&state, &ctx, &[JobState::Dead], q.name.as_deref()).await?;
+    Ok(Json(page(jobs, &q)))
+}
+
+/// Move the caller's dead jobs back to waiting.
+pub async fn requeue_dead_letter(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<RequeueQuery>,
+) -> Result<Json<RequeueResult>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let dead = visible_jobs(&state, &ctx, &[JobState::Dead], q.name.as_deref()).await?;
+    let mut requeued = 0;
+    for view in &dead {
+        // Another instance may have requeued it already
+        if state.jobs.retry(&view.job.id).await.map_err(queue_error)? {
+            requeued += 1;
+        }
+    }
+    if requeued > 0 {
+        audit::record(
+            &state,
+            &ctx,
+            "job.requeue_dead",
+            None,
+            serde_json::json!({ "name": q.name, "requeued": requeued }),
+        )
+        .await;
+    }
+    Ok(Json(RequeueResult { requeued }))
+}
+
+/// Redis job queue for multi-instance deployments.
+#[cfg(feature = "cloud")]
+pub mod cloud {
+    use super::*;
+    use redis::aio::ConnectionManager;
+    use tracing::info;
+
+    /// Promote due delayed jobs, then move the oldest waiting job to
+    /// active and lock it.
+    /// KEYS: wait, active, delayed. ARGV: job key prefix, now, token, lock ms.
+    const CLAIM_SCRIPT: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[3], '-inf', ARGV[2], 'LIMIT', 0, 100)
+for _, id in ipairs(due) do
+  redis.call('ZREM', KEYS[3], id)
+  redis.call('LPUSH', KEYS[1], id)
+end
+local id = redis.call('RPOPLPUSH', KEYS[1], KEYS[2])
+if not id then return false end
+redis.call('SET', ARGV[1] .. id .. ':lock', ARGV[3], 'PX', ARGV[4])
+redis.call('HSET', ARGV[1] .. id, 'processedOn', ARGV[2])
+return id
+"#;
+
+    /// ARGV: lock key, token, lock ms.
+    const EXTEND_SCRIPT: &str = r#"
+if redis.call('GET', ARGV[1]) ~= ARGV[2] then return 0 end
+redis.call('PEXPIRE', ARGV[1], ARGV[3])
+return 1
+"#;
+
+    /// KEYS: active, completed. ARGV: job key, id, token, now, return
+    /// value, remove on complete.
+    const COMPLETE_SCRIPT: &str = r#"
+if redis.call('GET', ARGV[1] .. ':lock') ~= ARGV[3] then return 0 end
+redis.call('DEL', ARGV[1] .. ':lock')
+redis.call('LREM', KEYS[1], 0, ARGV[2])
+if ARGV[6] == '1' then
+  redis.call('DEL', ARGV[1])
+else
+  redis.call('HSET', ARGV[1], 'finishedOn', ARGV[4], 'returnvalue', ARGV[5])
+  redis.call('ZADD', KEYS[2], ARGV[4], ARGV[2])
+end
+return 1
+"#;
+
+    /// KEYS: active, delayed, and the set the job ends up in if it isn't
+    /// retried (failed or dead). ARGV: job key, id, token, now, attempts
+    /// made, failed reason, stacktrace, retry at ('' for none).
+    const FAIL_SCRIPT: &str = r#"
+if redis.call('GET', ARGV[1] .. ':lock') ~= ARGV[3] then return 0 end
+redis.call('DEL', ARGV[1] .. ':lock')
+redis.call('LREM', KEYS[1], 0, ARGV[2])
+redis.call('HSET', ARGV[1], 'attemptsMade', ARGV[5], 'failedReason', ARGV[6], 'stacktrace', ARGV[7])
+if ARGV[8] ~= '' then
+  redis.call('ZADD', KEYS[2], ARGV[8], ARGV[2])
+else
+  redis.call('HSET', ARGV[1], 'finishedOn', ARGV[4])
+  redis.call('ZADD', KEYS[3], ARGV[4], ARGV[2])
+end
+return 1
+"#;
+
+    /// Return active jobs without a lock to the front of waiting.
+    /// KEYS: active, wait. ARGV: job key prefix.
+    const STALLED_SCRIPT: &str = r#"
+local ids = redis.call('LRANGE', KEYS[1], 0, -1)
+local n = 0
+for _, id in ipairs(ids) do
+  if redis.call('EXISTS', ARGV[1] .. id .. ':lock') == 0 then
+    redis.call('LREM', KEYS[1], 0, id)
+    redis.call('RPUSH', KEYS[2], id)
+    n = n + 1
+  end
+end
+return n
+"#;
+
+    /// Which state a job is in, as the name of its list or set.
+    /// KEYS: wait, active, delayed, completed, failed, dead. ARGV: id.
+    const STATE_SCRIPT: &str = r#"
+for i = 1, 2 do
+  for _, id in ipairs(redis.call('LRANGE', KEYS[i], 0, -1)) do
+    if id == ARGV[1] then return i end
+  end
+end
+for i = 3, 6 do
+  if redis.call('ZSCORE', KEYS[i], ARGV[1]) then return i end
+end
+return 0
+"#;
+
+    /// KEYS: failed, dead, wait. ARGV: job key, id.
+    const RETRY_SCRIPT: &str = r#"
+local removed = redis.call('ZREM', KEYS[1], ARGV[2]) + redis.call('ZREM', KEYS[2], ARGV[2])
+if removed == 0 then return 0 end
+redis.call('HSET', ARGV[1], 'attemptsMade', 0)
+redis.call('HDEL', ARGV[1], 'processedOn', 'finishedOn', 'failedReason')
+redis.call('LPUSH', KEYS[3], ARGV[2])
+return 1
+"#;
+
+    /// KEYS: delayed. ARGV: job key, id.
+    const CANCEL_SCRIPT: &str = r#"
+if redis.call('ZREM', KEYS[1], ARGV[2]) == 0 then return 0 end
+redis.call('DEL', ARGV[1], ARGV[1] .. ':lock')
+return 1
+"#;
+
+    /// Key suffix of each state's list or set, in `STATE_SCRIPT` order.
+    const STATE_KEYS: [(&str, JobState); 6] = [
+        ("wait", JobState::Waiting),
+        ("active", JobState::Active),
+        ("delayed", JobState::Delayed),
+        ("completed", JobState::Completed),
+        ("failed", JobState::Failed),
+        ("dead", JobState::Dead),
+    ];
+
+    fn state_key(state: JobState) -> &'static str {
+        STATE_KEYS
+            .iter()
+            .find(|(_, s)| *s == state)
+            .map(|(key, _)| *key)
+            .expect("every state has a key")
+    }
+
+    fn backend(e: redis::RedisError) -> JobError {
+        JobError::Backend(e.to_string())
+    }
+
+    pub struct RedisJobQueue {
+        conn: ConnectionManager,
+        /// `bull:<queue>:`
+        prefix: String,
+        claim: redis::Script,
+        extend: redis::Script,
+        complete: redis::Script,
+        fail: redis::Script,
+        stalled: redis::Script,
+        state: redis::Script,
+        retry: redis::Script,
+        cancel: redis::Script,
+    }
+
+    impl RedisJobQueue {
+        pub async fn new(redis_url: &str, queue: &str) -> Result<Self, redis::RedisError> {
+            let client = redis::Client::open(redis_url)?;
+            let conn = ConnectionManager::new(client).await?;
+            info!(queue, "Redis job queue initialized");
+            Ok(Self {
+                conn,
+                prefix: format!("{KEY_PREFIX}:{queue}:"),
+                claim: redis::Script::new(CLAIM_SCRIPT),
+                extend: redis::Script::new(EXTEND_SCRIPT),
+                complete: redis::Script::new(COMPLETE_SCRIPT),
+                fail: redis::Script::new(FAIL_SCRIPT),
+                stalled: redis::Script::new(STALLED_SCRIPT),
+                state: redis::Script::new(STATE_SCRIPT),
+                retry: redis::Script::new(RETRY_SCRIPT),
+                cancel: redis::Script::new(CANCEL_SCRIPT),
+            })
+        }
+
+        fn key(&self, name: &str) -> String {
+            format!("{}{name}", self.prefix)
+        }
+
+        fn conn(&self) -> ConnectionManager {
+            self.conn.clone()
+        }
+
+        async fn load(&self, id: &str) -> Result<Option<Job>, JobError> {
+            let fields: HashMap<String, String> = redis::cmd("HGETALL")
+                .arg(self.key(id))
+                .query_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            if fields.is_empty() {
+                return Ok(None);
+            }
+            job_from_hash(id, &fields).map(Some)
+        }
+    }
+
+    /// Read a job from its BullMQ hash.
+    fn job_from_hash(id: &str, fields: &HashMap<String, String>) -> Result<Job, JobError> {
+        let corrupt = |what: &str| JobError::Corrupt(id.to_string(), what.to_string());
+        let int = |name: &str| fields.get(name).and_then(|v| v.parse::<i64>().ok());
+        fn json<T: serde::de::DeserializeOwned>(fields: &HashMap<String, String>, name: &str) -> Option<T> {
+            fields.get(name).and_then(|v| serde_json::from_str(v).ok())
+        }
+        Ok(Job {
+            id: id.to_string(),
+            name: fields.get("name").cloned().ok_or_else(|| corrupt("no name"))?,
+            data: json(fields, "data").unwrap_or(serde_json::Value::Null),
+            opts: json(fields, "opts").unwrap_or_default(),
+            timestamp: int("timestamp").unwrap_or(0),
+            attempts_made: int("attemptsMade").unwrap_or(0) as u32,
+            processed_on: int("processedOn"),
+            finished_on: int("finishedOn"),
+            failed_reason: fields.get("failedReason").cloned(),
+            stacktrace: json(fields, "stacktrace").unwrap_or_default(),
+            returnvalue: json(fields, "returnvalue"),
+        })
+    }
+
+    #[async_trait]
+    impl JobQueue for RedisJobQueue {
+        async fn add(
+            &self,
+            name: &str,
+            data: serde_json::Value,
+            opts: JobOptions,
+        ) -> Result<Job, JobError> {
+            let mut conn = self.conn();
+            let seq: u64 = redis::cmd("INCR")
+                .arg(self.key("id"))
+                .query_async(&mut conn)
+                .await
+                .map_err(backend)?;
+            let now = now_ms();
+            let job = Job::new(seq.to_string(), name, data, opts, now);
+            let opts_json = serde_json::to_string(&job.opts)
+                .map_err(|e| JobError::Corrupt(job.id.clone(), e.to_string()))?;
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .cmd("HSET")
+                .arg(self.key(&job.id))
+                .arg("name")
+                .arg(&job.name)
+                .arg("data")
+                .arg(job.data.to_string())
+                .arg("opts")
+                .arg(opts_json)
+                .arg("timestamp")
+                .arg(now)
+                .arg("delay")
+                .arg(job.opts.delay)
+                .arg("attemptsMade")
+                .arg(0)
+                .ignore();
+            if job.opts.delay > 0 {
+                pipe.cmd("ZADD")
+                    .arg(self.key("delayed"))
+                    .arg(now + job.opts.delay as i64)
+                    .arg(&job.id)
+                    .ignore();
+            } else {
+                pipe.cmd("LPUSH").arg(self.key("wait")).arg(&job.id).ignore();
+            }
+            pipe.query_async::<_, ()>(&mut conn).await.map_err(backend)?;
+            Ok(job)
+        }
+
+        async fn claim(&self, token: &str, lock: Duration) -> Result<Option<Job>, JobError> {
+            let id: Option<String> = self
+                .claim
+                .key(self.key("wait"))
+                .key(self.key("active"))
+                .key(self.key("delayed"))
+                .arg(&self.prefix)
+                .arg(now_ms())
+                .arg(token)
+                .arg(lock.as_millis() as u64)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            match id {
+                Some(id) => self.load(&id).await,
+                None => Ok(None),
+            }
+        }
+
+        async fn extend_lock(
+            &self,
+            id: &str,
+            token: &str,
+            lock: Duration,
+        ) -> Result<bool, JobError> {
+            let extended: i64 = self
+                .extend
+                .arg(self.key(&format!("{id}:lock")))
+                .arg(token)
+                .arg(lock.as_millis() as u64)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(extended == 1)
+        }
+
+        async fn complete(
+            &self,
+            id: &str,
+            token: &str,
+            result: serde_json::Value,
+        ) -> Result<bool, JobError> {
+            let remove = self
+                .load(id)
+                .await?
+                .is_some_and(|j| j.opts.remove_on_complete);
+            let done: i64 = self
+                .complete
+                .key(self.key("active"))
+                .key(self.key("completed"))
+                .arg(self.key(id))
+                .arg(id)
+                .arg(token)
+                .arg(now_ms())
+                .arg(result.to_string())
+                .arg(if remove { "1" } else { "0" })
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(done == 1)
+        }
+
+        async fn fail(
+            &self,
+            id: &str,
+            token: &str,
+            error: &str,
+            retryable: bool,
+        ) -> Result<bool, JobError> {
+            let Some(mut job) = self.load(id).await? else {
+                return Ok(false);
+            };
+            let now = now_ms();
+            job.attempts_made += 1;
+            job.push_error(error);
+            let retry_at = job
+                .opts
+                .retry_delay(job.attempts_made)
+                .filter(|_| retryable)
+                .map_or(String::new(), |d| (now + d.as_millis() as i64).to_string());
+            let terminal = if retryable { "dead" } else { "failed" };
+            let stacktrace = serde_json::to_string(&job.stacktrace)
+                .map_err(|e| JobError::Corrupt(id.to_string(), e.to_string()))?;
+            let done: i64 = self
+                .fail
+                .key(self.key("active"))
+                .key(self.key("delayed"))
+                .key(self.key(terminal))
+                .arg(self.key(id))
+                .arg(id)
+                .arg(token)
+                .arg(now)
+                .arg(job.attempts_made)
+                .arg(error)
+                .arg(stacktrace)
+                .arg(retry_at)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(done == 1)
+        }
+
+        async fn requeue_stalled(&self) -> Result<usize, JobError> {
+            let n: usize = self
+                .stalled
+                .key(self.key("active"))
+                .key(self.key("wait"))
+                .arg(&self.prefix)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(n)
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<(Job, JobState)>, JobError> {
+            let mut invocation = self.state.prepare_invoke();
+            for (key, _) in STATE_KEYS {
+                invocation.key(self.key(key));
+            }
+            let index: usize = invocation
+                .arg(id)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            let Some((_, state)) = index.checked_sub(1).and_then(|i| STATE_KEYS.get(i)) else {
+                return Ok(None);
+            };
+            Ok(self.load(id).await?.map(|job| (job, *state)))
+        }
+
+        async fn list(&self, state: JobState, limit: usize) -> Result<Vec<Job>, JobError> {
+            if limit == 0 {
+                return Ok(Vec::new());
+            }
+            let key = self.key(state_key(state));
+            let stop = limit as isize - 1;
+            let mut conn = self.conn();
+            // Lists are pushed at the head, so both come back newest first
+            let ids: Vec<String> = match state {
+                JobState::Waiting | JobState::Active => redis::cmd("LRANGE")
+                    .arg(&key)
+                    .arg(0)
+                    .arg(stop)
+                    .query_async(&mut conn)
+                    .await,
+                _ => redis::cmd("ZREVRANGE")
+                    .arg(&key)
+                    .arg(0)
+                    .arg(stop)
+                    .query_async(&mut conn)
+                    .await,
+            }
+            .map_err(backend)?;
+            let mut pipe = redis::pipe();
+            for id in &ids {
+                pipe.cmd("HGETALL").arg(self.key(id));
+            }
+            let hashes: Vec<HashMap<String, String>> =
+                pipe.query_async(&mut conn).await.map_err(backend)?;
+            ids.iter()
+                .zip(hashes)
+                .filter(|(_, fields)| !fields.is_empty())
+                .map(|(id, fields)| job_from_hash(id, &fields))
+                .collect()
+        }
+
+        async fn retry(&self, id: &str) -> Result<bool, JobError> {
+            let retried: i64 = self
+                .retry
+                .key(self.key("failed"))
+                .key(self.key("dead"))
+                .key(self.key("wait"))
+                .arg(self.key(id))
+                .arg(id)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(retried == 1)
+        }
+
+        async fn cancel(&self, id: &str) -> Result<bool, JobError> {
+            let cancelled: i64 = self
+                .cancel
+                .key(self.key("delayed"))
+                .arg(self.key(id))
+                .arg(id)
+                .invoke_async(&mut self.conn())
+                .await
+                .map_err(backend)?;
+            Ok(cancelled == 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_read_bullmq_json() {
+        let opts: JobOptions = serde_json::from_value(serde_json::json!({
+            "attempts": 3,
+            "backoff": { "type": "exponential", "delay": 1000 },
+            "removeOnComplete": true,
+        }))
+        .unwrap();
+        assert_eq!(opts.retry_delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(opts.retry_delay(2), Some(Duration::from_secs(2)));
+        assert_eq!(opts.retry_delay(3), None);
+        assert_eq!(JobOptions::default().retry_delay(1), None);
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_retry_until_they_are_dead() {
+        let queue = MemoryJobQueue::new();
+        let opts = JobOptions {
+            attempts: 2,
+            ..Default::default()
+        };
+        let job = queue.add("embed_spans", serde_json::json!({}), opts).await.unwrap();
+        let lock = Duration::from_secs(30);
+
+        let claimed = queue.claim("a", lock).await.unwrap().unwrap();
+        assert_eq!(claimed.id, job.id);
+        assert!(queue.claim("b", lock).await.unwrap().is_none());
+        assert!(!queue.fail(&job.id, "b", "not mine", true).await.unwrap());
+        assert!(queue.fail(&job.id, "a", "boom", true).await.unwrap());
+
+        // No backoff, so the retry is due at once
+        let retried = queue.claim("a", lock).await.unwrap().unwrap();
+        assert_eq!(retried.attempts_made, 1);
+        assert!(queue.fail(&job.id, "a", "boom again", true).await.unwrap());
+        assert!(queue.claim("a", lock).await.unwrap().is_none());
+
+        let (dead, state) = queue.get(&job.id).await.unwrap().unwrap();
+        assert_eq!(state, JobState::Dead);
+        assert_eq!(dead.stacktrace, ["boom", "boom again"]);
+    }
+
+    #[tokio::test]
+    async fn retry_and_cancel() {
+        let queue = MemoryJobQueue::new();
+        let lock = Duration::from_secs(30);
+        let job = queue
+            .add("embed_spans", serde_json::json!({}), JobOptions::default())
+            .await
+            .unwrap();
+        queue.claim("a", lock).await.unwrap().unwrap();
+        assert!(queue.fail(&job.id, "a", "bad data", false).await.unwrap());
+        assert_eq!(queue.list(JobState::Failed, 10).await.unwrap().len(), 1);
+        assert!(!queue.cancel(&job.id).await.unwrap());
+
+        assert!(queue.retry(&job.id).await.unwrap());
+        assert!(!queue.retry(&job.id).await.unwrap());
+        let (retried, state) = queue.get(&job.id).await.unwrap().unwrap();
+        assert_eq!(state, JobState::Waiting);
+        assert_eq!(retried.attempts_made, 0);
+        assert_eq!(retried.stacktrace, ["bad data"]);
+
+        let delayed = queue
+            .add(
+                "send_digest",
+                serde_json::json!({}),
+                JobOptions {
+                    delay: 60_000,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(queue.cancel(&delayed.id).await.unwrap());
+        assert!(queue.get(&delayed.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn lapsed_locks_return_jobs_to_waiting() {
+        let queue = MemoryJobQueue::new();
+        let job = queue
+            .add("send_digest", serde_json::json!({}), JobOptions::default())
+            .await
+            .unwrap();
+        queue.claim("a", Duration::ZERO).await.unwrap().unwrap();
+        assert_eq!(queue.requeue_stalled().await.unwrap(), 1);
+
+        let reclaimed = queue.claim("b", Duration::from_secs(30)).await.unwrap().unwrap();
+        assert_eq!(reclaimed.id, job.id);
+        assert!(!queue.complete(&job.id, "a", serde_json::Value::Null).await.unwrap());
+        assert!(queue.complete(&job.id, "b", serde_json::Value::Null).await.unwrap());
+    }
+}
@@ -0,0 +1,77 @@
+//! The model behind the daemon's own LLM features, asked through the local
+//! proxy like any other client so its calls are traced and priced.
+
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::scorers::output_text;
+use crate::config::LlmConfig;
+
+pub struct LlmClient {
+    client: reqwest::Client,
+    config: LlmConfig,
+}
+
+impl LlmClient {
+    pub fn new(config: LlmConfig) -> Arc<Self> {
+        Arc::new(Self {
+            client: reqwest::Client::new(),
+            config,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    /// Send a chat completion with `system` and `user` messages through the
+    /// proxy at `proxy_url`, and return the text of the reply.
+    pub async fn complete(
+        &self,
+        proxy_url: &str,
+        system: &str,
+        user: &str,
+    ) -> Result<String, String> {
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/v1/chat/completions",
+                proxy_url.trim_end_matches('/')
+            ))
+            .json(&serde_json::json!({
+                "model": self.config.model,
+                "temperature": 0,
+                "messages": [
+                    { "role": "system", "content": system },
+                    { "role": "user", "content": user },
+                ],
+            }));
+        if let Ok(key) = std::env::var(&self.config.api_key_env) {
+            request = request.bearer_auth(key);
+        }
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| format!("model request failed: {e}"))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!("model returned {status}: {body}"));
+        }
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|e| format!("invalid model response: {e}"))?;
+        Ok(output_text(&body))
+    }
+}
+
+/// The JSON object in `text` from its first `{` to its last `}`, tolerating
+/// prose or code fences around it.
+pub fn json_object<T: DeserializeOwned>(text: &str) -> Option<T> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    serde_json::from_str(text.get(start..=end)?).ok()
+}
@@ -0,0 +1,244 @@
+//! Machine registry.
+//!
+//! Daemons register themselves with a periodic heartbeat, and their traces
+//! carry the same id in `machine_id`. A local daemon registers in its own
+//! store and, when bridged to a backend, with the backend too, so a cloud
+//! project lists every machine that reports into it.
+
+use std::path::Path;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use trace::{Machine, MachineStatus};
+use tracing::{info, warn};
+
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState, SharedStore};
+
+/// How often a local daemon re-registers.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Body for `POST /api/machines`, sent on every heartbeat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterMachine {
+    pub id: String,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub daemon_version: String,
+}
+
+impl RegisterMachine {
+    /// This daemon, identified by the id stored at `id_path` (created on
+    /// first use so it survives restarts).
+    pub fn local(id_path: &Path) -> Self {
+        let id = match std::fs::read_to_string(id_path) {
+            Ok(id) if !id.trim().is_empty() => id.trim().to_string(),
+            _ => {
+                let id = uuid::Uuid::new_v4().to_string();
+                if let Some(parent) = id_path.parent() {
+                    std::fs::create_dir_all(parent).ok();
+                }
+                if let Err(e) = std::fs::write(id_path, &id) {
+                    warn!(path = %id_path.display(), error = %e, "failed to persist machine id");
+                }
+                id
+            }
+        };
+        let hostname = nix::unistd::gethostname()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_default();
+        Self {
+            id,
+            hostname,
+            os: std::env::consts::OS.to_string(),
+            daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    fn into_machine(self) -> Machine {
+        let now = Utc::now();
+        Machine {
+            id: self.id,
+            hostname: self.hostname,
+            os: self.os,
+            daemon_version: self.daemon_version,
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+}
+
+/// A registered machine with its health as of the request.
+#[derive(Debug, Serialize)]
+pub struct MachineInfo {
+    #[serde(flatten)]
+    pub machine: Machine,
+    pub status: MachineStatus,
+}
+
+impl From<Machine> for MachineInfo {
+    fn from(machine: Machine) -> Self {
+        let status = machine.status_at(Utc::now());
+        Self { machine, status }
+    }
+}
+
+/// Query parameters for `GET /api/machines`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListMachinesQuery {
+    /// Case-insensitive substring of the id, hostname, OS, or daemon version.
+    pub q: Option<String>,
+    pub status: Option<MachineStatus>,
+}
+
+impl ListMachinesQuery {
+    fn matches(&self, info: &MachineInfo) -> bool {
+        if self.status.is_some_and(|s| s != info.status) {
+            return false;
+        }
+        let Some(q) = self.q.as_deref().map(str::to_lowercase) else {
+            return true;
+        };
+        let m = &info.machine;
+        [&m.id, &m.hostname, &m.os, &m.daemon_version]
+            .iter()
+            .any(|field| field.to_lowercase().contains(&q))
+    }
+}
+
+pub async fn register_machine(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<RegisterMachine>,
+) -> Result<Json<MachineInfo>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    if req.id.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "id must not be empty"));
+    }
+    let store = project_store(&ctx, &state).await?;
+    let machine = store
+        .register_machine(req.into_machine())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(machine.into()))
+}
+
+/// Registered machines, most recently seen first.
+pub async fn list_machines(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ListMachinesQuery>,
+) -> Result<Json<Vec<MachineInfo>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let machines = store
+        .list_machines()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut infos: Vec<MachineInfo> = machines
+        .into_iter()
+        .map(MachineInfo::from)
+        .filter(|info| q.matches(info))
+        .collect();
+    infos.sort_by_key(|info| std::cmp::Reverse(info.machine.last_seen));
+    Ok(Json(infos))
+}
+
+pub async fn delete_machine(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = project_store(&ctx, &state).await?;
+    let deleted = store
+        .delete_machine(&id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if deleted {
+        audit::record(
+            &state,
+            &ctx,
+            "machine.delete",
+            Some(id),
+            serde_json::Value::Null,
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(StatusCode::NOT_FOUND, "machine not found").with_code("machine_not_found"))
+    }
+}
+
+/// Register `machine` in `store`, and with the backend when bridged, every
+/// `HEARTBEAT_INTERVAL` until shutdown.
+pub fn spawn_heartbeat(
+    store: SharedStore,
+    machine: RegisterMachine,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> tokio::task::JoinHandle<()> {
+    info!(machine_id = %machine.id, hostname = %machine.hostname, "registering machine");
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = store.register_machine(machine.clone().into_machine()).await {
+                        warn!(error = %e, "failed to record machine heartbeat");
+                    }
+                    crate::proxy::bridge_register_machine(&client, &machine).await;
+                }
+                _ = shutdown_rx.changed() => return,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as ChronoDuration;
+
+    fn info(id: &str, hostname: &str, silent_secs: i64) -> MachineInfo {
+        let mut machine = RegisterMachine {
+            id: id.to_string(),
+            hostname: hostname.to_string(),
+            os: "linux".to_string(),
+            daemon_version: "0.1.0".to_string(),
+        }
+        .into_machine();
+        machine.last_seen -= ChronoDuration::seconds(silent_secs);
+        machine.into()
+    }
+
+    #[test]
+    fn status_follows_last_seen() {
+        assert_eq!(info("a", "h", 10).status, MachineStatus::Online);
+        assert_eq!(info("a", "h", 3_600).status, MachineStatus::Stale);
+        assert_eq!(info("a", "h", 2 * 86_400).status, MachineStatus::Offline);
+    }
+
+    #[test]
+    fn query_matches_fields_and_status() {
+        let laptop = info("m-1", "Dev-Laptop", 10);
+        let query = |q: &str, status| ListMachinesQuery {
+            q: Some(q.to_string()),
+            status,
+        };
+        assert!(query("laptop", None).matches(&laptop));
+        assert!(query("LINUX", Some(MachineStatus::Online)).matches(&laptop));
+        assert!(!query("laptop", Some(MachineStatus::Offline)).matches(&laptop));
+        assert!(!query("ci-runner", None).matches(&laptop));
+    }
+}
@@ -1,221 +1,400 @@
-//! Prometheus metrics for Traceway cloud deployment.
+//! Prometheus metrics.
 //!
-//! This module provides instrumentation for monitoring the health and performance
-//! of the Traceway service in production.
+//! One process-wide registry, [`global`], is shared by the API and the
+//! proxy. Counters and histograms are updated where things happen;
+//! `/api/metrics` renders them in the Prometheus text format along with
+//! span and trace counts, which are read at scrape time.
 
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use super::SystemEvent;
+
+/// Histogram bucket bounds, in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// The process-wide registry.
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
 
-/// Metrics registry for the application
 #[derive(Debug, Default)]
-pub struct Metrics {
-    // Counters
-    pub span_writes_total: AtomicU64,
-    pub span_reads_total: AtomicU64,
-    pub trace_writes_total: AtomicU64,
-    pub trace_reads_total: AtomicU64,
-    pub sse_connections_total: AtomicU64,
-    pub api_requests_total: AtomicU64,
-    pub api_errors_total: AtomicU64,
-
-    // Gauges (current values)
-    pub sse_connections_active: AtomicU64,
-    pub span_count: AtomicU64,
-    pub trace_count: AtomicU64,
-
-    // Histogram buckets for latency tracking
-    pub span_write_latency_sum_us: AtomicU64,
-    pub span_write_latency_count: AtomicU64,
-    pub api_latency_sum_us: AtomicU64,
-    pub api_latency_count: AtomicU64,
+pub struct Histogram {
+    /// Cumulative: each counts observations at or below its bound.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_us: AtomicU64,
+    count: AtomicU64,
 }
 
-impl Metrics {
-    pub fn new() -> Arc<Self> {
-        Arc::new(Self::default())
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, le) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if secs <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Record a span write operation
-    pub fn record_span_write(&self, duration: std::time::Duration) {
-        self.span_writes_total.fetch_add(1, Ordering::Relaxed);
-        self.span_write_latency_sum_us
-            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
-        self.span_write_latency_count
-            .fetch_add(1, Ordering::Relaxed);
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bucket, le) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let n = bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {n}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {sum:.6}");
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
     }
+}
+
+/// One metric's series, keyed by rendered label set, e.g. `op="save_span"`.
+#[derive(Debug, Default)]
+struct Family<T> {
+    series: RwLock<BTreeMap<String, Arc<T>>>,
+}
 
-    /// Record a span read operation
-    pub fn record_span_read(&self) {
-        self.span_reads_total.fetch_add(1, Ordering::Relaxed);
+impl<T: Default> Family<T> {
+    fn get(&self, labels: &[(&str, &str)]) -> Arc<T> {
+        let key = render_labels(labels);
+        if let Some(series) = self
+            .series
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return series.clone();
+        }
+        let mut series = self.series.write().unwrap_or_else(|e| e.into_inner());
+        series.entry(key).or_default().clone()
     }
 
-    /// Record a trace write operation
-    pub fn record_trace_write(&self) {
-        self.trace_writes_total.fetch_add(1, Ordering::Relaxed);
+    fn snapshot(&self) -> Vec<(String, Arc<T>)> {
+        let series = self.series.read().unwrap_or_else(|e| e.into_inner());
+        series.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
     }
+}
 
-    /// Record an API request
-    pub fn record_api_request(&self, duration: std::time::Duration, is_error: bool) {
-        self.api_requests_total.fetch_add(1, Ordering::Relaxed);
-        if is_error {
-            self.api_errors_total.fetch_add(1, Ordering::Relaxed);
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    for (i, (name, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
         }
-        self.api_latency_sum_us
-            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
-        self.api_latency_count.fetch_add(1, Ordering::Relaxed);
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = write!(out, "{name}=\"{value}\"");
     }
+    out
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// By source: `batch`, `otlp`, or `proxy`.
+    spans_ingested: Family<AtomicU64>,
+    spans_completed: AtomicU64,
+    spans_failed: AtomicU64,
+    traces_created: AtomicU64,
+    http_requests: Family<AtomicU64>,
+    http_duration: Family<Histogram>,
+    proxy_upstream: Family<Histogram>,
+    storage_writes: Family<Histogram>,
+    sse_connections: AtomicU64,
+    sse_subscribers: AtomicU64,
+}
 
-    /// Increment SSE connection count
-    pub fn sse_connect(&self) {
-        self.sse_connections_total.fetch_add(1, Ordering::Relaxed);
-        self.sse_connections_active.fetch_add(1, Ordering::Relaxed);
+impl Metrics {
+    pub fn record_ingest(&self, source: &str, spans: usize) {
+        self.spans_ingested
+            .get(&[("source", source)])
+            .fetch_add(spans as u64, Ordering::Relaxed);
     }
 
-    /// Decrement SSE connection count
-    pub fn sse_disconnect(&self) {
-        self.sse_connections_active.fetch_sub(1, Ordering::Relaxed);
+    pub fn record_span_finished(&self, failed: bool) {
+        let counter = if failed {
+            &self.spans_failed
+        } else {
+            &self.spans_completed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Update span/trace counts
-    pub fn update_counts(&self, spans: u64, traces: u64) {
-        self.span_count.store(spans, Ordering::Relaxed);
-        self.trace_count.store(traces, Ordering::Relaxed);
+    /// Count span completions and trace creations as they are emitted.
+    pub fn record_event(&self, event: &SystemEvent) {
+        match event {
+            SystemEvent::SpanCompleted { .. } => self.record_span_finished(false),
+            SystemEvent::SpanFailed { .. } => self.record_span_finished(true),
+            SystemEvent::TraceCreated { .. } => {
+                self.traces_created.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
     }
 
-    /// Export metrics in Prometheus text format
-    pub fn export_prometheus(&self) -> String {
-        let mut output = String::new();
+    pub fn record_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        let status = status.to_string();
+        self.http_requests
+            .get(&[("method", method), ("route", route), ("status", &status)])
+            .fetch_add(1, Ordering::Relaxed);
+        self.http_duration
+            .get(&[("method", method), ("route", route)])
+            .observe(duration);
+    }
 
-        // Counters
-        output
-            .push_str("# HELP traceway_span_writes_total Total number of span write operations\n");
-        output.push_str("# TYPE traceway_span_writes_total counter\n");
-        output.push_str(&format!(
-            "traceway_span_writes_total {}\n",
-            self.span_writes_total.load(Ordering::Relaxed)
-        ));
+    /// Time until the upstream's response headers, or its failure.
+    pub fn record_upstream(&self, provider: &str, duration: Duration) {
+        self.proxy_upstream
+            .get(&[("provider", provider)])
+            .observe(duration);
+    }
 
-        output.push_str("# HELP traceway_span_reads_total Total number of span read operations\n");
-        output.push_str("# TYPE traceway_span_reads_total counter\n");
-        output.push_str(&format!(
-            "traceway_span_reads_total {}\n",
-            self.span_reads_total.load(Ordering::Relaxed)
-        ));
+    pub fn record_storage_write(&self, op: &str, duration: Duration) {
+        self.storage_writes.get(&[("op", op)]).observe(duration);
+    }
 
-        output.push_str(
-            "# HELP traceway_trace_writes_total Total number of trace write operations\n",
-        );
-        output.push_str("# TYPE traceway_trace_writes_total counter\n");
-        output.push_str(&format!(
-            "traceway_trace_writes_total {}\n",
-            self.trace_writes_total.load(Ordering::Relaxed)
-        ));
-
-        output.push_str("# HELP traceway_api_requests_total Total number of API requests\n");
-        output.push_str("# TYPE traceway_api_requests_total counter\n");
-        output.push_str(&format!(
-            "traceway_api_requests_total {}\n",
-            self.api_requests_total.load(Ordering::Relaxed)
-        ));
-
-        output.push_str("# HELP traceway_api_errors_total Total number of API errors\n");
-        output.push_str("# TYPE traceway_api_errors_total counter\n");
-        output.push_str(&format!(
-            "traceway_api_errors_total {}\n",
-            self.api_errors_total.load(Ordering::Relaxed)
-        ));
-
-        output
-            .push_str("# HELP traceway_sse_connections_total Total SSE connections (cumulative)\n");
-        output.push_str("# TYPE traceway_sse_connections_total counter\n");
-        output.push_str(&format!(
-            "traceway_sse_connections_total {}\n",
-            self.sse_connections_total.load(Ordering::Relaxed)
-        ));
-
-        // Gauges
-        output.push_str("# HELP traceway_sse_connections_active Current active SSE connections\n");
-        output.push_str("# TYPE traceway_sse_connections_active gauge\n");
-        output.push_str(&format!(
-            "traceway_sse_connections_active {}\n",
-            self.sse_connections_active.load(Ordering::Relaxed)
-        ));
-
-        output.push_str("# HELP traceway_span_count Current number of spans in storage\n");
-        output.push_str("# TYPE traceway_span_count gauge\n");
-        output.push_str(&format!(
-            "traceway_span_count {}\n",
-            self.span_count.load(Ordering::Relaxed)
-        ));
-
-        output.push_str("# HELP traceway_trace_count Current number of traces in storage\n");
-        output.push_str("# TYPE traceway_trace_count gauge\n");
-        output.push_str(&format!(
-            "traceway_trace_count {}\n",
-            self.trace_count.load(Ordering::Relaxed)
-        ));
-
-        // Latency summaries
-        let span_write_count = self.span_write_latency_count.load(Ordering::Relaxed);
-        let span_write_sum = self.span_write_latency_sum_us.load(Ordering::Relaxed);
-        let span_write_avg = if span_write_count > 0 {
-            span_write_sum as f64 / span_write_count as f64 / 1000.0 // Convert to ms
-        } else {
-            0.0
+    /// Count an SSE subscriber until the guard is dropped.
+    pub fn sse_connect(&'static self) -> SseGuard {
+        self.sse_connections.fetch_add(1, Ordering::Relaxed);
+        self.sse_subscribers.fetch_add(1, Ordering::Relaxed);
+        SseGuard(self)
+    }
+
+    /// Render every metric, plus the current span and trace counts.
+    pub fn export_prometheus(&self, span_count: u64, trace_count: u64) -> String {
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
         };
 
-        output.push_str(
-            "# HELP traceway_span_write_latency_ms Average span write latency in milliseconds\n",
+        header(
+            &mut out,
+            "traceway_build_info",
+            "gauge",
+            "Build information",
         );
-        output.push_str("# TYPE traceway_span_write_latency_ms gauge\n");
-        output.push_str(&format!(
-            "traceway_span_write_latency_ms {:.3}\n",
-            span_write_avg
-        ));
-
-        let api_count = self.api_latency_count.load(Ordering::Relaxed);
-        let api_sum = self.api_latency_sum_us.load(Ordering::Relaxed);
-        let api_avg = if api_count > 0 {
-            api_sum as f64 / api_count as f64 / 1000.0
-        } else {
-            0.0
-        };
+        let _ = writeln!(
+            out,
+            "traceway_build_info{{version=\"{}\"}} 1",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        header(
+            &mut out,
+            "traceway_spans_ingested_total",
+            "counter",
+            "Spans accepted for storage, by ingest path",
+        );
+        for (labels, n) in self.spans_ingested.snapshot() {
+            let n = n.load(Ordering::Relaxed);
+            let _ = writeln!(out, "traceway_spans_ingested_total{{{labels}}} {n}");
+        }
 
-        output.push_str("# HELP traceway_api_latency_ms Average API latency in milliseconds\n");
-        output.push_str("# TYPE traceway_api_latency_ms gauge\n");
-        output.push_str(&format!("traceway_api_latency_ms {:.3}\n", api_avg));
+        for (name, help, value) in [
+            (
+                "traceway_spans_completed_total",
+                "Spans that completed",
+                &self.spans_completed,
+            ),
+            (
+                "traceway_spans_failed_total",
+                "Spans that failed",
+                &self.spans_failed,
+            ),
+            (
+                "traceway_traces_created_total",
+                "Traces created",
+                &self.traces_created,
+            ),
+            (
+                "traceway_sse_connections_total",
+                "SSE and WebSocket event streams opened",
+                &self.sse_connections,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
 
-        // Error rate
-        let total_requests = self.api_requests_total.load(Ordering::Relaxed);
-        let total_errors = self.api_errors_total.load(Ordering::Relaxed);
-        let error_rate = if total_requests > 0 {
-            total_errors as f64 / total_requests as f64
-        } else {
-            0.0
-        };
+        for (name, help, value) in [
+            (
+                "traceway_sse_subscribers",
+                "Open SSE and WebSocket event streams",
+                self.sse_subscribers.load(Ordering::Relaxed),
+            ),
+            ("traceway_span_count", "Spans held in memory", span_count),
+            ("traceway_trace_count", "Traces held in memory", trace_count),
+        ] {
+            header(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{name} {value}");
+        }
 
-        output.push_str("# HELP traceway_error_rate Current API error rate (0-1)\n");
-        output.push_str("# TYPE traceway_error_rate gauge\n");
-        output.push_str(&format!("traceway_error_rate {:.6}\n", error_rate));
+        header(
+            &mut out,
+            "traceway_http_requests_total",
+            "counter",
+            "API requests by route and status",
+        );
+        for (labels, n) in self.http_requests.snapshot() {
+            let n = n.load(Ordering::Relaxed);
+            let _ = writeln!(out, "traceway_http_requests_total{{{labels}}} {n}");
+        }
 
-        output
+        for (name, help, family) in [
+            (
+                "traceway_http_request_duration_seconds",
+                "API request latency by route",
+                &self.http_duration,
+            ),
+            (
+                "traceway_proxy_upstream_duration_seconds",
+                "Proxy upstream latency until response headers",
+                &self.proxy_upstream,
+            ),
+            (
+                "traceway_storage_write_duration_seconds",
+                "Storage backend write latency by operation",
+                &self.storage_writes,
+            ),
+        ] {
+            header(&mut out, name, "histogram", help);
+            for (labels, histogram) in family.snapshot() {
+                histogram.render(&mut out, name, &labels);
+            }
+        }
+
+        out
+    }
+}
+
+pub struct SseGuard(&'static Metrics);
+
+impl Drop for SseGuard {
+    fn drop(&mut self) {
+        self.0.sse_subscribers.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-/// Timer for measuring operation duration
-pub struct Timer {
-    start: Instant,
+/// Middleware recording each request's status and latency under its route
+/// pattern, e.g. `/api/traces/:id`.
+pub async fn track_requests(matched: Option<MatchedPath>, req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let route = matched.map_or_else(|| "unmatched".to_string(), |m| m.as_str().to_string());
+    let start = Instant::now();
+    let response = next.run(req).await;
+    global().record_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
 }
 
-impl Timer {
-    pub fn start() -> Self {
-        Self {
-            start: Instant::now(),
-        }
+/// Export Turbopuffer write-batching counters in Prometheus text format.
+pub fn export_write_batches(stats: &storage_turbopuffer::BatchStats) -> String {
+    let avg_batch = if stats.flushes > 0 {
+        stats.rows_flushed as f64 / stats.flushes as f64
+    } else {
+        0.0
+    };
+    let avg_flush_ms = if stats.flushes > 0 {
+        stats.flush_ms_total as f64 / stats.flushes as f64
+    } else {
+        0.0
+    };
+
+    let mut output = String::new();
+    for (name, kind, help, value) in [
+        ("traceway_tp_batch_flushes_total", "counter", "Turbopuffer upsert batches sent", stats.flushes.to_string()),
+        ("traceway_tp_batch_rows_total", "counter", "Rows written in Turbopuffer upsert batches", stats.rows_flushed.to_string()),
+        ("traceway_tp_batch_coalesced_rows_total", "counter", "Rows superseded by a later write before flush", stats.rows_coalesced.to_string()),
+        ("traceway_tp_batch_flush_errors_total", "counter", "Failed Turbopuffer batch flushes", stats.flush_errors.to_string()),
+        ("traceway_tp_batch_pending_rows", "gauge", "Rows buffered awaiting flush", stats.pending_rows.to_string()),
+        ("traceway_tp_batch_size_avg", "gauge", "Average rows per Turbopuffer batch", format!("{:.3}", avg_batch)),
+        ("traceway_tp_batch_size_max", "gauge", "Largest Turbopuffer batch", stats.max_batch_rows.to_string()),
+        ("traceway_tp_batch_flush_latency_ms", "gauge", "Average Turbopuffer batch flush latency in milliseconds", format!("{:.3}", avg_flush_ms)),
+        ("traceway_tp_batch_flush_latency_max_ms", "gauge", "Slowest Turbopuffer batch flush in milliseconds", stats.flush_ms_max.to_string()),
+    ] {
+        output.push_str(&format!("# HELP {name} {help}\n"));
+        output.push_str(&format!("# TYPE {name} {kind}\n"));
+        output.push_str(&format!("{name} {value}\n"));
+    }
+    output
+}
+
+/// Export Turbopuffer retry and hedging counters in Prometheus text format.
+pub fn export_retries(stats: &storage_turbopuffer::RetryStats) -> String {
+    let mut output = String::new();
+    for (name, help, value) in [
+        ("traceway_tp_retries_total", "Turbopuffer requests retried after a retryable failure", stats.retries),
+        ("traceway_tp_retry_recovered_total", "Turbopuffer requests that succeeded after retrying", stats.recovered),
+        ("traceway_tp_retry_exhausted_total", "Turbopuffer requests that failed after every retry", stats.exhausted),
+        ("traceway_tp_hedges_total", "Hedged copies sent for slow Turbopuffer queries", stats.hedges),
+        ("traceway_tp_hedge_wins_total", "Turbopuffer queries answered by their hedged copy", stats.hedge_wins),
+    ] {
+        output.push_str(&format!("# HELP {name} {help}\n"));
+        output.push_str(&format!("# TYPE {name} counter\n"));
+        output.push_str(&format!("{name} {value}\n"));
+    }
+    output
+}
+
+pub fn export_mirror_failures(failures: u64) -> String {
+    let name = "traceway_dual_write_mirror_failures_total";
+    let mut output = String::new();
+    output.push_str(&format!("# HELP {name} Writes the dual-write mirror failed and missed\n"));
+    output.push_str(&format!("# TYPE {name} counter\n"));
+    output.push_str(&format!("{name} {failures}\n"));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_labeled_histograms() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/api/traces", 200, Duration::from_millis(30));
+        metrics.record_request("GET", "/api/traces", 500, Duration::from_secs(2));
+        metrics.record_ingest("batch", 3);
+
+        let out = metrics.export_prometheus(0, 0);
+        let route = "method=\"GET\",route=\"/api/traces\"";
+        assert!(out.contains(&format!(
+            "traceway_http_request_duration_seconds_bucket{{{route},le=\"0.05\"}} 1"
+        )));
+        assert!(out.contains(&format!(
+            "traceway_http_request_duration_seconds_bucket{{{route},le=\"+Inf\"}} 2"
+        )));
+        assert!(out.contains(&format!(
+            "traceway_http_requests_total{{{route},status=\"500\"}} 1"
+        )));
+        assert!(out.contains("traceway_spans_ingested_total{source=\"batch\"} 3"));
     }
 
-    pub fn elapsed(&self) -> std::time::Duration {
-        self.start.elapsed()
+    #[test]
+    fn renders_mirror_failures() {
+        let out = export_mirror_failures(4);
+        assert!(out.contains("# TYPE traceway_dual_write_mirror_failures_total counter\n"));
+        assert!(out.ends_with("traceway_dual_write_mirror_failures_total 4\n"));
     }
 }
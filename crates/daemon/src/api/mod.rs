@@ -1,28 +1,82 @@
+pub mod analytics;
+pub mod annotations;
+pub mod anomalies;
 pub mod any_backend;
+pub mod archive;
+pub mod audit;
 pub mod auth_keys;
+pub mod billing;
+pub mod budgets;
 pub mod capture;
+pub mod clear;
+pub mod cors;
+pub mod curation;
+pub mod datasets;
+pub mod dedupe;
+pub mod edits;
+pub mod error;
+pub mod etag;
 pub mod event_log;
+pub mod event_stream;
 pub mod events;
+pub mod export;
+pub mod feedback;
+pub mod files;
+pub mod jobs;
+pub mod llm;
+pub mod machines;
 pub mod metrics;
+pub mod openapi;
 pub mod org_store;
+pub mod orgs;
 pub mod otlp;
+pub mod plan_sim;
+pub mod playground;
+pub mod queue;
+pub mod rate_limit;
+pub mod redaction;
+pub mod replay;
+pub mod reports;
+pub mod retention;
+pub mod sampling;
+pub mod scorers;
+pub mod search;
+pub mod sessions;
+pub mod share;
+pub mod slack;
+pub mod span_kinds;
+pub mod spans;
+pub mod stale;
+pub mod summaries;
+pub mod tags;
+pub mod traces;
+pub mod transfer;
+pub mod trash;
+pub mod views;
+pub mod webhooks;
+pub mod workers;
+pub mod ws;
 
+pub use error::ApiError;
 pub use org_store::OrgStoreManager;
 
 use std::sync::Arc;
 use std::time::Instant;
 
 use axum::{
-    extract::State,
+    extract::{DefaultBodyLimit, State},
     http::{header, StatusCode, Uri},
     response::{Html, IntoResponse, Response},
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{broadcast, watch, RwLock};
-use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use trace::pricing::PricingTable;
 
 pub use any_backend::AnyBackend;
 use trace::{
@@ -38,19 +92,33 @@ pub enum SystemEvent {
     SpanCreated { span: Span },
     SpanCompleted { span: Span },
     SpanFailed { span: Span },
+    /// Partial output of a span whose response is still streaming. Broadcast
+    /// only; deltas are not written to the durable event log.
+    SpanStreaming { span_id: SpanId, delta: String },
     TraceCreated { trace: Trace },
     TraceCompleted { trace: Trace },
     FileVersionCreated { file: FileVersion },
     SpanDeleted { span_id: SpanId },
     TraceDeleted { trace_id: TraceId },
     DatasetCreated { dataset: Dataset },
+    DatasetUpdated { dataset: Dataset },
     DatasetDeleted { dataset_id: DatasetId },
     DatapointCreated { datapoint: Datapoint },
+    DatapointUpdated { datapoint: Datapoint },
     QueueItemUpdated { item: QueueItem },
     EvalRunCreated { run: EvalRun },
     EvalRunUpdated { run: EvalRun },
     EvalRunCompleted { run: EvalRun },
     CaptureRuleFired { rule_id: CaptureRuleId, datapoint: Datapoint },
+    /// Spend reached one of a budget's limits for the first time this month.
+    BudgetAlert {
+        budget: budgets::Budget,
+        limit: budgets::BudgetLimit,
+        spent: f64,
+    },
+    /// A model's or span name's latency or error rate rose well above its
+    /// recent baseline.
+    AnomalyDetected { anomaly: anomalies::Anomaly },
     Cleared,
 }
 
@@ -59,28 +127,47 @@ pub enum SystemEvent {
 #[derive(Clone)]
 pub struct AppState {
     pub org_stores: Arc<OrgStoreManager>,
-    pub events_tx: broadcast::Sender<SystemEvent>,
-    /// Durable event log for SSE replay on reconnect.
-    pub event_log: Arc<dyn events::EventLog>,
+    /// Sequences events and keeps the journal consumers resume from.
+    pub journal: Arc<events::EventJournal>,
     pub start_time: Instant,
     pub config: Arc<RwLock<serde_json::Value>>,
     pub config_path: Arc<String>,
     pub shutdown_tx: Option<watch::Sender<bool>>,
     pub auth_config: auth::AuthConfig,
     pub api_key_lookup: Arc<dyn auth::ApiKeyLookup>,
+    /// Users, orgs, and memberships. Only set in cloud mode with Postgres.
+    pub auth_store: Option<Arc<dyn auth::AuthStore>>,
+    /// Model pricing (built-in defaults + `[pricing]` overrides from config).
+    pub pricing: Arc<RwLock<PricingTable>>,
+    pub retention: Arc<retention::RetentionPolicy>,
+    /// Default window for `POST /api/admin/archive`.
+    pub archive: crate::config::ArchiveConfig,
+    /// Set by `--simulate-plan`; enforces plan limits in local mode.
+    pub plan_sim: Option<Arc<plan_sim::PlanSimulator>>,
+    /// Base URL of the local LLM proxy, for LLM-as-judge scoring.
+    pub proxy_url: Option<String>,
+    /// The running proxy's default capture mode, when a proxy runs here.
+    pub proxy_capture: Option<crate::proxy::SharedCaptureMode>,
+    /// Head-based sampling for batch and OTLP ingest. `None` keeps everything.
+    pub sampler: Option<Arc<sampling::Sampler>>,
+    pub webhooks: Arc<webhooks::WebhookDispatcher>,
+    pub budgets: Arc<budgets::BudgetTracker>,
+    /// Spans stored per org, for billing.
+    pub usage: Arc<billing::UsageMeter>,
+    pub reports: Arc<reports::Reports>,
+    /// Background jobs for the workers.
+    pub jobs: Arc<dyn jobs::JobQueue>,
+    pub slack: Arc<slack::SlackNotifier>,
+    pub playground: Arc<playground::Playground>,
+    pub curation: Arc<curation::Curation>,
+    /// Model behind trace summaries and natural-language search.
+    pub llm: Arc<llm::LlmClient>,
 }
 
 impl AppState {
-    /// Emit a system event: broadcast to live SSE subscribers AND append to durable log.
+    /// Emit a system event: broadcast to live subscribers and journal it.
     pub fn emit_event(&self, event: SystemEvent, org_id: &str) {
-        let _ = self.events_tx.send(event.clone());
-        let log = self.event_log.clone();
-        let org_id = org_id.to_string();
-        tokio::spawn(async move {
-            if let Err(e) = log.append(&org_id, &event).await {
-                tracing::warn!("failed to append event to log: {e}");
-            }
-        });
+        self.journal.emit(org_id, event);
     }
 
     /// Get the store for a given org. Returns `Err((StatusCode, String))` on failure.
@@ -103,20 +190,64 @@ pub use org_store::SharedStore;
 
 // --- Helpers ---
 
-fn require_scope(ctx: &auth::AuthContext, scope: auth::Scope) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+
+/// Upper bound on `limit` for paged list endpoints.
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Events kept by the in-memory journal when there is no durable log.
+const MEMORY_JOURNAL_EVENTS: usize = 10_000;
+
+fn api_error(status: StatusCode, message: impl std::fmt::Display) -> ApiError {
+    ApiError::new(status, message)
+}
+
+fn require_scope(ctx: &auth::AuthContext, scope: auth::Scope) -> Result<(), ApiError> {
     if ctx.has_scope(scope) {
         Ok(())
     } else {
-        Err((
+        Err(api_error(
             StatusCode::FORBIDDEN,
-            Json(serde_json::json!({ "error": format!("insufficient permissions: requires {:?}", scope) })),
-        ))
+            format!("insufficient permissions: requires {:?}", scope),
+        )
+        .with_code("insufficient_scope"))
+    }
+}
+
+/// Allow the span and trace ingest routes, which ingest-only keys may use
+/// with `Scope::Ingest` in place of `TracesWrite`.
+fn require_ingest(ctx: &auth::AuthContext) -> Result<(), ApiError> {
+    if ctx.can_ingest() {
+        Ok(())
+    } else {
+        require_scope(ctx, auth::Scope::TracesWrite)
     }
 }
 
+/// The store of the caller's project.
+pub(crate) async fn project_store(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+) -> Result<SharedStore, ApiError> {
+    state
+        .store_for_project(ctx.org_id, ctx.project_id)
+        .await
+        .map_err(|(status, msg)| api_error(status, msg))
+}
+
+/// The store of the caller's org, for settings kept org-wide.
+pub(crate) async fn org_store(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+) -> Result<SharedStore, ApiError> {
+    state
+        .store_for_org(ctx.org_id)
+        .await
+        .map_err(|(status, msg)| api_error(status, msg))
+}
+
 // --- Health handler ---
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub struct HealthResponse {
     pub status: String,
     pub uptime_secs: u64,
@@ -128,29 +259,72 @@ pub struct HealthResponse {
     pub instance: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub struct StorageHealth {
     pub trace_count: usize,
     pub span_count: usize,
     pub backend: String,
+    /// Whether the connectivity probe succeeded.
+    pub reachable: bool,
+    /// Round trip of the probe.
+    pub latency_ms: f64,
+    /// Stores are still opening and loading their data.
+    pub loading: bool,
+    /// Writes queued on local disk while the backend was failing; `None`
+    /// when spilling is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spill: Option<storage::SpillStatus>,
+    /// Writes the `storage.dual_write` mirror has failed since startup;
+    /// `None` when not dual writing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror_failures: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Probe storage, timing the round trip.
+async fn probe_storage(state: &AppState) -> (Result<(), String>, f64) {
+    let started = Instant::now();
+    let result = state.org_stores.ping().await.map_err(|e| e.to_string());
+    (result, started.elapsed().as_secs_f64() * 1000.0)
 }
 
+/// Uptime, version, and storage status, including a connectivity probe.
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "health",
+    security(()),
+    responses((status = 200, body = HealthResponse))
+)]
 async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
     let uptime = state.start_time.elapsed().as_secs();
+    let (probe, latency_ms) = probe_storage(&state).await;
+    let loading = state.org_stores.is_loading();
+    let spill = state.org_stores.spill_status().await;
     let store = match state.store_for_project(uuid::Uuid::nil(), uuid::Uuid::nil()).await {
         Ok(s) => s,
-        Err(_) => {
+        Err((_, e)) => {
             return Json(HealthResponse {
                 status: "error".to_string(),
                 uptime_secs: uptime,
                 version: env!("CARGO_PKG_VERSION").to_string(),
-                storage: StorageHealth { trace_count: 0, span_count: 0, backend: "unavailable".to_string() },
+                storage: StorageHealth {
+                    trace_count: 0,
+                    span_count: 0,
+                    backend: "unavailable".to_string(),
+                    reachable: probe.is_ok(),
+                    latency_ms,
+                    loading,
+                    spill,
+                    mirror_failures: None,
+                    error: Some(probe.err().unwrap_or(e)),
+                },
                 region: None,
                 instance: None,
             });
         }
     };
-    let r = store.read().await;
 
     let region = std::env::var("FLY_REGION")
         .or_else(|_| std::env::var("RAILWAY_REGION"))
@@ -160,23 +334,62 @@ async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
         .ok();
 
     Json(HealthResponse {
-        status: "ok".to_string(),
+        status: if probe.is_ok() && spill.as_ref().is_none_or(|s| s.pending == 0) {
+            "ok"
+        } else {
+            "degraded"
+        }
+        .to_string(),
         uptime_secs: uptime,
         version: env!("CARGO_PKG_VERSION").to_string(),
         storage: StorageHealth {
-            trace_count: r.trace_count(),
-            span_count: r.span_count(),
-            backend: r.backend_type().to_string(),
+            trace_count: store.trace_count(),
+            span_count: store.span_count(),
+            backend: store.backend_type().to_string(),
+            reachable: probe.is_ok(),
+            latency_ms,
+            loading,
+            spill,
+            mirror_failures: store.backend().mirror_failures(),
+            error: probe.err(),
         },
         region,
         instance,
     })
 }
 
-async fn ready() -> StatusCode {
-    StatusCode::OK
+/// 503 while stores are loading or storage can't be reached.
+#[utoipa::path(
+    get,
+    path = "/api/ready",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "Ready to serve", body = serde_json::Value),
+        (status = 503, description = "Loading or storage unavailable", body = serde_json::Value),
+    )
+)]
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<serde_json::Value>) {
+    if state.org_stores.is_loading() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "loading" })),
+        );
+    }
+    match probe_storage(&state).await {
+        (Ok(()), latency_ms) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "ready", "latency_ms": latency_ms })),
+        ),
+        (Err(e), _) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "unavailable", "error": e })),
+        ),
+    }
 }
 
+/// Always 200 while the process is up.
+#[utoipa::path(get, path = "/api/live", tag = "health", security(()), responses((status = 200)))]
 async fn live() -> StatusCode {
     StatusCode::OK
 }
@@ -192,11 +405,17 @@ async fn prometheus_metrics(State(state): State<AppState>) -> Response {
                 .into_response();
         }
     };
-    let r = store.read().await;
-    let m = metrics::Metrics::new();
-    m.update_counts(r.span_count() as u64, r.trace_count() as u64);
-
-    let body = m.export_prometheus();
+    let mut body =
+        metrics::global().export_prometheus(store.span_count() as u64, store.trace_count() as u64);
+    if let Some(stats) = state.org_stores.write_batch_stats().await {
+        body.push_str(&metrics::export_write_batches(&stats));
+    }
+    if let Some(stats) = state.org_stores.retry_stats().await {
+        body.push_str(&metrics::export_retries(&stats));
+    }
+    if let Some(failures) = store.backend().mirror_failures() {
+        body.push_str(&metrics::export_mirror_failures(failures));
+    }
     (
         [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
         body,
@@ -209,7 +428,7 @@ async fn prometheus_metrics(State(state): State<AppState>) -> Response {
 async fn get_config(
     auth::Auth(ctx): auth::Auth,
     State(state): State<AppState>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+) -> Result<Json<serde_json::Value>, ApiError> {
     require_scope(&ctx, auth::Scope::Admin)?;
     let config = state.config.read().await;
     Ok(Json(config.clone()))
@@ -219,46 +438,122 @@ async fn update_config(
     auth::Auth(ctx): auth::Auth,
     State(state): State<AppState>,
     Json(new_config): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    if !ctx.has_scope(auth::Scope::Admin) {
-        return Err((StatusCode::FORBIDDEN, "insufficient permissions: requires Admin".to_string()));
-    }
+) -> Result<Json<serde_json::Value>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
     let config_path = state.config_path.as_str();
     if config_path.is_empty() {
-        return Err((StatusCode::SERVICE_UNAVAILABLE, "config path not set".to_string()));
+        return Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "config path not set"));
     }
 
-    let toml_str = toml::to_string_pretty(&new_config)
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid config: {}", e)))?;
+    write_config_file(config_path, &new_config)?;
+
+    *state.pricing.write().await = crate::config::PricingConfig::table_from_json(&new_config);
+    let mut config = state.config.write().await;
+    *config = new_config.clone();
+    drop(config);
+    audit::record(&state, &ctx, "config.update", None, serde_json::Value::Null).await;
+
+    tracing::info!("config updated and saved to {}", config_path);
+    Ok(Json(new_config))
+}
+
+/// Write `config` to `config_path` as TOML.
+fn write_config_file(config_path: &str, config: &serde_json::Value) -> Result<(), ApiError> {
+    let toml_str = toml::to_string_pretty(config)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid config: {}", e)))?;
 
     let path = std::path::Path::new(config_path);
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("failed to create config directory: {}", e)))?;
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to create config directory: {}", e)))?;
     }
     std::fs::write(path, &toml_str)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("failed to write config: {}", e)))?;
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to write config: {}", e)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProxyCapture {
+    /// `off`, `full`, `preview`, or `preview:<chars>`.
+    capture_mode: String,
+}
+
+async fn get_proxy_capture(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<ProxyCapture>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let mode = state
+        .proxy_capture
+        .as_ref()
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "no proxy is running"))?;
+    Ok(Json(ProxyCapture {
+        capture_mode: mode.get().to_string(),
+    }))
+}
+
+/// Change the proxy's default capture mode now, and save it to the config
+/// file when there is one. Routes with their own mode keep it.
+async fn set_proxy_capture(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(body): Json<ProxyCapture>,
+) -> Result<Json<ProxyCapture>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let shared = state
+        .proxy_capture
+        .as_ref()
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "no proxy is running"))?;
+    let mode: crate::proxy::CaptureMode = body
+        .capture_mode
+        .parse()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
 
     let mut config = state.config.write().await;
-    *config = new_config.clone();
+    if let Some(obj) = config.as_object_mut() {
+        let proxy = obj.entry("proxy").or_insert_with(|| serde_json::json!({}));
+        if let Some(proxy) = proxy.as_object_mut() {
+            proxy.insert("capture_mode".to_string(), mode.to_string().into());
+        }
+    }
+    if !state.config_path.is_empty() {
+        write_config_file(&state.config_path, &config)?;
+    }
+    shared.set(mode);
+    drop(config);
+    audit::record(
+        &state,
+        &ctx,
+        "proxy.capture_mode.update",
+        None,
+        serde_json::json!({ "capture_mode": mode.to_string() }),
+    )
+    .await;
 
-    tracing::info!("config updated and saved to {}", config_path);
-    Ok(Json(new_config))
+    tracing::info!(%mode, "proxy capture mode changed");
+    Ok(Json(ProxyCapture {
+        capture_mode: mode.to_string(),
+    }))
 }
 
 async fn post_shutdown(
     auth::Auth(ctx): auth::Auth,
     State(state): State<AppState>,
-) -> StatusCode {
-    if !ctx.has_scope(auth::Scope::Admin) {
-        return StatusCode::FORBIDDEN;
-    }
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
     if let Some(ref tx) = state.shutdown_tx {
         tracing::info!("shutdown requested via API");
+        audit::record(
+            &state,
+            &ctx,
+            "daemon.shutdown",
+            None,
+            serde_json::Value::Null,
+        )
+        .await;
         let _ = tx.send(true);
-        StatusCode::ACCEPTED
+        Ok(StatusCode::ACCEPTED)
     } else {
-        StatusCode::SERVICE_UNAVAILABLE
+        Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "shutdown is not available"))
     }
 }
 
@@ -326,6 +621,7 @@ pub fn router(store: SharedStore) -> Router {
 }
 
 /// Builder for creating a router with cloud-aware configuration.
+#[derive(Clone)]
 pub struct RouterBuilder {
     org_stores: Arc<OrgStoreManager>,
     start_time: Instant,
@@ -334,6 +630,24 @@ pub struct RouterBuilder {
     shutdown_tx: Option<watch::Sender<bool>>,
     auth_config: auth::AuthConfig,
     api_key_lookup: Option<Arc<dyn auth::ApiKeyLookup>>,
+    auth_store: Option<Arc<dyn auth::AuthStore>>,
+    events_tx: Option<broadcast::Sender<SystemEvent>>,
+    retention: Option<Arc<retention::RetentionPolicy>>,
+    plan_sim: Option<Arc<plan_sim::PlanSimulator>>,
+    proxy_url: Option<String>,
+    proxy_capture: Option<crate::proxy::SharedCaptureMode>,
+    stale_spans: Option<crate::config::StaleSpansConfig>,
+    archive: Option<crate::config::ArchiveConfig>,
+    queue: Option<crate::config::QueueConfig>,
+    sampling: Option<crate::config::SamplingConfig>,
+    rate_limiter: Option<Arc<rate_limit::RateLimiter>>,
+    event_bus: Option<Arc<dyn events::EventBus>>,
+    budgets: Option<Arc<budgets::BudgetTracker>>,
+    email_sender: Option<Arc<dyn auth::EmailSender>>,
+    job_queue: Option<Arc<dyn jobs::JobQueue>>,
+    workers: Option<crate::config::WorkersConfig>,
+    llm: Option<crate::config::LlmConfig>,
+    pricing: Option<Arc<RwLock<PricingTable>>>,
 }
 
 impl RouterBuilder {
@@ -347,6 +661,24 @@ impl RouterBuilder {
             shutdown_tx: None,
             auth_config: auth::AuthConfig::local(),
             api_key_lookup: None,
+            auth_store: None,
+            events_tx: None,
+            retention: None,
+            plan_sim: None,
+            proxy_url: None,
+            proxy_capture: None,
+            stale_spans: None,
+            archive: None,
+            queue: None,
+            sampling: None,
+            rate_limiter: None,
+            event_bus: None,
+            budgets: None,
+            email_sender: None,
+            job_queue: None,
+            workers: None,
+            llm: None,
+            pricing: None,
         }
     }
 
@@ -360,6 +692,24 @@ impl RouterBuilder {
             shutdown_tx: None,
             auth_config: auth::AuthConfig::local(),
             api_key_lookup: None,
+            auth_store: None,
+            events_tx: None,
+            retention: None,
+            plan_sim: None,
+            proxy_url: None,
+            proxy_capture: None,
+            stale_spans: None,
+            archive: None,
+            queue: None,
+            sampling: None,
+            rate_limiter: None,
+            event_bus: None,
+            budgets: None,
+            email_sender: None,
+            job_queue: None,
+            workers: None,
+            llm: None,
+            pricing: None,
         }
     }
 
@@ -370,17 +720,61 @@ impl RouterBuilder {
     pub fn shutdown_tx(mut self, tx: watch::Sender<bool>) -> Self { self.shutdown_tx = Some(tx); self }
     pub fn auth_config(mut self, c: auth::AuthConfig) -> Self { self.auth_config = c; self }
     pub fn api_key_lookup(mut self, l: Arc<dyn auth::ApiKeyLookup>) -> Self { self.api_key_lookup = Some(l); self }
+    /// Where users and org memberships live. Sessions are checked against
+    /// memberships when set, and org switching needs it.
+    pub fn auth_store(mut self, s: Arc<dyn auth::AuthStore>) -> Self { self.auth_store = Some(s); self }
+    /// Share an event bus with other components (e.g. the proxy). A fresh
+    /// channel is created if unset.
+    pub fn events_tx(mut self, tx: broadcast::Sender<SystemEvent>) -> Self { self.events_tx = Some(tx); self }
+    /// Retention windows for `DELETE /api/admin/prune`. Defaults to
+    /// `RetentionConfig::default()`.
+    pub fn retention(mut self, p: Arc<retention::RetentionPolicy>) -> Self { self.retention = Some(p); self }
+    pub fn plan_sim(mut self, s: Arc<plan_sim::PlanSimulator>) -> Self { self.plan_sim = Some(s); self }
+    pub fn proxy_url(mut self, url: String) -> Self { self.proxy_url = Some(url); self }
+    pub fn proxy_capture(mut self, mode: crate::proxy::SharedCaptureMode) -> Self { self.proxy_capture = Some(mode); self }
+    /// How long spans may run before being failed. Defaults to
+    /// `StaleSpansConfig::default()`.
+    pub fn stale_spans(mut self, c: crate::config::StaleSpansConfig) -> Self { self.stale_spans = Some(c); self }
+    /// Archive old spans in the background when enabled. Stores must be
+    /// opened with a segment store for it to do anything.
+    pub fn archive(mut self, c: crate::config::ArchiveConfig) -> Self { self.archive = Some(c); self }
+    /// When claimed queue items expire. Defaults to `QueueConfig::default()`.
+    pub fn queue(mut self, c: crate::config::QueueConfig) -> Self { self.queue = Some(c); self }
+    /// Span and trace sampling. Everything is kept if unset.
+    pub fn sampling(mut self, c: crate::config::SamplingConfig) -> Self { self.sampling = Some(c); self }
+    /// Rate limit `/api` routes other than health checks, and OTLP ingest.
+    pub fn rate_limiter(mut self, l: Arc<rate_limit::RateLimiter>) -> Self { self.rate_limiter = Some(l); self }
+    /// Share events with other API instances. A `LocalEventBus`, which keeps
+    /// them on this instance, is used if unset.
+    pub fn event_bus(mut self, b: Arc<dyn events::EventBus>) -> Self { self.event_bus = Some(b); self }
+    /// Share budget spend with the proxy. A tracker is created if unset.
+    pub fn budgets(mut self, t: Arc<budgets::BudgetTracker>) -> Self { self.budgets = Some(t); self }
+    /// Delivers scheduled reports. Uses Resend when `RESEND_API_KEY` is set,
+    /// otherwise reports are dropped.
+    pub fn email_sender(mut self, s: Arc<dyn auth::EmailSender>) -> Self { self.email_sender = Some(s); self }
+    /// Where background jobs are queued. Jobs stay in memory on this
+    /// instance if unset.
+    pub fn job_queue(mut self, q: Arc<dyn jobs::JobQueue>) -> Self { self.job_queue = Some(q); self }
+    /// Job workers started by `build_with_workers`. Defaults to
+    /// `WorkersConfig::default()`.
+    pub fn workers(mut self, c: crate::config::WorkersConfig) -> Self { self.workers = Some(c); self }
+    /// The model asked for trace summaries and natural-language search.
+    /// Defaults to `LlmConfig::default()`.
+    pub fn llm(mut self, c: crate::config::LlmConfig) -> Self { self.llm = Some(c); self }
+    /// Share model pricing with the proxy, so pricing changed through
+    /// `PUT /api/config` applies to proxied calls too. Built from `config`
+    /// if unset.
+    pub fn pricing(mut self, p: Arc<RwLock<PricingTable>>) -> Self { self.pricing = Some(p); self }
 
+    /// Build the router. No job workers are started.
     pub fn build(self) -> Router {
-        build_router(
-            self.org_stores,
-            self.start_time,
-            self.config,
-            self.config_path,
-            self.shutdown_tx,
-            self.auth_config,
-            self.api_key_lookup,
-        )
+        build_router(self, false).0
+    }
+
+    /// Build the router and start job workers unless disabled. Shut the
+    /// handle down once the server has stopped so in-flight jobs drain.
+    pub fn build_with_workers(self) -> (Router, Option<workers::WorkerHandle>) {
+        build_router(self, true)
     }
 }
 
@@ -391,23 +785,52 @@ pub fn router_with_start_time(
     config_path: String,
     shutdown_tx: Option<watch::Sender<bool>>,
 ) -> Router {
-    let org_stores = Arc::new(OrgStoreManager::single(store));
-    build_router(org_stores, start_time, config, config_path, shutdown_tx, auth::AuthConfig::local(), None)
+    let mut builder = RouterBuilder::new(store)
+        .start_time(start_time)
+        .config(config)
+        .config_path(config_path);
+    builder.shutdown_tx = shutdown_tx;
+    builder.build()
 }
 
 fn build_router(
-    org_stores: Arc<OrgStoreManager>,
-    start_time: Instant,
-    config: serde_json::Value,
-    config_path: String,
-    shutdown_tx: Option<watch::Sender<bool>>,
-    auth_config: auth::AuthConfig,
-    api_key_lookup: Option<Arc<dyn auth::ApiKeyLookup>>,
-) -> Router {
-    let (events_tx, _) = broadcast::channel(256);
+    builder: RouterBuilder,
+    start_workers: bool,
+) -> (Router, Option<workers::WorkerHandle>) {
+    let RouterBuilder {
+        org_stores,
+        start_time,
+        config,
+        config_path,
+        shutdown_tx,
+        auth_config,
+        api_key_lookup,
+        auth_store,
+        events_tx,
+        retention,
+        plan_sim,
+        proxy_url,
+        proxy_capture,
+        stale_spans,
+        archive,
+        queue,
+        sampling,
+        rate_limiter,
+        event_bus,
+        budgets,
+        email_sender,
+        job_queue,
+        workers,
+        llm,
+        pricing,
+    } = builder;
+    let events_tx = events_tx.unwrap_or_else(|| broadcast::channel(256).0);
+    let retention = retention.unwrap_or_else(|| {
+        Arc::new(retention::RetentionPolicy::new(Default::default()))
+    });
 
     // Create durable event log. In local mode, use SQLite alongside the config.
-    // In cloud mode, fall back to NoopEventLog (events are ephemeral via Redis Pub/Sub).
+    // In cloud mode, keep a short in-memory journal (events fan out over `event_bus`).
     let event_log: Arc<dyn events::EventLog> = if auth_config.local_mode {
         let log_path = if config_path.is_empty() {
             std::path::PathBuf::from("data/event_log.db")
@@ -423,92 +846,337 @@ fn build_router(
                 log
             }
             Err(e) => {
-                tracing::warn!("failed to open event log at {}: {e}, using noop", log_path.display());
-                Arc::new(events::NoopEventLog)
+                tracing::warn!("failed to open event log at {}: {e}, using in-memory journal", log_path.display());
+                Arc::new(events::MemoryEventLog::new(MEMORY_JOURNAL_EVENTS))
             }
         }
     } else {
-        Arc::new(events::NoopEventLog)
+        let log = Arc::new(events::MemoryEventLog::new(MEMORY_JOURNAL_EVENTS));
+        event_log::spawn_event_log_trimmer(log.clone(), std::time::Duration::from_secs(3600));
+        log
     };
+    let event_bus = event_bus.unwrap_or_else(|| Arc::new(events::LocalEventBus::default()));
+    let journal = events::EventJournal::spawn(event_log, events_tx, event_bus);
+    let webhooks = webhooks::WebhookDispatcher::new(org_stores.clone(), auth_config.local_mode);
+    webhooks.clone().spawn(journal.subscribe_local());
+    let stale_spans = stale_spans.unwrap_or_default();
+    if stale_spans.enabled {
+        stale::spawn_stale_span_sweeper(org_stores.clone(), Arc::downgrade(&journal), stale_spans);
+    }
+    let archive = archive.unwrap_or_default();
+    if archive.enabled {
+        archive::spawn_archive_task(
+            org_stores.clone(),
+            Arc::downgrade(&journal),
+            archive.clone(),
+        );
+    }
+    let queue = queue.unwrap_or_default();
+    if queue.claim_expiry {
+        queue::spawn_claim_expiry(org_stores.clone(), Arc::downgrade(&journal), queue);
+    }
+    let budgets = budgets.unwrap_or_else(|| budgets::BudgetTracker::new(org_stores.clone()));
+    budgets::spawn_budget_refresher(budgets.clone(), Arc::downgrade(&journal));
+    anomalies::spawn_anomaly_detector(
+        anomalies::AnomalyDetector::new(org_stores.clone()),
+        Arc::downgrade(&journal),
+    );
+    let usage = billing::UsageMeter::new(org_stores.clone());
+    billing::spawn_usage_flusher(Arc::downgrade(&usage));
+    let email_sender = email_sender.unwrap_or_else(|| match auth::ResendSender::from_env() {
+        Ok(sender) => Arc::new(sender),
+        Err(_) => Arc::new(auth::NoopEmailSender),
+    });
+    let slack = slack::SlackNotifier::new(org_stores.clone());
+    slack.clone().spawn(journal.subscribe_local());
+    let reports = reports::Reports::new(org_stores.clone(), email_sender, slack.clone());
+    let jobs = job_queue.unwrap_or_else(|| Arc::new(jobs::MemoryJobQueue::new()));
+    let workers = workers.unwrap_or_default();
+    let run_workers = start_workers && workers.enabled;
+    reports::spawn_report_scheduler(
+        reports.clone(),
+        Arc::downgrade(&journal),
+        run_workers.then(|| jobs.clone()),
+    );
+    let curation = curation::Curation::new(org_stores.clone());
+    curation::spawn_curation_scheduler(curation.clone(), Arc::downgrade(&journal));
+    trash::spawn_trash_purger(org_stores.clone(), Arc::downgrade(&journal));
+    let sampler = sampling.and_then(sampling::Sampler::new);
+    if let Some(sampler) = sampler.as_ref().filter(|s| s.tail_enabled()) {
+        sampling::spawn_tail_sampler(
+            org_stores.clone(),
+            Arc::downgrade(&journal),
+            sampler.clone(),
+        );
+    }
 
     let api_key_lookup: Arc<dyn auth::ApiKeyLookup> = api_key_lookup.unwrap_or_else(|| {
         Arc::new(auth_keys::NoopApiKeyLookup) as Arc<dyn auth::ApiKeyLookup>
     });
 
+    let pricing = pricing.unwrap_or_else(|| {
+        Arc::new(RwLock::new(crate::config::PricingConfig::table_from_json(&config)))
+    });
+
     let state = AppState {
         org_stores,
-        events_tx,
-        event_log,
+        journal,
         start_time,
+        pricing,
         config: Arc::new(RwLock::new(config)),
         config_path: Arc::new(config_path),
         shutdown_tx,
         auth_config: auth_config.clone(),
         api_key_lookup,
+        auth_store,
+        retention,
+        archive,
+        plan_sim,
+        proxy_url,
+        proxy_capture,
+        sampler,
+        webhooks,
+        budgets,
+        usage,
+        reports,
+        jobs,
+        slack,
+        playground: playground::Playground::new(),
+        curation,
+        llm: llm::LlmClient::new(llm.unwrap_or_default()),
     };
 
-    // In cloud mode with a separate frontend origin, we need explicit origins
-    // and credentials support. ALLOWED_ORIGINS env var is comma-separated.
-    // In local mode (no env var), allow any origin without credentials.
-    let cors = if let Ok(origins) = std::env::var("ALLOWED_ORIGINS") {
-        let origins: Vec<axum::http::HeaderValue> = origins
-            .split(',')
-            .filter_map(|s| {
-                let trimmed = s.trim();
-                match trimmed.parse::<axum::http::HeaderValue>() {
-                    Ok(v) => {
-                        tracing::info!(origin = trimmed, "CORS: allowing origin");
-                        Some(v)
-                    }
-                    Err(e) => {
-                        tracing::warn!(origin = trimmed, error = %e, "CORS: failed to parse origin, skipping");
-                        None
-                    }
-                }
-            })
-            .collect();
-        if origins.is_empty() {
-            tracing::warn!("CORS: ALLOWED_ORIGINS set but no valid origins parsed, falling back to permissive");
-        }
-        CorsLayer::new()
-            .allow_origin(AllowOrigin::list(origins))
-            .allow_methods([
-                axum::http::Method::GET,
-                axum::http::Method::POST,
-                axum::http::Method::PUT,
-                axum::http::Method::DELETE,
-                axum::http::Method::OPTIONS,
-                axum::http::Method::PATCH,
-            ])
-            .allow_headers([
-                header::CONTENT_TYPE,
-                header::AUTHORIZATION,
-                header::ACCEPT,
-                header::ORIGIN,
-                header::COOKIE,
-            ])
-            .allow_credentials(true)
-    } else {
-        CorsLayer::new()
-            .allow_origin(Any)
-            .allow_methods(Any)
-            .allow_headers(Any)
-    };
+    let cors = cors::layer(std::env::var("ALLOWED_ORIGINS").ok().as_deref());
 
     // Rust API is now ingest/infra-only. Public product APIs moved to Encore.
     let public = Router::new()
         .route("/health", get(health))
         .route("/ready", get(ready))
         .route("/live", get(live))
-        .route("/metrics", get(prometheus_metrics))
+        .route("/openapi.json", get(openapi::openapi_spec))
+        .route("/docs", get(openapi::docs))
+        .route("/metrics", get(prometheus_metrics));
+
+    let protected = Router::new()
         .route("/config", get(get_config).put(update_config))
-        .route("/shutdown", post(post_shutdown));
+        .route(
+            "/config/proxy/capture",
+            get(get_proxy_capture).put(set_proxy_capture),
+        )
+        .route("/shutdown", post(post_shutdown))
+        .route("/events", get(event_stream::stream_events))
+        .route("/events/poll", get(event_stream::poll_events))
+        .route("/ws", get(ws::ws_events))
+        .route("/spans", get(spans::list_spans))
+        // SDKs may send large batches with `Content-Encoding: gzip` or `zstd`
+        .route(
+            "/spans/batch",
+            post(spans::create_spans_batch).layer(RequestDecompressionLayer::new()),
+        )
+        .route("/spans/diff", get(spans::diff_spans))
+        .route("/spans/:id/complete", post(spans::complete_span))
+        .route("/spans/:id/payload", get(spans::get_payload))
+        .route("/spans/:id/replay", post(replay::replay_span))
+        .route("/spans/:id/annotate", post(annotations::annotate_span))
+        .route("/spans/:id/annotations", get(annotations::span_annotations))
+        .route("/annotations", get(annotations::list_annotations))
+        .route("/annotations/:id", delete(annotations::delete_annotation))
+        .route(
+            "/playground/runs",
+            get(playground::list_runs).post(playground::create_run),
+        )
+        .route("/playground/runs/:id", get(playground::get_run))
+        .route("/playground/runs/:id/compare", get(playground::compare_run))
+        .route("/export", get(export::export))
+        .route("/export/archive", get(transfer::export_archive))
+        // Archives may be sent with `Content-Encoding: gzip` or `zstd`
+        .route(
+            "/import/archive",
+            post(transfer::import_archive).layer((
+                RequestDecompressionLayer::new(),
+                DefaultBodyLimit::max(transfer::MAX_IMPORT_BYTES),
+            )),
+        )
+        .route("/files/content/:hash", get(files::get_content))
+        .route("/queue/stats", get(queue::labeler_stats))
+        .route("/queue/:id/claim", post(queue::claim_item))
+        .route("/queue/:id/submit", post(queue::submit_item))
+        .route("/queue/:id/release", post(queue::release_item))
+        .route("/queue/:id/reject", post(queue::reject_item))
+        .route("/queue/adjudication", get(queue::adjudication_queue))
+        .route("/queue/:id/reviewers", post(queue::assign_reviewers))
+        .route(
+            "/queue/:id/submissions",
+            get(queue::list_submissions).post(queue::submit_review),
+        )
+        .route("/queue/:id/adjudicate", post(queue::adjudicate_item))
+        .route("/traces", get(traces::list_traces))
+        .route("/sessions", get(sessions::list_sessions))
+        .route("/sessions/:id/traces", get(sessions::session_traces))
+        .route("/traces/facets", get(traces::trace_facets))
+        .route(
+            "/traces/:id",
+            get(traces::get_trace)
+                .put(traces::put_trace)
+                .delete(trash::delete_trace),
+        )
+        .route("/traces/:id/tree", get(traces::trace_tree))
+        .route("/traces/:id/stats", get(traces::trace_stats))
+        .route("/traces/:id/summarize", post(summaries::summarize_trace))
+        .route("/traces/:id/complete", post(traces::complete_trace))
+        .route("/traces/:id/share", post(share::share_trace))
+        .route("/traces/:id/tags", post(tags::add_tags))
+        .route("/traces/:id/tags/:tag", delete(tags::remove_tag))
+        .route("/tags", get(tags::list_tags))
+        .route("/tags/:tag/rename", post(tags::rename_tag))
+        .route("/trash", get(trash::list_trash))
+        .route("/trash/:id/restore", post(trash::restore))
+        .route("/views", get(views::list_views).post(views::create_view))
+        .route(
+            "/views/:id",
+            get(views::get_view)
+                .put(views::update_view)
+                .delete(views::delete_view),
+        )
+        .route("/views/:id/spans", get(views::apply_view))
+        .route("/auth/orgs", get(orgs::list_orgs))
+        .route("/auth/switch-org", post(orgs::switch_org))
+        .route("/auth/invites/accept", post(orgs::accept_invite))
+        .route("/org/members/:id", delete(orgs::remove_member))
+        .route("/org/members/:id/role", put(orgs::set_member_role))
+        .route(
+            "/org/span-kinds",
+            get(span_kinds::list_span_kinds).post(span_kinds::register_span_kind),
+        )
+        .route("/org/span-kinds/:name", delete(span_kinds::delete_span_kind))
+        .route("/org/audit-log", get(audit::list_audit_log))
+        .route("/org/billing/usage", get(billing::get_usage))
+        .route(
+            "/org/redaction",
+            get(redaction::get_redaction).put(redaction::set_redaction),
+        )
+        .route(
+            "/machines",
+            get(machines::list_machines).post(machines::register_machine),
+        )
+        .route("/machines/:id", delete(machines::delete_machine))
+        .route(
+            "/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::create_webhook),
+        )
+        .route("/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/webhooks/:id/deliveries", get(webhooks::list_deliveries))
+        .route(
+            "/budgets",
+            get(budgets::list_budgets).post(budgets::create_budget),
+        )
+        .route("/budgets/:id", delete(budgets::delete_budget))
+        .route("/anomalies", get(anomalies::list_anomalies))
+        .route(
+            "/reports/schedules",
+            get(reports::list_schedules).post(reports::create_schedule),
+        )
+        .route("/reports/schedules/:id", delete(reports::delete_schedule))
+        .route("/reports/preview", get(reports::preview_report))
+        .route(
+            "/integrations/slack",
+            get(slack::get_slack)
+                .put(slack::put_slack)
+                .delete(slack::delete_slack),
+        )
+        .route("/integrations/slack/test", post(slack::test_slack))
+        .route(
+            "/curation/rules",
+            get(curation::list_rules).post(curation::create_rule),
+        )
+        .route("/curation/rules/:id", delete(curation::delete_rule))
+        .route("/curation/rules/:id/run", post(curation::run_rule))
+        .route(
+            "/datasets/:id",
+            get(edits::get_dataset)
+                .patch(edits::update_dataset)
+                .delete(trash::delete_dataset),
+        )
+        .route("/datasets/:id/datapoints", patch(edits::update_datapoints))
+        .route(
+            "/datapoints/:id",
+            get(edits::get_datapoint).patch(edits::update_datapoint),
+        )
+        .route("/datasets/:id/split", post(datasets::split_dataset))
+        .route("/datasets/:id/sample", post(datasets::sample_dataset))
+        .route("/datasets/:id/dedupe", post(dedupe::dedupe_dataset))
+        .route("/datasets/:id/score", post(scorers::score_dataset))
+        .route("/eval/runs/:id/score", post(scorers::score_run))
+        .route("/search/semantic", post(search::semantic_search))
+        .route("/search/nl", post(search::nl_search))
+        .route("/analytics", post(analytics::query_analytics))
+        .route("/analytics/summary", get(analytics::summary))
+        .route("/analytics/concurrency", post(analytics::concurrency))
+        .route("/analytics/compare", post(analytics::compare))
+        .route("/analytics/timeseries", post(analytics::timeseries))
+        .route("/analytics/by-commit", get(analytics::by_commit))
+        .route("/admin/prune", delete(retention::prune))
+        .route("/admin/gc", post(retention::gc))
+        .route("/admin/archive", post(archive::archive))
+        .route("/admin/archive/segments", get(archive::list_segments))
+        .route("/admin/analytics/rebuild", post(analytics::rebuild))
+        .route("/admin/clear", delete(clear::clear))
+        .route("/jobs", get(jobs::list_jobs))
+        .route("/jobs/dead-letter", get(jobs::list_dead_letter))
+        .route("/jobs/dead-letter/requeue", post(jobs::requeue_dead_letter))
+        .route("/jobs/:id", get(jobs::get_job))
+        .route("/jobs/:id/stacktrace", get(jobs::job_stacktrace))
+        .route("/jobs/:id/retry", post(jobs::retry_job))
+        .route("/jobs/:id/cancel", post(jobs::cancel_job))
+        .route("/plan", get(plan_sim::get_plan))
+        .route("/plan/usage", put(plan_sim::set_usage))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth_keys::require_auth));
 
-    let api = Router::new().merge(public);
+    // Authenticated by a feedback or share token, or by the handler
+    let by_token = Router::new()
+        .route("/feedback", post(feedback::submit_feedback))
+        .route("/shared/:token", get(share::get_shared_trace))
+        .route(
+            "/shared/:token/spans/:id/payload",
+            get(share::get_shared_payload),
+        )
+        .route("/shared/:token/files/:hash", get(share::get_shared_file));
 
     // OTLP ingest routes — outside /api, with self-contained auth.
     let otlp = Router::new()
-        .route("/v1/traces", post(otlp::ingest_traces));
+        .route(
+            "/v1/traces",
+            post(otlp::ingest_traces).layer(RequestDecompressionLayer::new()),
+        );
+
+    // Outermost, so requests are limited before they're authenticated
+    let (protected, by_token, otlp) = match rate_limiter {
+        Some(limiter) => {
+            let limiter = (state.clone(), limiter);
+            (
+                protected.route_layer(middleware::from_fn_with_state(
+                    limiter.clone(),
+                    rate_limit::limit,
+                )),
+                by_token.route_layer(middleware::from_fn_with_state(
+                    limiter.clone(),
+                    rate_limit::limit,
+                )),
+                otlp.route_layer(middleware::from_fn_with_state(limiter, rate_limit::limit)),
+            )
+        }
+        None => (protected, by_token, otlp),
+    };
+
+    let api = Router::new()
+        .merge(public)
+        .merge(protected)
+        .merge(by_token)
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::map_response(error::problem_fallback));
+    let otlp = otlp
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::map_response(error::problem_fallback));
 
     let app = Router::new()
         .nest("/api", api)
@@ -519,34 +1187,39 @@ fn build_router(
         app.fallback(serve_ui)
     } else {
         app.route("/", get(serve_scalar_docs))
-            .fallback(|| async { StatusCode::NOT_FOUND })
+            .fallback(|| async { api_error(StatusCode::NOT_FOUND, "no such route") })
     };
 
-    app.layer(cors)
-        .with_state(state)
+    let worker = run_workers.then(|| workers::spawn_workers(&state, workers));
+
+    // Compression skips event streams and small bodies
+    let app = app
+        .layer(CompressionLayer::new())
+        .layer(cors)
+        .with_state(state);
+    (app, worker)
 }
 
 // --- Server ---
 
 pub async fn serve(store: SharedStore, addr: &str) -> std::io::Result<()> {
-    let org_stores = Arc::new(OrgStoreManager::single(store));
-    serve_with_shutdown(org_stores, addr, Instant::now(), serde_json::Value::Object(Default::default()), String::new(), None, std::future::pending()).await
+    serve_with_shutdown(RouterBuilder::new(store), addr, std::future::pending()).await
 }
 
 pub async fn serve_with_shutdown(
-    org_stores: Arc<OrgStoreManager>,
+    builder: RouterBuilder,
     addr: &str,
-    start_time: Instant,
-    config: serde_json::Value,
-    config_path: String,
-    shutdown_tx: Option<watch::Sender<bool>>,
     shutdown: impl std::future::Future<Output = ()> + Send + 'static,
 ) -> std::io::Result<()> {
-    let app = build_router(org_stores, start_time, config, config_path, shutdown_tx, auth::AuthConfig::local(), None);
     let listener = tokio::net::TcpListener::bind(addr).await?;
+    let (app, workers) = builder.build_with_workers();
     tracing::info!("api listening on {}", addr);
-    axum::serve(listener, app)
+    let served = axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
         .with_graceful_shutdown(shutdown)
         .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        .map_err(std::io::Error::other);
+    if let Some(workers) = workers {
+        workers.shutdown().await;
+    }
+    served
 }
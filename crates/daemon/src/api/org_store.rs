@@ -7,16 +7,21 @@
 //! In local mode, a single shared store is used (no isolation needed).
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use auth::{OrgId, ProjectId};
-use storage::PersistentStore;
-use tokio::sync::RwLock;
-use tracing::{info, error};
+use storage::{
+    NameNormalizer, PayloadOffloadConfig, PersistentStore, RedactionConfig, Redactor, SpillConfig,
+    SpillStatus, StorageBackend, StorageError, WriteBehindConfig,
+};
+use tokio::sync::{OnceCell, RwLock};
+use tracing::{info, error, warn};
 
+use super::redaction::REDACTION_SETTING;
 use super::AnyBackend;
 
-pub type SharedStore = Arc<RwLock<PersistentStore<AnyBackend>>>;
+pub type SharedStore = Arc<PersistentStore<AnyBackend>>;
 
 /// Composite key for per-project store lookup.
 type StoreKey = (OrgId, ProjectId);
@@ -28,6 +33,39 @@ type StoreKey = (OrgId, ProjectId);
 ///   each with its own Turbopuffer namespace prefix (`tw_{org_short}_{project_short}`).
 pub struct OrgStoreManager {
     mode: StoreMode,
+    /// Applied to per-project stores as they are opened.
+    write_behind: Option<WriteBehindConfig>,
+    /// Spill queue settings; each per-project store spills to a
+    /// subdirectory named after its namespace.
+    spill: Option<SpillConfig>,
+    /// Open per-project stores with `PersistentStore::open_lazy`.
+    lazy_load: bool,
+    /// Span name rules applied by per-project stores.
+    names: Option<Arc<NameNormalizer>>,
+    /// Payload offloading in per-project stores.
+    payloads: Option<PayloadOffloadConfig>,
+    /// Redaction for orgs that haven't saved their own settings.
+    redaction: RedactionConfig,
+    /// Effective redaction settings per org, loaded on first store open.
+    org_redaction: RwLock<HashMap<OrgId, RedactionConfig>>,
+    /// Stores being opened, which includes their initial load.
+    opening: AtomicUsize,
+}
+
+/// Counts a store as opening until dropped.
+struct Opening<'a>(&'a AtomicUsize);
+
+impl<'a> Opening<'a> {
+    fn start(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for Opening<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 enum StoreMode {
@@ -39,7 +77,9 @@ enum StoreMode {
         /// Cache of (org_id, project_id) -> store. Lazily populated on first access.
         stores: RwLock<HashMap<StoreKey, SharedStore>>,
         /// Base Turbopuffer config to derive per-project configs from.
-        base_config: storage_turbopuffer::TurbopufferConfig,
+        base_config: Box<storage_turbopuffer::TurbopufferConfig>,
+        /// Backend on the base namespace for health probes.
+        probe: OnceCell<Box<storage_turbopuffer::TurbopufferBackend>>,
     },
 }
 
@@ -48,6 +88,14 @@ impl OrgStoreManager {
     pub fn single(store: SharedStore) -> Self {
         Self {
             mode: StoreMode::Single(store),
+            write_behind: None,
+            spill: None,
+            lazy_load: false,
+            names: None,
+            payloads: None,
+            redaction: RedactionConfig::default(),
+            org_redaction: RwLock::new(HashMap::new()),
+            opening: AtomicUsize::new(0),
         }
     }
 
@@ -56,8 +104,127 @@ impl OrgStoreManager {
         Self {
             mode: StoreMode::PerProject {
                 stores: RwLock::new(HashMap::new()),
-                base_config,
+                base_config: Box::new(base_config),
+                probe: OnceCell::new(),
             },
+            write_behind: None,
+            spill: None,
+            lazy_load: false,
+            names: None,
+            payloads: None,
+            redaction: RedactionConfig::default(),
+            org_redaction: RwLock::new(HashMap::new()),
+            opening: AtomicUsize::new(0),
+        }
+    }
+
+    /// Buffer writes in per-project stores opened from now on.
+    pub fn with_write_behind(mut self, config: Option<WriteBehindConfig>) -> Self {
+        self.write_behind = config;
+        self
+    }
+
+    /// Spill failed writes of per-project stores opened from now on.
+    pub fn with_spill(mut self, config: Option<SpillConfig>) -> Self {
+        self.spill = config;
+        self
+    }
+
+    /// Open per-project stores without loading their data up front.
+    pub fn with_lazy_load(mut self, lazy: bool) -> Self {
+        self.lazy_load = lazy;
+        self
+    }
+
+    /// Normalize span names in per-project stores opened from now on.
+    pub fn with_name_normalizer(mut self, names: Option<Arc<NameNormalizer>>) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Offload large payloads in per-project stores opened from now on.
+    pub fn with_payload_offload(mut self, config: Option<PayloadOffloadConfig>) -> Self {
+        self.payloads = config;
+        self
+    }
+
+    /// Redact payloads with `config` unless an org saves its own settings.
+    /// Must be valid; compile it with `Redactor::new` first.
+    pub fn with_redaction(self, config: RedactionConfig) -> Self {
+        if let StoreMode::Single(store) = &self.mode {
+            store.set_redactor(compile_redaction(&config));
+        }
+        Self {
+            redaction: config,
+            ..self
+        }
+    }
+
+    /// Apply settings saved in the single store. Per-project stores load
+    /// theirs as they open.
+    pub async fn load_saved_redaction(&self) {
+        if let StoreMode::Single(store) = &self.mode {
+            let config = self.org_redaction_from(uuid::Uuid::nil(), store).await;
+            store.set_redactor(compile_redaction(&config));
+        }
+    }
+
+    /// Every org shares the single store, so they share its settings too.
+    fn redaction_key(&self, org_id: OrgId) -> OrgId {
+        match self.mode {
+            StoreMode::Single(_) => uuid::Uuid::nil(),
+            StoreMode::PerProject { .. } => org_id,
+        }
+    }
+
+    /// The org's effective redaction settings.
+    pub async fn redaction_for(&self, org_id: OrgId) -> Result<RedactionConfig, String> {
+        let key = self.redaction_key(org_id);
+        if let Some(config) = self.org_redaction.read().await.get(&key) {
+            return Ok(config.clone());
+        }
+        let org_store = self.get(org_id).await?;
+        Ok(self.org_redaction_from(key, &org_store).await)
+    }
+
+    /// Cache `org_id`'s settings, reading them from `org_store` (the org's
+    /// nil-project store) if not yet cached.
+    async fn org_redaction_from(
+        &self,
+        org_id: OrgId,
+        org_store: &PersistentStore<AnyBackend>,
+    ) -> RedactionConfig {
+        if let Some(config) = self.org_redaction.read().await.get(&org_id) {
+            return config.clone();
+        }
+        let config = match org_store.get_setting(REDACTION_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).unwrap_or_else(|e| {
+                warn!(%org_id, "ignoring invalid saved redaction settings: {e}");
+                self.redaction.clone()
+            }),
+            Ok(None) => self.redaction.clone(),
+            Err(e) => {
+                warn!(%org_id, "failed to load redaction settings: {e}");
+                self.redaction.clone()
+            }
+        };
+        self.org_redaction
+            .write()
+            .await
+            .insert(org_id, config.clone());
+        config
+    }
+
+    /// Use `config` for the org's open stores and any opened later. The
+    /// caller saves it.
+    pub async fn set_org_redaction(&self, org_id: OrgId, config: RedactionConfig) {
+        let redactor = compile_redaction(&config);
+        self.org_redaction
+            .write()
+            .await
+            .insert(self.redaction_key(org_id), config);
+        for store in self.cached_stores_for_org(org_id).await {
+            store.set_redactor(redactor.clone());
         }
     }
 
@@ -80,7 +247,9 @@ impl OrgStoreManager {
         match &self.mode {
             StoreMode::Single(store) => Ok(store.clone()),
 
-            StoreMode::PerProject { stores, base_config } => {
+            StoreMode::PerProject {
+                stores, base_config, ..
+            } => {
                 let key = (org_id, project_id);
 
                 // Fast path: check if already cached
@@ -96,7 +265,7 @@ impl OrgStoreManager {
                 let project_short = &project_id.to_string()[..8];
                 let namespace = format!("tw_{}_{}", org_short, project_short);
 
-                let project_config = base_config.clone().with_namespace(&namespace);
+                let project_config = base_config.as_ref().clone().with_namespace(&namespace);
                 info!(
                     org_id = %org_id,
                     project_id = %project_id,
@@ -106,15 +275,47 @@ impl OrgStoreManager {
 
                 let backend = storage_turbopuffer::TurbopufferBackend::new(project_config)
                     .map_err(|e| format!("Failed to create Turbopuffer backend for project {}: {}", project_id, e))?;
+                // Writes declare the schema themselves, so a failed check
+                // only leaves index setting changes unapplied
+                if let Err(e) = backend.ensure_schemas().await {
+                    warn!(namespace = %namespace, error = %e, "Failed to verify Turbopuffer schemas");
+                }
 
-                let persistent = PersistentStore::open(AnyBackend::Turbopuffer(backend))
-                    .await
+                let backend = AnyBackend::Turbopuffer(backend);
+                let _opening = Opening::start(&self.opening);
+                let opened = if self.lazy_load {
+                    PersistentStore::open_lazy(backend).await
+                } else {
+                    PersistentStore::open(backend).await
+                };
+                let mut persistent = opened
                     .map_err(|e| {
                         error!(org_id = %org_id, project_id = %project_id, error = %e, "Failed to open store for project");
                         format!("Failed to open store for project {}: {}", project_id, e)
                     })?;
+                if let Some(spill) = &self.spill {
+                    persistent = persistent
+                        .with_spill(&spill.nested(&namespace))
+                        .map_err(|e| format!("Failed to open spill queue: {}", e))?;
+                }
+                if let Some(config) = self.write_behind {
+                    persistent = persistent.with_write_behind(config);
+                }
+                if let Some(names) = &self.names {
+                    persistent = persistent.with_name_normalizer(names.clone());
+                }
+                if let Some(config) = self.payloads {
+                    persistent = persistent.with_payload_offload(config);
+                }
+                // The org's settings live in its nil-project store
+                let redaction = if project_id.is_nil() {
+                    self.org_redaction_from(org_id, &persistent).await
+                } else {
+                    Box::pin(self.redaction_for(org_id)).await?
+                };
+                persistent = persistent.with_redactor(compile_redaction(&redaction));
 
-                let store: SharedStore = Arc::new(RwLock::new(persistent));
+                let store: SharedStore = Arc::new(persistent);
 
                 // Cache it
                 let mut cache = stores.write().await;
@@ -125,6 +326,30 @@ impl OrgStoreManager {
         }
     }
 
+    /// Whether a store is still opening, e.g. loading its data.
+    pub fn is_loading(&self) -> bool {
+        self.opening.load(Ordering::SeqCst) > 0
+    }
+
+    /// Check that storage is reachable. In per-project mode this probes
+    /// Turbopuffer directly, so no project's store has to be open.
+    pub async fn ping(&self) -> Result<(), StorageError> {
+        match &self.mode {
+            StoreMode::Single(store) => store.ping().await,
+            StoreMode::PerProject {
+                base_config, probe, ..
+            } => {
+                let backend = probe
+                    .get_or_try_init(|| async {
+                        storage_turbopuffer::TurbopufferBackend::new(base_config.as_ref().clone())
+                            .map(Box::new)
+                    })
+                    .await?;
+                backend.ping().await
+            }
+        }
+    }
+
     /// Check if this manager is in per-org/per-project mode.
     pub fn is_per_org(&self) -> bool {
         matches!(self.mode, StoreMode::PerProject { .. })
@@ -142,6 +367,95 @@ impl OrgStoreManager {
         }
     }
 
+    /// Every open store with the org it belongs to. In single mode the one
+    /// store is returned under the nil org.
+    pub async fn all_stores(&self) -> Vec<(OrgId, SharedStore)> {
+        match &self.mode {
+            StoreMode::Single(store) => vec![(uuid::Uuid::nil(), store.clone())],
+            StoreMode::PerProject { .. } => self.cached_stores().await,
+        }
+    }
+
+    /// Flush buffered writes in every open store. Call before shutdown.
+    pub async fn flush_all(&self) {
+        let stores = match &self.mode {
+            StoreMode::Single(store) => vec![store.clone()],
+            StoreMode::PerProject { stores, .. } => stores.read().await.values().cloned().collect(),
+        };
+        for store in stores {
+            if let Err(e) = store.flush().await {
+                error!(error = %e, "Failed to flush buffered writes");
+            }
+        }
+    }
+
+    /// Turbopuffer write-batching counters summed across open stores, or
+    /// `None` if no store batches writes.
+    pub async fn write_batch_stats(&self) -> Option<storage_turbopuffer::BatchStats> {
+        let StoreMode::PerProject { stores, .. } = &self.mode else {
+            return None;
+        };
+        let stores: Vec<SharedStore> = stores.read().await.values().cloned().collect();
+        let mut total: Option<storage_turbopuffer::BatchStats> = None;
+        for store in stores {
+            let Some(s) = store.backend().write_batch_stats() else {
+                continue;
+            };
+            let t = total.get_or_insert_with(Default::default);
+            t.flushes += s.flushes;
+            t.rows_flushed += s.rows_flushed;
+            t.rows_coalesced += s.rows_coalesced;
+            t.max_batch_rows = t.max_batch_rows.max(s.max_batch_rows);
+            t.flush_errors += s.flush_errors;
+            t.flush_ms_total += s.flush_ms_total;
+            t.flush_ms_max = t.flush_ms_max.max(s.flush_ms_max);
+            t.pending_rows += s.pending_rows;
+        }
+        total
+    }
+
+    /// Turbopuffer retry counters summed across open stores, or `None` if
+    /// no store uses Turbopuffer.
+    pub async fn retry_stats(&self) -> Option<storage_turbopuffer::RetryStats> {
+        let StoreMode::PerProject { stores, .. } = &self.mode else {
+            return None;
+        };
+        let stores: Vec<SharedStore> = stores.read().await.values().cloned().collect();
+        let mut total: Option<storage_turbopuffer::RetryStats> = None;
+        for store in stores {
+            let Some(s) = store.backend().retry_stats() else {
+                continue;
+            };
+            let t = total.get_or_insert_with(Default::default);
+            t.retries += s.retries;
+            t.recovered += s.recovered;
+            t.exhausted += s.exhausted;
+            t.hedges += s.hedges;
+            t.hedge_wins += s.hedge_wins;
+        }
+        total
+    }
+
+    /// Spill queue status summed across open stores, or `None` if no store
+    /// spills.
+    pub async fn spill_status(&self) -> Option<SpillStatus> {
+        let stores: Vec<SharedStore> = match &self.mode {
+            StoreMode::Single(store) => vec![store.clone()],
+            StoreMode::PerProject { stores, .. } => stores.read().await.values().cloned().collect(),
+        };
+        let mut total: Option<SpillStatus> = None;
+        for store in stores {
+            let Some(s) = store.spill_status() else {
+                continue;
+            };
+            let t = total.get_or_insert_with(Default::default);
+            t.pending += s.pending;
+            t.replayed += s.replayed;
+            t.last_error = t.last_error.take().or(s.last_error);
+        }
+        total
+    }
+
     /// List all currently-cached stores for a specific org (across all its projects).
     /// Returns empty vec if no stores are cached for this org, or in single mode.
     pub async fn cached_stores_for_org(&self, org_id: OrgId) -> Vec<SharedStore> {
@@ -157,3 +471,14 @@ impl OrgStoreManager {
         }
     }
 }
+
+/// Compile settings that were validated when configured or saved.
+fn compile_redaction(config: &RedactionConfig) -> Option<Arc<Redactor>> {
+    match Redactor::new(config) {
+        Ok(redactor) => redactor.map(Arc::new),
+        Err(e) => {
+            error!("invalid redaction settings, not redacting: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Org membership for dashboard users.
+//!
+//! A user belongs to their home org and to any org whose invite they
+//! accept. A session is issued for one of them at a time; switching orgs
+//! issues a new session for another, which replaces the `session` cookie.
+//! Memberships live in the auth store, so these routes need one (cloud mode
+//! with Postgres).
+//!
+//! Admins change members' roles and remove members, by session or by an
+//! admin API key for deprovisioning from an identity provider. Only owners
+//! grant or take away ownership, and an org always keeps an owner.
+
+use std::sync::Arc;
+
+use auth::{AuthStore, Membership, OrgId, ProjectId, Role, UserId};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::plan_sim::plan_name;
+use super::{api_error, audit, require_scope, ApiError, AppState};
+
+/// One of the caller's orgs.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OrgMembership {
+    #[schema(value_type = String)]
+    pub org_id: OrgId,
+    pub name: String,
+    pub slug: String,
+    /// `owner`, `admin`, `member`, or `read_only`.
+    #[schema(value_type = String)]
+    pub role: Role,
+    /// Whether the caller's session is for this org.
+    pub active: bool,
+}
+
+/// Body for `POST /api/auth/switch-org`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SwitchOrgRequest {
+    #[schema(value_type = String)]
+    pub org_id: OrgId,
+    /// A project in the org; defaults to its default project.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub project_id: Option<ProjectId>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SwitchOrgResponse {
+    /// The new session token, also set as the `session` cookie.
+    pub token: String,
+    #[schema(value_type = String)]
+    pub org_id: OrgId,
+    #[schema(value_type = String)]
+    pub project_id: ProjectId,
+    #[schema(value_type = String)]
+    pub role: Role,
+}
+
+/// The orgs the signed-in user belongs to, oldest membership first.
+#[utoipa::path(
+    get,
+    path = "/api/auth/orgs",
+    tag = "auth",
+    responses(
+        (status = 200, body = Vec<OrgMembership>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_orgs(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<OrgMembership>>, ApiError> {
+    let store = auth_store(&state)?;
+    let user_id = session_user(&ctx)?;
+    let memberships = store
+        .list_memberships_for_user(user_id)
+        .await
+        .map_err(store_error)?;
+    let mut orgs = Vec::with_capacity(memberships.len());
+    for membership in memberships {
+        // Skip orgs deleted out from under the membership
+        let Some(org) = store.get_org(membership.org_id).await.map_err(store_error)? else {
+            continue;
+        };
+        orgs.push(OrgMembership {
+            org_id: org.id,
+            name: org.name,
+            slug: org.slug,
+            role: membership.role,
+            active: org.id == ctx.org_id,
+        });
+    }
+    Ok(Json(orgs))
+}
+
+/// Switch the signed-in user to another of their orgs.
+#[utoipa::path(
+    post,
+    path = "/api/auth/switch-org",
+    tag = "auth",
+    request_body = SwitchOrgRequest,
+    responses(
+        (status = 200, body = SwitchOrgResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn switch_org(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<SwitchOrgRequest>,
+) -> Result<Response, ApiError> {
+    let store = auth_store(&state)?;
+    let user_id = session_user(&ctx)?;
+    let membership = store
+        .get_membership(user_id, req.org_id)
+        .await
+        .map_err(store_error)?
+        .ok_or(auth::AuthError::NotAMember)?;
+    let project = match req.project_id {
+        Some(id) => store
+            .get_project(id)
+            .await
+            .map_err(store_error)?
+            .filter(|p| p.org_id == req.org_id)
+            .ok_or_else(|| {
+                api_error(StatusCode::NOT_FOUND, "project not found")
+                    .with_code("project_not_found")
+            })?,
+        None => store
+            .get_default_project(req.org_id)
+            .await
+            .map_err(store_error)?,
+    };
+
+    let token = auth::create_session(
+        user_id,
+        req.org_id,
+        project.id,
+        membership.role.scopes(),
+        &state.auth_config.jwt_secret,
+    )?;
+    let cookie = session_cookie(&token);
+    let body = SwitchOrgResponse {
+        token,
+        org_id: req.org_id,
+        project_id: project.id,
+        role: membership.role,
+    };
+    Ok(([(header::SET_COOKIE, cookie)], Json(body)).into_response())
+}
+
+/// A member of the caller's org.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Member {
+    #[schema(value_type = String)]
+    pub user_id: UserId,
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `owner`, `admin`, `member`, or `read_only`.
+    #[schema(value_type = String)]
+    pub role: Role,
+}
+
+/// Body for `PUT /api/org/members/:id/role`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetRoleRequest {
+    /// `owner`, `admin`, `member`, or `read_only`.
+    #[schema(value_type = String)]
+    pub role: Role,
+}
+
+/// Body for `POST /api/auth/invites/accept`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AcceptInviteRequest {
+    /// The token from the invite email.
+    pub token: String,
+}
+
+/// Join the org an invite is for. The invite must be addressed to the
+/// signed-in user's email, and the org must have a seat left on its plan.
+#[utoipa::path(
+    post,
+    path = "/api/auth/invites/accept",
+    tag = "auth",
+    request_body = AcceptInviteRequest,
+    responses(
+        (status = 200, body = OrgMembership),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn accept_invite(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<AcceptInviteRequest>,
+) -> Result<Json<OrgMembership>, ApiError> {
+    let store = auth_store(&state)?;
+    let user_id = session_user(&ctx)?;
+    // Invite tokens are stored as SHA-256 hex digests, like API keys
+    let invite = store
+        .get_invite_by_token_hash(&auth::hash_api_key(&req.token))
+        .await
+        .map_err(store_error)?
+        .filter(|i| i.expires_at > Utc::now())
+        .ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "invite not found or expired")
+                .with_code("invite_not_found")
+        })?;
+    let user = store
+        .get_user(user_id)
+        .await
+        .map_err(store_error)?
+        .ok_or(auth::AuthError::UserNotFound)?;
+    if !user.email.eq_ignore_ascii_case(invite.email.trim()) {
+        return Err(
+            api_error(StatusCode::FORBIDDEN, "the invite is for a different email")
+                .with_code("invite_email_mismatch"),
+        );
+    }
+    if store
+        .get_membership(user_id, invite.org_id)
+        .await
+        .map_err(store_error)?
+        .is_some()
+    {
+        return Err(api_error(StatusCode::CONFLICT, "already a member of this org")
+            .with_code("already_member"));
+    }
+    let org = store
+        .get_org(invite.org_id)
+        .await
+        .map_err(store_error)?
+        .ok_or(auth::AuthError::OrgNotFound)?;
+    let members = store.list_users_for_org(org.id).await.map_err(store_error)?.len();
+    let seats = org.plan.max_team_members();
+    if members >= seats {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            format!(
+                "{} has all {seats} members the {} plan allows",
+                org.name,
+                plan_name(org.plan),
+            ),
+        )
+        .with_code("member_limit"));
+    }
+
+    store
+        .save_membership(&Membership::new(user_id, org.id, invite.role))
+        .await
+        .map_err(store_error)?;
+    store.delete_invite(invite.id).await.map_err(store_error)?;
+    Ok(Json(OrgMembership {
+        org_id: org.id,
+        name: org.name,
+        slug: org.slug,
+        role: invite.role,
+        active: false,
+    }))
+}
+
+/// Change a member's role.
+#[utoipa::path(
+    put,
+    path = "/api/org/members/{id}/role",
+    tag = "auth",
+    params(("id" = String, Path, description = "User id")),
+    request_body = SetRoleRequest,
+    responses(
+        (status = 200, body = Member),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn set_member_role(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(user_id): Path<UserId>,
+    Json(req): Json<SetRoleRequest>,
+) -> Result<Json<Member>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = auth_store(&state)?;
+    let mut membership = member(store.as_ref(), user_id, ctx.org_id).await?;
+    let previous = membership.role;
+    if (previous == Role::Owner || req.role == Role::Owner)
+        && acting_role(store.as_ref(), &ctx).await? != Role::Owner
+    {
+        return Err(owner_required());
+    }
+    if previous == Role::Owner && req.role != Role::Owner {
+        ensure_other_owner(store.as_ref(), ctx.org_id, user_id).await?;
+    }
+
+    membership.role = req.role;
+    store.save_membership(&membership).await.map_err(store_error)?;
+    let mut user = store
+        .get_user(user_id)
+        .await
+        .map_err(store_error)?
+        .ok_or(auth::AuthError::UserNotFound)?;
+    // The user also carries their role in their home org
+    if user.org_id == ctx.org_id {
+        user.role = req.role;
+        user.updated_at = Utc::now();
+        store.save_user(&user).await.map_err(store_error)?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "member.role_change",
+        Some(user_id.to_string()),
+        serde_json::json!({ "from": previous, "to": req.role }),
+    )
+    .await;
+    Ok(Json(Member {
+        user_id,
+        email: user.email,
+        name: user.name,
+        role: req.role,
+    }))
+}
+
+/// Remove a member from the org. Members may remove themselves; removing
+/// anyone else takes admin.
+#[utoipa::path(
+    delete,
+    path = "/api/org/members/{id}",
+    tag = "auth",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn remove_member(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(user_id): Path<UserId>,
+) -> Result<StatusCode, ApiError> {
+    let leaving = ctx.user_id == Some(user_id);
+    if !leaving {
+        require_scope(&ctx, auth::Scope::Admin)?;
+    }
+    let store = auth_store(&state)?;
+    let membership = member(store.as_ref(), user_id, ctx.org_id).await?;
+    if membership.role == Role::Owner {
+        if !leaving && acting_role(store.as_ref(), &ctx).await? != Role::Owner {
+            return Err(owner_required());
+        }
+        ensure_other_owner(store.as_ref(), ctx.org_id, user_id).await?;
+    }
+
+    store
+        .delete_membership(user_id, ctx.org_id)
+        .await
+        .map_err(store_error)?;
+    rehome(store.as_ref(), user_id, ctx.org_id).await?;
+    audit::record(
+        &state,
+        &ctx,
+        "member.remove",
+        Some(user_id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn member(
+    store: &dyn AuthStore,
+    user_id: UserId,
+    org_id: OrgId,
+) -> Result<Membership, ApiError> {
+    store
+        .get_membership(user_id, org_id)
+        .await
+        .map_err(store_error)?
+        .ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "member not found").with_code("member_not_found")
+        })
+}
+
+/// The caller's role in their org. Admin API keys act as admins.
+async fn acting_role(store: &dyn AuthStore, ctx: &auth::AuthContext) -> Result<Role, ApiError> {
+    match ctx.user_id {
+        Some(user_id) => Ok(store
+            .get_membership(user_id, ctx.org_id)
+            .await
+            .map_err(store_error)?
+            .ok_or(auth::AuthError::NotAMember)?
+            .role),
+        None => Ok(Role::Admin),
+    }
+}
+
+async fn ensure_other_owner(
+    store: &dyn AuthStore,
+    org_id: OrgId,
+    user_id: UserId,
+) -> Result<(), ApiError> {
+    let owners = store.list_users_for_org(org_id).await.map_err(store_error)?;
+    if owners.iter().any(|u| u.id != user_id && u.role == Role::Owner) {
+        Ok(())
+    } else {
+        Err(api_error(
+            StatusCode::CONFLICT,
+            "an org needs an owner; make someone else owner first",
+        )
+        .with_code("last_owner"))
+    }
+}
+
+/// Move a user who left their home org to the oldest org they still
+/// belong to, so their next session starts somewhere they can sign in.
+async fn rehome(store: &dyn AuthStore, user_id: UserId, left: OrgId) -> Result<(), ApiError> {
+    let Some(mut user) = store.get_user(user_id).await.map_err(store_error)? else {
+        return Ok(());
+    };
+    if user.org_id != left {
+        return Ok(());
+    }
+    let memberships = store
+        .list_memberships_for_user(user_id)
+        .await
+        .map_err(store_error)?;
+    if let Some(next) = memberships.first() {
+        user.org_id = next.org_id;
+        user.role = next.role;
+        user.updated_at = Utc::now();
+        store.save_user(&user).await.map_err(store_error)?;
+    }
+    Ok(())
+}
+
+fn owner_required() -> ApiError {
+    api_error(StatusCode::FORBIDDEN, "only an owner can grant or take away ownership")
+        .with_code("owner_required")
+}
+
+fn auth_store(state: &AppState) -> Result<&Arc<dyn AuthStore>, ApiError> {
+    state.auth_store.as_ref().ok_or_else(|| {
+        api_error(
+            StatusCode::NOT_FOUND,
+            "org memberships need an auth database (DATABASE_URL)",
+        )
+        .with_code("memberships_unavailable")
+    })
+}
+
+/// API keys belong to a single org and project, so only sessions have orgs
+/// to switch between.
+fn session_user(ctx: &auth::AuthContext) -> Result<auth::UserId, ApiError> {
+    ctx.user_id.ok_or_else(|| {
+        api_error(StatusCode::FORBIDDEN, "only user sessions belong to orgs")
+            .with_code("session_required")
+    })
+}
+
+fn store_error(e: auth::AuthStoreError) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e)
+}
+
+/// The same cookie the dashboard signs in with.
+fn session_cookie(token: &str) -> String {
+    let max_age = auth::session::SESSION_DURATION_DAYS * 24 * 60 * 60;
+    // A dashboard on another origin needs a cross-site cookie
+    let same_site = if std::env::var("ALLOWED_ORIGINS").is_ok() {
+        "SameSite=None; Secure"
+    } else {
+        "SameSite=Lax"
+    };
+    format!("session={token}; HttpOnly; {same_site}; Path=/; Max-Age={max_age}")
+}
@@ -5,17 +5,20 @@
 //! system events so SSE subscribers and capture rules work seamlessly.
 
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 
-use axum::extract::State;
+use axum::extract::{ConnectInfo, State};
 use axum::http::{header, StatusCode};
 use axum::Json;
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use trace::{OrgId, Span, SpanId, SpanKind, SpanStatus, Trace, TraceId};
+use trace::git::GitInfo;
+use trace::{OrgId, Span, SpanId, SpanKind, SpanKindDefinition, SpanStatus, Trace, TraceId};
 
-use super::{capture, AppState, SystemEvent};
+use super::{capture, require_ingest, ApiError, AppState, SystemEvent};
+use crate::proxy::{preview_string, DEFAULT_PREVIEW_CHARS};
 
 #[derive(Clone)]
 struct EncoreTraceBridge {
@@ -336,6 +339,63 @@ fn extract_double_attr(attrs: &[OtlpKeyValue], key: &str) -> Option<f64> {
     })
 }
 
+/// Extract a count attribute, which some SDKs send as a double.
+fn extract_count_attr(attrs: &[OtlpKeyValue], key: &str) -> Option<u64> {
+    extract_int_attr(attrs, key).or_else(|| extract_double_attr(attrs, key).map(|d| d as u64))
+}
+
+/// Source control metadata from OpenTelemetry `vcs.*` resource attributes.
+fn git_info(attrs: &[OtlpKeyValue]) -> GitInfo {
+    GitInfo {
+        commit: extract_string_attr(attrs, "vcs.ref.head.revision"),
+        branch: extract_string_attr(attrs, "vcs.ref.head.name"),
+        repo: extract_string_attr(attrs, "vcs.repository.url.full"),
+    }
+}
+
+/// Map retrieval and embedding spans from the gen_ai conventions, and from
+/// the `openinference.span.kind` and `db.vector.*` attributes other
+/// instrumentations use.
+fn rag_span_kind(attrs: &[OtlpKeyValue]) -> Option<SpanKind> {
+    let operation = extract_string_attr(attrs, "gen_ai.operation.name");
+    let openinference = extract_string_attr(attrs, "openinference.span.kind");
+    let first_string = |keys: &[&str]| keys.iter().find_map(|k| extract_string_attr(attrs, k));
+
+    if operation.as_deref() == Some("embeddings") || openinference.as_deref() == Some("EMBEDDING") {
+        let model = first_string(&[
+            "gen_ai.request.model",
+            "gen_ai.response.model",
+            "embedding.model_name",
+        ]);
+        return Some(SpanKind::Embedding {
+            model: model.unwrap_or_else(|| "unknown".to_string()),
+            input_count: None,
+            dimensions: extract_count_attr(attrs, "gen_ai.embeddings.dimension.count"),
+        });
+    }
+
+    let vector_top_k = extract_count_attr(attrs, "db.vector.query.top_k");
+    let is_retrieval = operation.as_deref() == Some("retrieval")
+        || openinference.as_deref() == Some("RETRIEVER")
+        || vector_top_k.is_some();
+    if !is_retrieval {
+        return None;
+    }
+    let index = first_string(&[
+        "gen_ai.data_source.id",
+        "db.collection.name",
+        "db.namespace",
+        "db.system",
+    ]);
+    let query = first_string(&["db.query.text", "input.value"]);
+    Some(SpanKind::Retrieval {
+        index: index.unwrap_or_else(|| "unknown".to_string()),
+        query_preview: query.map(|q| preview_string(&q, DEFAULT_PREVIEW_CHARS)),
+        top_k: extract_count_attr(attrs, "gen_ai.request.top_k").or(vector_top_k),
+        num_results: extract_count_attr(attrs, "db.response.returned_rows"),
+    })
+}
+
 /// Convert OTel span kind integer to a human-readable name.
 fn otel_span_kind_name(kind: u32) -> &'static str {
     match kind {
@@ -366,6 +426,7 @@ fn convert_otlp_span(
     otel_span: &OtlpSpan,
     resource_attrs: &[OtlpKeyValue],
     org_id: OrgId,
+    span_kinds: &HashMap<String, SpanKindDefinition>,
 ) -> Result<Span, String> {
     let trace_id = otel_trace_id_to_uuid(&otel_span.trace_id)?;
     let span_id = otel_span_id_to_uuid(&otel_span.trace_id, &otel_span.span_id)?;
@@ -385,7 +446,9 @@ fn convert_otlp_span(
         .or_else(|| extract_string_attr(&otel_span.attributes, "gen_ai.response.model"));
     let system = extract_string_attr(&otel_span.attributes, "gen_ai.system");
 
-    let kind = if model.is_some() || system.is_some() {
+    let kind = if let Some(kind) = rag_span_kind(&otel_span.attributes) {
+        kind
+    } else if model.is_some() || system.is_some() {
         // This is an LLM call span
         let model_str = model.unwrap_or_else(|| "unknown".to_string());
         let provider = system.or_else(|| {
@@ -407,8 +470,10 @@ fn convert_otlp_span(
         }
         .with_estimated_cost()
     } else {
-        // Generic span → Custom kind with all attributes preserved
-        let kind_name = otel_span_kind_name(otel_span.kind).to_string();
+        // Generic span → Custom kind with all attributes preserved.
+        // `traceway.kind` selects an org-registered span kind definition.
+        let kind_name = extract_string_attr(&otel_span.attributes, "traceway.kind")
+            .unwrap_or_else(|| otel_span_kind_name(otel_span.kind).to_string());
         let mut attributes = HashMap::new();
         for kv in &otel_span.attributes {
             if let Some(ref v) = kv.value {
@@ -421,9 +486,26 @@ fn convert_otlp_span(
                 attributes.insert(format!("resource.{}", kv.key), otel_value_to_json(v));
             }
         }
-        SpanKind::Custom {
-            kind: kind_name,
-            attributes,
+        match span_kinds.get(&kind_name) {
+            Some(def) => {
+                let warnings = def.validate(&attributes);
+                if !warnings.is_empty() {
+                    tracing::warn!(
+                        kind = %kind_name,
+                        span = %name,
+                        warnings = %warnings.join("; "),
+                        "OTLP: span does not match registered span kind"
+                    );
+                }
+                def.apply_cost(SpanKind::Custom {
+                    kind: kind_name,
+                    attributes,
+                })
+            }
+            None => SpanKind::Custom {
+                kind: kind_name,
+                attributes,
+            },
         }
     };
 
@@ -466,11 +548,18 @@ fn convert_otlp_span(
 
 pub async fn ingest_traces(
     State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
     headers: axum::http::HeaderMap,
     Json(req): Json<ExportTraceServiceRequest>,
-) -> Result<Json<ExportTraceServiceResponse>, (StatusCode, Json<serde_json::Value>)> {
+) -> Result<Json<ExportTraceServiceResponse>, ApiError> {
     // ---- Auth: extract API key from Authorization header ----
-    let ctx = extract_otlp_auth(&state, &headers).await?;
+    let client_ip = auth::middleware::client_ip(
+        &headers,
+        peer.map(|c| c.0),
+        &state.auth_config.trusted_proxies,
+    );
+    let ctx = extract_otlp_auth(&state, &headers, client_ip).await?;
+    require_ingest(&ctx)?;
     let org_id = ctx.org_id;
     let project_id = ctx.project_id;
     let org_id_str = org_id.to_string();
@@ -479,18 +568,18 @@ pub async fn ingest_traces(
     let store = state
         .store_for_project(org_id, project_id)
         .await
-        .map_err(|e| {
-            (
-                e.0,
-                Json(serde_json::json!({ "error": e.1 })),
-            )
-        })?;
+        .map_err(|(status, msg)| ApiError::new(status, msg))?;
 
     // ---- Convert all spans, grouped by trace ----
     // Map: traceway_trace_id → (earliest_started_at, root_span_name, Vec<Span>)
     let mut traces_map: HashMap<TraceId, (DateTime<Utc>, Option<String>, Vec<Span>)> =
         HashMap::new();
     let mut conversion_errors: Vec<String> = Vec::new();
+    let span_kinds: HashMap<String, SpanKindDefinition> = store
+        .list_span_kinds()
+        .into_iter()
+        .map(|d| (d.name.clone(), d))
+        .collect();
 
     for resource_spans in &req.resource_spans {
         let resource_attrs = resource_spans
@@ -501,7 +590,7 @@ pub async fn ingest_traces(
 
         for scope_spans in &resource_spans.scope_spans {
             for otel_span in &scope_spans.spans {
-                match convert_otlp_span(otel_span, resource_attrs, org_id) {
+                match convert_otlp_span(otel_span, resource_attrs, org_id, &span_kinds) {
                     Ok(span) => {
                         let entry = traces_map
                             .entry(span.trace_id())
@@ -533,15 +622,30 @@ pub async fn ingest_traces(
         );
     }
 
-    // ---- Create traces + insert spans ----
-    let mut w = store.write().await;
+    // Head-based sampling; traces left without spans aren't created
+    if let Some(sampler) = &state.sampler {
+        for (_, _, spans) in traces_map.values_mut() {
+            spans.retain(|s| sampler.keep_span(s));
+        }
+        traces_map.retain(|_, (_, _, spans)| !spans.is_empty());
+    }
+
+    state.reserve_spans(traces_map.values().map(|(_, _, spans)| spans.len()).sum())?;
 
+    // ---- Create traces + insert spans ----
+    let session_id = super::sessions::session_from_headers(&headers);
     // Derive service.name from the first resource (used for trace naming)
     let service_name = req
         .resource_spans
         .first()
         .and_then(|rs| rs.resource.as_ref())
         .and_then(|r| extract_string_attr(&r.attributes, "service.name"));
+    let git = req
+        .resource_spans
+        .first()
+        .and_then(|rs| rs.resource.as_ref())
+        .map(|r| git_info(&r.attributes))
+        .unwrap_or_default();
 
     for (trace_id, (earliest_start, root_name, spans)) in &traces_map {
         // Always save the trace (INSERT OR REPLACE is idempotent).
@@ -559,21 +663,31 @@ pub async fn ingest_traces(
             started_at: *earliest_start,
             ended_at: None,
             machine_id: None,
+            session_id: session_id.clone(),
+            user_id: None,
+            git_commit: git.commit.clone(),
+            git_branch: git.branch.clone(),
+            repo: git.repo.clone(),
+            stats: Default::default(),
+            deleted_at: None,
         };
 
-        if let Err(e) = w.save_trace(trace).await {
+        if let Err(e) = store.save_trace(trace).await {
             tracing::error!(%trace_id, "OTLP: failed to save trace: {e}");
             continue;
         }
 
         // Insert all spans for this trace
         for span in spans {
-            if let Err(e) = w.insert(span.clone()).await {
-                tracing::error!(span_id = %span.id(), "OTLP: failed to insert span: {e}");
+            match store.insert(span.clone()).await {
+                Ok(_) => {
+                    super::metrics::global().record_ingest("otlp", 1);
+                    state.usage.record(org_id, 1);
+                }
+                Err(e) => tracing::error!(span_id = %span.id(), "OTLP: failed to insert span: {e}"),
             }
         }
     }
-    drop(w);
 
     // ---- Mirror traces/spans into Encore product API (daemon bridge) ----
     if let Some(bridge) = EncoreTraceBridge::from_env() {
@@ -644,7 +758,7 @@ pub async fn ingest_traces(
         }
     }
 
-    // ---- Emit events (outside write lock) ----
+    // ---- Emit events ----
     for (trace_id, (earliest_start, root_name, spans)) in traces_map {
         // Emit TraceCreated — harmless if trace already existed (UI deduplicates).
         let trace_name = root_name
@@ -658,6 +772,13 @@ pub async fn ingest_traces(
             started_at: earliest_start,
             ended_at: None,
             machine_id: None,
+            session_id: session_id.clone(),
+            user_id: None,
+            git_commit: git.commit.clone(),
+            git_branch: git.branch.clone(),
+            repo: git.repo.clone(),
+            stats: Default::default(),
+            deleted_at: None,
         };
         state.emit_event(SystemEvent::TraceCreated { trace }, &org_id_str);
 
@@ -679,15 +800,13 @@ pub async fn ingest_traces(
             // Process capture rules for completed/failed spans
             if span_clone.status().is_terminal() {
                 let store_clone = store.clone();
-                let events_tx = state.events_tx.clone();
-                let event_log = state.event_log.clone();
+                let journal = state.journal.clone();
                 let org_id_str2 = org_id_str.clone();
                 tokio::spawn(async move {
                     capture::process_capture_rules(
                         &store_clone,
                         &span_clone,
-                        &events_tx,
-                        &event_log,
+                        &journal,
                         &org_id_str2,
                     )
                     .await;
@@ -713,7 +832,8 @@ pub async fn ingest_traces(
 async fn extract_otlp_auth(
     state: &AppState,
     headers: &axum::http::HeaderMap,
-) -> Result<auth::AuthContext, (StatusCode, Json<serde_json::Value>)> {
+    client_ip: Option<IpAddr>,
+) -> Result<auth::AuthContext, ApiError> {
     // In local mode, skip auth entirely
     if state.auth_config.local_mode {
         return Ok(auth::AuthContext::local());
@@ -726,39 +846,74 @@ async fn extract_otlp_auth(
     let token = auth_header
         .and_then(|s| s.strip_prefix("Bearer "))
         .ok_or_else(|| {
-            (
+            ApiError::new(
                 StatusCode::UNAUTHORIZED,
-                Json(serde_json::json!({
-                    "error": "Missing or invalid Authorization header. Use: Authorization: Bearer tw_sk_..."
-                })),
+                "Missing or invalid Authorization header. Use: Authorization: Bearer tw_sk_...",
             )
+            .with_code("missing_auth")
         })?;
 
     if !token.starts_with("tw_sk_") || token.len() < 16 {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "error": "Invalid API key format" })),
-        ));
+        return Err(
+            ApiError::new(StatusCode::UNAUTHORIZED, "Invalid API key format")
+                .with_code("invalid_format"),
+        );
     }
 
     let prefix = &token[..16];
-    let (org_id, project_id, key_hash, scopes) = state
+    let grant = state
         .api_key_lookup
         .lookup_api_key(prefix)
         .await
         .ok_or_else(|| {
-            (
-                StatusCode::UNAUTHORIZED,
-                Json(serde_json::json!({ "error": "Unknown API key" })),
-            )
+            ApiError::new(StatusCode::UNAUTHORIZED, "Unknown API key").with_code("invalid_api_key")
         })?;
 
-    if !auth::verify_api_key(token, &key_hash) {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "error": "Invalid API key" })),
-        ));
+    if !auth::verify_api_key(token, &grant.key_hash) {
+        return Err(
+            ApiError::new(StatusCode::UNAUTHORIZED, "Invalid API key").with_code("invalid_api_key"),
+        );
+    }
+    grant.check_ip(client_ip)?;
+
+    Ok(auth::AuthContext::from_api_key(grant.org_id, grant.project_id, grant.scopes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn otlp_span(attributes: serde_json::Value) -> OtlpSpan {
+        serde_json::from_value(serde_json::json!({
+            "traceId": "5b8efff798038103d269b633813fc60c",
+            "spanId": "eee19b7ec3c1b174",
+            "name": "step",
+            "attributes": attributes,
+        }))
+        .unwrap()
     }
 
-    Ok(auth::AuthContext::from_api_key(org_id, project_id, scopes))
+    #[test]
+    fn rag_attributes_map_to_retrieval_and_embedding() {
+        let kinds = HashMap::new();
+        let span = otlp_span(serde_json::json!([
+            {"key": "db.collection.name", "value": {"stringValue": "docs"}},
+            {"key": "db.vector.query.top_k", "value": {"intValue": "5"}},
+            {"key": "db.response.returned_rows", "value": {"intValue": 3}},
+        ]));
+        let span = convert_otlp_span(&span, &[], Uuid::nil(), &kinds).unwrap();
+        assert!(matches!(
+            span.kind(),
+            SpanKind::Retrieval { index, top_k: Some(5), num_results: Some(3), .. } if index == "docs"
+        ));
+
+        let span = otlp_span(serde_json::json!([
+            {"key": "gen_ai.operation.name", "value": {"stringValue": "embeddings"}},
+            {"key": "gen_ai.request.model", "value": {"stringValue": "text-embedding-3-small"}},
+            {"key": "gen_ai.embeddings.dimension.count", "value": {"intValue": 1536}},
+        ]));
+        let span = convert_otlp_span(&span, &[], Uuid::nil(), &kinds).unwrap();
+        assert_eq!(span.kind().kind_name(), "embedding");
+        assert_eq!(span.kind().model(), Some("text-embedding-3-small"));
+    }
 }
@@ -0,0 +1,244 @@
+//! Plan limit simulation for local development.
+//!
+//! `--simulate-plan` makes a local daemon behave like a cloud org on that
+//! plan: span ingest counts against the monthly quota, plan-gated features
+//! are refused, and retention follows the plan window. Usage counters live in
+//! memory only and can be set through `PUT /api/plan/usage` to reach the
+//! limits quickly.
+
+use std::sync::Mutex;
+
+use auth::{Feature, Plan};
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{api_error, require_scope, ApiError, AppState};
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    /// `year * 12 + month0` of the period being counted.
+    period: i32,
+    spans: u64,
+}
+
+fn period_of(at: DateTime<Utc>) -> i32 {
+    at.year() * 12 + at.month0() as i32
+}
+
+pub struct PlanSimulator {
+    plan: Plan,
+    usage: Mutex<Usage>,
+}
+
+impl PlanSimulator {
+    pub fn new(plan: Plan) -> Self {
+        Self {
+            plan,
+            usage: Mutex::new(Usage {
+                period: period_of(Utc::now()),
+                spans: 0,
+            }),
+        }
+    }
+
+    pub fn plan(&self) -> Plan {
+        self.plan
+    }
+
+    fn with_usage<T>(&self, now: DateTime<Utc>, f: impl FnOnce(&mut Usage) -> T) -> T {
+        let mut usage = self.usage.lock().unwrap();
+        let period = period_of(now);
+        if usage.period != period {
+            *usage = Usage { period, spans: 0 };
+        }
+        f(&mut usage)
+    }
+
+    pub fn spans_this_month(&self) -> u64 {
+        self.with_usage(Utc::now(), |u| u.spans)
+    }
+
+    /// Count `n` spans against the monthly quota. All or nothing: a batch
+    /// that would cross the limit is refused without being counted.
+    pub fn reserve_spans(&self, n: u64) -> Result<(), ApiError> {
+        self.reserve_spans_at(n, Utc::now())
+    }
+
+    fn reserve_spans_at(&self, n: u64, now: DateTime<Utc>) -> Result<(), ApiError> {
+        let limit = self.plan.spans_per_month();
+        self.with_usage(now, |u| {
+            if u.spans.saturating_add(n) > limit {
+                return Err(api_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!(
+                        "monthly span quota exceeded ({} of {limit} used on the {} plan)",
+                        u.spans,
+                        plan_name(self.plan),
+                    ),
+                )
+                .with_code("quota_exceeded"));
+            }
+            u.spans += n;
+            Ok(())
+        })
+    }
+
+    pub fn require(&self, feature: Feature) -> Result<(), ApiError> {
+        if self.plan.includes(feature) {
+            Ok(())
+        } else {
+            Err(api_error(
+                StatusCode::FORBIDDEN,
+                format!(
+                    "{} is not available on the {} plan",
+                    feature_name(feature),
+                    plan_name(self.plan),
+                ),
+            )
+            .with_code("plan_feature_unavailable"))
+        }
+    }
+}
+
+pub(super) fn plan_name(plan: Plan) -> &'static str {
+    match plan {
+        Plan::Free => "free",
+        Plan::Pro => "pro",
+        Plan::Team => "team",
+        Plan::Enterprise => "enterprise",
+    }
+}
+
+fn feature_name(feature: Feature) -> &'static str {
+    match feature {
+        Feature::SemanticSearch => "semantic search",
+        Feature::ConcurrencyAnalytics => "concurrency analytics",
+    }
+}
+
+impl AppState {
+    /// Refuse a plan-gated feature. Always allowed unless a plan is simulated.
+    pub fn require_feature(&self, feature: Feature) -> Result<(), ApiError> {
+        match &self.plan_sim {
+            Some(sim) => sim.require(feature),
+            None => Ok(()),
+        }
+    }
+
+    /// Count ingested spans against the simulated quota, if any.
+    pub fn reserve_spans(&self, n: usize) -> Result<(), ApiError> {
+        match &self.plan_sim {
+            Some(sim) => sim.reserve_spans(n as u64),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanLimits {
+    pub spans_per_month: u64,
+    pub retention_days: u32,
+    pub max_team_members: usize,
+    pub max_api_keys: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanUsage {
+    pub spans_this_month: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanResponse {
+    pub plan: Plan,
+    pub limits: PlanLimits,
+    pub usage: PlanUsage,
+    pub features: Vec<Feature>,
+}
+
+fn plan_response(sim: &PlanSimulator) -> PlanResponse {
+    let plan = sim.plan();
+    PlanResponse {
+        plan,
+        limits: PlanLimits {
+            spans_per_month: plan.spans_per_month(),
+            retention_days: plan.retention_days(),
+            max_team_members: plan.max_team_members(),
+            max_api_keys: plan.max_api_keys(),
+        },
+        usage: PlanUsage {
+            spans_this_month: sim.spans_this_month(),
+        },
+        features: Feature::ALL
+            .into_iter()
+            .filter(|f| plan.includes(*f))
+            .collect(),
+    }
+}
+
+fn simulator(state: &AppState) -> Result<&PlanSimulator, ApiError> {
+    state.plan_sim.as_deref().ok_or_else(|| {
+        api_error(
+            StatusCode::NOT_FOUND,
+            "no plan is simulated (start the daemon with --simulate-plan)",
+        )
+    })
+}
+
+/// The simulated plan, its limits, and current usage.
+pub async fn get_plan(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<PlanResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    Ok(Json(plan_response(simulator(&state)?)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetUsageRequest {
+    pub spans_this_month: u64,
+}
+
+/// Overwrite the fake usage counters.
+pub async fn set_usage(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<SetUsageRequest>,
+) -> Result<Json<PlanResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let sim = simulator(&state)?;
+    sim.with_usage(Utc::now(), |u| u.spans = req.spans_this_month);
+    Ok(Json(plan_response(sim)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn quota_is_all_or_nothing_and_resets_monthly() {
+        let sim = PlanSimulator::new(Plan::Free);
+        let march = Utc.with_ymd_and_hms(2026, 3, 31, 23, 0, 0).unwrap();
+        let april = Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap();
+        sim.with_usage(march, |u| u.spans = 9_990);
+
+        let err = sim.reserve_spans_at(11, march).unwrap_err();
+        assert_eq!(err.problem().code, "quota_exceeded");
+        sim.reserve_spans_at(10, march).unwrap();
+        assert!(sim.reserve_spans_at(1, march).is_err());
+
+        sim.reserve_spans_at(1, april).unwrap();
+        assert_eq!(sim.with_usage(april, |u| u.spans), 1);
+    }
+
+    #[test]
+    fn free_plan_gates_features() {
+        let free = PlanSimulator::new(Plan::Free);
+        let pro = PlanSimulator::new(Plan::Pro);
+        for feature in Feature::ALL {
+            assert_eq!(free.require(feature).unwrap_err().problem().status, 403);
+            assert!(pro.require(feature).is_ok());
+        }
+    }
+}
@@ -0,0 +1,585 @@
+//! Playground runs: batch replays of captured LLM calls against several
+//! model/prompt configurations, compared side by side.
+//!
+//! - `POST /api/playground/runs` replays the given spans (or a dataset's
+//!   span-exported datapoints) once per variant in the background.
+//! - `GET /api/playground/runs/:id/compare` lines up each span's outputs
+//!   across variants with per-variant metrics and deltas from the first.
+//!
+//! Runs are kept in the project's settings, one entry per run plus an index.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use storage::analytics::{compare_cohorts, compute_cohort};
+use tokio::sync::Mutex;
+use trace::{CohortMetrics, CompareDeltas, DatasetId, Span, SpanId};
+use uuid::Uuid;
+
+use super::org_store::SharedStore;
+use super::replay::{self, ReplaySpan};
+use super::scorers::output_text;
+use super::{api_error, project_store, require_scope, ApiError, AppState};
+
+pub type PlaygroundRunId = Uuid;
+
+pub const RUNS_SETTING: &str = "playground_runs";
+const MIN_VARIANTS: usize = 2;
+const MAX_VARIANTS: usize = 8;
+const MAX_SPANS: usize = 200;
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 16;
+/// Runs kept per project; older ones are dropped.
+const MAX_RUNS: usize = 100;
+
+pub fn run_setting(id: PlaygroundRunId) -> String {
+    format!("playground_run:{id}")
+}
+
+/// One configuration every span is replayed with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaygroundVariant {
+    /// Defaults to the model, or `variant N`.
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub overrides: ReplaySpan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaygroundRunStatus {
+    Running,
+    Completed,
+}
+
+/// The replay of one span with one variant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaygroundResult {
+    pub span_id: SpanId,
+    /// Index into the run's `variants`.
+    pub variant: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_span_id: Option<SpanId>,
+    /// Status the upstream provider answered with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaygroundRun {
+    pub id: PlaygroundRunId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset_id: Option<DatasetId>,
+    pub span_ids: Vec<SpanId>,
+    pub variants: Vec<PlaygroundVariant>,
+    pub status: PlaygroundRunStatus,
+    /// Ordered by span, then variant. Empty while running.
+    #[serde(default)]
+    pub results: Vec<PlaygroundResult>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl PlaygroundRun {
+    fn label(&self, variant: usize) -> String {
+        let v = &self.variants[variant];
+        v.label
+            .clone()
+            .or_else(|| v.overrides.model.clone())
+            .unwrap_or_else(|| format!("variant {}", variant + 1))
+    }
+}
+
+/// Body for `POST /api/playground/runs`.
+#[derive(Debug, Deserialize)]
+pub struct CreatePlaygroundRun {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub span_ids: Vec<SpanId>,
+    /// Adds the source spans of the dataset's datapoints.
+    #[serde(default)]
+    pub dataset_id: Option<DatasetId>,
+    pub variants: Vec<PlaygroundVariant>,
+    /// Replays in flight at once.
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantOutput {
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// Output is the same as the first variant's, ignoring surrounding
+    /// whitespace.
+    pub matches_first: bool,
+}
+
+/// One span's replays across variants.
+#[derive(Debug, Serialize)]
+pub struct ItemComparison {
+    pub span_id: SpanId,
+    pub outputs: Vec<VariantOutput>,
+    /// Every variant produced the same output.
+    pub identical: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantSummary {
+    pub label: String,
+    pub metrics: CohortMetrics,
+    /// Change from the first variant; `None` for the first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vs_first: Option<CompareDeltas>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaygroundComparison {
+    pub run_id: PlaygroundRunId,
+    pub variants: Vec<VariantSummary>,
+    pub items: Vec<ItemComparison>,
+}
+
+/// Saved playground runs.
+pub struct Playground {
+    client: reqwest::Client,
+    /// Serializes changes to the run index.
+    edits: Mutex<()>,
+}
+
+impl Playground {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            client: reqwest::Client::new(),
+            edits: Mutex::new(()),
+        })
+    }
+
+    async fn index(store: &SharedStore) -> Result<Vec<PlaygroundRunId>, String> {
+        match store.get_setting(RUNS_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn get(
+        store: &SharedStore,
+        id: PlaygroundRunId,
+    ) -> Result<Option<PlaygroundRun>, String> {
+        match store.get_setting(&run_setting(id)).await {
+            Ok(Some(Value::Null)) | Ok(None) => Ok(None),
+            Ok(Some(value)) => serde_json::from_value(value)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn put(store: &SharedStore, run: &PlaygroundRun) -> Result<(), String> {
+        let value = serde_json::to_value(run).map_err(|e| e.to_string())?;
+        store
+            .save_setting(&run_setting(run.id), &value)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Save a new run and add it to the index, dropping the oldest runs
+    /// past `MAX_RUNS`.
+    async fn insert(&self, store: &SharedStore, run: &PlaygroundRun) -> Result<(), String> {
+        let _edit = self.edits.lock().await;
+        Self::put(store, run).await?;
+        let mut index = Self::index(store).await?;
+        index.insert(0, run.id);
+        for old in index.split_off(index.len().min(MAX_RUNS)) {
+            store
+                .save_setting(&run_setting(old), &Value::Null)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let value = serde_json::to_value(&index).map_err(|e| e.to_string())?;
+        store
+            .save_setting(RUNS_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Replay every span with every variant, at most `concurrency` at a time.
+    async fn execute(
+        &self,
+        store: SharedStore,
+        proxy_url: String,
+        mut run: PlaygroundRun,
+        spans: Vec<Span>,
+        concurrency: usize,
+    ) {
+        let variants = run.variants.len();
+        let items: Vec<(usize, usize)> = (0..spans.len())
+            .flat_map(|i| (0..variants).map(move |variant| (i, variant)))
+            .collect();
+        let mut results: Vec<PlaygroundResult> = stream::iter(items)
+            .map(|(i, variant)| {
+                self.replay(
+                    &store,
+                    &proxy_url,
+                    &spans[i],
+                    variant,
+                    &run.variants[variant].overrides,
+                )
+            })
+            .buffer_unordered(concurrency)
+            .collect()
+            .await;
+        let order: Vec<SpanId> = spans.iter().map(Span::id).collect();
+        results.sort_by_key(|r| (order.iter().position(|id| *id == r.span_id), r.variant));
+
+        run.results = results;
+        run.status = PlaygroundRunStatus::Completed;
+        run.completed_at = Some(Utc::now());
+        if let Err(e) = Self::put(&store, &run).await {
+            tracing::warn!(run_id = %run.id, "failed to save playground run: {e}");
+        }
+    }
+
+    async fn replay(
+        &self,
+        store: &SharedStore,
+        proxy_url: &str,
+        span: &Span,
+        variant: usize,
+        overrides: &ReplaySpan,
+    ) -> PlaygroundResult {
+        let mut result = PlaygroundResult {
+            span_id: span.id(),
+            variant,
+            replay_span_id: None,
+            status: None,
+            output: None,
+            latency_ms: None,
+            cost: None,
+            error: None,
+        };
+        let sent = match replay::replay_request(span, overrides) {
+            Ok((path, body)) => replay::send(&self.client, proxy_url, span, &path, &body).await,
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok((status, replay_id)) => {
+                result.status = Some(status);
+                result.replay_span_id = replay_id;
+                if let Some(replayed) = match replay_id {
+                    Some(id) => store.get_or_load(id).await,
+                    None => None,
+                } {
+                    result.output = replayed.output().cloned();
+                    result.latency_ms = replayed.duration_ms();
+                    result.cost = replayed.kind().cost();
+                }
+                if !(200..300).contains(&status) {
+                    result.error = Some(format!("upstream returned {status}"));
+                }
+            }
+            Err(e) => result.error = Some(e),
+        }
+        result
+    }
+}
+
+/// Per-item outputs across variants and per-variant metrics over the
+/// replayed spans.
+fn compare(run: &PlaygroundRun, replayed: &[Span]) -> PlaygroundComparison {
+    let metrics: Vec<CohortMetrics> = (0..run.variants.len())
+        .map(|variant| {
+            let ids: HashSet<SpanId> = run
+                .results
+                .iter()
+                .filter(|r| r.variant == variant)
+                .filter_map(|r| r.replay_span_id)
+                .collect();
+            let spans: Vec<&Span> = replayed.iter().filter(|s| ids.contains(&s.id())).collect();
+            compute_cohort(&spans, Some(run.label(variant)))
+        })
+        .collect();
+    let variants = metrics
+        .iter()
+        .enumerate()
+        .map(|(i, m)| VariantSummary {
+            label: run.label(i),
+            metrics: m.clone(),
+            vs_first: (i > 0).then(|| compare_cohorts(metrics[0].clone(), m.clone()).delta),
+        })
+        .collect();
+
+    let items = run
+        .span_ids
+        .iter()
+        .map(|span_id| {
+            let results: Vec<&PlaygroundResult> = run
+                .results
+                .iter()
+                .filter(|r| r.span_id == *span_id)
+                .collect();
+            let texts: Vec<Option<String>> = results
+                .iter()
+                .map(|r| r.output.as_ref().map(|o| output_text(o).trim().to_string()))
+                .collect();
+            let first = texts.first().cloned().flatten();
+            let outputs: Vec<VariantOutput> = results
+                .iter()
+                .zip(texts)
+                .map(|(r, text)| VariantOutput {
+                    label: run.label(r.variant),
+                    matches_first: text.is_some() && text == first,
+                    output: text,
+                    error: r.error.clone(),
+                    latency_ms: r.latency_ms,
+                    cost: r.cost,
+                })
+                .collect();
+            ItemComparison {
+                span_id: *span_id,
+                identical: !outputs.is_empty() && outputs.iter().all(|o| o.matches_first),
+                outputs,
+            }
+        })
+        .collect();
+
+    PlaygroundComparison {
+        run_id: run.id,
+        variants,
+        items,
+    }
+}
+
+pub async fn create_run(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<CreatePlaygroundRun>,
+) -> Result<(StatusCode, Json<PlaygroundRun>), ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let proxy_url = state.proxy_url.clone().ok_or_else(|| {
+        api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "playground runs need the local proxy, which is not running",
+        )
+    })?;
+    if !(MIN_VARIANTS..=MAX_VARIANTS).contains(&req.variants.len()) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("between {MIN_VARIANTS} and {MAX_VARIANTS} variants are required"),
+        ));
+    }
+    let concurrency = req
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .clamp(1, MAX_CONCURRENCY);
+
+    let store = project_store(&ctx, &state).await?;
+    let mut span_ids = req.span_ids;
+    if let Some(dataset_id) = req.dataset_id {
+        store.get_dataset_or_load(dataset_id).await.ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+        })?;
+        store.sync_datapoints_for_dataset(dataset_id).await;
+        let mut datapoints = store.datapoints_for_dataset(dataset_id);
+        datapoints.sort_by_key(|dp| dp.id);
+        span_ids.extend(datapoints.iter().filter_map(|dp| dp.source_span_id));
+    }
+    let mut seen = HashSet::new();
+    span_ids.retain(|id| seen.insert(*id));
+    if span_ids.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "no spans to replay"));
+    }
+    if span_ids.len() > MAX_SPANS {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("at most {MAX_SPANS} spans can be replayed per run"),
+        ));
+    }
+    let mut spans = Vec::with_capacity(span_ids.len());
+    for id in &span_ids {
+        let span = store.get_or_load(*id).await.ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, format!("span {id} not found"))
+                .with_code("span_not_found")
+        })?;
+        spans.push(span);
+    }
+
+    let run = PlaygroundRun {
+        id: Uuid::now_v7(),
+        name: req.name,
+        dataset_id: req.dataset_id,
+        span_ids,
+        variants: req.variants,
+        status: PlaygroundRunStatus::Running,
+        results: Vec::new(),
+        created_at: Utc::now(),
+        completed_at: None,
+    };
+    state
+        .playground
+        .insert(&store, &run)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let playground = state.playground.clone();
+    let pending = run.clone();
+    tokio::spawn(async move {
+        playground
+            .execute(store, proxy_url, pending, spans, concurrency)
+            .await;
+    });
+    Ok((StatusCode::ACCEPTED, Json(run)))
+}
+
+/// Runs newest first, without their results.
+pub async fn list_runs(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PlaygroundRun>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let index = Playground::index(&store)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let mut runs = Vec::with_capacity(index.len());
+    for id in index {
+        if let Ok(Some(mut run)) = Playground::get(&store, id).await {
+            run.results.clear();
+            runs.push(run);
+        }
+    }
+    Ok(Json(runs))
+}
+
+async fn load_run(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    id: PlaygroundRunId,
+) -> Result<(SharedStore, PlaygroundRun), ApiError> {
+    require_scope(ctx, auth::Scope::TracesRead)?;
+    let store = project_store(ctx, state).await?;
+    let run = Playground::get(&store, id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "playground run not found")
+                .with_code("playground_run_not_found")
+        })?;
+    Ok((store, run))
+}
+
+pub async fn get_run(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<PlaygroundRunId>,
+) -> Result<Json<PlaygroundRun>, ApiError> {
+    let (_, run) = load_run(&state, &ctx, id).await?;
+    Ok(Json(run))
+}
+
+pub async fn compare_run(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<PlaygroundRunId>,
+) -> Result<Json<PlaygroundComparison>, ApiError> {
+    let (store, run) = load_run(&state, &ctx, id).await?;
+    if run.status != PlaygroundRunStatus::Completed {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            "playground run is still running",
+        ));
+    }
+    let mut replayed = Vec::new();
+    for id in run.results.iter().filter_map(|r| r.replay_span_id) {
+        if let Some(span) = store.get_or_load(id).await {
+            replayed.push(span);
+        }
+    }
+    Ok(Json(compare(&run, &replayed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result(span_id: SpanId, variant: usize, output: &str) -> PlaygroundResult {
+        PlaygroundResult {
+            span_id,
+            variant,
+            replay_span_id: None,
+            status: Some(200),
+            output: Some(json!({ "content": output })),
+            latency_ms: Some(100),
+            cost: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn compare_flags_changed_outputs() {
+        let (same, changed) = (Uuid::now_v7(), Uuid::now_v7());
+        let variant = |model: &str| PlaygroundVariant {
+            label: None,
+            overrides: ReplaySpan {
+                model: Some(model.into()),
+                ..Default::default()
+            },
+        };
+        let run = PlaygroundRun {
+            id: Uuid::now_v7(),
+            name: None,
+            dataset_id: None,
+            span_ids: vec![same, changed],
+            variants: vec![variant("gpt-4o"), variant("gpt-4o-mini")],
+            status: PlaygroundRunStatus::Completed,
+            results: vec![
+                result(same, 0, "Paris"),
+                result(same, 1, " Paris\n"),
+                result(changed, 0, "4"),
+                result(changed, 1, "5"),
+            ],
+            created_at: Utc::now(),
+            completed_at: Some(Utc::now()),
+        };
+
+        let comparison = compare(&run, &[]);
+        assert_eq!(comparison.variants.len(), 2);
+        assert_eq!(comparison.variants[1].label, "gpt-4o-mini");
+        assert!(comparison.variants[0].vs_first.is_none());
+        assert!(comparison.variants[1].vs_first.is_some());
+        assert!(comparison.items[0].identical);
+        assert!(!comparison.items[1].identical);
+        assert!(!comparison.items[1].outputs[1].matches_first);
+        assert_eq!(comparison.items[1].outputs[1].output.as_deref(), Some("5"));
+    }
+}
@@ -0,0 +1,601 @@
+//! Labeling queue.
+//!
+//! A labeler claims a pending item, then submits it (completed), rejects
+//! it with a reason, or releases it back to pending. Claims held longer
+//! than `claim_ttl_secs` are released by a background task so abandoned
+//! items don't stay locked.
+//!
+//! An item can instead be assigned to several reviewers who each submit
+//! separately. Once all have, its consensus policy either completes it or
+//! moves it to the adjudication queue for someone to settle.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use trace::{
+    ConsensusPolicy, DatasetId, QueueItem, QueueItemId, QueueItemStatus, QueueSubmission,
+    ReviewPolicy,
+};
+use tracing::{error, info};
+use utoipa::{IntoParams, ToSchema};
+
+use super::error::Problem;
+use super::{
+    api_error, audit, events::EventJournal, project_store, require_scope, ApiError, AppState,
+    OrgStoreManager, SharedStore, SystemEvent,
+};
+use crate::config::QueueConfig;
+
+/// Body for `POST /api/queue/:id/claim`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ClaimRequest {
+    /// Defaults to the caller, e.g. `user:<id>`.
+    #[serde(default)]
+    pub claimed_by: Option<String>,
+}
+
+/// Body for `POST /api/queue/:id/submit` and `POST /api/queue/:id/adjudicate`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct SubmitRequest {
+    #[serde(default)]
+    pub edited_data: Option<serde_json::Value>,
+}
+
+/// Body for `POST /api/queue/:id/reject`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RejectRequest {
+    pub reason: String,
+}
+
+/// Body for `POST /api/queue/:id/reviewers`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AssignReviewersRequest {
+    pub reviewers: Vec<String>,
+    #[serde(default)]
+    pub consensus: ConsensusPolicy,
+}
+
+/// Body for `POST /api/queue/:id/submissions`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ReviewRequest {
+    /// Defaults to the caller, e.g. `user:<id>`.
+    #[serde(default)]
+    pub reviewer: Option<String>,
+    #[serde(default)]
+    pub edited_data: Option<serde_json::Value>,
+}
+
+/// Query parameters for `GET /api/queue/adjudication`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdjudicationQuery {
+    #[param(value_type = Option<String>)]
+    pub dataset_id: Option<DatasetId>,
+}
+
+/// Query parameters for `GET /api/queue/stats`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    #[param(value_type = Option<String>)]
+    pub dataset_id: Option<DatasetId>,
+    /// Only count items resolved at or after this time.
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// One labeler's throughput.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct LabelerStats {
+    pub labeler: String,
+    /// Items the labeler holds now.
+    pub claimed: usize,
+    pub completed: usize,
+    pub rejected: usize,
+    /// Mean time from claim to completion or rejection.
+    pub avg_resolve_ms: Option<f64>,
+    pub last_resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Turn the result of a queue transition into a response, emitting
+/// `queue_item_updated` on success. `None` means the item is missing or
+/// wasn't in the state the transition needs.
+fn transitioned(
+    state: &AppState,
+    ctx: &auth::AuthContext,
+    store: &SharedStore,
+    id: QueueItemId,
+    result: Result<Option<QueueItem>, storage::StorageError>,
+) -> Result<Json<QueueItem>, ApiError> {
+    match result.map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))? {
+        Some(item) => {
+            state.emit_event(
+                SystemEvent::QueueItemUpdated { item: item.clone() },
+                &ctx.org_id.to_string(),
+            );
+            Ok(Json(item))
+        }
+        None => match store.get_queue_item(id) {
+            Some(item) => Err(api_error(
+                StatusCode::CONFLICT,
+                format!("queue item is {}", item.status.as_str()),
+            )),
+            None => Err(api_error(StatusCode::NOT_FOUND, "queue item not found")
+                .with_code("queue_item_not_found")),
+        },
+    }
+}
+
+/// Claim a pending item for labeling.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/claim",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = Option<ClaimRequest>,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn claim_item(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    body: Option<Json<ClaimRequest>>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let claimed_by = body
+        .and_then(|Json(b)| b.claimed_by)
+        .filter(|c| !c.trim().is_empty())
+        .unwrap_or_else(|| audit::actor(&ctx));
+    let result = store.claim_queue_item(id, claimed_by).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Complete a claimed item, optionally with edited data.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/submit",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = Option<SubmitRequest>,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn submit_item(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    body: Option<Json<SubmitRequest>>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let edited_data = body.and_then(|Json(b)| b.edited_data);
+    let result = store.complete_queue_item(id, edited_data).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Put a claimed item back to pending.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/release",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn release_item(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let result = store.release_queue_item(id).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Reject a claimed item, e.g. because it can't be labeled.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/reject",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = RejectRequest,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn reject_item(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    Json(req): Json<RejectRequest>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let reason = req.reason.trim();
+    if reason.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "reason is required"));
+    }
+    let store = project_store(&ctx, &state).await?;
+    let result = store.reject_queue_item(id, reason).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Assign a pending item to several reviewers.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/reviewers",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = AssignReviewersRequest,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn assign_reviewers(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    Json(req): Json<AssignReviewersRequest>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let mut reviewers: Vec<String> = Vec::new();
+    for reviewer in req.reviewers {
+        let reviewer = reviewer.trim();
+        if !reviewer.is_empty() && !reviewers.iter().any(|r| r == reviewer) {
+            reviewers.push(reviewer.to_string());
+        }
+    }
+    if reviewers.is_empty() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "at least one reviewer is required",
+        ));
+    }
+    let store = project_store(&ctx, &state).await?;
+    let review = ReviewPolicy {
+        reviewers,
+        consensus: req.consensus,
+    };
+    let result = store.assign_queue_reviewers(id, review).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Reviewer submissions for an item, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/queue/{id}/submissions",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    responses(
+        (status = 200, body = Vec<QueueSubmission>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_submissions(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+) -> Result<Json<Vec<QueueSubmission>>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let internal = |e: storage::StorageError| api_error(StatusCode::INTERNAL_SERVER_ERROR, e);
+    if store.load_queue_item(id).await.map_err(internal)?.is_none() {
+        return Err(api_error(StatusCode::NOT_FOUND, "queue item not found")
+            .with_code("queue_item_not_found"));
+    }
+    let submissions = store.queue_submissions(id).await.map_err(internal)?;
+    Ok(Json(submissions))
+}
+
+/// Record one reviewer's submission. Returns the item, completed or sent
+/// to adjudication if this was the last submission it was waiting on.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/submissions",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = ReviewRequest,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn submit_review(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    Json(req): Json<ReviewRequest>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let internal = |e: storage::StorageError| api_error(StatusCode::INTERNAL_SERVER_ERROR, e);
+    let Some(item) = store.load_queue_item(id).await.map_err(internal)? else {
+        return Err(api_error(StatusCode::NOT_FOUND, "queue item not found")
+            .with_code("queue_item_not_found"));
+    };
+    let (QueueItemStatus::InReview, Some(review)) = (&item.status, &item.review) else {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            format!("queue item is {}", item.status.as_str()),
+        ));
+    };
+    let reviewer = req
+        .reviewer
+        .filter(|r| !r.trim().is_empty())
+        .unwrap_or_else(|| audit::actor(&ctx));
+    if !review.reviewers.contains(&reviewer) {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            format!("{reviewer} is not a reviewer of this item"),
+        ));
+    }
+    let submissions = store.queue_submissions(id).await.map_err(internal)?;
+    if submissions.iter().any(|s| s.reviewer == reviewer) {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            format!("{reviewer} has already submitted"),
+        ));
+    }
+    let submission = QueueSubmission::new(id, reviewer, req.edited_data);
+    store
+        .save_queue_submission(&submission)
+        .await
+        .map_err(internal)?;
+    match store.resolve_queue_review(id).await.map_err(internal)? {
+        Some(item) => {
+            state.emit_event(
+                SystemEvent::QueueItemUpdated { item: item.clone() },
+                &ctx.org_id.to_string(),
+            );
+            Ok(Json(item))
+        }
+        None => Ok(Json(store.get_queue_item(id).unwrap_or(item))),
+    }
+}
+
+/// Items whose reviewers disagreed, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/queue/adjudication",
+    tag = "queue",
+    params(AdjudicationQuery),
+    responses(
+        (status = 200, body = Vec<QueueItem>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn adjudication_queue(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<AdjudicationQuery>,
+) -> Result<Json<Vec<QueueItem>>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let mut items: Vec<QueueItem> = store
+        .load_queue_items()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .into_iter()
+        .filter(|item| item.status == QueueItemStatus::Adjudication)
+        .filter(|item| q.dataset_id.is_none_or(|id| item.dataset_id == id))
+        .collect();
+    items.sort_by_key(|item| item.created_at);
+    Ok(Json(items))
+}
+
+/// Complete a disputed item with the adjudicator's data.
+#[utoipa::path(
+    post,
+    path = "/api/queue/{id}/adjudicate",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue item id")),
+    request_body = Option<SubmitRequest>,
+    responses(
+        (status = 200, body = QueueItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn adjudicate_item(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<QueueItemId>,
+    body: Option<Json<SubmitRequest>>,
+) -> Result<Json<QueueItem>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let edited_data = body.and_then(|Json(b)| b.edited_data);
+    let result = store.adjudicate_queue_item(id, edited_data).await;
+    transitioned(&state, &ctx, &store, id, result)
+}
+
+/// Per-labeler counts and resolve times, busiest first.
+#[utoipa::path(
+    get,
+    path = "/api/queue/stats",
+    tag = "queue",
+    params(StatsQuery),
+    responses(
+        (status = 200, body = Vec<LabelerStats>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn labeler_stats(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<StatsQuery>,
+) -> Result<Json<Vec<LabelerStats>>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let items = store
+        .load_queue_items()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let items = items
+        .iter()
+        .filter(|item| q.dataset_id.is_none_or(|id| item.dataset_id == id));
+    Ok(Json(compute_stats(items, q.since)))
+}
+
+fn compute_stats<'a>(
+    items: impl Iterator<Item = &'a QueueItem>,
+    since: Option<DateTime<Utc>>,
+) -> Vec<LabelerStats> {
+    let mut by_labeler: BTreeMap<&str, (LabelerStats, f64, usize)> = BTreeMap::new();
+    for item in items {
+        let Some(labeler) = item.claimed_by.as_deref() else {
+            continue;
+        };
+        let (stats, resolve_ms, resolved) = by_labeler.entry(labeler).or_insert_with(|| {
+            let stats = LabelerStats {
+                labeler: labeler.to_string(),
+                ..Default::default()
+            };
+            (stats, 0.0, 0)
+        });
+        if item.status == QueueItemStatus::Claimed {
+            stats.claimed += 1;
+            continue;
+        }
+        let Some(resolved_at) = item.resolved_at else {
+            continue;
+        };
+        if since.is_some_and(|since| resolved_at < since) {
+            continue;
+        }
+        match item.status {
+            QueueItemStatus::Completed => stats.completed += 1,
+            QueueItemStatus::Rejected => stats.rejected += 1,
+            _ => continue,
+        }
+        if let Some(claimed_at) = item.claimed_at {
+            *resolve_ms += (resolved_at - claimed_at).num_milliseconds() as f64;
+            *resolved += 1;
+        }
+        stats.last_resolved_at = stats.last_resolved_at.max(Some(resolved_at));
+    }
+    let mut stats: Vec<LabelerStats> = by_labeler
+        .into_values()
+        .map(|(mut stats, resolve_ms, resolved)| {
+            stats.avg_resolve_ms = (resolved > 0).then(|| resolve_ms / resolved as f64);
+            stats
+        })
+        .collect();
+    stats.sort_by_key(|s| std::cmp::Reverse(s.completed + s.rejected));
+    stats
+}
+
+/// Release expired claims in every open store once. Returns how many were
+/// released.
+pub async fn release_expired(
+    org_stores: &OrgStoreManager,
+    journal: &EventJournal,
+    ttl: Duration,
+) -> usize {
+    let Ok(ttl) = chrono::Duration::from_std(ttl) else {
+        return 0;
+    };
+    let cutoff = Utc::now() - ttl;
+    let mut total = 0;
+    for (org_id, store) in org_stores.all_stores().await {
+        match store.release_expired_queue_items(cutoff).await {
+            Ok(released) => {
+                total += released.len();
+                let org_id = org_id.to_string();
+                for item in released {
+                    journal.emit(&org_id, SystemEvent::QueueItemUpdated { item });
+                }
+            }
+            Err(e) => error!(%org_id, "queue: releasing expired claims failed: {e}"),
+        }
+    }
+    total
+}
+
+/// Spawn the periodic claim expiry. Like the stale span sweeper, it stops
+/// once the journal is dropped.
+pub fn spawn_claim_expiry(
+    org_stores: Arc<OrgStoreManager>,
+    journal: Weak<EventJournal>,
+    config: QueueConfig,
+) -> tokio::task::JoinHandle<()> {
+    let ttl = Duration::from_secs(config.claim_ttl_secs);
+    let period = Duration::from_secs(config.interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            let released = release_expired(&org_stores, &journal, ttl).await;
+            if released > 0 {
+                info!(
+                    released,
+                    claim_ttl_secs = ttl.as_secs(),
+                    "queue: released expired claims"
+                );
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn item(labeler: &str, claimed_mins_ago: i64) -> QueueItem {
+        let mut item = QueueItem::new(Uuid::nil(), Uuid::now_v7(), None).claim(labeler);
+        item.claimed_at = Some(Utc::now() - Duration::minutes(claimed_mins_ago));
+        item
+    }
+
+    #[test]
+    fn stats_count_per_labeler() {
+        let items = [
+            item("ana", 10).complete(None),
+            item("ana", 4).reject("unreadable"),
+            item("ana", 1),
+            item("bo", 2).complete(None),
+            item("bo", 1).release(),
+        ];
+        let stats = compute_stats(items.iter(), None);
+        assert_eq!(stats.len(), 2);
+        let ana = &stats[0];
+        assert_eq!(
+            (
+                ana.labeler.as_str(),
+                ana.claimed,
+                ana.completed,
+                ana.rejected
+            ),
+            ("ana", 1, 1, 1)
+        );
+        // Claimed 10 and 4 minutes ago, resolved just now
+        let avg = ana.avg_resolve_ms.unwrap();
+        assert!((avg - 7.0 * 60_000.0).abs() < 1_000.0, "{avg}");
+        assert_eq!((stats[1].labeler.as_str(), stats[1].completed), ("bo", 1));
+
+        let later = compute_stats(items.iter(), Some(Utc::now() + Duration::minutes(1)));
+        assert_eq!((later[0].completed, later[0].claimed), (0, 1));
+    }
+}
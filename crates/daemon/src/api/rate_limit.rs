@@ -0,0 +1,451 @@
+//! Per-client rate limiting.
+//!
+//! Each client gets a token bucket per class: span and trace writes
+//! (including OTLP) and end-user feedback draw from `ingest`, everything
+//! else from `read`.
+//! Clients are told apart by API key prefix or a hash of their session
+//! token once it verifies, and otherwise by IP address, read through
+//! `X-Forwarded-For` only from trusted proxies. Responses carry
+//! `X-RateLimit-Limit`, `X-RateLimit-Remaining`, and `X-RateLimit-Reset`
+//! (seconds until the bucket is full again); rejected requests get a 429
+//! with `Retry-After`.
+//!
+//! Buckets live in memory by default. In cloud mode they live in Redis so
+//! every instance draws from the same bucket. If the store errors, the
+//! request is let through.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use super::{api_error, auth_keys, AppState};
+use crate::config::{RateLimit, RateLimitConfig};
+
+const LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Memory buckets kept before full ones are dropped.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateClass {
+    Ingest,
+    Read,
+}
+
+impl RateClass {
+    fn of(method: &Method, path: &str) -> Self {
+        let path = path.strip_prefix("/api").unwrap_or(path);
+        let writes_spans = ["/spans", "/traces", "/v1/traces", "/feedback"]
+            .iter()
+            .any(|prefix| path.starts_with(prefix));
+        if writes_spans && method != Method::GET && method != Method::HEAD {
+            RateClass::Ingest
+        } else {
+            RateClass::Read
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RateClass::Ingest => "ingest",
+            RateClass::Read => "read",
+        }
+    }
+}
+
+/// The outcome of taking a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full.
+    pub reset_secs: u64,
+    /// Seconds until a token is available, when denied.
+    pub retry_after_secs: Option<u64>,
+}
+
+impl Decision {
+    fn new(allowed: bool, tokens: f64, limit: &RateLimit) -> Self {
+        let per_ms = refill_per_ms(limit);
+        let secs = |missing: f64| (missing.max(0.0) / per_ms / 1000.0).ceil() as u64;
+        Self {
+            allowed,
+            limit: limit.capacity(),
+            remaining: tokens.floor() as u32,
+            reset_secs: secs(limit.capacity() as f64 - tokens),
+            retry_after_secs: (!allowed).then(|| secs(1.0 - tokens).max(1)),
+        }
+    }
+}
+
+fn refill_per_ms(limit: &RateLimit) -> f64 {
+    limit.per_minute.max(1) as f64 / 60_000.0
+}
+
+/// Refill a bucket holding `tokens` as of `updated_ms`, then take one if
+/// there is one. Returns whether a token was taken and what's left.
+fn take(tokens: f64, updated_ms: u64, now_ms: u64, limit: &RateLimit) -> (bool, f64) {
+    let elapsed = now_ms.saturating_sub(updated_ms) as f64;
+    let tokens = (tokens + elapsed * refill_per_ms(limit)).min(limit.capacity() as f64);
+    if tokens >= 1.0 {
+        (true, tokens - 1.0)
+    } else {
+        (false, tokens)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Where buckets are kept.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token from the bucket at `key`, creating it full.
+    async fn take(&self, key: &str, limit: &RateLimit) -> Result<Decision, String>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_ms: u64,
+    full_at_ms: u64,
+}
+
+/// Buckets in this process only.
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn take(&self, key: &str, limit: &RateLimit) -> Result<Decision, String> {
+        let now = now_ms();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_MEMORY_BUCKETS && !buckets.contains_key(key) {
+            // A full bucket is the same as no bucket
+            buckets.retain(|_, b| b.full_at_ms > now);
+        }
+        let (tokens, updated) = buckets
+            .get(key)
+            .map_or((limit.capacity() as f64, now), |b| (b.tokens, b.updated_ms));
+        let (allowed, tokens) = take(tokens, updated, now, limit);
+        let decision = Decision::new(allowed, tokens, limit);
+        buckets.insert(
+            key.to_string(),
+            Bucket {
+                tokens,
+                updated_ms: now,
+                full_at_ms: now + decision.reset_secs * 1000,
+            },
+        );
+        Ok(decision)
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, store: Arc<dyn RateLimitStore>) -> Self {
+        Self { config, store }
+    }
+
+    pub fn memory(config: RateLimitConfig) -> Self {
+        Self::new(config, Arc::new(MemoryRateLimitStore::default()))
+    }
+
+    fn limit(&self, class: RateClass) -> &RateLimit {
+        match class {
+            RateClass::Ingest => &self.config.ingest,
+            RateClass::Read => &self.config.read,
+        }
+    }
+}
+
+/// The caller the limiter authenticated, left in the request's extensions
+/// so `require_auth` doesn't verify the token again.
+#[derive(Clone)]
+pub(super) struct Authenticated(pub(super) auth::AuthContext);
+
+/// The bearer token, `session` cookie, or query token, in the order
+/// `auth::middleware::authenticate` reads them.
+fn token(headers: &HeaderMap, query_token: Option<&str>) -> Option<String> {
+    match headers.get(header::AUTHORIZATION) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .map(str::to_string),
+        None => headers
+            .get(header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|cookies| {
+                cookies
+                    .split(';')
+                    .find_map(|c| c.trim().strip_prefix("session=").map(str::to_string))
+            })
+            .or_else(|| query_token.map(str::to_string)),
+    }
+}
+
+/// Who a request comes from, as `key:`, `session:`, or `ip:` plus an id.
+/// Only a `caller` that authenticated is told apart by its token; anyone
+/// else shares the bucket of their address.
+fn client_key(
+    caller: Option<&auth::AuthContext>,
+    token: Option<&str>,
+    ip: Option<IpAddr>,
+) -> String {
+    let caller = caller.filter(|c| !c.is_local_mode);
+    if let Some(prefix) = caller.and_then(|c| c.api_key_prefix.as_deref()) {
+        return format!("key:{prefix}");
+    }
+    match (caller, token, ip) {
+        (Some(_), Some(token), _) => {
+            let digest = format!("{:x}", Sha256::digest(token.as_bytes()));
+            format!("session:{}", &digest[..32])
+        }
+        (_, _, Some(ip)) => format!("ip:{ip}"),
+        _ => "ip:unknown".to_string(),
+    }
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert(LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RESET, HeaderValue::from(decision.reset_secs));
+    if let Some(retry_after) = decision.retry_after_secs {
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+}
+
+/// Middleware taking a token for each request.
+pub async fn limit(
+    State((state, limiter)): State<(AppState, Arc<RateLimiter>)>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let class = RateClass::of(request.method(), request.uri().path());
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let headers = request.headers();
+    let query_token = request
+        .uri()
+        .query()
+        .and_then(auth_keys::extract_token_from_query);
+    let caller = auth::middleware::authenticate(
+        headers,
+        query_token.clone(),
+        peer,
+        &state.auth_config,
+        state.api_key_lookup.as_ref(),
+    )
+    .await
+    .ok();
+    let ip = auth::middleware::client_ip(headers, peer, &state.auth_config.trusted_proxies);
+    let token = token(headers, query_token.as_deref());
+    let client = client_key(caller.as_ref(), token.as_deref(), ip);
+    if let Some(caller) = caller {
+        request.extensions_mut().insert(Authenticated(caller));
+    }
+    let key = format!("{}:{}", class.as_str(), client);
+    let decision = match limiter.store.take(&key, limiter.limit(class)).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            warn!("rate limit: store unavailable, allowing request: {e}");
+            return next.run(request).await;
+        }
+    };
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        api_error(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded")
+            .with_code("rate_limited")
+            .into_response()
+    };
+    set_headers(response.headers_mut(), &decision);
+    response
+}
+
+/// Redis-backed buckets shared by every instance.
+#[cfg(feature = "cloud")]
+pub mod cloud {
+    use redis::aio::ConnectionManager;
+
+    use super::*;
+
+    const KEY_PREFIX: &str = "traceway:ratelimit:";
+
+    /// Same refill as [`super::take`], done atomically in Redis.
+    const TAKE_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local per_ms = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * per_ms)
+local allowed = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) / per_ms) + 1000)
+return {allowed, tostring(tokens)}
+"#;
+
+    pub struct RedisRateLimitStore {
+        conn: ConnectionManager,
+        script: redis::Script,
+    }
+
+    impl RedisRateLimitStore {
+        pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
+            let client = redis::Client::open(redis_url)?;
+            Ok(Self {
+                conn: ConnectionManager::new(client).await?,
+                script: redis::Script::new(TAKE_SCRIPT),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl RateLimitStore for RedisRateLimitStore {
+        async fn take(&self, key: &str, limit: &RateLimit) -> Result<Decision, String> {
+            let mut conn = self.conn.clone();
+            let (allowed, tokens): (i64, String) = self
+                .script
+                .key(format!("{KEY_PREFIX}{key}"))
+                .arg(limit.capacity())
+                .arg(refill_per_ms(limit))
+                .arg(now_ms())
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            let tokens = tokens
+                .parse()
+                .map_err(|e| format!("bad token count: {e}"))?;
+            Ok(Decision::new(allowed == 1, tokens, limit))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_drains_then_refills() {
+        let limit = RateLimit {
+            per_minute: 60,
+            burst: Some(2),
+        };
+        let (ok, tokens) = take(2.0, 0, 0, &limit);
+        assert!(ok);
+        let (ok, tokens) = take(tokens, 0, 0, &limit);
+        assert!(ok);
+        let (ok, tokens) = take(tokens, 0, 500, &limit);
+        assert!(!ok);
+        let denied = Decision::new(ok, tokens, &limit);
+        assert_eq!((denied.remaining, denied.retry_after_secs), (0, Some(1)));
+        assert_eq!(denied.reset_secs, 2);
+        // One token a second, capped at the burst
+        let (ok, tokens) = take(tokens, 500, 1000, &limit);
+        assert!(ok);
+        assert_eq!(take(tokens, 1000, 60_000, &limit).1, 1.0);
+    }
+
+    #[test]
+    fn classifies_and_identifies_clients() {
+        assert_eq!(
+            RateClass::of(&Method::POST, "/spans/batch"),
+            RateClass::Ingest
+        );
+        assert_eq!(
+            RateClass::of(&Method::POST, "/v1/traces"),
+            RateClass::Ingest
+        );
+        assert_eq!(
+            RateClass::of(&Method::POST, "/api/feedback"),
+            RateClass::Ingest
+        );
+        assert_eq!(RateClass::of(&Method::GET, "/api/traces"), RateClass::Read);
+        assert_eq!(RateClass::of(&Method::POST, "/analytics"), RateClass::Read);
+
+        let ip = Some("10.0.0.1".parse().unwrap());
+        assert_eq!(client_key(None, None, ip), "ip:10.0.0.1");
+        assert_eq!(client_key(None, None, None), "ip:unknown");
+        // A token that didn't verify doesn't get a bucket of its own
+        assert_eq!(client_key(None, Some("forged"), ip), "ip:10.0.0.1");
+        assert_eq!(
+            client_key(Some(&auth::AuthContext::local()), Some("abc"), ip),
+            "ip:10.0.0.1"
+        );
+
+        let session = auth::AuthContext::from_session(
+            uuid::Uuid::nil(),
+            uuid::Uuid::nil(),
+            uuid::Uuid::nil(),
+            Vec::new(),
+        );
+        assert!(client_key(Some(&session), Some("abc"), ip).starts_with("session:"));
+        let key = auth::AuthContext::from_api_key(uuid::Uuid::nil(), uuid::Uuid::nil(), Vec::new())
+            .with_api_key_prefix("tw_sk_0123456789");
+        assert_eq!(
+            client_key(Some(&key), Some("tw_sk_0123456789abcdef"), ip),
+            "key:tw_sk_0123456789"
+        );
+    }
+
+    #[test]
+    fn reads_tokens_in_auth_order() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(token(&headers, Some("q")), Some("q".to_string()));
+        headers.insert(header::COOKIE, "theme=dark; session=abc".parse().unwrap());
+        assert_eq!(token(&headers, Some("q")), Some("abc".to_string()));
+        headers.insert(header::AUTHORIZATION, "Bearer tw_sk_x".parse().unwrap());
+        assert_eq!(token(&headers, Some("q")), Some("tw_sk_x".to_string()));
+    }
+
+    #[test]
+    fn spoofed_forwarded_for_shares_the_peer_bucket() {
+        let peer: SocketAddr = "198.51.100.7:5000".parse().unwrap();
+        let trusted: Vec<auth::IpCidr> = vec!["10.0.0.0/8".parse().unwrap()];
+        let key = |forwarded: &str, peer: SocketAddr| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", forwarded.parse().unwrap());
+            let ip = auth::middleware::client_ip(&headers, Some(peer), &trusted);
+            client_key(None, None, ip)
+        };
+        // Rotating the header from an untrusted peer doesn't buy new buckets
+        assert_eq!(key("203.0.113.1", peer), "ip:198.51.100.7");
+        assert_eq!(key("203.0.113.2", peer), "ip:198.51.100.7");
+        // Behind a trusted proxy, the hop it appended is the client
+        let proxy: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        assert_eq!(key("203.0.113.1, 192.0.2.4", proxy), "ip:192.0.2.4");
+    }
+}
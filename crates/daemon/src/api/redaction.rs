@@ -0,0 +1,61 @@
+//! Per-org PII redaction settings.
+//!
+//! Orgs start with the daemon's configured redaction (on by default in
+//! cloud mode) and can replace it. Saved settings live in the org-level
+//! store and take effect for spans written afterwards; stored spans are
+//! not rewritten.
+
+use axum::{extract::State, http::StatusCode, Json};
+use storage::{RedactionConfig, Redactor};
+
+use super::{api_error, audit, org_store, require_scope, ApiError, AppState};
+
+/// Settings key the org's redaction config is saved under.
+pub const REDACTION_SETTING: &str = "redaction";
+
+pub async fn get_redaction(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<RedactionConfig>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let config = state
+        .org_stores
+        .redaction_for(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(config))
+}
+
+pub async fn set_redaction(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(config): Json<RedactionConfig>,
+) -> Result<Json<RedactionConfig>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    if let Some(rule) = config.rules.iter().find(|r| r.name.trim().is_empty()) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("rule with pattern '{}' needs a name", rule.pattern),
+        ));
+    }
+    Redactor::new(&config).map_err(|e| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            format!("invalid redaction pattern: {e}"),
+        )
+    })?;
+
+    let store = org_store(&ctx, &state).await?;
+    let value = serde_json::to_value(&config)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    store
+        .save_setting(REDACTION_SETTING, &value)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    state
+        .org_stores
+        .set_org_redaction(ctx.org_id, config.clone())
+        .await;
+    audit::record(&state, &ctx, "redaction.update", None, value).await;
+    Ok(Json(config))
+}
@@ -0,0 +1,245 @@
+//! Re-running a captured LLM call, optionally with a different model,
+//! temperature, or prompt.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use trace::{Span, SpanId, SpanKind};
+
+use super::{api_error, project_store, require_scope, ApiError, AppState};
+use crate::proxy::{
+    ApiShape, Message, NormalizedRequest, PARENT_HEADER, SPAN_ID_HEADER, TRACE_HEADER,
+};
+
+const DEFAULT_PATH: &str = "/v1/chat/completions";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Body of `POST /api/spans/:id/replay`. Unset fields keep the captured values.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplaySpan {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Replaces the captured messages, e.g. with another version of the prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<Message>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayResponse {
+    pub original_id: SpanId,
+    /// Status the upstream provider answered with.
+    pub status: u16,
+    /// The span recorded for the replay, a child of the original.
+    pub span: Option<Span>,
+}
+
+/// The proxy path and request body that replay `span` with `overrides`.
+pub(crate) fn replay_request(
+    span: &Span,
+    overrides: &ReplaySpan,
+) -> Result<(String, Value), String> {
+    let SpanKind::LlmCall {
+        model, provider, ..
+    } = span.kind()
+    else {
+        return Err("only llm_call spans can be replayed".into());
+    };
+    let input = span.input().ok_or("span has no captured input to replay")?;
+
+    // Proxy spans are named "METHOD /path"
+    let path = span
+        .name()
+        .strip_prefix("POST ")
+        .filter(|p| p.starts_with('/'))
+        .map(str::to_string)
+        .unwrap_or_else(|| match provider.as_deref() {
+            Some("anthropic") => "/v1/messages".into(),
+            Some("ollama") => "/api/chat".into(),
+            _ => DEFAULT_PATH.into(),
+        });
+    let model = overrides.model.as_deref().unwrap_or(model);
+
+    let normalized = ApiShape::from_path(&path)
+        .zip(serde_json::from_value::<NormalizedRequest>(input.clone()).ok());
+    let mut body = match normalized {
+        Some((shape, mut req)) => {
+            if let Some(messages) = &overrides.messages {
+                req.messages = messages.clone();
+            }
+            shape.request_body(model, &req)
+        }
+        // Captured as sent, for APIs the proxy doesn't normalize
+        None => {
+            let mut body = input.clone();
+            let object = body
+                .as_object_mut()
+                .ok_or("captured input is not a JSON request body")?;
+            object.insert("model".into(), Value::String(model.to_string()));
+            if let Some(messages) = &overrides.messages {
+                let messages = serde_json::to_value(messages).map_err(|e| e.to_string())?;
+                object.insert("messages".into(), messages);
+            }
+            body
+        }
+    };
+    if let Some(temperature) = overrides.temperature {
+        body["temperature"] = serde_json::json!(temperature);
+    }
+    Ok((path, body))
+}
+
+/// POST `body` to the proxy as a child of `span`. Returns the upstream
+/// status and the id of the span the proxy recorded.
+pub(crate) async fn send(
+    client: &reqwest::Client,
+    proxy_url: &str,
+    span: &Span,
+    path: &str,
+    body: &Value,
+) -> Result<(u16, Option<SpanId>), String> {
+    let mut request = client
+        .post(format!("{}{}", proxy_url.trim_end_matches('/'), path))
+        .header(TRACE_HEADER, span.trace_id().to_string())
+        .header(PARENT_HEADER, span.id().to_string())
+        .json(body);
+    // Captured spans never hold credentials, so use the daemon's own
+    match ApiShape::from_path(path) {
+        Some(ApiShape::AnthropicMessages) => {
+            if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
+                request = request
+                    .header("x-api-key", key)
+                    .header("anthropic-version", ANTHROPIC_VERSION);
+            }
+        }
+        _ => {
+            if let Ok(key) = std::env::var("OPENAI_API_KEY") {
+                request = request.bearer_auth(key);
+            }
+        }
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("proxy request failed: {e}"))?;
+
+    let replay_id = response
+        .headers()
+        .get(SPAN_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<SpanId>().ok());
+    let status = response.status().as_u16();
+    // Wait for the body so the span has completed before it's read back
+    let _ = response.bytes().await;
+    Ok((status, replay_id))
+}
+
+/// Send a captured LLM call through the local proxy again. The replay is
+/// recorded in the original trace as a child of the original span.
+pub async fn replay_span(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SpanId>,
+    body: Option<Json<ReplaySpan>>,
+) -> Result<(StatusCode, Json<ReplayResponse>), ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let Json(overrides) = body.unwrap_or_default();
+    let proxy_url = state.proxy_url.as_deref().ok_or_else(|| {
+        api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "replay needs the local proxy, which is not running",
+        )
+    })?;
+
+    let store = project_store(&ctx, &state).await?;
+    let span = store.get_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span not found").with_code("span_not_found")
+    })?;
+    let (path, body) = replay_request(&span, &overrides)
+        .map_err(|msg| api_error(StatusCode::UNPROCESSABLE_ENTITY, msg))?;
+
+    let (status, replay_id) = send(&reqwest::Client::new(), proxy_url, &span, &path, &body)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    let span = match replay_id {
+        Some(replay_id) => store.get_or_load(replay_id).await,
+        None => None,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ReplayResponse {
+            original_id: id,
+            status,
+            span,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trace::{SpanBuilder, TraceId};
+
+    fn llm_span(name: &str, input: Value) -> Span {
+        let kind = SpanKind::LlmCall {
+            model: "gpt-4o".into(),
+            provider: Some("openai".into()),
+            input_tokens: None,
+            output_tokens: None,
+            cost: None,
+            input_preview: None,
+            output_preview: None,
+        };
+        SpanBuilder::new(TraceId::new_v4(), name, kind)
+            .input(input)
+            .build()
+    }
+
+    #[test]
+    fn replay_applies_overrides() {
+        let span = llm_span(
+            "POST /v1/chat/completions",
+            serde_json::json!({
+                "messages": [{ "role": "user", "content": "hi" }],
+                "params": { "temperature": 0.2, "max_tokens": 10 },
+            }),
+        );
+        let overrides = ReplaySpan {
+            model: Some("gpt-4o-mini".into()),
+            temperature: Some(0.9),
+            messages: Some(vec![Message {
+                role: "user".into(),
+                content: "hello".into(),
+                ..Default::default()
+            }]),
+        };
+        let (path, body) = replay_request(&span, &overrides).unwrap();
+        assert_eq!(path, "/v1/chat/completions");
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(body["temperature"], 0.9);
+        assert_eq!(body["max_tokens"], 10);
+        assert_eq!(body["messages"][0]["content"], "hello");
+        assert_eq!(body["stream"], false);
+
+        let (_, body) = replay_request(&span, &ReplaySpan::default()).unwrap();
+        assert_eq!(body["model"], "gpt-4o");
+        assert_eq!(body["temperature"], 0.2);
+    }
+
+    #[test]
+    fn replay_needs_captured_input() {
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let span = SpanBuilder::new(TraceId::new_v4(), "step", kind).build();
+        assert!(replay_request(&span, &ReplaySpan::default()).is_err());
+    }
+}
@@ -0,0 +1,750 @@
+//! Scheduled digest reports.
+//!
+//! An org's schedules are saved in its settings. Each one emails a digest
+//! of the last day or week (total cost, top models by cost, error rate
+//! against the period before, and the slowest traces) to its recipients
+//! at `hour_utc`, on `weekday` for weekly reports, and posts it to the
+//! org's Slack channel if `slack` is set. The scheduler checks for due
+//! reports every minute; a report missed while the daemon was down is sent
+//! once when it comes back.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Html,
+    Json,
+};
+use chrono::{DateTime, Datelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use storage::{SpanFilter, StorageError, TraceFilter};
+use tokio::sync::Mutex;
+use trace::{OrgId, SpanStatus, TraceId};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::events::EventJournal;
+use super::jobs::{JobOptions, JobQueue};
+use super::slack::{self, SlackIntegration, SlackNotifier};
+use super::{api_error, audit, require_scope, ApiError, AppState, OrgStoreManager, SharedStore};
+
+/// Settings key an org's report schedules are saved under.
+pub const REPORTS_SETTING: &str = "report_schedules";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Rows in the top models and slowest traces tables.
+const TOP_N: usize = 5;
+/// An error rate at least this many times the previous period's is a spike.
+const SPIKE_FACTOR: f64 = 2.0;
+/// Fewer errors than this are never a spike.
+const SPIKE_MIN_ERRORS: usize = 5;
+
+pub type ReportScheduleId = Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFrequency {
+    Daily,
+    Weekly,
+}
+
+impl ReportFrequency {
+    fn period(self) -> chrono::Duration {
+        match self {
+            ReportFrequency::Daily => chrono::Duration::days(1),
+            ReportFrequency::Weekly => chrono::Duration::weeks(1),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ReportFrequency::Daily => "Daily",
+            ReportFrequency::Weekly => "Weekly",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportSchedule {
+    pub id: ReportScheduleId,
+    pub frequency: ReportFrequency,
+    pub recipients: Vec<String>,
+    /// Also post the digest to the org's Slack integration.
+    #[serde(default)]
+    pub slack: bool,
+    /// Hour of day the report is sent, UTC.
+    pub hour_utc: u32,
+    /// Day weekly reports are sent.
+    pub weekday: Weekday,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sent_at: Option<DateTime<Utc>>,
+}
+
+impl ReportSchedule {
+    /// The first scheduled send after `after`.
+    fn next_run(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        let mut day = after.date_naive();
+        loop {
+            let at = day
+                .and_hms_opt(self.hour_utc, 0, 0)
+                .unwrap_or_default()
+                .and_utc();
+            let on_day = self.frequency == ReportFrequency::Daily || day.weekday() == self.weekday;
+            if at > after && on_day {
+                return at;
+            }
+            day = day.succ_opt().unwrap_or(day);
+        }
+    }
+
+    fn due(&self, now: DateTime<Utc>) -> bool {
+        now >= self.next_run(self.last_sent_at.unwrap_or(self.created_at))
+    }
+}
+
+/// Body for `POST /api/reports/schedules`.
+#[derive(Debug, Deserialize)]
+pub struct CreateReportSchedule {
+    pub frequency: ReportFrequency,
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub slack: bool,
+    #[serde(default = "default_hour")]
+    pub hour_utc: u32,
+    #[serde(default = "default_weekday")]
+    pub weekday: Weekday,
+}
+
+fn default_hour() -> u32 {
+    9
+}
+
+fn default_weekday() -> Weekday {
+    Weekday::Mon
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    pub frequency: Option<ReportFrequency>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelSpend {
+    pub model: String,
+    pub calls: usize,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowTrace {
+    pub id: TraceId,
+    pub name: Option<String>,
+    pub duration_ms: i64,
+}
+
+/// What a report covers.
+#[derive(Debug, Clone, Serialize)]
+pub struct Digest {
+    pub frequency: ReportFrequency,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub total_cost: f64,
+    pub span_count: usize,
+    pub error_count: usize,
+    /// Error rate of the period before, for comparison.
+    pub previous_error_rate: f64,
+    pub top_models: Vec<ModelSpend>,
+    pub slowest_traces: Vec<SlowTrace>,
+}
+
+impl Digest {
+    pub fn error_rate(&self) -> f64 {
+        if self.span_count == 0 {
+            0.0
+        } else {
+            self.error_count as f64 / self.span_count as f64
+        }
+    }
+
+    /// Whether errors rose sharply against the period before.
+    pub fn error_spike(&self) -> bool {
+        self.error_count >= SPIKE_MIN_ERRORS
+            && self.error_rate() >= self.previous_error_rate * SPIKE_FACTOR
+    }
+
+    /// Summarize the `frequency` period ending at `to` in `stores`, read
+    /// from their backends so spans and traces the caches have dropped
+    /// count too.
+    pub async fn compute(
+        stores: &[SharedStore],
+        frequency: ReportFrequency,
+        to: DateTime<Utc>,
+    ) -> Result<Self, StorageError> {
+        let from = to - frequency.period();
+        let previous = SpanFilter {
+            since: Some(from - frequency.period()),
+            until: Some(from),
+            ..Default::default()
+        };
+        let current = SpanFilter {
+            since: Some(from),
+            until: Some(to),
+            ..Default::default()
+        };
+
+        let mut digest = Digest {
+            frequency,
+            from,
+            to,
+            total_cost: 0.0,
+            span_count: 0,
+            error_count: 0,
+            previous_error_rate: 0.0,
+            top_models: Vec::new(),
+            slowest_traces: Vec::new(),
+        };
+        let (mut previous_spans, mut previous_errors) = (0usize, 0usize);
+        let mut models: HashMap<String, ModelSpend> = HashMap::new();
+        let traces = TraceFilter {
+            since: Some(from),
+            until: Some(to),
+            ..Default::default()
+        };
+        for store in stores {
+            store
+                .scan_spans(&previous, |span| {
+                    previous_spans += 1;
+                    previous_errors +=
+                        usize::from(matches!(span.status(), SpanStatus::Failed { .. }));
+                })
+                .await?;
+            store
+                .scan_spans(&current, |span| {
+                    digest.span_count += 1;
+                    digest.error_count +=
+                        usize::from(matches!(span.status(), SpanStatus::Failed { .. }));
+                    let cost = span.kind().cost().unwrap_or(0.0);
+                    digest.total_cost += cost;
+                    if let Some(model) = span.kind().model() {
+                        let entry = models
+                            .entry(model.to_string())
+                            .or_insert_with(|| ModelSpend {
+                                model: model.to_string(),
+                                calls: 0,
+                                cost: 0.0,
+                            });
+                        entry.calls += 1;
+                        entry.cost += cost;
+                    }
+                })
+                .await?;
+            store
+                .scan_traces(&traces, |t| {
+                    if t.started_at < from || t.started_at >= to {
+                        return;
+                    }
+                    if let Some(duration_ms) = t.stats.duration_ms() {
+                        digest.slowest_traces.push(SlowTrace {
+                            duration_ms,
+                            id: t.id,
+                            name: t.name.clone(),
+                        });
+                    }
+                })
+                .await?;
+        }
+        if previous_spans > 0 {
+            digest.previous_error_rate = previous_errors as f64 / previous_spans as f64;
+        }
+        digest.top_models = models.into_values().collect();
+        digest
+            .top_models
+            .sort_by(|a, b| b.cost.total_cmp(&a.cost).then(b.calls.cmp(&a.calls)));
+        digest.top_models.truncate(TOP_N);
+        digest
+            .slowest_traces
+            .sort_by_key(|t| std::cmp::Reverse(t.duration_ms));
+        digest.slowest_traces.truncate(TOP_N);
+        Ok(digest)
+    }
+
+    fn subject(&self) -> String {
+        format!(
+            "{} Traceway report: ${:.2} across {} spans",
+            self.frequency.as_str(),
+            self.total_cost,
+            self.span_count
+        )
+    }
+
+    pub fn render_slack(&self, slack: &SlackIntegration) -> String {
+        let mut text = format!(
+            "*{}* {} to {} UTC\nTotal cost: ${:.2} across {} spans\nErrors: {} ({:.1}%, previously {:.1}%)",
+            self.subject(),
+            self.from.format("%Y-%m-%d %H:%M"),
+            self.to.format("%Y-%m-%d %H:%M"),
+            self.total_cost,
+            self.span_count,
+            self.error_count,
+            self.error_rate() * 100.0,
+            self.previous_error_rate * 100.0,
+        );
+        if self.error_spike() {
+            text.push_str("\n:rotating_light: Error spike: the error rate at least doubled.");
+        }
+        if !self.top_models.is_empty() {
+            text.push_str("\n*Top models*");
+            for m in &self.top_models {
+                let _ = write!(
+                    text,
+                    "\n• {}: {} calls, ${:.2}",
+                    slack::escape(&m.model),
+                    m.calls,
+                    m.cost
+                );
+            }
+        }
+        if !self.slowest_traces.is_empty() {
+            text.push_str("\n*Slowest traces*");
+            for t in &self.slowest_traces {
+                let name = t.name.clone().unwrap_or_else(|| t.id.to_string());
+                let _ = write!(
+                    text,
+                    "\n• {}: {:.1}s",
+                    slack.trace_link(t.id, &name),
+                    t.duration_ms as f64 / 1000.0
+                );
+            }
+        }
+        text
+    }
+
+    pub fn render_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<h2>{} report</h2><p>{} to {} (UTC)</p>",
+            self.frequency.as_str(),
+            self.from.format("%Y-%m-%d %H:%M"),
+            self.to.format("%Y-%m-%d %H:%M"),
+        );
+        let _ = write!(
+            html,
+            "<p><strong>Total cost:</strong> ${:.2}<br><strong>Spans:</strong> {}<br>\
+             <strong>Errors:</strong> {} ({:.1}%, previously {:.1}%)</p>",
+            self.total_cost,
+            self.span_count,
+            self.error_count,
+            self.error_rate() * 100.0,
+            self.previous_error_rate * 100.0,
+        );
+        if self.error_spike() {
+            html.push_str("<p><strong>Error spike:</strong> the error rate at least doubled.</p>");
+        }
+
+        html.push_str("<h3>Top models</h3>");
+        if self.top_models.is_empty() {
+            html.push_str("<p>No model calls.</p>");
+        } else {
+            html.push_str("<table><tr><th>Model</th><th>Calls</th><th>Cost</th></tr>");
+            for m in &self.top_models {
+                let _ = write!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>${:.2}</td></tr>",
+                    escape(&m.model),
+                    m.calls,
+                    m.cost
+                );
+            }
+            html.push_str("</table>");
+        }
+
+        html.push_str("<h3>Slowest traces</h3>");
+        if self.slowest_traces.is_empty() {
+            html.push_str("<p>No completed traces.</p>");
+        } else {
+            html.push_str("<table><tr><th>Trace</th><th>Duration</th></tr>");
+            for t in &self.slowest_traces {
+                let name = t.name.clone().unwrap_or_else(|| t.id.to_string());
+                let _ = write!(
+                    html,
+                    "<tr><td>{}</td><td>{:.1}s</td></tr>",
+                    escape(&name),
+                    t.duration_ms as f64 / 1000.0
+                );
+            }
+            html.push_str("</table>");
+        }
+        html
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Report schedules and the senders that deliver them.
+pub struct Reports {
+    org_stores: Arc<OrgStoreManager>,
+    email: Arc<dyn auth::EmailSender>,
+    slack: Arc<SlackNotifier>,
+    /// Serializes changes to saved schedules.
+    edits: Mutex<()>,
+}
+
+impl Reports {
+    pub fn new(
+        org_stores: Arc<OrgStoreManager>,
+        email: Arc<dyn auth::EmailSender>,
+        slack: Arc<SlackNotifier>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            email,
+            slack,
+            edits: Mutex::new(()),
+        })
+    }
+
+    async fn schedules(&self, org_id: OrgId) -> Result<Vec<ReportSchedule>, String> {
+        let store = self.org_stores.get(org_id).await?;
+        match store.get_setting(REPORTS_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn save(&self, org_id: OrgId, schedules: &[ReportSchedule]) -> Result<(), String> {
+        let store = self.org_stores.get(org_id).await?;
+        let value = serde_json::to_value(schedules).map_err(|e| e.to_string())?;
+        store
+            .save_setting(REPORTS_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn digest(&self, org_id: OrgId, frequency: ReportFrequency) -> Result<Digest, String> {
+        let stores = self.org_stores.cached_stores_for_org(org_id).await;
+        Digest::compute(&stores, frequency, Utc::now())
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Send the org's due reports. Returns how many were sent.
+    pub(super) async fn send_due(&self, org_id: OrgId) -> Result<usize, String> {
+        let _edit = self.edits.lock().await;
+        let mut schedules = self.schedules(org_id).await?;
+        let now = Utc::now();
+        let mut sent = 0;
+        for schedule in schedules.iter_mut().filter(|s| s.due(now)) {
+            let digest = match self.digest(org_id, schedule.frequency).await {
+                Ok(digest) => digest,
+                Err(e) => {
+                    // Left due, so it is tried again on the next check
+                    warn!(%org_id, schedule_id = %schedule.id, "failed to compute report: {e}");
+                    continue;
+                }
+            };
+            let html = digest.render_html();
+            for to in &schedule.recipients {
+                let email = auth::Email {
+                    to: to.clone(),
+                    subject: digest.subject(),
+                    html: html.clone(),
+                };
+                if let Err(e) = self.email.send(&email).await {
+                    warn!(%org_id, schedule_id = %schedule.id, "failed to send report: {e}");
+                }
+            }
+            if schedule.slack {
+                match self.slack.integration(org_id).await {
+                    Some(slack) => {
+                        let text = digest.render_slack(&slack);
+                        if let Err(e) = self.slack.post(&slack, &text).await {
+                            warn!(%org_id, schedule_id = %schedule.id, "failed to post report to Slack: {e}");
+                        }
+                    }
+                    None => warn!(%org_id, schedule_id = %schedule.id, "Slack is not configured"),
+                }
+            }
+            // Not retried; a failed send waits for the next period
+            schedule.last_sent_at = Some(now);
+            sent += 1;
+        }
+        if sent > 0 {
+            self.save(org_id, &schedules).await?;
+        }
+        Ok(sent)
+    }
+}
+
+/// Spawn the scheduler. It holds the journal weakly and stops once the
+/// router that owns it is gone. With `jobs`, each org's check is queued as
+/// a `send_digest` job for the workers rather than run here.
+pub fn spawn_report_scheduler(
+    reports: Arc<Reports>,
+    journal: Weak<EventJournal>,
+    jobs: Option<Arc<dyn JobQueue>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if journal.strong_count() == 0 {
+                return;
+            }
+            for (org_id, _) in reports.org_stores.all_stores().await {
+                if let Some(jobs) = &jobs {
+                    let data = serde_json::json!({ "org_id": org_id });
+                    if let Err(e) = jobs.add("send_digest", data, JobOptions::default()).await {
+                        warn!(%org_id, "failed to queue report schedules: {e}");
+                    }
+                    continue;
+                }
+                match reports.send_due(org_id).await {
+                    Ok(0) => {}
+                    Ok(sent) => info!(%org_id, sent, "sent scheduled reports"),
+                    Err(e) => warn!(%org_id, "failed to run report schedules: {e}"),
+                }
+            }
+        }
+    })
+}
+
+// --- Handlers ---
+
+pub async fn create_schedule(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<CreateReportSchedule>,
+) -> Result<(StatusCode, Json<ReportSchedule>), ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    if req.recipients.is_empty() && !req.slack {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "recipients must not be empty unless slack is set",
+        ));
+    }
+    if let Some(bad) = req.recipients.iter().find(|r| !r.contains('@')) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("invalid recipient '{bad}'"),
+        ));
+    }
+    if req.hour_utc > 23 {
+        return Err(api_error(StatusCode::BAD_REQUEST, "hour_utc must be 0-23"));
+    }
+    let schedule = ReportSchedule {
+        id: Uuid::now_v7(),
+        frequency: req.frequency,
+        recipients: req.recipients,
+        slack: req.slack,
+        hour_utc: req.hour_utc,
+        weekday: req.weekday,
+        created_at: Utc::now(),
+        last_sent_at: None,
+    };
+    let reports = &state.reports;
+    {
+        let _edit = reports.edits.lock().await;
+        let mut schedules = reports
+            .schedules(ctx.org_id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        schedules.push(schedule.clone());
+        reports
+            .save(ctx.org_id, &schedules)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "report_schedule.create",
+        Some(schedule.id.to_string()),
+        serde_json::to_value(&schedule).unwrap_or_default(),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(schedule)))
+}
+
+pub async fn list_schedules(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ReportSchedule>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let schedules = state
+        .reports
+        .schedules(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(schedules))
+}
+
+pub async fn delete_schedule(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<ReportScheduleId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let reports = &state.reports;
+    {
+        let _edit = reports.edits.lock().await;
+        let mut schedules = reports
+            .schedules(ctx.org_id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let before = schedules.len();
+        schedules.retain(|s| s.id != id);
+        if schedules.len() == before {
+            return Err(
+                api_error(StatusCode::NOT_FOUND, "report schedule not found")
+                    .with_code("report_schedule_not_found"),
+            );
+        }
+        reports
+            .save(ctx.org_id, &schedules)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "report_schedule.delete",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The report a schedule would send now, as HTML. Weekly unless
+/// `frequency` says otherwise.
+pub async fn preview_report(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<PreviewQuery>,
+) -> Result<Html<String>, ApiError> {
+    require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+    let frequency = q.frequency.unwrap_or(ReportFrequency::Weekly);
+    let digest = state
+        .reports
+        .digest(ctx.org_id, frequency)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Html(digest.render_html()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::{SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+    use crate::api::AnyBackend;
+
+    #[test]
+    fn weekly_schedule_runs_on_its_weekday() {
+        // 2025-06-04 was a Wednesday
+        let created_at = Utc.with_ymd_and_hms(2025, 6, 4, 12, 0, 0).unwrap();
+        let schedule = ReportSchedule {
+            id: Uuid::now_v7(),
+            frequency: ReportFrequency::Weekly,
+            recipients: vec!["ops@example.com".into()],
+            slack: false,
+            hour_utc: 9,
+            weekday: Weekday::Mon,
+            created_at,
+            last_sent_at: None,
+        };
+        let first = Utc.with_ymd_and_hms(2025, 6, 9, 9, 0, 0).unwrap();
+        assert_eq!(schedule.next_run(created_at), first);
+        assert!(!schedule.due(first - chrono::Duration::minutes(1)));
+        assert!(schedule.due(first));
+        assert_eq!(schedule.next_run(first), first + chrono::Duration::weeks(1));
+    }
+
+    #[tokio::test]
+    async fn digest_summarizes_the_period() {
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let trace = Trace::new(Some("checkout <agent>".into()));
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+        for (model, cost) in [("gpt-4o", 0.5), ("gpt-4o", 0.25), ("claude", 1.0)] {
+            let kind = SpanKind::LlmCall {
+                model: model.into(),
+                provider: None,
+                input_tokens: None,
+                output_tokens: None,
+                cost: Some(cost),
+                input_preview: None,
+                output_preview: None,
+            };
+            let id = store
+                .insert(SpanBuilder::new(trace_id, "call", kind).build())
+                .await
+                .unwrap();
+            store.complete_span(id, None).await.unwrap();
+        }
+
+        let to = Utc::now() + chrono::Duration::seconds(1);
+        let digest = Digest::compute(&[store], ReportFrequency::Daily, to)
+            .await
+            .unwrap();
+        assert_eq!(digest.span_count, 3);
+        assert_eq!(digest.total_cost, 1.75);
+        assert_eq!(digest.top_models[0].model, "claude");
+        assert_eq!(digest.top_models[1].calls, 2);
+        assert_eq!(digest.slowest_traces[0].id, trace_id);
+        assert!(!digest.error_spike());
+        assert!(digest.render_html().contains("checkout &lt;agent&gt;"));
+    }
+
+    #[tokio::test]
+    async fn digest_reads_spans_outside_the_cache() {
+        let dir = std::env::temp_dir().join(format!("reports-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let writer = PersistentStore::open(AnyBackend::Sqlite(SqliteBackend::open(&db).unwrap()))
+            .await
+            .unwrap();
+        let trace = Trace::new(Some("nightly".into()));
+        let trace_id = trace.id;
+        writer.save_trace(trace).await.unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let id = writer
+            .insert(SpanBuilder::new(trace_id, "step", kind).build())
+            .await
+            .unwrap();
+        writer.complete_span(id, None).await.unwrap();
+        drop(writer);
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(&db).unwrap());
+        let store = Arc::new(PersistentStore::open_lazy(backend).await.unwrap());
+        let to = Utc::now() + chrono::Duration::seconds(1);
+        let digest = Digest::compute(&[store], ReportFrequency::Daily, to)
+            .await
+            .unwrap();
+        assert_eq!(digest.span_count, 1);
+        assert_eq!(digest.slowest_traces[0].id, trace_id);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
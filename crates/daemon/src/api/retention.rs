@@ -0,0 +1,466 @@
+//! Retention pruning.
+//!
+//! A background task periodically deletes traces, spans, and file version
+//! records older than the retention window, and audit log events older
+//! than the (longer) audit window. File content that no remaining version
+//! refers to is then garbage collected. Local mode uses the configured
+//! window for the single store. In cloud mode each org's window comes from
+//! its plan when an auth store is available to look it up, and applies to
+//! every open project store of that org.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use storage::{GcReport, PruneReport, StorageError};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use super::jobs::{JobOptions, JobQueue};
+use super::{
+    api_error, audit, project_store, require_scope, ApiError, AppState, OrgStoreManager,
+    SharedStore,
+};
+use crate::config::RetentionConfig;
+
+pub struct RetentionPolicy {
+    config: RetentionConfig,
+    auth_store: Option<Arc<dyn auth::AuthStore>>,
+    plan: Option<auth::Plan>,
+}
+
+impl RetentionPolicy {
+    pub fn new(config: RetentionConfig) -> Self {
+        Self {
+            config,
+            auth_store: None,
+            plan: None,
+        }
+    }
+
+    /// Use this plan's window for every org (plan simulation).
+    pub fn with_plan(mut self, plan: auth::Plan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    /// Look org plans up here so each org gets `Plan::retention_days()`.
+    #[cfg(feature = "cloud")]
+    pub fn with_auth_store(mut self, store: Arc<dyn auth::AuthStore>) -> Self {
+        self.auth_store = Some(store);
+        self
+    }
+
+    async fn plan_for_org(&self, org_id: auth::OrgId) -> Option<auth::Plan> {
+        if self.plan.is_some() {
+            return self.plan;
+        }
+        match self.auth_store.as_ref()?.get_org(org_id).await {
+            Ok(org) => org.map(|org| org.plan),
+            Err(e) => {
+                warn!(%org_id, "retention: failed to look up org plan: {e}");
+                None
+            }
+        }
+    }
+
+    /// The org's plan window, or the configured window if the plan can't be
+    /// looked up.
+    pub async fn days_for_org(&self, org_id: auth::OrgId) -> u32 {
+        self.plan_for_org(org_id)
+            .await
+            .map_or(self.config.days, |plan| plan.retention_days())
+    }
+
+    /// Like `days_for_org`, for audit log events.
+    pub async fn audit_days_for_org(&self, org_id: auth::OrgId) -> u32 {
+        self.plan_for_org(org_id)
+            .await
+            .map_or(self.config.audit_days, |plan| plan.audit_log_days())
+    }
+}
+
+fn cutoff(days: u32) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::days(i64::from(days))
+}
+
+async fn prune_store(
+    store: &SharedStore,
+    days: u32,
+    dry_run: bool,
+) -> Result<PruneReport, StorageError> {
+    store.prune_before(cutoff(days), dry_run).await
+}
+
+/// Prune every open store once.
+pub async fn prune_all(org_stores: &OrgStoreManager, policy: &RetentionPolicy) {
+    for (org_id, store) in org_stores.all_stores().await {
+        prune_org_store(org_id, &store, policy).await;
+    }
+}
+
+/// Prune one org's open stores once. Returns how many were pruned.
+pub async fn prune_org(
+    org_stores: &OrgStoreManager,
+    policy: &RetentionPolicy,
+    org_id: auth::OrgId,
+) -> usize {
+    let stores = org_stores.cached_stores_for_org(org_id).await;
+    for store in &stores {
+        prune_org_store(org_id, store, policy).await;
+    }
+    stores.len()
+}
+
+async fn prune_org_store(org_id: auth::OrgId, store: &SharedStore, policy: &RetentionPolicy) {
+    let dry_run = policy.config.dry_run;
+    let days = policy.days_for_org(org_id).await;
+    match prune_store(store, days, dry_run).await {
+        Ok(r) if r.traces + r.spans + r.file_versions == 0 => {}
+        Ok(r) => info!(
+            %org_id,
+            days,
+            dry_run,
+            traces = r.traces,
+            spans = r.spans,
+            file_versions = r.file_versions,
+            "retention: pruned expired data"
+        ),
+        Err(e) => error!(%org_id, "retention: prune failed: {e}"),
+    }
+    if dry_run {
+        return;
+    }
+    match store.gc_file_contents(false).await {
+        Ok(r) if r.blobs_deleted == 0 => {}
+        Ok(r) => info!(
+            %org_id,
+            blobs = r.blobs_deleted,
+            bytes = r.bytes_reclaimed,
+            "retention: deleted unreferenced file content"
+        ),
+        Err(e) => error!(%org_id, "retention: file content gc failed: {e}"),
+    }
+    let audit_days = policy.audit_days_for_org(org_id).await;
+    match store.delete_audit_events_before(cutoff(audit_days)).await {
+        Ok(0) => {}
+        Ok(n) => info!(%org_id, audit_days, events = n, "retention: pruned audit log"),
+        Err(e) => error!(%org_id, "retention: audit log prune failed: {e}"),
+    }
+}
+
+/// Spawn the periodic pruning task. Stops when shutdown is signalled. With
+/// `jobs`, each run is queued as a `prune_retention` job for the workers
+/// rather than run here.
+pub fn spawn_retention_task(
+    org_stores: Arc<OrgStoreManager>,
+    policy: Arc<RetentionPolicy>,
+    jobs: Option<Arc<dyn JobQueue>>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> tokio::task::JoinHandle<()> {
+    let period = Duration::from_secs(policy.config.interval_secs.max(60));
+    info!(
+        days = policy
+            .plan
+            .map_or(policy.config.days, |p| p.retention_days()),
+        interval_secs = period.as_secs(),
+        dry_run = policy.config.dry_run,
+        "starting retention task"
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => match &jobs {
+                    Some(jobs) => {
+                        let queued = jobs
+                            .add("prune_retention", serde_json::Value::Null, JobOptions::default())
+                            .await;
+                        if let Err(e) = queued {
+                            error!("retention: failed to queue prune: {e}");
+                        }
+                    }
+                    None => prune_all(&org_stores, &policy).await,
+                },
+                _ = shutdown_rx.changed() => return,
+            }
+        }
+    })
+}
+
+/// Query parameters for `DELETE /api/admin/prune`.
+#[derive(Debug, Default, Deserialize)]
+pub struct PruneQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Prune with a shorter window than the org's retention.
+    pub days: Option<u32>,
+}
+
+/// Prune the caller's project now.
+pub async fn prune(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<PruneQuery>,
+) -> Result<Json<PruneReport>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let max_days = state.retention.days_for_org(ctx.org_id).await;
+    let days = q.days.map_or(max_days, |d| d.min(max_days));
+    if days == 0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "days must be at least 1",
+        ));
+    }
+    let store = project_store(&ctx, &state).await?;
+    let report = prune_store(&store, days, q.dry_run)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !q.dry_run {
+        audit::record(
+            &state,
+            &ctx,
+            "data.prune",
+            None,
+            serde_json::json!({ "days": days, "report": report }),
+        )
+        .await;
+    }
+    Ok(Json(report))
+}
+
+/// Query parameters for `POST /api/admin/gc`.
+#[derive(Debug, Default, Deserialize)]
+pub struct GcQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Delete the caller's project's unreferenced file content now.
+pub async fn gc(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<GcQuery>,
+) -> Result<Json<GcReport>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = project_store(&ctx, &state).await?;
+    let report = store
+        .gc_file_contents(q.dry_run)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !q.dry_run && report.blobs_deleted > 0 {
+        audit::record(
+            &state,
+            &ctx,
+            "data.gc",
+            None,
+            serde_json::json!({ "report": report }),
+        )
+        .await;
+    }
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::FileVersion;
+
+    use super::super::AnyBackend;
+    use super::*;
+
+    async fn open(path: &std::path::Path) -> PersistentStore<AnyBackend> {
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(path).unwrap());
+        PersistentStore::open(backend).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn gc_deletes_unreferenced_content() {
+        let dir = std::env::temp_dir().join(format!("gc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let store = open(&db).await;
+        let kept = trace::content_hash(b"kept");
+        let orphan = trace::content_hash(b"orphan");
+        store.save_file_content(&kept, b"kept").await.unwrap();
+        store.save_file_content(&orphan, b"orphan").await.unwrap();
+        store
+            .save_file_version(FileVersion {
+                hash: kept.clone(),
+                path: "/src/a.rs".into(),
+                size: 4,
+                created_at: Utc::now(),
+                created_by_span: None,
+            })
+            .await
+            .unwrap();
+        // Content saved by this store may still be about to get a version
+        let report = store.gc_file_contents(false).await.unwrap();
+        assert_eq!(report.blobs_deleted, 0);
+        drop(store);
+
+        let store = open(&db).await;
+        let report = store.gc_file_contents(true).await.unwrap();
+        assert_eq!((report.blobs_kept, report.blobs_deleted), (1, 1));
+        assert_eq!(report.bytes_reclaimed, 6);
+        assert!(store.load_file_content(&orphan).await.is_ok());
+
+        store.gc_file_contents(false).await.unwrap();
+        assert!(matches!(
+            store.load_file_content(&orphan).await,
+            Err(StorageError::NotFound)
+        ));
+        assert_eq!(store.load_file_content(&kept).await.unwrap(), b"kept");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gc_collects_payloads_no_span_refers_to() {
+        let dir = std::env::temp_dir().join(format!("gc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+        let input = serde_json::json!({ "prompt": "x".repeat(1000) });
+
+        let config = storage::PayloadOffloadConfig {
+            threshold_bytes: 100,
+            preview_chars: 10,
+        };
+        let store = open(&db).await.with_payload_offload(config);
+        let span = trace::SpanBuilder::new(
+            trace::TraceId::new_v4(),
+            "call",
+            trace::SpanKind::Custom {
+                kind: "step".into(),
+                attributes: Default::default(),
+            },
+        )
+        .input(input.clone())
+        .build();
+        let id = store.insert(span.clone()).await.unwrap();
+        // The same payload in another trace shares the blob
+        let copy = trace::SpanBuilder::new(trace::TraceId::new_v4(), "call", span.kind().clone())
+            .input(input.clone())
+            .build();
+        store.insert(copy.clone()).await.unwrap();
+        drop(store);
+
+        let store = open(&db).await;
+        let report = store.gc_file_contents(false).await.unwrap();
+        assert_eq!(report.blobs_deleted, 0);
+        let span = store.get_or_load(id).await.unwrap();
+        let inline = span.input().unwrap();
+        let reference = storage::PayloadRef::from_value(inline).unwrap();
+        assert_eq!(store.resolve_payload(inline).await.unwrap(), input);
+
+        store.delete_trace(span.trace_id()).await.unwrap();
+        let report = store.gc_file_contents(false).await.unwrap();
+        assert_eq!(report.blobs_deleted, 0);
+        assert_eq!(store.resolve_payload(inline).await.unwrap(), input);
+
+        // Once no span refers to it, the blob goes
+        store.delete_trace(copy.trace_id()).await.unwrap();
+        let report = store.gc_file_contents(false).await.unwrap();
+        assert_eq!(report.blobs_deleted, 1);
+        assert!(matches!(
+            store.load_file_content(&reference.blob_key()).await,
+            Err(StorageError::NotFound)
+        ));
+
+        // Clearing removes payloads without waiting for GC
+        let store = store.with_payload_offload(config);
+        store.insert(copy).await.unwrap();
+        assert!(store.load_file_content(&reference.blob_key()).await.is_ok());
+        store.clear(storage::ClearScope::All).await.unwrap();
+        assert!(matches!(
+            store.load_file_content(&reference.blob_key()).await,
+            Err(StorageError::NotFound)
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removed_traces_take_their_summaries() {
+        use super::super::summaries::summary_setting;
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = PersistentStore::open(backend).await.unwrap();
+        let summary = serde_json::json!({ "summary": "ran" });
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let trace = trace::Trace::new(Some("run".into()));
+            ids.push(trace.id);
+            store.save_trace(trace).await.unwrap();
+            let key = summary_setting(*ids.last().unwrap());
+            store.save_setting(&key, &summary).await.unwrap();
+        }
+        let summaries = |ids: &[trace::TraceId]| {
+            let keys: Vec<String> = ids.iter().map(|id| summary_setting(*id)).collect();
+            let store = &store;
+            async move {
+                let mut found = 0;
+                for key in keys {
+                    found += usize::from(store.get_setting(&key).await.unwrap().is_some());
+                }
+                found
+            }
+        };
+
+        store.delete_trace(ids[0]).await.unwrap();
+        assert_eq!(summaries(&ids).await, 2);
+        store
+            .prune_before(Utc::now() - chrono::Duration::days(1), false)
+            .await
+            .unwrap();
+        assert_eq!(summaries(&ids).await, 2);
+        store
+            .prune_before(Utc::now() + chrono::Duration::seconds(1), false)
+            .await
+            .unwrap();
+        assert_eq!(summaries(&ids).await, 0);
+
+        let trace = trace::Trace::new(Some("run".into()));
+        let id = trace.id;
+        store.save_trace(trace).await.unwrap();
+        store
+            .save_setting(&summary_setting(id), &summary)
+            .await
+            .unwrap();
+        store.clear(storage::ClearScope::All).await.unwrap();
+        assert_eq!(summaries(&[id]).await, 0);
+    }
+
+    #[tokio::test]
+    async fn runs_are_queued_for_workers() {
+        use super::super::jobs::{JobState, MemoryJobQueue};
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let org_stores = Arc::new(OrgStoreManager::single(store));
+        let policy = Arc::new(RetentionPolicy::new(RetentionConfig::default()));
+        let jobs: Arc<dyn JobQueue> = Arc::new(MemoryJobQueue::new());
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let task = spawn_retention_task(org_stores, policy, Some(jobs.clone()), shutdown_rx);
+
+        // The first run is due immediately
+        let mut waiting = Vec::new();
+        for _ in 0..100 {
+            waiting = jobs.list(JobState::Waiting, 10).await.unwrap();
+            if !waiting.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting[0].name, "prune_retention");
+        shutdown_tx.send(true).unwrap();
+        task.await.unwrap();
+    }
+}
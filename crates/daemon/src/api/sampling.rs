@@ -0,0 +1,235 @@
+//! Span and trace sampling.
+//!
+//! Head-based sampling decides each span as it is ingested, at the rate of
+//! the first matching rule. The decision hashes the trace id, so spans of
+//! one kind in one trace are kept or dropped together, and a trace kept at
+//! 10% is also kept at 50%. Spans dropped while still running are simply
+//! not stored; later updates to them fail as for any unknown span. The
+//! proxy decides once a call has finished, so failed calls can be kept.
+//!
+//! Tail-based sampling runs in the background: once a trace has been
+//! complete for `decision_wait_secs`, it is deleted unless it failed, ran
+//! long, or cost enough to keep.
+
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use chrono::Utc;
+use trace::{Span, SpanStatus, Trace, TraceId};
+use tracing::{error, info};
+
+use super::{events::EventJournal, OrgStoreManager, SystemEvent};
+use crate::config::{SampleRule, SamplingConfig};
+use crate::proxy::glob_match;
+
+/// How far back from a tail window's start traces are looked up. Traces
+/// that started earlier are never tail sampled.
+const TAIL_LOOKBACK: chrono::Duration = chrono::Duration::days(1);
+
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    config: SamplingConfig,
+}
+
+impl Sampler {
+    /// `None` when the config keeps everything.
+    pub fn new(config: SamplingConfig) -> Option<Arc<Self>> {
+        let keeps_all = config.default_rate >= 1.0 && config.rules.iter().all(|r| r.rate >= 1.0);
+        (!keeps_all || config.tail.enabled).then(|| Arc::new(Self { config }))
+    }
+
+    pub fn tail_enabled(&self) -> bool {
+        self.config.tail.enabled
+    }
+
+    fn rate(&self, span: &Span) -> f64 {
+        self.config
+            .rules
+            .iter()
+            .find(|rule| rule_matches(rule, span))
+            .map_or(self.config.default_rate, |rule| rule.rate)
+    }
+
+    /// Whether head-based sampling keeps `span`.
+    pub fn keep_span(&self, span: &Span) -> bool {
+        if self.config.keep_errors && matches!(span.status(), SpanStatus::Failed { .. }) {
+            return true;
+        }
+        trace_fraction(span.trace_id()) < self.rate(span)
+    }
+
+    /// Whether tail-based sampling keeps the completed `trace`.
+    pub fn keep_trace(&self, trace: &Trace) -> bool {
+        let tail = &self.config.tail;
+        if self.config.keep_errors && trace.stats.error_count > 0 {
+            return true;
+        }
+        let duration_ms = trace.completed_at().map_or(0, |end| {
+            (end - trace.started_at).num_milliseconds().max(0) as u64
+        });
+        tail.min_duration_ms.is_some_and(|min| duration_ms >= min)
+            || tail
+                .min_cost
+                .is_some_and(|min| trace.stats.total_cost >= min)
+    }
+}
+
+fn rule_matches(rule: &SampleRule, span: &Span) -> bool {
+    rule.kind
+        .as_deref()
+        .is_none_or(|k| span.kind().kind_name() == k)
+        && rule
+            .name
+            .as_deref()
+            .is_none_or(|p| glob_match(p, span.name()))
+        && rule
+            .model
+            .as_deref()
+            .is_none_or(|p| span.kind().model().is_some_and(|m| glob_match(p, m)))
+}
+
+/// Where `trace_id` falls in `[0, 1)`, from its random low bytes.
+fn trace_fraction(trace_id: TraceId) -> f64 {
+    let bytes: [u8; 8] = trace_id.as_bytes()[8..].try_into().unwrap_or_default();
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Delete traces in every open store that completed in `(from, to]` and
+/// that `sampler` doesn't keep. Returns how many were deleted.
+pub async fn tail_sweep(
+    org_stores: &OrgStoreManager,
+    journal: &EventJournal,
+    sampler: &Sampler,
+    from: chrono::DateTime<Utc>,
+    to: chrono::DateTime<Utc>,
+) -> usize {
+    let mut total = 0;
+    for (org_id, store) in org_stores.all_stores().await {
+        let traces = match store
+            .traces_completed_between(from - TAIL_LOOKBACK, from, to)
+            .await
+        {
+            Ok(traces) => traces,
+            Err(e) => {
+                error!(%org_id, "tail sampling: listing traces failed: {e}");
+                continue;
+            }
+        };
+        let org_id = org_id.to_string();
+        for trace in traces.iter().filter(|t| !sampler.keep_trace(t)) {
+            match store.delete_trace(trace.id).await {
+                Ok(_) => {
+                    total += 1;
+                    journal.emit(&org_id, SystemEvent::TraceDeleted { trace_id: trace.id });
+                }
+                Err(e) => {
+                    error!(%org_id, trace_id = %trace.id, "tail sampling: delete failed: {e}")
+                }
+            }
+        }
+    }
+    total
+}
+
+/// Spawn the periodic tail sweep. Each pass judges the traces that
+/// completed since the last one, `decision_wait_secs` behind. It holds the
+/// journal weakly and stops once the router that owns it is gone.
+pub fn spawn_tail_sampler(
+    org_stores: Arc<OrgStoreManager>,
+    journal: Weak<EventJournal>,
+    sampler: Arc<Sampler>,
+) -> tokio::task::JoinHandle<()> {
+    let tail = &sampler.config.tail;
+    let wait = chrono::Duration::seconds(tail.decision_wait_secs as i64);
+    let period = Duration::from_secs(tail.interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut from = Utc::now() - wait;
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            let to = Utc::now() - wait;
+            let dropped = tail_sweep(&org_stores, &journal, &sampler, from, to).await;
+            from = to;
+            if dropped > 0 {
+                info!(dropped, "tail sampling: deleted unremarkable traces");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use trace::{SpanBuilder, SpanKind};
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn config(rules: Vec<SampleRule>) -> SamplingConfig {
+        SamplingConfig {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    fn fs_read(trace_id: TraceId) -> Span {
+        let kind = SpanKind::FsRead {
+            path: "/tmp/x".into(),
+            file_version: None,
+            bytes_read: 1,
+        };
+        SpanBuilder::new(trace_id, "read", kind).build()
+    }
+
+    #[test]
+    fn head_rate_follows_first_matching_rule() {
+        assert!(Sampler::new(SamplingConfig::default()).is_none());
+        let sampler = Sampler::new(config(vec![
+            SampleRule {
+                kind: Some("fs_read".into()),
+                rate: 0.1,
+                ..Default::default()
+            },
+            SampleRule {
+                rate: 1.0,
+                ..Default::default()
+            },
+        ]))
+        .unwrap();
+
+        let kept = (0..2000)
+            .filter(|_| sampler.keep_span(&fs_read(Uuid::new_v4())))
+            .count();
+        assert!((100..300).contains(&kept), "kept {kept} of 2000");
+
+        // Failures are kept whatever the rate
+        let mut dropped = (0..100)
+            .map(|_| fs_read(Uuid::new_v4()))
+            .filter(|s| !sampler.keep_span(s));
+        let span = dropped.next().unwrap();
+        assert!(sampler.keep_span(&span.fail("boom")));
+    }
+
+    #[test]
+    fn tail_keeps_slow_costly_or_failed_traces() {
+        let mut cfg = config(Vec::new());
+        cfg.tail.enabled = true;
+        cfg.tail.min_duration_ms = Some(1000);
+        cfg.tail.min_cost = Some(0.5);
+        let sampler = Sampler::new(cfg).unwrap();
+
+        let mut trace = Trace::new(None);
+        trace.ended_at = Some(trace.started_at + chrono::Duration::milliseconds(200));
+        assert!(!sampler.keep_trace(&trace));
+        trace.stats.total_cost = 0.5;
+        assert!(sampler.keep_trace(&trace));
+        trace.stats.total_cost = 0.0;
+        trace.stats.error_count = 1;
+        assert!(sampler.keep_trace(&trace));
+        trace.stats.error_count = 0;
+        trace.ended_at = Some(trace.started_at + chrono::Duration::seconds(2));
+        assert!(sampler.keep_trace(&trace));
+    }
+}
@@ -0,0 +1,724 @@
+//! Pluggable scorers for eval results.
+//!
+//! A scorer compares an actual output with a datapoint's expected output and
+//! yields a score in `[0, 1]` and a pass/fail verdict. Scores are kept per
+//! result under the scorer's name and summarized per run in
+//! `EvalRunResults::by_scorer`; a result's overall `score` is the mean of its
+//! scorers' and it passes when every scorer passes.
+//!
+//! - `POST /api/eval/runs/:id/score` rescores an existing run's results.
+//! - `POST /api/datasets/:id/score` scores the datapoints exported from
+//!   spans, taking each source span's output as the actual output, and
+//!   records the scores as a new run.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use storage_turbopuffer::{Embedder, EmbeddingConfig};
+use trace::{
+    DatapointKind, DatasetId, EvalConfig, EvalResult, EvalResultStatus, EvalRun, EvalRunId,
+    EvalRunStatus, ScoreSummary, ScorerScore, ScorerSpec, ScoringStrategy,
+};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{api_error, project_store, require_scope, ApiError, AppState, SystemEvent};
+
+#[async_trait]
+pub trait Scorer: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Score `actual` against `expected`. Errors are reported on the result
+    /// rather than failing the whole request.
+    async fn score(&self, actual: &Value, expected: Option<&Value>) -> Result<ScorerScore, String>;
+}
+
+/// Build the scorer for `spec`. `proxy_url` is where LLM judges send their
+/// requests.
+pub fn build(spec: &ScorerSpec, proxy_url: Option<&str>) -> Result<Box<dyn Scorer>, String> {
+    Ok(match spec {
+        ScorerSpec::ExactMatch { ignore_case } => Box::new(ExactMatch {
+            ignore_case: *ignore_case,
+        }),
+        ScorerSpec::Regex { pattern } => Box::new(RegexScorer {
+            pattern: Regex::new(pattern).map_err(|e| format!("invalid regex: {e}"))?,
+        }),
+        ScorerSpec::JsonSubset => Box::new(JsonSubset),
+        ScorerSpec::EmbeddingSimilarity { threshold } => {
+            let config = EmbeddingConfig::from_env()
+                .map_err(|e| e.to_string())?
+                .ok_or("embedding_similarity needs an embedding provider (EMBEDDING_PROVIDER)")?;
+            Box::new(EmbeddingSimilarity {
+                embedder: Embedder::new(reqwest::Client::new(), config),
+                threshold: *threshold,
+            })
+        }
+        ScorerSpec::LlmJudge {
+            model,
+            criteria,
+            threshold,
+            api_key_env,
+        } => {
+            let proxy_url =
+                proxy_url.ok_or("llm_judge needs the local proxy, which is not running")?;
+            let key_env = api_key_env.as_deref().unwrap_or("OPENAI_API_KEY");
+            Box::new(LlmJudge {
+                client: reqwest::Client::new(),
+                url: format!("{}/v1/chat/completions", proxy_url.trim_end_matches('/')),
+                model: model.clone(),
+                criteria: criteria.clone(),
+                threshold: *threshold,
+                api_key: std::env::var(key_env).ok(),
+            })
+        }
+    })
+}
+
+/// The text of an output: strings as-is, the content of a chat message or
+/// an OpenAI or Anthropic completion, otherwise compact JSON.
+pub fn output_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        _ => ["/content", "/choices/0/message/content", "/content/0/text"]
+            .iter()
+            .find_map(|p| value.pointer(p).and_then(Value::as_str))
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string()),
+    }
+}
+
+fn binary(scorer: &str, passed: bool, reason: Option<String>) -> ScorerScore {
+    ScorerScore {
+        scorer: scorer.to_string(),
+        score: if passed { 1.0 } else { 0.0 },
+        passed,
+        reason,
+    }
+}
+
+fn require_expected(expected: Option<&Value>) -> Result<&Value, String> {
+    expected.ok_or_else(|| "datapoint has no expected output".to_string())
+}
+
+pub struct ExactMatch {
+    pub ignore_case: bool,
+}
+
+#[async_trait]
+impl Scorer for ExactMatch {
+    fn name(&self) -> &str {
+        "exact_match"
+    }
+
+    async fn score(&self, actual: &Value, expected: Option<&Value>) -> Result<ScorerScore, String> {
+        let expected = output_text(require_expected(expected)?);
+        let actual = output_text(actual);
+        let (expected, actual) = (expected.trim(), actual.trim());
+        let passed = if self.ignore_case {
+            expected.to_lowercase() == actual.to_lowercase()
+        } else {
+            expected == actual
+        };
+        Ok(binary(self.name(), passed, None))
+    }
+}
+
+pub struct RegexScorer {
+    pub pattern: Regex,
+}
+
+#[async_trait]
+impl Scorer for RegexScorer {
+    fn name(&self) -> &str {
+        "regex"
+    }
+
+    async fn score(
+        &self,
+        actual: &Value,
+        _expected: Option<&Value>,
+    ) -> Result<ScorerScore, String> {
+        let passed = self.pattern.is_match(&output_text(actual));
+        Ok(binary(self.name(), passed, None))
+    }
+}
+
+pub struct JsonSubset;
+
+/// Strings holding JSON are compared as the JSON they hold.
+fn as_json(value: &Value) -> Value {
+    match value {
+        Value::String(s) => serde_json::from_str(s).unwrap_or_else(|_| value.clone()),
+        _ => value.clone(),
+    }
+}
+
+/// `(matched, total)` leaf values of `expected` found at the same path in
+/// `actual`. Arrays are compared by index.
+fn subset_leaves(expected: &Value, actual: Option<&Value>) -> (usize, usize) {
+    let add = |(m, t): (usize, usize), (m2, t2): (usize, usize)| (m + m2, t + t2);
+    match expected {
+        Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .map(|(k, v)| subset_leaves(v, actual.and_then(|a| a.get(k))))
+            .fold((0, 0), add),
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| subset_leaves(v, actual.and_then(|a| a.get(i))))
+            .fold((0, 0), add),
+        leaf => (usize::from(actual == Some(leaf)), 1),
+    }
+}
+
+#[async_trait]
+impl Scorer for JsonSubset {
+    fn name(&self) -> &str {
+        "json_subset"
+    }
+
+    async fn score(&self, actual: &Value, expected: Option<&Value>) -> Result<ScorerScore, String> {
+        let expected = as_json(require_expected(expected)?);
+        // A completion's JSON is usually in its message content.
+        let (matched, total) = [
+            as_json(actual),
+            as_json(&Value::String(output_text(actual))),
+        ]
+        .iter()
+        .map(|a| subset_leaves(&expected, Some(a)))
+        .max()
+        .unwrap_or_default();
+        Ok(ScorerScore {
+            scorer: self.name().to_string(),
+            score: matched as f64 / total.max(1) as f64,
+            passed: matched == total,
+            reason: Some(format!("{matched}/{total} expected values matched")),
+        })
+    }
+}
+
+pub struct EmbeddingSimilarity {
+    pub embedder: Embedder,
+    pub threshold: f64,
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| f64::from(*x) * f64::from(*y))
+        .sum();
+    let norm = |v: &[f32]| v.iter().map(|x| f64::from(*x).powi(2)).sum::<f64>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 {
+        0.0
+    } else {
+        dot / denom
+    }
+}
+
+#[async_trait]
+impl Scorer for EmbeddingSimilarity {
+    fn name(&self) -> &str {
+        "embedding_similarity"
+    }
+
+    async fn score(&self, actual: &Value, expected: Option<&Value>) -> Result<ScorerScore, String> {
+        let expected = output_text(require_expected(expected)?);
+        let actual = output_text(actual);
+        if expected.trim().is_empty() || actual.trim().is_empty() {
+            return Err("cannot embed an empty output".to_string());
+        }
+        let (a, b) = tokio::try_join!(self.embedder.embed(&actual), self.embedder.embed(&expected))
+            .map_err(|e| e.to_string())?;
+        let score = cosine(&a, &b).clamp(0.0, 1.0);
+        Ok(ScorerScore {
+            scorer: self.name().to_string(),
+            score,
+            passed: score >= self.threshold,
+            reason: None,
+        })
+    }
+}
+
+pub struct LlmJudge {
+    client: reqwest::Client,
+    url: String,
+    model: String,
+    criteria: Option<String>,
+    threshold: f64,
+    api_key: Option<String>,
+}
+
+const JUDGE_SYSTEM_PROMPT: &str = "You grade model outputs. Reply with only a JSON object: \
+{\"score\": <number from 0 to 1>, \"reason\": \"<one sentence>\"}.";
+
+#[derive(Deserialize)]
+struct Verdict {
+    score: f64,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// The first JSON object in `text`, tolerating prose or code fences around it.
+fn parse_verdict(text: &str) -> Option<Verdict> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    serde_json::from_str(text.get(start..=end)?).ok()
+}
+
+#[async_trait]
+impl Scorer for LlmJudge {
+    fn name(&self) -> &str {
+        "llm_judge"
+    }
+
+    async fn score(&self, actual: &Value, expected: Option<&Value>) -> Result<ScorerScore, String> {
+        let criteria = self
+            .criteria
+            .as_deref()
+            .unwrap_or("Is the actual output correct and consistent with the expected output?");
+        let mut prompt = format!("Criteria: {criteria}\n\n");
+        if let Some(expected) = expected {
+            prompt.push_str(&format!("Expected output:\n{}\n\n", output_text(expected)));
+        }
+        prompt.push_str(&format!("Actual output:\n{}", output_text(actual)));
+
+        let mut request = self.client.post(&self.url).json(&serde_json::json!({
+            "model": self.model,
+            "temperature": 0,
+            "messages": [
+                { "role": "system", "content": JUDGE_SYSTEM_PROMPT },
+                { "role": "user", "content": prompt },
+            ],
+        }));
+        if let Some(ref key) = self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| format!("judge request failed: {e}"))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!("judge returned {status}: {body}"));
+        }
+        let body: Value = resp
+            .json()
+            .await
+            .map_err(|e| format!("invalid judge response: {e}"))?;
+        let text = output_text(&body);
+        let verdict =
+            parse_verdict(&text).ok_or_else(|| format!("judge did not return a score: {text}"))?;
+        let score = verdict.score.clamp(0.0, 1.0);
+        Ok(ScorerScore {
+            scorer: self.name().to_string(),
+            score,
+            passed: score >= self.threshold,
+            reason: verdict.reason,
+        })
+    }
+}
+
+/// Run every scorer on `result` and set its scores, overall score, and
+/// status. Results without an output (the model call failed or was
+/// skipped) are left alone.
+async fn score_result(
+    scorers: &[Box<dyn Scorer>],
+    result: &mut EvalResult,
+    expected: Option<&Value>,
+) {
+    if result.actual_output.is_null() {
+        return;
+    }
+    let outcomes = futures::future::join_all(
+        scorers
+            .iter()
+            .map(|s| s.score(&result.actual_output, expected)),
+    )
+    .await;
+
+    let mut scores = Vec::new();
+    let mut errors = Vec::new();
+    for (scorer, outcome) in scorers.iter().zip(outcomes) {
+        match outcome {
+            Ok(score) => scores.push(score),
+            Err(e) => errors.push(format!("{}: {e}", scorer.name())),
+        }
+    }
+    result.score = (!scores.is_empty())
+        .then(|| scores.iter().map(|s| s.score).sum::<f64>() / scores.len() as f64);
+    result.score_reason = None;
+    if errors.is_empty() {
+        result.status = if scores.iter().all(|s| s.passed) {
+            EvalResultStatus::Passed
+        } else {
+            EvalResultStatus::Failed
+        };
+        result.error = None;
+    } else {
+        result.status = EvalResultStatus::Error;
+        result.error = Some(errors.join("; "));
+    }
+    result.scores = scores;
+}
+
+/// Summary of `(score, passed)` pairs; `None` fields when there are none.
+pub fn summarize(scores: &[(f64, bool)]) -> ScoreSummary {
+    if scores.is_empty() {
+        return ScoreSummary::default();
+    }
+    let mut values: Vec<f64> = scores.iter().map(|(s, _)| *s).collect();
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    let median = if n.is_multiple_of(2) {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    } else {
+        values[n / 2]
+    };
+    let passed = scores.iter().filter(|(_, p)| *p).count();
+    ScoreSummary {
+        mean: Some(values.iter().sum::<f64>() / n as f64),
+        median: Some(median),
+        min: values.first().copied(),
+        max: values.last().copied(),
+        pass_rate: Some(passed as f64 / n as f64),
+    }
+}
+
+/// Recompute `run`'s overall and per-scorer summaries from `results`.
+fn aggregate(run: &mut EvalRun, results: &[EvalResult]) {
+    let overall: Vec<(f64, bool)> = results
+        .iter()
+        .filter_map(|r| Some((r.score?, r.status == EvalResultStatus::Passed)))
+        .collect();
+    let mut by_scorer: BTreeMap<String, Vec<(f64, bool)>> = BTreeMap::new();
+    for score in results.iter().flat_map(|r| &r.scores) {
+        by_scorer
+            .entry(score.scorer.clone())
+            .or_default()
+            .push((score.score, score.passed));
+    }
+    run.results.scores = summarize(&overall);
+    run.results.by_scorer = by_scorer
+        .into_iter()
+        .map(|(name, scores)| (name, summarize(&scores)))
+        .collect();
+}
+
+/// Body for both scoring endpoints.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScoreRequest {
+    pub scorers: Vec<ScorerSpec>,
+    /// Name of the run `POST /api/datasets/:id/score` creates.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ScoredRun {
+    pub run: EvalRun,
+    pub results: Vec<EvalResult>,
+}
+
+fn build_all(specs: &[ScorerSpec], state: &AppState) -> Result<Vec<Box<dyn Scorer>>, ApiError> {
+    if specs.is_empty() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "at least one scorer is required",
+        ));
+    }
+    let mut seen = HashSet::new();
+    if let Some(dup) = specs.iter().find(|s| !seen.insert(s.name())) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("scorer {} given more than once", dup.name()),
+        ));
+    }
+    specs
+        .iter()
+        .map(|spec| build(spec, state.proxy_url.as_deref()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))
+}
+
+/// The expected output of a datapoint, if it has one.
+fn expected_output(kind: &DatapointKind) -> Option<Value> {
+    match kind {
+        DatapointKind::LlmConversation { expected, .. } => {
+            expected.as_ref().map(|m| Value::String(m.content.clone()))
+        }
+        DatapointKind::Generic {
+            expected_output, ..
+        } => expected_output.clone(),
+    }
+}
+
+/// Score an eval run's results, replacing earlier scores.
+#[utoipa::path(
+    post,
+    path = "/api/eval/runs/{id}/score",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Eval run id")),
+    request_body = ScoreRequest,
+    responses(
+        (status = 200, body = ScoredRun),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn score_run(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<EvalRunId>,
+    Json(req): Json<ScoreRequest>,
+) -> Result<Json<ScoredRun>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let scorers = build_all(&req.scorers, &state)?;
+    let store = project_store(&ctx, &state).await?;
+    let mut run = store.get_eval_run(id).ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "eval run not found").with_code("eval_run_not_found")
+    })?;
+
+    store.sync_datapoints_for_dataset(run.dataset_id).await;
+    let expected: HashMap<_, _> = store
+        .datapoints_for_dataset(run.dataset_id)
+        .into_iter()
+        .map(|dp| (dp.id, expected_output(&dp.kind)))
+        .collect();
+
+    let mut results = store.eval_results_for_run(id);
+    results.sort_by_key(|r| r.datapoint_id);
+    for result in &mut results {
+        let expected = expected.get(&result.datapoint_id).cloned().flatten();
+        score_result(&scorers, result, expected.as_ref()).await;
+        store
+            .save_eval_result(result.clone())
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+
+    run.scoring = ScoringStrategy::Scorers;
+    aggregate(&mut run, &results);
+    store
+        .save_eval_run(run.clone())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.emit_event(
+        SystemEvent::EvalRunUpdated { run: run.clone() },
+        &ctx.org_id.to_string(),
+    );
+    Ok(Json(ScoredRun { run, results }))
+}
+
+/// Score a dataset's span-exported datapoints as a new run. The actual
+/// output is the datapoint's `actual_output` when set, else the source
+/// span's output. Nothing is re-run, so the run's `config.model` is empty.
+#[utoipa::path(
+    post,
+    path = "/api/datasets/{id}/score",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    request_body = ScoreRequest,
+    responses(
+        (status = 200, body = ScoredRun),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn score_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+    Json(req): Json<ScoreRequest>,
+) -> Result<Json<ScoredRun>, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let scorers = build_all(&req.scorers, &state)?;
+    let store = project_store(&ctx, &state).await?;
+    store.get_dataset_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "dataset not found").with_code("dataset_not_found")
+    })?;
+    store.sync_datapoints_for_dataset(id).await;
+    let mut datapoints: Vec<_> = store
+        .datapoints_for_dataset(id)
+        .into_iter()
+        .filter(|dp| dp.source_span_id.is_some())
+        .collect();
+    if datapoints.is_empty() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "dataset has no datapoints exported from spans",
+        ));
+    }
+    datapoints.sort_by_key(|dp| dp.id);
+
+    let config = EvalConfig {
+        model: String::new(),
+        provider: None,
+        provider_url: None,
+        api_key_env: None,
+        provider_connection_id: None,
+        system_prompt: None,
+        temperature: None,
+        max_tokens: None,
+        extra: None,
+    };
+    let mut run = EvalRun::new(id, req.name, config, ScoringStrategy::Scorers);
+
+    let mut results = Vec::with_capacity(datapoints.len());
+    for dp in &datapoints {
+        let mut result = EvalResult::new(run.id, dp.id);
+        result.span_id = dp.source_span_id;
+        let span = match dp.source_span_id {
+            Some(span_id) => store.get_or_load(span_id).await,
+            None => None,
+        };
+        if let Some(ref span) = span {
+            result.latency_ms = span.duration_ms().unwrap_or(0).max(0) as u64;
+            result.input_tokens = span.kind().input_tokens().map(|t| t as u32);
+            result.output_tokens = span.kind().output_tokens().map(|t| t as u32);
+        }
+        let actual = match &dp.kind {
+            DatapointKind::Generic {
+                actual_output: Some(actual),
+                ..
+            } => Some(actual.clone()),
+            _ => span.as_ref().and_then(|s| s.output().cloned()),
+        };
+        match actual {
+            Some(actual) => {
+                result.actual_output = actual;
+                score_result(&scorers, &mut result, expected_output(&dp.kind).as_ref()).await;
+            }
+            None => result.error = Some("source span has no output".to_string()),
+        }
+        store
+            .save_eval_result(result.clone())
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        results.push(result);
+    }
+
+    let count = |status: EvalResultStatus| results.iter().filter(|r| r.status == status).count();
+    run.results.total = results.len();
+    run.results.completed = count(EvalResultStatus::Passed) + count(EvalResultStatus::Failed);
+    run.results.failed = count(EvalResultStatus::Error);
+    aggregate(&mut run, &results);
+    run.status = EvalRunStatus::Completed;
+    run.completed_at = Some(Utc::now());
+    store
+        .save_eval_run(run.clone())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.emit_event(
+        SystemEvent::EvalRunCompleted { run: run.clone() },
+        &ctx.org_id.to_string(),
+    );
+    Ok(Json(ScoredRun { run, results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn run(scorer: &dyn Scorer, actual: Value, expected: Value) -> ScorerScore {
+        scorer.score(&actual, Some(&expected)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn exact_match_reads_message_content() {
+        let scorer = ExactMatch { ignore_case: true };
+        let completion = json!({ "choices": [{ "message": { "content": " Paris " } }] });
+        assert!(run(&scorer, completion, json!("paris")).await.passed);
+        assert!(!run(&scorer, json!("Lyon"), json!("Paris")).await.passed);
+        assert!(scorer.score(&json!("Paris"), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn regex_ignores_expected() {
+        let scorer = build(
+            &ScorerSpec::Regex {
+                pattern: r"^\d{4}$".into(),
+            },
+            None,
+        )
+        .unwrap();
+        assert!(scorer.score(&json!("2024"), None).await.unwrap().passed);
+        assert!(
+            !scorer
+                .score(&json!("year 2024"), None)
+                .await
+                .unwrap()
+                .passed
+        );
+    }
+
+    #[tokio::test]
+    async fn json_subset_scores_matched_fraction() {
+        let expected = json!({ "city": "Paris", "tags": ["a", "b"], "meta": { "ok": true } });
+        let actual = json!({ "content": r#"{"city": "Paris", "tags": ["a", "c"], "meta": {"ok": true}, "extra": 1}"# });
+        let score = run(&JsonSubset, actual, expected.clone()).await;
+        assert_eq!(score.score, 0.75);
+        assert!(!score.passed);
+        assert!(run(&JsonSubset, expected.clone(), expected).await.passed);
+    }
+
+    #[test]
+    fn judge_verdict_survives_prose() {
+        let v =
+            parse_verdict("Sure!\n```json\n{\"score\": 0.9, \"reason\": \"close\"}\n```").unwrap();
+        assert_eq!(v.score, 0.9);
+        assert_eq!(v.reason.as_deref(), Some("close"));
+        assert!(parse_verdict("no idea").is_none());
+    }
+
+    #[test]
+    fn summaries_per_scorer() {
+        let mut run = EvalRun::new(
+            uuid::Uuid::now_v7(),
+            None,
+            serde_json::from_value(json!({ "model": "" })).unwrap(),
+            ScoringStrategy::Scorers,
+        );
+        let result = |scores: &[(&str, f64, bool)]| {
+            let mut r = EvalResult::new(run.id, uuid::Uuid::now_v7());
+            r.scores = scores
+                .iter()
+                .map(|(name, score, passed)| ScorerScore {
+                    scorer: name.to_string(),
+                    score: *score,
+                    passed: *passed,
+                    reason: None,
+                })
+                .collect();
+            r.score = Some(r.scores.iter().map(|s| s.score).sum::<f64>() / r.scores.len() as f64);
+            r.status = if r.scores.iter().all(|s| s.passed) {
+                EvalResultStatus::Passed
+            } else {
+                EvalResultStatus::Failed
+            };
+            r
+        };
+        let results = vec![
+            result(&[("exact_match", 1.0, true), ("json_subset", 1.0, true)]),
+            result(&[("exact_match", 0.0, false), ("json_subset", 0.5, false)]),
+        ];
+        aggregate(&mut run, &results);
+        assert_eq!(run.results.scores.mean, Some(0.625));
+        assert_eq!(run.results.scores.pass_rate, Some(0.5));
+        let json_subset = &run.results.by_scorer["json_subset"];
+        assert_eq!(json_subset.median, Some(0.75));
+        assert_eq!(json_subset.min, Some(0.5));
+        assert_eq!(run.results.by_scorer["exact_match"].pass_rate, Some(0.5));
+    }
+}
@@ -0,0 +1,270 @@
+//! Span search.
+//!
+//! - `POST /api/search/semantic` ranks spans by similarity to a query.
+//! - `POST /api/search/nl` has the `[llm]` model turn a question into a
+//!   span filter or an analytics query, runs it, and returns both, so the
+//!   query can be shown and edited.
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{Page, ScoredSpan, SpanFilter, StorageError, TraceFilter};
+use trace::{AnalyticsQuery, AnalyticsResponse, FacetCount, Span, TraceId};
+
+use super::llm::json_object;
+use super::spans::ListSpansQuery;
+use super::{
+    analytics, api_error, project_store, require_scope, ApiError, AppState, MAX_PAGE_LIMIT,
+};
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_QUESTION_CHARS: usize = 1000;
+/// Models and providers named to the model, most used first.
+const MAX_VOCABULARY: usize = 50;
+
+const NL_SYSTEM_PROMPT: &str = r#"You turn questions about the spans recorded from an LLM application into a query. Reply with only a JSON object of one of two shapes.
+
+To list spans: {"type": "spans", "filter": {...}}. The filter may set:
+- kind: llm_call, tool_call, agent_step, retrieval, embedding, guardrail, fs_read, fs_write, or a custom kind
+- model, provider: exact names, chosen from those in use
+- status: running, completed, or failed
+- tool (tool name of tool_call spans), index (index of retrieval spans), verdict (pass, fail, or flag for guardrail spans)
+- name_contains, and q for text anywhere in a span's name, input, or output
+- since, until: RFC 3339 timestamps bounding when spans started
+- duration_min, duration_max: milliseconds
+- cost_min: US dollars
+- score_min, score_max, label: annotations on the span
+- limit, sort (started_at, duration, tokens, cost, or name), order (asc or desc)
+
+To compute metrics: {"type": "analytics", "query": {"metrics": [...], "group_by": [...], "filter": {...}}}.
+- metrics: total_cost, total_input_tokens, total_output_tokens, total_tokens, avg_latency_ms, span_count, error_count, p50_latency_ms, p95_latency_ms, p99_latency_ms
+- group_by: model, provider, kind, status, trace, day, hour, name, tool, index, tag
+- filter may set kind, model, provider, status, since, and until as above
+
+Answer questions about totals, averages, counts, or breakdowns with analytics, and questions asking for spans with spans. Leave out fields the question doesn't constrain."#;
+
+/// Body for `POST /api/search/semantic`. Filter fields narrow the candidate
+/// spans before ranking.
+#[derive(Debug, Deserialize)]
+pub struct SemanticSearchRequest {
+    pub query: String,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub trace_id: Option<TraceId>,
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Spans ranked by similarity to the query, closest first.
+pub async fn semantic_search(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<SemanticSearchRequest>,
+) -> Result<Json<Vec<ScoredSpan>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    state.require_feature(auth::Feature::SemanticSearch)?;
+    let query = req.query.trim();
+    if query.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "query must not be empty"));
+    }
+    let limit = req
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let filter = SpanFilter {
+        trace_id: req.trace_id,
+        kind: req.kind,
+        model: req.model,
+        provider: req.provider,
+        status: req.status,
+        since: req.since,
+        until: req.until,
+        ..Default::default()
+    };
+
+    let store = project_store(&ctx, &state).await?;
+    let results = store
+        .semantic_search(query, &filter, limit)
+        .await
+        .map_err(|e| match e {
+            StorageError::Unsupported(_) => api_error(StatusCode::NOT_IMPLEMENTED, e),
+            _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+        })?;
+    Ok(Json(results))
+}
+
+/// Body for `POST /api/search/nl`.
+#[derive(Debug, Deserialize)]
+pub struct NlSearchRequest {
+    pub question: String,
+}
+
+/// A question translated into the query it stands for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NlQuery {
+    /// Spans, filtered as by `GET /api/spans`.
+    Spans { filter: Box<ListSpansQuery> },
+    /// Metrics, as computed by `POST /api/analytics`.
+    Analytics { query: AnalyticsQuery },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum NlResults {
+    Spans(Page<Span>),
+    Analytics(AnalyticsResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct NlSearchResponse {
+    pub query: NlQuery,
+    pub results: NlResults,
+}
+
+/// What the model is told besides the question: the current time, so it
+/// can resolve "yesterday", and the models and providers it can filter on.
+fn nl_context(now: DateTime<Utc>, models: &[FacetCount], providers: &[FacetCount]) -> String {
+    let names = |facets: &[FacetCount]| {
+        let names: Vec<&str> = facets
+            .iter()
+            .take(MAX_VOCABULARY)
+            .map(|f| f.value.as_str())
+            .collect();
+        if names.is_empty() {
+            "none recorded".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    format!(
+        "Current time: {}\nModels in use: {}\nProviders in use: {}\n",
+        now.to_rfc3339(),
+        names(models),
+        names(providers)
+    )
+}
+
+/// Answer a question in plain language, such as "failed claude calls that
+/// cost more than $0.50 yesterday", with the span filter or analytics query
+/// the model translates it into.
+pub async fn nl_search(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<NlSearchRequest>,
+) -> Result<Json<NlSearchResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let question = req.question.trim();
+    if question.is_empty() || question.chars().count() > MAX_QUESTION_CHARS {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("question must be 1 to {MAX_QUESTION_CHARS} characters"),
+        ));
+    }
+    let proxy_url = state.proxy_url.as_deref().ok_or_else(|| {
+        api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "natural-language search needs the local proxy, which is not running",
+        )
+    })?;
+
+    let store = project_store(&ctx, &state).await?;
+    let facets = store
+        .query_trace_facets(&TraceFilter::default(), None)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let prompt = format!(
+        "{}\nQuestion: {question}",
+        nl_context(Utc::now(), &facets.models, &facets.providers)
+    );
+    let reply = state
+        .llm
+        .complete(proxy_url, NL_SYSTEM_PROMPT, &prompt)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e).with_code("translation_failed"))?;
+    let query: NlQuery = json_object(&reply).ok_or_else(|| {
+        api_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("could not turn the question into a query: {reply}"),
+        )
+        .with_code("question_not_understood")
+    })?;
+
+    let results = match &query {
+        NlQuery::Spans { filter } => {
+            let filter = SpanFilter::from(ListSpansQuery {
+                cursor: None,
+                fields: None,
+                ..(**filter).clone()
+            });
+            let page = store
+                .query_spans(&filter)
+                .await
+                .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+            NlResults::Spans(page)
+        }
+        NlQuery::Analytics { query } => {
+            require_scope(&ctx, auth::Scope::AnalyticsRead)?;
+            NlResults::Analytics(analytics::run_query(&ctx, &state, query).await?)
+        }
+    };
+    Ok(Json(NlSearchResponse { query, results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use trace::{AnalyticsMetric, GroupByField};
+
+    use super::*;
+
+    #[test]
+    fn replies_parse_into_either_query() {
+        let reply = r#"```json
+{"type": "spans", "filter": {"provider": "anthropic", "status": "failed", "cost_min": 0.5, "since": "2026-10-15T00:00:00Z"}}
+```"#;
+        let Some(NlQuery::Spans { filter }) = json_object(reply) else {
+            panic!("not a span query: {reply}");
+        };
+        let filter = SpanFilter::from(*filter);
+        assert_eq!(filter.provider.as_deref(), Some("anthropic"));
+        assert_eq!(filter.status.as_deref(), Some("failed"));
+        assert_eq!(filter.cost_min, Some(0.5));
+        assert!(filter.since.is_some() && filter.until.is_none());
+
+        let reply =
+            r#"{"type": "analytics", "query": {"metrics": ["total_cost"], "group_by": ["model"]}}"#;
+        let Some(NlQuery::Analytics { query }) = json_object(reply) else {
+            panic!("not an analytics query: {reply}");
+        };
+        assert_eq!(query.metrics, [AnalyticsMetric::TotalCost]);
+        assert_eq!(query.group_by, [GroupByField::Model]);
+
+        assert!(json_object::<NlQuery>(r#"{"type": "traces"}"#).is_none());
+    }
+
+    #[test]
+    fn context_names_the_time_and_vocabulary() {
+        let now = DateTime::parse_from_rfc3339("2026-10-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let models = [FacetCount {
+            value: "claude-sonnet-4".into(),
+            count: 3,
+        }];
+        let context = nl_context(now, &models, &[]);
+        assert!(context.contains("Current time: 2026-10-16T12:00:00+00:00"));
+        assert!(context.contains("Models in use: claude-sonnet-4\n"));
+        assert!(context.contains("Providers in use: none recorded"));
+    }
+}
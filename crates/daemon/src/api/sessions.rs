@@ -0,0 +1,192 @@
+//! Sessions: traces grouped by `Trace::session_id`.
+//!
+//! A session has no record of its own; it exists while some trace carries
+//! its id. Clients set the id in the trace body or, for the SDK and the
+//! proxy, through the `x-traceway-session-id` header.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use storage::{Page, TraceFilter};
+use trace::{Session, Trace};
+use utoipa::IntoParams;
+
+use super::error::Problem;
+use super::traces::{split_tags, ListTracesQuery};
+use super::{api_error, project_store, require_scope, ApiError, AppState, MAX_PAGE_LIMIT};
+
+pub const SESSION_HEADER: &str = "x-traceway-session-id";
+/// Longest session id accepted from a header.
+const MAX_SESSION_ID_LEN: usize = 256;
+
+/// The session id in `headers`, if present and usable.
+pub fn session_from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && v.len() <= MAX_SESSION_ID_LEN)
+        .map(str::to_string)
+}
+
+/// Query parameters for `GET /api/sessions`. Trace filters select which
+/// traces are rolled up.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListSessionsQuery {
+    /// Comma-separated list; traces must have every tag.
+    pub tags: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub machine_id: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// "last_activity_at" (default), "started_at", "duration", "cost", or
+    /// "trace_count".
+    pub sort: Option<String>,
+    /// "asc" or "desc" (default).
+    pub order: Option<String>,
+}
+
+fn sort_sessions(sessions: &mut [Session], sort: Option<&str>, desc: bool) {
+    sessions.sort_by(|a, b| {
+        let ord = match sort {
+            Some("started_at") => a.started_at.cmp(&b.started_at),
+            Some("duration") => a.duration_ms.cmp(&b.duration_ms),
+            Some("cost") => a.total_cost.total_cmp(&b.total_cost),
+            Some("trace_count") => a.trace_count.cmp(&b.trace_count),
+            _ => a.last_activity_at.cmp(&b.last_activity_at),
+        };
+        let ord = if desc { ord.reverse() } else { ord };
+        ord.then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+/// Sessions with their trace count, token and cost totals, and duration.
+#[utoipa::path(
+    get,
+    path = "/api/sessions",
+    tag = "sessions",
+    params(ListSessionsQuery),
+    responses(
+        (status = 200, body = Page<Session>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_sessions(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<ListSessionsQuery>,
+) -> Result<Json<Page<Session>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let filter = TraceFilter {
+        tags: q.tags.as_deref().map(split_tags),
+        since: q.since,
+        until: q.until,
+        machine_id: q.machine_id,
+        ..Default::default()
+    };
+    let mut sessions = store
+        .list_sessions(&filter)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    sort_sessions(
+        &mut sessions,
+        q.sort.as_deref(),
+        q.order.as_deref() != Some("asc"),
+    );
+
+    let total = sessions.len();
+    let offset = q.offset.unwrap_or(0);
+    let limit = q.limit.unwrap_or(50).clamp(1, MAX_PAGE_LIMIT);
+    let items: Vec<Session> = sessions.into_iter().skip(offset).take(limit).collect();
+    Ok(Json(Page {
+        has_more: offset + items.len() < total,
+        items,
+        total: Some(total),
+        next_cursor: None,
+    }))
+}
+
+/// One page of a session's traces. Accepts the `GET /api/traces` parameters.
+#[utoipa::path(
+    get,
+    path = "/api/sessions/{id}/traces",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session id"), ListTracesQuery),
+    responses(
+        (status = 200, body = Page<Trace>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn session_traces(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(q): Query<ListTracesQuery>,
+) -> Result<Json<Page<Trace>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let filter = TraceFilter {
+        session_id: Some(id),
+        ..q.into()
+    };
+    let page = store.query_traces(&filter).await.map_err(|e| match e {
+        storage::StorageError::Serialization(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(Json(page))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn session(id: &str, cost: f64, active_secs: i64) -> Session {
+        let started_at = Utc::now();
+        Session {
+            id: id.into(),
+            user_id: None,
+            trace_count: 1,
+            span_count: 1,
+            error_count: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            total_cost: cost,
+            started_at,
+            last_activity_at: started_at + Duration::seconds(active_secs),
+            duration_ms: active_secs * 1000,
+        }
+    }
+
+    #[test]
+    fn sorts_by_requested_field() {
+        let mut sessions = vec![
+            session("a", 2.0, 5),
+            session("b", 1.0, 30),
+            session("c", 3.0, 10),
+        ];
+        sort_sessions(&mut sessions, None, true);
+        let ids: Vec<_> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["b", "c", "a"]);
+
+        sort_sessions(&mut sessions, Some("cost"), false);
+        let ids: Vec<_> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn reads_session_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_from_headers(&headers), None);
+        headers.insert(SESSION_HEADER, " chat-7 ".parse().unwrap());
+        assert_eq!(session_from_headers(&headers).as_deref(), Some("chat-7"));
+    }
+}
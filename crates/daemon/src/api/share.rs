@@ -0,0 +1,266 @@
+//! Trace share links: read-only access to one trace for someone outside
+//! the org, such as the reader of a bug report.
+//!
+//! `POST /api/traces/:id/share` mints a signed, expiring share token. The
+//! `/api/shared/:token` routes sit outside the authenticated routes and
+//! serve only what the token names: the trace's spans, their full
+//! payloads, and the content of files they read or wrote. Tokens aren't
+//! stored, so a link works until it expires.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{PayloadField, StorageError};
+use trace::{Span, SpanId, SpanKind, Trace, TraceId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::spans::PayloadQuery;
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState, SharedStore};
+
+const DEFAULT_EXPIRY_HOURS: u32 = 7 * 24;
+const MAX_EXPIRY_HOURS: u32 = 30 * 24;
+
+/// Body for `POST /api/traces/:id/share`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ShareTraceRequest {
+    /// How long the link works, up to 720 hours. Defaults to 168 (a week).
+    #[serde(default)]
+    pub expires_in_hours: Option<u32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShareLink {
+    pub token: String,
+    /// Path of the shared trace, relative to the API's origin.
+    pub path: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A shared trace as its link shows it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SharedTrace {
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    /// The trace's metadata, if it was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Trace>,
+    /// Spans, oldest first, including archived ones.
+    pub spans: Vec<Span>,
+    pub count: usize,
+    /// When the link stops working.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Mint a link that lets anyone holding it read this trace until it
+/// expires.
+#[utoipa::path(
+    post,
+    path = "/api/traces/{id}/share",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    request_body = Option<ShareTraceRequest>,
+    responses(
+        (status = 201, body = ShareLink),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn share_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    req: Option<Json<ShareTraceRequest>>,
+) -> Result<(StatusCode, Json<ShareLink>), ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let hours = req.expires_in_hours.unwrap_or(DEFAULT_EXPIRY_HOURS);
+    if hours == 0 || hours > MAX_EXPIRY_HOURS {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("expires_in_hours must be 1 to {MAX_EXPIRY_HOURS}"),
+        ));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+    trace_spans(&store, id).await?;
+
+    let (token, issued) = auth::create_share_token(
+        ctx.org_id,
+        ctx.project_id,
+        id,
+        Duration::hours(i64::from(hours)),
+        &state.auth_config.jwt_secret,
+    )?;
+    audit::record(
+        &state,
+        &ctx,
+        "trace.share",
+        Some(id.to_string()),
+        serde_json::json!({ "link_id": issued.id, "expires_at": issued.expires_at }),
+    )
+    .await;
+    Ok((
+        StatusCode::CREATED,
+        Json(ShareLink {
+            path: format!("/api/shared/{token}"),
+            token,
+            expires_at: issued.expires_at,
+        }),
+    ))
+}
+
+/// The trace a share link points to.
+#[utoipa::path(
+    get,
+    path = "/api/shared/{token}",
+    tag = "traces",
+    params(("token" = String, Path, description = "Share token")),
+    security(()),
+    responses(
+        (status = 200, body = SharedTrace),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_shared_trace(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<SharedTrace>, ApiError> {
+    let (token, store) = open(&state, &token).await?;
+    let spans = trace_spans(&store, token.trace_id).await?;
+    Ok(Json(SharedTrace {
+        trace_id: token.trace_id,
+        trace: store.get_trace_or_load(token.trace_id).await,
+        count: spans.len(),
+        spans,
+        expires_at: token.expires_at,
+    }))
+}
+
+/// A shared span's full input or output.
+#[utoipa::path(
+    get,
+    path = "/api/shared/{token}/spans/{id}/payload",
+    tag = "traces",
+    params(
+        ("token" = String, Path, description = "Share token"),
+        ("id" = String, Path, description = "Span id"),
+        PayloadQuery,
+    ),
+    security(()),
+    responses(
+        (status = 200, description = "The payload as sent", body = serde_json::Value),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_shared_payload(
+    State(state): State<AppState>,
+    Path((token, span_id)): Path<(String, SpanId)>,
+    Query(q): Query<PayloadQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let (token, store) = open(&state, &token).await?;
+    let span = store
+        .get_or_load(span_id)
+        .await
+        .filter(|s| s.trace_id() == token.trace_id)
+        .ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "span not found").with_code("span_not_found")
+        })?;
+    let payload = match q.which {
+        PayloadField::Input => span.input(),
+        PayloadField::Output => span.output(),
+    }
+    .ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span has no such payload").with_code("payload_not_found")
+    })?;
+    let body = store
+        .resolve_payload(payload)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(body))
+}
+
+/// Content of a file a shared span read or wrote.
+#[utoipa::path(
+    get,
+    path = "/api/shared/{token}/files/{hash}",
+    tag = "traces",
+    params(
+        ("token" = String, Path, description = "Share token"),
+        ("hash" = String, Path, description = "Hex SHA-256 of the content"),
+    ),
+    security(()),
+    responses(
+        (status = 200, description = "The content", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_shared_file(
+    State(state): State<AppState>,
+    Path((token, hash)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (token, store) = open(&state, &token).await?;
+    let not_found =
+        || api_error(StatusCode::NOT_FOUND, "content not found").with_code("content_not_found");
+    let etag = ETag::immutable(&hash)
+        .filter(|_| trace::is_content_hash(&hash))
+        .ok_or_else(not_found)?;
+    // Only files the trace touched, so a link can't be used to probe for
+    // other content in the project
+    let spans = trace_spans(&store, token.trace_id).await?;
+    if !spans.iter().any(|s| file_version(s) == Some(hash.as_str())) {
+        return Err(not_found());
+    }
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let content = store.load_file_content(&hash).await.map_err(|e| match e {
+        StorageError::NotFound => not_found(),
+        e => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(etag.respond((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        content,
+    )))
+}
+
+/// Verify a share token and open the store of the project it belongs to.
+async fn open(state: &AppState, token: &str) -> Result<(auth::ShareToken, SharedStore), ApiError> {
+    let token = auth::verify_share_token(token, &state.auth_config.jwt_secret)?;
+    let store = state
+        .store_for_project(token.org_id, token.project_id)
+        .await
+        .map_err(|(status, msg)| api_error(status, msg))?;
+    Ok((token, store))
+}
+
+async fn trace_spans(store: &SharedStore, id: TraceId) -> Result<Vec<Span>, ApiError> {
+    let spans = store
+        .trace_spans(id, false)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if spans.is_empty() && store.get_trace_or_load(id).await.is_none() {
+        return Err(
+            api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+        );
+    }
+    Ok(spans)
+}
+
+fn file_version(span: &Span) -> Option<&str> {
+    match span.kind() {
+        SpanKind::FsWrite { file_version, .. } => Some(file_version),
+        SpanKind::FsRead {
+            file_version: Some(file_version),
+            ..
+        } => Some(file_version),
+        _ => None,
+    }
+}
@@ -0,0 +1,386 @@
+//! Slack notifications through an org's incoming webhook.
+//!
+//! Budget alerts are posted as they fire, report schedules with `slack`
+//! set post their digest, and with `trace_failures` on, the first failed
+//! span of each trace is posted too. Messages link to the trace or page in
+//! the UI at `ui_url`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use trace::{OrgId, Span, SpanStatus, TraceId};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::budgets::BudgetLimit;
+use super::events::StoredEvent;
+use super::org_store::OrgStoreManager;
+use super::{api_error, audit, require_scope, ApiError, AppState, SystemEvent};
+
+/// Settings key an org's Slack integration is saved under.
+pub const SLACK_SETTING: &str = "slack";
+
+const DEFAULT_UI_URL: &str = "http://localhost:3000";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the notifier trusts its copy of an org's integration.
+const CACHE_TTL: Duration = Duration::from_secs(30);
+/// Traces remembered as already reported failing, across all orgs.
+const MAX_NOTIFIED_TRACES: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlackIntegration {
+    /// Incoming webhook URL. Redacted in responses.
+    pub webhook_url: String,
+    /// Where messages link to.
+    #[serde(default = "default_ui_url")]
+    pub ui_url: String,
+    #[serde(default = "default_true")]
+    pub alerts: bool,
+    /// Post when a trace fails.
+    #[serde(default)]
+    pub trace_failures: bool,
+}
+
+fn default_ui_url() -> String {
+    DEFAULT_UI_URL.to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SlackIntegration {
+    fn validate(&self) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.webhook_url)
+            .map_err(|e| format!("invalid webhook_url: {e}"))?;
+        if url.scheme() != "https" {
+            return Err("webhook_url must be https".into());
+        }
+        reqwest::Url::parse(&self.ui_url).map_err(|e| format!("invalid ui_url: {e}"))?;
+        Ok(())
+    }
+
+    pub fn trace_link(&self, trace_id: TraceId, text: &str) -> String {
+        format!(
+            "<{}/traces/{trace_id}|{}>",
+            self.ui_url.trim_end_matches('/'),
+            escape(text)
+        )
+    }
+
+    fn redacted(mut self) -> Self {
+        let tail: String = self
+            .webhook_url
+            .chars()
+            .rev()
+            .take(4)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        self.webhook_url = format!("…{tail}");
+        self
+    }
+}
+
+/// Escape text for Slack's mrkdwn.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn budget_alert_text(
+    slack: &SlackIntegration,
+    budget: &super::budgets::Budget,
+    limit: BudgetLimit,
+    spent: f64,
+) -> String {
+    let (which, cap) = match limit {
+        BudgetLimit::Soft => ("soft", budget.soft_limit),
+        BudgetLimit::Hard => ("hard", budget.hard_limit),
+    };
+    let mut text = format!(
+        ":warning: Budget *{}* reached its {which} limit: ${spent:.2} of ${:.2} this month.",
+        escape(&budget.name),
+        cap.unwrap_or_default()
+    );
+    if limit == BudgetLimit::Hard {
+        text.push_str(" Proxied calls in its scope are rejected until the month resets.");
+    }
+    text.push_str(&format!(
+        " <{}/analytics|View spend>",
+        slack.ui_url.trim_end_matches('/')
+    ));
+    text
+}
+
+fn span_failed_text(slack: &SlackIntegration, span: &Span) -> String {
+    let error = match span.status() {
+        SpanStatus::Failed { error } => error.as_str(),
+        _ => "",
+    };
+    format!(
+        ":x: Trace {} failed at span *{}*: {}",
+        slack.trace_link(span.trace_id(), &span.trace_id().to_string()),
+        escape(span.name()),
+        escape(error)
+    )
+}
+
+type CachedIntegration = (Instant, Option<Arc<SlackIntegration>>);
+
+/// Posts an org's notifications to its Slack webhook.
+pub struct SlackNotifier {
+    org_stores: Arc<OrgStoreManager>,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<OrgId, CachedIntegration>>,
+    /// Traces whose failure was already posted.
+    notified: Mutex<HashSet<TraceId>>,
+}
+
+impl SlackNotifier {
+    pub fn new(org_stores: Arc<OrgStoreManager>) -> Arc<Self> {
+        Arc::new(Self {
+            org_stores,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            cache: Mutex::new(HashMap::new()),
+            notified: Mutex::new(HashSet::new()),
+        })
+    }
+
+    async fn saved(&self, org_id: OrgId) -> Result<Option<SlackIntegration>, String> {
+        let store = self.org_stores.get(org_id).await?;
+        match store.get_setting(SLACK_SETTING).await {
+            Ok(Some(value)) => serde_json::from_value(value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn save(&self, org_id: OrgId, slack: Option<&SlackIntegration>) -> Result<(), String> {
+        let store = self.org_stores.get(org_id).await?;
+        let value = serde_json::to_value(slack).map_err(|e| e.to_string())?;
+        let saved = store
+            .save_setting(SLACK_SETTING, &value)
+            .await
+            .map_err(|e| e.to_string());
+        self.cache.lock().await.remove(&org_id);
+        saved
+    }
+
+    /// The org's integration, if it has one.
+    pub async fn integration(&self, org_id: OrgId) -> Option<Arc<SlackIntegration>> {
+        if let Some((loaded, slack)) = self.cache.lock().await.get(&org_id) {
+            if loaded.elapsed() < CACHE_TTL {
+                return slack.clone();
+            }
+        }
+        let slack = match self.saved(org_id).await {
+            Ok(slack) => slack.map(Arc::new),
+            Err(e) => {
+                warn!(%org_id, "failed to load Slack integration: {e}");
+                None
+            }
+        };
+        self.cache
+            .lock()
+            .await
+            .insert(org_id, (Instant::now(), slack.clone()));
+        slack
+    }
+
+    /// Post `text` to `slack`'s channel.
+    pub async fn post(&self, slack: &SlackIntegration, text: &str) -> Result<(), String> {
+        let resp = self
+            .client
+            .post(&slack.webhook_url)
+            .json(&serde_json::json!({ "text": text }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Slack returned {}", resp.status()))
+        }
+    }
+
+    /// Post every relevant event from `rx` until the journal closes.
+    pub fn spawn(
+        self: Arc<Self>,
+        mut rx: broadcast::Receiver<StoredEvent>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => self.notify(event).await,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!(skipped = n, "Slack notifier lagged; events not posted");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+
+    async fn notify(&self, event: StoredEvent) {
+        if !matches!(
+            event.event,
+            SystemEvent::BudgetAlert { .. } | SystemEvent::SpanFailed { .. }
+        ) {
+            return;
+        }
+        let Ok(org_id) = event.org_id.parse::<Uuid>() else {
+            return;
+        };
+        let Some(slack) = self.integration(org_id).await else {
+            return;
+        };
+        let text = match &event.event {
+            SystemEvent::BudgetAlert {
+                budget,
+                limit,
+                spent,
+            } if slack.alerts => budget_alert_text(&slack, budget, *limit, *spent),
+            SystemEvent::SpanFailed { span } if slack.trace_failures => {
+                let mut notified = self.notified.lock().await;
+                if notified.len() >= MAX_NOTIFIED_TRACES {
+                    notified.clear();
+                }
+                if !notified.insert(span.trace_id()) {
+                    return;
+                }
+                span_failed_text(&slack, span)
+            }
+            _ => return,
+        };
+        if let Err(e) = self.post(&slack, &text).await {
+            warn!(%org_id, "failed to post to Slack: {e}");
+        }
+    }
+}
+
+// --- Handlers ---
+
+/// The org's Slack integration, with the webhook URL redacted.
+pub async fn get_slack(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Option<SlackIntegration>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let slack = state
+        .slack
+        .saved(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(slack.map(SlackIntegration::redacted)))
+}
+
+pub async fn put_slack(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(slack): Json<SlackIntegration>,
+) -> Result<Json<SlackIntegration>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    slack
+        .validate()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    state
+        .slack
+        .save(ctx.org_id, Some(&slack))
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let slack = slack.redacted();
+    audit::record(
+        &state,
+        &ctx,
+        "slack.update",
+        None,
+        serde_json::to_value(&slack).unwrap_or_default(),
+    )
+    .await;
+    Ok(Json(slack))
+}
+
+pub async fn delete_slack(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    state
+        .slack
+        .save(ctx.org_id, None)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    audit::record(&state, &ctx, "slack.delete", None, serde_json::Value::Null).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Post a test message to the org's channel.
+pub async fn test_slack(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let slack = state
+        .slack
+        .saved(ctx.org_id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "Slack is not configured"))?;
+    let text = format!(
+        "Traceway is connected. <{}|Open Traceway>",
+        slack.ui_url.trim_end_matches('/')
+    );
+    state
+        .slack
+        .post(&slack, &text)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use trace::{SpanBuilder, SpanKind};
+
+    use super::*;
+
+    fn slack() -> SlackIntegration {
+        serde_json::from_value(serde_json::json!({
+            "webhook_url": "https://hooks.slack.com/services/T0/B0/secret",
+            "ui_url": "https://app.example.com/",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn failed_span_message_links_to_trace() {
+        let slack = slack();
+        assert!(slack.alerts && !slack.trace_failures);
+        let trace_id = Uuid::now_v7();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let span = SpanBuilder::new(trace_id, "fetch <user>", kind)
+            .build()
+            .fail("timeout & retry");
+        let text = span_failed_text(&slack, &span);
+        assert!(text.contains(&format!("<https://app.example.com/traces/{trace_id}|")));
+        assert!(text.contains("*fetch &lt;user&gt;*: timeout &amp; retry"));
+    }
+
+    #[test]
+    fn redaction_keeps_url_tail() {
+        assert_eq!(slack().redacted().webhook_url, "…cret");
+    }
+}
@@ -0,0 +1,101 @@
+//! Org-registered custom span kind definitions.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use trace::{CostFormula, SpanAttributeDef, SpanKindDefinition, SpanKindDisplay};
+
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState};
+
+/// Body for `POST /api/org/span-kinds`. Registering an existing name replaces
+/// its definition.
+#[derive(Debug, Deserialize)]
+pub struct SpanKindRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub attributes: Vec<SpanAttributeDef>,
+    #[serde(default)]
+    pub display: SpanKindDisplay,
+    #[serde(default)]
+    pub cost: Option<CostFormula>,
+}
+
+pub async fn list_span_kinds(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SpanKindDefinition>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    Ok(Json(store.list_span_kinds()))
+}
+
+pub async fn register_span_kind(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<SpanKindRequest>,
+) -> Result<Json<SpanKindDefinition>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "name must not be empty"));
+    }
+
+    let store = project_store(&ctx, &state).await?;
+
+    let mut def = SpanKindDefinition::new(name);
+    if let Some(existing) = store.get_span_kind(name) {
+        def.created_at = existing.created_at;
+    }
+    def.description = req.description;
+    def.attributes = req.attributes;
+    def.display = req.display;
+    def.cost = req.cost;
+    def.updated_at = Utc::now();
+
+    store
+        .save_span_kind(def.clone())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    audit::record(
+        &state,
+        &ctx,
+        "span_kind.register",
+        Some(def.name.clone()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(Json(def))
+}
+
+pub async fn delete_span_kind(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = project_store(&ctx, &state).await?;
+    let deleted = store
+        .delete_span_kind(&name)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if deleted {
+        audit::record(
+            &state,
+            &ctx,
+            "span_kind.delete",
+            Some(name),
+            serde_json::Value::Null,
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(StatusCode::NOT_FOUND, "span kind not found")
+            .with_code("span_kind_not_found"))
+    }
+}
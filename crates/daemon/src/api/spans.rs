@@ -0,0 +1,620 @@
+//! Span lifecycle endpoints.
+
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{Page, PayloadField, SpanFilter};
+use trace::diff::{DiffSide, SpanDiff};
+use trace::{pricing::PricingTable, OrgId, Span, SpanId, SpanKind, SpanStatus, TraceId};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::org_store::SharedStore;
+use super::{
+    api_error, capture, project_store, require_ingest, require_scope, ApiError, AppState, SystemEvent,
+    MAX_PAGE_LIMIT,
+};
+
+/// Query parameters for `GET /api/spans`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListSpansQuery {
+    #[param(value_type = Option<String>)]
+    pub trace_id: Option<TraceId>,
+    pub kind: Option<String>,
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<String>,
+    /// Tool name of `tool_call` spans.
+    pub tool: Option<String>,
+    /// Index of `retrieval` spans.
+    pub index: Option<String>,
+    /// Verdict of `guardrail` spans: `pass`, `fail` or `flag`.
+    pub verdict: Option<String>,
+    pub name_contains: Option<String>,
+    /// Case-insensitive search across name, input, and output.
+    pub q: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub duration_min: Option<i64>,
+    pub duration_max: Option<i64>,
+    pub cost_min: Option<f64>,
+    /// Spans with an annotation scored at least this.
+    pub score_min: Option<f64>,
+    /// Spans with an annotation scored at most this.
+    pub score_max: Option<f64>,
+    /// Spans with an annotation carrying this label.
+    pub label: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+    /// "started_at" (default), "duration", "tokens", "cost", or "name".
+    pub sort: Option<String>,
+    /// "asc" or "desc" (default).
+    pub order: Option<String>,
+    /// Comma-separated span fields to return, e.g.
+    /// `id,name,kind,duration,status`. All fields when unset.
+    pub fields: Option<String>,
+}
+
+impl From<ListSpansQuery> for SpanFilter {
+    fn from(q: ListSpansQuery) -> Self {
+        SpanFilter {
+            trace_id: q.trace_id,
+            kind: q.kind,
+            model: q.model,
+            provider: q.provider,
+            status: q.status,
+            tool: q.tool,
+            index: q.index,
+            verdict: q.verdict,
+            name_contains: q.name_contains,
+            text_contains: q.q,
+            since: q.since,
+            until: q.until,
+            duration_min: q.duration_min,
+            duration_max: q.duration_max,
+            cost_min: q.cost_min,
+            score_min: q.score_min,
+            score_max: q.score_max,
+            label: q.label,
+            limit: q.limit.map(|l| l.clamp(1, MAX_PAGE_LIMIT)),
+            offset: q.offset,
+            cursor: q.cursor,
+            sort_by: q.sort,
+            sort_order: q.order,
+            ..Default::default()
+        }
+    }
+}
+
+/// Span fields that `?fields=` can select. `duration` is returned as
+/// `duration_ms`.
+const SPAN_FIELDS: &[&str] = &[
+    "id",
+    "trace_id",
+    "org_id",
+    "parent_id",
+    "name",
+    "name_normalized",
+    "kind",
+    "status",
+    "started_at",
+    "ended_at",
+    "duration",
+    "input",
+    "output",
+    "file",
+];
+
+/// The span fields picked by a `?fields=` parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Projection(Vec<String>);
+
+impl Projection {
+    /// `None` when `fields` is unset, i.e. every field is wanted.
+    pub(crate) fn parse(fields: Option<&str>) -> Result<Option<Self>, ApiError> {
+        let Some(fields) = fields else {
+            return Ok(None);
+        };
+        let mut picked = Vec::new();
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let field = if field == "duration_ms" {
+                "duration"
+            } else {
+                field
+            };
+            if !SPAN_FIELDS.contains(&field) {
+                return Err(api_error(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "unknown span field '{field}'; expected one of {}",
+                        SPAN_FIELDS.join(", ")
+                    ),
+                )
+                .with_code("unknown_field"));
+            }
+            if !picked.iter().any(|f| f == field) {
+                picked.push(field.to_string());
+            }
+        }
+        Ok(Some(Self(picked)))
+    }
+
+    /// Whether `input` or `output` is among the fields, so the backend
+    /// has to read payloads.
+    pub(crate) fn needs_payloads(&self) -> bool {
+        self.0.iter().any(|f| f == "input" || f == "output")
+    }
+
+    pub(crate) fn apply(&self, span: &Span) -> serde_json::Value {
+        let mut full = match serde_json::to_value(span) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => Default::default(),
+        };
+        let mut out = serde_json::Map::new();
+        for field in &self.0 {
+            match field.as_str() {
+                "duration" => {
+                    out.insert("duration_ms".into(), span.duration_ms().into());
+                }
+                field => {
+                    if let Some(value) = full.remove(field) {
+                        out.insert(field.to_string(), value);
+                    }
+                }
+            }
+        }
+        serde_json::Value::Object(out)
+    }
+}
+
+/// List spans one page at a time. Follow `next_cursor` for the next page.
+#[utoipa::path(
+    get,
+    path = "/api/spans",
+    tag = "spans",
+    params(ListSpansQuery),
+    responses(
+        (status = 200, description = "One page of spans", body = Page<Span>),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_spans(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(mut q): Query<ListSpansQuery>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let projection = Projection::parse(q.fields.take().as_deref())?;
+    let store = project_store(&ctx, &state).await?;
+    let etag = ETag::for_revision(store.revision(), &uri);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let filter = SpanFilter {
+        skip_payloads: projection.as_ref().is_some_and(|p| !p.needs_payloads()),
+        ..q.into()
+    };
+    let page = store.query_spans(&filter).await.map_err(|e| match e {
+        // Malformed or mismatched cursor
+        storage::StorageError::Serialization(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(match projection {
+        Some(projection) => etag.respond(Json(Page {
+            items: page.items.iter().map(|s| projection.apply(s)).collect(),
+            total: page.total,
+            next_cursor: page.next_cursor,
+            has_more: page.has_more,
+        })),
+        None => etag.respond(Json(page)),
+    })
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PayloadQuery {
+    pub which: PayloadField,
+}
+
+/// A span's full input or output, including one offloaded to a blob.
+#[utoipa::path(
+    get,
+    path = "/api/spans/{id}/payload",
+    tag = "spans",
+    params(("id" = String, Path, description = "Span id"), PayloadQuery),
+    responses(
+        (status = 200, description = "The payload as sent", body = serde_json::Value),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_payload(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SpanId>,
+    Query(q): Query<PayloadQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let span = store.get_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span not found").with_code("span_not_found")
+    })?;
+    let payload = match q.which {
+        PayloadField::Input => span.input(),
+        PayloadField::Output => span.output(),
+    }
+    .ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span has no such payload").with_code("payload_not_found")
+    })?;
+    let body = store
+        .resolve_payload(payload)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(body))
+}
+
+/// Query parameters for `GET /api/spans/diff`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SpanDiffQuery {
+    #[param(value_type = String)]
+    pub a: SpanId,
+    #[param(value_type = String)]
+    pub b: SpanId,
+}
+
+/// `payload`, loaded from blob storage if it was offloaded.
+async fn resolved(
+    store: &SharedStore,
+    payload: Option<&serde_json::Value>,
+) -> Result<Option<serde_json::Value>, storage::StorageError> {
+    match payload {
+        Some(payload) => store.resolve_payload(payload).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Compare two spans: inputs and outputs message by message for chat calls
+/// (line by line otherwise), plus status, latency, token counts, and the
+/// rest of each span's kind attributes. Offloaded payloads are compared in
+/// full.
+#[utoipa::path(
+    get,
+    path = "/api/spans/diff",
+    tag = "spans",
+    params(SpanDiffQuery),
+    responses(
+        (status = 200, body = SpanDiff),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn diff_spans(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<SpanDiffQuery>,
+) -> Result<Json<SpanDiff>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let mut spans = Vec::with_capacity(2);
+    for id in [q.a, q.b] {
+        spans.push(store.get_or_load(id).await.ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, format!("span {id} not found"))
+                .with_code("span_not_found")
+        })?);
+    }
+    let (a, b) = (&spans[0], &spans[1]);
+    let (a_input, a_output, b_input, b_output) = tokio::try_join!(
+        resolved(&store, a.input()),
+        resolved(&store, a.output()),
+        resolved(&store, b.input()),
+        resolved(&store, b.output()),
+    )
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(SpanDiff::between(
+        DiffSide {
+            span: a,
+            input: a_input.as_ref(),
+            output: a_output.as_ref(),
+        },
+        DiffSide {
+            span: b,
+            input: b_input.as_ref(),
+            output: b_output.as_ref(),
+        },
+    )))
+}
+
+/// Body for `POST /api/spans/:id/complete`. All fields are optional.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CompleteSpanRequest {
+    #[serde(default)]
+    pub output: Option<serde_json::Value>,
+    #[serde(default)]
+    pub input_tokens: Option<u64>,
+    #[serde(default)]
+    pub output_tokens: Option<u64>,
+    /// Explicit cost in USD. When omitted, cost is computed from the pricing table.
+    #[serde(default)]
+    pub cost: Option<f64>,
+}
+
+/// Complete a running span. For LLM calls, token counts from the body are
+/// merged into the span kind and cost is filled in from the pricing table.
+#[utoipa::path(
+    post,
+    path = "/api/spans/{id}/complete",
+    tag = "spans",
+    params(("id" = String, Path, description = "Span id")),
+    request_body = Option<CompleteSpanRequest>,
+    responses(
+        (status = 200, description = "The completed span", body = Span),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn complete_span(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SpanId>,
+    body: Option<Json<CompleteSpanRequest>>,
+) -> Result<Json<Span>, ApiError> {
+    require_ingest(&ctx)?;
+    let Json(req) = body.unwrap_or_default();
+
+    let store = project_store(&ctx, &state).await?;
+
+    let span = store.get_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "span not found").with_code("span_not_found")
+    })?;
+    if span.status().is_terminal() {
+        return Err(
+            api_error(StatusCode::CONFLICT, "span is already in a terminal state")
+                .with_code("span_already_terminal"),
+        );
+    }
+
+    let completed = match span.kind().clone() {
+        SpanKind::LlmCall {
+            model,
+            provider,
+            input_tokens,
+            output_tokens,
+            cost,
+            input_preview,
+            output_preview,
+        } => {
+            let pricing = state.pricing.read().await;
+            let kind = SpanKind::LlmCall {
+                model,
+                provider,
+                input_tokens: req.input_tokens.or(input_tokens),
+                output_tokens: req.output_tokens.or(output_tokens),
+                cost: req.cost.or(cost),
+                input_preview,
+                output_preview,
+            }
+            .with_cost_from(&pricing);
+            store.complete_span_with_kind(id, kind, req.output).await
+        }
+        _ => store.complete_span(id, req.output).await,
+    }
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or_else(|| {
+        api_error(StatusCode::CONFLICT, "span is already in a terminal state")
+            .with_code("span_already_terminal")
+    })?;
+
+    state.emit_event(
+        SystemEvent::SpanCompleted {
+            span: completed.clone(),
+        },
+        &ctx.org_id.to_string(),
+    );
+    Ok(Json(completed))
+}
+
+/// Most spans accepted by one `POST /api/spans/batch` request.
+const MAX_BATCH_SPANS: usize = 1000;
+
+/// One span in a `POST /api/spans/batch` body. Buffered SDKs send spans after
+/// the fact, so ids, timestamps, and status may all be explicit.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchSpan {
+    /// Client-assigned id. Generated when omitted.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub id: Option<SpanId>,
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub parent_id: Option<SpanId>,
+    pub name: String,
+    pub kind: SpanKind,
+    /// Defaults to `completed` when `ended_at` is set, otherwise `running`.
+    #[serde(default)]
+    pub status: Option<SpanStatus>,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    /// Defaults to now for completed or failed spans.
+    #[serde(default)]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub input: Option<serde_json::Value>,
+    #[serde(default)]
+    pub output: Option<serde_json::Value>,
+}
+
+impl BatchSpan {
+    fn into_span(
+        self,
+        org_id: OrgId,
+        pricing: &PricingTable,
+        now: DateTime<Utc>,
+    ) -> Result<Span, String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        let status = self.status.unwrap_or(if self.ended_at.is_some() {
+            SpanStatus::Completed
+        } else {
+            SpanStatus::Running
+        });
+        let started_at = self.started_at.unwrap_or(now);
+        let ended_at = match (&status, self.ended_at) {
+            (SpanStatus::Running, Some(_)) => {
+                return Err("running spans must not have ended_at".to_string())
+            }
+            (SpanStatus::Running, None) => None,
+            (_, ended_at) => Some(ended_at.unwrap_or(now)),
+        };
+        if ended_at.is_some_and(|end| end < started_at) {
+            return Err("ended_at precedes started_at".to_string());
+        }
+        Ok(Span::from_parts(
+            self.id.unwrap_or_else(Uuid::now_v7),
+            self.trace_id,
+            Some(org_id),
+            self.parent_id,
+            self.name,
+            self.kind.with_cost_from(pricing),
+            status,
+            started_at,
+            ended_at,
+            self.input,
+            self.output,
+        ))
+    }
+}
+
+/// Query parameters for `POST /api/spans/batch`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchSpansQuery {
+    /// Return a feedback token for each stored span, for end users to
+    /// send feedback with through `POST /api/feedback`.
+    #[serde(default)]
+    pub feedback_tokens: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchSpansResponse {
+    /// Spans stored, in request order.
+    #[schema(value_type = Vec<String>)]
+    pub ids: Vec<SpanId>,
+    /// Spans dropped by head-based sampling.
+    pub sampled_out: usize,
+    /// One per entry of `ids`, when requested with `?feedback_tokens=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feedback_tokens: Option<Vec<String>>,
+}
+
+/// Write a batch of spans in one request. The whole batch is rejected if any
+/// span is invalid; errors name the offending index.
+#[utoipa::path(
+    post,
+    path = "/api/spans/batch",
+    tag = "spans",
+    params(BatchSpansQuery),
+    request_body = Vec<BatchSpan>,
+    responses(
+        (status = 200, body = BatchSpansResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn create_spans_batch(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<BatchSpansQuery>,
+    Json(batch): Json<Vec<BatchSpan>>,
+) -> Result<Json<BatchSpansResponse>, ApiError> {
+    require_ingest(&ctx)?;
+    if batch.len() > MAX_BATCH_SPANS {
+        return Err(api_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("at most {MAX_BATCH_SPANS} spans per batch"),
+        ));
+    }
+
+    let now = Utc::now();
+    let pricing = state.pricing.read().await;
+    let mut seen = HashSet::with_capacity(batch.len());
+    let mut spans = Vec::with_capacity(batch.len());
+    for (i, item) in batch.into_iter().enumerate() {
+        let span = item
+            .into_span(ctx.org_id, &pricing, now)
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("spans[{i}]: {e}")))?;
+        if !seen.insert(span.id()) {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                format!("spans[{i}]: duplicate id {}", span.id()),
+            ));
+        }
+        spans.push(span);
+    }
+    drop(pricing);
+    let before = spans.len();
+    if let Some(sampler) = &state.sampler {
+        spans.retain(|s| sampler.keep_span(s));
+    }
+    let sampled_out = before - spans.len();
+    state.reserve_spans(spans.len())?;
+
+    let store = project_store(&ctx, &state).await?;
+    let spans = store.insert_batch(spans).await.map_err(|e| match e {
+        storage::StorageError::InvalidInput(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    super::metrics::global().record_ingest("batch", spans.len());
+    state.usage.record(ctx.org_id, spans.len());
+
+    let feedback_tokens = if q.feedback_tokens {
+        let secret = &state.auth_config.jwt_secret;
+        let tokens = spans
+            .iter()
+            .map(|s| {
+                auth::create_feedback_token(ctx.org_id, ctx.project_id, s.id(), s.trace_id(), secret)
+            })
+            .collect::<Result<_, _>>()?;
+        Some(tokens)
+    } else {
+        None
+    };
+    let org_id = ctx.org_id.to_string();
+    let ids = spans.iter().map(|s| s.id()).collect();
+    for span in spans {
+        if span.status().is_terminal() {
+            let store = store.clone();
+            let journal = state.journal.clone();
+            let org_id = org_id.clone();
+            let span = span.clone();
+            tokio::spawn(async move {
+                capture::process_capture_rules(&store, &span, &journal, &org_id)
+                    .await;
+            });
+        }
+        let event = match span.status() {
+            SpanStatus::Running => SystemEvent::SpanCreated { span },
+            SpanStatus::Completed => SystemEvent::SpanCompleted { span },
+            SpanStatus::Failed { .. } => SystemEvent::SpanFailed { span },
+        };
+        state.emit_event(event, &org_id);
+    }
+    Ok(Json(BatchSpansResponse {
+        ids,
+        sampled_out,
+        feedback_tokens,
+    }))
+}
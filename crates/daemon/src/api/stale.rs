@@ -0,0 +1,71 @@
+//! Failing spans left running.
+//!
+//! A client that crashes mid-span never completes it. A background task
+//! fails spans that have been running longer than `max_age_secs` with
+//! "timed out" and emits `span_failed` for each, as the API would.
+
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use chrono::Utc;
+use tracing::{error, info};
+
+use super::{events::EventJournal, OrgStoreManager, SystemEvent};
+use crate::config::StaleSpansConfig;
+
+/// Error recorded on spans failed by the sweeper.
+pub const STALE_SPAN_ERROR: &str = "timed out";
+
+/// Fail stale spans in every open store once. Returns how many were failed.
+pub async fn sweep(
+    org_stores: &OrgStoreManager,
+    journal: &EventJournal,
+    max_age: Duration,
+) -> usize {
+    let Ok(max_age) = chrono::Duration::from_std(max_age) else {
+        return 0;
+    };
+    let cutoff = Utc::now() - max_age;
+    let mut total = 0;
+    for (org_id, store) in org_stores.all_stores().await {
+        match store.fail_stale_spans(cutoff, STALE_SPAN_ERROR).await {
+            Ok(failed) => {
+                total += failed.len();
+                let org_id = org_id.to_string();
+                for span in failed {
+                    journal.emit(&org_id, SystemEvent::SpanFailed { span });
+                }
+            }
+            Err(e) => error!(%org_id, "stale spans: sweep failed: {e}"),
+        }
+    }
+    total
+}
+
+/// Spawn the periodic sweep. It holds the journal weakly and stops once
+/// the router that owns it is gone.
+pub fn spawn_stale_span_sweeper(
+    org_stores: Arc<OrgStoreManager>,
+    journal: Weak<EventJournal>,
+    config: StaleSpansConfig,
+) -> tokio::task::JoinHandle<()> {
+    let max_age = Duration::from_secs(config.max_age_secs);
+    let period = Duration::from_secs(config.interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Some(journal) = journal.upgrade() else {
+                return;
+            };
+            let failed = sweep(&org_stores, &journal, max_age).await;
+            if failed > 0 {
+                info!(
+                    failed,
+                    max_age_secs = max_age.as_secs(),
+                    "stale spans: failed long-running spans"
+                );
+            }
+        }
+    })
+}
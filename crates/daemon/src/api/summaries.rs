@@ -0,0 +1,438 @@
+//! LLM-written trace summaries.
+//!
+//! `POST /api/traces/:id/summarize` condenses the trace's span tree into a
+//! short outline, one line per span with truncated inputs and outputs, and
+//! asks the `[llm]` model through the local proxy for a summary and a
+//! guess at why the trace failed. The result is kept in the project's
+//! settings under the trace id along with a hash of the outline, and is
+//! served from there until the trace changes. The store deletes it along
+//! with the trace.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use trace::tree::{SpanNode, TraceTree};
+use trace::{Span, SpanKind, SpanStatus, Trace, TraceId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::llm::json_object;
+use super::scorers::output_text;
+use super::traces::load_tree;
+use super::{api_error, project_store, require_scope, ApiError, AppState};
+
+/// Characters kept of each span input, output, and error.
+const PREVIEW_CHARS: usize = 200;
+/// Outline lines sent to the model; later spans are counted but not shown.
+const MAX_LINES: usize = 400;
+
+const SYSTEM_PROMPT: &str = "You review traces of LLM applications and agents. You get an \
+outline of one trace: each line is a span, indented under its parent, with its kind, status, \
+duration, and truncated input and output. Reply with only a JSON object: {\"summary\": \"<what \
+the trace did and how it ended, in at most five sentences>\", \"failure_hypothesis\": \"<the \
+most likely root cause of any failure or wrong result, citing span names>\" or null if it \
+succeeded}.";
+
+pub fn summary_setting(id: TraceId) -> String {
+    storage::trace_setting_key(storage::TRACE_SUMMARY_SETTING, id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TraceSummary {
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    pub summary: String,
+    /// Likely root cause of the trace's failures. `None` when the model
+    /// found nothing wrong.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_hypothesis: Option<String>,
+    pub model: String,
+    /// Hash of the model and outline the summary was written from.
+    pub content_hash: String,
+    pub span_count: usize,
+    pub created_at: DateTime<Utc>,
+    /// Served from an earlier request rather than generated by this one.
+    #[serde(default)]
+    pub cached: bool,
+}
+
+/// Body for `POST /api/traces/:id/summarize`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct SummarizeRequest {
+    /// Ask the model again even if the trace hasn't changed.
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    summary: String,
+    #[serde(default)]
+    failure_hypothesis: Option<String>,
+}
+
+/// The model's reply, or the whole of `text` as the summary when it isn't
+/// the JSON object asked for.
+fn parse_reply(text: &str) -> Reply {
+    let mut reply = json_object(text).unwrap_or_else(|| Reply {
+        summary: text.trim().to_string(),
+        failure_hypothesis: None,
+    });
+    reply.failure_hypothesis = reply
+        .failure_hypothesis
+        .filter(|h| !h.trim().is_empty() && !h.eq_ignore_ascii_case("null"));
+    reply
+}
+
+/// `text` on one line, cut to `PREVIEW_CHARS`.
+fn preview(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(PREVIEW_CHARS) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text,
+    }
+}
+
+/// What went into and came out of `span`, preferring the previews its kind
+/// carries over the raw payloads.
+fn span_io(span: &Span) -> (Option<String>, Option<String>) {
+    let (input, output) = match span.kind() {
+        SpanKind::LlmCall {
+            input_preview,
+            output_preview,
+            ..
+        } => (input_preview.clone(), output_preview.clone()),
+        SpanKind::ToolCall {
+            arguments,
+            result_preview,
+            ..
+        } => (
+            arguments.as_ref().map(Value::to_string),
+            result_preview.clone(),
+        ),
+        SpanKind::Retrieval { query_preview, .. } => (query_preview.clone(), None),
+        _ => (None, None),
+    };
+    (
+        input.or_else(|| span.input().map(output_text)),
+        output.or_else(|| span.output().map(output_text)),
+    )
+}
+
+fn describe(span: &Span) -> String {
+    let mut line = format!(
+        "{} [{}] {}",
+        span.name(),
+        span.kind().kind_name(),
+        span.status().as_str()
+    );
+    match span.duration_ms() {
+        Some(ms) => line.push_str(&format!(", {ms}ms")),
+        None => line.push_str(", still running"),
+    }
+    if let Some(model) = span.kind().model() {
+        line.push_str(&format!(", model {model}"));
+    }
+    if let (Some(input), Some(output)) = (span.kind().input_tokens(), span.kind().output_tokens()) {
+        line.push_str(&format!(", {input}/{output} tokens"));
+    }
+    if let Some(verdict) = span.kind().verdict() {
+        line.push_str(&format!(", verdict {}", verdict.as_str()));
+    }
+    line
+}
+
+struct Outline {
+    lines: Vec<String>,
+    spans_shown: usize,
+}
+
+impl Outline {
+    fn full(&self) -> bool {
+        self.lines.len() >= MAX_LINES
+    }
+
+    /// Add `nodes` at `depth`. Childless siblings that share a name, kind,
+    /// and status are folded into one line, so a loop of identical tool
+    /// calls doesn't crowd out the rest of the trace.
+    fn push(&mut self, nodes: &[SpanNode], depth: usize) {
+        let indent = "  ".repeat(depth);
+        let mut i = 0;
+        while i < nodes.len() && !self.full() {
+            let span = &nodes[i].span;
+            let repeats = nodes[i..]
+                .iter()
+                .take_while(|n| {
+                    n.children.is_empty()
+                        && n.span.name() == span.name()
+                        && n.span.kind().kind_name() == span.kind().kind_name()
+                        && n.span.status() == span.status()
+                })
+                .count();
+            if repeats > 1 {
+                let total: i64 = nodes[i..i + repeats]
+                    .iter()
+                    .filter_map(|n| n.span.duration_ms())
+                    .sum();
+                self.lines.push(format!(
+                    "{indent}- {} [{}] {} ×{repeats}, {total}ms in total",
+                    span.name(),
+                    span.kind().kind_name(),
+                    span.status().as_str(),
+                ));
+                self.push_details(span, &indent);
+                self.spans_shown += repeats;
+                i += repeats;
+                continue;
+            }
+
+            self.lines.push(format!("{indent}- {}", describe(span)));
+            self.push_details(span, &indent);
+            self.spans_shown += 1;
+            self.push(&nodes[i].children, depth + 1);
+            i += 1;
+        }
+    }
+
+    fn push_details(&mut self, span: &Span, indent: &str) {
+        let (input, output) = span_io(span);
+        if let Some(input) = input.filter(|s| !s.trim().is_empty()) {
+            self.lines
+                .push(format!("{indent}  in: {}", preview(&input)));
+        }
+        if let Some(output) = output.filter(|s| !s.trim().is_empty()) {
+            self.lines
+                .push(format!("{indent}  out: {}", preview(&output)));
+        }
+        if let SpanStatus::Failed { error } = span.status() {
+            self.lines
+                .push(format!("{indent}  error: {}", preview(error)));
+        }
+    }
+}
+
+/// The outline of `tree` sent to the model.
+pub fn outline(trace: Option<&Trace>, tree: &TraceTree) -> String {
+    let mut outline = Outline {
+        lines: Vec::new(),
+        spans_shown: 0,
+    };
+    if let Some(trace) = trace {
+        let mut header = format!("Trace {}", trace.name.as_deref().unwrap_or("(unnamed)"));
+        if !trace.tags.is_empty() {
+            header.push_str(&format!(", tags: {}", trace.tags.join(", ")));
+        }
+        outline.lines.push(header);
+    }
+    outline.push(&tree.roots, 0);
+    let hidden = tree.span_count.saturating_sub(outline.spans_shown);
+    if hidden > 0 {
+        outline
+            .lines
+            .push(format!("… {hidden} more spans not shown"));
+    }
+    outline.lines.join("\n")
+}
+
+async fn cached(store: &super::SharedStore, id: TraceId) -> Option<TraceSummary> {
+    match store.get_setting(&summary_setting(id)).await {
+        Ok(Some(value)) => serde_json::from_value(value).ok(),
+        _ => None,
+    }
+}
+
+/// Summarize a trace with the configured model, noting the likely cause of
+/// any failure. The summary is reused until the trace's spans change.
+#[utoipa::path(
+    post,
+    path = "/api/traces/{id}/summarize",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    request_body = Option<SummarizeRequest>,
+    responses(
+        (status = 200, body = TraceSummary),
+        (status = "4XX", response = Problem),
+        (status = "5XX", response = Problem),
+    )
+)]
+pub async fn summarize_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    req: Option<Json<SummarizeRequest>>,
+) -> Result<Json<TraceSummary>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let (trace, tree) = load_tree(&ctx, &state, id).await?;
+    let outline = outline(trace.as_ref(), &tree);
+    let model = state.llm.model();
+    let content_hash = trace::content_hash(format!("{model}\n{outline}").as_bytes());
+
+    let store = project_store(&ctx, &state).await?;
+    if !req.refresh {
+        if let Some(summary) = cached(&store, id)
+            .await
+            .filter(|s| s.content_hash == content_hash)
+        {
+            return Ok(Json(TraceSummary {
+                cached: true,
+                ..summary
+            }));
+        }
+    }
+
+    let proxy_url = state.proxy_url.as_deref().ok_or_else(|| {
+        api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "trace summaries need the local proxy, which is not running",
+        )
+    })?;
+    let text = state
+        .llm
+        .complete(proxy_url, SYSTEM_PROMPT, &outline)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e).with_code("summary_failed"))?;
+    let reply = parse_reply(&text);
+    let summary = TraceSummary {
+        trace_id: id,
+        summary: reply.summary,
+        failure_hypothesis: reply.failure_hypothesis,
+        model: model.to_string(),
+        content_hash,
+        span_count: tree.span_count,
+        created_at: Utc::now(),
+        cached: false,
+    };
+    let value = serde_json::to_value(&summary)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    store
+        .save_setting(&summary_setting(id), &value)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use trace::SpanId;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn span(
+        id: SpanId,
+        parent: Option<SpanId>,
+        name: &str,
+        kind: SpanKind,
+        status: SpanStatus,
+        start_ms: i64,
+    ) -> Span {
+        let start = DateTime::UNIX_EPOCH + Duration::milliseconds(start_ms);
+        Span::from_parts(
+            id,
+            Uuid::nil(),
+            None,
+            parent,
+            name.to_string(),
+            kind,
+            status,
+            start,
+            Some(start + Duration::milliseconds(100)),
+            None,
+            None,
+        )
+    }
+
+    fn tool(parent: SpanId, status: SpanStatus, start_ms: i64) -> Span {
+        let kind = SpanKind::ToolCall {
+            tool_name: "search".into(),
+            arguments: Some(serde_json::json!({ "q": "weather" })),
+            result_preview: None,
+        };
+        span(
+            Uuid::new_v4(),
+            Some(parent),
+            "search",
+            kind,
+            status,
+            start_ms,
+        )
+    }
+
+    #[test]
+    fn outline_folds_repeated_siblings_and_shows_failures() {
+        let root = Uuid::new_v4();
+        let llm = SpanKind::LlmCall {
+            model: "gpt-4o".into(),
+            provider: None,
+            input_tokens: Some(120),
+            output_tokens: Some(30),
+            cost: None,
+            input_preview: Some("What's the\nweather?".into()),
+            output_preview: Some("x".repeat(PREVIEW_CHARS + 50)),
+        };
+        let step = SpanKind::AgentStep {
+            step_type: "act".into(),
+            iteration: None,
+        };
+        let mut spans = vec![
+            span(root, None, "agent", step, SpanStatus::Completed, 0),
+            span(
+                Uuid::new_v4(),
+                Some(root),
+                "chat",
+                llm,
+                SpanStatus::Completed,
+                1,
+            ),
+        ];
+        for start in 2..5 {
+            spans.push(tool(root, SpanStatus::Completed, start));
+        }
+        let failed = SpanStatus::Failed {
+            error: "rate limited".into(),
+        };
+        spans.push(tool(root, failed, 5));
+        let tree = TraceTree::build(spans);
+
+        let text = outline(None, &tree);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "- agent [agent_step] completed, 100ms");
+        assert_eq!(
+            lines[1],
+            "  - chat [llm_call] completed, 100ms, model gpt-4o, 120/30 tokens"
+        );
+        assert_eq!(lines[2], "    in: What's the weather?");
+        assert_eq!(
+            lines[3].chars().count(),
+            "    out: ".len() + PREVIEW_CHARS + 1
+        );
+        assert_eq!(
+            lines[4],
+            "  - search [tool_call] completed ×3, 300ms in total"
+        );
+        assert_eq!(lines[5], "    in: {\"q\":\"weather\"}");
+        assert_eq!(lines[6], "  - search [tool_call] failed, 100ms");
+        assert_eq!(lines[8], "    error: rate limited");
+        assert_eq!(lines.len(), 9);
+    }
+
+    #[test]
+    fn replies_tolerate_prose_and_null_hypotheses() {
+        let reply = parse_reply(
+            "Here you go:\n```json\n{\"summary\": \"Looked up the weather.\", \"failure_hypothesis\": null}\n```",
+        );
+        assert_eq!(reply.summary, "Looked up the weather.");
+        assert_eq!(reply.failure_hypothesis, None);
+
+        let reply = parse_reply("  The agent answered directly. ");
+        assert_eq!(reply.summary, "The agent answered directly.");
+    }
+}
@@ -0,0 +1,287 @@
+//! Trace tags: adding and removing them after a trace is created, listing
+//! them with usage counts, and renaming one across every trace.
+//!
+//! Tags are free-form, but can't contain commas, since `GET /api/traces`
+//! takes its `tags` filter as a comma-separated list.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use trace::{FacetCount, TraceId};
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{api_error, audit, project_store, require_scope, ApiError, AppState, SharedStore};
+
+const MAX_TAG_LEN: usize = 128;
+
+/// Body for `POST /api/traces/:id/tags`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddTagsRequest {
+    pub tags: Vec<String>,
+}
+
+/// Body for `POST /api/tags/:tag/rename`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RenameTagRequest {
+    pub to: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TraceTags {
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TagList {
+    /// Most used first.
+    pub tags: Vec<FacetCount>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RenamedTag {
+    pub from: String,
+    pub to: String,
+    /// Traces rewritten, including ones in the trash.
+    pub traces: usize,
+}
+
+/// Trim `tag` and check it can round-trip through the `tags` list filter.
+fn valid_tag(tag: &str) -> Result<String, ApiError> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.contains(',') || tag.len() > MAX_TAG_LEN {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("tags must be 1 to {MAX_TAG_LEN} bytes without commas, got {tag:?}"),
+        )
+        .with_code("invalid_tag"));
+    }
+    Ok(tag.to_string())
+}
+
+async fn retag(
+    store: &SharedStore,
+    id: TraceId,
+    add: &[String],
+    remove: &[String],
+) -> Result<TraceTags, ApiError> {
+    let trace = store
+        .retag_trace(id, add, remove)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+        })?;
+    Ok(TraceTags {
+        trace_id: trace.id,
+        tags: trace.tags,
+    })
+}
+
+/// Add tags to a trace. Tags it already has are left alone.
+#[utoipa::path(
+    post,
+    path = "/api/traces/{id}/tags",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    request_body = AddTagsRequest,
+    responses(
+        (status = 200, body = TraceTags),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn add_tags(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    Json(req): Json<AddTagsRequest>,
+) -> Result<Json<TraceTags>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    if req.tags.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "tags must not be empty"));
+    }
+    let tags = req
+        .tags
+        .iter()
+        .map(|t| valid_tag(t))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let store = project_store(&ctx, &state).await?;
+    let tagged = retag(&store, id, &tags, &[]).await?;
+    audit::record(
+        &state,
+        &ctx,
+        "trace.tag",
+        Some(id.to_string()),
+        serde_json::json!({ "added": tags }),
+    )
+    .await;
+    Ok(Json(tagged))
+}
+
+/// Remove a tag from a trace. Removing a tag it doesn't have is a no-op.
+#[utoipa::path(
+    delete,
+    path = "/api/traces/{id}/tags/{tag}",
+    tag = "traces",
+    params(
+        ("id" = String, Path, description = "Trace id"),
+        ("tag" = String, Path, description = "Tag to remove"),
+    ),
+    responses(
+        (status = 200, body = TraceTags),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn remove_tag(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path((id, tag)): Path<(TraceId, String)>,
+) -> Result<Json<TraceTags>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let store = project_store(&ctx, &state).await?;
+    let tagged = retag(&store, id, &[], std::slice::from_ref(&tag)).await?;
+    audit::record(
+        &state,
+        &ctx,
+        "trace.untag",
+        Some(id.to_string()),
+        serde_json::json!({ "removed": tag }),
+    )
+    .await;
+    Ok(Json(tagged))
+}
+
+/// Every tag in use, with the number of traces carrying it. Trashed traces
+/// aren't counted.
+#[utoipa::path(
+    get,
+    path = "/api/tags",
+    tag = "traces",
+    responses(
+        (status = 200, body = TagList),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_tags(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<TagList>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let tags = project_store(&ctx, &state)
+        .await?
+        .tag_counts()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(TagList { tags }))
+}
+
+/// Rename a tag on every trace that has it. Renaming onto a tag that is
+/// already in use merges the two.
+#[utoipa::path(
+    post,
+    path = "/api/tags/{tag}/rename",
+    tag = "traces",
+    params(("tag" = String, Path, description = "Tag to rename")),
+    request_body = RenameTagRequest,
+    responses(
+        (status = 200, body = RenamedTag),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn rename_tag(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(from): Path<String>,
+    Json(req): Json<RenameTagRequest>,
+) -> Result<Json<RenamedTag>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let to = valid_tag(&req.to)?;
+    if to == from {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "new name is the same as the old one",
+        ));
+    }
+
+    let traces = project_store(&ctx, &state)
+        .await?
+        .rename_tag(&from, &to)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if traces == 0 {
+        return Err(
+            api_error(StatusCode::NOT_FOUND, "no trace has this tag").with_code("tag_not_found")
+        );
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "tag.rename",
+        None,
+        serde_json::json!({ "from": from, "to": to, "traces": traces }),
+    )
+    .await;
+    Ok(Json(RenamedTag { from, to, traces }))
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{PersistentStore, TraceFilter};
+    use storage_sqlite::SqliteBackend;
+    use trace::Trace;
+    use uuid::Uuid;
+
+    use super::super::AnyBackend;
+    use super::*;
+
+    #[tokio::test]
+    async fn tags_are_edited_counted_and_renamed() {
+        let dir = std::env::temp_dir().join(format!("tags-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(&dir.join("traces.db")).unwrap());
+        let store = PersistentStore::open(backend).await.unwrap();
+
+        let a = Trace::new(Some("a".into())).with_tags(vec!["prod".into()]);
+        let b = Trace::new(Some("b".into())).with_tags(vec!["staging".into()]);
+        store.save_trace(a.clone()).await.unwrap();
+        store.save_trace(b.clone()).await.unwrap();
+
+        let tagged = store
+            .retag_trace(b.id, &["prod".into(), "slow".into()], &[])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tagged.tags, ["staging", "prod", "slow"]);
+        store
+            .retag_trace(b.id, &[], &["slow".into()])
+            .await
+            .unwrap();
+        let counts = store.tag_counts().await.unwrap();
+        assert_eq!(
+            counts[0],
+            FacetCount {
+                value: "prod".into(),
+                count: 2
+            }
+        );
+        assert_eq!(counts.len(), 2);
+
+        // `b` already has `prod`, so renaming `staging` onto it merges them
+        assert_eq!(store.rename_tag("staging", "prod").await.unwrap(), 1);
+        assert_eq!(store.get_trace(b.id).unwrap().tags, ["prod"]);
+        let filter = TraceFilter {
+            tags: Some(vec!["prod".into()]),
+            ..Default::default()
+        };
+        assert_eq!(store.query_traces(&filter).await.unwrap().items.len(), 2);
+        assert_eq!(store.rename_tag("staging", "prod").await.unwrap(), 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
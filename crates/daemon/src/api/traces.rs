@@ -0,0 +1,541 @@
+//! Trace query endpoints.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use storage::{Page, SpanFilter, TraceFilter, TrashScope};
+use trace::tree::{TraceTree, WaterfallStats};
+use trace::{Span, Trace, TraceFacets, TraceId};
+use utoipa::{IntoParams, ToSchema};
+
+use super::error::Problem;
+use super::etag::ETag;
+use super::spans::Projection;
+use super::{
+    api_error, project_store, require_ingest, require_scope, sessions, ApiError, AppState, SystemEvent,
+    MAX_PAGE_LIMIT,
+};
+
+const DEFAULT_SLOWEST_SPANS: usize = 10;
+
+pub(super) fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Query parameters for `GET /api/traces`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListTracesQuery {
+    pub name_contains: Option<String>,
+    /// Comma-separated list; traces must have every tag.
+    pub tags: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only traces reported by this machine.
+    pub machine_id: Option<String>,
+    /// Only traces in this session.
+    pub session_id: Option<String>,
+    pub git_commit: Option<String>,
+    pub git_branch: Option<String>,
+    pub repo: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+    /// "started_at" (default), "duration", "cost", or "name".
+    pub sort: Option<String>,
+    /// "asc" or "desc" (default).
+    pub order: Option<String>,
+}
+
+impl From<ListTracesQuery> for TraceFilter {
+    fn from(q: ListTracesQuery) -> Self {
+        TraceFilter {
+            name_contains: q.name_contains,
+            tags: q.tags.as_deref().map(split_tags),
+            since: q.since,
+            until: q.until,
+            machine_id: q.machine_id,
+            session_id: q.session_id,
+            git_commit: q.git_commit,
+            git_branch: q.git_branch,
+            repo: q.repo,
+            limit: q.limit.map(|l| l.clamp(1, MAX_PAGE_LIMIT)),
+            offset: q.offset,
+            cursor: q.cursor,
+            sort_by: q.sort,
+            sort_order: q.order,
+            trash: TrashScope::Live,
+        }
+    }
+}
+
+/// List traces one page at a time. Follow `next_cursor` for the next page.
+#[utoipa::path(
+    get,
+    path = "/api/traces",
+    tag = "traces",
+    params(ListTracesQuery),
+    responses(
+        (status = 200, description = "One page of traces", body = Page<Trace>),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_traces(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(q): Query<ListTracesQuery>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let etag = ETag::for_revision(store.revision(), &uri);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let page = store.query_traces(&q.into()).await.map_err(|e| match e {
+        // Malformed or mismatched cursor
+        storage::StorageError::Serialization(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(etag.respond(Json(page)))
+}
+
+/// Query parameters for `GET /api/traces/facets`.
+///
+/// Trace-level filters (`name_contains`, `tags`, `since`, `until`,
+/// `machine_id`) apply to the trace; span-level filters (`kind`, `model`,
+/// `provider`, `status`) keep traces with at least one matching span.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FacetsQuery {
+    /// A span query in the language `?q=` and `tail --filter` use, e.g.
+    /// `model:gpt-4o status:failed`; traces are kept when a span matches
+    /// it. `kind`, `model`, `provider` and `status` given as their own
+    /// parameters take precedence.
+    pub filter: Option<String>,
+    pub name_contains: Option<String>,
+    /// Comma-separated list; traces must have every tag.
+    pub tags: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub machine_id: Option<String>,
+    pub kind: Option<String>,
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<String>,
+}
+
+impl FacetsQuery {
+    fn trace_filter(&self) -> TraceFilter {
+        TraceFilter {
+            name_contains: self.name_contains.clone(),
+            tags: self.tags.as_deref().map(split_tags),
+            since: self.since,
+            until: self.until,
+            machine_id: self.machine_id.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// `filter` with the span-level parameters applied on top, or `None`
+    /// when neither is given.
+    fn span_filter(&self) -> Result<Option<SpanFilter>, ApiError> {
+        let bad_request = |msg: String| api_error(StatusCode::BAD_REQUEST, msg);
+        let parsed = match &self.filter {
+            Some(query) => {
+                let filter =
+                    storage::parse_span_query(query).map_err(|e| bad_request(e.to_string()))?;
+                // Facets are counted without annotations
+                if filter.has_annotation_predicates() {
+                    return Err(bad_request("score and label can't filter facets".into()));
+                }
+                Some(filter)
+            }
+            None => None,
+        };
+        if parsed.is_none()
+            && self.kind.is_none()
+            && self.model.is_none()
+            && self.provider.is_none()
+            && self.status.is_none()
+        {
+            return Ok(None);
+        }
+        let filter = parsed.unwrap_or_default();
+        Ok(Some(SpanFilter {
+            kind: self.kind.clone().or(filter.kind),
+            model: self.model.clone().or(filter.model),
+            provider: self.provider.clone().or(filter.provider),
+            status: self.status.clone().or(filter.status),
+            ..filter
+        }))
+    }
+}
+
+/// Facet counts per model, provider, status, tag, and error fingerprint for
+/// the traces matching the filter.
+#[utoipa::path(
+    get,
+    path = "/api/traces/facets",
+    tag = "traces",
+    params(FacetsQuery),
+    responses(
+        (status = 200, body = TraceFacets),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn trace_facets(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Query(q): Query<FacetsQuery>,
+) -> Result<Json<TraceFacets>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let span_filter = q.span_filter()?;
+    let store = project_store(&ctx, &state).await?;
+    let facets = store
+        .query_trace_facets(&q.trace_filter(), span_filter.as_ref())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(facets))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TraceTreeResponse {
+    /// `None` when spans arrived without trace metadata.
+    pub trace: Option<Trace>,
+    #[serde(flatten)]
+    pub tree: TraceTree,
+}
+
+/// A trace's spans nested under their parents, with depth and self-time
+/// per span. Spans whose parent is missing are returned as extra roots.
+#[utoipa::path(
+    get,
+    path = "/api/traces/{id}/tree",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    responses(
+        (status = 200, body = TraceTreeResponse),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn trace_tree(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+) -> Result<Json<TraceTreeResponse>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let (trace, tree) = load_tree(&ctx, &state, id).await?;
+    Ok(Json(TraceTreeResponse { trace, tree }))
+}
+
+/// The trace, if it has metadata, and its spans as a tree. Not found when
+/// it has neither.
+pub(super) async fn load_tree(
+    ctx: &auth::AuthContext,
+    state: &AppState,
+    id: TraceId,
+) -> Result<(Option<Trace>, TraceTree), ApiError> {
+    let store = project_store(ctx, state).await?;
+
+    let trace = store.get_trace_or_load(id).await;
+    let spans: Vec<_> = store
+        .spans_for_trace_or_load(id)
+        .await
+        .into_iter()
+        .filter_map(|span_id| store.get(span_id))
+        .collect();
+    if trace.is_none() && spans.is_empty() {
+        return Err(
+            api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+        );
+    }
+    Ok((trace, TraceTree::build(spans)))
+}
+
+/// Query parameters for `GET /api/traces/:id/stats`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TraceStatsQuery {
+    /// Number of slowest spans to return (default 10).
+    pub slowest: Option<usize>,
+}
+
+/// Waterfall statistics: the critical path, self time per span kind, how
+/// much spans overlap, and the slowest spans. Running spans are measured up
+/// to now.
+#[utoipa::path(
+    get,
+    path = "/api/traces/{id}/stats",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id"), TraceStatsQuery),
+    responses(
+        (status = 200, body = WaterfallStats),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn trace_stats(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    Query(query): Query<TraceStatsQuery>,
+) -> Result<Json<WaterfallStats>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let (_, tree) = load_tree(&ctx, &state, id).await?;
+    let slowest = query
+        .slowest
+        .unwrap_or(DEFAULT_SLOWEST_SPANS)
+        .min(MAX_PAGE_LIMIT);
+    Ok(Json(tree.stats(Utc::now(), slowest)))
+}
+
+/// Mark a trace ended. Spans still running are left as they are.
+#[utoipa::path(
+    post,
+    path = "/api/traces/{id}/complete",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    responses(
+        (status = 200, body = Trace),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn complete_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+) -> Result<Json<Trace>, ApiError> {
+    require_ingest(&ctx)?;
+    let store = project_store(&ctx, &state).await?;
+
+    let trace = store.get_trace_or_load(id).await.ok_or_else(|| {
+        api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+    })?;
+    if trace.ended_at.is_some() {
+        return Err(api_error(StatusCode::CONFLICT, "trace is already complete")
+            .with_code("trace_already_complete"));
+    }
+    store
+        .save_trace(trace.complete())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let trace = store
+        .get_trace(id)
+        .ok_or_else(|| api_error(StatusCode::INTERNAL_SERVER_ERROR, "trace was not saved"))?;
+    state.emit_event(
+        SystemEvent::TraceCompleted {
+            trace: trace.clone(),
+        },
+        &ctx.org_id.to_string(),
+    );
+    Ok(Json(trace))
+}
+
+/// Body for `PUT /api/traces/:id`. Client SDKs report trace metadata this
+/// way, alongside their span batches.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct PutTraceRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Defaults to the stored start, or now for a new trace.
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub git_commit: Option<String>,
+    #[serde(default)]
+    pub git_branch: Option<String>,
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTraceQuery {
+    /// Comma-separated span fields to return; see `GET /api/spans`.
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TraceSpans {
+    /// Spans, or the requested fields of each.
+    #[schema(value_type = Vec<Span>)]
+    pub spans: serde_json::Value,
+    pub count: usize,
+}
+
+/// A trace's spans, oldest first, including archived ones.
+#[utoipa::path(
+    get,
+    path = "/api/traces/{id}",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id"), GetTraceQuery),
+    responses(
+        (status = 200, body = TraceSpans),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(q): Query<GetTraceQuery>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let projection = Projection::parse(q.fields.as_deref())?;
+    let store = project_store(&ctx, &state).await?;
+    let etag = ETag::for_revision(store.revision(), &uri);
+    if etag.matches(&headers) {
+        return Ok(etag.not_modified());
+    }
+    let skip_payloads = projection.as_ref().is_some_and(|p| !p.needs_payloads());
+    let spans = store
+        .trace_spans(id, skip_payloads)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if spans.is_empty() && store.get_trace_or_load(id).await.is_none() {
+        return Err(
+            api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+        );
+    }
+    let count = spans.len();
+    let spans = match projection {
+        Some(projection) => spans.iter().map(|s| projection.apply(s)).collect(),
+        None => serde_json::to_value(&spans)
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?,
+    };
+    Ok(etag.respond(Json(TraceSpans { spans, count })))
+}
+
+/// Create or replace a trace's metadata. Its span rollup is kept.
+#[utoipa::path(
+    put,
+    path = "/api/traces/{id}",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    request_body = PutTraceRequest,
+    responses(
+        (status = 200, body = Trace),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn put_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+    headers: HeaderMap,
+    Json(req): Json<PutTraceRequest>,
+) -> Result<Json<Trace>, ApiError> {
+    require_ingest(&ctx)?;
+    let store = project_store(&ctx, &state).await?;
+
+    if store.is_trashed(id) {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            "trace is in the trash; restore it first",
+        )
+        .with_code("trace_trashed"));
+    }
+    let existing = store.get_trace_or_load(id).await;
+    let started_at = req
+        .started_at
+        .or(existing.as_ref().map(|t| t.started_at))
+        .unwrap_or_else(Utc::now);
+    if req.ended_at.is_some_and(|end| end < started_at) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "ended_at precedes started_at",
+        ));
+    }
+    let trace = Trace {
+        id,
+        org_id: Some(ctx.org_id),
+        name: req.name,
+        tags: req.tags,
+        started_at,
+        ended_at: req.ended_at,
+        machine_id: existing.as_ref().and_then(|t| t.machine_id.clone()),
+        session_id: req
+            .session_id
+            .or_else(|| sessions::session_from_headers(&headers)),
+        user_id: req.user_id,
+        git_commit: req.git_commit,
+        git_branch: req.git_branch,
+        repo: req.repo,
+        stats: Default::default(),
+        deleted_at: None,
+    };
+    store
+        .save_trace(trace)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let trace = store
+        .get_trace(id)
+        .ok_or_else(|| api_error(StatusCode::INTERNAL_SERVER_ERROR, "trace was not saved"))?;
+
+    let event = match (&existing, trace.ended_at) {
+        (None, _) => SystemEvent::TraceCreated {
+            trace: trace.clone(),
+        },
+        (Some(prev), Some(_)) if prev.ended_at.is_none() => SystemEvent::TraceCompleted {
+            trace: trace.clone(),
+        },
+        _ => return Ok(Json(trace)),
+    };
+    state.emit_event(event, &ctx.org_id.to_string());
+    Ok(Json(trace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn facets_filter_is_a_span_query() {
+        let q = FacetsQuery {
+            filter: Some("model:gpt-4o status:failed duration:>500ms".into()),
+            status: Some("completed".into()),
+            ..Default::default()
+        };
+        let filter = q.span_filter().unwrap().unwrap();
+        assert_eq!(filter.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(filter.duration_min, Some(500));
+        // Parameters given directly win over the filter string
+        assert_eq!(filter.status.as_deref(), Some("completed"));
+        assert!(FacetsQuery::default().span_filter().unwrap().is_none());
+
+        let bad = FacetsQuery {
+            filter: Some("since:yesterday".into()),
+            ..Default::default()
+        };
+        let err = bad.span_filter().unwrap_err().problem();
+        assert_eq!(err.status, 400);
+        assert!(err.detail.contains("invalid value for since"));
+
+        let annotated = FacetsQuery {
+            filter: Some("score:>0.5".into()),
+            ..Default::default()
+        };
+        assert!(annotated.span_filter().is_err());
+    }
+}
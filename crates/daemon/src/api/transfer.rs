@@ -0,0 +1,596 @@
+//! Portable workspace archives, for moving a project between deployments,
+//! such as from a laptop to a cloud org.
+//!
+//! `GET /api/export/archive` streams a tar holding `manifest.json`; JSONL
+//! entries for traces, the settings kept per trace (such as summaries) and
+//! spans, a page per entry; JSONL for datasets,
+//! datapoints, queue items, and file versions; and every stored blob under
+//! `files/<hash>`. `POST /api/import/archive` reads one back. IDs are kept,
+//! so importing the same archive twice overwrites rather than duplicates.
+
+use std::io::Read;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use storage::{SpanFilter, StorageBackend, StorageError, TraceFilter};
+use trace::{Datapoint, Dataset, FileVersion, QueueItem, Span, Trace, TraceId};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::error::Problem;
+use super::{
+    api_error, audit, project_store, require_scope, ApiError, AppState, SharedStore, MAX_PAGE_LIMIT,
+};
+
+/// Bumped whenever the archive layout changes incompatibly.
+pub const ARCHIVE_FORMAT: u32 = 1;
+
+/// Largest archive `POST /api/import/archive` accepts, after decompression.
+pub const MAX_IMPORT_BYTES: usize = 512 * 1024 * 1024;
+
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format: u32,
+    pub exported_at: DateTime<Utc>,
+}
+
+/// One of the settings kept for a trace; see `storage::TRACE_SETTINGS`.
+#[derive(Debug, Serialize, Deserialize)]
+struct TraceSetting {
+    trace_id: TraceId,
+    name: String,
+    value: serde_json::Value,
+}
+
+/// What `POST /api/import/archive` wrote.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ImportSummary {
+    pub traces: usize,
+    pub trace_settings: usize,
+    pub spans: usize,
+    pub datasets: usize,
+    pub datapoints: usize,
+    pub queue_items: usize,
+    pub file_versions: usize,
+    pub file_contents: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Manifest,
+    Traces,
+    Spans,
+    Records,
+    Files,
+    Done,
+}
+
+/// Builds the archive an entry or a page at a time.
+struct ArchiveWriter {
+    store: SharedStore,
+    tar: tar::Builder<Vec<u8>>,
+    exported_at: DateTime<Utc>,
+    stage: Stage,
+    cursor: Option<String>,
+    page: usize,
+    blobs: Vec<String>,
+}
+
+impl ArchiveWriter {
+    fn new(store: SharedStore) -> Self {
+        Self {
+            store,
+            tar: tar::Builder::new(Vec::new()),
+            exported_at: Utc::now(),
+            stage: Stage::Manifest,
+            cursor: None,
+            page: 0,
+            blobs: Vec::new(),
+        }
+    }
+
+    /// The next stretch of the tar, or `None` once it has all been sent.
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, StorageError> {
+        match self.stage {
+            Stage::Manifest => {
+                let manifest = ArchiveManifest {
+                    format: ARCHIVE_FORMAT,
+                    exported_at: self.exported_at,
+                };
+                self.append(MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+                self.stage = Stage::Traces;
+            }
+            Stage::Traces => {
+                let page = self
+                    .store
+                    .query_traces(&TraceFilter {
+                        cursor: self.cursor.take(),
+                        limit: Some(MAX_PAGE_LIMIT),
+                        ..Default::default()
+                    })
+                    .await?;
+                self.append_page("traces", &page.items)?;
+                let mut settings = Vec::new();
+                for trace in &page.items {
+                    for name in storage::TRACE_SETTINGS {
+                        let key = storage::trace_setting_key(name, trace.id);
+                        if let Some(value) = self.store.get_setting(&key).await? {
+                            settings.push(TraceSetting {
+                                trace_id: trace.id,
+                                name: name.to_string(),
+                                value,
+                            });
+                        }
+                    }
+                }
+                self.append_page("trace_settings", &settings)?;
+                self.cursor = page.next_cursor.filter(|_| page.has_more);
+                if self.cursor.is_none() {
+                    self.page = 0;
+                    self.stage = Stage::Spans;
+                }
+            }
+            Stage::Spans => {
+                let page = self
+                    .store
+                    .query_spans(&SpanFilter {
+                        cursor: self.cursor.take(),
+                        limit: Some(MAX_PAGE_LIMIT),
+                        ..Default::default()
+                    })
+                    .await?;
+                self.append_page("spans", &page.items)?;
+                self.cursor = page.next_cursor.filter(|_| page.has_more);
+                if self.cursor.is_none() {
+                    self.stage = Stage::Records;
+                }
+            }
+            Stage::Records => {
+                // The rest is read from the backend, which lazily loaded
+                // stores may not hold in memory
+                self.store.flush().await?;
+                let backend = self.store.backend();
+                let (datasets, datapoints, queue_items, file_versions, contents) = tokio::try_join!(
+                    backend.load_all_datasets(),
+                    backend.load_all_datapoints(),
+                    backend.load_all_queue_items(),
+                    backend.list_file_versions(),
+                    backend.list_file_contents(),
+                )?;
+                self.append_lines("datasets.jsonl", &datasets)?;
+                self.append_lines("datapoints.jsonl", &datapoints)?;
+                self.append_lines("queue_items.jsonl", &queue_items)?;
+                self.append_lines("file_versions.jsonl", &file_versions)?;
+                self.blobs = contents.into_iter().map(|(hash, _)| hash).collect();
+                self.stage = Stage::Files;
+            }
+            Stage::Files => match self.blobs.pop() {
+                Some(hash) => {
+                    let content = self.store.load_file_content(&hash).await?;
+                    self.append(&format!("files/{hash}"), &content)?;
+                }
+                None => {
+                    self.tar.finish()?;
+                    self.stage = Stage::Done;
+                }
+            },
+            Stage::Done => return Ok(None),
+        }
+        Ok(Some(Bytes::from(std::mem::take(self.tar.get_mut()))))
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.exported_at.timestamp().max(0) as u64);
+        self.tar.append_data(&mut header, path, data)?;
+        Ok(())
+    }
+
+    fn append_lines<T: Serialize>(&mut self, path: &str, items: &[T]) -> Result<(), StorageError> {
+        let mut out = Vec::new();
+        for item in items {
+            serde_json::to_writer(&mut out, item)?;
+            out.push(b'\n');
+        }
+        self.append(path, &out)
+    }
+
+    fn append_page<T: Serialize>(&mut self, kind: &str, items: &[T]) -> Result<(), StorageError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        self.page += 1;
+        self.append_lines(&format!("{kind}/{:06}.jsonl", self.page), items)
+    }
+}
+
+/// Stream the project's traces, spans, datasets, datapoints, queue items,
+/// and files as a tar archive that `POST /api/import/archive` reads back.
+#[utoipa::path(
+    get,
+    path = "/api/export/archive",
+    tag = "export",
+    responses(
+        (status = 200, description = "A workspace archive", content_type = "application/x-tar"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn export_archive(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    require_scope(&ctx, auth::Scope::DatasetsRead)?;
+    let store = project_store(&ctx, &state).await?;
+
+    let mut writer = ArchiveWriter::new(store);
+    let first = writer
+        .next_chunk()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let filename = format!(
+        "workspace-{}.tar",
+        writer.exported_at.format("%Y%m%d-%H%M%S")
+    );
+    let rest = futures::stream::try_unfold(writer, |mut writer| async move {
+        match writer.next_chunk().await {
+            Ok(chunk) => Ok(chunk.map(|chunk| (chunk, writer))),
+            Err(e) => {
+                warn!("export: aborting archive: {e}");
+                Err(e)
+            }
+        }
+    });
+    let body = futures::stream::iter(first.map(Ok::<_, StorageError>)).chain(rest);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// The entities of an archive, in the order they are imported.
+#[derive(Debug, Default)]
+struct Archive {
+    contents: Vec<(String, Vec<u8>)>,
+    file_versions: Vec<FileVersion>,
+    traces: Vec<Trace>,
+    trace_settings: Vec<TraceSetting>,
+    spans: Vec<Span>,
+    datasets: Vec<Dataset>,
+    datapoints: Vec<Datapoint>,
+    queue_items: Vec<QueueItem>,
+}
+
+fn read_archive(bytes: &[u8]) -> Result<Archive, String> {
+    let mut archive = Archive::default();
+    let mut manifest: Option<ArchiveManifest> = None;
+    let mut tar = tar::Archive::new(bytes);
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        let path = entry
+            .path()
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .into_owned();
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|e| format!("{path}: {e}"))?;
+        match path.as_str() {
+            MANIFEST => {
+                let parsed: ArchiveManifest =
+                    serde_json::from_slice(&data).map_err(|e| format!("{MANIFEST}: {e}"))?;
+                if parsed.format != ARCHIVE_FORMAT {
+                    return Err(format!("unsupported archive format {}", parsed.format));
+                }
+                manifest = Some(parsed);
+            }
+            "datasets.jsonl" => archive.datasets = parse_lines(&path, &data)?,
+            "datapoints.jsonl" => archive.datapoints = parse_lines(&path, &data)?,
+            "queue_items.jsonl" => archive.queue_items = parse_lines(&path, &data)?,
+            "file_versions.jsonl" => archive.file_versions = parse_lines(&path, &data)?,
+            p if p.starts_with("traces/") => archive.traces.extend(parse_lines(&path, &data)?),
+            p if p.starts_with("trace_settings/") => {
+                archive.trace_settings.extend(parse_lines(&path, &data)?)
+            }
+            p if p.starts_with("spans/") => archive.spans.extend(parse_lines(&path, &data)?),
+            p => {
+                if let Some(hash) = p.strip_prefix("files/") {
+                    if !trace::is_content_hash(hash) || trace::content_hash(&data) != hash {
+                        return Err(format!("{p}: content doesn't match its hash"));
+                    }
+                    archive.contents.push((hash.to_string(), data));
+                }
+            }
+        }
+    }
+    if manifest.is_none() {
+        return Err(format!("missing {MANIFEST}"));
+    }
+    Ok(archive)
+}
+
+fn parse_lines<T: DeserializeOwned>(path: &str, data: &[u8]) -> Result<Vec<T>, String> {
+    data.split(|b| *b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| serde_json::from_slice(line).map_err(|e| format!("{path}:{}: {e}", i + 1)))
+        .collect()
+}
+
+/// Write everything in `archive` to `store`, blobs first so that spans
+/// with offloaded payloads resolve as soon as they land.
+async fn import(store: &SharedStore, archive: Archive) -> Result<ImportSummary, StorageError> {
+    let mut summary = ImportSummary::default();
+    for (hash, content) in &archive.contents {
+        store.save_file_content(hash, content).await?;
+        summary.file_contents += 1;
+    }
+    for version in archive.file_versions {
+        store.save_file_version(version).await?;
+        summary.file_versions += 1;
+    }
+    for trace in archive.traces {
+        store.save_trace(trace).await?;
+        summary.traces += 1;
+    }
+    // Only names kept per trace, so an archive can't overwrite org settings
+    for setting in archive.trace_settings {
+        if let Some(name) = storage::TRACE_SETTINGS.iter().find(|n| **n == setting.name) {
+            let key = storage::trace_setting_key(name, setting.trace_id);
+            store.save_setting(&key, &setting.value).await?;
+            summary.trace_settings += 1;
+        }
+    }
+    let mut spans = archive.spans;
+    while !spans.is_empty() {
+        let rest = spans.split_off(spans.len().min(MAX_PAGE_LIMIT));
+        summary.spans += store.insert_batch(spans).await?.len();
+        spans = rest;
+    }
+    for dataset in archive.datasets {
+        store.save_dataset(dataset).await?;
+        summary.datasets += 1;
+    }
+    for datapoint in archive.datapoints {
+        store.save_datapoint(datapoint).await?;
+        summary.datapoints += 1;
+    }
+    for item in archive.queue_items {
+        store.save_queue_item(item).await?;
+        summary.queue_items += 1;
+    }
+    Ok(summary)
+}
+
+/// Import an archive from `GET /api/export/archive` into the project. The
+/// body may be sent compressed with `Content-Encoding: gzip` or `zstd`.
+#[utoipa::path(
+    post,
+    path = "/api/import/archive",
+    tag = "export",
+    request_body(content = Vec<u8>, content_type = "application/x-tar"),
+    responses(
+        (status = 200, body = ImportSummary),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn import_archive(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<ImportSummary>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let archive = tokio::task::spawn_blocking(move || read_archive(&body))
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| {
+            api_error(StatusCode::BAD_REQUEST, format!("invalid archive: {e}"))
+                .with_code("invalid_archive")
+        })?;
+    state.reserve_spans(archive.spans.len())?;
+
+    let store = project_store(&ctx, &state).await?;
+    let summary = import(&store, archive).await.map_err(|e| match e {
+        StorageError::InvalidInput(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    state.usage.record(ctx.org_id, summary.spans);
+    audit::record(
+        &state,
+        &ctx,
+        "workspace.import",
+        None,
+        serde_json::to_value(&summary).unwrap_or_default(),
+    )
+    .await;
+    Ok(Json(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use storage::PersistentStore;
+    use storage_sqlite::SqliteBackend;
+    use trace::{DatapointKind, DatapointSource, SpanBuilder, SpanKind};
+
+    use super::*;
+    use crate::api::AnyBackend;
+
+    async fn store() -> SharedStore {
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        Arc::new(PersistentStore::open(backend).await.unwrap())
+    }
+
+    async fn export(store: SharedStore) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(store);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = writer.next_chunk().await.unwrap() {
+            bytes.extend_from_slice(&chunk);
+        }
+        bytes
+    }
+
+    fn datapoint(dataset: &Dataset, input: &str) -> Datapoint {
+        Datapoint::new(
+            dataset.id,
+            DatapointKind::Generic {
+                input: serde_json::json!(input),
+                expected_output: None,
+                actual_output: None,
+                score: None,
+                metadata: Default::default(),
+            },
+            DatapointSource::Manual,
+        )
+    }
+
+    #[tokio::test]
+    async fn archives_round_trip() {
+        let source = store().await;
+        let trace = Trace::new(Some("run".into()));
+        let trace_id = trace.id;
+        source.save_trace(trace).await.unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        for i in 0..3 {
+            let span = SpanBuilder::new(trace_id, format!("step {i}"), kind.clone()).build();
+            source.insert(span).await.unwrap();
+        }
+        let dataset = Dataset::new("golden", None);
+        let datapoint = datapoint(&dataset, "q");
+        source.save_dataset(dataset).await.unwrap();
+        source.save_datapoint(datapoint).await.unwrap();
+        source
+            .save_file_content(&trace::content_hash(b"hello"), b"hello")
+            .await
+            .unwrap();
+
+        let summary_key = crate::api::summaries::summary_setting(trace_id);
+        let written = serde_json::json!({"summary": "ran"});
+        source.save_setting(&summary_key, &written).await.unwrap();
+
+        let archive = read_archive(&export(source).await).unwrap();
+        let dest = store().await;
+        let summary = import(&dest, archive).await.unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                traces: 1,
+                trace_settings: 1,
+                spans: 3,
+                datasets: 1,
+                datapoints: 1,
+                queue_items: 0,
+                file_versions: 0,
+                file_contents: 1,
+            }
+        );
+        assert_eq!(dest.spans_for_trace(trace_id).len(), 3);
+        assert_eq!(dest.get_trace(trace_id).unwrap().stats.span_count, 3);
+        assert_eq!(dest.get_setting(&summary_key).await.unwrap(), Some(written));
+
+        // A blob that doesn't match its name is refused
+        let mut tampered = tar::Builder::new(Vec::new());
+        let manifest = serde_json::to_vec(&ArchiveManifest {
+            format: ARCHIVE_FORMAT,
+            exported_at: Utc::now(),
+        })
+        .unwrap();
+        for (path, data) in [
+            (MANIFEST.to_string(), manifest),
+            (
+                format!("files/{}", trace::content_hash(b"a")),
+                b"b".to_vec(),
+            ),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            tampered
+                .append_data(&mut header, path, data.as_slice())
+                .unwrap();
+        }
+        assert!(read_archive(&tampered.into_inner().unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn records_and_files_round_trip() {
+        let source = store().await;
+        let dataset = Dataset::new("golden", None);
+        let points = [datapoint(&dataset, "a"), datapoint(&dataset, "b")];
+        let item = QueueItem::new(dataset.id, points[0].id, None);
+        source.save_dataset(dataset.clone()).await.unwrap();
+        for point in &points {
+            source.save_datapoint(point.clone()).await.unwrap();
+        }
+        source.save_queue_item(item.clone()).await.unwrap();
+        let blobs: [&[u8]; 2] = [b"first", b"second"];
+        for blob in blobs {
+            source
+                .save_file_content(&trace::content_hash(blob), blob)
+                .await
+                .unwrap();
+        }
+        let version = FileVersion {
+            hash: trace::content_hash(b"second"),
+            path: "notes.md".into(),
+            size: 6,
+            created_at: Utc::now(),
+            created_by_span: None,
+        };
+        source.save_file_version(version.clone()).await.unwrap();
+
+        let dest = store().await;
+        let summary = import(&dest, read_archive(&export(source).await).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                datasets: 1,
+                datapoints: 2,
+                queue_items: 1,
+                file_versions: 1,
+                file_contents: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(dest.get_dataset(dataset.id).unwrap().name, "golden");
+        for point in &points {
+            assert!(dest.get_datapoint(point.id).is_some());
+        }
+        assert_eq!(
+            dest.get_queue_item(item.id).unwrap().datapoint_id,
+            points[0].id
+        );
+        assert_eq!(dest.get_file_versions("notes.md")[0].hash, version.hash);
+        for blob in blobs {
+            let hash = trace::content_hash(blob);
+            assert_eq!(dest.load_file_content(&hash).await.unwrap(), blob);
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! Trash: deleted traces and datasets, kept for a while before they go.
+//!
+//! `DELETE /api/traces/:id` and `DELETE /api/datasets/:id` mark the item
+//! deleted instead of removing it. Trashed items are hidden everywhere
+//! else but can be listed and restored; a background task deletes them
+//! for good once they have been in the trash for `TRASH_DAYS`.
+
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use storage::Versioned;
+use trace::{Dataset, DatasetId, Trace, TraceId};
+use tracing::{error, info};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::error::Problem;
+use super::events::EventJournal;
+use super::{
+    api_error, audit, project_store, require_scope, ApiError, AppState, OrgStoreManager,
+    SystemEvent,
+};
+
+/// Days an item stays in the trash before it is purged.
+pub const TRASH_DAYS: i64 = 30;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrashKind {
+    Trace,
+    Dataset,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrashItem {
+    #[serde(rename = "type")]
+    pub kind: TrashKind,
+    #[schema(value_type = String)]
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub deleted_at: DateTime<Utc>,
+    /// When the item is deleted for good.
+    pub purge_at: DateTime<Utc>,
+}
+
+impl TrashItem {
+    fn new(kind: TrashKind, id: Uuid, name: Option<String>, deleted_at: DateTime<Utc>) -> Self {
+        Self {
+            kind,
+            id,
+            name,
+            deleted_at,
+            purge_at: deleted_at + chrono::Duration::days(TRASH_DAYS),
+        }
+    }
+
+    fn trace(trace: &Trace) -> Option<Self> {
+        let deleted_at = trace.deleted_at?;
+        Some(Self::new(
+            TrashKind::Trace,
+            trace.id,
+            trace.name.clone(),
+            deleted_at,
+        ))
+    }
+
+    fn dataset(dataset: &Dataset) -> Option<Self> {
+        let deleted_at = dataset.deleted_at?;
+        Some(Self::new(
+            TrashKind::Dataset,
+            dataset.id,
+            Some(dataset.name.clone()),
+            deleted_at,
+        ))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RestoredItem {
+    #[serde(rename = "type")]
+    pub kind: TrashKind,
+    #[schema(value_type = String)]
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrashList {
+    /// Most recently deleted first.
+    pub items: Vec<TrashItem>,
+}
+
+/// Other writers kept changing the dataset while it was being moved.
+fn dataset_moving() -> ApiError {
+    api_error(
+        StatusCode::CONFLICT,
+        "dataset is being changed by another request; try again",
+    )
+    .with_code("version_conflict")
+}
+
+/// Move a trace to the trash. It can be restored for 30 days.
+#[utoipa::path(
+    delete,
+    path = "/api/traces/{id}",
+    tag = "traces",
+    params(("id" = String, Path, description = "Trace id")),
+    responses(
+        (status = 204, description = "Moved to the trash"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn delete_trace(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<TraceId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let trashed = project_store(&ctx, &state)
+        .await?
+        .trash_trace(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !trashed {
+        return Err(
+            api_error(StatusCode::NOT_FOUND, "trace not found").with_code("trace_not_found")
+        );
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "trace.trash",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    state.emit_event(
+        SystemEvent::TraceDeleted { trace_id: id },
+        &ctx.org_id.to_string(),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Move a dataset to the trash. Its capture rules stop firing until it is
+/// restored.
+#[utoipa::path(
+    delete,
+    path = "/api/datasets/{id}",
+    tag = "datasets",
+    params(("id" = String, Path, description = "Dataset id")),
+    responses(
+        (status = 204, description = "Moved to the trash"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn delete_dataset(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<DatasetId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+    let moved = project_store(&ctx, &state)
+        .await?
+        .trash_dataset(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    match moved {
+        Versioned::Updated(_) => {}
+        Versioned::Conflict(_) => return Err(dataset_moving()),
+        Versioned::NotFound => {
+            return Err(api_error(StatusCode::NOT_FOUND, "dataset not found")
+                .with_code("dataset_not_found"))
+        }
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "dataset.trash",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    state.emit_event(
+        SystemEvent::DatasetDeleted { dataset_id: id },
+        &ctx.org_id.to_string(),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Traces and datasets in the trash. Datasets are included when the key
+/// can read them.
+#[utoipa::path(
+    get,
+    path = "/api/trash",
+    tag = "trash",
+    responses(
+        (status = 200, body = TrashList),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_trash(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<TrashList>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = project_store(&ctx, &state).await?;
+    let traces = store
+        .trashed_traces()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let mut items: Vec<TrashItem> = traces.iter().filter_map(TrashItem::trace).collect();
+    if ctx.has_scope(auth::Scope::DatasetsRead) {
+        items.extend(
+            store
+                .trashed_datasets()
+                .iter()
+                .filter_map(TrashItem::dataset),
+        );
+    }
+    items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+    Ok(Json(TrashList { items }))
+}
+
+/// Take a trace or dataset out of the trash.
+#[utoipa::path(
+    post,
+    path = "/api/trash/{id}/restore",
+    tag = "trash",
+    params(("id" = String, Path, description = "Trace or dataset id")),
+    responses(
+        (status = 200, body = RestoredItem),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn restore(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RestoredItem>, ApiError> {
+    let store = project_store(&ctx, &state).await?;
+    let restored = if store.is_trashed(id) {
+        require_scope(&ctx, auth::Scope::TracesWrite)?;
+        store
+            .restore_trace(id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+            .map(|trace| RestoredItem {
+                kind: TrashKind::Trace,
+                id,
+                name: trace.name,
+            })
+    } else if store.trashed_datasets().iter().any(|d| d.id == id) {
+        require_scope(&ctx, auth::Scope::DatasetsWrite)?;
+        let moved = store
+            .restore_dataset(id)
+            .await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        match moved {
+            Versioned::Updated(dataset) => Some(RestoredItem {
+                kind: TrashKind::Dataset,
+                id,
+                name: Some(dataset.name),
+            }),
+            Versioned::Conflict(_) => return Err(dataset_moving()),
+            Versioned::NotFound => None,
+        }
+    } else {
+        None
+    };
+    let Some(restored) = restored else {
+        return Err(api_error(StatusCode::NOT_FOUND, "not in the trash").with_code("not_in_trash"));
+    };
+    audit::record(
+        &state,
+        &ctx,
+        "trash.restore",
+        Some(id.to_string()),
+        serde_json::json!({ "type": restored.kind }),
+    )
+    .await;
+    Ok(Json(restored))
+}
+
+/// Purge items trashed more than `TRASH_DAYS` ago from every open store.
+pub async fn purge_all(org_stores: &OrgStoreManager) {
+    let cutoff = Utc::now() - chrono::Duration::days(TRASH_DAYS);
+    for (org_id, store) in org_stores.all_stores().await {
+        match store.purge_trash(cutoff).await {
+            Ok(r) if r.traces + r.datasets > 0 => info!(
+                %org_id,
+                traces = r.traces,
+                datasets = r.datasets,
+                "trash: purged expired items"
+            ),
+            Ok(_) => {}
+            Err(e) => error!(%org_id, "trash: purge failed: {e}"),
+        }
+    }
+}
+
+/// Spawn the periodic purge. It holds the journal weakly and stops once
+/// the router that owns it is gone.
+pub fn spawn_trash_purger(
+    org_stores: Arc<OrgStoreManager>,
+    journal: Weak<EventJournal>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if journal.upgrade().is_none() {
+                return;
+            }
+            purge_all(&org_stores).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{PersistentStore, SpanFilter, SpillConfig, StorageBackend, TraceFilter};
+    use storage_sqlite::SqliteBackend;
+    use trace::{SpanBuilder, SpanKind};
+
+    use super::super::AnyBackend;
+    use super::*;
+
+    async fn open(path: &std::path::Path) -> PersistentStore<AnyBackend> {
+        let backend = AnyBackend::Sqlite(SqliteBackend::open(path).unwrap());
+        PersistentStore::open(backend).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn trashed_traces_are_hidden_until_restored_or_purged() {
+        let dir = std::env::temp_dir().join(format!("trash-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let store = open(&db).await;
+        let trace = Trace::new(Some("run".into()));
+        store.save_trace(trace.clone()).await.unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let span = SpanBuilder::new(trace.id, "step", kind).build();
+        store.insert(span.clone()).await.unwrap();
+        let dataset = Dataset::new("golden", None);
+        store.save_dataset(dataset.clone()).await.unwrap();
+
+        assert!(store.trash_trace(trace.id).await.unwrap());
+        assert!(!store.trash_trace(trace.id).await.unwrap());
+        assert!(matches!(
+            store.trash_dataset(dataset.id).await.unwrap(),
+            Versioned::Updated(_)
+        ));
+        let all = TraceFilter::default();
+        assert!(store.query_traces(&all).await.unwrap().items.is_empty());
+        assert!(store
+            .query_spans(&SpanFilter::default())
+            .await
+            .unwrap()
+            .items
+            .is_empty());
+        assert!(store.get_trace_or_load(trace.id).await.is_none());
+        assert!(store.all_datasets().is_empty());
+        drop(store);
+
+        // The trash survives a restart
+        let store = open(&db).await;
+        assert!(store.is_trashed(trace.id));
+        assert!(store.trace_spans(trace.id, false).await.unwrap().is_empty());
+        assert_eq!(store.trashed_datasets().len(), 1);
+        store.restore_trace(trace.id).await.unwrap().unwrap();
+        assert!(matches!(
+            store.restore_dataset(dataset.id).await.unwrap(),
+            Versioned::Updated(_)
+        ));
+        assert_eq!(store.query_traces(&all).await.unwrap().items.len(), 1);
+        assert_eq!(store.trace_spans(trace.id, false).await.unwrap().len(), 1);
+        assert!(store.get_dataset(dataset.id).is_some());
+
+        // Only items trashed before the cutoff are purged
+        store.trash_trace(trace.id).await.unwrap();
+        let report = store
+            .purge_trash(Utc::now() - chrono::Duration::days(TRASH_DAYS))
+            .await
+            .unwrap();
+        assert_eq!(report.traces, 0);
+        let report = store.purge_trash(Utc::now()).await.unwrap();
+        assert_eq!(report.traces, 1);
+        assert!(store.trashed_traces().await.unwrap().is_empty());
+        assert!(store.get(span.id()).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn trashed_traces_stay_out_of_listings_after_restart() {
+        let dir = std::env::temp_dir().join(format!("trash-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let store = open(&db).await;
+        let live = Trace::new(Some("live".into()));
+        let trashed = Trace::new(Some("trashed".into()));
+        store.save_trace(live.clone()).await.unwrap();
+        store.save_trace(trashed.clone()).await.unwrap();
+        assert!(store.trash_trace(trashed.id).await.unwrap());
+        drop(store);
+
+        let store = open(&db).await;
+        let listed = store.query_traces(&TraceFilter::default()).await.unwrap();
+        let ids: Vec<_> = listed.items.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [live.id]);
+        let in_trash = store.trashed_traces().await.unwrap();
+        assert_eq!(in_trash.len(), 1);
+        assert_eq!(in_trash[0].id, trashed.id);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn trashing_a_stale_dataset_keeps_newer_edits() {
+        let dir = std::env::temp_dir().join(format!("trash-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+
+        let first = open(&db).await;
+        let dataset = Dataset::new("golden", None);
+        first.save_dataset(dataset.clone()).await.unwrap();
+
+        // The second instance renames it while the first still has version 0
+        let second = open(&db).await;
+        let mut renamed = second.get_dataset(dataset.id).unwrap();
+        renamed.name = "golden v2".into();
+        assert!(matches!(
+            second.update_dataset(renamed).await.unwrap(),
+            Versioned::Updated(_)
+        ));
+
+        let Versioned::Updated(trashed) = first.trash_dataset(dataset.id).await.unwrap() else {
+            panic!("trashing should apply");
+        };
+        assert_eq!((trashed.name.as_str(), trashed.version), ("golden v2", 2));
+        assert!(matches!(
+            first.trash_dataset(dataset.id).await.unwrap(),
+            Versioned::NotFound
+        ));
+
+        // The second instance's cache still shows it live at version 1
+        let Versioned::Updated(restored) = second.restore_dataset(dataset.id).await.unwrap() else {
+            panic!("restoring should apply");
+        };
+        assert_eq!(restored.version, 3);
+        assert!(restored.deleted_at.is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn spilled_writes_do_not_bring_back_deleted_traces() {
+        let dir = std::env::temp_dir().join(format!("trash-{}", Uuid::new_v4()));
+        let spill_dir = dir.join("spill");
+        std::fs::create_dir_all(&spill_dir).unwrap();
+        let db = dir.join("traces.db");
+        let trace = Trace::new(Some("run".into()));
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let spilled = SpanBuilder::new(trace.id, "step", kind).build();
+
+        // A write the backend failed on, still queued for replay
+        let line = serde_json::json!({ "kind": "span", "data": spilled });
+        std::fs::write(spill_dir.join("spill.jsonl"), format!("{line}\n")).unwrap();
+        let config = SpillConfig {
+            dir: spill_dir,
+            replay_interval: std::time::Duration::from_secs(3600),
+        };
+        let store = open(&db).await.with_spill(&config).unwrap();
+        store.save_trace(trace.clone()).await.unwrap();
+        assert_eq!(store.spill_status().unwrap().pending, 2);
+
+        store.delete_trace(trace.id).await.unwrap();
+        assert_eq!(store.spill_status().unwrap().pending, 0);
+        drop(store);
+
+        let store = open(&db).await.with_spill(&config).unwrap();
+        assert!(store
+            .backend()
+            .get_span(spilled.id())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.get_trace_or_load(trace.id).await.is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
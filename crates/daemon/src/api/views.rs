@@ -0,0 +1,296 @@
+//! Saved views: named span queries shared across an org, such as
+//! "prod gpt-4o errors last 24h".
+//!
+//! A view stores a query in the span query language together with the
+//! columns and sort order to show its results in. Views live in the org
+//! store, so every project in the org sees the same ones; applying a view
+//! runs its query against the caller's project.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use storage::filter::SPAN_SORT_FIELDS;
+use storage::Page;
+use trace::{SavedView, SavedViewId, Span};
+use utoipa::{IntoParams, ToSchema};
+
+use super::error::Problem;
+use super::{
+    api_error, audit, org_store, project_store, require_scope, ApiError, AppState, SharedStore,
+    MAX_PAGE_LIMIT,
+};
+
+const MAX_NAME_LEN: usize = 200;
+const MAX_COLUMNS: usize = 50;
+
+/// Body for `POST /api/views` and `PUT /api/views/:id`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ViewRequest {
+    pub name: String,
+    /// In the span query language, e.g. `model:gpt-4o status:failed since:24h`.
+    pub query: String,
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// One of `started_at`, `duration`, `tokens`, `cost`, or `name`.
+    #[serde(default)]
+    pub sort_by: Option<String>,
+    /// `asc` or `desc`.
+    #[serde(default)]
+    pub sort_order: Option<String>,
+}
+
+impl ViewRequest {
+    fn validate(&self) -> Result<(), ApiError> {
+        let bad = |msg: String| api_error(StatusCode::BAD_REQUEST, msg);
+        if self.name.trim().is_empty() || self.name.len() > MAX_NAME_LEN {
+            return Err(bad(format!("name must be 1 to {MAX_NAME_LEN} characters")));
+        }
+        storage::parse_span_query(&self.query).map_err(|e| bad(e.to_string()))?;
+        if self.columns.len() > MAX_COLUMNS {
+            return Err(bad(format!("at most {MAX_COLUMNS} columns")));
+        }
+        if let Some(sort_by) = self.sort_by.as_deref() {
+            if !SPAN_SORT_FIELDS.contains(&sort_by) {
+                return Err(bad(format!(
+                    "sort_by must be one of {}",
+                    SPAN_SORT_FIELDS.join(", ")
+                )));
+            }
+        }
+        if self
+            .sort_order
+            .as_deref()
+            .is_some_and(|o| o != "asc" && o != "desc")
+        {
+            return Err(bad("sort_order must be asc or desc".to_string()));
+        }
+        Ok(())
+    }
+
+    fn apply(self, view: &mut SavedView) {
+        view.name = self.name.trim().to_string();
+        view.query = self.query;
+        view.columns = self.columns;
+        view.sort_by = self.sort_by;
+        view.sort_order = self.sort_order;
+    }
+}
+
+/// Query parameters for `GET /api/views/:id/spans`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ApplyViewQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+async fn find_view(store: &SharedStore, id: SavedViewId) -> Result<SavedView, ApiError> {
+    store
+        .list_views()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .into_iter()
+        .find(|v| v.id == id)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "view not found").with_code("view_not_found"))
+}
+
+/// Saved views, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/views",
+    tag = "views",
+    responses(
+        (status = 200, body = Vec<SavedView>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn list_views(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SavedView>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let views = org_store(&ctx, &state)
+        .await?
+        .list_views()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(views))
+}
+
+/// Save a view for everyone in the org.
+#[utoipa::path(
+    post,
+    path = "/api/views",
+    tag = "views",
+    request_body = ViewRequest,
+    responses(
+        (status = 201, body = SavedView),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn create_view(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<ViewRequest>,
+) -> Result<(StatusCode, Json<SavedView>), ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    req.validate()?;
+    let mut view = SavedView::new(String::new(), String::new(), audit::actor(&ctx));
+    req.apply(&mut view);
+    org_store(&ctx, &state)
+        .await?
+        .save_view(&view)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok((StatusCode::CREATED, Json(view)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/views/{id}",
+    tag = "views",
+    params(("id" = String, Path, description = "View id")),
+    responses(
+        (status = 200, body = SavedView),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn get_view(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SavedViewId>,
+) -> Result<Json<SavedView>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let store = org_store(&ctx, &state).await?;
+    Ok(Json(find_view(&store, id).await?))
+}
+
+/// Replace a view's name, query, columns, and sort.
+#[utoipa::path(
+    put,
+    path = "/api/views/{id}",
+    tag = "views",
+    params(("id" = String, Path, description = "View id")),
+    request_body = ViewRequest,
+    responses(
+        (status = 200, body = SavedView),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn update_view(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SavedViewId>,
+    Json(req): Json<ViewRequest>,
+) -> Result<Json<SavedView>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    req.validate()?;
+    let store = org_store(&ctx, &state).await?;
+    let mut view = find_view(&store, id).await?;
+    req.apply(&mut view);
+    view.updated_at = Utc::now();
+    store
+        .save_view(&view)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(view))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/views/{id}",
+    tag = "views",
+    params(("id" = String, Path, description = "View id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn delete_view(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SavedViewId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesWrite)?;
+    let deleted = org_store(&ctx, &state)
+        .await?
+        .delete_view(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !deleted {
+        return Err(api_error(StatusCode::NOT_FOUND, "view not found").with_code("view_not_found"));
+    }
+    audit::record(
+        &state,
+        &ctx,
+        "view.delete",
+        Some(id.to_string()),
+        serde_json::Value::Null,
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// One page of the spans a view selects in the caller's project, in the
+/// view's sort order.
+#[utoipa::path(
+    get,
+    path = "/api/views/{id}/spans",
+    tag = "views",
+    params(("id" = String, Path, description = "View id"), ApplyViewQuery),
+    responses(
+        (status = 200, body = Page<Span>),
+        (status = "4XX", response = Problem),
+    )
+)]
+pub async fn apply_view(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<SavedViewId>,
+    Query(q): Query<ApplyViewQuery>,
+) -> Result<Json<Page<Span>>, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let view = find_view(&org_store(&ctx, &state).await?, id).await?;
+    let mut filter = storage::parse_span_query(&view.query)
+        .map_err(|e| api_error(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    // Sort terms written into the query win over the view's own
+    filter.sort_by = filter.sort_by.or(view.sort_by);
+    filter.sort_order = filter.sort_order.or(view.sort_order);
+    filter.limit = q.limit.map(|l| l.clamp(1, MAX_PAGE_LIMIT));
+    filter.cursor = q.cursor;
+
+    let store = project_store(&ctx, &state).await?;
+    let page = store.query_spans(&filter).await.map_err(|e| match e {
+        storage::StorageError::Serialization(_) => api_error(StatusCode::BAD_REQUEST, e),
+        _ => api_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    })?;
+    Ok(Json(page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str, sort_by: Option<&str>) -> ViewRequest {
+        ViewRequest {
+            name: "prod errors".into(),
+            query: query.into(),
+            columns: vec!["name".into(), "duration".into()],
+            sort_by: sort_by.map(Into::into),
+            sort_order: Some("desc".into()),
+        }
+    }
+
+    #[test]
+    fn views_must_hold_a_valid_query_and_sort() {
+        assert!(request("model:gpt-4o status:failed since:24h", Some("cost"))
+            .validate()
+            .is_ok());
+        assert!(request("since:yesterday-ish", None).validate().is_err());
+        assert!(request("status:failed", Some("color")).validate().is_err());
+    }
+}
@@ -0,0 +1,674 @@
+//! Outbound webhooks.
+//!
+//! Subscriptions belong to an org, like the event journal they follow. The
+//! dispatcher reads every journaled event and POSTs it to each enabled
+//! webhook that wants its type, signed with the webhook's secret:
+//!
+//! `X-Traceway-Signature: sha256=<hex HMAC-SHA256(secret, "{timestamp}.{body}")>`
+//!
+//! where `timestamp` is the `X-Traceway-Timestamp` header. Failed
+//! deliveries are retried with backoff, and the outcome of each one is kept
+//! in the delivery log.
+//!
+//! Outside local mode, webhooks may only reach public addresses: a URL
+//! whose host is or resolves to a loopback, private or link-local address
+//! is refused when registered, and deliveries connect only to the public
+//! addresses their host resolves to at the time. Redirects are never
+//! followed.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{broadcast, Mutex, Semaphore};
+use trace::{DeliveryStatus, Webhook, WebhookDelivery, WebhookId};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::events::StoredEvent;
+use super::org_store::OrgStoreManager;
+use super::{
+    api_error, audit, org_store, require_scope, ApiError, AppState, SharedStore, SystemEvent,
+    MAX_PAGE_LIMIT,
+};
+
+/// Event types a webhook can subscribe to. `span_streaming` deltas are not
+/// journaled, so they are never delivered.
+pub const EVENT_TYPES: &[&str] = &[
+    "span_created",
+    "span_completed",
+    "span_failed",
+    "trace_created",
+    "trace_completed",
+    "file_version_created",
+    "span_deleted",
+    "trace_deleted",
+    "dataset_created",
+    "dataset_updated",
+    "dataset_deleted",
+    "datapoint_created",
+    "datapoint_updated",
+    "queue_item_updated",
+    "eval_run_created",
+    "eval_run_updated",
+    "eval_run_completed",
+    "capture_rule_fired",
+    "budget_alert",
+    "anomaly_detected",
+    "cleared",
+];
+
+/// Wait before each retry; a delivery gets one more attempt than this has entries.
+const RETRY_DELAYS: [Duration; 4] = [
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+    Duration::from_secs(120),
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the dispatcher trusts its copy of an org's webhooks.
+const CACHE_TTL: Duration = Duration::from_secs(30);
+/// Deliveries in flight at once, across all orgs.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Body for `POST /api/webhooks`.
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    /// Generated when omitted; returned only in the create response.
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    pub limit: Option<usize>,
+}
+
+/// What a receiver gets: the journaled event plus its delivery metadata.
+#[derive(Serialize)]
+struct Payload<'a> {
+    delivery_id: Uuid,
+    sequence: u64,
+    timestamp: chrono::DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a SystemEvent,
+}
+
+/// `sha256=<hex>` signature of `body` sent at `timestamp`.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+fn generate_secret() -> String {
+    let bytes: [u8; 24] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("whsec_{hex}")
+}
+
+/// The serde tag of `event`, e.g. `span_failed`.
+fn event_type(event: &SystemEvent) -> Option<String> {
+    let value = serde_json::to_value(event).ok()?;
+    value.get("type")?.as_str().map(str::to_string)
+}
+
+/// Hide all but the last four characters of a secret.
+fn redact(mut webhook: Webhook) -> Webhook {
+    let tail: String = webhook
+        .secret
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    webhook.secret = format!("…{tail}");
+    webhook
+}
+
+type CachedWebhooks = (Instant, Arc<Vec<Webhook>>);
+
+/// Whether `ip` can be reached from outside the daemon's own host and
+/// network. Cloud metadata endpoints such as 169.254.169.254 are
+/// link-local, so they count as internal.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    // Unique local, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link-local, fe80::/10
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The address `url` names directly, if its host is an IP literal.
+fn literal_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Refuse `url` if its host is, or resolves to, an address that isn't
+/// public.
+async fn check_destination(url: &reqwest::Url) -> Result<(), String> {
+    let addrs: Vec<IpAddr> = match literal_ip(url) {
+        Some(ip) => vec![ip],
+        None => {
+            let host = url.host_str().ok_or("url has no host")?;
+            let port = url.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("can't resolve {host}: {e}"))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    match addrs.into_iter().find(|ip| !is_public(*ip)) {
+        Some(ip) => Err(format!(
+            "url resolves to {ip}, which is not a public address"
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Resolves webhook hosts to their public addresses only. The client
+/// connects to what this returns, so a host can't pass registration and
+/// later resolve somewhere internal.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Fans journaled events out to subscribed webhooks.
+pub struct WebhookDispatcher {
+    org_stores: Arc<OrgStoreManager>,
+    client: reqwest::Client,
+    /// Deliver only to public addresses; off in local mode.
+    public_only: bool,
+    /// Each org's webhooks and when they were loaded.
+    cache: Mutex<HashMap<Uuid, CachedWebhooks>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(org_stores: Arc<OrgStoreManager>, local_mode: bool) -> Arc<Self> {
+        let mut client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none());
+        if !local_mode {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+        Arc::new(Self {
+            org_stores,
+            // Never the default client, which would follow redirects and
+            // resolve hosts anywhere
+            client: client.build().expect("webhook client"),
+            public_only: !local_mode,
+            cache: Mutex::new(HashMap::new()),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        })
+    }
+
+    /// Drop the cached subscriptions for `org_id` after they change.
+    pub async fn invalidate(&self, org_id: Uuid) {
+        self.cache.lock().await.remove(&org_id);
+    }
+
+    async fn webhooks_for(&self, org_id: Uuid, store: &SharedStore) -> Arc<Vec<Webhook>> {
+        if let Some((loaded, hooks)) = self.cache.lock().await.get(&org_id) {
+            if loaded.elapsed() < CACHE_TTL {
+                return hooks.clone();
+            }
+        }
+        let hooks = match store.list_webhooks().await {
+            Ok(hooks) => Arc::new(hooks),
+            Err(e) => {
+                warn!(%org_id, error = %e, "failed to load webhooks");
+                Arc::default()
+            }
+        };
+        self.cache
+            .lock()
+            .await
+            .insert(org_id, (Instant::now(), hooks.clone()));
+        hooks
+    }
+
+    /// Deliver every event from `rx` until the journal closes.
+    pub fn spawn(
+        self: Arc<Self>,
+        mut rx: broadcast::Receiver<StoredEvent>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => self.dispatch(Arc::new(event)).await,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!(
+                            skipped = n,
+                            "webhook dispatcher lagged; events not delivered"
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+
+    async fn dispatch(&self, event: Arc<StoredEvent>) {
+        let Ok(org_id) = event.org_id.parse::<Uuid>() else {
+            return;
+        };
+        let Some(event_type) = event_type(&event.event) else {
+            return;
+        };
+        let Ok(store) = self.org_stores.get(org_id).await else {
+            return;
+        };
+        let hooks = self.webhooks_for(org_id, &store).await;
+        for webhook in hooks.iter().filter(|w| w.wants(&event_type)) {
+            let Ok(permit) = self.in_flight.clone().acquire_owned().await else {
+                return;
+            };
+            let client = self.client.clone();
+            let store = store.clone();
+            let webhook = webhook.clone();
+            let event = event.clone();
+            let event_type = event_type.clone();
+            let public_only = self.public_only;
+            tokio::spawn(async move {
+                let delivery = deliver(&client, &webhook, &event, event_type, public_only).await;
+                if let Err(e) = store.save_webhook_delivery(&delivery).await {
+                    warn!(webhook_id = %webhook.id, error = %e, "failed to record webhook delivery");
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// POST `event` to `webhook`, retrying failures, and describe how it went.
+/// With `public_only`, a URL naming an internal address directly fails
+/// without a request; hostnames are left to the client's resolver.
+async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: &StoredEvent,
+    event_type: String,
+    public_only: bool,
+) -> WebhookDelivery {
+    let id = Uuid::now_v7();
+    let created_at = Utc::now();
+    let body = serde_json::to_vec(&Payload {
+        delivery_id: id,
+        sequence: event.sequence,
+        timestamp: event.timestamp,
+        event: &event.event,
+    })
+    .unwrap_or_default();
+
+    let mut attempts = 0;
+    let mut delays = RETRY_DELAYS.iter();
+    let internal = reqwest::Url::parse(&webhook.url)
+        .ok()
+        .and_then(|url| literal_ip(&url))
+        .filter(|ip| public_only && !is_public(*ip));
+    let (status, response_status, error) = loop {
+        if let Some(ip) = internal {
+            break (
+                DeliveryStatus::Failed,
+                None,
+                Some(format!("{ip} is not a public address")),
+            );
+        }
+        attempts += 1;
+        let timestamp = Utc::now().timestamp();
+        let result = client
+            .post(&webhook.url)
+            .header("content-type", "application/json")
+            .header("x-traceway-event", &event_type)
+            .header("x-traceway-delivery", id.to_string())
+            .header("x-traceway-timestamp", timestamp.to_string())
+            .header(
+                "x-traceway-signature",
+                sign(&webhook.secret, timestamp, &body),
+            )
+            .body(body.clone())
+            .send()
+            .await;
+        let (response_status, error) = match result {
+            Ok(resp) if resp.status().is_success() => {
+                break (
+                    DeliveryStatus::Succeeded,
+                    Some(resp.status().as_u16()),
+                    None,
+                );
+            }
+            Ok(resp) => (
+                Some(resp.status().as_u16()),
+                format!("receiver returned {}", resp.status()),
+            ),
+            Err(e) => (None, e.to_string()),
+        };
+        let Some(delay) = delays.next() else {
+            break (DeliveryStatus::Failed, response_status, Some(error));
+        };
+        debug!(webhook_id = %webhook.id, attempts, "webhook delivery failed, retrying");
+        tokio::time::sleep(*delay).await;
+    };
+
+    WebhookDelivery {
+        id,
+        webhook_id: webhook.id,
+        event_type,
+        sequence: event.sequence,
+        status,
+        attempts,
+        response_status,
+        error,
+        created_at,
+        completed_at: Utc::now(),
+    }
+}
+
+// --- Handlers ---
+
+/// Register a webhook. The response is the only one that includes the secret.
+pub async fn create_webhook(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Json(req): Json<CreateWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let url = reqwest::Url::parse(&req.url)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid url: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "url must be http or https",
+        ));
+    }
+    if !state.auth_config.local_mode {
+        check_destination(&url)
+            .await
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    }
+    if let Some(unknown) = req
+        .events
+        .iter()
+        .find(|e| !EVENT_TYPES.contains(&e.as_str()))
+    {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("unknown event type '{unknown}'"),
+        ));
+    }
+    let secret = match req.secret {
+        Some(secret) if secret.is_empty() => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "secret must not be empty",
+            ));
+        }
+        Some(secret) => secret,
+        None => generate_secret(),
+    };
+
+    let webhook = Webhook::new(req.url, secret, req.events);
+    let store = org_store(&ctx, &state).await?;
+    store
+        .save_webhook(&webhook)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.webhooks.invalidate(ctx.org_id).await;
+    audit::record(
+        &state,
+        &ctx,
+        "webhook.create",
+        Some(webhook.id.to_string()),
+        serde_json::json!({ "url": webhook.url, "events": webhook.events }),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// Registered webhooks, with secrets redacted.
+pub async fn list_webhooks(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Webhook>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = org_store(&ctx, &state).await?;
+    let webhooks = store
+        .list_webhooks()
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(webhooks.into_iter().map(redact).collect()))
+}
+
+pub async fn delete_webhook(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<WebhookId>,
+) -> Result<StatusCode, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = org_store(&ctx, &state).await?;
+    let deleted = store
+        .delete_webhook(id)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.webhooks.invalidate(ctx.org_id).await;
+    if deleted {
+        audit::record(
+            &state,
+            &ctx,
+            "webhook.delete",
+            Some(id.to_string()),
+            serde_json::Value::Null,
+        )
+        .await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(StatusCode::NOT_FOUND, "webhook not found").with_code("webhook_not_found"))
+    }
+}
+
+/// Delivery log for one webhook, newest first.
+pub async fn list_deliveries(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    Path(id): Path<WebhookId>,
+    Query(q): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<WebhookDelivery>>, ApiError> {
+    require_scope(&ctx, auth::Scope::Admin)?;
+    let store = org_store(&ctx, &state).await?;
+    let limit = q.limit.unwrap_or(100).min(MAX_PAGE_LIMIT);
+    let deliveries = store
+        .list_webhook_deliveries(id, limit)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let sig = sign("whsec_test", 1_700_000_000, b"{\"a\":1}");
+        assert!(sig.starts_with("sha256="));
+        assert_eq!(sig.len(), "sha256=".len() + 64);
+        assert_eq!(sig, sign("whsec_test", 1_700_000_000, b"{\"a\":1}"));
+        assert_ne!(sig, sign("whsec_test", 1_700_000_001, b"{\"a\":1}"));
+        assert_ne!(sig, sign("whsec_other", 1_700_000_000, b"{\"a\":1}"));
+    }
+
+    #[test]
+    fn event_types_match_serde_tags() {
+        assert_eq!(
+            event_type(&SystemEvent::Cleared).as_deref(),
+            Some("cleared")
+        );
+        let deleted = SystemEvent::TraceDeleted {
+            trace_id: Uuid::now_v7(),
+        };
+        let tag = event_type(&deleted).unwrap();
+        assert_eq!(tag, "trace_deleted");
+        assert!(EVENT_TYPES.contains(&tag.as_str()));
+    }
+
+    #[test]
+    fn filter_and_redaction() {
+        let mut hook = Webhook::new(
+            "https://example.com/hook".into(),
+            "whsec_abcdef".into(),
+            vec!["span_failed".into()],
+        );
+        assert!(hook.wants("span_failed"));
+        assert!(!hook.wants("span_created"));
+        hook.enabled = false;
+        assert!(!hook.wants("span_failed"));
+        assert_eq!(redact(hook).secret, "…cdef");
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for internal in [
+            "127.0.0.1",
+            "10.0.0.8",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(internal.parse().unwrap()), "{internal}");
+        }
+        for public in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(public.parse().unwrap()), "{public}");
+        }
+        let url = reqwest::Url::parse("http://[::1]:8080/hook").unwrap();
+        assert_eq!(literal_ip(&url), Some("::1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn deliveries_stay_off_internal_addresses() {
+        use axum::response::Redirect;
+        use storage::PersistentStore;
+        use storage_sqlite::SqliteBackend;
+
+        use crate::api::AnyBackend;
+
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = hits.clone();
+        let receiver = axum::Router::new()
+            .route(
+                "/hook",
+                axum::routing::post(|| async { Redirect::temporary("/moved") }),
+            )
+            .route(
+                "/moved",
+                axum::routing::post(move || {
+                    counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async { StatusCode::OK }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let org_stores = Arc::new(OrgStoreManager::single(store));
+        let event = StoredEvent {
+            sequence: 1,
+            event: SystemEvent::Cleared,
+            timestamp: Utc::now(),
+            org_id: Uuid::nil().to_string(),
+        };
+        let hook = Webhook::new(url.clone(), "whsec_test".into(), Vec::new());
+
+        // Named directly, a loopback address isn't even tried
+        let cloud = WebhookDispatcher::new(org_stores.clone(), false);
+        let delivery = deliver(&cloud.client, &hook, &event, "cleared".into(), true).await;
+        assert!(matches!(delivery.status, DeliveryStatus::Failed));
+        assert_eq!(delivery.attempts, 0);
+
+        // Redirects aren't followed, even locally
+        let local = WebhookDispatcher::new(org_stores, true);
+        let response = local.client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // Hostnames resolve to public addresses only
+        let local_name = url.replace("127.0.0.1", "localhost");
+        assert!(cloud.client.post(&local_name).send().await.is_err());
+        assert!(
+            check_destination(&reqwest::Url::parse(&local_name).unwrap())
+                .await
+                .is_err()
+        );
+    }
+}
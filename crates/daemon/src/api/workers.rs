@@ -0,0 +1,428 @@
+//! Background job workers.
+//!
+//! A worker claims jobs from a [`JobQueue`] and runs the handler
+//! registered under the job's name, up to `concurrency` at once. Each
+//! claimed job's lock is renewed while its handler runs, so a job is only
+//! picked up again if its worker dies. A failed job is retried with its
+//! backoff until its attempts run out and it goes to the dead-letter
+//! queue. Jobs with no handler, or data their handler can't read, fail
+//! without retrying.
+//!
+//! On shutdown the worker stops claiming and waits up to `drain_secs` for
+//! in-flight jobs. Anything still running after that keeps its lock until
+//! it lapses and is then retried, by this or another instance.
+
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde::Deserialize;
+use storage::{SpanFilter, StorageBackend};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tracing::{error, info, warn};
+
+use super::jobs::{Job, JobQueue};
+use super::{reports, retention, AppState, OrgStoreManager};
+use crate::config::WorkersConfig;
+
+/// How long an idle worker waits before polling the queue again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Spans re-saved per page by `embed_spans`.
+const EMBED_PAGE: usize = 500;
+
+/// Why a job failed.
+#[derive(Debug)]
+pub enum JobFailure {
+    /// Retried per the job's backoff, then dead-lettered.
+    Retry(String),
+    /// Failed at once; running it again wouldn't help.
+    Permanent(String),
+}
+
+impl From<String> for JobFailure {
+    fn from(e: String) -> Self {
+        JobFailure::Retry(e)
+    }
+}
+
+/// Runs jobs of one name.
+#[async_trait]
+pub trait JobHandler: Send + Sync + 'static {
+    /// Returns the job's result, or why it failed.
+    async fn handle(&self, job: &Job) -> Result<serde_json::Value, JobFailure>;
+}
+
+pub struct Worker {
+    queue: Arc<dyn JobQueue>,
+    config: WorkersConfig,
+    handlers: HashMap<String, Arc<dyn JobHandler>>,
+}
+
+/// A running worker.
+pub struct WorkerHandle {
+    stop: watch::Sender<bool>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl WorkerHandle {
+    /// Stop claiming jobs and wait for in-flight ones to drain.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        if let Err(e) = self.task.await {
+            error!("workers: run loop failed: {e}");
+        }
+    }
+}
+
+impl Worker {
+    pub fn new(queue: Arc<dyn JobQueue>, config: WorkersConfig) -> Self {
+        Self {
+            queue,
+            config,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Run `handler` for jobs named `name`.
+    pub fn register(mut self, name: &str, handler: Arc<dyn JobHandler>) -> Self {
+        self.handlers.insert(name.to_string(), handler);
+        self
+    }
+
+    pub fn spawn(self) -> WorkerHandle {
+        let (stop, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(Arc::new(self).run(stop_rx));
+        WorkerHandle { stop, task }
+    }
+
+    fn lock(&self) -> Duration {
+        Duration::from_secs(self.config.lock_secs.max(1))
+    }
+
+    async fn run(self: Arc<Self>, mut stop: watch::Receiver<bool>) {
+        let concurrency = self.config.concurrency.max(1);
+        // In-flight jobs hold a slot; draining takes them all back
+        let slots = Arc::new(Semaphore::new(concurrency));
+        let mut last_stalled_check: Option<Instant> = None;
+        info!(
+            concurrency,
+            lock_secs = self.lock().as_secs(),
+            handlers = ?self.handlers.keys().collect::<Vec<_>>(),
+            "starting job workers"
+        );
+
+        loop {
+            let permit = tokio::select! {
+                permit = slots.clone().acquire_owned() => permit.expect("worker slots closed"),
+                _ = stop.changed() => break,
+            };
+            if last_stalled_check.is_none_or(|at| at.elapsed() >= self.lock()) {
+                last_stalled_check = Some(Instant::now());
+                match self.queue.requeue_stalled().await {
+                    Ok(0) => {}
+                    Ok(n) => warn!(jobs = n, "workers: requeued stalled jobs"),
+                    Err(e) => error!("workers: stalled job check failed: {e}"),
+                }
+            }
+
+            let token = uuid::Uuid::new_v4().to_string();
+            match self.queue.claim(&token, self.lock()).await {
+                Ok(Some(job)) => {
+                    tokio::spawn(self.clone().process(job, token, permit));
+                }
+                Ok(None) => {
+                    drop(permit);
+                    tokio::select! {
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                        _ = stop.changed() => break,
+                    }
+                }
+                Err(e) => {
+                    drop(permit);
+                    error!("workers: failed to claim a job: {e}");
+                    tokio::select! {
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                        _ = stop.changed() => break,
+                    }
+                }
+            }
+        }
+
+        let drain = Duration::from_secs(self.config.drain_secs);
+        let running = concurrency - slots.available_permits();
+        if running > 0 {
+            info!(jobs = running, "workers: draining in-flight jobs");
+        }
+        let drained = tokio::time::timeout(drain, slots.acquire_many(concurrency as u32)).await;
+        if drained.is_err() {
+            warn!(
+                jobs = concurrency - slots.available_permits(),
+                "workers: jobs still running after drain timeout; they will be retried once their locks lapse"
+            );
+        }
+    }
+
+    async fn process(self: Arc<Self>, job: Job, token: String, _permit: OwnedSemaphorePermit) {
+        let started = Instant::now();
+        let result = match self.handlers.get(&job.name) {
+            Some(handler) => {
+                let renewal = tokio::spawn(renew_lock(
+                    self.queue.clone(),
+                    job.id.clone(),
+                    token.clone(),
+                    self.lock(),
+                ));
+                let result = AssertUnwindSafe(handler.handle(&job))
+                    .catch_unwind()
+                    .await
+                    .unwrap_or_else(|_| Err(JobFailure::Retry("handler panicked".into())));
+                renewal.abort();
+                result
+            }
+            None => Err(JobFailure::Permanent(format!(
+                "no handler registered for job {:?}",
+                job.name
+            ))),
+        };
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        let finished = match result {
+            Ok(value) => self.queue.complete(&job.id, &token, value).await,
+            Err(failure) => {
+                let (e, retryable) = match failure {
+                    JobFailure::Retry(e) => (e, true),
+                    JobFailure::Permanent(e) => (e, false),
+                };
+                warn!(
+                    job_id = %job.id,
+                    job = %job.name,
+                    attempt = job.attempts_made + 1,
+                    attempts = job.opts.attempts,
+                    retryable,
+                    "workers: job failed: {e}"
+                );
+                self.queue.fail(&job.id, &token, &e, retryable).await
+            }
+        };
+        match finished {
+            Ok(true) => info!(job_id = %job.id, job = %job.name, elapsed_ms, "workers: job finished"),
+            Ok(false) => warn!(
+                job_id = %job.id,
+                job = %job.name,
+                "workers: lost the job's lock before it finished; the result was dropped"
+            ),
+            Err(e) => error!(job_id = %job.id, job = %job.name, "workers: failed to record job result: {e}"),
+        }
+    }
+}
+
+/// Renew a job's lock at half its length until aborted or lost.
+async fn renew_lock(queue: Arc<dyn JobQueue>, id: String, token: String, lock: Duration) {
+    let mut interval = tokio::time::interval(lock / 2);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        match queue.extend_lock(&id, &token, lock).await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(job_id = %id, "workers: job lock lost while running");
+                return;
+            }
+            Err(e) => warn!(job_id = %id, "workers: failed to renew job lock: {e}"),
+        }
+    }
+}
+
+fn job_data<T: for<'de> Deserialize<'de>>(job: &Job) -> Result<T, JobFailure> {
+    serde_json::from_value(job.data.clone())
+        .map_err(|e| JobFailure::Permanent(format!("invalid job data: {e}")))
+}
+
+// --- Built-in handlers ---
+
+#[derive(Debug, Deserialize)]
+struct EmbedSpansData {
+    org_id: auth::OrgId,
+    #[serde(default)]
+    project_id: Option<auth::ProjectId>,
+    #[serde(default)]
+    trace_id: Option<trace::TraceId>,
+    #[serde(default)]
+    since: Option<DateTime<Utc>>,
+    /// Stop after this many spans.
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// `embed_spans`: write completed spans through the storage backend
+/// again, so backends that embed on write (Turbopuffer with an embedder)
+/// compute vectors for spans stored before embedding was set up.
+pub struct EmbedSpans {
+    org_stores: Arc<OrgStoreManager>,
+}
+
+#[async_trait]
+impl JobHandler for EmbedSpans {
+    async fn handle(&self, job: &Job) -> Result<serde_json::Value, JobFailure> {
+        let data: EmbedSpansData = job_data(job)?;
+        let store = self
+            .org_stores
+            .get_for_project(data.org_id, data.project_id.unwrap_or_default())
+            .await?;
+        let limit = data.limit.unwrap_or(usize::MAX);
+        let mut filter = SpanFilter {
+            status: Some("completed".into()),
+            trace_id: data.trace_id,
+            since: data.since,
+            ..Default::default()
+        };
+        let mut spans = 0;
+        while spans < limit {
+            filter.limit = Some(EMBED_PAGE.min(limit - spans));
+            let page = store.query_spans(&filter).await.map_err(|e| e.to_string())?;
+            for span in &page.items {
+                store.backend().save_span(span).await.map_err(|e| e.to_string())?;
+            }
+            spans += page.items.len();
+            match page.next_cursor {
+                Some(cursor) if page.has_more => filter.cursor = Some(cursor),
+                _ => break,
+            }
+        }
+        Ok(serde_json::json!({ "spans": spans }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SendDigestData {
+    org_id: auth::OrgId,
+}
+
+/// `send_digest`: send an org's scheduled reports that are due.
+pub struct SendDigest {
+    reports: Arc<reports::Reports>,
+}
+
+#[async_trait]
+impl JobHandler for SendDigest {
+    async fn handle(&self, job: &Job) -> Result<serde_json::Value, JobFailure> {
+        let data: SendDigestData = job_data(job)?;
+        let sent = self.reports.send_due(data.org_id).await?;
+        Ok(serde_json::json!({ "sent": sent }))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PruneRetentionData {
+    /// Every open store when unset.
+    #[serde(default)]
+    org_id: Option<auth::OrgId>,
+}
+
+/// `prune_retention`: prune expired data now rather than at the next
+/// retention interval.
+pub struct PruneRetention {
+    org_stores: Arc<OrgStoreManager>,
+    policy: Arc<retention::RetentionPolicy>,
+}
+
+#[async_trait]
+impl JobHandler for PruneRetention {
+    async fn handle(&self, job: &Job) -> Result<serde_json::Value, JobFailure> {
+        let data: PruneRetentionData = if job.data.is_null() {
+            PruneRetentionData::default()
+        } else {
+            job_data(job)?
+        };
+        match data.org_id {
+            Some(org_id) => {
+                let stores = retention::prune_org(&self.org_stores, &self.policy, org_id).await;
+                Ok(serde_json::json!({ "stores": stores }))
+            }
+            None => {
+                retention::prune_all(&self.org_stores, &self.policy).await;
+                Ok(serde_json::json!({}))
+            }
+        }
+    }
+}
+
+/// Spawn a worker for the state's job queue with the built-in handlers.
+pub fn spawn_workers(state: &AppState, config: WorkersConfig) -> WorkerHandle {
+    Worker::new(state.jobs.clone(), config)
+        .register(
+            "embed_spans",
+            Arc::new(EmbedSpans {
+                org_stores: state.org_stores.clone(),
+            }),
+        )
+        .register(
+            "send_digest",
+            Arc::new(SendDigest {
+                reports: state.reports.clone(),
+            }),
+        )
+        .register(
+            "prune_retention",
+            Arc::new(PruneRetention {
+                org_stores: state.org_stores.clone(),
+                policy: state.retention.clone(),
+            }),
+        )
+        .spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::jobs::{Backoff, JobOptions, MemoryJobQueue};
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails until its third call.
+    struct Flaky(AtomicU32);
+
+    #[async_trait]
+    impl JobHandler for Flaky {
+        async fn handle(&self, _job: &Job) -> Result<serde_json::Value, JobFailure> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(JobFailure::Retry("not yet".into())),
+                n => Ok(serde_json::json!({ "calls": n + 1 })),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_then_drains_on_shutdown() {
+        let queue = Arc::new(MemoryJobQueue::new());
+        let flaky = Arc::new(Flaky(AtomicU32::new(0)));
+        let opts = JobOptions {
+            attempts: 3,
+            backoff: Some(Backoff::Fixed { delay: 10 }),
+            ..Default::default()
+        };
+        queue.add("flaky", serde_json::json!({}), opts).await.unwrap();
+
+        let worker = Worker::new(queue.clone(), WorkersConfig::default())
+            .register("flaky", flaky.clone())
+            .spawn();
+        for _ in 0..50 {
+            if flaky.0.load(Ordering::SeqCst) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        worker.shutdown().await;
+
+        assert_eq!(flaky.0.load(Ordering::SeqCst), 3);
+        assert!(queue
+            .claim("t", Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,75 @@
+//! The event stream over WebSocket, at `GET /api/ws`.
+//!
+//! Takes the same query parameters as `GET /api/events` and sends each
+//! stream item as a JSON text message:
+//!
+//! ```text
+//! {"frame":"event","id":42,"event":{"type":"span_completed",...}}
+//! {"frame":"delta","event":{"type":"span_streaming",...}}
+//! {"frame":"gap","after":3,"earliest":10,"latest":42}
+//! ```
+//!
+//! To resume after a disconnect, reconnect with the last `id` seen as
+//! `?after=` or a `Last-Event-ID` header; missed events are replayed from
+//! the journal before live ones. Messages from the client are ignored.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use tokio::sync::mpsc;
+
+use super::event_stream::{open_stream, resume_point, EventFilter, EventsQuery, StreamItem};
+use super::{api_error, require_scope, ApiError, AppState};
+
+/// Keeps idle connections open through proxies.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+pub async fn ws_events(
+    auth::Auth(ctx): auth::Auth,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    require_scope(&ctx, auth::Scope::TracesRead)?;
+    let filter = EventFilter::from_query(&q).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let after = resume_point(&q, &headers);
+    let rx = open_stream(&state, ctx.org_id.to_string(), after, filter);
+    Ok(ws.on_upgrade(move |socket| forward(socket, rx)))
+}
+
+/// Relay stream items until either side goes away.
+async fn forward(mut socket: WebSocket, mut rx: mpsc::Receiver<StreamItem>) {
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    loop {
+        tokio::select! {
+            item = rx.recv() => {
+                let Some(item) = item else {
+                    break;
+                };
+                let Ok(text) = serde_json::to_string(&item) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
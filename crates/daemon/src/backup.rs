@@ -0,0 +1,237 @@
+//! `traceway backup` and `traceway restore`: archives of the local database.
+//!
+//! An archive is a zstd-compressed tarball holding `manifest.json` and a
+//! snapshot of the database taken with SQLite's backup API, so backing up
+//! a running daemon is safe. An incremental archive holds only the spans
+//! started since the watermark of the archive it follows, plus everything
+//! else whole; restore a full archive and then its incrementals in order.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use storage_sqlite::backup;
+
+use crate::config::Config;
+use crate::pid;
+
+const MANIFEST: &str = "manifest.json";
+const DATABASE: &str = "traces.db";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(clap::Args, Debug)]
+pub struct BackupArgs {
+    /// Archive to write, e.g. `traces-2024.tar.zst`
+    #[arg(long)]
+    out: PathBuf,
+
+    /// Earlier archive to continue from: only spans started since its
+    /// watermark are included
+    #[arg(long)]
+    since: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// A full archive, then any incremental archives taken after it,
+    /// oldest first
+    #[arg(required = true)]
+    archives: Vec<PathBuf>,
+
+    /// Replace an existing database
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    created_at: DateTime<Utc>,
+    /// The watermark this archive continues from; `None` for a full one.
+    since: Option<String>,
+    /// Where an archive continuing from this one starts.
+    watermark: Option<String>,
+    spans: usize,
+}
+
+pub fn backup(args: BackupArgs, db_path: &Path) -> Result<(), String> {
+    let since = match &args.since {
+        Some(previous) => {
+            let manifest = read_manifest(previous)?;
+            match manifest.watermark {
+                Some(watermark) => Some(watermark),
+                None => {
+                    return Err(format!(
+                        "{} holds no spans to continue from",
+                        previous.display()
+                    ))
+                }
+            }
+        }
+        None => None,
+    };
+
+    let work = WorkDir::new()?;
+    let snapshot = work.path().join(DATABASE);
+    let info = backup::snapshot(db_path, &snapshot, since.as_deref())
+        .map_err(|e| format!("{}: {e}", db_path.display()))?;
+    let manifest = Manifest {
+        format: FORMAT_VERSION,
+        created_at: Utc::now(),
+        since,
+        watermark: info.watermark,
+        spans: info.spans,
+    };
+    let manifest_path = work.path().join(MANIFEST);
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    std::fs::write(&manifest_path, json).map_err(|e| e.to_string())?;
+
+    write_archive(&args.out, &manifest_path, &snapshot)
+        .map_err(|e| format!("{}: {e}", args.out.display()))?;
+    let kind = if manifest.since.is_some() {
+        "incremental"
+    } else {
+        "full"
+    };
+    println!(
+        "wrote {kind} backup of {} spans to {}",
+        manifest.spans,
+        args.out.display()
+    );
+    if let Some(watermark) = &manifest.watermark {
+        println!("watermark: {watermark}");
+    }
+    Ok(())
+}
+
+pub fn restore(args: RestoreArgs, db_path: &Path) -> Result<(), String> {
+    if let Some(pid) = pid::check_running(&Config::pid_path()) {
+        return Err(format!(
+            "daemon is running (pid {pid}); stop it before restoring"
+        ));
+    }
+    if db_path.exists() && !args.force {
+        return Err(format!(
+            "{} already exists; pass --force to replace it",
+            db_path.display()
+        ));
+    }
+
+    let mut previous: Option<Manifest> = None;
+    for archive in &args.archives {
+        let work = WorkDir::new()?;
+        extract_archive(archive, work.path()).map_err(|e| format!("{}: {e}", archive.display()))?;
+        let manifest = read_manifest_file(&work.path().join(MANIFEST))
+            .map_err(|e| format!("{}: {e}", archive.display()))?;
+        let snapshot = work.path().join(DATABASE);
+
+        match (&previous, &manifest.since) {
+            (None, None) => {
+                backup::restore(&snapshot, db_path).map_err(|e| e.to_string())?;
+                println!("{}: restored {} spans", archive.display(), manifest.spans);
+            }
+            (None, Some(_)) => {
+                return Err(format!(
+                    "{} is incremental; restore the full backup it follows first",
+                    archive.display()
+                ))
+            }
+            (Some(last), Some(since)) if last.watermark.as_ref() == Some(since) => {
+                let rows = backup::merge(&snapshot, db_path).map_err(|e| e.to_string())?;
+                println!("{}: merged {rows} rows", archive.display());
+            }
+            (Some(_), _) => {
+                return Err(format!(
+                    "{} doesn't continue from the archive before it",
+                    archive.display()
+                ))
+            }
+        }
+        previous = Some(manifest);
+    }
+    Ok(())
+}
+
+fn write_archive(out: &Path, manifest: &Path, snapshot: &Path) -> std::io::Result<()> {
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let encoder = zstd::Encoder::new(File::create(out)?, 0)?;
+    let mut tar = tar::Builder::new(encoder);
+    tar.append_path_with_name(manifest, MANIFEST)?;
+    tar.append_path_with_name(snapshot, DATABASE)?;
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn extract_archive(archive: &Path, dest: &Path) -> std::io::Result<()> {
+    let decoder = zstd::Decoder::new(File::open(archive)?)?;
+    let mut tar = tar::Archive::new(decoder);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if name == MANIFEST || name == DATABASE {
+            entry.unpack(dest.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// The manifest of an archive, read without unpacking the database.
+fn read_manifest(archive: &Path) -> Result<Manifest, String> {
+    let read = || -> std::io::Result<Option<Vec<u8>>> {
+        let decoder = zstd::Decoder::new(File::open(archive)?)?;
+        let mut tar = tar::Archive::new(decoder);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.as_os_str() == MANIFEST {
+                let mut bytes = Vec::new();
+                std::io::Read::read_to_end(&mut entry, &mut bytes)?;
+                return Ok(Some(bytes));
+            }
+        }
+        Ok(None)
+    };
+    match read() {
+        Ok(Some(bytes)) => parse_manifest(&bytes),
+        Ok(None) => Err(format!("no {MANIFEST}")),
+        Err(e) => Err(e.to_string()),
+    }
+    .map_err(|e| format!("{}: {e}", archive.display()))
+}
+
+fn read_manifest_file(path: &Path) -> Result<Manifest, String> {
+    let bytes = std::fs::read(path).map_err(|_| format!("no {MANIFEST}"))?;
+    parse_manifest(&bytes)
+}
+
+fn parse_manifest(bytes: &[u8]) -> Result<Manifest, String> {
+    let manifest: Manifest =
+        serde_json::from_slice(bytes).map_err(|e| format!("invalid {MANIFEST}: {e}"))?;
+    if manifest.format != FORMAT_VERSION {
+        return Err(format!("unsupported backup format {}", manifest.format));
+    }
+    Ok(manifest)
+}
+
+/// A scratch directory, removed when dropped.
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    fn new() -> Result<Self, String> {
+        let path = std::env::temp_dir().join(format!("traceway-backup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
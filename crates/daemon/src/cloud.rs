@@ -6,6 +6,11 @@
 use std::env;
 use tracing::{info, warn};
 
+use crate::config::{
+    PayloadSettings, QueueConfig, RateLimit, RateLimitConfig, RetentionConfig, SamplingConfig,
+    StaleSpansConfig, WorkersConfig, WriteBehindSettings,
+};
+
 /// Cloud deployment configuration loaded from environment variables
 #[derive(Debug, Clone)]
 pub struct CloudConfig {
@@ -35,6 +40,56 @@ pub struct CloudConfig {
 
     /// Instance ID (from FLY_ALLOC_ID, RAILWAY_REPLICA_ID, etc.)
     pub instance_id: Option<String>,
+
+    /// Retention pruning (from RETENTION_ENABLED, RETENTION_DAYS,
+    /// RETENTION_AUDIT_DAYS, RETENTION_INTERVAL_SECS, RETENTION_DRY_RUN)
+    pub retention: RetentionConfig,
+
+    /// Failing spans left running (from STALE_SPANS_ENABLED, default true;
+    /// STALE_SPAN_MAX_AGE_SECS, STALE_SPAN_INTERVAL_SECS)
+    pub stale_spans: StaleSpansConfig,
+
+    /// Labeling queue claim expiry (from QUEUE_CLAIM_EXPIRY, default true;
+    /// QUEUE_CLAIM_TTL_SECS, QUEUE_CLAIM_INTERVAL_SECS)
+    pub queue: QueueConfig,
+
+    /// Background job workers (from WORKERS_ENABLED, default true;
+    /// WORKER_CONCURRENCY, WORKER_LOCK_SECS, WORKER_DRAIN_SECS). Jobs are
+    /// shared through Redis when REDIS_URL is set.
+    pub workers: WorkersConfig,
+
+    /// Span and trace sampling, as a JSON object shaped like the `[sampling]`
+    /// config section (from SAMPLING_CONFIG)
+    pub sampling: SamplingConfig,
+
+    /// Per-client API rate limits, shared through Redis when REDIS_URL is
+    /// set (from RATE_LIMIT_ENABLED, default true; RATE_LIMIT_INGEST_PER_MINUTE,
+    /// RATE_LIMIT_INGEST_BURST, RATE_LIMIT_READ_PER_MINUTE, RATE_LIMIT_READ_BURST)
+    pub rate_limit: RateLimitConfig,
+
+    /// Buffered span/trace writes (from WRITE_BEHIND_ENABLED,
+    /// WRITE_BEHIND_CAPACITY, WRITE_BEHIND_MAX_BATCH, WRITE_BEHIND_MAX_DELAY_MS,
+    /// WRITE_BEHIND_MAX_ATTEMPTS)
+    pub write_behind: WriteBehindSettings,
+
+    /// Open stores without loading their data up front (from STORAGE_LAZY_LOAD)
+    pub lazy_load: bool,
+
+    /// Queue span/trace writes on local disk while the backend is failing,
+    /// replaying them when it recovers (from SPILL_DIR, unset to disable;
+    /// SPILL_REPLAY_INTERVAL_MS)
+    pub spill: Option<storage::SpillConfig>,
+
+    /// Large payload offloading (from PAYLOAD_OFFLOAD_BYTES, 0 to disable;
+    /// PAYLOAD_PREVIEW_CHARS)
+    pub payloads: PayloadSettings,
+
+    /// Span name normalization rules, as a JSON array (from SPAN_NAME_RULES)
+    pub span_name_rules: Vec<storage::NameRule>,
+
+    /// Default PII redaction for orgs (from REDACTION_ENABLED, default true;
+    /// REDACTION_DETECTORS, comma-separated; REDACTION_RULES, a JSON array)
+    pub redaction: storage::RedactionConfig,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -99,6 +154,168 @@ impl CloudConfig {
             .or_else(|_| env::var("HOSTNAME"))
             .ok();
 
+        let defaults = RetentionConfig::default();
+        let flag = |name: &str| {
+            env::var(name)
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(false)
+        };
+        let retention = RetentionConfig {
+            enabled: flag("RETENTION_ENABLED"),
+            days: env::var("RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.days),
+            audit_days: env::var("RETENTION_AUDIT_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.audit_days),
+            interval_secs: env::var("RETENTION_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.interval_secs),
+            dry_run: flag("RETENTION_DRY_RUN"),
+        };
+
+        let wb_defaults = WriteBehindSettings::default();
+        let number = |name: &str| env::var(name).ok().and_then(|s| s.parse().ok());
+        let stale_defaults = StaleSpansConfig::default();
+        let stale_spans = StaleSpansConfig {
+            enabled: env::var("STALE_SPANS_ENABLED")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(stale_defaults.enabled),
+            max_age_secs: env::var("STALE_SPAN_MAX_AGE_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(stale_defaults.max_age_secs),
+            interval_secs: env::var("STALE_SPAN_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(stale_defaults.interval_secs),
+        };
+        let queue_defaults = QueueConfig::default();
+        let queue = QueueConfig {
+            claim_expiry: env::var("QUEUE_CLAIM_EXPIRY")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(queue_defaults.claim_expiry),
+            claim_ttl_secs: env::var("QUEUE_CLAIM_TTL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(queue_defaults.claim_ttl_secs),
+            interval_secs: env::var("QUEUE_CLAIM_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(queue_defaults.interval_secs),
+        };
+        let worker_defaults = WorkersConfig::default();
+        let workers = WorkersConfig {
+            enabled: env::var("WORKERS_ENABLED")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(worker_defaults.enabled),
+            concurrency: env::var("WORKER_CONCURRENCY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(worker_defaults.concurrency),
+            lock_secs: env::var("WORKER_LOCK_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(worker_defaults.lock_secs),
+            drain_secs: env::var("WORKER_DRAIN_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(worker_defaults.drain_secs),
+        };
+        let rate_defaults = RateLimitConfig::default();
+        let rate_limit_from_env = |class: &str, default: RateLimit| RateLimit {
+            per_minute: env::var(format!("RATE_LIMIT_{class}_PER_MINUTE"))
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.per_minute),
+            burst: env::var(format!("RATE_LIMIT_{class}_BURST"))
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .or(default.burst),
+        };
+        let rate_limit = RateLimitConfig {
+            enabled: env::var("RATE_LIMIT_ENABLED")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(true),
+            ingest: rate_limit_from_env("INGEST", rate_defaults.ingest),
+            read: rate_limit_from_env("READ", rate_defaults.read),
+        };
+        let write_behind = WriteBehindSettings {
+            enabled: flag("WRITE_BEHIND_ENABLED"),
+            capacity: number("WRITE_BEHIND_CAPACITY").unwrap_or(wb_defaults.capacity),
+            max_batch: number("WRITE_BEHIND_MAX_BATCH").unwrap_or(wb_defaults.max_batch),
+            max_delay_ms: env::var("WRITE_BEHIND_MAX_DELAY_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(wb_defaults.max_delay_ms),
+            max_attempts: env::var("WRITE_BEHIND_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(wb_defaults.max_attempts),
+        };
+        let spill = env::var("SPILL_DIR").ok().map(|dir| {
+            let mut config = storage::SpillConfig::new(dir);
+            if let Some(ms) = env::var("SPILL_REPLAY_INTERVAL_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+            {
+                config.replay_interval = std::time::Duration::from_millis(ms);
+            }
+            config
+        });
+        let payload_defaults = PayloadSettings::default();
+        let payloads = PayloadSettings {
+            offload_bytes: number("PAYLOAD_OFFLOAD_BYTES")
+                .unwrap_or(payload_defaults.offload_bytes),
+            preview_chars: number("PAYLOAD_PREVIEW_CHARS")
+                .unwrap_or(payload_defaults.preview_chars),
+        };
+
+        let sampling = match env::var("SAMPLING_CONFIG") {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!(error = %e, "ignoring invalid SAMPLING_CONFIG");
+                SamplingConfig::default()
+            }),
+            Err(_) => SamplingConfig::default(),
+        };
+
+        let span_name_rules = match env::var("SPAN_NAME_RULES") {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!(error = %e, "ignoring invalid SPAN_NAME_RULES");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        let redaction = storage::RedactionConfig {
+            enabled: env::var("REDACTION_ENABLED")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(true),
+            detectors: match env::var("REDACTION_DETECTORS") {
+                Ok(list) => list
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|d| !d.is_empty())
+                    .filter_map(|d| {
+                        serde_json::from_value(serde_json::Value::String(d.to_string()))
+                            .map_err(|_| warn!(detector = d, "ignoring unknown redaction detector"))
+                            .ok()
+                    })
+                    .collect(),
+                Err(_) => storage::Detector::ALL.to_vec(),
+            },
+            rules: match env::var("REDACTION_RULES") {
+                Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                    warn!(error = %e, "ignoring invalid REDACTION_RULES");
+                    Vec::new()
+                }),
+                Err(_) => Vec::new(),
+            },
+        };
+
         Self {
             port,
             redis_url,
@@ -109,6 +326,18 @@ impl CloudConfig {
             log_format,
             region,
             instance_id,
+            retention,
+            stale_spans,
+            queue,
+            workers,
+            sampling,
+            rate_limit,
+            write_behind,
+            lazy_load: flag("STORAGE_LAZY_LOAD"),
+            spill,
+            payloads,
+            span_name_rules,
+            redaction,
         }
     }
 
@@ -137,6 +366,18 @@ impl CloudConfig {
             metrics = self.metrics_enabled,
             region = ?self.region,
             instance = ?self.instance_id,
+            retention = self.retention.enabled,
+            stale_span_max_age_secs = self.stale_spans.enabled.then_some(self.stale_spans.max_age_secs),
+            queue_claim_ttl_secs = self.queue.claim_expiry.then_some(self.queue.claim_ttl_secs),
+            worker_concurrency = self.workers.enabled.then_some(self.workers.concurrency),
+            sampling_rules = self.sampling.rules.len(),
+            tail_sampling = self.sampling.tail.enabled,
+            rate_limit = self.rate_limit.enabled,
+            write_behind = self.write_behind.enabled,
+            lazy_load = self.lazy_load,
+            spill = self.spill.is_some(),
+            span_name_rules = self.span_name_rules.len(),
+            redaction = self.redaction.enabled,
             "Cloud configuration loaded"
         );
 
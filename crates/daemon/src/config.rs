@@ -1,6 +1,8 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
+use trace::pricing::{ModelPricing, PricingTable};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
@@ -9,6 +11,26 @@ pub struct Config {
     pub proxy: ProxyConfig,
     pub storage: StorageConfig,
     pub logging: LoggingConfig,
+    pub pricing: PricingConfig,
+    pub retention: RetentionConfig,
+    pub archive: ArchiveConfig,
+    pub stale_spans: StaleSpansConfig,
+    pub queue: QueueConfig,
+    pub workers: WorkersConfig,
+    pub sampling: SamplingConfig,
+    pub rate_limit: RateLimitConfig,
+    pub normalization: NormalizationConfig,
+    pub tracking: TrackingConfig,
+    pub llm: LlmConfig,
+    /// PII masking on ingest. Off unless enabled here or per org.
+    ///
+    /// ```toml
+    /// [redaction]
+    /// enabled = true
+    /// detectors = ["email", "phone", "credit_card", "api_key"]
+    /// rules = [{ name = "ssn", pattern = '\b\d{3}-\d{2}-\d{4}\b' }]
+    /// ```
+    pub redaction: storage::RedactionConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,8 +51,21 @@ impl Default for ApiConfig {
 #[serde(default)]
 pub struct ProxyConfig {
     pub addr: String,
+    /// Where requests go when no route matches their model.
     pub target: String,
+    /// `off`, `full`, `preview`, or `preview:<chars>`.
     pub capture_mode: String,
+    pub routes: Vec<ProxyRoute>,
+    pub timeouts: ProxyTimeouts,
+    /// Extra attempts when the upstream connection cannot be opened.
+    pub connect_retries: u32,
+    /// Applied to requests before they are forwarded upstream.
+    pub request_headers: HeaderRules,
+    /// Applied to upstream responses before they reach the client.
+    pub response_headers: HeaderRules,
+    /// Copied from the top-level `[sampling]` section at startup.
+    #[serde(skip)]
+    pub sampling: SamplingConfig,
 }
 
 impl Default for ProxyConfig {
@@ -39,19 +74,251 @@ impl Default for ProxyConfig {
             addr: "127.0.0.1:3001".to_string(),
             target: "http://localhost:11434".to_string(),
             capture_mode: "full".to_string(),
+            routes: Vec::new(),
+            timeouts: ProxyTimeouts::default(),
+            connect_retries: 1,
+            request_headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            sampling: SamplingConfig::default(),
         }
     }
 }
 
+/// Upstream timeouts in milliseconds; 0 disables one.
+///
+/// ```toml
+/// [proxy.timeouts]
+/// connect_ms = 5000
+/// first_byte_ms = 120000
+/// total_ms = 0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyTimeouts {
+    pub connect_ms: u64,
+    /// Until the response headers arrive. For non-streamed completions
+    /// that is the whole generation.
+    pub first_byte_ms: u64,
+    /// The whole exchange, including a streamed body.
+    pub total_ms: u64,
+}
+
+impl Default for ProxyTimeouts {
+    fn default() -> Self {
+        Self {
+            connect_ms: 10_000,
+            first_byte_ms: 300_000,
+            total_ms: 0,
+        }
+    }
+}
+
+/// Header edits, applied after the proxy's own.
+///
+/// ```toml
+/// [proxy.request_headers]
+/// set = { authorization = "Bearer ${OPENAI_API_KEY}" }
+/// remove = ["x-internal-*"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct HeaderRules {
+    /// Set, replacing any existing value. `${VAR}` expands from the
+    /// environment at startup.
+    pub set: BTreeMap<String, String>,
+    /// Names to drop; a trailing `*` matches a prefix.
+    pub remove: Vec<String>,
+}
+
+/// Sends requests for matching models to another backend. Routes are tried
+/// in order.
+///
+/// ```toml
+/// [[proxy.routes]]
+/// models = ["gpt-*", "o1*"]
+/// target = "https://api.openai.com"
+/// api_key_env = "OPENAI_API_KEY"
+///
+/// [[proxy.routes]]
+/// models = ["claude-*"]
+/// target = "https://api.anthropic.com"
+/// api_key_env = "ANTHROPIC_API_KEY"
+/// capture_mode = "preview:200"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ProxyRoute {
+    /// Model name patterns; `*` matches any run of characters.
+    pub models: Vec<String>,
+    pub target: String,
+    /// Detected from the target URL when unset.
+    pub provider: Option<String>,
+    /// Injected upstream in place of the client's credentials.
+    pub api_key: Option<String>,
+    /// Environment variable holding the API key; wins over `api_key`.
+    pub api_key_env: Option<String>,
+    /// Replaces `proxy.capture_mode` for these models.
+    pub capture_mode: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct StorageConfig {
     pub db_path: Option<String>,
+    /// Load data on demand instead of reading the whole database at startup.
+    pub lazy_load: bool,
+    pub write_behind: WriteBehindSettings,
+    pub analytics: AnalyticsStoreSettings,
+    pub payloads: PayloadSettings,
+    /// Mirror every write to a second backend while reads stay on the
+    /// database, for moving to another backend without downtime:
+    /// `sqlite:<path>` or `turbopuffer:<namespace>`. Copy the history
+    /// with `traceway migrate` once this is on.
+    ///
+    /// ```toml
+    /// [storage]
+    /// dual_write = "turbopuffer:tw_local"
+    /// ```
+    pub dual_write: Option<String>,
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
-        Self { db_path: None }
+        Self {
+            db_path: None,
+            lazy_load: false,
+            write_behind: WriteBehindSettings::default(),
+            analytics: AnalyticsStoreSettings::default(),
+            payloads: PayloadSettings::default(),
+            dual_write: None,
+        }
+    }
+}
+
+/// An embedded analytical database mirroring span summaries, which serves
+/// `/api/analytics` and timeseries queries in SQL. It lives next to the
+/// main database unless `path` is set, and is filled from existing spans
+/// when first created. `engine` is `sqlite` or, in builds with the
+/// `duckdb` feature, `duckdb`.
+///
+/// ```toml
+/// [storage.analytics]
+/// enabled = true
+/// engine = "duckdb"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsStoreSettings {
+    pub enabled: bool,
+    pub engine: String,
+    pub path: Option<String>,
+}
+
+impl Default for AnalyticsStoreSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            engine: "sqlite".to_string(),
+            path: None,
+        }
+    }
+}
+
+impl AnalyticsStoreSettings {
+    pub fn path_beside(&self, db_path: &Path) -> PathBuf {
+        let file_name = match self.engine.as_str() {
+            "duckdb" => "analytics.duckdb",
+            _ => "analytics.db",
+        };
+        self.path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| db_path.with_file_name(file_name))
+    }
+}
+
+/// Span payloads larger than `offload_bytes` of JSON are stored as
+/// separate blobs, with the first `preview_chars` kept inline. Fetch the
+/// full body from `/api/spans/:id/payload`. Set `offload_bytes = 0` to keep
+/// every payload inline.
+///
+/// ```toml
+/// [storage.payloads]
+/// offload_bytes = 65536
+/// preview_chars = 1024
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PayloadSettings {
+    pub offload_bytes: usize,
+    pub preview_chars: usize,
+}
+
+impl Default for PayloadSettings {
+    fn default() -> Self {
+        let defaults = storage::PayloadOffloadConfig::default();
+        Self {
+            offload_bytes: defaults.threshold_bytes,
+            preview_chars: defaults.preview_chars,
+        }
+    }
+}
+
+impl PayloadSettings {
+    /// The store config, or `None` when offloading is disabled.
+    pub fn config(&self) -> Option<storage::PayloadOffloadConfig> {
+        (self.offload_bytes > 0).then_some(storage::PayloadOffloadConfig {
+            threshold_bytes: self.offload_bytes,
+            preview_chars: self.preview_chars,
+        })
+    }
+}
+
+/// Buffered span and trace writes.
+///
+/// ```toml
+/// [storage.write_behind]
+/// enabled = true
+/// capacity = 10000
+/// max_batch = 500
+/// max_delay_ms = 50
+/// max_attempts = 8
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WriteBehindSettings {
+    pub enabled: bool,
+    /// Writes that may be queued before ingest waits on storage.
+    pub capacity: usize,
+    pub max_batch: usize,
+    pub max_delay_ms: u64,
+    /// Tries at a failing batch before its writes are dropped. Batches the
+    /// spill queue takes are not dropped.
+    pub max_attempts: u32,
+}
+
+impl Default for WriteBehindSettings {
+    fn default() -> Self {
+        let defaults = storage::WriteBehindConfig::default();
+        Self {
+            enabled: false,
+            capacity: defaults.capacity,
+            max_batch: defaults.max_batch,
+            max_delay_ms: defaults.max_delay.as_millis() as u64,
+            max_attempts: defaults.max_attempts,
+        }
+    }
+}
+
+impl WriteBehindSettings {
+    /// The store config, or `None` when write-behind is disabled.
+    pub fn config(&self) -> Option<storage::WriteBehindConfig> {
+        self.enabled.then(|| storage::WriteBehindConfig {
+            capacity: self.capacity,
+            max_batch: self.max_batch,
+            max_delay: std::time::Duration::from_millis(self.max_delay_ms),
+            max_attempts: self.max_attempts,
+        })
     }
 }
 
@@ -69,6 +336,417 @@ impl Default for LoggingConfig {
     }
 }
 
+/// Automatic pruning of old trace data.
+///
+/// ```toml
+/// [retention]
+/// enabled = true
+/// days = 30
+/// audit_days = 90
+/// interval_secs = 3600
+/// dry_run = false
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Run the background pruning task.
+    pub enabled: bool,
+    /// Retention window, used when an org's plan can't be looked up.
+    pub days: u32,
+    /// Audit log window, used when an org's plan can't be looked up.
+    pub audit_days: u32,
+    pub interval_secs: u64,
+    /// Log what would be pruned without deleting anything.
+    pub dry_run: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            days: 30,
+            audit_days: 90,
+            interval_secs: 3600,
+            dry_run: false,
+        }
+    }
+}
+
+/// Moving old spans out of the local database into compressed segment
+/// files, read back when historical data is queried. Segments go under
+/// `dir` (default `~/.traceway/archive`), or to S3 when `s3` is set, with
+/// credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+///
+/// ```toml
+/// [archive]
+/// enabled = true
+/// days = 7
+/// interval_secs = 3600
+/// s3 = { bucket = "traceway-archive", region = "us-east-1" }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    /// Spans started more than this many days ago are archived.
+    pub days: u32,
+    pub interval_secs: u64,
+    pub dir: Option<String>,
+    pub s3: Option<S3Config>,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            days: 7,
+            interval_secs: 3600,
+            dir: None,
+            s3: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// For S3-compatible stores; defaults to AWS.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Prepended to every segment key.
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// Failing spans left running, e.g. by a client that crashed.
+///
+/// ```toml
+/// [stale_spans]
+/// enabled = true
+/// max_age_secs = 3600
+/// interval_secs = 60
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaleSpansConfig {
+    pub enabled: bool,
+    /// Running spans older than this are failed with "timed out".
+    pub max_age_secs: u64,
+    pub interval_secs: u64,
+}
+
+impl Default for StaleSpansConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_age_secs: 3600,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Returning labeling queue items to the queue when a labeler claims one
+/// and never finishes it.
+///
+/// ```toml
+/// [queue]
+/// claim_expiry = true
+/// claim_ttl_secs = 1800
+/// interval_secs = 60
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub claim_expiry: bool,
+    /// Items claimed longer than this go back to pending.
+    pub claim_ttl_secs: u64,
+    pub interval_secs: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            claim_expiry: true,
+            claim_ttl_secs: 1800,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Background job workers (`embed_spans`, `send_digest`,
+/// `prune_retention`). In-flight jobs get `drain_secs` to finish on
+/// shutdown; jobs still running after that are retried once their lock
+/// lapses.
+///
+/// ```toml
+/// [workers]
+/// enabled = true
+/// concurrency = 4
+/// lock_secs = 30
+/// drain_secs = 8
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkersConfig {
+    pub enabled: bool,
+    /// Jobs processed at once per instance.
+    pub concurrency: usize,
+    /// How long a claimed job stays locked without being renewed.
+    pub lock_secs: u64,
+    /// Kept under the daemon's 10 second shutdown timeout.
+    pub drain_secs: u64,
+}
+
+impl Default for WorkersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            concurrency: 4,
+            lock_secs: 30,
+            drain_secs: 8,
+        }
+    }
+}
+
+/// Dropping part of the span volume before it is stored. Applies to the
+/// proxy, `POST /api/spans/batch`, and OTLP ingest.
+///
+/// ```toml
+/// [sampling]
+/// default_rate = 1.0
+/// rules = [
+///   { kind = "fs_read", rate = 0.1 },
+///   { name = "POST /v1/embeddings", rate = 0.25 },
+/// ]
+///
+/// [sampling.tail]
+/// enabled = true
+/// min_duration_ms = 10000
+/// min_cost = 0.05
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// Share of spans kept when no rule matches, from 0.0 to 1.0.
+    pub default_rate: f64,
+    /// Failed spans, and completed traces with failures, are always kept.
+    pub keep_errors: bool,
+    /// Head-based rates. The first rule that matches a span sets its rate.
+    pub rules: Vec<SampleRule>,
+    pub tail: TailSamplingConfig,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            default_rate: 1.0,
+            keep_errors: true,
+            rules: Vec::new(),
+            tail: TailSamplingConfig::default(),
+        }
+    }
+}
+
+/// Every field that is set must match; `*` in a pattern matches any run of
+/// characters.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SampleRule {
+    /// Span kind, e.g. `llm_call` or `fs_read`.
+    pub kind: Option<String>,
+    /// Span name pattern. Proxy spans are named after the route, e.g.
+    /// `POST /v1/chat/completions`.
+    pub name: Option<String>,
+    /// Model name pattern, for LLM call spans.
+    pub model: Option<String>,
+    pub rate: f64,
+}
+
+/// Deleting completed traces that were fast, cheap, and error-free.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TailSamplingConfig {
+    pub enabled: bool,
+    /// Traces that took at least this long are kept.
+    pub min_duration_ms: Option<u64>,
+    /// Traces that cost at least this much are kept.
+    pub min_cost: Option<f64>,
+    /// How long after a trace completes it is judged, so late spans are
+    /// counted.
+    pub decision_wait_secs: u64,
+    pub interval_secs: u64,
+}
+
+impl Default for TailSamplingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_duration_ms: None,
+            min_cost: None,
+            decision_wait_secs: 30,
+            interval_secs: 30,
+        }
+    }
+}
+
+/// Per-client token buckets on the API. Clients are told by API key,
+/// session, or IP address.
+///
+/// ```toml
+/// [rate_limit]
+/// enabled = true
+/// ingest = { per_minute = 1200, burst = 200 }
+/// read = { per_minute = 600 }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Span and trace writes, including OTLP.
+    pub ingest: RateLimit,
+    /// Everything else.
+    pub read: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ingest: RateLimit {
+                per_minute: 1200,
+                burst: None,
+            },
+            read: RateLimit {
+                per_minute: 600,
+                burst: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sustained rate the bucket refills at.
+    pub per_minute: u32,
+    /// Bucket size; defaults to `per_minute`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn capacity(&self) -> u32 {
+        self.burst.unwrap_or(self.per_minute).max(1)
+    }
+}
+
+/// Span name normalization rules, applied in order on ingest.
+///
+/// ```toml
+/// [[normalization.rules]]
+/// pattern = "[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}"
+/// replace = "{uuid}"
+///
+/// [[normalization.rules]]
+/// template = "fetch_user_{id}"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct NormalizationConfig {
+    pub rules: Vec<storage::NameRule>,
+}
+
+/// Recording changes to files on disk, for setups that can't mount memfs.
+///
+/// ```toml
+/// [tracking]
+/// paths = ["./src", "./prompts"]
+/// debounce_ms = 500
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackingConfig {
+    /// Directories watched recursively. Relative paths are resolved against
+    /// the directory the daemon starts in. Empty turns tracking off.
+    pub paths: Vec<String>,
+    /// How long files must be quiet before their changes are recorded.
+    pub debounce_ms: u64,
+    /// Files larger than this are not recorded.
+    pub max_file_bytes: u64,
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            debounce_ms: 500,
+            max_file_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// The model asked, through the local proxy, for trace summaries and to
+/// turn natural-language searches into queries.
+///
+/// ```toml
+/// [llm]
+/// model = "gpt-4o-mini"
+/// api_key_env = "OPENAI_API_KEY"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LlmConfig {
+    pub model: String,
+    /// Environment variable holding the provider API key, sent to the proxy
+    /// as a bearer token.
+    pub api_key_env: String,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4o-mini".to_string(),
+            api_key_env: "OPENAI_API_KEY".to_string(),
+        }
+    }
+}
+
+/// Model pricing overrides layered over the built-in pricing table.
+///
+/// ```toml
+/// [pricing.models."my-finetune"]
+/// input_per_mtok = 4.0
+/// output_per_mtok = 8.0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PricingConfig {
+    /// Per-model overrides keyed by model name or name prefix.
+    pub models: BTreeMap<String, ModelPricing>,
+}
+
+impl PricingConfig {
+    /// Build a pricing table from the configured overrides.
+    pub fn table(&self) -> PricingTable {
+        let mut table = PricingTable::new();
+        for (model, pricing) in &self.models {
+            table.set_override(model.clone(), *pricing);
+        }
+        table
+    }
+
+    /// Read the `pricing` section out of a JSON-serialized config.
+    /// Missing or malformed sections fall back to built-in pricing only.
+    pub fn table_from_json(config: &serde_json::Value) -> PricingTable {
+        config
+            .get("pricing")
+            .cloned()
+            .and_then(|v| serde_json::from_value::<PricingConfig>(v).ok())
+            .unwrap_or_default()
+            .table()
+    }
+}
+
 impl Config {
     /// Load config from `~/.traceway/config.toml`, returning defaults if file is missing.
     pub fn load() -> Self {
@@ -117,6 +795,19 @@ impl Config {
         Self::data_dir().join("logs")
     }
 
+    pub fn archive_dir(&self) -> PathBuf {
+        self.archive
+            .dir
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Self::data_dir().join("archive"))
+    }
+
+    /// This daemon's machine id, generated on first start.
+    pub fn machine_id_path() -> PathBuf {
+        Self::data_dir().join("machine_id")
+    }
+
     pub fn pid_path() -> PathBuf {
         Self::data_dir().join("daemon.pid")
     }
@@ -13,7 +13,7 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::sync::{watch, RwLock};
+use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
 use crate::api::AnyBackend;
@@ -69,7 +69,7 @@ fn pick<'a>(items: &'a [&str], seed: &mut u64) -> &'a str {
 
 /// Run the synthetic ingest loop until shutdown is signalled.
 pub async fn run_synthetic_ingest(
-    store: Arc<RwLock<PersistentStore<AnyBackend>>>,
+    store: Arc<PersistentStore<AnyBackend>>,
     interval: Duration,
     mut shutdown_rx: watch::Receiver<bool>,
 ) {
@@ -106,7 +106,7 @@ pub async fn run_synthetic_ingest(
 }
 
 async fn generate_trace(
-    store: &Arc<RwLock<PersistentStore<AnyBackend>>>,
+    store: &Arc<PersistentStore<AnyBackend>>,
     seed: &mut u64,
     trace_name: &str,
 ) -> Result<(), String> {
@@ -115,10 +115,7 @@ async fn generate_trace(
         .with_tags(vec!["synthetic".to_string(), "dev".to_string()]);
     let trace_id = trace.id;
 
-    {
-        let mut s = store.write().await;
-        s.save_trace(trace).await;
-    }
+    store.save_trace(trace).await.map_err(|e| e.to_string())?;
 
     debug!(%trace_id, name = trace_name, "created synthetic trace");
 
@@ -182,7 +179,7 @@ async fn generate_trace(
             // File write (15%)
             let path = pick(FILE_PATHS, seed);
             let bytes = 128 + (cheap_random(seed) % 32768);
-            let hash = format!("{:016x}", cheap_random(seed));
+            let hash = trace::content_hash(&cheap_random(seed).to_le_bytes());
             (
                 format!("write-{}", path.rsplit('/').next().unwrap_or("file")),
                 SpanKind::FsWrite {
@@ -216,10 +213,7 @@ async fn generate_trace(
         let span = builder.build();
         let span_id = span.id();
 
-        {
-            let mut s = store.write().await;
-            s.insert(span).await;
-        }
+        store.insert(span).await.map_err(|e| e.to_string())?;
 
         debug!(%trace_id, %span_id, span_name = name, "inserted synthetic span");
 
@@ -233,44 +227,42 @@ async fn generate_trace(
 
         // Complete or fail the span
         let fail_roll = cheap_random(seed) % 100;
-        {
-            let mut s = store.write().await;
-            if fail_roll < 10 {
-                // 10% failure rate
-                s.fail_span(span_id, "synthetic error: something went wrong")
-                    .await;
-                debug!(%span_id, "failed synthetic span");
-            } else {
-                s.complete_span(
+        if fail_roll < 10 {
+            // 10% failure rate
+            store
+                .fail_span(span_id, "synthetic error: something went wrong")
+                .await
+                .map_err(|e| e.to_string())?;
+            debug!(%span_id, "failed synthetic span");
+        } else {
+            store
+                .complete_span(
                     span_id,
                     Some(serde_json::json!({"synthetic": true, "result": "ok"})),
                 )
-                .await;
-                debug!(%span_id, "completed synthetic span");
-            }
+                .await
+                .map_err(|e| e.to_string())?;
+            debug!(%span_id, "completed synthetic span");
         }
     }
 
     // Complete the trace
-    {
-        let mut s = store.write().await;
-        if let Some(trace) = s.get_trace(trace_id).cloned() {
-            s.save_trace(trace.complete()).await;
-        }
+    if let Some(trace) = store.get_trace_or_load(trace_id).await {
+        store
+            .save_trace(trace.complete())
+            .await
+            .map_err(|e| e.to_string())?;
     }
 
     // Log summary
-    {
-        let s = store.read().await;
-        info!(
-            %trace_id,
-            name = trace_name,
-            spans = span_count,
-            total_spans = s.span_count(),
-            total_traces = s.trace_count(),
-            "synthetic trace complete"
-        );
-    }
+    info!(
+        %trace_id,
+        name = trace_name,
+        spans = span_count,
+        total_spans = store.span_count(),
+        total_traces = store.trace_count(),
+        "synthetic trace complete"
+    );
 
     Ok(())
 }
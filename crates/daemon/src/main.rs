@@ -1,8 +1,13 @@
+mod admin;
 mod api;
+mod backup;
 mod config;
 mod ingest;
+mod migrate;
 mod pid;
 mod proxy;
+mod tail;
+mod tracking;
 
 #[cfg(feature = "cloud")]
 mod cloud;
@@ -13,12 +18,15 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use clap::Parser;
-use tokio::sync::{watch, RwLock};
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{error, info, warn};
 
 use crate::api::AnyBackend;
-use storage::PersistentStore;
-use storage_sqlite::SqliteBackend;
+use storage::{
+    AnalyticalStore, NameNormalizer, NameRule, PersistentStore, RedactionConfig, Redactor,
+    StorageError,
+};
+use storage_sqlite::{SqliteAnalytics, SqliteBackend};
 
 use crate::config::Config;
 use crate::pid::PidFile;
@@ -72,6 +80,55 @@ struct Args {
     /// Run in cloud mode (load config from environment)
     #[arg(long)]
     cloud: bool,
+
+    /// Enforce a cloud plan's quota, retention, and feature limits locally,
+    /// with in-memory usage counters
+    #[arg(long, value_enum, conflicts_with = "cloud")]
+    simulate_plan: Option<SimulatedPlan>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Print spans from a running daemon as they complete
+    Tail(tail::TailArgs),
+    /// Print the API's OpenAPI spec as JSON
+    Openapi,
+    /// Storage maintenance
+    #[command(subcommand)]
+    Admin(admin::AdminCommand),
+    /// Copy every entity from one storage backend into another
+    Migrate(migrate::MigrateArgs),
+    /// Write the local database to a compressed archive
+    Backup(backup::BackupArgs),
+    /// Recreate the local database from backup archives
+    Restore(backup::RestoreArgs),
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum SimulatedPlan {
+    Free,
+    Pro,
+}
+
+impl SimulatedPlan {
+    fn as_arg(self) -> &'static str {
+        match self {
+            SimulatedPlan::Free => "free",
+            SimulatedPlan::Pro => "pro",
+        }
+    }
+}
+
+impl From<SimulatedPlan> for auth::Plan {
+    fn from(plan: SimulatedPlan) -> Self {
+        match plan {
+            SimulatedPlan::Free => auth::Plan::Free,
+            SimulatedPlan::Pro => auth::Plan::Pro,
+        }
+    }
 }
 
 /// Resolved configuration merging CLI args over config file over defaults.
@@ -175,6 +232,45 @@ fn check_port_available(addr: &str) -> Result<(), String> {
     }
 }
 
+/// Compile span name rules, exiting on an invalid pattern.
+fn name_normalizer(rules: &[NameRule]) -> Option<Arc<NameNormalizer>> {
+    match NameNormalizer::new(rules) {
+        Ok(n) if n.is_empty() => None,
+        Ok(n) => Some(Arc::new(n)),
+        Err(e) => {
+            error!("invalid span name rule: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exit if the configured redaction settings don't compile.
+fn check_redaction(config: &RedactionConfig) {
+    if let Err(e) = Redactor::new(config) {
+        error!("invalid redaction rule: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Open the analytical store for `engine`, as set in `storage.analytics`.
+fn open_analytical(
+    engine: &str,
+    path: &std::path::Path,
+) -> Result<Arc<dyn AnalyticalStore>, StorageError> {
+    match engine {
+        "sqlite" => Ok(Arc::new(SqliteAnalytics::open(path)?)),
+        #[cfg(feature = "duckdb")]
+        "duckdb" => Ok(Arc::new(storage_sqlite::DuckDbAnalytics::open(path)?)),
+        #[cfg(not(feature = "duckdb"))]
+        "duckdb" => Err(StorageError::Configuration(
+            "this build lacks the `duckdb` feature".to_string(),
+        )),
+        other => Err(StorageError::Configuration(format!(
+            "unknown analytics engine `{other}`"
+        ))),
+    }
+}
+
 /// Create shutdown signal listener (SIGINT + SIGTERM).
 async fn shutdown_signal(mut shutdown_rx: watch::Receiver<bool>) {
     shutdown_rx.changed().await.ok();
@@ -182,30 +278,22 @@ async fn shutdown_signal(mut shutdown_rx: watch::Receiver<bool>) {
 
 /// Run the API server with supervision (restart on crash).
 async fn run_api_supervised(
-    org_stores: Arc<api::OrgStoreManager>,
+    builder: api::RouterBuilder,
     addr: String,
-    start_time: Instant,
-    config_json: serde_json::Value,
-    config_path: String,
-    shutdown_tx: watch::Sender<bool>,
     shutdown_rx: watch::Receiver<bool>,
 ) {
     let mut restarts = 0u32;
     let mut backoff = Duration::from_secs(1);
 
     loop {
-        let api_stores = org_stores.clone();
+        let api_builder = builder.clone();
         let api_addr = addr.clone();
-        let api_start_time = start_time;
-        let api_config = config_json.clone();
-        let api_config_path = config_path.clone();
-        let api_shutdown_tx = shutdown_tx.clone();
         let rx = shutdown_rx.clone();
 
         info!("starting api server on {}", api_addr);
 
         let result = tokio::spawn(async move {
-            api::serve_with_shutdown(api_stores, &api_addr, api_start_time, api_config, api_config_path, Some(api_shutdown_tx), shutdown_signal(rx)).await
+            api::serve_with_shutdown(api_builder, &api_addr, shutdown_signal(rx)).await
         })
         .await;
 
@@ -246,9 +334,12 @@ async fn run_api_supervised(
 
 /// Run the proxy server with supervision (restart on crash).
 async fn run_proxy_supervised(
-    store: Arc<RwLock<PersistentStore<AnyBackend>>>,
+    store: Arc<PersistentStore<AnyBackend>>,
     addr: String,
-    target_url: String,
+    proxy_config: config::ProxyConfig,
+    capture_mode: proxy::SharedCaptureMode,
+    pricing: Arc<RwLock<trace::pricing::PricingTable>>,
+    links: proxy::ApiLinks,
     shutdown_rx: watch::Receiver<bool>,
 ) {
     let mut restarts = 0u32;
@@ -257,13 +348,27 @@ async fn run_proxy_supervised(
     loop {
         let proxy_store = store.clone();
         let proxy_addr = addr.clone();
-        let proxy_target = target_url.clone();
+        let proxy_config = proxy_config.clone();
+        let proxy_capture = capture_mode.clone();
+        let proxy_pricing = pricing.clone();
+        let proxy_links = links.clone();
         let rx = shutdown_rx.clone();
 
-        info!("starting proxy server on {} -> {}", proxy_addr, proxy_target);
+        info!(
+            "starting proxy server on {} -> {}",
+            proxy_addr, proxy_config.target
+        );
 
         let result = tokio::spawn(async move {
-            proxy::serve_with_shutdown(proxy_store, &proxy_addr, &proxy_target, shutdown_signal(rx))
+            proxy::serve_with_shutdown(
+                proxy_store,
+                &proxy_addr,
+                &proxy_config,
+                proxy_capture,
+                proxy_pricing,
+                proxy_links,
+                shutdown_signal(rx),
+            )
                 .await
         })
         .await;
@@ -339,6 +444,9 @@ fn daemonize(args: &Args) -> ! {
         cmd.arg("--dev-ingest-interval")
             .arg(args.dev_ingest_interval.to_string());
     }
+    if let Some(plan) = args.simulate_plan {
+        cmd.arg("--simulate-plan").arg(plan.as_arg());
+    }
 
     // Redirect stdio to /dev/null for the background process
     use std::process::Stdio;
@@ -365,7 +473,7 @@ async fn main() {
     // Load .env file if present (silently ignored if missing)
     dotenvy::dotenv().ok();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
 
     // Cloud mode: load all config from environment
     #[cfg(feature = "cloud")]
@@ -382,6 +490,50 @@ async fn main() {
 
     let resolved = ResolvedConfig::from_args_and_config(&args, &config);
 
+    match args.command.take() {
+        Some(Command::Tail(tail_args)) => {
+            if let Err(e) = tail::run(tail_args, &resolved.api_addr).await {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Openapi) => {
+            let spec = <api::openapi::ApiDoc as utoipa::OpenApi>::openapi();
+            println!("{}", spec.to_pretty_json().expect("spec serializes"));
+            return;
+        }
+        Some(Command::Admin(command)) => {
+            if let Err(e) = admin::run(command).await {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Migrate(migrate_args)) => {
+            if let Err(e) = migrate::run(migrate_args).await {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Backup(backup_args)) => {
+            if let Err(e) = backup::backup(backup_args, &resolved.db_path) {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Restore(restore_args)) => {
+            if let Err(e) = backup::restore(restore_args, &resolved.db_path) {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
     // --- Daemonize (re-exec with --foreground in background) ---
     if !resolved.foreground {
         daemonize(&args);
@@ -418,21 +570,89 @@ async fn main() {
     if let Some(parent) = resolved.db_path.parent() {
         std::fs::create_dir_all(parent).ok();
     }
-    let backend = match SqliteBackend::open(&resolved.db_path) {
+    let mut backend = match SqliteBackend::open(&resolved.db_path) {
         Ok(b) => AnyBackend::Sqlite(b),
         Err(e) => {
             error!("failed to open database: {}", e);
             std::process::exit(1);
         }
     };
-    let persistent = match PersistentStore::open(backend).await {
+    if let Some(target) = &config.storage.dual_write {
+        let mirror = match target.parse::<api::any_backend::BackendSpec>() {
+            Ok(spec) => spec.open().await,
+            Err(e) => Err(e),
+        };
+        match mirror {
+            Ok(mirror) => {
+                info!(%target, "dual write enabled");
+                backend = backend.dual_write(mirror);
+            }
+            Err(e) => {
+                error!("failed to open dual-write backend: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let opened = if config.storage.lazy_load {
+        PersistentStore::open_lazy(backend).await
+    } else {
+        PersistentStore::open(backend).await
+    };
+    let mut persistent = match opened {
         Ok(p) => p,
         Err(e) => {
             error!("failed to load data: {}", e);
             std::process::exit(1);
         }
     };
-    let store = Arc::new(RwLock::new(persistent));
+    if let Some(wb) = config.storage.write_behind.config() {
+        info!(capacity = wb.capacity, max_batch = wb.max_batch, "write-behind enabled");
+        persistent = persistent.with_write_behind(wb);
+    }
+    if let Some(names) = name_normalizer(&config.normalization.rules) {
+        persistent = persistent.with_name_normalizer(names);
+    }
+    if let Some(payloads) = config.storage.payloads.config() {
+        persistent = persistent.with_payload_offload(payloads);
+    }
+    // Attached even when archiving is off, so earlier segments stay readable
+    match api::archive::segment_store(&config.archive, config.archive_dir()) {
+        Ok(segments) => persistent = persistent.with_archive(segments),
+        Err(e) => {
+            error!("invalid archive config: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let mut backfill_analytics = false;
+    if config.storage.analytics.enabled {
+        let path = config.storage.analytics.path_beside(&resolved.db_path);
+        backfill_analytics = !path.exists();
+        match open_analytical(&config.storage.analytics.engine, &path) {
+            Ok(analytical) => {
+                info!(
+                    path = %path.display(),
+                    engine = analytical.engine(),
+                    "analytical store enabled"
+                );
+                persistent = persistent.with_analytical(analytical);
+            }
+            Err(e) => {
+                error!("failed to open analytical store: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let machine = api::machines::RegisterMachine::local(&Config::machine_id_path());
+    let store = Arc::new(persistent.with_machine_id(machine.id.clone()));
+    if backfill_analytics {
+        let store = store.clone();
+        tokio::spawn(async move {
+            match store.rebuild_analytical().await {
+                Ok(count) => info!(count, "analytical store filled from existing spans"),
+                Err(e) => error!("failed to fill analytical store: {}", e),
+            }
+        });
+    }
     info!("storage ready");
 
     // 2. Shutdown signal channel
@@ -446,16 +666,82 @@ async fn main() {
         .unwrap_or_else(|| Config::default_path().to_string_lossy().to_string());
 
     // 3. Wrap in OrgStoreManager (local mode = single store for all orgs)
-    let org_stores = Arc::new(api::OrgStoreManager::single(store.clone()));
+    check_redaction(&config.redaction);
+    let org_stores = Arc::new(
+        api::OrgStoreManager::single(store.clone()).with_redaction(config.redaction.clone()),
+    );
+    org_stores.load_saved_redaction().await;
+
+    // Event bus shared by the API and proxy so streamed proxy output reaches
+    // API subscribers
+    let (events_tx, _) = broadcast::channel(256);
+
+    let plan = args.simulate_plan.map(auth::Plan::from);
+    let mut retention = api::retention::RetentionPolicy::new(config.retention.clone());
+    if let Some(plan) = plan {
+        warn!(
+            plan = ?plan,
+            retention_days = plan.retention_days(),
+            "simulating plan limits; data older than the plan's retention will be pruned"
+        );
+        retention = retention.with_plan(plan);
+    }
+    let retention = Arc::new(retention);
+    // Shared so the retention task queues its runs for the API's workers
+    let job_queue: Arc<dyn api::jobs::JobQueue> = Arc::new(api::jobs::MemoryJobQueue::new());
+    let retention_handle = (config.retention.enabled || plan.is_some()).then(|| {
+        api::retention::spawn_retention_task(
+            org_stores.clone(),
+            retention.clone(),
+            config.workers.enabled.then(|| job_queue.clone()),
+            shutdown_rx.clone(),
+        )
+    });
+    let heartbeat_handle =
+        api::machines::spawn_heartbeat(store.clone(), machine, shutdown_rx.clone());
+
+    // Shared so the API can change it without restarting the proxy
+    let capture_mode =
+        proxy::SharedCaptureMode::new(proxy::CaptureMode::from_config(&config.proxy.capture_mode));
+
+    // Spend is shared so the proxy enforces budgets set through the API
+    let budgets = api::budgets::BudgetTracker::new(org_stores.clone());
+
+    // Pricing is shared so `PUT /api/config` reprices proxied calls too
+    let pricing = Arc::new(RwLock::new(config.pricing.table()));
 
     // 4. API server (supervised)
+    let api_builder = api::RouterBuilder::with_org_stores(org_stores)
+        .start_time(start_time)
+        .config(config_json)
+        .config_path(config_path_str)
+        .shutdown_tx(shutdown_tx.clone())
+        .events_tx(events_tx.clone())
+        .retention(retention)
+        .stale_spans(config.stale_spans.clone())
+        .archive(config.archive.clone())
+        .queue(config.queue.clone())
+        .workers(config.workers.clone())
+        .sampling(config.sampling.clone())
+        .llm(config.llm.clone())
+        .proxy_url(format!("http://{}", resolved.proxy_addr))
+        .proxy_capture(capture_mode.clone())
+        .job_queue(job_queue)
+        .pricing(pricing.clone())
+        .budgets(budgets.clone());
+    let api_builder = match plan {
+        Some(plan) => api_builder.plan_sim(Arc::new(api::plan_sim::PlanSimulator::new(plan))),
+        None => api_builder,
+    };
+    let api_builder = if config.rate_limit.enabled {
+        let limiter = api::rate_limit::RateLimiter::memory(config.rate_limit.clone());
+        api_builder.rate_limiter(Arc::new(limiter))
+    } else {
+        api_builder
+    };
     let api_handle = tokio::spawn(run_api_supervised(
-        org_stores,
+        api_builder,
         resolved.api_addr.clone(),
-        start_time,
-        config_json,
-        config_path_str,
-        shutdown_tx.clone(),
         shutdown_rx.clone(),
     ));
 
@@ -466,7 +752,17 @@ async fn main() {
     let proxy_handle = tokio::spawn(run_proxy_supervised(
         store.clone(),
         resolved.proxy_addr.clone(),
-        resolved.target_url.clone(),
+        config::ProxyConfig {
+            target: resolved.target_url.clone(),
+            sampling: config.sampling.clone(),
+            ..config.proxy.clone()
+        },
+        capture_mode,
+        pricing,
+        proxy::ApiLinks {
+            events_tx: Some(events_tx),
+            budgets: Some(budgets),
+        },
         shutdown_rx.clone(),
     ));
 
@@ -486,6 +782,9 @@ async fn main() {
         None
     };
 
+    // 6. File tracking (optional)
+    let tracking_handle = tracking::spawn(store.clone(), &config.tracking, shutdown_rx.clone());
+
     info!(
         "daemon ready — api http://{} | proxy http://{} -> {}",
         resolved.api_addr, resolved.proxy_addr, resolved.target_url
@@ -526,6 +825,16 @@ async fn main() {
             if let Some(h) = ingest_handle {
                 let _ = h.await;
             }
+            if let Some(h) = tracking_handle {
+                let _ = h.await;
+            }
+            if let Some(h) = retention_handle {
+                let _ = h.await;
+            }
+            let _ = heartbeat_handle.await;
+            if let Err(e) = store.flush().await {
+                error!("failed to flush buffered writes: {}", e);
+            }
         },
     )
     .await;
@@ -558,6 +867,8 @@ async fn run_cloud_mode() {
     let auth_config = api::auth_keys::auth_config_from_env();
 
     // ── Trace storage ───────────────────────────────────────────────
+    let names = name_normalizer(&cloud_config.span_name_rules);
+    check_redaction(&cloud_config.redaction);
     let org_stores: Arc<api::OrgStoreManager> = match cloud_config.storage_backend {
         cloud::StorageBackendType::Sqlite => {
             let db_path = std::env::var("DB_PATH")
@@ -578,15 +889,41 @@ async fn run_cloud_mode() {
                 }
             };
 
-            let store = match PersistentStore::open(backend).await {
-                Ok(p) => Arc::new(RwLock::new(p)),
+            let opened = if cloud_config.lazy_load {
+                PersistentStore::open_lazy(backend).await
+            } else {
+                PersistentStore::open(backend).await
+            };
+            let mut store = match opened {
+                Ok(p) => p,
                 Err(e) => {
                     error!("Failed to load data: {}", e);
                     std::process::exit(1);
                 }
             };
+            if let Some(spill) = &cloud_config.spill {
+                store = match store.with_spill(spill) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("Failed to open spill queue: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            if let Some(wb) = cloud_config.write_behind.config() {
+                store = store.with_write_behind(wb);
+            }
+            if let Some(names) = names {
+                store = store.with_name_normalizer(names);
+            }
+            if let Some(payloads) = cloud_config.payloads.config() {
+                store = store.with_payload_offload(payloads);
+            }
+            let store = Arc::new(store);
 
-            Arc::new(api::OrgStoreManager::single(store))
+            Arc::new(
+                api::OrgStoreManager::single(store).with_redaction(cloud_config.redaction.clone()),
+            )
         }
         cloud::StorageBackendType::Turbopuffer => {
             info!("Using Turbopuffer storage (per-org namespaces)");
@@ -599,9 +936,18 @@ async fn run_cloud_mode() {
                 }
             };
 
-            Arc::new(api::OrgStoreManager::per_org(tp_config))
+            Arc::new(
+                api::OrgStoreManager::per_org(tp_config)
+                    .with_write_behind(cloud_config.write_behind.config())
+                    .with_spill(cloud_config.spill.clone())
+                    .with_lazy_load(cloud_config.lazy_load)
+                    .with_name_normalizer(names)
+                    .with_payload_offload(cloud_config.payloads.config())
+                    .with_redaction(cloud_config.redaction.clone()),
+            )
         }
     };
+    org_stores.load_saved_redaction().await;
 
     info!("Storage ready");
 
@@ -615,6 +961,76 @@ async fn run_cloud_mode() {
         "region": cloud_config.region,
     });
 
+    // Org plans (and so per-org retention windows) and memberships live in
+    // Postgres when it's configured; otherwise every org gets RETENTION_DAYS
+    // and sessions aren't checked against memberships.
+    let auth_store: Option<Arc<dyn auth::AuthStore>> = if std::env::var("DATABASE_URL").is_ok() {
+        match storage_postgres::PostgresAuthStore::from_env().await {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                warn!("Auth store: can't reach Postgres, using RETENTION_DAYS and no org switching: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut retention = api::retention::RetentionPolicy::new(cloud_config.retention.clone());
+    if let Some(store) = &auth_store {
+        retention = retention.with_auth_store(store.clone());
+    }
+    let retention = Arc::new(retention);
+
+    let rate_limiter = if cloud_config.rate_limit.enabled {
+        let config = cloud_config.rate_limit.clone();
+        let limiter = match &cloud_config.redis_url {
+            Some(url) => match api::rate_limit::cloud::RedisRateLimitStore::new(url).await {
+                Ok(store) => api::rate_limit::RateLimiter::new(config, Arc::new(store)),
+                Err(e) => {
+                    warn!("Rate limiting: can't reach Redis, limiting per instance: {e}");
+                    api::rate_limit::RateLimiter::memory(config)
+                }
+            },
+            None => api::rate_limit::RateLimiter::memory(config),
+        };
+        Some(Arc::new(limiter))
+    } else {
+        None
+    };
+
+    let event_bus = match &cloud_config.redis_url {
+        Some(url) => match api::events::RedisEventBus::new(url).await {
+            Ok(bus) => Some(Arc::new(bus) as Arc<dyn api::events::EventBus>),
+            Err(e) => {
+                warn!("Event bus: can't reach Redis, events stay on this instance: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let job_queue = match &cloud_config.redis_url {
+        Some(url) => match api::jobs::cloud::RedisJobQueue::new(url, api::jobs::DEFAULT_QUEUE).await {
+            Ok(queue) => Some(Arc::new(queue) as Arc<dyn api::jobs::JobQueue>),
+            Err(e) => {
+                warn!("Jobs: can't reach Redis, jobs stay on this instance: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    let job_queue: Arc<dyn api::jobs::JobQueue> =
+        job_queue.unwrap_or_else(|| Arc::new(api::jobs::MemoryJobQueue::new()));
+
+    if cloud_config.retention.enabled {
+        api::retention::spawn_retention_task(
+            org_stores.clone(),
+            retention.clone(),
+            cloud_config.workers.enabled.then(|| job_queue.clone()),
+            shutdown_rx.clone(),
+        );
+    }
+
     let addr = cloud_config.bind_addr();
     info!(addr = %addr, "Starting API server");
 
@@ -630,17 +1046,42 @@ async fn run_cloud_mode() {
             .config(config_json)
             .config_path(String::new())
             .shutdown_tx(shutdown_tx_clone)
-            .auth_config(auth_config);
-
-        let app = builder.build();
+            .auth_config(auth_config)
+            .retention(retention)
+            .stale_spans(cloud_config.stale_spans.clone())
+            .queue(cloud_config.queue.clone())
+            .workers(cloud_config.workers.clone())
+            .sampling(cloud_config.sampling.clone());
+        let builder = match rate_limiter {
+            Some(limiter) => builder.rate_limiter(limiter),
+            None => builder,
+        };
+        let builder = match event_bus {
+            Some(bus) => builder.event_bus(bus),
+            None => builder,
+        };
+        let builder = match auth_store {
+            Some(store) => builder.auth_store(store),
+            None => builder,
+        };
+        let builder = builder.job_queue(job_queue);
+
+        let (app, workers) = builder.build_with_workers();
 
         async move {
             let listener = tokio::net::TcpListener::bind(&addr).await?;
             tracing::info!("api listening on {}", addr);
-            axum::serve(listener, app)
-                .with_graceful_shutdown(shutdown_signal(shutdown_rx))
-                .await
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            let served = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal(shutdown_rx))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+            if let Some(workers) = workers {
+                workers.shutdown().await;
+            }
+            served
         }
     });
 
@@ -672,6 +1113,7 @@ async fn run_cloud_mode() {
 
     let shutdown_result = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
         let _ = api_handle.await;
+        org_stores.flush_all().await;
     }).await;
 
     match shutdown_result {
@@ -0,0 +1,494 @@
+//! `traceway migrate`: copy everything in one storage backend into another,
+//! e.g. from the local SQLite database to Turbopuffer, then check that both
+//! hold the same data.
+//!
+//! Spans are streamed in pages; the other entities are small enough to
+//! copy whole. Run the daemon with `storage.dual_write` pointing at the
+//! destination while migrating so that nothing written meanwhile is lost.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use storage::error::StorageError;
+use storage::filter::{self, AnnotationFilter, AuditFilter, SpanFilter, TraceFilter, TrashScope};
+use storage::StorageBackend;
+use trace::Span;
+
+use crate::api::any_backend::BackendSpec;
+use crate::api::AnyBackend;
+
+/// Stands in for "no limit" in per-kind list calls; SQLite limits are
+/// signed, so `usize::MAX` won't do.
+const ALL_ROWS: usize = u32::MAX as usize;
+
+/// Settings written by the daemon, beyond the per-run playground keys
+/// found through `playground_runs` and the per-trace keys of
+/// `storage::TRACE_SETTINGS`.
+const SETTING_KEYS: &[&str] = &[
+    crate::api::reports::REPORTS_SETTING,
+    crate::api::slack::SLACK_SETTING,
+    crate::api::budgets::BUDGETS_SETTING,
+    crate::api::curation::CURATION_SETTING,
+    crate::api::billing::USAGE_SETTING,
+    crate::api::playground::RUNS_SETTING,
+    crate::api::redaction::REDACTION_SETTING,
+    crate::api::anomalies::ANOMALIES_SETTING,
+    storage::archive::SEGMENTS_SETTING,
+];
+
+#[derive(clap::Args, Debug)]
+pub struct MigrateArgs {
+    /// Backend to copy from: `sqlite:<path>` or `turbopuffer:<namespace>`
+    #[arg(long)]
+    from: BackendSpec,
+
+    /// Backend to copy into, in the same form as `--from`
+    #[arg(long)]
+    to: BackendSpec,
+
+    /// Spans read and written at a time
+    #[arg(long, default_value_t = 500)]
+    batch_size: usize,
+
+    /// Only compare the two backends, copying nothing
+    #[arg(long, conflicts_with = "no_verify")]
+    verify: bool,
+
+    /// Skip comparing the backends after copying
+    #[arg(long)]
+    no_verify: bool,
+}
+
+pub async fn run(args: MigrateArgs) -> Result<(), String> {
+    let from = args.from.open().await?;
+    let to = args.to.open().await?;
+    let batch_size = args.batch_size.max(1);
+
+    if !args.verify {
+        println!("copying {} to {}", args.from, args.to);
+        copy(&from, &to, batch_size)
+            .await
+            .map_err(|e| format!("copy failed: {e}"))?;
+    }
+    if args.no_verify {
+        return Ok(());
+    }
+
+    println!("verifying");
+    let source = checksums(&from, batch_size)
+        .await
+        .map_err(|e| format!("{}: {e}", args.from))?;
+    let dest = checksums(&to, batch_size)
+        .await
+        .map_err(|e| format!("{}: {e}", args.to))?;
+    let mismatched = compare(&source, &dest);
+    if mismatched.is_empty() {
+        println!("{} and {} match", args.from, args.to);
+        Ok(())
+    } else {
+        Err(format!("{} differ: {}", args.to, mismatched.join(", ")))
+    }
+}
+
+/// Save `$items` (or everything `$load` returns from `$from`) into `$to`
+/// one at a time, reporting how many were copied.
+macro_rules! copy_each {
+    ($from:ident.$load:ident => $to:ident.$save:ident, $kind:literal) => {
+        copy_each!($to, $kind, $from.$load().await?, $save)
+    };
+    ($to:ident, $kind:literal, $items:expr, $save:ident) => {{
+        let items = $items;
+        for item in &items {
+            $to.$save(item).await?;
+        }
+        println!("  {}: {}", $kind, items.len());
+    }};
+}
+
+/// Copy every entity in `from` into `to`. Rows already in `to` are
+/// overwritten, so an interrupted copy can simply be run again.
+pub async fn copy(
+    from: &AnyBackend,
+    to: &AnyBackend,
+    batch_size: usize,
+) -> Result<(), StorageError> {
+    copy_each!(to, "traces", all_traces(from).await?, save_trace);
+
+    let mut pages = SpanPages::new(from, batch_size);
+    let mut copied = 0;
+    while let Some(spans) = pages.next().await? {
+        to.save_spans_batch(&spans).await?;
+        copied += spans.len();
+        print!("\r  spans: {copied}");
+        let _ = std::io::stdout().flush();
+    }
+    println!("\r  spans: {copied}");
+
+    copy_each!(from.load_all_datasets => to.save_dataset, "datasets");
+    let datapoints = from.load_all_datapoints().await?;
+    for chunk in datapoints.chunks(batch_size) {
+        to.save_datapoints_batch(chunk).await?;
+    }
+    println!("  datapoints: {}", datapoints.len());
+
+    let queue_items = from.load_all_queue_items().await?;
+    let mut submissions = Vec::new();
+    for item in &queue_items {
+        submissions.extend(from.list_queue_submissions(item.id).await?);
+    }
+    copy_each!(to, "queue items", queue_items, save_queue_item);
+    copy_each!(to, "queue submissions", submissions, save_queue_submission);
+
+    copy_each!(from.load_all_eval_runs => to.save_eval_run, "eval runs");
+    copy_each!(from.load_all_eval_results => to.save_eval_result, "eval results");
+    copy_each!(from.load_all_capture_rules => to.save_capture_rule, "capture rules");
+    copy_each!(
+        from.load_all_provider_connections => to.save_provider_connection,
+        "provider connections"
+    );
+    copy_each!(from.load_all_span_kinds => to.save_span_kind, "span kinds");
+    copy_each!(from.list_machines => to.save_machine, "machines");
+
+    let webhooks = from.list_webhooks().await?;
+    let mut deliveries = Vec::new();
+    for webhook in &webhooks {
+        deliveries.extend(from.list_webhook_deliveries(webhook.id, ALL_ROWS).await?);
+    }
+    copy_each!(to, "webhooks", webhooks, save_webhook);
+    copy_each!(to, "webhook deliveries", deliveries, save_webhook_delivery);
+
+    copy_each!(from.list_views => to.save_view, "views");
+    let annotations = all_annotations(from).await?;
+    copy_each!(to, "annotations", annotations, save_annotation);
+    let audit_events = all_audit_events(from).await?;
+    copy_each!(to, "audit events", audit_events, save_audit_event);
+    copy_each!(from.list_file_versions => to.save_file_version, "file versions");
+
+    let contents = from.list_file_contents().await?;
+    for (hash, _) in &contents {
+        let content = from.load_file_content(hash).await?;
+        to.save_file_content(hash, &content).await?;
+    }
+    println!("  file contents: {}", contents.len());
+
+    let settings = all_settings(from).await?;
+    for (key, value) in &settings {
+        to.save_setting(key, value).await?;
+    }
+    println!("  settings: {}", settings.len());
+
+    to.flush().await
+}
+
+/// How many entities of a kind a backend holds, and a digest of them that
+/// doesn't depend on the order they're listed in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub count: usize,
+    pub digest: u64,
+}
+
+impl Tally {
+    fn add(&mut self, item: &impl Serialize) {
+        // Through `Value` so map keys come out sorted
+        let value = serde_json::to_value(item).unwrap_or_default();
+        self.add_bytes(&serde_json::to_vec(&value).unwrap_or_default());
+    }
+
+    fn add_bytes(&mut self, bytes: &[u8]) {
+        let hash = Sha256::digest(bytes);
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash[..8]);
+        self.digest = self.digest.wrapping_add(u64::from_le_bytes(prefix));
+        self.count += 1;
+    }
+
+    fn of<T: Serialize>(items: &[T]) -> Self {
+        let mut tally = Tally::default();
+        for item in items {
+            tally.add(item);
+        }
+        tally
+    }
+}
+
+/// A `Tally` for every kind of entity in `backend`.
+pub async fn checksums(
+    backend: &AnyBackend,
+    batch_size: usize,
+) -> Result<BTreeMap<&'static str, Tally>, StorageError> {
+    let mut tallies = BTreeMap::new();
+    tallies.insert("traces", Tally::of(&all_traces(backend).await?));
+
+    let mut spans = Tally::default();
+    let mut pages = SpanPages::new(backend, batch_size);
+    while let Some(page) = pages.next().await? {
+        for span in &page {
+            spans.add(span);
+        }
+    }
+    tallies.insert("spans", spans);
+
+    tallies.insert("datasets", Tally::of(&backend.load_all_datasets().await?));
+    tallies.insert(
+        "datapoints",
+        Tally::of(&backend.load_all_datapoints().await?),
+    );
+    let queue_items = backend.load_all_queue_items().await?;
+    let mut submissions = Tally::default();
+    for item in &queue_items {
+        for submission in backend.list_queue_submissions(item.id).await? {
+            submissions.add(&submission);
+        }
+    }
+    tallies.insert("queue items", Tally::of(&queue_items));
+    tallies.insert("queue submissions", submissions);
+    tallies.insert("eval runs", Tally::of(&backend.load_all_eval_runs().await?));
+    tallies.insert(
+        "eval results",
+        Tally::of(&backend.load_all_eval_results().await?),
+    );
+    tallies.insert(
+        "capture rules",
+        Tally::of(&backend.load_all_capture_rules().await?),
+    );
+    tallies.insert(
+        "provider connections",
+        Tally::of(&backend.load_all_provider_connections().await?),
+    );
+    tallies.insert(
+        "span kinds",
+        Tally::of(&backend.load_all_span_kinds().await?),
+    );
+    tallies.insert("machines", Tally::of(&backend.list_machines().await?));
+    let webhooks = backend.list_webhooks().await?;
+    let mut deliveries = Tally::default();
+    for webhook in &webhooks {
+        for delivery in backend
+            .list_webhook_deliveries(webhook.id, ALL_ROWS)
+            .await?
+        {
+            deliveries.add(&delivery);
+        }
+    }
+    tallies.insert("webhooks", Tally::of(&webhooks));
+    tallies.insert("webhook deliveries", deliveries);
+    tallies.insert("views", Tally::of(&backend.list_views().await?));
+    tallies.insert("annotations", Tally::of(&all_annotations(backend).await?));
+    tallies.insert("audit events", Tally::of(&all_audit_events(backend).await?));
+    tallies.insert(
+        "file versions",
+        Tally::of(&backend.list_file_versions().await?),
+    );
+
+    let mut contents = Tally::default();
+    for (hash, _) in backend.list_file_contents().await? {
+        contents.add_bytes(&backend.load_file_content(&hash).await?);
+    }
+    tallies.insert("file contents", contents);
+    let settings: Vec<_> = all_settings(backend).await?.into_iter().collect();
+    tallies.insert("settings", Tally::of(&settings));
+    Ok(tallies)
+}
+
+/// Print how each kind compares, returning the kinds that differ.
+fn compare(
+    source: &BTreeMap<&'static str, Tally>,
+    dest: &BTreeMap<&'static str, Tally>,
+) -> Vec<&'static str> {
+    let mut mismatched = Vec::new();
+    for (kind, expected) in source {
+        let actual = dest.get(kind).copied().unwrap_or_default();
+        if actual == *expected {
+            println!("  {kind}: {} ok", expected.count);
+        } else if actual.count != expected.count {
+            println!(
+                "  {kind}: {} in source, {} in destination",
+                expected.count, actual.count
+            );
+            mismatched.push(*kind);
+        } else {
+            println!("  {kind}: {} each, but checksums differ", expected.count);
+            mismatched.push(*kind);
+        }
+    }
+    mismatched
+}
+
+/// Every trace, including those in the trash.
+async fn all_traces(backend: &AnyBackend) -> Result<Vec<trace::Trace>, StorageError> {
+    backend
+        .list_traces(&TraceFilter {
+            trash: TrashScope::All,
+            ..Default::default()
+        })
+        .await
+}
+
+async fn all_annotations(backend: &AnyBackend) -> Result<Vec<trace::Annotation>, StorageError> {
+    backend
+        .list_annotations(&AnnotationFilter {
+            limit: Some(ALL_ROWS),
+            ..Default::default()
+        })
+        .await
+}
+
+async fn all_audit_events(backend: &AnyBackend) -> Result<Vec<trace::AuditEvent>, StorageError> {
+    backend
+        .list_audit_events(&AuditFilter {
+            limit: Some(ALL_ROWS),
+            ..Default::default()
+        })
+        .await
+}
+
+/// Every known setting that is set, keyed by name.
+async fn all_settings(
+    backend: &AnyBackend,
+) -> Result<BTreeMap<String, serde_json::Value>, StorageError> {
+    let mut keys: Vec<String> = SETTING_KEYS.iter().map(|k| k.to_string()).collect();
+    if let Some(runs) = backend
+        .get_setting(crate::api::playground::RUNS_SETTING)
+        .await?
+    {
+        let ids: Vec<crate::api::playground::PlaygroundRunId> =
+            serde_json::from_value(runs).unwrap_or_default();
+        keys.extend(ids.into_iter().map(crate::api::playground::run_setting));
+    }
+    for trace in all_traces(backend).await? {
+        keys.extend(
+            storage::TRACE_SETTINGS
+                .iter()
+                .map(|name| storage::trace_setting_key(name, trace.id)),
+        );
+    }
+
+    let mut settings = BTreeMap::new();
+    for key in keys {
+        match backend.get_setting(&key).await? {
+            None | Some(serde_json::Value::Null) => {}
+            Some(value) => {
+                settings.insert(key, value);
+            }
+        }
+    }
+    Ok(settings)
+}
+
+/// Every span in a backend, oldest first, a page at a time.
+struct SpanPages<'a> {
+    backend: &'a AnyBackend,
+    filter: SpanFilter,
+    limit: usize,
+    done: bool,
+}
+
+impl<'a> SpanPages<'a> {
+    fn new(backend: &'a AnyBackend, limit: usize) -> Self {
+        Self {
+            backend,
+            filter: SpanFilter {
+                limit: Some(limit + 1),
+                sort_by: Some("started_at".to_string()),
+                sort_order: Some("asc".to_string()),
+                ..Default::default()
+            },
+            limit,
+            done: false,
+        }
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<Span>>, StorageError> {
+        if self.done {
+            return Ok(None);
+        }
+        let spans = self.backend.list_spans(&self.filter).await?;
+        let page = filter::into_page(spans, self.limit, "started_at", |s| {
+            (filter::span_sort_value(s, "started_at"), s.id().to_string())
+        });
+        match page.next_cursor {
+            Some(cursor) => self.filter.cursor = Some(cursor),
+            None => self.done = true,
+        }
+        Ok(Some(page.items).filter(|items| !items.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage_sqlite::SqliteBackend;
+    use trace::{Dataset, SpanBuilder, SpanKind, Trace};
+
+    use super::*;
+
+    fn open(dir: &std::path::Path, name: &str) -> AnyBackend {
+        AnyBackend::Sqlite(SqliteBackend::open(&dir.join(name)).unwrap())
+    }
+
+    #[tokio::test]
+    async fn copies_every_page_and_verifies() {
+        let dir = std::env::temp_dir().join(format!("migrate-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = open(&dir, "from.db");
+        let to = open(&dir, "to.db");
+
+        let trace = Trace::new(Some("run".into()));
+        from.save_trace(&trace).await.unwrap();
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        let spans: Vec<Span> = (0..7)
+            .map(|i| SpanBuilder::new(trace.id, format!("step {i}"), kind.clone()).build())
+            .collect();
+        from.save_spans_batch(&spans).await.unwrap();
+        from.save_dataset(&Dataset::new("golden", None))
+            .await
+            .unwrap();
+        let slack = serde_json::json!({"channel": "#ops"});
+        from.save_setting(crate::api::slack::SLACK_SETTING, &slack)
+            .await
+            .unwrap();
+        let summary = crate::api::summaries::summary_setting(trace.id);
+        let written = serde_json::json!({"summary": "ran"});
+        from.save_setting(&summary, &written).await.unwrap();
+
+        copy(&from, &to, 3).await.unwrap();
+        let source = checksums(&from, 3).await.unwrap();
+        assert_eq!(source["spans"].count, 7);
+        assert_eq!(source["settings"].count, 2);
+        assert_eq!(to.get_setting(&summary).await.unwrap(), Some(written));
+        assert!(compare(&source, &checksums(&to, 2).await.unwrap()).is_empty());
+
+        to.save_dataset(&Dataset::new("extra", None)).await.unwrap();
+        assert_eq!(
+            compare(&source, &checksums(&to, 3).await.unwrap()),
+            vec!["datasets"]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn dual_write_mirrors_writes_and_reads_primary() {
+        let dir = std::env::temp_dir().join(format!("migrate-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backend = open(&dir, "primary.db").dual_write(open(&dir, "mirror.db"));
+
+        let dataset = Dataset::new("golden", None);
+        backend.save_dataset(&dataset).await.unwrap();
+        assert_eq!(backend.list_datasets().await.unwrap().len(), 1);
+        assert_eq!(backend.mirror_failures(), Some(0));
+        drop(backend);
+
+        let mirror = open(&dir, "mirror.db");
+        let mirrored = mirror.list_datasets().await.unwrap();
+        assert_eq!(mirrored.len(), 1);
+        assert_eq!(mirrored[0].id, dataset.id);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -90,6 +90,11 @@ pub fn is_process_alive(pid: u32) -> bool {
 
 /// Read PID from a file path and check if alive.
 pub fn check_running(pid_path: &Path) -> Option<u32> {
-    let pid_file = PidFile::new(pid_path.to_path_buf());
-    pid_file.is_running()
+    // Not through `PidFile`, whose drop would remove the running daemon's file
+    let pid = fs::read_to_string(pid_path)
+        .ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+    is_process_alive(pid).then_some(pid)
 }
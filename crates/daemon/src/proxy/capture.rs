@@ -0,0 +1,153 @@
+//! How much of each proxied exchange is stored.
+//!
+//! The mode is set by `proxy.capture_mode` (`off`, `full`, `preview`, or
+//! `preview:<chars>`), can be overridden per route, and the default can be
+//! changed at runtime through `PUT /api/config/proxy/capture`.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use serde_json::Value;
+
+use super::preview_string;
+
+/// Characters kept per string by a bare `preview`.
+pub const DEFAULT_PREVIEW_CHARS: usize = 500;
+
+/// Payload capture mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureMode {
+    Off,
+    Preview(usize), // max chars
+    #[default]
+    Full,
+}
+
+impl FromStr for CaptureMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(CaptureMode::Off),
+            "full" => Ok(CaptureMode::Full),
+            "preview" => Ok(CaptureMode::Preview(DEFAULT_PREVIEW_CHARS)),
+            other => other
+                .strip_prefix("preview:")
+                .and_then(|n| n.parse().ok())
+                .map(CaptureMode::Preview)
+                .ok_or_else(|| {
+                    format!("invalid capture mode '{s}': expected off, full, preview, or preview:<chars>")
+                }),
+        }
+    }
+}
+
+impl fmt::Display for CaptureMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureMode::Off => f.write_str("off"),
+            CaptureMode::Preview(max) => write!(f, "preview:{max}"),
+            CaptureMode::Full => f.write_str("full"),
+        }
+    }
+}
+
+impl CaptureMode {
+    /// Parse a configured mode, falling back to `Full` with a warning.
+    pub fn from_config(s: &str) -> Self {
+        s.parse().unwrap_or_else(|e| {
+            tracing::warn!("{e}; capturing full payloads");
+            CaptureMode::Full
+        })
+    }
+
+    /// What to store of a request or response body. Previews keep the
+    /// JSON shape and truncate each string, so chat messages still render.
+    pub fn payload(self, value: &Value) -> Option<Value> {
+        match self {
+            CaptureMode::Off => None,
+            CaptureMode::Preview(max) => {
+                let mut value = value.clone();
+                truncate_strings(&mut value, max);
+                Some(value)
+            }
+            CaptureMode::Full => Some(value.clone()),
+        }
+    }
+
+    /// The one-line preview kept on the span kind.
+    pub fn preview(self, text: &str) -> Option<String> {
+        match self {
+            CaptureMode::Off => None,
+            CaptureMode::Preview(max) => Some(preview_string(text, max)),
+            CaptureMode::Full => Some(text.to_string()),
+        }
+    }
+}
+
+fn truncate_strings(value: &mut Value, max: usize) {
+    match value {
+        Value::String(s) if s.chars().nth(max).is_some() => *s = preview_string(s, max),
+        Value::Array(items) => items.iter_mut().for_each(|v| truncate_strings(v, max)),
+        Value::Object(map) => map.values_mut().for_each(|v| truncate_strings(v, max)),
+        _ => {}
+    }
+}
+
+/// The proxy's default mode, shared with the API so it can be changed
+/// while the proxy runs.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCaptureMode(Arc<RwLock<CaptureMode>>);
+
+impl SharedCaptureMode {
+    pub fn new(mode: CaptureMode) -> Self {
+        Self(Arc::new(RwLock::new(mode)))
+    }
+
+    pub fn get(&self) -> CaptureMode {
+        *self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set(&self, mode: CaptureMode) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = mode;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_modes() {
+        assert_eq!("off".parse(), Ok(CaptureMode::Off));
+        assert_eq!(" Full ".parse(), Ok(CaptureMode::Full));
+        assert_eq!(
+            "preview".parse(),
+            Ok(CaptureMode::Preview(DEFAULT_PREVIEW_CHARS))
+        );
+        assert_eq!("preview:80".parse(), Ok(CaptureMode::Preview(80)));
+        assert!("preview:lots".parse::<CaptureMode>().is_err());
+        assert_eq!(CaptureMode::Preview(80).to_string(), "preview:80");
+        assert_eq!(CaptureMode::from_config("nope"), CaptureMode::Full);
+    }
+
+    #[test]
+    fn preview_truncates_every_string() {
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "tell me a long story"}],
+            "temperature": 0.2
+        });
+        assert_eq!(
+            CaptureMode::Preview(6).payload(&body),
+            Some(serde_json::json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "tell m..."}],
+                "temperature": 0.2
+            }))
+        );
+        assert_eq!(CaptureMode::Off.payload(&body), None);
+        assert_eq!(CaptureMode::Full.payload(&body), Some(body));
+    }
+}
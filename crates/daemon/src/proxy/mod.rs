@@ -1,35 +1,89 @@
-use crate::api::SharedStore;
+mod capture;
+mod providers;
+mod routes;
+mod transport;
+
+use crate::api::budgets::{BudgetStatus, BudgetTracker};
+use crate::api::metrics;
+use crate::api::sampling::Sampler;
+use crate::api::sessions::SESSION_HEADER;
+use crate::api::{SharedStore, SystemEvent};
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::State,
     http::Request,
     response::{IntoResponse, Response},
     Router,
 };
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde::Deserialize;
 use serde_json::Value;
-use trace::{SpanBuilder, SpanKind};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
+use trace::git::GitInfo;
+use trace::pricing::PricingTable;
+use trace::{Span, SpanBuilder, SpanKind};
 
-/// Payload capture mode
-#[derive(Debug, Clone)]
-pub enum CaptureMode {
-    Off,
-    Preview(usize), // max chars
-    Full,
-}
+use crate::config::{ProxyConfig, ProxyTimeouts};
+pub use capture::{CaptureMode, SharedCaptureMode, DEFAULT_PREVIEW_CHARS};
+pub(crate) use providers::{ApiShape, Message, NormalizedRequest};
+use providers::{NormalizedResponse, StreamedToolCalls};
+pub use routes::glob_match;
+use routes::RouteTable;
+use transport::{HeaderEdits, SendError};
 
-impl Default for CaptureMode {
-    fn default() -> Self {
-        CaptureMode::Full
-    }
-}
+/// The proxy serves the local org, so budgets are checked against its.
+const PROXY_ORG: uuid::Uuid = uuid::Uuid::nil();
+/// Minimum gap between `SpanStreaming` events for one span.
+const STREAM_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+/// Largest delta carried by a single `SpanStreaming` event; the rest waits
+/// for the next one.
+const MAX_STREAM_DELTA_CHARS: usize = 4096;
 
 #[derive(Clone)]
 struct ProxyState {
     store: SharedStore,
-    target_url: String,
+    routes: Arc<RouteTable>,
     client: reqwest::Client,
-    capture_mode: CaptureMode,
+    timeouts: ProxyTimeouts,
+    connect_retries: u32,
+    request_headers: Arc<HeaderEdits>,
+    response_headers: Arc<HeaderEdits>,
+    /// Used for requests whose route doesn't set its own mode.
+    capture_mode: SharedCaptureMode,
+    /// Shared with the API, so `PUT /api/config` reprices later calls.
+    pricing: Arc<RwLock<PricingTable>>,
     encore_bridge: Option<EncoreBridgeConfig>,
+    events_tx: Option<broadcast::Sender<SystemEvent>>,
+    sampler: Option<Arc<Sampler>>,
+    budgets: Option<Arc<BudgetTracker>>,
+    /// The checkout the proxy runs in, stamped on traces it creates.
+    git: Arc<GitInfo>,
+}
+
+/// The LLM call span a proxied request is recorded as.
+struct ProxiedCall {
+    trace_id: trace::TraceId,
+    span_id: trace::SpanId,
+    model: String,
+    provider: Option<String>,
+    shape: Option<ApiShape>,
+    capture: CaptureMode,
+    input_preview: Option<String>,
+    /// The trace's tags, for budget scopes.
+    tags: Vec<String>,
+}
+
+impl ProxiedCall {
+    /// Whose usage fields to read: the API called, else the target's provider.
+    fn token_dialect(&self) -> Option<&str> {
+        self.shape
+            .map(ApiShape::provider)
+            .or(self.provider.as_deref())
+    }
 }
 
 #[derive(Clone)]
@@ -69,16 +123,22 @@ impl EncoreBridgeConfig {
     }
 }
 
-async fn bridge_create_trace(config: &EncoreBridgeConfig, client: &reqwest::Client, trace_id: trace::TraceId, name: &str) {
+async fn bridge_create_trace(config: &EncoreBridgeConfig, client: &reqwest::Client, trace: &trace::Trace) {
     let _ = client
         .post(format!("{}/traces", config.base_url))
         .header("x-traceway-control-token", &config.control_token)
         .header("x-traceway-org-id", &config.org_id)
         .header("x-traceway-project-id", &config.project_id)
         .json(&serde_json::json!({
-            "id": trace_id.to_string(),
-            "name": name,
-            "tags": ["proxy"],
+            "id": trace.id.to_string(),
+            "name": trace.name,
+            "tags": trace.tags,
+            "session_id": trace.session_id,
+            "user_id": trace.user_id,
+            "machine_id": trace.machine_id,
+            "git_commit": trace.git_commit,
+            "git_branch": trace.git_branch,
+            "repo": trace.repo,
         }))
         .send()
         .await;
@@ -131,6 +191,24 @@ async fn bridge_fail_span(config: &EncoreBridgeConfig, client: &reqwest::Client,
         .await;
 }
 
+/// Register this daemon with the backend, if bridging is configured.
+pub async fn bridge_register_machine(
+    client: &reqwest::Client,
+    machine: &crate::api::machines::RegisterMachine,
+) {
+    let Some(config) = EncoreBridgeConfig::from_env() else {
+        return;
+    };
+    let _ = client
+        .post(format!("{}/machines", config.base_url))
+        .header("x-traceway-control-token", &config.control_token)
+        .header("x-traceway-org-id", &config.org_id)
+        .header("x-traceway-project-id", &config.project_id)
+        .json(machine)
+        .send()
+        .await;
+}
+
 /// Detect provider from target URL
 fn detect_provider(url: &str) -> Option<String> {
     if url.contains("localhost:11434") || url.contains("ollama") {
@@ -185,8 +263,229 @@ fn extract_tokens(body: &Value, provider: Option<&str>) -> (Option<u64>, Option<
     }
 }
 
+/// Whether the upstream response is a token stream (SSE or NDJSON).
+fn is_streaming(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| {
+            ct.starts_with("text/event-stream") || ct.starts_with("application/x-ndjson")
+        })
+}
+
+const USER_HEADER: &str = "x-traceway-user-id";
+const TAGS_HEADER: &str = "x-traceway-tags";
+/// Record the call in this existing trace instead of a new one.
+pub(crate) const TRACE_HEADER: &str = "x-traceway-trace-id";
+/// Record the call as a child of this span.
+pub(crate) const PARENT_HEADER: &str = "x-traceway-parent-id";
+/// Set on responses to the id of the span the call was recorded as.
+pub(crate) const SPAN_ID_HEADER: &str = "x-traceway-span-id";
+/// Longest session id, user id, or tag accepted from a header.
+const MAX_CONTEXT_LEN: usize = 256;
+
+/// Trace context sent by apps that instrument through headers instead of
+/// the SDK. Stripped before the request is forwarded upstream.
+#[derive(Debug, Default, PartialEq)]
+struct CallContext {
+    session_id: Option<String>,
+    user_id: Option<String>,
+    /// From a comma-separated `X-Traceway-Tags`.
+    tags: Vec<String>,
+    trace_id: Option<trace::TraceId>,
+    parent_id: Option<trace::SpanId>,
+}
+
+impl CallContext {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty() && v.len() <= MAX_CONTEXT_LEN)
+                .map(str::to_string)
+        };
+        let mut tags: Vec<String> = Vec::new();
+        for header in headers.get_all(TAGS_HEADER) {
+            let Ok(header) = header.to_str() else {
+                continue;
+            };
+            for tag in header.split(',').map(str::trim) {
+                let valid = !tag.is_empty() && tag.len() <= MAX_CONTEXT_LEN;
+                if valid && !tags.iter().any(|t| t == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+        }
+        Self {
+            session_id: value(SESSION_HEADER),
+            user_id: value(USER_HEADER),
+            tags,
+            trace_id: value(TRACE_HEADER).and_then(|v| v.parse().ok()),
+            parent_id: value(PARENT_HEADER).and_then(|v| v.parse().ok()),
+        }
+    }
+
+    fn is_context_header(name: &str) -> bool {
+        [
+            SESSION_HEADER,
+            USER_HEADER,
+            TAGS_HEADER,
+            TRACE_HEADER,
+            PARENT_HEADER,
+        ]
+        .contains(&name)
+    }
+
+    /// The trace a proxied call is recorded under.
+    fn trace(&self, name: String) -> trace::Trace {
+        let mut tags = vec!["proxy".to_string()];
+        tags.extend(self.tags.iter().filter(|t| *t != "proxy").cloned());
+        let mut trace = trace::Trace::new(Some(name)).with_tags(tags);
+        if let Some(id) = self.trace_id {
+            trace.id = id;
+        }
+        trace.session_id = self.session_id.clone();
+        trace.user_id = self.user_id.clone();
+        trace
+    }
+}
+
+/// Text delta carried by one streamed event: OpenAI chat/completions chunks,
+/// Anthropic `content_block_delta`, or Ollama chat/generate lines.
+fn extract_stream_delta(event: &Value) -> Option<&str> {
+    let choice = event.get("choices").and_then(|c| c.get(0));
+    choice
+        .and_then(|c| c.get("delta"))
+        .and_then(|d| d.get("content"))
+        .or_else(|| choice.and_then(|c| c.get("text")))
+        .or_else(|| event.get("delta").and_then(|d| d.get("text")))
+        .or_else(|| event.get("message").and_then(|m| m.get("content")))
+        .or_else(|| event.get("response"))
+        .and_then(|v| v.as_str())
+}
+
+/// Incremental parser for a streamed completion. Collects the generated text
+/// and token usage, and batches text deltas for live `SpanStreaming` events.
+struct StreamTap {
+    provider: Option<String>,
+    line: Vec<u8>,
+    text: String,
+    tool_calls: StreamedToolCalls,
+    tokens: (Option<u64>, Option<u64>),
+    pending: String,
+    /// Chars still allowed in live events (the capture preview limit);
+    /// `None` is unlimited.
+    budget: Option<usize>,
+    interval: Duration,
+    last_emit: Option<Instant>,
+}
+
+impl StreamTap {
+    fn new(provider: Option<String>, capture_mode: &CaptureMode) -> Self {
+        let budget = match capture_mode {
+            CaptureMode::Off => Some(0),
+            CaptureMode::Preview(max) => Some(*max),
+            CaptureMode::Full => None,
+        };
+        Self {
+            provider,
+            line: Vec::new(),
+            text: String::new(),
+            tool_calls: StreamedToolCalls::default(),
+            tokens: (None, None),
+            pending: String::new(),
+            budget,
+            interval: STREAM_EVENT_INTERVAL,
+            last_emit: None,
+        }
+    }
+
+    /// Consume a chunk of the response body. Returns a delta to publish if
+    /// the throttle interval has passed.
+    fn feed(&mut self, chunk: &[u8]) -> Option<String> {
+        self.line.extend_from_slice(chunk);
+        while let Some(pos) = self.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=pos).collect();
+            self.parse_line(&line);
+        }
+        if self.last_emit.is_none_or(|t| t.elapsed() >= self.interval) {
+            self.take_delta()
+        } else {
+            None
+        }
+    }
+
+    /// Parse any unterminated last line and return the unsent delta.
+    fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.line);
+        self.parse_line(&line);
+        self.take_delta()
+    }
+
+    /// Everything generated, as the span output.
+    fn output(&self) -> Value {
+        let response = NormalizedResponse::assistant(
+            self.text.clone(),
+            self.tool_calls.calls(),
+            self.tool_calls.finish_reason.clone(),
+        );
+        serde_json::to_value(response).unwrap_or_default()
+    }
+
+    fn parse_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        let data = line.strip_prefix("data:").map(str::trim).unwrap_or(line);
+        if !data.starts_with('{') {
+            return;
+        }
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            return;
+        };
+        if let Some(delta) = extract_stream_delta(&event) {
+            self.text.push_str(delta);
+            self.pending.push_str(delta);
+        }
+        self.tool_calls.feed(&event);
+        // Usage arrives on the final chunk (OpenAI, Ollama), or split across
+        // Anthropic's `message_start` message and `message_delta`.
+        for v in [Some(&event), event.get("message")].into_iter().flatten() {
+            let (input, output) = extract_tokens(v, self.provider.as_deref());
+            self.tokens.0 = input.or(self.tokens.0);
+            self.tokens.1 = output.or(self.tokens.1);
+        }
+    }
+
+    fn take_delta(&mut self) -> Option<String> {
+        let allowed = self
+            .budget
+            .unwrap_or(usize::MAX)
+            .min(MAX_STREAM_DELTA_CHARS);
+        let split = self
+            .pending
+            .char_indices()
+            .nth(allowed)
+            .map_or(self.pending.len(), |(i, _)| i);
+        let rest = self.pending.split_off(split);
+        let delta = std::mem::replace(&mut self.pending, rest);
+        if let Some(budget) = self.budget.as_mut() {
+            *budget -= delta.chars().count();
+            if *budget == 0 {
+                self.pending.clear();
+            }
+        }
+        if delta.is_empty() {
+            return None;
+        }
+        self.last_emit = Some(Instant::now());
+        Some(delta)
+    }
+}
+
 /// Truncate a string for preview mode (character-aware, safe for multi-byte UTF-8)
-fn preview_string(s: &str, max_chars: usize) -> String {
+pub(crate) fn preview_string(s: &str, max_chars: usize) -> String {
     let mut chars = s.chars();
     let truncated: String = chars.by_ref().take(max_chars).collect();
     if chars.next().is_some() {
@@ -223,6 +522,26 @@ mod tests {
         assert_eq!(preview_string("日本語テスト", 6), "日本語テスト");
     }
 
+    #[test]
+    fn call_context_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-traceway-session-id", " chat-42 ".parse().unwrap());
+        headers.insert("x-traceway-user-id", "".parse().unwrap());
+        headers.append("x-traceway-tags", "beta, checkout,,beta".parse().unwrap());
+        headers.append("x-traceway-tags", "proxy".parse().unwrap());
+
+        let context = CallContext::from_headers(&headers);
+        assert_eq!(context.session_id.as_deref(), Some("chat-42"));
+        assert_eq!(context.user_id, None);
+        assert_eq!(context.tags, ["beta", "checkout", "proxy"]);
+
+        let trace = context.trace("POST /v1/chat/completions".to_string());
+        assert_eq!(trace.tags, ["proxy", "beta", "checkout"]);
+        assert_eq!(trace.session_id.as_deref(), Some("chat-42"));
+        assert!(CallContext::is_context_header("x-traceway-tags"));
+        assert!(!CallContext::is_context_header("authorization"));
+    }
+
     #[test]
     fn preview_string_empty() {
         assert_eq!(preview_string("", 10), "");
@@ -233,6 +552,160 @@ mod tests {
     fn preview_string_zero_max() {
         assert_eq!(preview_string("hello", 0), "...");
     }
+
+    fn unthrottled(provider: &str, capture_mode: CaptureMode) -> StreamTap {
+        let mut tap = StreamTap::new(Some(provider.to_string()), &capture_mode);
+        tap.interval = Duration::ZERO;
+        tap
+    }
+
+    #[test]
+    fn stream_tap_openai_split_chunks() {
+        let mut tap = unthrottled("openai", CaptureMode::Full);
+        assert_eq!(tap.feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"Hel"), None);
+        assert_eq!(
+            tap.feed(b"lo\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\" there\"}}]}\n\n")
+                .as_deref(),
+            Some("Hello there")
+        );
+        tap.feed(b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}}\n\ndata: [DONE]\n\n");
+        assert_eq!(tap.finish(), None);
+        assert_eq!(tap.tokens, (Some(5), Some(2)));
+        assert_eq!(
+            tap.output(),
+            serde_json::json!({ "role": "assistant", "content": "Hello there" })
+        );
+    }
+
+    #[test]
+    fn stream_tap_anthropic_usage() {
+        let mut tap = unthrottled("anthropic", CaptureMode::Full);
+        tap.feed(b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n");
+        let delta = tap.feed(b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n");
+        assert_eq!(delta.as_deref(), Some("Hi"));
+        tap.feed(b"data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":7}}\n\n");
+        assert_eq!(tap.tokens, (Some(12), Some(7)));
+    }
+
+    #[test]
+    fn stream_tap_respects_capture_limit() {
+        let mut tap = unthrottled("ollama", CaptureMode::Preview(4));
+        assert_eq!(tap.feed(b"{\"response\":\"abc\"}\n").as_deref(), Some("abc"));
+        assert_eq!(tap.feed(b"{\"response\":\"def\"}\n").as_deref(), Some("d"));
+        assert_eq!(tap.feed(b"{\"response\":\"ghi\"}\n"), None);
+        assert_eq!(tap.text, "abcdefghi");
+
+        let mut off = unthrottled("ollama", CaptureMode::Off);
+        assert_eq!(off.feed(b"{\"response\":\"abc\"}\n"), None);
+    }
+
+    #[test]
+    fn tool_calls_become_child_spans() {
+        let call = ProxiedCall {
+            trace_id: uuid::Uuid::new_v4(),
+            span_id: uuid::Uuid::new_v4(),
+            model: "gpt-4o".into(),
+            provider: Some("openai".into()),
+            shape: Some(ApiShape::OpenAiChat),
+            capture: CaptureMode::Off,
+            input_preview: None,
+            tags: Vec::new(),
+        };
+        let output = serde_json::json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [{ "id": "call_1", "name": "search", "arguments": { "q": "rust" } }],
+        });
+        let spans = tool_call_spans(&call, &output);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].parent_id(), Some(call.span_id));
+        assert_eq!(spans[0].kind().tool_name(), Some("search"));
+        // Arguments are payload, so capture mode applies
+        assert!(matches!(
+            spans[0].kind(),
+            SpanKind::ToolCall {
+                arguments: None,
+                ..
+            }
+        ));
+        assert!(tool_call_spans(&call, &serde_json::json!({ "choices": [] })).is_empty());
+    }
+
+    #[test]
+    fn stream_tap_throttles_deltas() {
+        let mut tap = StreamTap::new(None, &CaptureMode::Full);
+        tap.interval = Duration::from_secs(60);
+        assert_eq!(tap.feed(b"{\"response\":\"a\"}\n").as_deref(), Some("a"));
+        assert_eq!(tap.feed(b"{\"response\":\"b\"}\n"), None);
+        assert_eq!(tap.feed(b"{\"response\":\"c\"}\n"), None);
+        assert_eq!(tap.finish().as_deref(), Some("bc"));
+    }
+
+    #[tokio::test]
+    async fn config_update_reprices_proxied_calls() {
+        use storage::PersistentStore;
+        use storage_sqlite::SqliteBackend;
+        use tower::ServiceExt;
+
+        use crate::api::{AnyBackend, RouterBuilder};
+
+        // Upstream that bills one million input tokens per call
+        let upstream = Router::new().fallback(|| async {
+            axum::Json(serde_json::json!({
+                "model": "house-model",
+                "usage": { "prompt_tokens": 1_000_000, "completion_tokens": 0 },
+            }))
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let dir = std::env::temp_dir().join(format!("proxy-pricing-{}", uuid::Uuid::new_v4()));
+        let pricing = Arc::new(RwLock::new(PricingTable::default()));
+        let api = RouterBuilder::new(store.clone())
+            .config_path(dir.join("config.toml").to_string_lossy().into_owned())
+            .pricing(pricing.clone())
+            .build();
+        let config = ProxyConfig {
+            target,
+            ..Default::default()
+        };
+        let capture_mode = SharedCaptureMode::new(CaptureMode::from_config(&config.capture_mode));
+        let proxy = router(store.clone(), &config, capture_mode, pricing, ApiLinks::default());
+
+        let update = serde_json::json!({
+            "pricing": { "models": { "house-model": { "input_per_mtok": 7.0, "output_per_mtok": 0.0 } } },
+        });
+        let resp = api
+            .oneshot(
+                Request::put("/api/config")
+                    .header("content-type", "application/json")
+                    .body(Body::from(update.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let call = serde_json::json!({ "model": "house-model", "messages": [] });
+        let resp = proxy
+            .oneshot(
+                Request::post("/v1/chat/completions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(call.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let spans = store.all_spans();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].kind().cost(), Some(7.0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
 
 async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> Response {
@@ -244,10 +717,11 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
         .unwrap_or_else(|| "/".to_string());
     let span_name = format!("{} {}", method, path);
 
-    let provider = detect_provider(&state.target_url);
+    let shape = ApiShape::from_path(req.uri().path());
 
     // Read request body
     let (parts, body) = req.into_parts();
+    let context = CallContext::from_headers(&parts.headers);
     let body_bytes = match axum::body::to_bytes(body, 10 * 1024 * 1024).await {
         Ok(b) => b,
         Err(e) => {
@@ -258,20 +732,32 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
 
     // Parse request JSON for model extraction
     let req_json = serde_json::from_slice::<Value>(&body_bytes).ok();
-    let model = req_json
-        .as_ref()
-        .and_then(extract_model)
-        .unwrap_or_else(|| "unknown".to_string());
-
-    // Build input preview
-    let input_preview = match &state.capture_mode {
-        CaptureMode::Off => None,
-        CaptureMode::Preview(max) => {
-            let raw = String::from_utf8_lossy(&body_bytes);
-            Some(preview_string(&raw, *max))
+    let requested_model = req_json.as_ref().and_then(extract_model);
+    let upstream = state.routes.resolve(requested_model.as_deref()).clone();
+    let provider = upstream
+        .provider
+        .clone()
+        .or_else(|| shape.map(|s| s.provider().to_string()));
+    let model = requested_model.unwrap_or_else(|| "unknown".to_string());
+
+    // Nothing is recorded for calls a spent budget rejects
+    let existing = context.trace_id.and_then(|id| state.store.get_trace(id));
+    let mut trace = existing
+        .clone()
+        .unwrap_or_else(|| context.trace(span_name.clone()));
+    state.git.apply(&mut trace);
+    if let Some(budgets) = &state.budgets {
+        if let Some(over) = budgets.exceeded(PROXY_ORG, &model, &trace.tags).await {
+            tracing::warn!(budget = %over.budget.name, %model, "rejecting call over budget");
+            return budget_exceeded(&over);
         }
-        CaptureMode::Full => Some(String::from_utf8_lossy(&body_bytes).to_string()),
-    };
+    }
+
+    // Resolved once so a mode change mid-request can't split the span
+    let capture = upstream
+        .capture_mode
+        .unwrap_or_else(|| state.capture_mode.get());
+    let input_preview = capture.preview(&String::from_utf8_lossy(&body_bytes));
 
     // Build span kind
     let kind = SpanKind::LlmCall {
@@ -284,34 +770,40 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
         output_preview: None,
     };
 
-    // Build input payload
-    let input_payload = match &state.capture_mode {
-        CaptureMode::Off => None,
-        _ => req_json.clone(),
-    };
+    // Build input payload, in the common message format when the API is known
+    let input_payload = req_json.as_ref().and_then(|json| {
+        let normalized = shape
+            .and_then(|s| s.normalize_request(json))
+            .and_then(|n| serde_json::to_value(n).ok());
+        capture.payload(normalized.as_ref().unwrap_or(json))
+    });
 
-    // Create and insert span
-    let mut builder = SpanBuilder::new(
-        trace::Trace::new(Some(span_name.clone())).id,
-        &span_name,
-        kind,
-    );
+    // Create the trace unless the caller named one, then insert the span under it
+    let trace_id = trace.id;
+    if existing.is_none() {
+        if let Err(e) = state.store.save_trace(trace.clone()).await {
+            tracing::error!(%trace_id, "failed to save proxy trace: {e}");
+        }
+    }
+    let mut builder = SpanBuilder::new(trace_id, &span_name, kind);
     if let Some(input) = input_payload {
         builder = builder.input(input);
     }
+    if let Some(parent_id) = context.parent_id {
+        builder = builder.parent(parent_id);
+    }
     let span = builder.build();
     let span_id = span.id();
-    let trace_id = span.trace_id();
 
-    {
-        let mut store = state.store.write().await;
-        if let Err(e) = store.insert(span).await {
-            tracing::error!(%span_id, "failed to insert proxy span: {e}");
-        }
+    match state.store.insert(span).await {
+        Ok(_) => metrics::global().record_ingest("proxy", 1),
+        Err(e) => tracing::error!(%span_id, "failed to insert proxy span: {e}"),
     }
 
     if let Some(config) = &state.encore_bridge {
-        bridge_create_trace(config, &state.client, trace_id, &span_name).await;
+        if existing.is_none() {
+            bridge_create_trace(config, &state.client, &trace).await;
+        }
         bridge_create_span(
             config,
             &state.client,
@@ -332,95 +824,76 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
         .await;
     }
 
-    tracing::info!(%trace_id, %span_id, %span_name, %model, "proxying request");
+    tracing::info!(%trace_id, %span_id, %span_name, %model, target = %upstream.target, "proxying request");
 
     // Build target URL and request
-    let target_url = format!("{}{}", state.target_url, path);
-    let mut target_req = state.client.request(method, &target_url);
+    let target_url = format!("{}{}", upstream.target, path);
+    let mut forwarded = HeaderMap::new();
     for (name, value) in parts.headers.iter() {
-        if name != "host" {
-            target_req = target_req.header(name, value);
+        // A route's own key replaces whatever credentials the client sent
+        let replaced =
+            upstream.api_key.is_some() && (name == "authorization" || name == "x-api-key");
+        if name != "host" && !CallContext::is_context_header(name.as_str()) && !replaced {
+            forwarded.append(name, value.clone());
+        }
+    }
+    if let Some((name, value)) = upstream.auth_header() {
+        if let Ok(value) = value.parse() {
+            forwarded.insert(name, value);
         }
     }
+    state.request_headers.apply(&mut forwarded);
+    let target_req = state
+        .client
+        .request(method, &target_url)
+        .headers(forwarded)
+        .body(body_bytes.to_vec());
 
-    let result = target_req.body(body_bytes.to_vec()).send().await;
+    let sent_at = Instant::now();
+    let result = transport::send(target_req, state.connect_retries, &state.timeouts).await;
+    metrics::global().record_upstream(provider.as_deref().unwrap_or("unknown"), sent_at.elapsed());
+
+    let call = ProxiedCall {
+        trace_id,
+        span_id,
+        model,
+        provider,
+        shape,
+        capture,
+        input_preview,
+        tags: trace.tags,
+    };
 
     match result {
         Ok(response) => {
             let status = response.status();
-            let headers = response.headers().clone();
+            let mut headers = response.headers().clone();
+            state.response_headers.apply(&mut headers);
+            if let Ok(value) = call.span_id.to_string().parse() {
+                headers.insert(SPAN_ID_HEADER, value);
+            }
+
+            if is_streaming(&headers) {
+                return stream_response(state, call, response, status, headers);
+            }
 
             match response.bytes().await {
                 Ok(resp_bytes) => {
                     let resp_json = serde_json::from_slice::<Value>(&resp_bytes).ok();
 
                     // Extract tokens
-                    let (input_tokens, output_tokens) = resp_json
+                    let tokens = resp_json
                         .as_ref()
-                        .map(|j| extract_tokens(j, provider.as_deref()))
+                        .map(|j| extract_tokens(j, call.token_dialect()))
                         .unwrap_or((None, None));
+                    let resp_json = resp_json.map(|json| {
+                        call.shape
+                            .and_then(|s| s.normalize_response(&json))
+                            .and_then(|n| serde_json::to_value(n).ok())
+                            .unwrap_or(json)
+                    });
 
-                    // Build output payload
-                    let output_payload = match &state.capture_mode {
-                        CaptureMode::Off => None,
-                        CaptureMode::Preview(_) => resp_json.as_ref().map(|j| {
-                            serde_json::json!({
-                                "preview": preview_string(&j.to_string(), 500)
-                            })
-                        }),
-                        CaptureMode::Full => resp_json.clone(),
-                    };
-
-                    // Build output preview for the updated kind
-                    let output_preview = match &state.capture_mode {
-                        CaptureMode::Off => None,
-                        CaptureMode::Preview(max) => resp_json
-                            .as_ref()
-                            .map(|j| preview_string(&j.to_string(), *max)),
-                        CaptureMode::Full => resp_json
-                            .as_ref()
-                            .map(|j| j.to_string()),
-                    };
-
-                    // Build updated SpanKind with actual token counts + estimated cost
-                    let updated_kind = SpanKind::LlmCall {
-                        model: model.clone(),
-                        provider: provider.clone(),
-                        input_tokens,
-                        output_tokens,
-                        cost: None,
-                        input_preview: input_preview.clone(),
-                        output_preview,
-                    }.with_estimated_cost();
-
-                    {
-                        let mut store = state.store.write().await;
-                        if status.is_success() {
-                            if let Err(e) = store
-                                .complete_span_with_kind(span_id, updated_kind, output_payload.clone())
-                                .await
-                            {
-                                tracing::error!(%span_id, "failed to complete proxy span: {e}");
-                            }
-                        } else {
-                            if let Err(e) = store
-                                .fail_span(span_id, format!("HTTP {}", status))
-                                .await
-                            {
-                                tracing::error!(%span_id, "failed to fail proxy span: {e}");
-                            }
-                        }
-                    }
-
-                    if let Some(config) = &state.encore_bridge {
-                        if status.is_success() {
-                            bridge_complete_span(config, &state.client, span_id, output_payload.clone()).await;
-                        } else {
-                            bridge_fail_span(config, &state.client, span_id, format!("HTTP {}", status)).await;
-                        }
-                    }
-
-                    tracing::info!(%span_id, %status, ?input_tokens, ?output_tokens, "request completed");
+                    record_response(&state, &call, status, resp_json, tokens).await;
 
                     let mut builder = Response::builder().status(status);
                     for (name, value) in headers.iter() {
@@ -429,12 +902,7 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
                     builder.body(Body::from(resp_bytes)).unwrap()
                 }
                 Err(e) => {
-                    fail_span_helper(
-                        &state.store,
-                        span_id,
-                        &format!("Failed to read response: {}", e),
-                    )
-                    .await;
+                    fail_call(&state, &call, &format!("Failed to read response: {}", e)).await;
                     (
                         axum::http::StatusCode::BAD_GATEWAY,
                         "Failed to read response",
@@ -443,55 +911,294 @@ async fn proxy_handler(State(state): State<ProxyState>, req: Request<Body>) -> R
                 }
             }
         }
-        Err(e) => {
-            fail_span_helper(
-                &state.store,
-                span_id,
-                &format!("Request failed: {}", e),
-            )
-            .await;
+        Err(e @ SendError::FirstByteTimeout(_)) => {
+            fail_call(&state, &call, &format!("Upstream timed out: {}", e)).await;
             (
-                axum::http::StatusCode::BAD_GATEWAY,
-                format!("Proxy error: {}", e),
+                axum::http::StatusCode::GATEWAY_TIMEOUT,
+                format!("Proxy error: upstream sent {}", e),
             )
                 .into_response()
         }
+        Err(SendError::Request(e)) => {
+            let gateway_status = if e.is_timeout() {
+                axum::http::StatusCode::GATEWAY_TIMEOUT
+            } else {
+                axum::http::StatusCode::BAD_GATEWAY
+            };
+            fail_call(&state, &call, &format!("Request failed: {}", e)).await;
+            (gateway_status, format!("Proxy error: {}", e)).into_response()
+        }
+    }
+}
+
+/// 429 for a call in scope of a spent budget, retryable once it resets.
+fn budget_exceeded(over: &BudgetStatus) -> Response {
+    let retry_after = (over.resets_at - chrono::Utc::now()).num_seconds().max(1);
+    let body = serde_json::json!({
+        "error": format!("budget '{}' exceeded", over.budget.name),
+        "budget_id": over.budget.id,
+        "resets_at": over.resets_at,
+    });
+    (
+        axum::http::StatusCode::TOO_MANY_REQUESTS,
+        [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+        axum::Json(body),
+    )
+        .into_response()
+}
+
+/// Relay a streamed response to the client as it arrives, publishing text
+/// deltas as `SpanStreaming` events and completing the span once the
+/// upstream body ends.
+fn stream_response(
+    state: ProxyState,
+    call: ProxiedCall,
+    mut response: reqwest::Response,
+    status: StatusCode,
+    headers: HeaderMap,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut tap = StreamTap::new(call.token_dialect().map(str::to_string), &call.capture);
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    if let Some(delta) = tap.feed(&chunk) {
+                        publish_delta(&state, call.span_id, delta);
+                    }
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        fail_call(&state, &call, "Client disconnected mid-stream").await;
+                        return;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let error = format!("Failed to read response stream: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    fail_call(&state, &call, &error).await;
+                    return;
+                }
+            }
+        }
+        if let Some(delta) = tap.finish() {
+            publish_delta(&state, call.span_id, delta);
+        }
+        let output = tap.output();
+        record_response(&state, &call, status, Some(output), tap.tokens).await;
+    });
+
+    let mut builder = Response::builder().status(status);
+    for (name, value) in headers.iter() {
+        builder = builder.header(name, value);
+    }
+    builder.body(Body::from_stream(ReceiverStream::new(rx))).unwrap()
+}
+
+fn publish_delta(state: &ProxyState, span_id: trace::SpanId, delta: String) {
+    if let Some(tx) = &state.events_tx {
+        // No receivers is fine; nobody is watching this span live
+        let _ = tx.send(SystemEvent::SpanStreaming { span_id, delta });
+    }
+}
+
+/// One `ToolCall` span, a child of the call's span, per tool call in the
+/// normalized `output`.
+fn tool_call_spans(call: &ProxiedCall, output: &Value) -> Vec<Span> {
+    let Ok(response) = NormalizedResponse::deserialize(output) else {
+        return Vec::new();
+    };
+    response
+        .message
+        .tool_calls
+        .into_iter()
+        .map(|tool_call| {
+            let kind = SpanKind::ToolCall {
+                arguments: call.capture.payload(&tool_call.arguments),
+                tool_name: tool_call.name.clone(),
+                result_preview: None,
+            };
+            SpanBuilder::new(call.trace_id, tool_call.name, kind)
+                .parent(call.span_id)
+                .build()
+        })
+        .collect()
+}
+
+async fn record_tool_calls(state: &ProxyState, call: &ProxiedCall, output: &Value) {
+    for span in tool_call_spans(call, output) {
+        let span_id = span.id();
+        let recorded = match state.store.insert(span).await {
+            Ok(_) => state.store.complete_span(span_id, None).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = recorded {
+            tracing::error!(%span_id, "failed to record tool call span: {e}");
+        }
     }
 }
 
-async fn fail_span_helper(store: &SharedStore, span_id: trace::SpanId, error: &str) {
-    let mut w = store.write().await;
-    if let Err(e) = w.fail_span(span_id, error).await {
+/// Complete (or fail, for non-2xx responses) the span for a finished call.
+async fn record_response(
+    state: &ProxyState,
+    call: &ProxiedCall,
+    status: StatusCode,
+    resp_json: Option<Value>,
+    (input_tokens, output_tokens): (Option<u64>, Option<u64>),
+) {
+    let span_id = call.span_id;
+
+    let output_payload = resp_json.as_ref().and_then(|j| call.capture.payload(j));
+    let output_preview = resp_json
+        .as_ref()
+        .and_then(|j| call.capture.preview(&j.to_string()));
+
+    // Build updated SpanKind with actual token counts + estimated cost
+    let updated_kind = SpanKind::LlmCall {
+        model: call.model.clone(),
+        provider: call.provider.clone(),
+        input_tokens,
+        output_tokens,
+        cost: None,
+        input_preview: call.input_preview.clone(),
+        output_preview,
+    }.with_cost_from(&*state.pricing.read().await);
+    let cost = updated_kind.cost();
+
+    if status.is_success() {
+        if let Err(e) = state
+            .store
+            .complete_span_with_kind(span_id, updated_kind, output_payload.clone())
+            .await
+        {
+            tracing::error!(%span_id, "failed to complete proxy span: {e}");
+        }
+    } else if let Err(e) = state
+        .store
+        .fail_span(span_id, format!("HTTP {}", status))
+        .await
+    {
+        tracing::error!(%span_id, "failed to fail proxy span: {e}");
+    }
+
+    if let Some(config) = &state.encore_bridge {
+        if status.is_success() {
+            bridge_complete_span(config, &state.client, span_id, output_payload.clone()).await;
+        } else {
+            bridge_fail_span(config, &state.client, span_id, format!("HTTP {}", status)).await;
+        }
+    }
+
+    if let (true, Some(output)) = (status.is_success(), &resp_json) {
+        record_tool_calls(state, call, output).await;
+    }
+    if let (true, Some(budgets), Some(cost)) = (status.is_success(), &state.budgets, cost) {
+        budgets
+            .record(PROXY_ORG, &call.model, &call.tags, cost)
+            .await;
+    }
+
+    tracing::info!(%span_id, %status, ?input_tokens, ?output_tokens, "request completed");
+    metrics::global().record_span_finished(!status.is_success());
+    apply_sampling(state, call).await;
+}
+
+async fn fail_call(state: &ProxyState, call: &ProxiedCall, error: &str) {
+    let span_id = call.span_id;
+    if let Err(e) = state.store.fail_span(span_id, error).await {
         tracing::error!(%span_id, "failed to record span failure: {e}");
     }
     tracing::warn!(%span_id, %error, "span failed");
+    metrics::global().record_span_finished(true);
+    apply_sampling(state, call).await;
 }
 
-pub fn router(store: SharedStore, target_url: String) -> Router {
+/// Delete a finished call's trace if head-based sampling drops its span.
+async fn apply_sampling(state: &ProxyState, call: &ProxiedCall) {
+    let Some(sampler) = &state.sampler else {
+        return;
+    };
+    let Some(span) = state.store.get(call.span_id) else {
+        return;
+    };
+    if !sampler.keep_span(&span) {
+        if let Err(e) = state.store.delete_trace(call.trace_id).await {
+            tracing::error!(trace_id = %call.trace_id, "failed to drop sampled-out trace: {e}");
+        }
+    }
+}
+
+/// What the proxy shares with an API running alongside it.
+#[derive(Clone, Default)]
+pub struct ApiLinks {
+    /// Streamed output is published here as it arrives.
+    pub events_tx: Option<broadcast::Sender<SystemEvent>>,
+    /// Budgets to enforce, and to add the cost of each call to.
+    pub budgets: Option<Arc<BudgetTracker>>,
+}
+
+pub fn router(
+    store: SharedStore,
+    config: &ProxyConfig,
+    capture_mode: SharedCaptureMode,
+    pricing: Arc<RwLock<PricingTable>>,
+    links: ApiLinks,
+) -> Router {
     let state = ProxyState {
         store,
-        target_url,
-        client: reqwest::Client::new(),
-        capture_mode: CaptureMode::default(),
+        routes: Arc::new(RouteTable::new(config.target.clone(), &config.routes)),
+        client: transport::build_client(&config.timeouts),
+        timeouts: config.timeouts.clone(),
+        connect_retries: config.connect_retries,
+        request_headers: Arc::new(HeaderEdits::new(&config.request_headers)),
+        response_headers: Arc::new(HeaderEdits::new(&config.response_headers)),
+        capture_mode,
+        pricing,
         encore_bridge: EncoreBridgeConfig::from_env(),
+        events_tx: links.events_tx,
+        sampler: Sampler::new(config.sampling.clone()),
+        budgets: links.budgets,
+        git: Arc::new(GitInfo::from_current_dir()),
     };
 
     Router::new().fallback(proxy_handler).with_state(state)
 }
 
 pub async fn serve(store: SharedStore, addr: &str, target_url: &str) -> std::io::Result<()> {
-    serve_with_shutdown(store, addr, target_url, std::future::pending()).await
+    let config = ProxyConfig {
+        target: target_url.to_string(),
+        ..Default::default()
+    };
+    let capture_mode = SharedCaptureMode::new(CaptureMode::from_config(&config.capture_mode));
+    serve_with_shutdown(
+        store,
+        addr,
+        &config,
+        capture_mode,
+        Arc::new(RwLock::new(PricingTable::default())),
+        ApiLinks::default(),
+        std::future::pending(),
+    )
+    .await
 }
 
 pub async fn serve_with_shutdown(
     store: SharedStore,
     addr: &str,
-    target_url: &str,
+    config: &ProxyConfig,
+    capture_mode: SharedCaptureMode,
+    pricing: Arc<RwLock<PricingTable>>,
+    links: ApiLinks,
     shutdown: impl std::future::Future<Output = ()> + Send + 'static,
 ) -> std::io::Result<()> {
-    let app = router(store, target_url.to_string());
+    let app = router(store, config, capture_mode, pricing, links);
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    tracing::info!("proxy listening on {} -> {}", addr, target_url);
+    tracing::info!(
+        routes = config.routes.len(),
+        "proxy listening on {} -> {}",
+        addr,
+        config.target
+    );
     axum::serve(listener, app)
         .with_graceful_shutdown(shutdown)
         .await
@@ -0,0 +1,812 @@
+//! Provider request shapes and the common message format spans store.
+//!
+//! The proxy recognizes OpenAI chat completions, Anthropic messages, and
+//! Ollama chat/generate calls by path, and records their input and output as
+//! plain `{role, content, tool_calls}` messages whichever provider served
+//! them. Tool calls (OpenAI `tool_calls`/`function_call`, Anthropic
+//! `tool_use`, Ollama `tool_calls`) become structured `ToolCall`s with parsed
+//! arguments.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A known LLM API, recognized from the request path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiShape {
+    /// `/v1/chat/completions`, also served by OpenAI-compatible backends.
+    OpenAiChat,
+    /// `/v1/messages`
+    AnthropicMessages,
+    /// `/api/chat`
+    OllamaChat,
+    /// `/api/generate`
+    OllamaGenerate,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    /// Text parts joined with newlines; other parts become placeholders
+    /// like `[image]`.
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// For `tool` messages, the call this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    /// Parsed JSON when the provider sent a JSON string.
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments.
+    #[serde(default)]
+    pub parameters: Value,
+}
+
+/// A request as stored in a span's `input`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedRequest {
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+    /// Remaining request fields (temperature, max_tokens, ...) as sent.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+}
+
+/// A completion as stored in a span's `output`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedResponse {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+}
+
+impl NormalizedResponse {
+    pub fn assistant(
+        content: String,
+        tool_calls: Vec<ToolCall>,
+        finish_reason: Option<String>,
+    ) -> Self {
+        Self {
+            message: Message {
+                role: "assistant".to_string(),
+                content,
+                tool_calls,
+                tool_call_id: None,
+            },
+            finish_reason,
+        }
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+/// Request fields other than `skip`, for `NormalizedRequest::params`.
+fn params_except(body: &Value, skip: &[&str]) -> Map<String, Value> {
+    body.as_object()
+        .map(|fields| {
+            fields
+                .iter()
+                .filter(|(k, _)| !skip.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Text of a message content: a string, or the text parts of a content
+/// array.
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") | None => part
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .or_else(|| part.as_str().map(str::to_string)),
+                Some("image" | "image_url" | "input_image") => Some("[image]".to_string()),
+                Some("tool_use" | "tool_result") => None,
+                Some(other) => Some(format!("[{other}]")),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// OpenAI sends arguments as a JSON string; Ollama as an object.
+fn parse_arguments(arguments: Option<&Value>) -> Value {
+    match arguments {
+        Some(Value::String(s)) if s.trim().is_empty() => Value::Object(Map::new()),
+        Some(Value::String(s)) => {
+            serde_json::from_str(s).unwrap_or_else(|_| Value::String(s.clone()))
+        }
+        Some(v) => v.clone(),
+        None => Value::Object(Map::new()),
+    }
+}
+
+/// A function-style tool call (`{id, function: {name, arguments}}`).
+fn function_call(call: &Value) -> Option<ToolCall> {
+    let function = call.get("function").unwrap_or(call);
+    Some(ToolCall {
+        id: str_field(call, "id"),
+        name: str_field(function, "name")?,
+        arguments: parse_arguments(function.get("arguments")),
+    })
+}
+
+/// An OpenAI or Ollama chat message.
+fn chat_message(m: &Value) -> Message {
+    let mut tool_calls: Vec<ToolCall> = m
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(function_call)
+        .collect();
+    // Legacy single `function_call`
+    tool_calls.extend(m.get("function_call").and_then(function_call));
+    Message {
+        role: str_field(m, "role").unwrap_or_else(|| "user".to_string()),
+        content: m.get("content").map(text_of).unwrap_or_default(),
+        tool_calls,
+        tool_call_id: str_field(m, "tool_call_id"),
+    }
+}
+
+/// OpenAI/Ollama `tools` (`{type: function, function: {...}}`) or legacy
+/// OpenAI `functions`.
+fn function_tools(body: &Value) -> Vec<ToolDefinition> {
+    let tools = body
+        .get("tools")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    let functions = body
+        .get("functions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten();
+    tools
+        .chain(functions)
+        .filter_map(|tool| {
+            let function = tool.get("function").unwrap_or(tool);
+            Some(ToolDefinition {
+                name: str_field(function, "name")?,
+                description: str_field(function, "description"),
+                parameters: function.get("parameters").cloned().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Anthropic content blocks: text into the message, `tool_use` into its
+/// tool calls, and each `tool_result` into a separate `tool` message placed
+/// before it.
+fn anthropic_messages(role: &str, content: &Value) -> Vec<Message> {
+    let Some(blocks) = content.as_array() else {
+        return vec![Message {
+            role: role.to_string(),
+            content: text_of(content),
+            ..Default::default()
+        }];
+    };
+    let mut out: Vec<Message> = blocks
+        .iter()
+        .filter(|b| b.get("type").and_then(Value::as_str) == Some("tool_result"))
+        .map(|b| Message {
+            role: "tool".to_string(),
+            content: b.get("content").map(text_of).unwrap_or_default(),
+            tool_calls: Vec::new(),
+            tool_call_id: str_field(b, "tool_use_id"),
+        })
+        .collect();
+    let tool_calls: Vec<ToolCall> = blocks
+        .iter()
+        .filter(|b| b.get("type").and_then(Value::as_str) == Some("tool_use"))
+        .filter_map(|b| {
+            Some(ToolCall {
+                id: str_field(b, "id"),
+                name: str_field(b, "name")?,
+                arguments: b.get("input").cloned().unwrap_or_default(),
+            })
+        })
+        .collect();
+    let text = text_of(content);
+    if !text.is_empty() || !tool_calls.is_empty() || out.is_empty() {
+        out.push(Message {
+            role: role.to_string(),
+            content: text,
+            tool_calls,
+            tool_call_id: None,
+        });
+    }
+    out
+}
+
+impl ApiShape {
+    /// The API a request path calls, allowing a gateway prefix before it.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.split('?').next().unwrap_or(path).trim_end_matches('/');
+        if path.ends_with("/chat/completions") {
+            Some(Self::OpenAiChat)
+        } else if path.ends_with("/v1/messages") {
+            Some(Self::AnthropicMessages)
+        } else if path.ends_with("/api/chat") {
+            Some(Self::OllamaChat)
+        } else if path.ends_with("/api/generate") {
+            Some(Self::OllamaGenerate)
+        } else {
+            None
+        }
+    }
+
+    /// Provider whose wire format this is (token usage fields included).
+    pub fn provider(self) -> &'static str {
+        match self {
+            Self::OpenAiChat => "openai",
+            Self::AnthropicMessages => "anthropic",
+            Self::OllamaChat | Self::OllamaGenerate => "ollama",
+        }
+    }
+
+    /// `None` when the body is not a request of this shape.
+    pub fn normalize_request(self, body: &Value) -> Option<NormalizedRequest> {
+        match self {
+            Self::OpenAiChat | Self::OllamaChat => {
+                let messages = body.get("messages")?.as_array()?;
+                Some(NormalizedRequest {
+                    messages: messages.iter().map(chat_message).collect(),
+                    tools: function_tools(body),
+                    params: params_except(
+                        body,
+                        &["model", "messages", "tools", "functions", "stream"],
+                    ),
+                })
+            }
+            Self::AnthropicMessages => {
+                let messages = body.get("messages")?.as_array()?;
+                let mut normalized = Vec::new();
+                if let Some(system) = body.get("system").map(text_of).filter(|s| !s.is_empty()) {
+                    normalized.push(Message {
+                        role: "system".to_string(),
+                        content: system,
+                        ..Default::default()
+                    });
+                }
+                for m in messages {
+                    let role = m.get("role").and_then(Value::as_str).unwrap_or("user");
+                    normalized.extend(anthropic_messages(
+                        role,
+                        m.get("content").unwrap_or(&Value::Null),
+                    ));
+                }
+                let tools = body
+                    .get("tools")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|tool| {
+                        Some(ToolDefinition {
+                            name: str_field(tool, "name")?,
+                            description: str_field(tool, "description"),
+                            parameters: tool.get("input_schema").cloned().unwrap_or_default(),
+                        })
+                    })
+                    .collect();
+                Some(NormalizedRequest {
+                    messages: normalized,
+                    tools,
+                    params: params_except(
+                        body,
+                        &["model", "messages", "system", "tools", "stream"],
+                    ),
+                })
+            }
+            Self::OllamaGenerate => {
+                let prompt = body.get("prompt")?.as_str()?;
+                let mut messages = Vec::new();
+                if let Some(system) = str_field(body, "system").filter(|s| !s.is_empty()) {
+                    messages.push(Message {
+                        role: "system".to_string(),
+                        content: system,
+                        ..Default::default()
+                    });
+                }
+                messages.push(Message {
+                    role: "user".to_string(),
+                    content: prompt.to_string(),
+                    ..Default::default()
+                });
+                Some(NormalizedRequest {
+                    messages,
+                    tools: Vec::new(),
+                    // `context` and `images` are large and not useful to read
+                    params: params_except(
+                        body,
+                        &["model", "prompt", "system", "stream", "context", "images"],
+                    ),
+                })
+            }
+        }
+    }
+
+    /// `None` when the body is not a completion of this shape (e.g. an
+    /// error response).
+    pub fn normalize_response(self, body: &Value) -> Option<NormalizedResponse> {
+        match self {
+            Self::OpenAiChat => {
+                let choice = body.get("choices")?.get(0)?;
+                let message = chat_message(choice.get("message")?);
+                Some(NormalizedResponse {
+                    message,
+                    finish_reason: str_field(choice, "finish_reason"),
+                })
+            }
+            Self::AnthropicMessages => {
+                let content = body.get("content")?;
+                let message = anthropic_messages("assistant", content).pop()?;
+                Some(NormalizedResponse {
+                    message,
+                    finish_reason: str_field(body, "stop_reason"),
+                })
+            }
+            Self::OllamaChat => Some(NormalizedResponse {
+                message: chat_message(body.get("message")?),
+                finish_reason: str_field(body, "done_reason"),
+            }),
+            Self::OllamaGenerate => Some(NormalizedResponse::assistant(
+                body.get("response")?.as_str()?.to_string(),
+                Vec::new(),
+                str_field(body, "done_reason"),
+            )),
+        }
+    }
+
+    /// A non-streaming request of this shape for `model` carrying `req`,
+    /// the inverse of `normalize_request` (placeholders like `[image]` stay
+    /// as text).
+    pub fn request_body(self, model: &str, req: &NormalizedRequest) -> Value {
+        let mut body = req.params.clone();
+        match self {
+            Self::OpenAiChat | Self::OllamaChat => {
+                let ollama = self == Self::OllamaChat;
+                let messages: Vec<Value> = req
+                    .messages
+                    .iter()
+                    .map(|m| {
+                        let mut message = serde_json::json!({ "role": m.role, "content": m.content });
+                        if !m.tool_calls.is_empty() {
+                            let calls: Vec<Value> = m
+                                .tool_calls
+                                .iter()
+                                .map(|c| {
+                                    if ollama {
+                                        serde_json::json!({ "function": { "name": c.name, "arguments": c.arguments } })
+                                    } else {
+                                        let arguments = match &c.arguments {
+                                            Value::String(s) => s.clone(),
+                                            other => other.to_string(),
+                                        };
+                                        serde_json::json!({
+                                            "id": c.id,
+                                            "type": "function",
+                                            "function": { "name": c.name, "arguments": arguments },
+                                        })
+                                    }
+                                })
+                                .collect();
+                            message["tool_calls"] = Value::Array(calls);
+                        }
+                        if let Some(id) = &m.tool_call_id {
+                            message["tool_call_id"] = Value::String(id.clone());
+                        }
+                        message
+                    })
+                    .collect();
+                body.insert("messages".into(), Value::Array(messages));
+                if !req.tools.is_empty() {
+                    let tools = req
+                        .tools
+                        .iter()
+                        .map(|t| {
+                            serde_json::json!({
+                                "type": "function",
+                                "function": {
+                                    "name": t.name,
+                                    "description": t.description,
+                                    "parameters": t.parameters,
+                                },
+                            })
+                        })
+                        .collect();
+                    body.insert("tools".into(), Value::Array(tools));
+                }
+            }
+            Self::AnthropicMessages => {
+                let system: Vec<&str> = req
+                    .messages
+                    .iter()
+                    .filter(|m| m.role == "system")
+                    .map(|m| m.content.as_str())
+                    .collect();
+                if !system.is_empty() {
+                    body.insert("system".into(), Value::String(system.join("\n")));
+                }
+                let messages: Vec<Value> = req
+                    .messages
+                    .iter()
+                    .filter(|m| m.role != "system")
+                    .map(|m| {
+                        if let Some(id) = &m.tool_call_id {
+                            return serde_json::json!({
+                                "role": "user",
+                                "content": [{ "type": "tool_result", "tool_use_id": id, "content": m.content }],
+                            });
+                        }
+                        let mut blocks = Vec::new();
+                        if !m.content.is_empty() {
+                            blocks.push(serde_json::json!({ "type": "text", "text": m.content }));
+                        }
+                        for c in &m.tool_calls {
+                            blocks.push(serde_json::json!({
+                                "type": "tool_use",
+                                "id": c.id,
+                                "name": c.name,
+                                "input": c.arguments,
+                            }));
+                        }
+                        serde_json::json!({ "role": m.role, "content": blocks })
+                    })
+                    .collect();
+                body.insert("messages".into(), Value::Array(messages));
+                if !req.tools.is_empty() {
+                    let tools = req
+                        .tools
+                        .iter()
+                        .map(|t| {
+                            serde_json::json!({
+                                "name": t.name,
+                                "description": t.description,
+                                "input_schema": t.parameters,
+                            })
+                        })
+                        .collect();
+                    body.insert("tools".into(), Value::Array(tools));
+                }
+                // Required by the API
+                body.entry("max_tokens").or_insert(Value::from(1024));
+            }
+            Self::OllamaGenerate => {
+                if let Some(system) = req.messages.iter().find(|m| m.role == "system") {
+                    body.insert("system".into(), Value::String(system.content.clone()));
+                }
+                let prompt = req
+                    .messages
+                    .iter()
+                    .rev()
+                    .find(|m| m.role == "user")
+                    .map(|m| m.content.clone())
+                    .unwrap_or_default();
+                body.insert("prompt".into(), Value::String(prompt));
+            }
+        }
+        body.insert("model".into(), Value::String(model.to_string()));
+        body.insert("stream".into(), Value::Bool(false));
+        Value::Object(body)
+    }
+}
+
+#[derive(Debug, Default)]
+struct PartialCall {
+    id: Option<String>,
+    name: String,
+    /// Argument JSON received so far.
+    arguments: String,
+    /// Arguments that arrived whole (Ollama).
+    parsed: Option<Value>,
+}
+
+/// Tool calls assembled from the chunks of a streamed completion.
+#[derive(Debug, Default)]
+pub struct StreamedToolCalls {
+    /// By the provider's index: OpenAI `tool_calls[].index`, Anthropic
+    /// content block index, or arrival order for Ollama.
+    calls: BTreeMap<u64, PartialCall>,
+    pub finish_reason: Option<String>,
+}
+
+impl StreamedToolCalls {
+    /// Take what one streamed event adds: OpenAI `delta.tool_calls`,
+    /// Anthropic `tool_use` blocks and `input_json_delta`s, or Ollama
+    /// `message.tool_calls`.
+    pub fn feed(&mut self, event: &Value) {
+        let choice = event.get("choices").and_then(|c| c.get(0));
+        if let Some(reason) = choice
+            .and_then(|c| str_field(c, "finish_reason"))
+            .or_else(|| event.get("delta").and_then(|d| str_field(d, "stop_reason")))
+            .or_else(|| str_field(event, "done_reason"))
+        {
+            self.finish_reason = Some(reason);
+        }
+
+        let openai = choice
+            .and_then(|c| c.get("delta"))
+            .and_then(|d| d.get("tool_calls"))
+            .and_then(Value::as_array);
+        for call in openai.into_iter().flatten() {
+            let index = call.get("index").and_then(Value::as_u64).unwrap_or(0);
+            let partial = self.calls.entry(index).or_default();
+            if let Some(id) = str_field(call, "id") {
+                partial.id = Some(id);
+            }
+            if let Some(function) = call.get("function") {
+                if let Some(name) = str_field(function, "name") {
+                    partial.name.push_str(&name);
+                }
+                if let Some(args) = function.get("arguments").and_then(Value::as_str) {
+                    partial.arguments.push_str(args);
+                }
+            }
+        }
+
+        let index = event.get("index").and_then(Value::as_u64).unwrap_or(0);
+        match event.get("type").and_then(Value::as_str) {
+            Some("content_block_start") => {
+                let block = event.get("content_block");
+                if block.and_then(|b| b.get("type")).and_then(Value::as_str) == Some("tool_use") {
+                    let block = block.unwrap_or(&Value::Null);
+                    self.calls.insert(
+                        index,
+                        PartialCall {
+                            id: str_field(block, "id"),
+                            name: str_field(block, "name").unwrap_or_default(),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = event.get("delta");
+                if let Some(json) = delta
+                    .and_then(|d| d.get("partial_json"))
+                    .and_then(Value::as_str)
+                {
+                    if let Some(partial) = self.calls.get_mut(&index) {
+                        partial.arguments.push_str(json);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let ollama = event
+            .get("message")
+            .and_then(|m| m.get("tool_calls"))
+            .and_then(Value::as_array);
+        for call in ollama.into_iter().flatten().filter_map(function_call) {
+            let next = self.calls.keys().next_back().map_or(0, |k| k + 1);
+            self.calls.insert(
+                next,
+                PartialCall {
+                    id: call.id,
+                    name: call.name,
+                    arguments: String::new(),
+                    parsed: Some(call.arguments),
+                },
+            );
+        }
+    }
+
+    pub fn calls(&self) -> Vec<ToolCall> {
+        self.calls
+            .values()
+            .filter(|p| !p.name.is_empty())
+            .map(|p| ToolCall {
+                id: p.id.clone(),
+                name: p.name.clone(),
+                arguments: p
+                    .parsed
+                    .clone()
+                    .unwrap_or_else(|| parse_arguments(Some(&Value::String(p.arguments.clone())))),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn request_body_round_trips() {
+        let original = json!({
+            "model": "claude-3-5-sonnet",
+            "system": "be brief",
+            "max_tokens": 200,
+            "messages": [
+                { "role": "user", "content": "weather?" },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "t1", "name": "weather", "input": { "city": "Oslo" } },
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "content": "rain" },
+                ] },
+            ],
+        });
+        let shape = ApiShape::AnthropicMessages;
+        let normalized = shape.normalize_request(&original).unwrap();
+        let body = shape.request_body("claude-3-5-haiku", &normalized);
+        assert_eq!(body["model"], "claude-3-5-haiku");
+        assert_eq!(body["stream"], false);
+        assert_eq!(shape.normalize_request(&body).unwrap(), normalized);
+
+        let openai = ApiShape::OpenAiChat.request_body("gpt-4o", &normalized);
+        assert_eq!(
+            openai["messages"][0],
+            json!({ "role": "system", "content": "be brief" })
+        );
+        assert_eq!(
+            openai["messages"][2]["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":\"Oslo\"}"
+        );
+        assert_eq!(openai["messages"][3]["tool_call_id"], "t1");
+    }
+
+    #[test]
+    fn shapes_from_paths() {
+        assert_eq!(
+            ApiShape::from_path("/v1/chat/completions"),
+            Some(ApiShape::OpenAiChat)
+        );
+        assert_eq!(
+            ApiShape::from_path("/openai/v1/chat/completions?x=1"),
+            Some(ApiShape::OpenAiChat)
+        );
+        assert_eq!(
+            ApiShape::from_path("/v1/messages"),
+            Some(ApiShape::AnthropicMessages)
+        );
+        assert_eq!(
+            ApiShape::from_path("/api/generate"),
+            Some(ApiShape::OllamaGenerate)
+        );
+        assert_eq!(ApiShape::from_path("/v1/embeddings"), None);
+    }
+
+    #[test]
+    fn openai_tool_calls() {
+        let req = json!({
+            "model": "gpt-4o",
+            "temperature": 0.2,
+            "messages": [
+                { "role": "user", "content": [{ "type": "text", "text": "Weather?" }, { "type": "image_url", "image_url": {} }] },
+                { "role": "assistant", "content": null, "tool_calls": [
+                    { "id": "call_1", "type": "function", "function": { "name": "weather", "arguments": "{\"city\":\"Oslo\"}" } }
+                ] },
+                { "role": "tool", "tool_call_id": "call_1", "content": "4C" }
+            ],
+            "tools": [{ "type": "function", "function": { "name": "weather", "parameters": { "type": "object" } } }]
+        });
+        let n = ApiShape::OpenAiChat.normalize_request(&req).unwrap();
+        assert_eq!(n.messages[0].content, "Weather?\n[image]");
+        assert_eq!(
+            n.messages[1].tool_calls[0].arguments,
+            json!({ "city": "Oslo" })
+        );
+        assert_eq!(n.messages[2].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(n.tools[0].name, "weather");
+        assert_eq!(n.params.keys().collect::<Vec<_>>(), ["temperature"]);
+
+        let resp = json!({ "choices": [{ "finish_reason": "tool_calls", "message": {
+            "role": "assistant", "content": null,
+            "tool_calls": [{ "id": "call_2", "function": { "name": "weather", "arguments": "{}" } }]
+        } }] });
+        let out = ApiShape::OpenAiChat.normalize_response(&resp).unwrap();
+        assert_eq!(out.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(out.message.tool_calls[0].id.as_deref(), Some("call_2"));
+    }
+
+    #[test]
+    fn anthropic_blocks() {
+        let req = json!({
+            "model": "claude-sonnet",
+            "max_tokens": 100,
+            "system": [{ "type": "text", "text": "Be brief." }],
+            "messages": [
+                { "role": "user", "content": "Weather in Oslo?" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "Checking." },
+                    { "type": "tool_use", "id": "tu_1", "name": "weather", "input": { "city": "Oslo" } }
+                ] },
+                { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "tu_1", "content": "4C" }] }
+            ],
+            "tools": [{ "name": "weather", "input_schema": { "type": "object" } }]
+        });
+        let n = ApiShape::AnthropicMessages.normalize_request(&req).unwrap();
+        let roles: Vec<&str> = n.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool"]);
+        assert_eq!(n.messages[0].content, "Be brief.");
+        assert_eq!(
+            n.messages[2].tool_calls[0].arguments,
+            json!({ "city": "Oslo" })
+        );
+        assert_eq!(n.messages[3].tool_call_id.as_deref(), Some("tu_1"));
+        assert_eq!(n.tools[0].parameters, json!({ "type": "object" }));
+
+        let resp =
+            json!({ "stop_reason": "end_turn", "content": [{ "type": "text", "text": "4C." }] });
+        let out = ApiShape::AnthropicMessages
+            .normalize_response(&resp)
+            .unwrap();
+        assert_eq!(out.message.content, "4C.");
+        assert!(ApiShape::AnthropicMessages
+            .normalize_response(&json!({ "type": "error" }))
+            .is_none());
+    }
+
+    #[test]
+    fn ollama_generate() {
+        let req =
+            json!({ "model": "llama3", "prompt": "Hi", "system": "Be nice", "context": [1, 2] });
+        let n = ApiShape::OllamaGenerate.normalize_request(&req).unwrap();
+        assert_eq!(n.messages.len(), 2);
+        assert!(n.params.is_empty());
+        let out = ApiShape::OllamaGenerate
+            .normalize_response(&json!({ "response": "Hello", "done_reason": "stop" }))
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(out).unwrap(),
+            json!({ "role": "assistant", "content": "Hello", "finish_reason": "stop" })
+        );
+    }
+
+    #[test]
+    fn streamed_tool_calls() {
+        let mut openai = StreamedToolCalls::default();
+        openai.feed(&json!({ "choices": [{ "delta": { "tool_calls": [
+            { "index": 0, "id": "call_1", "function": { "name": "weather", "arguments": "{\"ci" } }
+        ] } }] }));
+        openai.feed(&json!({ "choices": [{ "delta": { "tool_calls": [
+            { "index": 0, "function": { "arguments": "ty\":\"Oslo\"}" } }
+        ] } }] }));
+        openai.feed(&json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }));
+        assert_eq!(openai.calls()[0].arguments, json!({ "city": "Oslo" }));
+        assert_eq!(openai.finish_reason.as_deref(), Some("tool_calls"));
+
+        let mut anthropic = StreamedToolCalls::default();
+        anthropic.feed(&json!({ "type": "content_block_start", "index": 1,
+            "content_block": { "type": "tool_use", "id": "tu_1", "name": "weather", "input": {} } }));
+        anthropic.feed(&json!({ "type": "content_block_delta", "index": 1,
+            "delta": { "type": "input_json_delta", "partial_json": "{\"city\": \"Oslo\"}" } }));
+        anthropic.feed(&json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" } }));
+        let calls = anthropic.calls();
+        assert_eq!(calls[0].id.as_deref(), Some("tu_1"));
+        assert_eq!(calls[0].arguments, json!({ "city": "Oslo" }));
+        assert_eq!(anthropic.finish_reason.as_deref(), Some("tool_use"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Model-based routing: one proxy port in front of several backends.
+
+use super::CaptureMode;
+use crate::config::ProxyRoute;
+
+/// Where a request goes and how to authenticate there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub target: String,
+    pub provider: Option<String>,
+    /// Injected in place of whatever credentials the client sent.
+    pub api_key: Option<String>,
+    /// Overrides the proxy's default capture mode.
+    pub capture_mode: Option<CaptureMode>,
+}
+
+impl Upstream {
+    /// Header carrying `api_key`: Anthropic uses `x-api-key`, the rest a
+    /// bearer token.
+    pub fn auth_header(&self) -> Option<(&'static str, String)> {
+        let key = self.api_key.as_deref()?;
+        Some(match self.provider.as_deref() {
+            Some("anthropic") => ("x-api-key", key.to_string()),
+            _ => ("authorization", format!("Bearer {key}")),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    patterns: Vec<String>,
+    upstream: Upstream,
+}
+
+/// Configured routes, tried in order before the default target.
+#[derive(Debug)]
+pub struct RouteTable {
+    routes: Vec<Route>,
+    default: Upstream,
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl RouteTable {
+    /// Resolve each route's API key (from `api_key_env` if set) now, so a
+    /// missing variable is reported at startup.
+    pub fn new(default_target: String, routes: &[ProxyRoute]) -> Self {
+        let routes = routes
+            .iter()
+            .map(|r| {
+                let api_key = r.api_key_env.as_deref().and_then(|var| {
+                    let key = std::env::var(var).ok().filter(|k| !k.is_empty());
+                    if key.is_none() {
+                        tracing::warn!(env = var, target = %r.target, "proxy route api key variable is not set");
+                    }
+                    key
+                });
+                Route {
+                    patterns: r.models.clone(),
+                    upstream: Upstream {
+                        target: r.target.trim_end_matches('/').to_string(),
+                        provider: r
+                            .provider
+                            .clone()
+                            .or_else(|| super::detect_provider(&r.target)),
+                        api_key: api_key.or_else(|| r.api_key.clone()),
+                        capture_mode: r.capture_mode.as_deref().map(CaptureMode::from_config),
+                    },
+                }
+            })
+            .collect();
+        Self {
+            routes,
+            default: Upstream {
+                provider: super::detect_provider(&default_target),
+                target: default_target,
+                api_key: None,
+                capture_mode: None,
+            },
+        }
+    }
+
+    /// The first route with a pattern matching `model`, else the default.
+    pub fn resolve(&self, model: Option<&str>) -> &Upstream {
+        model
+            .and_then(|model| {
+                self.routes
+                    .iter()
+                    .find(|r| r.patterns.iter().any(|p| glob_match(p, model)))
+            })
+            .map_or(&self.default, |r| &r.upstream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(models: &[&str], target: &str) -> ProxyRoute {
+        ProxyRoute {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            target: target.to_string(),
+            api_key: Some("sk-test".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("gpt-*", "gpt-4o"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("llama*", "llama3.1:8b"));
+        assert!(glob_match("*-mini", "gpt-4o-mini"));
+        assert!(glob_match("claude-*-sonnet*", "claude-3-5-sonnet-latest"));
+        assert!(glob_match("o1", "o1"));
+        assert!(!glob_match("o1", "o1-mini"));
+        assert!(!glob_match("gpt-*", "chatgpt-4o"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn resolves_first_matching_route() {
+        let table = RouteTable::new(
+            "http://localhost:11434".to_string(),
+            &[
+                route(&["gpt-*", "o1*"], "https://api.openai.com/"),
+                route(&["claude-*"], "https://api.anthropic.com"),
+            ],
+        );
+        let openai = table.resolve(Some("o1-mini"));
+        assert_eq!(openai.target, "https://api.openai.com");
+        assert_eq!(
+            openai.auth_header(),
+            Some(("authorization", "Bearer sk-test".to_string()))
+        );
+
+        let anthropic = table.resolve(Some("claude-sonnet-4"));
+        assert_eq!(anthropic.provider.as_deref(), Some("anthropic"));
+        assert_eq!(anthropic.auth_header().unwrap().0, "x-api-key");
+
+        let fallback = table.resolve(Some("llama3"));
+        assert_eq!(fallback.target, "http://localhost:11434");
+        assert_eq!(fallback.auth_header(), None);
+        assert_eq!(table.resolve(None).provider.as_deref(), Some("ollama"));
+    }
+}
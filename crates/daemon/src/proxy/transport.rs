@@ -0,0 +1,188 @@
+//! How the proxy talks to upstreams: client timeouts, retries when a
+//! connection cannot be opened, and configured header edits.
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use super::routes::glob_match;
+use crate::config::{HeaderRules, ProxyTimeouts};
+
+/// First wait between connect retries; doubles each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+fn millis(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+pub fn build_client(timeouts: &ProxyTimeouts) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder();
+    if let Some(connect) = millis(timeouts.connect_ms) {
+        builder = builder.connect_timeout(connect);
+    }
+    if let Some(total) = millis(timeouts.total_ms) {
+        builder = builder.timeout(total);
+    }
+    builder.build().unwrap_or_else(|e| {
+        tracing::warn!("invalid proxy client settings, using defaults: {e}");
+        reqwest::Client::new()
+    })
+}
+
+/// Why an upstream call produced no response.
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    #[error("no response within {0:?}")]
+    FirstByteTimeout(Duration),
+    #[error("{0}")]
+    Request(#[from] reqwest::Error),
+}
+
+/// Send `request`, retrying up to `retries` times while the connection
+/// cannot be opened (nothing reached the upstream, so a retry is safe).
+/// Waits at most `timeouts.first_byte_ms` for the response headers.
+pub async fn send(
+    mut request: reqwest::RequestBuilder,
+    retries: u32,
+    timeouts: &ProxyTimeouts,
+) -> Result<reqwest::Response, SendError> {
+    let first_byte = millis(timeouts.first_byte_ms);
+    let mut backoff = RETRY_BACKOFF;
+    for attempt in 1.. {
+        // Bodies are buffered bytes, so cloning only fails for streams
+        let retry = request.try_clone().filter(|_| attempt <= retries);
+        match (request_once(request, first_byte).await, retry) {
+            (Err(SendError::Request(e)), Some(next)) if e.is_connect() => {
+                tracing::warn!(attempt, "upstream connect failed, retrying: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                request = next;
+            }
+            (result, _) => return result,
+        }
+    }
+    unreachable!("retry loop only exits by returning")
+}
+
+async fn request_once(
+    request: reqwest::RequestBuilder,
+    first_byte: Option<Duration>,
+) -> Result<reqwest::Response, SendError> {
+    match first_byte {
+        Some(limit) => tokio::time::timeout(limit, request.send())
+            .await
+            .map_err(|_| SendError::FirstByteTimeout(limit))?
+            .map_err(SendError::from),
+        None => request.send().await.map_err(SendError::from),
+    }
+}
+
+/// `HeaderRules` with names parsed and variables expanded.
+#[derive(Debug, Default)]
+pub struct HeaderEdits {
+    set: Vec<(HeaderName, HeaderValue)>,
+    /// Lowercase name patterns.
+    remove: Vec<String>,
+}
+
+/// Replace each `${VAR}` with the variable's value (empty when unset).
+fn expand_env(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let var = &rest[start + 2..start + len];
+        out.push_str(&std::env::var(var).unwrap_or_else(|_| {
+            tracing::warn!(var, "proxy header references an unset variable");
+            String::new()
+        }));
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl HeaderEdits {
+    pub fn new(rules: &HeaderRules) -> Self {
+        let set = rules
+            .set
+            .iter()
+            .filter_map(|(name, value)| {
+                let parsed = HeaderName::try_from(name.as_str())
+                    .ok()
+                    .zip(HeaderValue::try_from(expand_env(value)).ok());
+                if parsed.is_none() {
+                    tracing::warn!(header = %name, "ignoring invalid proxy header rule");
+                }
+                parsed
+            })
+            .collect();
+        Self {
+            set,
+            remove: rules
+                .remove
+                .iter()
+                .map(|n| n.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if !self.remove.is_empty() {
+            let doomed: Vec<HeaderName> = headers
+                .keys()
+                .filter(|name| self.remove.iter().any(|p| glob_match(p, name.as_str())))
+                .cloned()
+                .collect();
+            for name in doomed {
+                headers.remove(name);
+            }
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_remove_by_prefix_then_set() {
+        std::env::set_var("TRACEWAY_TEST_PROXY_KEY", "sk-1");
+        let rules = HeaderRules {
+            set: [
+                (
+                    "Authorization".to_string(),
+                    "Bearer ${TRACEWAY_TEST_PROXY_KEY}".to_string(),
+                ),
+                ("bad header".to_string(), "x".to_string()),
+            ]
+            .into(),
+            remove: vec!["X-Internal-*".to_string(), "cookie".to_string()],
+        };
+        let edits = HeaderEdits::new(&rules);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-internal-user", "42".parse().unwrap());
+        headers.insert("cookie", "a=b".parse().unwrap());
+        headers.insert("authorization", "Bearer client".parse().unwrap());
+        headers.insert("x-request-id", "r1".parse().unwrap());
+        edits.apply(&mut headers);
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["authorization"], "Bearer sk-1");
+        assert_eq!(headers["x-request-id"], "r1");
+    }
+
+    #[test]
+    fn expands_variables() {
+        std::env::set_var("TRACEWAY_TEST_PROXY_ORG", "org-9");
+        assert_eq!(expand_env("a ${TRACEWAY_TEST_PROXY_ORG} b"), "a org-9 b");
+        assert_eq!(expand_env("no vars"), "no vars");
+        assert_eq!(expand_env("${UNCLOSED"), "${UNCLOSED");
+    }
+}
@@ -0,0 +1,240 @@
+//! `traceway tail`: follow a running daemon's event stream and print spans
+//! as they finish.
+
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+use storage::SpanFilter;
+use trace::{Span, SpanStatus};
+
+use crate::api::scorers::output_text;
+use crate::api::SystemEvent;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct TailArgs {
+    /// Only show spans matching this query, e.g. `model:gpt-4o duration:>2s`
+    #[arg(long, short = 'f')]
+    filter: Option<String>,
+
+    /// API base URL [default: the configured API address]
+    #[arg(long)]
+    url: Option<String>,
+
+    /// API key for a cloud or auth-enabled API (or set TRACEWAY_API_KEY)
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Characters of output to show per span (0 hides output)
+    #[arg(long, default_value = "80")]
+    width: usize,
+}
+
+/// Splits an SSE byte stream into the `data` of each event.
+#[derive(Default)]
+struct SseParser {
+    /// Bytes of an incomplete line; chunks can split UTF-8 characters.
+    buf: Vec<u8>,
+    data: String,
+    last_id: Option<String>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(std::mem::take(&mut self.data));
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                if !self.data.is_empty() {
+                    self.data.push('\n');
+                }
+                self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+            } else if let Some(id) = line.strip_prefix("id:") {
+                self.last_id = Some(id.trim().to_string());
+            }
+        }
+        events
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flat.chars().count() <= width {
+        return flat;
+    }
+    let mut cut: String = flat.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+fn format_duration(ms: i64) -> String {
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
+
+/// One line per span: time, status, name, model, duration, tokens, output.
+fn format_span(span: &Span, width: usize, color: bool) -> String {
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    };
+    let mut line = format!(
+        "{} {} {}",
+        paint("2", &span.started_at().format("%H:%M:%S").to_string()),
+        match span.status() {
+            SpanStatus::Failed { .. } => paint("31", "FAIL"),
+            _ => paint("32", " OK "),
+        },
+        paint("1", span.name()),
+    );
+    if let Some(model) = span.kind().model() {
+        line.push_str(&format!(" {}", paint("36", model)));
+    }
+    if let Some(ms) = span.duration_ms() {
+        line.push_str(&format!(" {}", format_duration(ms)));
+    }
+    match (span.kind().input_tokens(), span.kind().output_tokens()) {
+        (Some(i), Some(o)) => line.push_str(&format!(" {i}→{o} tok")),
+        (Some(t), None) | (None, Some(t)) => line.push_str(&format!(" {t} tok")),
+        (None, None) => {}
+    }
+    let detail = match span.status() {
+        SpanStatus::Failed { error } => Some(paint("31", &truncate(error, width.max(40)))),
+        _ if width == 0 => None,
+        _ => span
+            .output()
+            .map(output_text)
+            .filter(|text| !text.is_empty())
+            .map(|text| paint("2", &truncate(&text, width))),
+    };
+    if let Some(detail) = detail {
+        line.push_str("  ");
+        line.push_str(&detail);
+    }
+    line
+}
+
+/// Follow `{base_url}/api/events` until interrupted, reconnecting from the
+/// last event seen when the connection drops.
+pub async fn run(args: TailArgs, default_addr: &str) -> Result<(), String> {
+    let filter = match args.filter.as_deref() {
+        Some(query) => storage::parse_span_query(query).map_err(|e| e.to_string())?,
+        None => SpanFilter::default(),
+    };
+    let base_url = args.url.unwrap_or_else(|| format!("http://{default_addr}"));
+    let url = format!("{}/api/events", base_url.trim_end_matches('/'));
+    let api_key = args
+        .api_key
+        .or_else(|| std::env::var("TRACEWAY_API_KEY").ok());
+    let color = std::io::stdout().is_terminal();
+    let client = reqwest::Client::new();
+
+    let mut last_id: Option<String> = None;
+    let mut connected_once = false;
+    loop {
+        // The daemon filters too; matching again here covers older ones
+        let mut req = client
+            .get(&url)
+            .header("accept", "text/event-stream")
+            .query(&[("types", "span_completed,span_failed")]);
+        if let Some(ref query) = args.filter {
+            req = req.query(&[("q", query)]);
+        }
+        if let Some(ref key) = api_key {
+            req = req.bearer_auth(key);
+        }
+        if let Some(ref id) = last_id {
+            req = req.header("last-event-id", id);
+        }
+        let mut resp = match req.send().await {
+            Ok(resp) if resp.status().is_success() => resp,
+            Ok(resp) if !connected_once => {
+                return Err(format!("{url} returned {}", resp.status()));
+            }
+            Err(e) if !connected_once => return Err(format!("failed to connect to {url}: {e}")),
+            _ => {
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if !connected_once {
+            eprintln!("following {url}");
+            connected_once = true;
+        }
+
+        let mut parser = SseParser::default();
+        while let Ok(Some(chunk)) = resp.chunk().await {
+            for data in parser.push(&chunk) {
+                let span = match serde_json::from_str::<SystemEvent>(&data) {
+                    Ok(SystemEvent::SpanCompleted { span } | SystemEvent::SpanFailed { span }) => {
+                        span
+                    }
+                    _ => continue,
+                };
+                if filter.matches(&span) {
+                    let mut out = std::io::stdout().lock();
+                    if writeln!(out, "{}", format_span(&span, args.width, color)).is_err() {
+                        // stdout closed, e.g. piped into `head`
+                        return Ok(());
+                    }
+                }
+            }
+            last_id = parser.last_id.clone().or(last_id);
+        }
+        eprintln!("connection lost, reconnecting…");
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trace::{SpanBuilder, SpanKind, TraceId};
+
+    #[test]
+    fn sse_events_split_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"id: 7\ndata: {\"type\":").is_empty());
+        let events = parser.push(b"\"cleared\"}\n\n: keep-alive\n\n");
+        assert_eq!(events, vec![r#"{"type":"cleared"}"#.to_string()]);
+        assert_eq!(parser.last_id.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn formats_llm_span() {
+        let span = SpanBuilder::new(
+            TraceId::now_v7(),
+            "chat",
+            SpanKind::LlmCall {
+                model: "gpt-4o".into(),
+                provider: None,
+                input_tokens: Some(12),
+                output_tokens: Some(3),
+                cost: None,
+                input_preview: None,
+                output_preview: None,
+            },
+        )
+        .build()
+        .complete(Some(
+            serde_json::json!({ "content": "hello\n  there, this is long" }),
+        ));
+        let line = format_span(&span, 11, false);
+        assert!(line.contains(" OK  chat gpt-4o "), "{line}");
+        assert!(line.ends_with("12→3 tok  hello ther…"), "{line}");
+    }
+}
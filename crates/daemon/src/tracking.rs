@@ -0,0 +1,370 @@
+//! Recording changes to tracked directories without memfs.
+//!
+//! Watches `[tracking] paths` and records a file version whenever a file's
+//! content changes. While a trace is open, the change is also recorded as
+//! an `FsWrite` span in it, and a file opened without being changed as an
+//! `FsRead` span of the version last seen. A file is recorded once the
+//! tracked directories have been quiet for `debounce_ms`, so an editor's
+//! save becomes one version.
+//!
+//! Reads are inferred from open events, which only some platforms report
+//! (inotify on Linux does). Elsewhere only writes are recorded.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use storage::{PersistentStore, StorageError};
+use tokio::sync::{mpsc, watch};
+use trace::{FileVersion, SpanBuilder, SpanKind, TraceId};
+use tracing::{debug, info, warn};
+
+use crate::api::AnyBackend;
+use crate::config::TrackingConfig;
+
+/// Open events for a file this soon after the tracker read it are its own.
+const OWN_READ_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Opened,
+    Written,
+}
+
+struct Tracker {
+    store: Arc<PersistentStore<AnyBackend>>,
+    roots: Vec<PathBuf>,
+    max_file_bytes: u64,
+    /// Hash and size of the last version recorded for each file.
+    known: HashMap<PathBuf, (String, u64)>,
+    /// When the tracker last read each file itself.
+    own_reads: HashMap<PathBuf, Instant>,
+}
+
+/// Start watching the configured paths. `None` when tracking is off or no
+/// path could be resolved.
+pub fn spawn(
+    store: Arc<PersistentStore<AnyBackend>>,
+    config: &TrackingConfig,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let roots: Vec<PathBuf> = config
+        .paths
+        .iter()
+        .filter_map(|path| match std::fs::canonicalize(path) {
+            Ok(root) => Some(root),
+            Err(e) => {
+                warn!(path, "tracking: skipping path: {e}");
+                None
+            }
+        })
+        .collect();
+    if roots.is_empty() {
+        return None;
+    }
+    let debounce = Duration::from_millis(config.debounce_ms);
+    let mut tracker = Tracker {
+        store,
+        roots,
+        max_file_bytes: config.max_file_bytes,
+        known: HashMap::new(),
+        own_reads: HashMap::new(),
+    };
+
+    Some(tokio::spawn(async move {
+        // Scan before watching, so the scan's own reads aren't reported
+        tracker.scan().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!("tracking: failed to start watcher: {e}");
+                return;
+            }
+        };
+        for root in &tracker.roots {
+            if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+                warn!(path = %root.display(), "tracking: failed to watch: {e}");
+            }
+        }
+        info!(paths = tracker.roots.len(), "tracking file changes");
+
+        let mut pending: HashMap<PathBuf, Change> = HashMap::new();
+        let mut quiet_at = tokio::time::Instant::now();
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some(Ok(event)) => {
+                        tracker.note(event, &mut pending);
+                        quiet_at = tokio::time::Instant::now() + debounce;
+                    }
+                    Some(Err(e)) => warn!("tracking: watch error: {e}"),
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(quiet_at), if !pending.is_empty() => {
+                    tracker.flush(std::mem::take(&mut pending)).await;
+                }
+                _ = shutdown_rx.changed() => return,
+            }
+        }
+    }))
+}
+
+impl Tracker {
+    /// Whether `path` is outside the roots or under a hidden name, such as
+    /// `.git/` or an editor's swap file.
+    fn ignored(&self, path: &Path) -> bool {
+        let Some(rel) = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+        else {
+            return true;
+        };
+        rel.components().any(|c| match c {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                name.starts_with('.') || name.ends_with('~')
+            }
+            _ => false,
+        })
+    }
+
+    fn note(&mut self, event: Event, pending: &mut HashMap<PathBuf, Change>) {
+        let change = match event.kind {
+            EventKind::Access(AccessKind::Open(_)) => Change::Opened,
+            EventKind::Modify(ModifyKind::Metadata(_)) => return,
+            EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write)) => Change::Written,
+            EventKind::Remove(_) => {
+                for path in &event.paths {
+                    self.known.remove(path);
+                    pending.remove(path);
+                }
+                return;
+            }
+            _ => return,
+        };
+        for path in event.paths {
+            if self.ignored(&path) {
+                continue;
+            }
+            if change == Change::Opened
+                && self
+                    .own_reads
+                    .get(&path)
+                    .is_some_and(|at| at.elapsed() < OWN_READ_WINDOW)
+            {
+                continue;
+            }
+            let entry = pending.entry(path).or_insert(change);
+            if change == Change::Written {
+                *entry = Change::Written;
+            }
+        }
+    }
+
+    /// Record the current version of every file under the roots.
+    async fn scan(&mut self) {
+        let mut dirs = self.roots.clone();
+        while let Some(dir) = dirs.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!(path = %dir.display(), "tracking: failed to list: {e}");
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if self.ignored(&path) {
+                    continue;
+                }
+                match entry.file_type() {
+                    Ok(t) if t.is_dir() => dirs.push(path),
+                    Ok(t) if t.is_file() => {
+                        if let Err(e) = self.record_write(&path, None).await {
+                            warn!(path = %path.display(), "tracking: {e}");
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        debug!(files = self.known.len(), "tracking: initial scan done");
+    }
+
+    async fn flush(&mut self, pending: HashMap<PathBuf, Change>) {
+        let trace_id = self.active_trace();
+        for (path, change) in pending {
+            let result = match change {
+                Change::Written => self.record_write(&path, trace_id).await,
+                Change::Opened => self.record_read(&path, trace_id).await,
+            };
+            if let Err(e) = result {
+                warn!(path = %path.display(), "tracking: {e}");
+            }
+        }
+    }
+
+    /// The most recently started trace that hasn't ended.
+    fn active_trace(&self) -> Option<TraceId> {
+        self.store
+            .all_traces()
+            .into_iter()
+            .filter(|t| t.ended_at.is_none())
+            .max_by_key(|t| t.started_at)
+            .map(|t| t.id)
+    }
+
+    fn read(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let meta = std::fs::metadata(path).ok()?;
+        if !meta.is_file() || meta.len() > self.max_file_bytes {
+            return None;
+        }
+        self.own_reads.insert(path.to_path_buf(), Instant::now());
+        std::fs::read(path).ok()
+    }
+
+    /// Record `path`'s content as a new version if it changed, under an
+    /// `FsWrite` span when `trace_id` is set.
+    async fn record_write(
+        &mut self,
+        path: &Path,
+        trace_id: Option<TraceId>,
+    ) -> Result<(), StorageError> {
+        let Some(content) = self.read(path) else {
+            return Ok(());
+        };
+        let hash = trace::content_hash(&content);
+        if self
+            .known
+            .get(path)
+            .is_some_and(|(known, _)| *known == hash)
+        {
+            return Ok(());
+        }
+        let size = content.len() as u64;
+        let path_str = path.display().to_string();
+        self.store.save_file_content(&hash, &content).await?;
+        match trace_id {
+            Some(trace_id) => {
+                let kind = SpanKind::FsWrite {
+                    path: path_str,
+                    file_version: hash.clone(),
+                    bytes_written: size,
+                };
+                let span = SpanBuilder::new(trace_id, span_name("write", path), kind).build();
+                let id = self.store.insert(span).await?;
+                self.store.complete_span(id, None).await?;
+            }
+            None if self
+                .store
+                .get_file_versions(&path_str)
+                .iter()
+                .any(|v| v.hash == hash) => {}
+            None => {
+                let version = FileVersion {
+                    hash: hash.clone(),
+                    path: path_str,
+                    size,
+                    created_at: Utc::now(),
+                    created_by_span: None,
+                };
+                self.store.save_file_version(version).await?;
+            }
+        }
+        self.known.insert(path.to_path_buf(), (hash, size));
+        Ok(())
+    }
+
+    /// Record an `FsRead` span of the version last seen for `path`.
+    async fn record_read(
+        &mut self,
+        path: &Path,
+        trace_id: Option<TraceId>,
+    ) -> Result<(), StorageError> {
+        let (Some(trace_id), Some((hash, size))) = (trace_id, self.known.get(path)) else {
+            return Ok(());
+        };
+        let kind = SpanKind::FsRead {
+            path: path.display().to_string(),
+            file_version: Some(hash.clone()),
+            bytes_read: *size,
+        };
+        let span = SpanBuilder::new(trace_id, span_name("read", path), kind).build();
+        let id = self.store.insert(span).await?;
+        self.store.complete_span(id, None).await?;
+        Ok(())
+    }
+}
+
+fn span_name(op: &str, path: &Path) -> String {
+    let file = path.file_name().unwrap_or_default().to_string_lossy();
+    format!("{op}-{file}")
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::FileFilter;
+    use storage_sqlite::SqliteBackend;
+    use trace::Trace;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn changes_become_versions_and_spans() {
+        let root = std::env::temp_dir().join(format!("tracking-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let prompt = root.join("system.txt");
+        std::fs::write(&prompt, "be brief").unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref").unwrap();
+
+        let backend = AnyBackend::Sqlite(SqliteBackend::memory().unwrap());
+        let store = Arc::new(PersistentStore::open(backend).await.unwrap());
+        let mut tracker = Tracker {
+            store: store.clone(),
+            roots: vec![root.clone()],
+            max_file_bytes: 1024,
+            known: HashMap::new(),
+            own_reads: HashMap::new(),
+        };
+        tracker.scan().await;
+        let versions = store.list_files(&FileFilter::default());
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].created_by_span, None);
+
+        let trace = Trace::new(Some("agent".into()));
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+        assert_eq!(tracker.active_trace(), Some(trace_id));
+
+        std::fs::write(&prompt, "be very brief").unwrap();
+        let pending = HashMap::from([(prompt.clone(), Change::Written)]);
+        tracker.flush(pending.clone()).await;
+        // Unchanged content isn't a new version
+        tracker.flush(pending).await;
+        tracker
+            .flush(HashMap::from([(prompt.clone(), Change::Opened)]))
+            .await;
+
+        let spans: Vec<_> = store
+            .spans_for_trace(trace_id)
+            .into_iter()
+            .filter_map(|id| store.get(id))
+            .collect();
+        let kinds: Vec<_> = spans.iter().map(|s| s.kind().kind_name()).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&"fs_write") && kinds.contains(&"fs_read"));
+        let versions = store.get_file_versions(&prompt.display().to_string());
+        assert_eq!(versions.len(), 2);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
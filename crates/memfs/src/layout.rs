@@ -50,15 +50,20 @@
 //!   ```
 //! - **`summary.json`**: Aggregated metrics for this trace (total tokens, cost, latency, error count)
 //!
-//! ## `workspace/` — read-write shared context (Phase 3)
+//! ## `workspace/` — read-write shared context
 //!
-//! A real writable directory where applications store shared context. Every
-//! filesystem operation is automatically instrumented:
+//! A real writable directory where applications store shared context,
+//! passed through to a backing directory on disk. Filesystem operations are
+//! instrumented:
 //!
 //! - `read()` → creates `SpanKind::FsRead { path, file_version, bytes_read }`
-//! - `write()` → computes SHA256 hash, stores content (deduped), creates
-//!   `SpanKind::FsWrite { path, file_version, bytes_written }`
-//! - `create()`/`mkdir()`/`unlink()` → tracked but no span (metadata-only changes)
+//!   (not yet implemented)
+//! - `write()` → when the handle is released, computes the SHA256 hash of
+//!   the whole file, stores content (deduped), and creates
+//!   `SpanKind::FsWrite { path, file_version, bytes_written }` under a
+//!   per-mount `memfs workspace` trace, which records the `FileVersion`
+//! - `create()`/`mkdir()`/`unlink()`/`rename()` → no span (metadata-only
+//!   changes), though a created file counts as written
 //!
 //! Content is stored in a content-addressed object store at
 //! `~/.traceway/objects/{hash[0:2]}/{hash[2:]}`. File metadata (path, inode,
@@ -86,7 +91,7 @@ pub mod paths {
     /// Top-level read-only trace observation directory.
     pub const TRACES_DIR: &str = "traces";
 
-    /// Top-level read-write workspace directory (Phase 3).
+    /// Top-level read-write workspace directory.
     pub const WORKSPACE_DIR: &str = "workspace";
 
     /// Symlink name pointing to the most recently started trace.
@@ -121,7 +126,8 @@ pub mod paths {
 /// - 2-9: well-known top-level entries
 /// - 10-99: reserved for future well-known entries
 /// - 100-999: per-trace directories (allocated dynamically)
-/// - 1000+: span files and workspace entries (allocated dynamically)
+/// - 1000+: span files (allocated dynamically)
+/// - 2^40+: workspace entries (allocated dynamically)
 pub mod inodes {
     pub const ROOT: u64 = 1;
     pub const TRACES_DIR: u64 = 2;
@@ -133,6 +139,9 @@ pub mod inodes {
 
     /// First dynamically allocated inode (for trace dirs, span files, etc.)
     pub const DYNAMIC_START: u64 = 100;
+
+    /// First inode for workspace entries, clear of the trace ranges.
+    pub const WORKSPACE_START: u64 = 1 << 40;
 }
 
 /// File extensions used in the virtual filesystem.
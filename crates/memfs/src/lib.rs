@@ -1,36 +1,88 @@
 pub mod layout;
+pub mod workspace;
 
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use fuser::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
 };
-use tokio::sync::RwLock;
+use tokio::runtime::Handle;
 
-use storage::SpanStore;
-use trace::{SpanId, SpanStatus, TraceId};
+use storage::{PersistentStore, StorageBackend, StorageError};
+use trace::{SpanId, Trace, TraceId};
+
+use layout::{inodes, paths};
+use workspace::Workspace;
 
 const TTL: Duration = Duration::from_secs(1);
-const ROOT_INO: u64 = 1;
-const TRACES_INO: u64 = 2;
+const ROOT_INO: u64 = inodes::ROOT;
+const TRACES_INO: u64 = inodes::TRACES_DIR;
+
+/// Name of the trace that workspace writes are recorded under.
+const WORKSPACE_TRACE_NAME: &str = "memfs workspace";
 
-pub struct TraceFs {
-    store: Arc<RwLock<SpanStore>>,
+pub struct TraceFs<B: StorageBackend> {
+    store: Arc<PersistentStore<B>>,
+    /// Runs store calls from FUSE callbacks, which are synchronous.
+    runtime: Handle,
+    workspace: Workspace,
+    /// Created on the first workspace write of this mount.
+    workspace_trace: Option<TraceId>,
     trace_inos: HashMap<TraceId, u64>,
     span_inos: HashMap<SpanId, u64>,
     next_ino: u64,
 }
 
-impl TraceFs {
-    pub fn new(store: Arc<RwLock<SpanStore>>) -> Self {
+impl<B: StorageBackend> TraceFs<B> {
+    /// `workspace` is the directory backing `workspace/`.
+    pub fn new(store: Arc<PersistentStore<B>>, runtime: Handle, workspace: PathBuf) -> Self {
         Self {
             store,
+            runtime,
+            workspace: Workspace::new(workspace),
+            workspace_trace: None,
             trace_inos: HashMap::new(),
             span_inos: HashMap::new(),
-            next_ino: 100,
+            next_ino: inodes::DYNAMIC_START,
+        }
+    }
+
+    fn workspace_trace(&mut self) -> Result<TraceId, StorageError> {
+        if let Some(id) = self.workspace_trace {
+            return Ok(id);
+        }
+        let trace = Trace::new(Some(WORKSPACE_TRACE_NAME.to_string()));
+        let id = trace.id;
+        self.runtime.block_on(self.store.save_trace(trace))?;
+        self.workspace_trace = Some(id);
+        Ok(id)
+    }
+
+    /// Record the current content of a workspace file as a new version.
+    fn record_write(&mut self, rel: &Path) {
+        let path = Workspace::span_path(rel);
+        let content = match std::fs::read(self.workspace.full_path(rel)) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!(%path, "memfs: can't read written file: {e}");
+                return;
+            }
+        };
+        let result = self.workspace_trace().and_then(|trace_id| {
+            self.runtime.block_on(workspace::record_write(
+                &self.store,
+                trace_id,
+                path.clone(),
+                &content,
+            ))
+        });
+        if let Err(e) = result {
+            tracing::error!(%path, "memfs: failed to record write: {e}");
         }
     }
 
@@ -75,17 +127,31 @@ impl TraceFs {
     }
 }
 
-impl Filesystem for TraceFs {
+impl<B: StorageBackend> Filesystem for TraceFs<B> {
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
         match ino {
             ROOT_INO | TRACES_INO => reply.attr(&TTL, &Self::dir_attr(ino)),
+            _ if self.workspace.contains(ino) => match self.workspace.getattr(ino) {
+                Ok(attr) => reply.attr(&TTL, &attr),
+                Err(e) => reply.error(e),
+            },
             _ => reply.error(libc::ENOENT),
         }
     }
 
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        if parent == ROOT_INO && name == "traces" {
+        if parent == ROOT_INO && name == paths::TRACES_DIR {
             reply.entry(&TTL, &Self::dir_attr(TRACES_INO), 0);
+        } else if parent == ROOT_INO && name == paths::WORKSPACE_DIR {
+            match self.workspace.getattr(inodes::WORKSPACE_DIR) {
+                Ok(attr) => reply.entry(&TTL, &attr, 0),
+                Err(e) => reply.error(e),
+            }
+        } else if self.workspace.contains(parent) {
+            match self.workspace.lookup(parent, name) {
+                Ok(attr) => reply.entry(&TTL, &attr, 0),
+                Err(e) => reply.error(e),
+            }
         } else {
             reply.error(libc::ENOENT);
         }
@@ -99,16 +165,35 @@ impl Filesystem for TraceFs {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        let entries: Vec<(u64, FileType, &str)> = match ino {
+        let entries: Vec<(u64, FileType, std::ffi::OsString)> = match ino {
             ROOT_INO => vec![
-                (ROOT_INO, FileType::Directory, "."),
-                (ROOT_INO, FileType::Directory, ".."),
-                (TRACES_INO, FileType::Directory, "traces"),
+                (ROOT_INO, FileType::Directory, ".".into()),
+                (ROOT_INO, FileType::Directory, "..".into()),
+                (TRACES_INO, FileType::Directory, paths::TRACES_DIR.into()),
+                (
+                    inodes::WORKSPACE_DIR,
+                    FileType::Directory,
+                    paths::WORKSPACE_DIR.into(),
+                ),
             ],
             TRACES_INO => vec![
-                (TRACES_INO, FileType::Directory, "."),
-                (ROOT_INO, FileType::Directory, ".."),
+                (TRACES_INO, FileType::Directory, ".".into()),
+                (ROOT_INO, FileType::Directory, "..".into()),
             ],
+            _ if self.workspace.contains(ino) => match self.workspace.readdir(ino) {
+                Ok(children) => {
+                    let mut entries = vec![
+                        (ino, FileType::Directory, ".".into()),
+                        (ROOT_INO, FileType::Directory, "..".into()),
+                    ];
+                    entries.extend(children);
+                    entries
+                }
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
+            },
             _ => {
                 reply.error(libc::ENOENT);
                 return;
@@ -123,25 +208,167 @@ impl Filesystem for TraceFs {
         reply.ok();
     }
 
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.workspace.open(ino, flags) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match self.workspace.create(parent, name, mode & !umask, flags) {
+            Ok((attr, fh)) => reply.created(&TTL, &attr, 0, fh, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
     fn read(
         &mut self,
         _req: &Request,
         _ino: u64,
-        _fh: u64,
-        _offset: i64,
-        _size: u32,
+        fh: u64,
+        offset: i64,
+        size: u32,
         _flags: i32,
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        reply.error(libc::ENOENT);
+        match self.workspace.read(fh, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.workspace.write(fh, offset, data) {
+            Ok(n) => reply.written(n),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        match self.workspace.setattr(ino, mode, size, fh) {
+            Ok((attr, truncated)) => {
+                if let Some(rel) = truncated {
+                    self.record_write(&rel);
+                }
+                reply.attr(&TTL, &attr);
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if let Some(rel) = self.workspace.release(fh) {
+            self.record_write(&rel);
+        }
+        reply.ok();
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match self.workspace.mkdir(parent, name, mode & !umask) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.workspace.unlink(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.workspace.rmdir(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        match self.workspace.rename(parent, name, newparent, newname) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
     }
 }
 
-pub fn mount(store: Arc<RwLock<SpanStore>>, mountpoint: &str) -> std::io::Result<()> {
-    let fs = TraceFs::new(store);
+/// Mount at `mountpoint`, with `workspace/` backed by the `workspace`
+/// directory. Blocks until unmounted, so call it from a blocking task of
+/// the runtime that `store` is used on.
+pub fn mount<B: StorageBackend>(
+    store: Arc<PersistentStore<B>>,
+    mountpoint: &str,
+    workspace: &Path,
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(workspace)?;
+    let fs = TraceFs::new(store, Handle::current(), workspace.to_path_buf());
     let options = vec![
-        fuser::MountOption::RO,
+        fuser::MountOption::RW,
         fuser::MountOption::FSName("tracefs".to_string()),
     ];
     fuser::mount2(fs, mountpoint, &options)?;
@@ -0,0 +1,397 @@
+//! The read-write `workspace/` subtree.
+//!
+//! Passes through to a backing directory on disk. Handles that were written
+//! to (or opened with `O_TRUNC`) are reported when released, so the caller
+//! can record the file's new content as a version; see [`record_write`].
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType};
+
+use storage::{PersistentStore, StorageBackend, StorageError};
+use trace::{SpanBuilder, SpanId, SpanKind, TraceId};
+
+use crate::layout::{inodes, paths};
+
+/// An errno for the FUSE reply.
+pub type Errno = i32;
+
+fn errno(e: io::Error) -> Errno {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+struct OpenFile {
+    file: File,
+    ino: u64,
+    written: bool,
+}
+
+pub struct Workspace {
+    root: PathBuf,
+    /// Paths relative to `root`, by inode. The root itself is
+    /// `inodes::WORKSPACE_DIR` and has the empty path.
+    paths: HashMap<u64, PathBuf>,
+    inos: HashMap<PathBuf, u64>,
+    next_ino: u64,
+    handles: HashMap<u64, OpenFile>,
+    next_fh: u64,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let mut paths = HashMap::new();
+        let mut inos = HashMap::new();
+        paths.insert(inodes::WORKSPACE_DIR, PathBuf::new());
+        inos.insert(PathBuf::new(), inodes::WORKSPACE_DIR);
+        Self {
+            root: root.into(),
+            paths,
+            inos,
+            next_ino: inodes::WORKSPACE_START,
+            handles: HashMap::new(),
+            next_fh: 1,
+        }
+    }
+
+    pub fn contains(&self, ino: u64) -> bool {
+        self.paths.contains_key(&ino)
+    }
+
+    /// The backing file for a workspace-relative path.
+    pub fn full_path(&self, rel: &Path) -> PathBuf {
+        self.root.join(rel)
+    }
+
+    /// The path spans record, e.g. `/workspace/notes/todo.md`.
+    pub fn span_path(rel: &Path) -> String {
+        format!("/{}/{}", paths::WORKSPACE_DIR, rel.display())
+    }
+
+    fn rel(&self, ino: u64) -> Result<PathBuf, Errno> {
+        self.paths.get(&ino).cloned().ok_or(libc::ENOENT)
+    }
+
+    fn child(&self, parent: u64, name: &OsStr) -> Result<PathBuf, Errno> {
+        Ok(self.rel(parent)?.join(name))
+    }
+
+    fn ino_for(&mut self, rel: PathBuf) -> u64 {
+        if let Some(&ino) = self.inos.get(&rel) {
+            return ino;
+        }
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.paths.insert(ino, rel.clone());
+        self.inos.insert(rel, ino);
+        ino
+    }
+
+    fn forget(&mut self, rel: &Path) {
+        if let Some(ino) = self.inos.remove(rel) {
+            self.paths.remove(&ino);
+        }
+    }
+
+    fn stat(&mut self, rel: PathBuf) -> Result<FileAttr, Errno> {
+        let meta = fs::symlink_metadata(self.full_path(&rel)).map_err(errno)?;
+        let ino = self.ino_for(rel);
+        Ok(attr(ino, &meta))
+    }
+
+    pub fn getattr(&mut self, ino: u64) -> Result<FileAttr, Errno> {
+        let rel = self.rel(ino)?;
+        self.stat(rel)
+    }
+
+    pub fn lookup(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, Errno> {
+        let rel = self.child(parent, name)?;
+        self.stat(rel)
+    }
+
+    /// Entries of a directory, without `.` and `..`.
+    pub fn readdir(&mut self, ino: u64) -> Result<Vec<(u64, FileType, OsString)>, Errno> {
+        let rel = self.rel(ino)?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.full_path(&rel)).map_err(errno)? {
+            let entry = entry.map_err(errno)?;
+            let kind = match entry.file_type().map_err(errno)? {
+                t if t.is_dir() => FileType::Directory,
+                t if t.is_symlink() => FileType::Symlink,
+                _ => FileType::RegularFile,
+            };
+            let ino = self.ino_for(rel.join(entry.file_name()));
+            entries.push((ino, kind, entry.file_name()));
+        }
+        Ok(entries)
+    }
+
+    fn open_handle(&mut self, ino: u64, file: File, flags: i32) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let written = flags & libc::O_TRUNC != 0;
+        self.handles.insert(fh, OpenFile { file, ino, written });
+        fh
+    }
+
+    pub fn open(&mut self, ino: u64, flags: i32) -> Result<u64, Errno> {
+        let rel = self.rel(ino)?;
+        let file = open_options(flags)
+            .open(self.full_path(&rel))
+            .map_err(errno)?;
+        Ok(self.open_handle(ino, file, flags))
+    }
+
+    pub fn create(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: i32,
+    ) -> Result<(FileAttr, u64), Errno> {
+        let rel = self.child(parent, name)?;
+        let file = open_options(flags)
+            .create(true)
+            .mode(mode)
+            .open(self.full_path(&rel))
+            .map_err(errno)?;
+        let attr = self.stat(rel)?;
+        // A new file is a write even if nothing is written to it
+        let fh = self.open_handle(attr.ino, file, flags | libc::O_TRUNC);
+        Ok((attr, fh))
+    }
+
+    pub fn read(&mut self, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>, Errno> {
+        let handle = self.handles.get(&fh).ok_or(libc::EBADF)?;
+        let mut buf = vec![0; size as usize];
+        let n = handle
+            .file
+            .read_at(&mut buf, offset.max(0) as u64)
+            .map_err(errno)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    pub fn write(&mut self, fh: u64, offset: i64, data: &[u8]) -> Result<u32, Errno> {
+        let handle = self.handles.get_mut(&fh).ok_or(libc::EBADF)?;
+        handle
+            .file
+            .write_all_at(data, offset.max(0) as u64)
+            .map_err(errno)?;
+        handle.written = true;
+        Ok(data.len() as u32)
+    }
+
+    /// Apply a `setattr`. Returns the new attributes, and the file's path
+    /// when it was truncated outside an open handle.
+    pub fn setattr(
+        &mut self,
+        ino: u64,
+        mode: Option<u32>,
+        size: Option<u64>,
+        fh: Option<u64>,
+    ) -> Result<(FileAttr, Option<PathBuf>), Errno> {
+        let rel = self.rel(ino)?;
+        let full = self.full_path(&rel);
+        if let Some(mode) = mode {
+            fs::set_permissions(&full, fs::Permissions::from_mode(mode)).map_err(errno)?;
+        }
+        let mut truncated = None;
+        if let Some(size) = size {
+            match fh.and_then(|fh| self.handles.get_mut(&fh)) {
+                Some(handle) => {
+                    handle.file.set_len(size).map_err(errno)?;
+                    handle.written = true;
+                }
+                None => {
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&full)
+                        .and_then(|f| f.set_len(size))
+                        .map_err(errno)?;
+                    truncated = Some(rel.clone());
+                }
+            }
+        }
+        Ok((self.stat(rel)?, truncated))
+    }
+
+    /// Close a handle. Returns the file's path if it was written through it.
+    pub fn release(&mut self, fh: u64) -> Option<PathBuf> {
+        let handle = self.handles.remove(&fh)?;
+        if !handle.written {
+            return None;
+        }
+        self.paths.get(&handle.ino).cloned()
+    }
+
+    pub fn mkdir(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr, Errno> {
+        let rel = self.child(parent, name)?;
+        let full = self.full_path(&rel);
+        fs::create_dir(&full).map_err(errno)?;
+        fs::set_permissions(&full, fs::Permissions::from_mode(mode)).map_err(errno)?;
+        self.stat(rel)
+    }
+
+    pub fn unlink(&mut self, parent: u64, name: &OsStr) -> Result<(), Errno> {
+        let rel = self.child(parent, name)?;
+        fs::remove_file(self.full_path(&rel)).map_err(errno)?;
+        self.forget(&rel);
+        Ok(())
+    }
+
+    pub fn rmdir(&mut self, parent: u64, name: &OsStr) -> Result<(), Errno> {
+        let rel = self.child(parent, name)?;
+        fs::remove_dir(self.full_path(&rel)).map_err(errno)?;
+        self.forget(&rel);
+        Ok(())
+    }
+
+    /// Rename within the workspace. The file keeps its inode; no version is
+    /// recorded, since its content is unchanged.
+    pub fn rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+    ) -> Result<(), Errno> {
+        let from = self.child(parent, name)?;
+        let to = self.child(new_parent, new_name)?;
+        fs::rename(self.full_path(&from), self.full_path(&to)).map_err(errno)?;
+        self.forget(&to);
+        // Re-key the moved entry and everything under it
+        let moved: Vec<(PathBuf, u64)> = self
+            .inos
+            .iter()
+            .filter(|(path, _)| path.starts_with(&from))
+            .map(|(path, &ino)| (path.clone(), ino))
+            .collect();
+        for (path, ino) in moved {
+            let new_path = to.join(path.strip_prefix(&from).unwrap_or(Path::new("")));
+            self.inos.remove(&path);
+            self.inos.insert(new_path.clone(), ino);
+            self.paths.insert(ino, new_path);
+        }
+        Ok(())
+    }
+}
+
+fn open_options(flags: i32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match flags & libc::O_ACCMODE {
+        libc::O_WRONLY => options.write(true),
+        libc::O_RDWR => options.read(true).write(true),
+        _ => options.read(true),
+    };
+    options
+        .append(flags & libc::O_APPEND != 0)
+        .truncate(flags & libc::O_TRUNC != 0);
+    options
+}
+
+fn attr(ino: u64, meta: &fs::Metadata) -> FileAttr {
+    let kind = if meta.is_dir() {
+        FileType::Directory
+    } else if meta.file_type().is_symlink() {
+        FileType::Symlink
+    } else {
+        FileType::RegularFile
+    };
+    let ctime = SystemTime::UNIX_EPOCH + Duration::from_secs(meta.ctime().max(0) as u64);
+    FileAttr {
+        ino,
+        size: meta.len(),
+        blocks: meta.blocks(),
+        atime: meta.accessed().unwrap_or(SystemTime::UNIX_EPOCH),
+        mtime: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        ctime,
+        crtime: meta.created().unwrap_or(ctime),
+        kind,
+        perm: (meta.mode() & 0o7777) as u16,
+        nlink: meta.nlink() as u32,
+        uid: meta.uid(),
+        gid: meta.gid(),
+        rdev: meta.rdev() as u32,
+        blksize: meta.blksize() as u32,
+        flags: 0,
+    }
+}
+
+/// Store `content` by hash and record a completed `FsWrite` span for it
+/// under `trace_id`. Inserting the span records the file version.
+pub async fn record_write<B: StorageBackend>(
+    store: &PersistentStore<B>,
+    trace_id: TraceId,
+    path: String,
+    content: &[u8],
+) -> Result<SpanId, StorageError> {
+    let hash = trace::content_hash(content);
+    store.save_file_content(&hash, content).await?;
+    let name = format!("write-{}", path.rsplit('/').next().unwrap_or("file"));
+    let kind = SpanKind::FsWrite {
+        path,
+        file_version: hash,
+        bytes_written: content.len() as u64,
+    };
+    let id = store
+        .insert(SpanBuilder::new(trace_id, &name, kind).build())
+        .await?;
+    store.complete_span(id, None).await?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::FileFilter;
+    use storage_sqlite::SqliteBackend;
+    use trace::Trace;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn written_files_become_versions() {
+        let dir = std::env::temp_dir().join(format!("memfs-{}", uuid::Uuid::new_v4()));
+        let backing = dir.join("workspace");
+        fs::create_dir_all(&backing).unwrap();
+        let backend = SqliteBackend::open(&dir.join("traces.db")).unwrap();
+        let store = PersistentStore::open(backend).await.unwrap();
+
+        let mut ws = Workspace::new(&backing);
+        let notes = ws
+            .mkdir(inodes::WORKSPACE_DIR, "notes".as_ref(), 0o755)
+            .unwrap();
+        let (_, fh) = ws
+            .create(notes.ino, "todo.md".as_ref(), 0o644, libc::O_WRONLY)
+            .unwrap();
+        ws.write(fh, 0, b"ship it").unwrap();
+        let rel = ws.release(fh).unwrap();
+        assert_eq!(Workspace::span_path(&rel), "/workspace/notes/todo.md");
+
+        // Reading doesn't count as a write
+        let file = ws.lookup(notes.ino, "todo.md".as_ref()).unwrap();
+        let fh = ws.open(file.ino, libc::O_RDONLY).unwrap();
+        assert_eq!(ws.read(fh, 0, 64).unwrap(), b"ship it");
+        assert_eq!(ws.release(fh), None);
+
+        let trace = Trace::new(None);
+        let trace_id = trace.id;
+        store.save_trace(trace).await.unwrap();
+        let content = fs::read(ws.full_path(&rel)).unwrap();
+        let span_id = record_write(&store, trace_id, Workspace::span_path(&rel), &content)
+            .await
+            .unwrap();
+
+        let versions = store.list_files(&FileFilter::default());
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].created_by_span, Some(span_id));
+        let stored = store.load_file_content(&versions[0].hash).await.unwrap();
+        assert_eq!(stored, b"ship it");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
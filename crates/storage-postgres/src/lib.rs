@@ -1,15 +1,15 @@
 //! Postgres storage backend for Traceway cloud auth.
 //!
 //! Handles all user-facing data in cloud mode: organizations, users,
-//! API keys, invites. Trace data stays in Turbopuffer/SQLite — this
+//! memberships, API keys, invites. Trace data stays in Turbopuffer/SQLite — this
 //! crate only owns the auth/identity layer.
 
 pub mod migrations;
 
 use async_trait::async_trait;
 use auth::{
-    ApiKey, ApiKeyId, AuthStore, AuthStoreError, Invite, OrgId, Organization, PasswordResetToken,
-    Project, ProjectId, Role, Scope, User, UserId,
+    ApiKey, ApiKeyId, AuthStore, AuthStoreError, Invite, Membership, OrgId, Organization,
+    PasswordResetToken, Project, ProjectId, Role, Scope, User, UserId,
 };
 use chrono::{DateTime, Utc};
 use sqlx::postgres::{PgPool, PgPoolOptions};
@@ -258,7 +258,15 @@ impl AuthStore for PostgresAuthStore {
         .execute(&self.pool)
         .await
         .map_err(db_err)?;
-        Ok(())
+
+        // Keep the home org membership in step with `role`
+        self.save_membership(&Membership {
+            user_id: user.id,
+            org_id: user.org_id,
+            role: user.role,
+            created_at: user.created_at,
+        })
+        .await
     }
 
     async fn get_user(&self, id: UserId) -> Result<Option<User>, AuthStoreError> {
@@ -287,7 +295,9 @@ impl AuthStore for PostgresAuthStore {
 
     async fn list_users_for_org(&self, org_id: OrgId) -> Result<Vec<User>, AuthStoreError> {
         let rows = sqlx::query_as::<_, UserRow>(
-            "SELECT id, email, name, password_hash, org_id, role, created_at, updated_at FROM users WHERE org_id = $1 ORDER BY created_at",
+            r#"SELECT u.id, u.email, u.name, u.password_hash, m.org_id, m.role, u.created_at, u.updated_at
+               FROM memberships m JOIN users u ON u.id = m.user_id
+               WHERE m.org_id = $1 ORDER BY m.created_at"#,
         )
         .bind(org_id)
         .fetch_all(&self.pool)
@@ -297,17 +307,84 @@ impl AuthStore for PostgresAuthStore {
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
+    // ── Membership ───────────────────────────────────────────────────
+
+    async fn save_membership(&self, membership: &Membership) -> Result<(), AuthStoreError> {
+        sqlx::query(
+            r#"INSERT INTO memberships (user_id, org_id, role, created_at)
+               VALUES ($1, $2, $3, $4)
+               ON CONFLICT (user_id, org_id) DO UPDATE SET
+                 role = EXCLUDED.role"#,
+        )
+        .bind(membership.user_id)
+        .bind(membership.org_id)
+        .bind(role_to_str(membership.role))
+        .bind(membership.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err)?;
+        Ok(())
+    }
+
+    async fn get_membership(
+        &self,
+        user_id: UserId,
+        org_id: OrgId,
+    ) -> Result<Option<Membership>, AuthStoreError> {
+        let row = sqlx::query_as::<_, MembershipRow>(
+            "SELECT user_id, org_id, role, created_at FROM memberships WHERE user_id = $1 AND org_id = $2",
+        )
+        .bind(user_id)
+        .bind(org_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err)?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    async fn list_memberships_for_user(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<Membership>, AuthStoreError> {
+        let rows = sqlx::query_as::<_, MembershipRow>(
+            "SELECT user_id, org_id, role, created_at FROM memberships WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_err)?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    async fn delete_membership(
+        &self,
+        user_id: UserId,
+        org_id: OrgId,
+    ) -> Result<bool, AuthStoreError> {
+        let result = sqlx::query("DELETE FROM memberships WHERE user_id = $1 AND org_id = $2")
+            .bind(user_id)
+            .bind(org_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err)?;
+        Ok(result.rows_affected() > 0)
+    }
+
     // ── API Key ──────────────────────────────────────────────────────
 
     async fn save_api_key(&self, key: &ApiKey) -> Result<(), AuthStoreError> {
         sqlx::query(
-            r#"INSERT INTO api_keys (id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at)
-               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            r#"INSERT INTO api_keys (id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at, ingest_only, allowed_ips)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
                ON CONFLICT (id) DO UPDATE SET
                  name = EXCLUDED.name,
                  scopes = EXCLUDED.scopes,
                  last_used_at = EXCLUDED.last_used_at,
-                 project_id = EXCLUDED.project_id"#,
+                 project_id = EXCLUDED.project_id,
+                 ingest_only = EXCLUDED.ingest_only,
+                 allowed_ips = EXCLUDED.allowed_ips"#,
         )
         .bind(key.id)
         .bind(key.org_id)
@@ -319,6 +396,8 @@ impl AuthStore for PostgresAuthStore {
         .bind(key.created_at)
         .bind(key.last_used_at)
         .bind(key.expires_at)
+        .bind(key.ingest_only)
+        .bind(serde_json::json!(key.allowed_ips))
         .execute(&self.pool)
         .await
         .map_err(db_err)?;
@@ -327,50 +406,50 @@ impl AuthStore for PostgresAuthStore {
 
     async fn get_api_key(&self, id: ApiKeyId) -> Result<Option<ApiKey>, AuthStoreError> {
         let row = sqlx::query_as::<_, ApiKeyRow>(
-            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at FROM api_keys WHERE id = $1",
+            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at, ingest_only, allowed_ips FROM api_keys WHERE id = $1",
         )
         .bind(id)
         .fetch_optional(&self.pool)
         .await
         .map_err(db_err)?;
 
-        Ok(row.map(|r| r.into()))
+        row.map(ApiKey::try_from).transpose()
     }
 
     async fn list_api_keys_for_org(&self, org_id: OrgId) -> Result<Vec<ApiKey>, AuthStoreError> {
         let rows = sqlx::query_as::<_, ApiKeyRow>(
-            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at FROM api_keys WHERE org_id = $1 ORDER BY created_at DESC",
+            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at, ingest_only, allowed_ips FROM api_keys WHERE org_id = $1 ORDER BY created_at DESC",
         )
         .bind(org_id)
         .fetch_all(&self.pool)
         .await
         .map_err(db_err)?;
 
-        Ok(rows.into_iter().map(|r| r.into()).collect())
+        rows.into_iter().map(ApiKey::try_from).collect()
     }
 
     async fn list_api_keys_for_project(&self, project_id: ProjectId) -> Result<Vec<ApiKey>, AuthStoreError> {
         let rows = sqlx::query_as::<_, ApiKeyRow>(
-            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at FROM api_keys WHERE project_id = $1 ORDER BY created_at DESC",
+            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at, ingest_only, allowed_ips FROM api_keys WHERE project_id = $1 ORDER BY created_at DESC",
         )
         .bind(project_id)
         .fetch_all(&self.pool)
         .await
         .map_err(db_err)?;
 
-        Ok(rows.into_iter().map(|r| r.into()).collect())
+        rows.into_iter().map(ApiKey::try_from).collect()
     }
 
     async fn lookup_api_key_by_prefix(&self, prefix: &str) -> Result<Option<ApiKey>, AuthStoreError> {
         let row = sqlx::query_as::<_, ApiKeyRow>(
-            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at FROM api_keys WHERE key_prefix = $1",
+            "SELECT id, org_id, project_id, name, key_prefix, key_hash, scopes, created_at, last_used_at, expires_at, ingest_only, allowed_ips FROM api_keys WHERE key_prefix = $1",
         )
         .bind(prefix)
         .fetch_optional(&self.pool)
         .await
         .map_err(db_err)?;
 
-        Ok(row.map(|r| r.into()))
+        row.map(ApiKey::try_from).transpose()
     }
 
     async fn delete_api_key(&self, id: ApiKeyId) -> Result<bool, AuthStoreError> {
@@ -570,6 +649,25 @@ impl From<UserRow> for User {
     }
 }
 
+#[derive(sqlx::FromRow)]
+struct MembershipRow {
+    user_id: uuid::Uuid,
+    org_id: uuid::Uuid,
+    role: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<MembershipRow> for Membership {
+    fn from(r: MembershipRow) -> Self {
+        Self {
+            user_id: r.user_id,
+            org_id: r.org_id,
+            role: role_from_str(&r.role),
+            created_at: r.created_at,
+        }
+    }
+}
+
 #[derive(sqlx::FromRow)]
 struct ApiKeyRow {
     id: uuid::Uuid,
@@ -582,11 +680,20 @@ struct ApiKeyRow {
     created_at: DateTime<Utc>,
     last_used_at: Option<DateTime<Utc>>,
     expires_at: Option<DateTime<Utc>>,
+    ingest_only: bool,
+    allowed_ips: serde_json::Value,
 }
 
-impl From<ApiKeyRow> for ApiKey {
-    fn from(r: ApiKeyRow) -> Self {
-        Self {
+impl TryFrom<ApiKeyRow> for ApiKey {
+    type Error = AuthStoreError;
+
+    /// Fails on an allowlist that doesn't parse rather than dropping it,
+    /// which would let the key be used from anywhere.
+    fn try_from(r: ApiKeyRow) -> Result<Self, Self::Error> {
+        let allowed_ips = serde_json::from_value(r.allowed_ips).map_err(|e| {
+            AuthStoreError::Database(format!("API key {} has an invalid allowed_ips: {e}", r.id))
+        })?;
+        Ok(Self {
             id: r.id,
             org_id: r.org_id,
             project_id: r.project_id.unwrap_or(uuid::Uuid::nil()),
@@ -597,7 +704,9 @@ impl From<ApiKeyRow> for ApiKey {
             created_at: r.created_at,
             last_used_at: r.last_used_at,
             expires_at: r.expires_at,
-        }
+            ingest_only: r.ingest_only,
+            allowed_ips,
+        })
     }
 }
 
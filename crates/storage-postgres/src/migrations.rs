@@ -111,6 +111,31 @@ const MIGRATIONS: &[(&str, &str)] = &[
         ) WHERE project_id IS NULL;
         "#,
     ),
+    (
+        "004_memberships",
+        r#"
+        CREATE TABLE IF NOT EXISTS memberships (
+            user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            org_id      UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            role        TEXT NOT NULL DEFAULT 'member',
+            created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (user_id, org_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_memberships_org_id ON memberships(org_id);
+
+        -- Every user belongs to their home org
+        INSERT INTO memberships (user_id, org_id, role, created_at)
+        SELECT id, org_id, role, created_at FROM users
+        ON CONFLICT (user_id, org_id) DO NOTHING;
+        "#,
+    ),
+    (
+        "005_api_key_restrictions",
+        r#"
+        ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS ingest_only BOOLEAN NOT NULL DEFAULT FALSE;
+        ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS allowed_ips JSONB NOT NULL DEFAULT '[]';
+        "#,
+    ),
 ];
 
 /// Run pending migrations.
@@ -0,0 +1,51 @@
+//! `cargo bench -p storage-sqlite`: the storage harness against a SQLite
+//! file and an in-memory database. See `storage::bench` for settings.
+
+use std::path::{Path, PathBuf};
+
+use storage::bench::{self, BenchConfig};
+use storage_sqlite::SqliteBackend;
+
+fn bench_path(run: usize) -> PathBuf {
+    std::env::temp_dir().join(format!("traceway-bench-{}-{run}.db", std::process::id()))
+}
+
+fn remove_db(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        std::fs::remove_file(file).ok();
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let config = BenchConfig::from_env();
+    let mut results = Vec::new();
+
+    let mut runs = 0;
+    let mut paths = Vec::new();
+    let file = bench::run_backend("sqlite-file", &config, || {
+        runs += 1;
+        let path = bench_path(runs);
+        remove_db(&path);
+        paths.push(path.clone());
+        async move { SqliteBackend::open(&path) }
+    })
+    .await;
+    for path in &paths {
+        remove_db(path);
+    }
+    results.extend(file.expect("sqlite-file benchmark failed"));
+
+    let memory = bench::run_backend("sqlite-memory", &config, || async {
+        SqliteBackend::open(Path::new(":memory:"))
+    })
+    .await;
+    results.extend(memory.expect("sqlite-memory benchmark failed"));
+
+    let regressions = bench::report(&results, &config).expect("failed to report results");
+    if !regressions.is_empty() {
+        std::process::exit(1);
+    }
+}
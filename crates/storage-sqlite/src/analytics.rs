@@ -0,0 +1,466 @@
+//! Embedded analytical store for span summaries.
+//!
+//! Kept in its own database file, apart from the operational tables, so
+//! aggregations never hold up ingest. The schema is one wide `span_facts`
+//! table with no payload columns; each query is a single `GROUP BY`.
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection};
+use storage::analytical::{field_name, Aggregate, Aggregation, AnalyticalStore, SpanRow};
+use storage::StorageError;
+use tokio::sync::Mutex;
+use trace::{AnalyticsFilter, GroupByField, SpanId, TraceId};
+
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS span_facts (
+        id TEXT PRIMARY KEY,
+        trace_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        model TEXT,
+        provider TEXT,
+        tool TEXT,
+        idx TEXT,
+        status TEXT NOT NULL,
+        started_ms INTEGER NOT NULL,
+        duration_ms INTEGER,
+        cost REAL,
+        input_tokens INTEGER,
+        output_tokens INTEGER,
+        total_tokens INTEGER,
+        attributes TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_span_facts_started ON span_facts(started_ms);
+    CREATE INDEX IF NOT EXISTS idx_span_facts_trace ON span_facts(trace_id);
+"#;
+
+pub struct SqliteAnalytics {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteAnalytics {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        add_attributes_column(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn memory() -> Result<Self, StorageError> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Files created before `attributes` was mirrored lack the column. Their
+/// rows group as `unknown` by attribute until `rebuild_analytical` runs.
+fn add_attributes_column(conn: &Connection) -> Result<(), StorageError> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('span_facts') WHERE name = 'attributes'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute_batch("ALTER TABLE span_facts ADD COLUMN attributes TEXT")?;
+    }
+    Ok(())
+}
+
+/// Column expression for a group-by field, pushing any parameters it takes
+/// onto `params`. Missing values group as `unknown`, as in
+/// `analytics::compute_analytics`.
+fn group_expr(field: &GroupByField, params: &mut Vec<Value>) -> Result<&'static str, StorageError> {
+    Ok(match field {
+        GroupByField::Model => "COALESCE(model, 'unknown')",
+        GroupByField::Provider => "COALESCE(provider, 'unknown')",
+        GroupByField::Kind => "kind",
+        GroupByField::Status => "status",
+        GroupByField::Trace => "trace_id",
+        GroupByField::Day => "strftime('%Y-%m-%d', started_ms / 1000, 'unixepoch')",
+        GroupByField::Hour => "strftime('%Y-%m-%dT%H:00', started_ms / 1000, 'unixepoch')",
+        GroupByField::Name => "name",
+        GroupByField::Tool => "COALESCE(tool, 'unknown')",
+        GroupByField::Index => "COALESCE(idx, 'unknown')",
+        GroupByField::Attribute(name) => {
+            params.push(Value::Text(format!("$.\"{name}\"")));
+            "COALESCE(json_extract(attributes, ?), 'unknown')"
+        }
+        GroupByField::Tag => {
+            return Err(StorageError::Unsupported(
+                "trace tags aren't mirrored to the analytical store".into(),
+            ));
+        }
+    })
+}
+
+fn push_filter(filter: &AnalyticsFilter, clauses: &mut Vec<String>, values: &mut Vec<Value>) {
+    let mut eq = |column: &str, value: Value| {
+        clauses.push(format!("{column} = ?"));
+        values.push(value);
+    };
+    if let Some(kind) = &filter.kind {
+        eq("kind", Value::Text(kind.clone()));
+    }
+    if let Some(model) = &filter.model {
+        eq("model", Value::Text(model.clone()));
+    }
+    if let Some(provider) = &filter.provider {
+        eq("provider", Value::Text(provider.clone()));
+    }
+    if let Some(status) = &filter.status {
+        eq("status", Value::Text(status.clone()));
+    }
+    if let Some(trace_id) = filter.trace_id {
+        eq("trace_id", Value::Text(trace_id.to_string()));
+    }
+    if let Some(since) = filter.since {
+        clauses.push("started_ms >= ?".into());
+        values.push(Value::Integer(since.timestamp_millis()));
+    }
+    if let Some(until) = filter.until {
+        clauses.push("started_ms <= ?".into());
+        values.push(Value::Integer(until.timestamp_millis()));
+    }
+}
+
+pub(crate) fn where_sql(clauses: &[String]) -> String {
+    if clauses.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", clauses.join(" AND "))
+    }
+}
+
+#[async_trait]
+impl AnalyticalStore for SqliteAnalytics {
+    fn engine(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn upsert(&self, rows: &[SpanRow]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO span_facts (id, trace_id, name, kind, model, provider, tool, idx, status, started_ms, duration_ms, cost, input_tokens, output_tokens, total_tokens, attributes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            )?;
+            for row in rows {
+                let attributes = if row.attributes.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&row.attributes)?)
+                };
+                stmt.execute(params![
+                    row.id.to_string(),
+                    row.trace_id.to_string(),
+                    row.name,
+                    row.kind,
+                    row.model,
+                    row.provider,
+                    row.tool,
+                    row.index,
+                    row.status,
+                    row.started_at.timestamp_millis(),
+                    row.duration_ms,
+                    row.cost,
+                    row.input_tokens.map(|t| t as i64),
+                    row.output_tokens.map(|t| t as i64),
+                    row.total_tokens.map(|t| t as i64),
+                    attributes,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn delete(&self, ids: &[SpanId]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM span_facts WHERE id = ?1")?;
+            for id in ids {
+                stmt.execute(params![id.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn delete_traces(&self, ids: &[TraceId]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM span_facts WHERE trace_id = ?1")?;
+            for id in ids {
+                stmt.execute(params![id.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        conn.execute("DELETE FROM span_facts", [])?;
+        Ok(())
+    }
+
+    async fn earliest(
+        &self,
+        filter: &AnalyticsFilter,
+    ) -> Result<Option<DateTime<Utc>>, StorageError> {
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+        push_filter(filter, &mut clauses, &mut values);
+        let sql = format!(
+            "SELECT MIN(started_ms) FROM span_facts{}",
+            where_sql(&clauses)
+        );
+        let conn = self.conn.lock().await;
+        let ms: Option<i64> = conn.query_row(&sql, params_from_iter(values), |row| row.get(0))?;
+        Ok(ms.and_then(DateTime::from_timestamp_millis))
+    }
+
+    async fn aggregate(&self, query: &Aggregation) -> Result<Vec<Aggregate>, StorageError> {
+        let mut fields = query.group_by.clone();
+        fields.sort_by_key(field_name);
+        fields.dedup();
+
+        let mut clauses = Vec::new();
+        // Column parameters come first in the statement, so bind them first
+        let mut values = Vec::new();
+        let mut columns = Vec::new();
+        for field in &fields {
+            columns.push(group_expr(field, &mut values)?.to_string());
+        }
+        if let Some(buckets) = &query.buckets {
+            columns.insert(
+                0,
+                format!(
+                    "(started_ms - {}) / {}",
+                    buckets.first_ms(),
+                    buckets.width_ms()
+                ),
+            );
+            clauses.push("started_ms >= ? AND started_ms <= ?".to_string());
+            values.push(Value::Integer(buckets.since.timestamp_millis()));
+            values.push(Value::Integer(buckets.until.timestamp_millis()));
+        } else {
+            columns.insert(0, "NULL".to_string());
+        }
+        push_filter(&query.filter, &mut clauses, &mut values);
+
+        let group_by = if columns.len() > 1 || query.buckets.is_some() {
+            let positions: Vec<String> = (1..=columns.len()).map(|i| i.to_string()).collect();
+            format!(" GROUP BY {}", positions.join(", "))
+        } else {
+            String::new()
+        };
+        let sql = format!(
+            "SELECT {}, COUNT(*), SUM(status = 'failed'), COALESCE(SUM(cost), 0), COALESCE(SUM(input_tokens), 0), COALESCE(SUM(output_tokens), 0), COALESCE(SUM(total_tokens), 0), COALESCE(SUM(duration_ms), 0), COUNT(duration_ms) FROM span_facts{}{}",
+            columns.join(", "),
+            where_sql(&clauses),
+            group_by
+        );
+
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(&sql)?;
+        let n = fields.len();
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            let bucket: Option<i64> = row.get(0)?;
+            let mut key = Vec::with_capacity(n);
+            for (i, field) in fields.iter().enumerate() {
+                key.push((field_name(field), row.get::<_, String>(i + 1)?));
+            }
+            let count = |i: usize| {
+                row.get::<_, Option<i64>>(n + i)
+                    .map(|v| v.unwrap_or(0) as u64)
+            };
+            Ok(Aggregate {
+                bucket: bucket.and_then(|b| usize::try_from(b).ok()),
+                key,
+                span_count: count(1)?,
+                error_count: count(2)?,
+                cost: row.get(n + 3)?,
+                input_tokens: count(4)?,
+                output_tokens: count(5)?,
+                total_tokens: count(6)?,
+                latency_sum_ms: row.get(n + 7)?,
+                latency_count: count(8)?,
+            })
+        })?;
+        let aggregates = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(aggregates
+            .into_iter()
+            .filter(|a| a.span_count > 0)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use storage::analytics::{compute_analytics, compute_timeseries};
+    use trace::{
+        AnalyticsInterval, AnalyticsMetric, AnalyticsQuery, Span, SpanBuilder, SpanKind,
+        TimeseriesQuery,
+    };
+
+    use super::*;
+
+    fn llm_span(model: &str, tokens: u64, cost: f64) -> Span {
+        let kind = SpanKind::LlmCall {
+            model: model.into(),
+            provider: Some("openai".into()),
+            input_tokens: Some(tokens),
+            output_tokens: Some(tokens / 2),
+            cost: Some(cost),
+            input_preview: None,
+            output_preview: None,
+        };
+        SpanBuilder::new(TraceId::new_v4(), "chat", kind).build()
+    }
+
+    #[tokio::test]
+    async fn sql_aggregates_match_in_memory() {
+        let spans = [
+            llm_span("gpt-4o", 100, 0.5).complete(None),
+            llm_span("gpt-4o", 40, 0.25).fail("timeout"),
+            llm_span("gpt-4o-mini", 10, 0.01).complete(None),
+            llm_span("gpt-4o-mini", 20, 0.02),
+        ];
+        let store = SqliteAnalytics::memory().unwrap();
+        let rows: Vec<SpanRow> = spans.iter().map(SpanRow::from).collect();
+        store.upsert(&rows).await.unwrap();
+        let refs: Vec<&Span> = spans.iter().collect();
+
+        let query = AnalyticsQuery {
+            metrics: vec![
+                AnalyticsMetric::TotalCost,
+                AnalyticsMetric::TotalTokens,
+                AnalyticsMetric::SpanCount,
+                AnalyticsMetric::ErrorCount,
+                AnalyticsMetric::AvgLatencyMs,
+            ],
+            group_by: vec![GroupByField::Model, GroupByField::Status],
+            filter: Default::default(),
+        };
+        let sql = storage::analytical::analytics(&store, &query)
+            .await
+            .unwrap();
+        let memory = compute_analytics(&refs, &HashMap::new(), &query);
+        let sorted = |mut groups: Vec<trace::AnalyticsGroup>| {
+            groups.sort_by_key(|g| format!("{:?}", g.key.iter().collect::<BTreeMap<_, _>>()));
+            groups
+        };
+        assert_eq!(sql.groups.len(), 4);
+        for (a, b) in sorted(sql.groups).iter().zip(sorted(memory.groups)) {
+            assert_eq!(a.key, b.key);
+            assert_eq!(a.metrics.span_count, b.metrics.span_count);
+            assert_eq!(a.metrics.error_count, b.metrics.error_count);
+            assert_eq!(a.metrics.total_tokens, b.metrics.total_tokens);
+        }
+        assert_eq!(sql.totals.span_count, Some(4));
+        assert_eq!(sql.totals.error_count, Some(1));
+        assert!((sql.totals.total_cost.unwrap() - 0.78).abs() < 1e-9);
+
+        store.delete(&[spans[0].id()]).await.unwrap();
+        let sql = storage::analytical::analytics(&store, &query)
+            .await
+            .unwrap();
+        assert_eq!(sql.totals.span_count, Some(3));
+
+        let query = TimeseriesQuery {
+            interval: AnalyticsInterval::Hour,
+            metrics: vec![AnalyticsMetric::SpanCount],
+            group_by: vec![GroupByField::Model],
+            filter: Default::default(),
+        };
+        let since = spans[0].started_at() - chrono::Duration::hours(2);
+        let until = spans[3].started_at();
+        let sql = storage::analytical::timeseries(&store, &query, since, until)
+            .await
+            .unwrap();
+        let memory = compute_timeseries(&refs[1..], &HashMap::new(), &query, since, until);
+        assert_eq!(sql.buckets, memory.buckets);
+        let counts = |r: &trace::TimeseriesResponse| -> Vec<Option<u64>> {
+            r.totals.iter().map(|m| m.span_count).collect()
+        };
+        assert_eq!(counts(&sql), counts(&memory));
+        assert_eq!(sql.series.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn sql_groups_by_custom_attribute() {
+        let custom = |attributes: serde_json::Value| {
+            let kind = SpanKind::Custom {
+                kind: "rerank".into(),
+                attributes: serde_json::from_value(attributes).unwrap(),
+            };
+            SpanBuilder::new(TraceId::new_v4(), "rerank", kind).build()
+        };
+        let spans = [
+            custom(serde_json::json!({ "feature": "search", "cost": 0.5 })),
+            custom(serde_json::json!({ "feature": "search", "cost": 0.25 })),
+            custom(serde_json::json!({ "feature": 7 })),
+            llm_span("gpt-4o", 10, 1.0),
+        ];
+        let store = SqliteAnalytics::memory().unwrap();
+        let rows: Vec<SpanRow> = spans.iter().map(SpanRow::from).collect();
+        store.upsert(&rows).await.unwrap();
+        let refs: Vec<&Span> = spans.iter().collect();
+
+        let query = AnalyticsQuery {
+            metrics: vec![AnalyticsMetric::TotalCost, AnalyticsMetric::SpanCount],
+            group_by: vec![GroupByField::Attribute("feature".into())],
+            filter: Default::default(),
+        };
+        let by_feature = |response: trace::AnalyticsResponse| -> BTreeMap<String, u64> {
+            response
+                .groups
+                .into_iter()
+                .map(|g| {
+                    (
+                        g.key["attribute.feature"].clone(),
+                        g.metrics.span_count.unwrap(),
+                    )
+                })
+                .collect()
+        };
+        let sql = storage::analytical::analytics(&store, &query)
+            .await
+            .unwrap();
+        let memory = compute_analytics(&refs, &HashMap::new(), &query);
+        assert_eq!(by_feature(sql), by_feature(memory.clone()));
+        assert_eq!(
+            by_feature(memory),
+            BTreeMap::from([
+                ("7".to_string(), 1),
+                ("search".to_string(), 2),
+                ("unknown".to_string(), 1),
+            ])
+        );
+
+        let query = AnalyticsQuery {
+            group_by: vec![GroupByField::Tag],
+            ..query
+        };
+        assert!(storage::analytical::analytics(&store, &query)
+            .await
+            .is_err());
+    }
+}
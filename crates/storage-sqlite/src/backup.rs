@@ -0,0 +1,172 @@
+//! Snapshots of the database file, for `traceway backup` and `restore`.
+//!
+//! Snapshots go through SQLite's online backup API, so they are consistent
+//! while the daemon keeps writing. File contents live in the same database
+//! and come along with everything else.
+
+use std::path::Path;
+
+use rusqlite::{Connection, DatabaseName};
+use storage::StorageError;
+
+use super::run_migrations;
+
+/// What a snapshot holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub spans: usize,
+    /// Where the next incremental snapshot should start: the start of the
+    /// earliest span still running, or else of the latest span. Spans
+    /// starting at or after it are taken again, so one that completes
+    /// after this snapshot is picked up by the next.
+    pub watermark: Option<String>,
+}
+
+/// Snapshot the database at `src` into a new file at `dest`.
+///
+/// With `since` (a previous snapshot's watermark), only spans that started
+/// at or after it are kept, along with their traces and any trace started
+/// since. Every other table is kept whole. Deletions aren't recorded, so
+/// rows deleted after the previous snapshot come back on restore.
+pub fn snapshot(
+    src: &Path,
+    dest: &Path,
+    since: Option<&str>,
+) -> Result<SnapshotInfo, StorageError> {
+    if !src.exists() {
+        return Err(StorageError::NotFound);
+    }
+    let source = Connection::open(src)?;
+    source.backup(DatabaseName::Main, dest, None)?;
+
+    let conn = Connection::open(dest)?;
+    if let Some(since) = since {
+        conn.execute("DELETE FROM spans WHERE started_at < ?1", [since])?;
+        conn.execute(
+            "DELETE FROM traces WHERE started_at < ?1 AND id NOT IN (SELECT trace_id FROM spans)",
+            [since],
+        )?;
+        conn.execute_batch("VACUUM")?;
+    }
+    let spans: i64 = conn.query_row("SELECT COUNT(*) FROM spans", [], |row| row.get(0))?;
+    let watermark: Option<String> = conn.query_row(
+        "SELECT COALESCE(
+            (SELECT MIN(started_at) FROM spans WHERE status = 'running'),
+            (SELECT MAX(started_at) FROM spans)
+        )",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(SnapshotInfo {
+        spans: spans as usize,
+        watermark: watermark.or_else(|| since.map(str::to_string)),
+    })
+}
+
+/// Replace the database at `dest` with the full snapshot at `src`. Nothing
+/// else may have `dest` open.
+pub fn restore(src: &Path, dest: &Path) -> Result<(), StorageError> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut conn = Connection::open(dest)?;
+    conn.restore(
+        DatabaseName::Main,
+        src,
+        None::<fn(rusqlite::backup::Progress)>,
+    )?;
+    run_migrations(&conn)?;
+    Ok(())
+}
+
+/// Upsert every row of the incremental snapshot at `src` into the database
+/// at `dest`, returning how many rows were written. Nothing else may have
+/// `dest` open.
+pub fn merge(src: &Path, dest: &Path) -> Result<usize, StorageError> {
+    // Bring both to the same schema, in case the snapshot is older
+    run_migrations(&Connection::open(src)?)?;
+    let mut conn = Connection::open(dest)?;
+    run_migrations(&conn)?;
+
+    // Parents and children arrive in table order, and REPLACE on a parent
+    // would cascade to its children, so keys are left unchecked
+    conn.execute_batch("PRAGMA foreign_keys=OFF")?;
+    conn.execute("ATTACH DATABASE ?1 AS snapshot", [src.to_string_lossy()])?;
+    let tables: Vec<String> = {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM snapshot.sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != 'migrations'",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    let tx = conn.transaction()?;
+    let mut written = 0;
+    for table in &tables {
+        let columns: Vec<String> = {
+            let mut stmt = tx.prepare(&format!("PRAGMA snapshot.table_info(\"{table}\")"))?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(1))?;
+            rows.map(|name| name.map(|n| format!("\"{n}\"")))
+                .collect::<Result<_, _>>()?
+        };
+        let columns = columns.join(", ");
+        written += tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO main.\"{table}\" ({columns}) SELECT {columns} FROM snapshot.\"{table}\""
+            ),
+            [],
+        )?;
+    }
+    tx.commit()?;
+    conn.execute_batch("DETACH DATABASE snapshot")?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::StorageBackend;
+    use trace::{SpanBuilder, SpanKind, TraceId};
+
+    use super::*;
+    use crate::SqliteBackend;
+
+    fn span(name: &str) -> trace::Span {
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        SpanBuilder::new(TraceId::new_v4(), name, kind).build()
+    }
+
+    #[tokio::test]
+    async fn incremental_snapshots_restore_on_top_of_a_full_one() {
+        let dir = std::env::temp_dir().join(format!("backup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("traces.db");
+        let backend = SqliteBackend::open(&db).unwrap();
+
+        let first = span("first").complete(None);
+        backend.save_span(&first).await.unwrap();
+        let full = snapshot(&db, &dir.join("full.db"), None).unwrap();
+        assert_eq!(full.spans, 1);
+        assert_eq!(full.watermark, Some(first.started_at().to_rfc3339()));
+
+        let second = span("second");
+        backend.save_span(&second).await.unwrap();
+        let inc = snapshot(&db, &dir.join("inc.db"), full.watermark.as_deref()).unwrap();
+        // The first span starts exactly at the watermark, so it is taken again
+        assert_eq!(inc.spans, 2);
+        assert_eq!(inc.watermark, Some(second.started_at().to_rfc3339()));
+        drop(backend);
+
+        let restored = dir.join("restored.db");
+        restore(&dir.join("full.db"), &restored).unwrap();
+        assert!(merge(&dir.join("inc.db"), &restored).unwrap() >= 2);
+        let backend = SqliteBackend::open(&restored).unwrap();
+        assert!(backend.get_span(second.id()).await.unwrap().is_some());
+        assert!(backend.get_span(first.id()).await.unwrap().is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,398 @@
+//! DuckDB engine for the analytical store.
+//!
+//! Same `span_facts` table and queries as [`super::SqliteAnalytics`], run
+//! on DuckDB's columnar engine, which scans and groups large tables much
+//! faster. DuckDB keeps min/max zone maps per column, so `started_ms` needs
+//! no index; the primary key is what `INSERT OR REPLACE` matches on.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use duckdb::{params, params_from_iter, types::Value, Connection};
+use storage::analytical::{field_name, Aggregate, Aggregation, AnalyticalStore, SpanRow};
+use storage::StorageError;
+use tokio::sync::Mutex;
+use trace::{AnalyticsFilter, GroupByField, SpanId, TraceId};
+
+use crate::analytics::where_sql;
+
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS span_facts (
+        id VARCHAR PRIMARY KEY,
+        trace_id VARCHAR NOT NULL,
+        name VARCHAR NOT NULL,
+        kind VARCHAR NOT NULL,
+        model VARCHAR,
+        provider VARCHAR,
+        tool VARCHAR,
+        idx VARCHAR,
+        status VARCHAR NOT NULL,
+        started_ms BIGINT NOT NULL,
+        duration_ms BIGINT,
+        cost DOUBLE,
+        input_tokens BIGINT,
+        output_tokens BIGINT,
+        total_tokens BIGINT,
+        attributes VARCHAR
+    );
+"#;
+
+pub struct DuckDbAnalytics {
+    conn: Mutex<Connection>,
+}
+
+impl DuckDbAnalytics {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn memory() -> Result<Self, StorageError> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Column expression for a group-by field, pushing any parameters it takes
+/// onto `params`. Missing values group as `unknown`, as in
+/// `analytics::compute_analytics`.
+fn group_expr(field: &GroupByField, params: &mut Vec<Value>) -> Result<&'static str, StorageError> {
+    Ok(match field {
+        GroupByField::Model => "COALESCE(model, 'unknown')",
+        GroupByField::Provider => "COALESCE(provider, 'unknown')",
+        GroupByField::Kind => "kind",
+        GroupByField::Status => "status",
+        GroupByField::Trace => "trace_id",
+        GroupByField::Day => "strftime(epoch_ms(started_ms), '%Y-%m-%d')",
+        GroupByField::Hour => "strftime(epoch_ms(started_ms), '%Y-%m-%dT%H:00')",
+        GroupByField::Name => "name",
+        GroupByField::Tool => "COALESCE(tool, 'unknown')",
+        GroupByField::Index => "COALESCE(idx, 'unknown')",
+        GroupByField::Attribute(name) => {
+            params.push(Value::Text(format!("$.\"{name}\"")));
+            "COALESCE(json_extract_string(attributes, ?), 'unknown')"
+        }
+        GroupByField::Tag => {
+            return Err(StorageError::Unsupported(
+                "trace tags aren't mirrored to the analytical store".into(),
+            ));
+        }
+    })
+}
+
+fn push_filter(filter: &AnalyticsFilter, clauses: &mut Vec<String>, values: &mut Vec<Value>) {
+    let mut eq = |column: &str, value: Value| {
+        clauses.push(format!("{column} = ?"));
+        values.push(value);
+    };
+    if let Some(kind) = &filter.kind {
+        eq("kind", Value::Text(kind.clone()));
+    }
+    if let Some(model) = &filter.model {
+        eq("model", Value::Text(model.clone()));
+    }
+    if let Some(provider) = &filter.provider {
+        eq("provider", Value::Text(provider.clone()));
+    }
+    if let Some(status) = &filter.status {
+        eq("status", Value::Text(status.clone()));
+    }
+    if let Some(trace_id) = filter.trace_id {
+        eq("trace_id", Value::Text(trace_id.to_string()));
+    }
+    if let Some(since) = filter.since {
+        clauses.push("started_ms >= ?".into());
+        values.push(Value::BigInt(since.timestamp_millis()));
+    }
+    if let Some(until) = filter.until {
+        clauses.push("started_ms <= ?".into());
+        values.push(Value::BigInt(until.timestamp_millis()));
+    }
+}
+
+#[async_trait]
+impl AnalyticalStore for DuckDbAnalytics {
+    fn engine(&self) -> &'static str {
+        "duckdb"
+    }
+
+    async fn upsert(&self, rows: &[SpanRow]) -> Result<(), StorageError> {
+        // DuckDB rejects replacing the same key twice in one transaction,
+        // so keep only the last row written for each span
+        let mut last = HashMap::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            last.insert(row.id, i);
+        }
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO span_facts (id, trace_id, name, kind, model, provider, tool, idx, status, started_ms, duration_ms, cost, input_tokens, output_tokens, total_tokens, attributes) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for (i, row) in rows.iter().enumerate() {
+                if last[&row.id] != i {
+                    continue;
+                }
+                let attributes = if row.attributes.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&row.attributes)?)
+                };
+                stmt.execute(params![
+                    row.id.to_string(),
+                    row.trace_id.to_string(),
+                    row.name,
+                    row.kind,
+                    row.model,
+                    row.provider,
+                    row.tool,
+                    row.index,
+                    row.status,
+                    row.started_at.timestamp_millis(),
+                    row.duration_ms,
+                    row.cost,
+                    row.input_tokens.map(|t| t as i64),
+                    row.output_tokens.map(|t| t as i64),
+                    row.total_tokens.map(|t| t as i64),
+                    attributes,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn delete(&self, ids: &[SpanId]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM span_facts WHERE id = ?")?;
+            for id in ids {
+                stmt.execute(params![id.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn delete_traces(&self, ids: &[TraceId]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM span_facts WHERE trace_id = ?")?;
+            for id in ids {
+                stmt.execute(params![id.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        conn.execute("DELETE FROM span_facts", [])?;
+        Ok(())
+    }
+
+    async fn earliest(
+        &self,
+        filter: &AnalyticsFilter,
+    ) -> Result<Option<DateTime<Utc>>, StorageError> {
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+        push_filter(filter, &mut clauses, &mut values);
+        let sql = format!(
+            "SELECT MIN(started_ms) FROM span_facts{}",
+            where_sql(&clauses)
+        );
+        let conn = self.conn.lock().await;
+        let ms: Option<i64> = conn.query_row(&sql, params_from_iter(values), |row| row.get(0))?;
+        Ok(ms.and_then(DateTime::from_timestamp_millis))
+    }
+
+    async fn aggregate(&self, query: &Aggregation) -> Result<Vec<Aggregate>, StorageError> {
+        let mut fields = query.group_by.clone();
+        fields.sort_by_key(field_name);
+        fields.dedup();
+
+        let mut clauses = Vec::new();
+        // Column parameters come first in the statement, so bind them first
+        let mut values = Vec::new();
+        let mut columns = Vec::new();
+        for field in &fields {
+            columns.push(group_expr(field, &mut values)?.to_string());
+        }
+        if let Some(buckets) = &query.buckets {
+            // `//` is integer division; `/` would give a DOUBLE
+            columns.insert(
+                0,
+                format!(
+                    "(started_ms - {}) // {}",
+                    buckets.first_ms(),
+                    buckets.width_ms()
+                ),
+            );
+            clauses.push("started_ms >= ? AND started_ms <= ?".to_string());
+            values.push(Value::BigInt(buckets.since.timestamp_millis()));
+            values.push(Value::BigInt(buckets.until.timestamp_millis()));
+        } else {
+            columns.insert(0, "CAST(NULL AS BIGINT)".to_string());
+        }
+        push_filter(&query.filter, &mut clauses, &mut values);
+
+        let group_by = if columns.len() > 1 || query.buckets.is_some() {
+            let positions: Vec<String> = (1..=columns.len()).map(|i| i.to_string()).collect();
+            format!(" GROUP BY {}", positions.join(", "))
+        } else {
+            String::new()
+        };
+        // SUM over BIGINT yields HUGEINT, so cast the integer sums back
+        let sql = format!(
+            "SELECT {}, COUNT(*), COUNT(*) FILTER (WHERE status = 'failed'), COALESCE(SUM(cost), 0), CAST(COALESCE(SUM(input_tokens), 0) AS BIGINT), CAST(COALESCE(SUM(output_tokens), 0) AS BIGINT), CAST(COALESCE(SUM(total_tokens), 0) AS BIGINT), CAST(COALESCE(SUM(duration_ms), 0) AS DOUBLE), COUNT(duration_ms) FROM span_facts{}{}",
+            columns.join(", "),
+            where_sql(&clauses),
+            group_by
+        );
+
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(&sql)?;
+        let n = fields.len();
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            let bucket: Option<i64> = row.get(0)?;
+            let mut key = Vec::with_capacity(n);
+            for (i, field) in fields.iter().enumerate() {
+                key.push((field_name(field), row.get::<_, String>(i + 1)?));
+            }
+            let count = |i: usize| row.get::<_, i64>(n + i).map(|v| v as u64);
+            Ok(Aggregate {
+                bucket: bucket.and_then(|b| usize::try_from(b).ok()),
+                key,
+                span_count: count(1)?,
+                error_count: count(2)?,
+                cost: row.get(n + 3)?,
+                input_tokens: count(4)?,
+                output_tokens: count(5)?,
+                total_tokens: count(6)?,
+                latency_sum_ms: row.get(n + 7)?,
+                latency_count: count(8)?,
+            })
+        })?;
+        let aggregates = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(aggregates
+            .into_iter()
+            .filter(|a| a.span_count > 0)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::analytical::Buckets;
+    use trace::{AnalyticsInterval, Span, SpanBuilder, SpanKind};
+
+    use super::*;
+    use crate::SqliteAnalytics;
+
+    fn llm_span(model: &str, tokens: u64, cost: f64) -> Span {
+        let kind = SpanKind::LlmCall {
+            model: model.into(),
+            provider: Some("openai".into()),
+            input_tokens: Some(tokens),
+            output_tokens: Some(tokens / 2),
+            cost: Some(cost),
+            input_preview: None,
+            output_preview: None,
+        };
+        SpanBuilder::new(TraceId::new_v4(), "chat", kind).build()
+    }
+
+    fn custom_span(attributes: serde_json::Value) -> Span {
+        let kind = SpanKind::Custom {
+            kind: "rerank".into(),
+            attributes: serde_json::from_value(attributes).unwrap(),
+        };
+        SpanBuilder::new(TraceId::new_v4(), "rerank", kind).build()
+    }
+
+    async fn sorted(store: &dyn AnalyticalStore, query: &Aggregation) -> Vec<Aggregate> {
+        let mut aggregates = store.aggregate(query).await.unwrap();
+        aggregates.sort_by(|a, b| (a.bucket, &a.key).cmp(&(b.bucket, &b.key)));
+        aggregates
+    }
+
+    #[tokio::test]
+    async fn aggregates_match_sqlite() {
+        // Costs are exact in binary, so sums don't depend on row order
+        let spans = [
+            llm_span("gpt-4o", 100, 0.5).complete(None),
+            llm_span("gpt-4o", 40, 0.25).fail("timeout"),
+            llm_span("gpt-4o-mini", 10, 0.125).complete(None),
+            custom_span(serde_json::json!({ "feature": "search" })),
+            custom_span(serde_json::json!({ "feature": 7 })).complete(None),
+        ];
+        let mut rows: Vec<SpanRow> = spans.iter().map(SpanRow::from).collect();
+        // A span written twice in one batch keeps its last row
+        let mut stale = SpanRow::from(&llm_span("gpt-4o", 1, 8.0));
+        stale.id = rows[0].id;
+        rows.insert(0, stale);
+
+        let duck = DuckDbAnalytics::memory().unwrap();
+        let lite = SqliteAnalytics::memory().unwrap();
+        duck.upsert(&rows).await.unwrap();
+        lite.upsert(&rows).await.unwrap();
+        duck.delete(&[spans[2].id()]).await.unwrap();
+        lite.delete(&[spans[2].id()]).await.unwrap();
+
+        let buckets = Buckets {
+            interval: AnalyticsInterval::Hour,
+            since: spans[0].started_at() - chrono::Duration::hours(2),
+            until: spans[4].started_at(),
+        };
+        let totals = Aggregation {
+            filter: Default::default(),
+            group_by: vec![],
+            buckets: None,
+        };
+        assert_eq!(sorted(&duck, &totals).await[0].span_count, 4);
+        for (group_by, buckets) in [
+            (vec![], None),
+            (vec![GroupByField::Model, GroupByField::Status], None),
+            (vec![GroupByField::Day, GroupByField::Hour], None),
+            (vec![GroupByField::Attribute("feature".into())], None),
+            (vec![GroupByField::Model], Some(buckets)),
+        ] {
+            let query = Aggregation {
+                filter: Default::default(),
+                group_by,
+                buckets,
+            };
+            let expected = sorted(&lite, &query).await;
+            assert!(!expected.is_empty());
+            assert_eq!(sorted(&duck, &query).await, expected);
+        }
+
+        let filter = AnalyticsFilter {
+            model: Some("gpt-4o".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            duck.earliest(&filter).await.unwrap(),
+            lite.earliest(&filter).await.unwrap(),
+        );
+
+        duck.delete_traces(&[spans[0].trace_id()]).await.unwrap();
+        duck.clear().await.unwrap();
+        assert_eq!(duck.earliest(&Default::default()).await.unwrap(), None);
+    }
+}
@@ -2,21 +2,36 @@
 //!
 //! This crate provides a SQLite-based implementation of the `StorageBackend` trait,
 //! suitable for local-first development and single-machine deployments.
+//! The `duckdb` feature adds a DuckDB engine for the analytical store.
+
+mod analytics;
+pub mod backup;
+#[cfg(feature = "duckdb")]
+mod duckdb_analytics;
+
+pub use analytics::SqliteAnalytics;
+#[cfg(feature = "duckdb")]
+pub use duckdb_analytics::DuckDbAnalytics;
 
 use std::path::Path;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, types::Value, Connection, TransactionBehavior};
 use storage::{
-    filter::{SpanFilter, TraceFilter},
+    filter::{
+        AnnotationFilter, AuditFilter, CursorPosition, SortValue, SpanFilter, TraceFilter,
+        TrashScope,
+    },
     StorageBackend, StorageError,
 };
 use tokio::sync::Mutex;
 use trace::{
-    CaptureRule, CaptureRuleId, Datapoint, DatapointId, Dataset, DatasetId, EvalResult,
-    EvalResultId, EvalRun, EvalRunId, FileVersion, ProviderConnection, ProviderConnectionId,
-    QueueItem, QueueItemId, Span, SpanId, SpanKind, SpanStatus, Trace, TraceId,
+    Annotation, AnnotationId, AuditEvent, CaptureRule, CaptureRuleId, Datapoint, DatapointId,
+    Dataset, DatasetId, EvalResult, EvalResultId, EvalRun, EvalRunId, FileVersion, Machine,
+    ProviderConnection, ProviderConnectionId, QueueItem, QueueItemId, QueueSubmission, SavedView,
+    SavedViewId, Session, Span, SpanId, SpanKindDefinition, SpanStatus, Trace, TraceId, TraceStats,
+    Webhook, WebhookDelivery, WebhookId,
 };
 
 // --- Migration system ---
@@ -183,6 +198,133 @@ const MIGRATIONS: &[&str] = &[
     ALTER TABLE datasets ADD COLUMN org_id TEXT;
     CREATE INDEX IF NOT EXISTS idx_datasets_org_id ON datasets(org_id);
     "#,
+    // v7: custom span kind definitions
+    r#"
+    CREATE TABLE IF NOT EXISTS span_kinds (
+        name TEXT PRIMARY KEY,
+        data TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    "#,
+    // v8: per-trace span rollups
+    r#"
+    ALTER TABLE traces ADD COLUMN stats_json TEXT;
+    "#,
+    // v9: session and end-user attribution
+    r#"
+    ALTER TABLE traces ADD COLUMN session_id TEXT;
+    ALTER TABLE traces ADD COLUMN user_id TEXT;
+    CREATE INDEX IF NOT EXISTS idx_traces_session_id ON traces(session_id);
+    "#,
+    // v10: normalized span names
+    r#"
+    ALTER TABLE spans ADD COLUMN name_normalized TEXT;
+    "#,
+    // v11: machine registry
+    r#"
+    CREATE TABLE IF NOT EXISTS machines (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL,
+        last_seen TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_traces_machine_id ON traces(machine_id);
+    "#,
+    // v12: per-scorer eval scores
+    r#"
+    ALTER TABLE eval_results ADD COLUMN scores_json TEXT;
+    "#,
+    // v13: webhook subscriptions and delivery log
+    r#"
+    CREATE TABLE IF NOT EXISTS webhooks (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS webhook_deliveries (
+        id TEXT PRIMARY KEY,
+        webhook_id TEXT NOT NULL,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at);
+    "#,
+    // v14: key/value settings
+    r#"
+    CREATE TABLE IF NOT EXISTS settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    "#,
+    // v15: audit log
+    r#"
+    CREATE TABLE IF NOT EXISTS audit_events (
+        id TEXT PRIMARY KEY,
+        actor TEXT NOT NULL,
+        action TEXT NOT NULL,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_audit_events_created_at ON audit_events(created_at);
+    "#,
+    // v16: queue item rejection and resolution time
+    r#"
+    ALTER TABLE queue_items ADD COLUMN rejected_reason TEXT;
+    ALTER TABLE queue_items ADD COLUMN resolved_at TEXT;
+    "#,
+    // v17: multi-reviewer queue items
+    r#"
+    ALTER TABLE queue_items ADD COLUMN review_json TEXT;
+    CREATE TABLE IF NOT EXISTS queue_submissions (
+        id TEXT PRIMARY KEY,
+        queue_item_id TEXT NOT NULL REFERENCES queue_items(id) ON DELETE CASCADE,
+        reviewer TEXT NOT NULL,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_queue_submissions_queue_item_id ON queue_submissions(queue_item_id);
+    "#,
+    // v18: source control metadata
+    r#"
+    ALTER TABLE traces ADD COLUMN git_commit TEXT;
+    ALTER TABLE traces ADD COLUMN git_branch TEXT;
+    ALTER TABLE traces ADD COLUMN repo TEXT;
+    CREATE INDEX IF NOT EXISTS idx_traces_git_commit ON traces(git_commit);
+    "#,
+    // v19: span annotations
+    r#"
+    CREATE TABLE IF NOT EXISTS annotations (
+        id TEXT PRIMARY KEY,
+        span_id TEXT NOT NULL,
+        trace_id TEXT NOT NULL,
+        score REAL,
+        label TEXT,
+        created_by TEXT NOT NULL,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_annotations_span_id ON annotations(span_id);
+    CREATE INDEX IF NOT EXISTS idx_annotations_label ON annotations(label);
+    "#,
+    // v20: saved views
+    r#"
+    CREATE TABLE IF NOT EXISTS saved_views (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    "#,
+    // v21: trash
+    r#"
+    ALTER TABLE traces ADD COLUMN deleted_at TEXT;
+    ALTER TABLE datasets ADD COLUMN deleted_at TEXT;
+    CREATE INDEX IF NOT EXISTS idx_traces_deleted_at ON traces(deleted_at);
+    "#,
+    // v22: row versions for conditional updates
+    r#"
+    ALTER TABLE datasets ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE datapoints ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+    "#,
 ];
 
 fn run_migrations(conn: &Connection) -> Result<(), StorageError> {
@@ -216,106 +358,446 @@ fn run_migrations(conn: &Connection) -> Result<(), StorageError> {
     Ok(())
 }
 
-// --- SqliteBackend ---
+// --- Filter predicates ---
 
-pub struct SqliteBackend {
-    conn: Mutex<Connection>,
+/// Append `AND ...` conditions for every predicate set on a span filter.
+/// Sorting and paging fields are not applied here.
+fn push_span_predicates(sql: &mut String, params: &mut Vec<Value>, filter: &SpanFilter) {
+    if let Some(ref trace_id) = filter.trace_id {
+        sql.push_str(" AND trace_id = ?");
+        params.push(Value::Text(trace_id.to_string()));
+    }
+    if let Some(ref status) = filter.status {
+        sql.push_str(" AND status = ?");
+        params.push(Value::Text(status.clone()));
+    }
+    if let Some(ref kind) = filter.kind {
+        sql.push_str(&format!(" AND {SPAN_KIND_SQL} = ?"));
+        params.push(Value::Text(kind.clone()));
+    }
+    if let Some(ref model) = filter.model {
+        sql.push_str(" AND json_extract(kind_json, '$.model') = ?");
+        params.push(Value::Text(model.clone()));
+    }
+    if let Some(ref provider) = filter.provider {
+        sql.push_str(" AND json_extract(kind_json, '$.provider') = ?");
+        params.push(Value::Text(provider.clone()));
+    }
+    if let Some(ref path) = filter.path {
+        sql.push_str(" AND json_extract(kind_json, '$.path') = ?");
+        params.push(Value::Text(path.clone()));
+    }
+    if let Some(ref tool) = filter.tool {
+        sql.push_str(" AND json_extract(kind_json, '$.tool_name') = ?");
+        params.push(Value::Text(tool.clone()));
+    }
+    if let Some(ref index) = filter.index {
+        sql.push_str(" AND json_extract(kind_json, '$.index') = ?");
+        params.push(Value::Text(index.clone()));
+    }
+    if let Some(ref verdict) = filter.verdict {
+        sql.push_str(" AND json_extract(kind_json, '$.verdict') = ?");
+        params.push(Value::Text(verdict.clone()));
+    }
+    if let Some(since) = filter.since {
+        sql.push_str(" AND started_at >= ?");
+        params.push(Value::Text(since.to_rfc3339()));
+    }
+    if let Some(until) = filter.until {
+        sql.push_str(" AND started_at <= ?");
+        params.push(Value::Text(until.to_rfc3339()));
+    }
+    if let Some(ref name) = filter.name_contains {
+        sql.push_str(" AND name LIKE ?");
+        params.push(Value::Text(format!("%{}%", name)));
+    }
+    if let Some(min_ms) = filter.duration_min {
+        sql.push_str(&format!(" AND ended_at IS NOT NULL AND {DURATION_MS_SQL} >= ?"));
+        params.push(Value::Integer(min_ms));
+    }
+    if let Some(max_ms) = filter.duration_max {
+        sql.push_str(&format!(" AND ended_at IS NOT NULL AND {DURATION_MS_SQL} <= ?"));
+        params.push(Value::Integer(max_ms));
+    }
+    if let Some(min_tokens) = filter.tokens_min {
+        sql.push_str(&format!(
+            " AND (json_extract(kind_json, '$.input_tokens') IS NOT NULL OR json_extract(kind_json, '$.output_tokens') IS NOT NULL) AND {SPAN_TOKENS_SQL} >= ?"
+        ));
+        params.push(Value::Integer(min_tokens as i64));
+    }
+    if let Some(min_cost) = filter.cost_min {
+        sql.push_str(
+            " AND COALESCE(json_extract(kind_json, '$.cost'), json_extract(kind_json, '$.attributes.cost')) >= ?",
+        );
+        params.push(Value::Real(min_cost));
+    }
+    if let Some(ref text) = filter.text_contains {
+        sql.push_str(
+            " AND (LOWER(name) LIKE ? OR LOWER(COALESCE(input_json, '')) LIKE ? OR LOWER(COALESCE(output_json, '')) LIKE ?)",
+        );
+        let pattern = format!("%{}%", text.to_lowercase());
+        params.extend(std::iter::repeat_n(Value::Text(pattern), 3));
+    }
+    if let Some(ref text) = filter.input_contains {
+        sql.push_str(" AND LOWER(input_json) LIKE ?");
+        params.push(Value::Text(format!("%{}%", text.to_lowercase())));
+    }
+    if let Some(ref text) = filter.output_contains {
+        sql.push_str(" AND LOWER(output_json) LIKE ?");
+        params.push(Value::Text(format!("%{}%", text.to_lowercase())));
+    }
+    if let Some(ref ids) = filter.span_ids {
+        if ids.is_empty() {
+            sql.push_str(" AND 0");
+        } else {
+            sql.push_str(&format!(" AND id IN ({})", vec!["?"; ids.len()].join(", ")));
+            params.extend(ids.iter().map(|id| Value::Text(id.to_string())));
+        }
+    }
 }
 
-impl SqliteBackend {
-    pub fn open(path: &Path) -> Result<Self, StorageError> {
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+/// Append `AND ...` conditions for every predicate set on a trace filter.
+fn push_trace_predicates(sql: &mut String, params: &mut Vec<Value>, filter: &TraceFilter) {
+    if let Some(ref name) = filter.name_contains {
+        sql.push_str(" AND name LIKE ?");
+        params.push(Value::Text(format!("%{}%", name)));
+    }
+    if let Some(ref tags) = filter.tags {
+        for tag in tags {
+            sql.push_str(" AND EXISTS (SELECT 1 FROM json_each(traces.tags_json) WHERE json_each.value = ?)");
+            params.push(Value::Text(tag.clone()));
         }
-        let conn = Connection::open(path)?;
-        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")?;
-        run_migrations(&conn)?;
+    }
+    if let Some(since) = filter.since {
+        sql.push_str(" AND started_at >= ?");
+        params.push(Value::Text(since.to_rfc3339()));
+    }
+    if let Some(until) = filter.until {
+        sql.push_str(" AND started_at <= ?");
+        params.push(Value::Text(until.to_rfc3339()));
+    }
+    if let Some(ref machine_id) = filter.machine_id {
+        sql.push_str(" AND machine_id = ?");
+        params.push(Value::Text(machine_id.clone()));
+    }
+    if let Some(ref session_id) = filter.session_id {
+        sql.push_str(" AND session_id = ?");
+        params.push(Value::Text(session_id.clone()));
+    }
+    if let Some(ref git_commit) = filter.git_commit {
+        sql.push_str(" AND git_commit = ?");
+        params.push(Value::Text(git_commit.clone()));
+    }
+    if let Some(ref git_branch) = filter.git_branch {
+        sql.push_str(" AND git_branch = ?");
+        params.push(Value::Text(git_branch.clone()));
+    }
+    if let Some(ref repo) = filter.repo {
+        sql.push_str(" AND repo = ?");
+        params.push(Value::Text(repo.clone()));
+    }
+    match filter.trash {
+        TrashScope::Live => sql.push_str(" AND deleted_at IS NULL"),
+        TrashScope::Trashed => sql.push_str(" AND deleted_at IS NOT NULL"),
+        TrashScope::All => {}
+    }
+}
+
+// --- Sorting and paging ---
+
+/// LLM call cost, or the `cost` attribute of a custom span.
+const SPAN_COST_SQL: &str =
+    "COALESCE(json_extract(kind_json, '$.cost'), json_extract(kind_json, '$.attributes.cost'), 0)";
+const DURATION_MS_SQL: &str = "(julianday(ended_at) - julianday(started_at)) * 86400000.0";
+/// `SpanKind::kind_name()`: the serde tag, or the custom kind name.
+const SPAN_KIND_SQL: &str = "CASE json_extract(kind_json, '$.type') WHEN 'custom' THEN json_extract(kind_json, '$.kind') ELSE json_extract(kind_json, '$.type') END";
+const SPAN_TOKENS_SQL: &str = "COALESCE(json_extract(kind_json, '$.input_tokens'), 0) + COALESCE(json_extract(kind_json, '$.output_tokens'), 0)";
+
+fn span_sort_sql(field: &str) -> String {
+    match field {
+        "duration" => format!("COALESCE({DURATION_MS_SQL}, 0)"),
+        "tokens" => SPAN_TOKENS_SQL.to_string(),
+        "cost" => SPAN_COST_SQL.to_string(),
+        "name" => "name".to_string(),
+        _ => "started_at".to_string(),
+    }
+}
+
+fn trace_sort_sql(field: &str) -> String {
+    match field {
+        "duration" => format!("COALESCE({DURATION_MS_SQL}, 0)"),
+        "cost" => format!(
+            "COALESCE((SELECT SUM({SPAN_COST_SQL}) FROM spans WHERE spans.trace_id = traces.id), 0)"
+        ),
+        "name" => "COALESCE(name, '')".to_string(),
+        _ => "started_at".to_string(),
+    }
+}
+
+/// Append the keyset condition for rows after the cursor position. The cursor
+/// row's sort value is re-read from `table` so numeric values never
+/// round-trip through the cursor; the encoded value is only used if that row
+/// has been deleted.
+fn push_keyset(
+    sql: &mut String,
+    params: &mut Vec<Value>,
+    table: &str,
+    sort_expr: &str,
+    after: Option<CursorPosition>,
+    desc: bool,
+) {
+    if let Some((value, id)) = after {
+        let op = if desc { "<" } else { ">" };
+        sql.push_str(&format!(
+            " AND ({sort_expr}, id) {op} (COALESCE((SELECT {sort_expr} FROM {table} WHERE id = ?), ?), ?)"
+        ));
+        params.push(Value::Text(id.clone()));
+        params.push(match value {
+            SortValue::Number(n) => Value::Real(n),
+            SortValue::Text(s) => Value::Text(s),
+        });
+        params.push(Value::Text(id));
+    }
+}
+
+/// Append ORDER BY (ties broken by id) and LIMIT/OFFSET clauses.
+fn push_order_and_limit(
+    sql: &mut String,
+    sort_expr: &str,
+    desc: bool,
+    limit: Option<usize>,
+    offset: Option<usize>,
+) {
+    let dir = if desc { "DESC" } else { "ASC" };
+    sql.push_str(&format!(" ORDER BY {sort_expr} {dir}, id {dir}"));
+    sql.push_str(&format!(
+        " LIMIT {} OFFSET {}",
+        limit.map_or(-1, |l| l as i64),
+        offset.unwrap_or(0)
+    ));
+}
+
+/// Rows written before v8 have no stats; they read back as empty.
+fn parse_trace_stats(json: Option<&str>) -> TraceStats {
+    json.and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or_default()
+}
+
+fn parse_deleted_at(value: Option<&str>) -> Result<Option<DateTime<Utc>>, StorageError> {
+    value
+        .map(|s| {
+            DateTime::parse_from_rfc3339(s)
+                .map_err(|e| StorageError::Database(format!("invalid deleted_at: {}", e)))
+                .map(|t| t.with_timezone(&Utc))
+        })
+        .transpose()
+}
+
+const TRACE_COLUMNS: &str = "id, name, tags_json, started_at, ended_at, machine_id, stats_json, \
+     session_id, user_id, git_commit, git_branch, repo, deleted_at";
+
+/// Raw `traces` row, in `TRACE_COLUMNS` order.
+struct TraceRow {
+    id: String,
+    name: Option<String>,
+    tags_json: String,
+    started_at: String,
+    ended_at: Option<String>,
+    machine_id: Option<String>,
+    stats_json: Option<String>,
+    session_id: Option<String>,
+    user_id: Option<String>,
+    git_commit: Option<String>,
+    git_branch: Option<String>,
+    repo: Option<String>,
+    deleted_at: Option<String>,
+}
+
+impl TraceRow {
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
         Ok(Self {
-            conn: Mutex::new(conn),
+            id: row.get(0)?,
+            name: row.get(1)?,
+            tags_json: row.get(2)?,
+            started_at: row.get(3)?,
+            ended_at: row.get(4)?,
+            machine_id: row.get(5)?,
+            stats_json: row.get(6)?,
+            session_id: row.get(7)?,
+            user_id: row.get(8)?,
+            git_commit: row.get(9)?,
+            git_branch: row.get(10)?,
+            repo: row.get(11)?,
+            deleted_at: row.get(12)?,
         })
     }
 
-    pub fn memory() -> Result<Self, StorageError> {
-        let conn = Connection::open_in_memory()?;
-        run_migrations(&conn)?;
+    fn into_trace(self) -> Result<Trace, StorageError> {
+        let id: TraceId = self
+            .id
+            .parse()
+            .map_err(|e| StorageError::Database(format!("invalid trace id: {}", e)))?;
+        let started_at: DateTime<Utc> = DateTime::parse_from_rfc3339(&self.started_at)
+            .map_err(|e| StorageError::Database(format!("invalid started_at: {}", e)))?
+            .with_timezone(&Utc);
+        let ended_at: Option<DateTime<Utc>> = self
+            .ended_at
+            .as_ref()
+            .map(|s| {
+                DateTime::parse_from_rfc3339(s)
+                    .map_err(|e| StorageError::Database(format!("invalid ended_at: {}", e)))
+                    .map(|t| t.with_timezone(&Utc))
+            })
+            .transpose()?;
+        Ok(Trace {
+            id,
+            org_id: None,
+            name: self.name,
+            tags: serde_json::from_str(&self.tags_json).unwrap_or_default(),
+            started_at,
+            ended_at,
+            machine_id: self.machine_id,
+            session_id: self.session_id,
+            user_id: self.user_id,
+            git_commit: self.git_commit,
+            git_branch: self.git_branch,
+            repo: self.repo,
+            stats: parse_trace_stats(self.stats_json.as_deref()),
+            deleted_at: parse_deleted_at(self.deleted_at.as_deref())?,
+        })
+    }
+}
+
+const SPAN_COLUMNS: &str =
+    "id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, input_json, output_json, name_normalized";
+/// `SPAN_COLUMNS` with the payloads left out, for `SpanFilter::skip_payloads`.
+const SPAN_COLUMNS_WITHOUT_PAYLOADS: &str =
+    "id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, NULL, NULL, name_normalized";
+
+/// Raw `spans` row, in `SPAN_COLUMNS` order.
+struct SpanRow {
+    id: String,
+    trace_id: String,
+    parent_id: Option<String>,
+    name: String,
+    kind_json: String,
+    status: String,
+    error: Option<String>,
+    started_at: String,
+    ended_at: Option<String>,
+    input_json: Option<String>,
+    output_json: Option<String>,
+    name_normalized: Option<String>,
+}
+
+impl SpanRow {
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
         Ok(Self {
-            conn: Mutex::new(conn),
+            id: row.get(0)?,
+            trace_id: row.get(1)?,
+            parent_id: row.get(2)?,
+            name: row.get(3)?,
+            kind_json: row.get(4)?,
+            status: row.get(5)?,
+            error: row.get(6)?,
+            started_at: row.get(7)?,
+            ended_at: row.get(8)?,
+            input_json: row.get(9)?,
+            output_json: row.get(10)?,
+            name_normalized: row.get(11)?,
         })
     }
 
-    fn deserialize_span(
-        id: &str,
-        trace_id: &str,
-        parent_id: Option<&str>,
-        name: &str,
-        kind_json: &str,
-        status_str: &str,
-        error: Option<&str>,
-        started_at: &str,
-        ended_at: Option<&str>,
-        input_json: Option<&str>,
-        output_json: Option<&str>,
-    ) -> Result<Span, StorageError> {
-        let id: SpanId = id
+    fn into_span(self) -> Result<Span, StorageError> {
+        let id: SpanId = self
+            .id
             .parse()
             .map_err(|e| StorageError::Database(format!("invalid span id: {}", e)))?;
-        let trace_id: TraceId = trace_id
+        let trace_id: TraceId = self
+            .trace_id
             .parse()
             .map_err(|e| StorageError::Database(format!("invalid trace id: {}", e)))?;
-        let parent_id: Option<SpanId> = parent_id
+        let parent_id: Option<SpanId> = self
+            .parent_id
+            .as_ref()
             .map(|s| {
                 s.parse()
                     .map_err(|e| StorageError::Database(format!("invalid parent id: {}", e)))
             })
             .transpose()?;
-        let _kind: SpanKind = serde_json::from_str(kind_json)?;
-        let _status = match status_str {
-            "running" => SpanStatus::Running,
-            "completed" => SpanStatus::Completed,
-            "failed" => SpanStatus::Failed {
-                error: error.unwrap_or_default().to_string(),
-            },
-            other => {
-                return Err(StorageError::Database(format!(
-                    "unknown status: {}",
-                    other
-                )))
-            }
+        let kind: serde_json::Value = serde_json::from_str(&self.kind_json)?;
+        let status = match self.status.as_str() {
+            "running" => serde_json::json!("running"),
+            "completed" => serde_json::json!("completed"),
+            "failed" => serde_json::json!({"failed": {"error": self.error.unwrap_or_default()}}),
+            other => return Err(StorageError::Database(format!("unknown status: {}", other))),
         };
-        let started_at: DateTime<Utc> = DateTime::parse_from_rfc3339(started_at)
+        let started_at: DateTime<Utc> = DateTime::parse_from_rfc3339(&self.started_at)
             .map_err(|e| StorageError::Database(format!("invalid started_at: {}", e)))?
             .with_timezone(&Utc);
-        let ended_at: Option<DateTime<Utc>> = ended_at
+        let ended_at: Option<DateTime<Utc>> = self
+            .ended_at
+            .as_ref()
             .map(|s| {
                 DateTime::parse_from_rfc3339(s)
                     .map_err(|e| StorageError::Database(format!("invalid ended_at: {}", e)))
                     .map(|t| t.with_timezone(&Utc))
             })
             .transpose()?;
-        let input: Option<serde_json::Value> =
-            input_json.map(|s| serde_json::from_str(s)).transpose()?;
-        let output: Option<serde_json::Value> =
-            output_json.map(|s| serde_json::from_str(s)).transpose()?;
+        let input: Option<serde_json::Value> = self
+            .input_json
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+        let output: Option<serde_json::Value> = self
+            .output_json
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
 
         // Reconstruct span via serde (Span fields are private)
         let span_value = serde_json::json!({
             "id": id,
             "trace_id": trace_id,
             "parent_id": parent_id,
-            "name": name,
-            "kind": serde_json::from_str::<serde_json::Value>(kind_json)?,
-            "status": match status_str {
-                "running" => serde_json::json!("running"),
-                "completed" => serde_json::json!("completed"),
-                _ => serde_json::json!({"failed": {"error": error.unwrap_or_default()}}),
-            },
+            "name": self.name,
+            "name_normalized": self.name_normalized,
+            "kind": kind,
+            "status": status,
             "started_at": started_at,
             "ended_at": ended_at,
             "input": input,
             "output": output,
         });
-        let span: Span = serde_json::from_value(span_value)?;
-        Ok(span)
+        Ok(serde_json::from_value(span_value)?)
+    }
+}
+
+// --- SqliteBackend ---
+
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")?;
+        run_migrations(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn memory() -> Result<Self, StorageError> {
+        let conn = Connection::open_in_memory()?;
+        run_migrations(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
     }
 }
 
@@ -325,13 +807,20 @@ impl StorageBackend for SqliteBackend {
         "sqlite"
     }
 
+    async fn ping(&self) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        conn.query_row("PRAGMA schema_version", [], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+
     // --- Trace operations ---
 
     async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
         let conn = self.conn.lock().await;
         let tags_json = serde_json::to_string(&trace.tags)?;
+        let stats_json = serde_json::to_string(&trace.stats)?;
         conn.execute(
-            "INSERT OR REPLACE INTO traces (id, name, tags_json, started_at, ended_at, machine_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT OR REPLACE INTO traces (id, name, tags_json, started_at, ended_at, machine_id, stats_json, session_id, user_id, git_commit, git_branch, repo, deleted_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
             params![
                 trace.id.to_string(),
                 trace.name,
@@ -339,6 +828,13 @@ impl StorageBackend for SqliteBackend {
                 trace.started_at.to_rfc3339(),
                 trace.ended_at.map(|t| t.to_rfc3339()),
                 trace.machine_id,
+                stats_json,
+                trace.session_id,
+                trace.user_id,
+                trace.git_commit,
+                trace.git_branch,
+                trace.repo,
+                trace.deleted_at.map(|t| t.to_rfc3339()),
             ],
         )?;
         Ok(())
@@ -347,47 +843,13 @@ impl StorageBackend for SqliteBackend {
     async fn get_trace(&self, id: TraceId) -> Result<Option<Trace>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, name, tags_json, started_at, ended_at, machine_id FROM traces WHERE id = ?1",
+            &format!("SELECT {TRACE_COLUMNS} FROM traces WHERE id = ?1"),
             params![id.to_string()],
-            |row| {
-                let id_str: String = row.get(0)?;
-                let name: Option<String> = row.get(1)?;
-                let tags_json: String = row.get(2)?;
-                let started_at_str: String = row.get(3)?;
-                let ended_at_str: Option<String> = row.get(4)?;
-                let machine_id: Option<String> = row.get(5)?;
-                Ok((id_str, name, tags_json, started_at_str, ended_at_str, machine_id))
-            },
+            TraceRow::read,
         );
 
         match result {
-            Ok((id_str, name, tags_json, started_at_str, ended_at_str, machine_id)) => {
-                let id: TraceId = id_str
-                    .parse()
-                    .map_err(|e| StorageError::Database(format!("invalid trace id: {}", e)))?;
-                let started_at: DateTime<Utc> = DateTime::parse_from_rfc3339(&started_at_str)
-                    .map_err(|e| StorageError::Database(format!("invalid started_at: {}", e)))?
-                    .with_timezone(&Utc);
-                let ended_at: Option<DateTime<Utc>> = ended_at_str
-                    .as_ref()
-                    .map(|s| {
-                        DateTime::parse_from_rfc3339(s)
-                            .map_err(|e| StorageError::Database(format!("invalid ended_at: {}", e)))
-                            .map(|t| t.with_timezone(&Utc))
-                    })
-                    .transpose()?;
-                let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-
-                Ok(Some(Trace {
-                    id,
-                    org_id: None,
-                    name,
-                    tags,
-                    started_at,
-                    ended_at,
-                    machine_id,
-                }))
-            }
+            Ok(row) => row.into_trace().map(Some),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(StorageError::Database(e.to_string())),
         }
@@ -395,83 +857,79 @@ impl StorageBackend for SqliteBackend {
 
     async fn list_traces(&self, filter: &TraceFilter) -> Result<Vec<Trace>, StorageError> {
         let conn = self.conn.lock().await;
-        let mut sql = String::from(
-            "SELECT id, name, tags_json, started_at, ended_at, machine_id FROM traces WHERE 1=1",
+        let mut sql = format!("SELECT {TRACE_COLUMNS} FROM traces WHERE 1=1");
+        let mut params_vec: Vec<Value> = Vec::new();
+
+        push_trace_predicates(&mut sql, &mut params_vec, filter);
+
+        let sort_expr = trace_sort_sql(filter.sort_field());
+        push_keyset(
+            &mut sql,
+            &mut params_vec,
+            "traces",
+            &sort_expr,
+            filter.cursor_position()?,
+            filter.sort_desc(),
         );
-        let mut params_vec: Vec<String> = Vec::new();
+        push_order_and_limit(&mut sql, &sort_expr, filter.sort_desc(), filter.limit, filter.offset);
 
-        if let Some(ref name) = filter.name_contains {
-            sql.push_str(" AND name LIKE ?");
-            params_vec.push(format!("%{}%", name));
-        }
-        if let Some(since) = filter.since {
-            sql.push_str(" AND started_at >= ?");
-            params_vec.push(since.to_rfc3339());
-        }
-        if let Some(until) = filter.until {
-            sql.push_str(" AND started_at <= ?");
-            params_vec.push(until.to_rfc3339());
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(params_vec.iter()), TraceRow::read)?;
+
+        let mut traces = Vec::new();
+        for row in rows {
+            traces.push(row?.into_trace()?);
         }
 
-        sql.push_str(" ORDER BY started_at DESC");
+        Ok(traces)
+    }
 
-        if let Some(limit) = filter.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
-        }
+    async fn list_sessions(&self, filter: &TraceFilter) -> Result<Vec<Session>, StorageError> {
+        let conn = self.conn.lock().await;
+        let stat = |field: &str| format!("SUM(COALESCE(json_extract(stats_json, '$.{field}'), 0))");
+        let mut sql = format!(
+            "SELECT session_id, MAX(user_id), COUNT(*), {}, {}, {}, {}, {}, MIN(started_at), MAX(COALESCE(ended_at, started_at)) FROM traces WHERE session_id IS NOT NULL",
+            stat("span_count"), stat("error_count"), stat("input_tokens"), stat("output_tokens"), stat("total_cost"),
+        );
+        let mut params_vec: Vec<Value> = Vec::new();
+        push_trace_predicates(&mut sql, &mut params_vec, filter);
+        sql.push_str(" GROUP BY session_id ORDER BY MAX(COALESCE(ended_at, started_at)) DESC, session_id");
 
         let mut stmt = conn.prepare(&sql)?;
-        let params_refs: Vec<&dyn rusqlite::ToSql> =
-            params_vec.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
-
-        let rows = stmt.query_map(params_refs.as_slice(), |row| {
-            let id_str: String = row.get(0)?;
-            let name: Option<String> = row.get(1)?;
-            let tags_json: String = row.get(2)?;
-            let started_at_str: String = row.get(3)?;
-            let ended_at_str: Option<String> = row.get(4)?;
-            let machine_id: Option<String> = row.get(5)?;
+        let rows = stmt.query_map(params_from_iter(params_vec.iter()), |row| {
             Ok((
-                id_str,
-                name,
-                tags_json,
-                started_at_str,
-                ended_at_str,
-                machine_id,
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                [row.get::<_, i64>(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?],
+                row.get::<_, f64>(7)?,
+                row.get::<_, String>(8)?,
+                row.get::<_, String>(9)?,
             ))
         })?;
-
-        let mut traces = Vec::new();
-        for row_result in rows {
-            let (id_str, name, tags_json, started_at_str, ended_at_str, machine_id) = row_result?;
-
-            let id: TraceId = id_str
-                .parse()
-                .map_err(|e| StorageError::Database(format!("invalid trace id: {}", e)))?;
-            let started_at: DateTime<Utc> = DateTime::parse_from_rfc3339(&started_at_str)
-                .map_err(|e| StorageError::Database(format!("invalid started_at: {}", e)))?
-                .with_timezone(&Utc);
-            let ended_at: Option<DateTime<Utc>> = ended_at_str
-                .as_ref()
-                .map(|s| {
-                    DateTime::parse_from_rfc3339(s)
-                        .map_err(|e| StorageError::Database(format!("invalid ended_at: {}", e)))
-                        .map(|t| t.with_timezone(&Utc))
-                })
-                .transpose()?;
-            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
-
-            traces.push(Trace {
+        let parse = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|e| StorageError::Database(format!("invalid timestamp: {}", e)))
+        };
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (id, user_id, [trace_count, span_count, error_count, input_tokens, output_tokens], total_cost, started_at, last_activity_at) = row?;
+            let (started_at, last_activity_at) = (parse(&started_at)?, parse(&last_activity_at)?);
+            sessions.push(Session {
                 id,
-                org_id: None,
-                name,
-                tags,
+                user_id,
+                trace_count: trace_count as u64,
+                span_count: span_count as u64,
+                error_count: error_count as u64,
+                input_tokens: input_tokens as u64,
+                output_tokens: output_tokens as u64,
+                total_cost,
                 started_at,
-                ended_at,
-                machine_id,
+                last_activity_at,
+                duration_ms: (last_activity_at - started_at).num_milliseconds(),
             });
         }
-
-        Ok(traces)
+        Ok(sessions)
     }
 
     async fn delete_trace(&self, trace_id: TraceId) -> Result<bool, StorageError> {
@@ -485,6 +943,25 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted > 0)
     }
 
+    async fn delete_traces_by_filter(&self, filter: &TraceFilter) -> Result<usize, StorageError> {
+        let mut conn = self.conn.lock().await;
+        let mut predicates = String::new();
+        let mut params_vec: Vec<Value> = Vec::new();
+        push_trace_predicates(&mut predicates, &mut params_vec, filter);
+
+        let tx = conn.transaction()?;
+        tx.execute(
+            &format!("DELETE FROM spans WHERE trace_id IN (SELECT id FROM traces WHERE 1=1{predicates})"),
+            params_from_iter(params_vec.iter()),
+        )?;
+        let deleted = tx.execute(
+            &format!("DELETE FROM traces WHERE 1=1{predicates}"),
+            params_from_iter(params_vec.iter()),
+        )?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
     // --- Span operations ---
 
     async fn save_span(&self, span: &Span) -> Result<(), StorageError> {
@@ -512,8 +989,8 @@ impl StorageBackend for SqliteBackend {
             .transpose()?;
 
         conn.execute(
-            "INSERT OR REPLACE INTO spans (id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, input_json, output_json) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-            params![id, trace_id, parent_id, name, kind_json, status_str, error, started_at, ended_at, input_json, output_json],
+            "INSERT OR REPLACE INTO spans (id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, input_json, output_json, name_normalized) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![id, trace_id, parent_id, name, kind_json, status_str, error, started_at, ended_at, input_json, output_json, span.name_normalized()],
         )?;
 
         tracing::trace!(span_id = %span.id(), "saved span to sqlite");
@@ -523,56 +1000,13 @@ impl StorageBackend for SqliteBackend {
     async fn get_span(&self, id: SpanId) -> Result<Option<Span>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, input_json, output_json FROM spans WHERE id = ?1",
+            &format!("SELECT {SPAN_COLUMNS} FROM spans WHERE id = ?1"),
             params![id.to_string()],
-            |row| {
-                let id: String = row.get(0)?;
-                let trace_id: String = row.get(1)?;
-                let parent_id: Option<String> = row.get(2)?;
-                let name: String = row.get(3)?;
-                let kind_json: String = row.get(4)?;
-                let status_str: String = row.get(5)?;
-                let error: Option<String> = row.get(6)?;
-                let started_at: String = row.get(7)?;
-                let ended_at: Option<String> = row.get(8)?;
-                let input_json: Option<String> = row.get(9)?;
-                let output_json: Option<String> = row.get(10)?;
-                Ok((
-                    id, trace_id, parent_id, name, kind_json, status_str, error, started_at,
-                    ended_at, input_json, output_json,
-                ))
-            },
+            SpanRow::read,
         );
 
         match result {
-            Ok((
-                id,
-                trace_id,
-                parent_id,
-                name,
-                kind_json,
-                status_str,
-                error,
-                started_at,
-                ended_at,
-                input_json,
-                output_json,
-            )) => {
-                let span = Self::deserialize_span(
-                    &id,
-                    &trace_id,
-                    parent_id.as_deref(),
-                    &name,
-                    &kind_json,
-                    &status_str,
-                    error.as_deref(),
-                    &started_at,
-                    ended_at.as_deref(),
-                    input_json.as_deref(),
-                    output_json.as_deref(),
-                )?;
-                Ok(Some(span))
-            }
+            Ok(row) => Ok(Some(row.into_span()?)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(StorageError::Database(e.to_string())),
         }
@@ -580,99 +1014,33 @@ impl StorageBackend for SqliteBackend {
 
     async fn list_spans(&self, filter: &SpanFilter) -> Result<Vec<Span>, StorageError> {
         let conn = self.conn.lock().await;
-        let mut sql = String::from(
-            "SELECT id, trace_id, parent_id, name, kind_json, status, error, started_at, ended_at, input_json, output_json FROM spans WHERE 1=1",
+        let columns = if filter.skip_payloads {
+            SPAN_COLUMNS_WITHOUT_PAYLOADS
+        } else {
+            SPAN_COLUMNS
+        };
+        let mut sql = format!("SELECT {columns} FROM spans WHERE 1=1");
+        let mut params_vec: Vec<Value> = Vec::new();
+
+        push_span_predicates(&mut sql, &mut params_vec, filter);
+
+        let sort_expr = span_sort_sql(filter.sort_field());
+        push_keyset(
+            &mut sql,
+            &mut params_vec,
+            "spans",
+            &sort_expr,
+            filter.cursor_position()?,
+            filter.sort_desc(),
         );
-        let mut params_vec: Vec<String> = Vec::new();
-
-        if let Some(ref trace_id) = filter.trace_id {
-            sql.push_str(" AND trace_id = ?");
-            params_vec.push(trace_id.to_string());
-        }
-        if let Some(ref status) = filter.status {
-            sql.push_str(" AND status = ?");
-            params_vec.push(status.clone());
-        }
-        if let Some(since) = filter.since {
-            sql.push_str(" AND started_at >= ?");
-            params_vec.push(since.to_rfc3339());
-        }
-        if let Some(until) = filter.until {
-            sql.push_str(" AND started_at <= ?");
-            params_vec.push(until.to_rfc3339());
-        }
-        if let Some(ref name) = filter.name_contains {
-            sql.push_str(" AND name LIKE ?");
-            params_vec.push(format!("%{}%", name));
-        }
-
-        sql.push_str(" ORDER BY started_at DESC");
-
-        if let Some(limit) = filter.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
-        }
+        push_order_and_limit(&mut sql, &sort_expr, filter.sort_desc(), filter.limit, filter.offset);
 
         let mut stmt = conn.prepare(&sql)?;
-        let params_refs: Vec<&dyn rusqlite::ToSql> =
-            params_vec.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
-
-        let rows = stmt.query_map(params_refs.as_slice(), |row| {
-            let id: String = row.get(0)?;
-            let trace_id: String = row.get(1)?;
-            let parent_id: Option<String> = row.get(2)?;
-            let name: String = row.get(3)?;
-            let kind_json: String = row.get(4)?;
-            let status_str: String = row.get(5)?;
-            let error: Option<String> = row.get(6)?;
-            let started_at: String = row.get(7)?;
-            let ended_at: Option<String> = row.get(8)?;
-            let input_json: Option<String> = row.get(9)?;
-            let output_json: Option<String> = row.get(10)?;
-            Ok((
-                id,
-                trace_id,
-                parent_id,
-                name,
-                kind_json,
-                status_str,
-                error,
-                started_at,
-                ended_at,
-                input_json,
-                output_json,
-            ))
-        })?;
+        let rows = stmt.query_map(params_from_iter(params_vec.iter()), SpanRow::read)?;
 
         let mut spans = Vec::new();
-        for row_result in rows {
-            let (
-                id,
-                trace_id,
-                parent_id,
-                name,
-                kind_json,
-                status_str,
-                error,
-                started_at,
-                ended_at,
-                input_json,
-                output_json,
-            ) = row_result?;
-
-            let span = Self::deserialize_span(
-                &id,
-                &trace_id,
-                parent_id.as_deref(),
-                &name,
-                &kind_json,
-                &status_str,
-                error.as_deref(),
-                &started_at,
-                ended_at.as_deref(),
-                input_json.as_deref(),
-                output_json.as_deref(),
-            )?;
-            spans.push(span);
+        for row in rows {
+            spans.push(row?.into_span()?);
         }
 
         tracing::debug!(count = spans.len(), "loaded spans from sqlite");
@@ -694,6 +1062,15 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted)
     }
 
+    async fn delete_spans_by_filter(&self, filter: &SpanFilter) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut sql = String::from("DELETE FROM spans WHERE 1=1");
+        let mut params_vec: Vec<Value> = Vec::new();
+        push_span_predicates(&mut sql, &mut params_vec, filter);
+        let deleted = conn.execute(&sql, params_from_iter(params_vec.iter()))?;
+        Ok(deleted)
+    }
+
     async fn clear_spans(&self) -> Result<(), StorageError> {
         let conn = self.conn.lock().await;
         conn.execute("DELETE FROM spans", [])?;
@@ -705,7 +1082,7 @@ impl StorageBackend for SqliteBackend {
     async fn save_dataset(&self, dataset: &Dataset) -> Result<(), StorageError> {
         let conn = self.conn.lock().await;
         conn.execute(
-            "INSERT OR REPLACE INTO datasets (id, org_id, name, description, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT OR REPLACE INTO datasets (id, org_id, name, description, created_at, updated_at, deleted_at, version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![
                 dataset.id.to_string(),
                 dataset.org_id.map(|id| id.to_string()),
@@ -713,6 +1090,8 @@ impl StorageBackend for SqliteBackend {
                 dataset.description,
                 dataset.created_at.to_rfc3339(),
                 dataset.updated_at.to_rfc3339(),
+                dataset.deleted_at.map(|t| t.to_rfc3339()),
+                dataset.version as i64,
             ],
         )?;
         Ok(())
@@ -721,7 +1100,7 @@ impl StorageBackend for SqliteBackend {
     async fn get_dataset(&self, id: DatasetId) -> Result<Option<Dataset>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, org_id, name, description, created_at, updated_at FROM datasets WHERE id = ?1",
+            "SELECT id, org_id, name, description, created_at, updated_at, deleted_at, version FROM datasets WHERE id = ?1",
             params![id.to_string()],
             |row| {
                 let id: String = row.get(0)?;
@@ -730,12 +1109,14 @@ impl StorageBackend for SqliteBackend {
                 let description: Option<String> = row.get(3)?;
                 let created_at: String = row.get(4)?;
                 let updated_at: String = row.get(5)?;
-                Ok((id, org_id, name, description, created_at, updated_at))
+                let deleted_at: Option<String> = row.get(6)?;
+                let version: i64 = row.get(7)?;
+                Ok((id, org_id, name, description, created_at, updated_at, deleted_at, version))
             },
         );
 
         match result {
-            Ok((id_str, org_id_str, name, description, created_at_str, updated_at_str)) => {
+            Ok((id_str, org_id_str, name, description, created_at_str, updated_at_str, deleted_at_str, version)) => {
                 let id: DatasetId = id_str
                     .parse()
                     .map_err(|e| StorageError::Database(format!("invalid dataset id: {}", e)))?;
@@ -754,6 +1135,8 @@ impl StorageBackend for SqliteBackend {
                     description,
                     created_at,
                     updated_at,
+                    deleted_at: parse_deleted_at(deleted_at_str.as_deref())?,
+                    version: version as u64,
                 }))
             }
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -764,7 +1147,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_datasets(&self) -> Result<Vec<Dataset>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt =
-            conn.prepare("SELECT id, org_id, name, description, created_at, updated_at FROM datasets")?;
+            conn.prepare("SELECT id, org_id, name, description, created_at, updated_at, deleted_at, version FROM datasets")?;
         let rows = stmt.query_map([], |row| {
             let id: String = row.get(0)?;
             let org_id: Option<String> = row.get(1)?;
@@ -772,12 +1155,15 @@ impl StorageBackend for SqliteBackend {
             let description: Option<String> = row.get(3)?;
             let created_at: String = row.get(4)?;
             let updated_at: String = row.get(5)?;
-            Ok((id, org_id, name, description, created_at, updated_at))
+            let deleted_at: Option<String> = row.get(6)?;
+            let version: i64 = row.get(7)?;
+            Ok((id, org_id, name, description, created_at, updated_at, deleted_at, version))
         })?;
 
         let mut datasets = Vec::new();
         for row_result in rows {
-            let (id_str, org_id_str, name, description, created_at_str, updated_at_str) = row_result?;
+            let (id_str, org_id_str, name, description, created_at_str, updated_at_str, deleted_at_str, version) =
+                row_result?;
             let id: DatasetId = id_str
                 .parse()
                 .map_err(|e| StorageError::Database(format!("invalid dataset id: {}", e)))?;
@@ -796,6 +1182,8 @@ impl StorageBackend for SqliteBackend {
                 description,
                 created_at,
                 updated_at,
+                deleted_at: parse_deleted_at(deleted_at_str.as_deref())?,
+                version: version as u64,
             });
         }
         Ok(datasets)
@@ -808,6 +1196,23 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted > 0)
     }
 
+    async fn update_dataset(&self, dataset: &Dataset, expected: u64) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let updated = conn.execute(
+            "UPDATE datasets SET name = ?1, description = ?2, updated_at = ?3, deleted_at = ?4, version = ?5 WHERE id = ?6 AND version = ?7",
+            params![
+                dataset.name,
+                dataset.description,
+                dataset.updated_at.to_rfc3339(),
+                dataset.deleted_at.map(|t| t.to_rfc3339()),
+                dataset.version as i64,
+                dataset.id.to_string(),
+                expected as i64,
+            ],
+        )?;
+        Ok(updated > 0)
+    }
+
     // --- Datapoint operations ---
 
     async fn save_datapoint(&self, dp: &Datapoint) -> Result<(), StorageError> {
@@ -816,7 +1221,7 @@ impl StorageBackend for SqliteBackend {
         let source_str = serde_json::to_value(&dp.source)?;
         let source_str = source_str.as_str().unwrap_or("manual");
         conn.execute(
-            "INSERT OR REPLACE INTO datapoints (id, dataset_id, kind_json, source, source_span_id, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT OR REPLACE INTO datapoints (id, dataset_id, kind_json, source, source_span_id, created_at, version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![
                 dp.id.to_string(),
                 dp.dataset_id.to_string(),
@@ -824,6 +1229,7 @@ impl StorageBackend for SqliteBackend {
                 source_str,
                 dp.source_span_id.map(|id| id.to_string()),
                 dp.created_at.to_rfc3339(),
+                dp.version as i64,
             ],
         )?;
         Ok(())
@@ -832,7 +1238,7 @@ impl StorageBackend for SqliteBackend {
     async fn get_datapoint(&self, id: DatapointId) -> Result<Option<Datapoint>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at FROM datapoints WHERE id = ?1",
+            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at, version FROM datapoints WHERE id = ?1",
             params![id.to_string()],
             |row| {
                 let id: String = row.get(0)?;
@@ -841,12 +1247,13 @@ impl StorageBackend for SqliteBackend {
                 let source: String = row.get(3)?;
                 let source_span_id: Option<String> = row.get(4)?;
                 let created_at: String = row.get(5)?;
-                Ok((id, dataset_id, kind_json, source, source_span_id, created_at))
+                let version: i64 = row.get(6)?;
+                Ok((id, dataset_id, kind_json, source, source_span_id, created_at, version))
             },
         );
 
         match result {
-            Ok((id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str)) => {
+            Ok((id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str, version)) => {
                 let id: DatapointId = id_str
                     .parse()
                     .map_err(|e| StorageError::Database(format!("invalid datapoint id: {}", e)))?;
@@ -871,6 +1278,7 @@ impl StorageBackend for SqliteBackend {
                     source,
                     source_span_id,
                     created_at,
+                    version: version as u64,
                 }))
             }
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -881,7 +1289,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_datapoints(&self, dataset_id: DatasetId) -> Result<Vec<Datapoint>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at FROM datapoints WHERE dataset_id = ?1",
+            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at, version FROM datapoints WHERE dataset_id = ?1",
         )?;
         let rows = stmt.query_map(params![dataset_id.to_string()], |row| {
             let id: String = row.get(0)?;
@@ -890,12 +1298,13 @@ impl StorageBackend for SqliteBackend {
             let source: String = row.get(3)?;
             let source_span_id: Option<String> = row.get(4)?;
             let created_at: String = row.get(5)?;
-            Ok((id, dataset_id, kind_json, source, source_span_id, created_at))
+            let version: i64 = row.get(6)?;
+            Ok((id, dataset_id, kind_json, source, source_span_id, created_at, version))
         })?;
 
         let mut datapoints = Vec::new();
         for row_result in rows {
-            let (id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str) =
+            let (id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str, version) =
                 row_result?;
             let id: DatapointId = id_str
                 .parse()
@@ -921,6 +1330,7 @@ impl StorageBackend for SqliteBackend {
                 source,
                 source_span_id,
                 created_at,
+                version: version as u64,
             });
         }
         Ok(datapoints)
@@ -929,7 +1339,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_datapoints_all(&self) -> Result<Vec<Datapoint>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at FROM datapoints",
+            "SELECT id, dataset_id, kind_json, source, source_span_id, created_at, version FROM datapoints",
         )?;
         let rows = stmt.query_map([], |row| {
             let id: String = row.get(0)?;
@@ -938,12 +1348,13 @@ impl StorageBackend for SqliteBackend {
             let source: String = row.get(3)?;
             let source_span_id: Option<String> = row.get(4)?;
             let created_at: String = row.get(5)?;
-            Ok((id, dataset_id, kind_json, source, source_span_id, created_at))
+            let version: i64 = row.get(6)?;
+            Ok((id, dataset_id, kind_json, source, source_span_id, created_at, version))
         })?;
 
         let mut datapoints = Vec::new();
         for row_result in rows {
-            let (id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str) =
+            let (id_str, dataset_id_str, kind_json, source_str, source_span_id_str, created_at_str, version) =
                 row_result?;
             let id: DatapointId = id_str
                 .parse()
@@ -969,6 +1380,7 @@ impl StorageBackend for SqliteBackend {
                 source,
                 source_span_id,
                 created_at,
+                version: version as u64,
             });
         }
         Ok(datapoints)
@@ -981,6 +1393,16 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted > 0)
     }
 
+    async fn update_datapoint(&self, dp: &Datapoint, expected: u64) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let kind_json = serde_json::to_string(&dp.kind)?;
+        let updated = conn.execute(
+            "UPDATE datapoints SET kind_json = ?1, version = ?2 WHERE id = ?3 AND version = ?4",
+            params![kind_json, dp.version as i64, dp.id.to_string(), expected as i64],
+        )?;
+        Ok(updated > 0)
+    }
+
     async fn delete_dataset_datapoints(
         &self,
         dataset_id: DatasetId,
@@ -1007,8 +1429,12 @@ impl StorageBackend for SqliteBackend {
             .as_ref()
             .map(|v| serde_json::to_string(v))
             .transpose()?;
+        let review_json = item.review.as_ref().map(serde_json::to_string).transpose()?;
+        // An upsert rather than REPLACE, which would delete the row and
+        // cascade to its submissions
         conn.execute(
-            "INSERT OR REPLACE INTO queue_items (id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO queue_items (id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at, rejected_reason, resolved_at, review_json) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT(id) DO UPDATE SET dataset_id = excluded.dataset_id, datapoint_id = excluded.datapoint_id, status = excluded.status, claimed_by = excluded.claimed_by, claimed_at = excluded.claimed_at, original_data_json = excluded.original_data_json, edited_data_json = excluded.edited_data_json, created_at = excluded.created_at, rejected_reason = excluded.rejected_reason, resolved_at = excluded.resolved_at, review_json = excluded.review_json",
             params![
                 item.id.to_string(),
                 item.dataset_id.to_string(),
@@ -1019,6 +1445,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 item.created_at.to_rfc3339(),
+                item.rejected_reason,
+                item.resolved_at.map(|t| t.to_rfc3339()),
+                review_json,
             ],
         )?;
         Ok(())
@@ -1027,7 +1456,7 @@ impl StorageBackend for SqliteBackend {
     async fn get_queue_item(&self, id: QueueItemId) -> Result<Option<QueueItem>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at FROM queue_items WHERE id = ?1",
+            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at, rejected_reason, resolved_at, review_json FROM queue_items WHERE id = ?1",
             params![id.to_string()],
             |row| {
                 let id: String = row.get(0)?;
@@ -1039,9 +1468,12 @@ impl StorageBackend for SqliteBackend {
                 let original_data_json: Option<String> = row.get(6)?;
                 let edited_data_json: Option<String> = row.get(7)?;
                 let created_at: String = row.get(8)?;
+                let rejected_reason: Option<String> = row.get(9)?;
+                let resolved_at: Option<String> = row.get(10)?;
+                let review_json: Option<String> = row.get(11)?;
                 Ok((
                     id, dataset_id, datapoint_id, status, claimed_by, claimed_at,
-                    original_data_json, edited_data_json, created_at,
+                    original_data_json, edited_data_json, created_at, rejected_reason, resolved_at, review_json,
                 ))
             },
         );
@@ -1057,6 +1489,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 created_at_str,
+                rejected_reason,
+                resolved_at_str,
+                review_json,
             )) => {
                 let id: QueueItemId = id_str
                     .parse()
@@ -1082,6 +1517,14 @@ impl StorageBackend for SqliteBackend {
                 let created_at = DateTime::parse_from_rfc3339(&created_at_str)
                     .map_err(|e| StorageError::Database(format!("invalid created_at: {}", e)))?
                     .with_timezone(&Utc);
+                let resolved_at = resolved_at_str
+                    .map(|s| {
+                        DateTime::parse_from_rfc3339(&s)
+                            .map_err(|e| StorageError::Database(format!("invalid resolved_at: {}", e)))
+                            .map(|t| t.with_timezone(&Utc))
+                    })
+                    .transpose()?;
+                let review = review_json.map(|s| serde_json::from_str(&s)).transpose()?;
                 Ok(Some(QueueItem {
                     id,
                     dataset_id,
@@ -1091,6 +1534,9 @@ impl StorageBackend for SqliteBackend {
                     claimed_at,
                     original_data,
                     edited_data,
+                    rejected_reason,
+                    resolved_at,
+                    review,
                     created_at,
                 }))
             }
@@ -1102,7 +1548,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_queue_items(&self, dataset_id: DatasetId) -> Result<Vec<QueueItem>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at FROM queue_items WHERE dataset_id = ?1",
+            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at, rejected_reason, resolved_at, review_json FROM queue_items WHERE dataset_id = ?1",
         )?;
         let rows = stmt.query_map(params![dataset_id.to_string()], |row| {
             let id: String = row.get(0)?;
@@ -1114,6 +1560,9 @@ impl StorageBackend for SqliteBackend {
             let original_data_json: Option<String> = row.get(6)?;
             let edited_data_json: Option<String> = row.get(7)?;
             let created_at: String = row.get(8)?;
+            let rejected_reason: Option<String> = row.get(9)?;
+            let resolved_at: Option<String> = row.get(10)?;
+            let review_json: Option<String> = row.get(11)?;
             Ok((
                 id,
                 dataset_id,
@@ -1124,6 +1573,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 created_at,
+                rejected_reason,
+                resolved_at,
+                review_json,
             ))
         })?;
 
@@ -1139,6 +1591,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 created_at_str,
+                rejected_reason,
+                resolved_at_str,
+                review_json,
             ) = row_result?;
             let id: QueueItemId = id_str
                 .parse()
@@ -1164,6 +1619,14 @@ impl StorageBackend for SqliteBackend {
             let created_at = DateTime::parse_from_rfc3339(&created_at_str)
                 .map_err(|e| StorageError::Database(format!("invalid created_at: {}", e)))?
                 .with_timezone(&Utc);
+            let resolved_at = resolved_at_str
+                .map(|s| {
+                    DateTime::parse_from_rfc3339(&s)
+                        .map_err(|e| StorageError::Database(format!("invalid resolved_at: {}", e)))
+                        .map(|t| t.with_timezone(&Utc))
+                })
+                .transpose()?;
+            let review = review_json.map(|s| serde_json::from_str(&s)).transpose()?;
             items.push(QueueItem {
                 id,
                 dataset_id,
@@ -1173,6 +1636,9 @@ impl StorageBackend for SqliteBackend {
                 claimed_at,
                 original_data,
                 edited_data,
+                rejected_reason,
+                resolved_at,
+                review,
                 created_at,
             });
         }
@@ -1182,7 +1648,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_queue_items_all(&self) -> Result<Vec<QueueItem>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at FROM queue_items",
+            "SELECT id, dataset_id, datapoint_id, status, claimed_by, claimed_at, original_data_json, edited_data_json, created_at, rejected_reason, resolved_at, review_json FROM queue_items",
         )?;
         let rows = stmt.query_map([], |row| {
             let id: String = row.get(0)?;
@@ -1194,6 +1660,9 @@ impl StorageBackend for SqliteBackend {
             let original_data_json: Option<String> = row.get(6)?;
             let edited_data_json: Option<String> = row.get(7)?;
             let created_at: String = row.get(8)?;
+            let rejected_reason: Option<String> = row.get(9)?;
+            let resolved_at: Option<String> = row.get(10)?;
+            let review_json: Option<String> = row.get(11)?;
             Ok((
                 id,
                 dataset_id,
@@ -1204,6 +1673,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 created_at,
+                rejected_reason,
+                resolved_at,
+                review_json,
             ))
         })?;
 
@@ -1219,6 +1691,9 @@ impl StorageBackend for SqliteBackend {
                 original_data_json,
                 edited_data_json,
                 created_at_str,
+                rejected_reason,
+                resolved_at_str,
+                review_json,
             ) = row_result?;
             let id: QueueItemId = id_str
                 .parse()
@@ -1244,6 +1719,14 @@ impl StorageBackend for SqliteBackend {
             let created_at = DateTime::parse_from_rfc3339(&created_at_str)
                 .map_err(|e| StorageError::Database(format!("invalid created_at: {}", e)))?
                 .with_timezone(&Utc);
+            let resolved_at = resolved_at_str
+                .map(|s| {
+                    DateTime::parse_from_rfc3339(&s)
+                        .map_err(|e| StorageError::Database(format!("invalid resolved_at: {}", e)))
+                        .map(|t| t.with_timezone(&Utc))
+                })
+                .transpose()?;
+            let review = review_json.map(|s| serde_json::from_str(&s)).transpose()?;
             items.push(QueueItem {
                 id,
                 dataset_id,
@@ -1253,6 +1736,9 @@ impl StorageBackend for SqliteBackend {
                 claimed_at,
                 original_data,
                 edited_data,
+                rejected_reason,
+                resolved_at,
+                review,
                 created_at,
             });
         }
@@ -1266,6 +1752,31 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted > 0)
     }
 
+    async fn save_queue_submission(&self, submission: &QueueSubmission) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(submission)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO queue_submissions (id, queue_item_id, reviewer, data, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![submission.id.to_string(), submission.queue_item_id.to_string(), submission.reviewer, data, submission.created_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_queue_submissions(&self, queue_item_id: QueueItemId) -> Result<Vec<QueueSubmission>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT data FROM queue_submissions WHERE queue_item_id = ?1 ORDER BY created_at ASC",
+        )?;
+        let rows = stmt.query_map(params![queue_item_id.to_string()], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(submission) = serde_json::from_str::<QueueSubmission>(&data) {
+                result.push(submission);
+            }
+        }
+        Ok(result)
+    }
+
     // --- Eval Run operations ---
 
     async fn save_eval_run(&self, run: &EvalRun) -> Result<(), StorageError> {
@@ -1412,8 +1923,9 @@ impl StorageBackend for SqliteBackend {
     async fn save_eval_result(&self, result: &EvalResult) -> Result<(), StorageError> {
         let conn = self.conn.lock().await;
         let actual_output_json = serde_json::to_string(&result.actual_output)?;
+        let scores_json = if result.scores.is_empty() { None } else { Some(serde_json::to_string(&result.scores)?) };
         conn.execute(
-            "INSERT OR REPLACE INTO eval_results (id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            "INSERT OR REPLACE INTO eval_results (id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
             params![
                 result.id.to_string(),
                 result.run_id.to_string(),
@@ -1427,6 +1939,7 @@ impl StorageBackend for SqliteBackend {
                 result.output_tokens.map(|t| t as i64),
                 result.error,
                 result.span_id.map(|id| id.to_string()),
+                scores_json,
             ],
         )?;
         Ok(())
@@ -1435,7 +1948,7 @@ impl StorageBackend for SqliteBackend {
     async fn get_eval_result(&self, id: EvalResultId) -> Result<Option<EvalResult>, StorageError> {
         let conn = self.conn.lock().await;
         let result = conn.query_row(
-            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id FROM eval_results WHERE id = ?1",
+            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json FROM eval_results WHERE id = ?1",
             params![id.to_string()],
             |row| {
                 let id: String = row.get(0)?;
@@ -1450,18 +1963,20 @@ impl StorageBackend for SqliteBackend {
                 let output_tokens: Option<i64> = row.get(9)?;
                 let error: Option<String> = row.get(10)?;
                 let span_id: Option<String> = row.get(11)?;
-                Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id))
+                let scores_json: Option<String> = row.get(12)?;
+                Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json))
             },
         );
         match result {
-            Ok((id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str)) => {
+            Ok((id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str, scores_json)) => {
                 let id: EvalResultId = id_str.parse().map_err(|e| StorageError::Database(format!("invalid eval result id: {}", e)))?;
                 let run_id: EvalRunId = run_id_str.parse().map_err(|e| StorageError::Database(format!("invalid run id: {}", e)))?;
                 let datapoint_id: DatapointId = dp_id_str.parse().map_err(|e| StorageError::Database(format!("invalid datapoint id: {}", e)))?;
                 let status = serde_json::from_value(serde_json::Value::String(status_str))?;
                 let actual_output = serde_json::from_str(&actual_output_json)?;
                 let span_id = span_id_str.map(|s| s.parse()).transpose().map_err(|e| StorageError::Database(format!("invalid span id: {}", e)))?;
-                Ok(Some(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id }))
+                let scores = scores_json.map(|s| serde_json::from_str(&s)).transpose()?.unwrap_or_default();
+                Ok(Some(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id, scores }))
             }
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(StorageError::Database(e.to_string())),
@@ -1471,7 +1986,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_eval_results(&self, run_id: EvalRunId) -> Result<Vec<EvalResult>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id FROM eval_results WHERE run_id = ?1",
+            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json FROM eval_results WHERE run_id = ?1",
         )?;
         let rows = stmt.query_map(params![run_id.to_string()], |row| {
             let id: String = row.get(0)?;
@@ -1486,18 +2001,20 @@ impl StorageBackend for SqliteBackend {
             let output_tokens: Option<i64> = row.get(9)?;
             let error: Option<String> = row.get(10)?;
             let span_id: Option<String> = row.get(11)?;
-            Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id))
+            let scores_json: Option<String> = row.get(12)?;
+            Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json))
         })?;
         let mut results = Vec::new();
         for row_result in rows {
-            let (id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str) = row_result?;
+            let (id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str, scores_json) = row_result?;
             let id: EvalResultId = id_str.parse().map_err(|e| StorageError::Database(format!("invalid eval result id: {}", e)))?;
             let run_id: EvalRunId = run_id_str.parse().map_err(|e| StorageError::Database(format!("invalid run id: {}", e)))?;
             let datapoint_id: DatapointId = dp_id_str.parse().map_err(|e| StorageError::Database(format!("invalid datapoint id: {}", e)))?;
             let status = serde_json::from_value(serde_json::Value::String(status_str))?;
             let actual_output = serde_json::from_str(&actual_output_json)?;
             let span_id = span_id_str.map(|s| s.parse()).transpose().map_err(|e| StorageError::Database(format!("invalid span id: {}", e)))?;
-            results.push(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id });
+            let scores = scores_json.map(|s| serde_json::from_str(&s)).transpose()?.unwrap_or_default();
+            results.push(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id, scores });
         }
         Ok(results)
     }
@@ -1505,7 +2022,7 @@ impl StorageBackend for SqliteBackend {
     async fn list_eval_results_all(&self) -> Result<Vec<EvalResult>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt = conn.prepare(
-            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id FROM eval_results",
+            "SELECT id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json FROM eval_results",
         )?;
         let rows = stmt.query_map([], |row| {
             let id: String = row.get(0)?;
@@ -1520,18 +2037,20 @@ impl StorageBackend for SqliteBackend {
             let output_tokens: Option<i64> = row.get(9)?;
             let error: Option<String> = row.get(10)?;
             let span_id: Option<String> = row.get(11)?;
-            Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id))
+            let scores_json: Option<String> = row.get(12)?;
+            Ok((id, run_id, datapoint_id, status, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id, scores_json))
         })?;
         let mut results = Vec::new();
         for row_result in rows {
-            let (id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str) = row_result?;
+            let (id_str, run_id_str, dp_id_str, status_str, actual_output_json, score, score_reason, latency_ms, input_tokens, output_tokens, error, span_id_str, scores_json) = row_result?;
             let id: EvalResultId = id_str.parse().map_err(|e| StorageError::Database(format!("invalid eval result id: {}", e)))?;
             let run_id: EvalRunId = run_id_str.parse().map_err(|e| StorageError::Database(format!("invalid run id: {}", e)))?;
             let datapoint_id: DatapointId = dp_id_str.parse().map_err(|e| StorageError::Database(format!("invalid datapoint id: {}", e)))?;
             let status = serde_json::from_value(serde_json::Value::String(status_str))?;
             let actual_output = serde_json::from_str(&actual_output_json)?;
             let span_id = span_id_str.map(|s| s.parse()).transpose().map_err(|e| StorageError::Database(format!("invalid span id: {}", e)))?;
-            results.push(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id });
+            let scores = scores_json.map(|s| serde_json::from_str(&s)).transpose()?.unwrap_or_default();
+            results.push(EvalResult { id, run_id, datapoint_id, status, actual_output, score, score_reason, latency_ms: latency_ms as u64, input_tokens: input_tokens.map(|t| t as u32), output_tokens: output_tokens.map(|t| t as u32), error, span_id, scores });
         }
         Ok(results)
     }
@@ -1715,6 +2234,359 @@ impl StorageBackend for SqliteBackend {
         Ok(deleted > 0)
     }
 
+    // --- Span Kind Definition operations ---
+
+    async fn save_span_kind(&self, def: &SpanKindDefinition) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(def)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO span_kinds (name, data, updated_at) VALUES (?1, ?2, ?3)",
+            params![def.name, data, def.updated_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT data FROM span_kinds ORDER BY name")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(def) = serde_json::from_str::<SpanKindDefinition>(&data) {
+                result.push(def);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_span_kind(&self, name: &str) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute("DELETE FROM span_kinds WHERE name = ?1", params![name])?;
+        Ok(deleted > 0)
+    }
+
+    // --- Machine registry ---
+
+    async fn save_machine(&self, machine: &Machine) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(machine)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO machines (id, data, last_seen) VALUES (?1, ?2, ?3)",
+            params![machine.id, data, machine.last_seen.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_machines(&self) -> Result<Vec<Machine>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT data FROM machines ORDER BY id")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(machine) = serde_json::from_str::<Machine>(&data) {
+                result.push(machine);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_machine(&self, id: &str) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute("DELETE FROM machines WHERE id = ?1", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    // --- Settings ---
+
+    async fn get_setting(&self, key: &str) -> Result<Option<serde_json::Value>, StorageError> {
+        let conn = self.conn.lock().await;
+        let result = conn.query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(value) => Ok(Some(serde_json::from_str(&value)?)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(StorageError::Database(e.to_string())),
+        }
+    }
+
+    async fn save_setting(&self, key: &str, value: &serde_json::Value) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            params![key, value.to_string(), Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_settings(&self, keys: &[String]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        for chunk in keys.chunks(500) {
+            tx.execute(
+                &format!(
+                    "DELETE FROM settings WHERE key IN ({})",
+                    vec!["?"; chunk.len()].join(", ")
+                ),
+                params_from_iter(chunk.iter()),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.conn.lock().await;
+        // Immediate, so another process sharing the file can't write
+        // between the read and the write
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let stored = tx.query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, String>(0),
+        );
+        let stored: Option<serde_json::Value> = match stored {
+            Ok(stored) => Some(serde_json::from_str(&stored)?),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(StorageError::Database(e.to_string())),
+        };
+        if stored.as_ref() != expected {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            params![key, value.to_string(), Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    // --- Webhooks ---
+
+    async fn save_webhook(&self, webhook: &Webhook) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(webhook)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO webhooks (id, data, created_at) VALUES (?1, ?2, ?3)",
+            params![webhook.id.to_string(), data, webhook.created_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT data FROM webhooks ORDER BY created_at")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(webhook) = serde_json::from_str::<Webhook>(&data) {
+                result.push(webhook);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_webhook(&self, id: WebhookId) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        conn.execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", params![id.to_string()])?;
+        let deleted = conn.execute("DELETE FROM webhooks WHERE id = ?1", params![id.to_string()])?;
+        Ok(deleted > 0)
+    }
+
+    async fn save_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(delivery)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO webhook_deliveries (id, webhook_id, data, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![delivery.id.to_string(), delivery.webhook_id.to_string(), data, delivery.created_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_webhook_deliveries(&self, webhook_id: WebhookId, limit: usize) -> Result<Vec<WebhookDelivery>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT data FROM webhook_deliveries WHERE webhook_id = ?1 ORDER BY created_at DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![webhook_id.to_string(), limit as i64], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(delivery) = serde_json::from_str::<WebhookDelivery>(&data) {
+                result.push(delivery);
+            }
+        }
+        Ok(result)
+    }
+
+    // --- Audit log ---
+
+    async fn save_audit_event(&self, event: &AuditEvent) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(event)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO audit_events (id, actor, action, data, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![event.id.to_string(), event.actor, event.action, data, event.created_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut sql = "SELECT data FROM audit_events WHERE 1=1".to_string();
+        let mut params_vec: Vec<Value> = Vec::new();
+        if let Some(ref actor) = filter.actor {
+            sql.push_str(" AND actor = ?");
+            params_vec.push(Value::Text(actor.clone()));
+        }
+        if let Some(ref action) = filter.action {
+            if action.ends_with('.') {
+                sql.push_str(" AND substr(action, 1, ?) = ?");
+                params_vec.push(Value::Integer(action.len() as i64));
+            } else {
+                sql.push_str(" AND action = ?");
+            }
+            params_vec.push(Value::Text(action.clone()));
+        }
+        if let Some(since) = filter.since {
+            sql.push_str(" AND created_at >= ?");
+            params_vec.push(Value::Text(since.to_rfc3339()));
+        }
+        if let Some(until) = filter.until {
+            sql.push_str(" AND created_at <= ?");
+            params_vec.push(Value::Text(until.to_rfc3339()));
+        }
+        sql.push_str(" ORDER BY created_at DESC, id DESC");
+        if let Some(limit) = filter.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(params_vec.iter()), |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(event) = serde_json::from_str::<AuditEvent>(&data) {
+                result.push(event);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute(
+            "DELETE FROM audit_events WHERE created_at < ?1",
+            params![cutoff.to_rfc3339()],
+        )?;
+        Ok(deleted)
+    }
+
+    // --- Annotations ---
+
+    async fn save_annotation(&self, annotation: &Annotation) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(annotation)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO annotations (id, span_id, trace_id, score, label, created_by, data, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                annotation.id.to_string(),
+                annotation.span_id.to_string(),
+                annotation.trace_id.to_string(),
+                annotation.score,
+                annotation.label,
+                annotation.created_by,
+                data,
+                annotation.created_at.to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn list_annotations(&self, filter: &AnnotationFilter) -> Result<Vec<Annotation>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut sql = "SELECT data FROM annotations WHERE 1=1".to_string();
+        let mut params_vec: Vec<Value> = Vec::new();
+        if let Some(span_id) = filter.span_id {
+            sql.push_str(" AND span_id = ?");
+            params_vec.push(Value::Text(span_id.to_string()));
+        }
+        if let Some(trace_id) = filter.trace_id {
+            sql.push_str(" AND trace_id = ?");
+            params_vec.push(Value::Text(trace_id.to_string()));
+        }
+        if let Some(ref label) = filter.label {
+            sql.push_str(" AND label = ?");
+            params_vec.push(Value::Text(label.clone()));
+        }
+        if let Some(min) = filter.score_min {
+            sql.push_str(" AND score >= ?");
+            params_vec.push(Value::Real(min));
+        }
+        if let Some(max) = filter.score_max {
+            sql.push_str(" AND score <= ?");
+            params_vec.push(Value::Real(max));
+        }
+        if let Some(ref created_by) = filter.created_by {
+            sql.push_str(" AND created_by = ?");
+            params_vec.push(Value::Text(created_by.clone()));
+        }
+        sql.push_str(" ORDER BY created_at DESC, id DESC");
+        if let Some(limit) = filter.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(params_vec.iter()), |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(annotation) = serde_json::from_str::<Annotation>(&data) {
+                result.push(annotation);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_annotation(&self, id: AnnotationId) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute("DELETE FROM annotations WHERE id = ?1", params![id.to_string()])?;
+        Ok(deleted > 0)
+    }
+
+    // --- Saved views ---
+
+    async fn save_view(&self, view: &SavedView) -> Result<(), StorageError> {
+        let conn = self.conn.lock().await;
+        let data = serde_json::to_string(view)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO saved_views (id, data, created_at) VALUES (?1, ?2, ?3)",
+            params![view.id.to_string(), data, view.created_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    async fn list_views(&self) -> Result<Vec<SavedView>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT data FROM saved_views ORDER BY created_at")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut result = Vec::new();
+        for data in rows.flatten() {
+            if let Ok(view) = serde_json::from_str::<SavedView>(&data) {
+                result.push(view);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn delete_view(&self, id: SavedViewId) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute("DELETE FROM saved_views WHERE id = ?1", params![id.to_string()])?;
+        Ok(deleted > 0)
+    }
+
     // --- File operations ---
 
     async fn save_file_version(&self, version: &FileVersion) -> Result<(), StorageError> {
@@ -1732,6 +2604,18 @@ impl StorageBackend for SqliteBackend {
         Ok(())
     }
 
+    async fn delete_file_versions_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().await;
+        let deleted = conn.execute(
+            "DELETE FROM files WHERE created_at <= ?1",
+            params![cutoff.to_rfc3339()],
+        )?;
+        Ok(deleted)
+    }
+
     async fn list_file_versions(&self) -> Result<Vec<FileVersion>, StorageError> {
         let conn = self.conn.lock().await;
         let mut stmt =
@@ -1793,4 +2677,233 @@ impl StorageBackend for SqliteBackend {
             other => StorageError::Database(other.to_string()),
         })
     }
+
+    async fn list_file_contents(&self) -> Result<Vec<(String, u64)>, StorageError> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT hash, length(content) FROM file_contents")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn delete_file_contents(&self, hashes: &[String]) -> Result<usize, StorageError> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        {
+            let mut stmt = tx.prepare("DELETE FROM file_contents WHERE hash = ?1")?;
+            for hash in hashes {
+                deleted += stmt.execute(params![hash])?;
+            }
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use storage::{ClearScope, PersistentStore, WriteBehindConfig};
+    use trace::{SpanBuilder, SpanKind};
+
+    use super::*;
+
+    fn span(trace_id: TraceId, name: &str) -> Span {
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        SpanBuilder::new(trace_id, name, kind).build()
+    }
+
+    async fn buffered_store() -> Arc<PersistentStore<SqliteBackend>> {
+        let store = PersistentStore::open(SqliteBackend::memory().unwrap())
+            .await
+            .unwrap()
+            .with_write_behind(WriteBehindConfig {
+                max_delay: Duration::from_millis(5),
+                ..Default::default()
+            });
+        Arc::new(store)
+    }
+
+    /// Span ids in the cache and in the backend, which should always agree.
+    async fn cached_and_stored(
+        store: &PersistentStore<SqliteBackend>,
+        trace_id: Option<TraceId>,
+    ) -> (BTreeSet<SpanId>, BTreeSet<SpanId>) {
+        store.flush().await.unwrap();
+        let cached = store
+            .all_spans()
+            .iter()
+            .filter(|s| trace_id.is_none_or(|id| s.trace_id() == id))
+            .map(Span::id)
+            .collect();
+        let filter = SpanFilter {
+            trace_id,
+            ..Default::default()
+        };
+        let stored = store
+            .backend()
+            .list_spans(&filter)
+            .await
+            .unwrap()
+            .iter()
+            .map(Span::id)
+            .collect();
+        (cached, stored)
+    }
+
+    #[tokio::test]
+    async fn trace_filter_trash_scopes() {
+        let backend = SqliteBackend::memory().unwrap();
+        let live = Trace::new(Some("live".into()));
+        let mut trashed = Trace::new(Some("trashed".into()));
+        trashed.deleted_at = Some(Utc::now());
+        backend.save_trace(&live).await.unwrap();
+        backend.save_trace(&trashed).await.unwrap();
+
+        let ids = |trash| {
+            let backend = &backend;
+            async move {
+                let filter = TraceFilter {
+                    trash,
+                    ..Default::default()
+                };
+                let mut ids: Vec<TraceId> = backend
+                    .list_traces(&filter)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|t| t.id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+        assert_eq!(ids(TrashScope::Live).await, vec![live.id]);
+        assert_eq!(ids(TrashScope::Trashed).await, vec![trashed.id]);
+        let mut all = vec![live.id, trashed.id];
+        all.sort();
+        assert_eq!(ids(TrashScope::All).await, all);
+    }
+
+    #[tokio::test]
+    async fn update_setting_only_replaces_the_expected_value() {
+        let backend = SqliteBackend::memory().unwrap();
+        let (first, second) = (serde_json::json!([1]), serde_json::json!([1, 2]));
+
+        assert!(backend.update_setting("k", &first, None).await.unwrap());
+        assert!(!backend.update_setting("k", &second, None).await.unwrap());
+        assert!(!backend.update_setting("k", &second, Some(&second)).await.unwrap());
+        assert!(backend.update_setting("k", &second, Some(&first)).await.unwrap());
+        assert_eq!(backend.get_setting("k").await.unwrap(), Some(second));
+    }
+
+    #[tokio::test]
+    async fn deletes_drop_queued_writes() {
+        let store = PersistentStore::open(SqliteBackend::memory().unwrap())
+            .await
+            .unwrap()
+            .with_write_behind(WriteBehindConfig {
+                max_delay: Duration::from_secs(60),
+                ..Default::default()
+            });
+        let trace = Trace::new(Some("run".into()));
+        store.save_trace(trace.clone()).await.unwrap();
+        let a = span(trace.id, "a");
+        let b = span(trace.id, "b");
+        store.insert(a.clone()).await.unwrap();
+        store.insert(b.clone()).await.unwrap();
+
+        assert!(store.delete_span(a.id()).await.unwrap());
+        store.flush().await.unwrap();
+        assert!(store.backend().get_span(a.id()).await.unwrap().is_none());
+        assert!(store.backend().get_span(b.id()).await.unwrap().is_some());
+
+        // A write queued just before the trace is deleted stays deleted
+        store.insert(b.clone().complete(None)).await.unwrap();
+        store.delete_trace(trace.id).await.unwrap();
+        store.flush().await.unwrap();
+        assert!(store.backend().get_span(b.id()).await.unwrap().is_none());
+        assert!(store.backend().get_trace(trace.id).await.unwrap().is_none());
+        assert!(store.get(b.id()).is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn inserts_race_trace_deletes_on_shared_and_separate_shards() {
+        let store = buffered_store().await;
+        // The store has 16 shards keyed by `id % 16`: 1 and 17 share one
+        let ids = [1, 17, 2].map(TraceId::from_u128);
+        for id in ids {
+            let mut trace = Trace::new(Some("run".into()));
+            trace.id = id;
+            store.save_trace(trace).await.unwrap();
+        }
+
+        let mut tasks = Vec::new();
+        for id in ids {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..50 {
+                    store.insert(span(id, &format!("step-{i}"))).await.unwrap();
+                }
+            }));
+        }
+        let deleter = store.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..10 {
+                deleter.delete_trace(ids[0]).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        }));
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let (cached, stored) = cached_and_stored(&store, Some(ids[0])).await;
+        assert_eq!(cached, stored);
+        for id in &ids[1..] {
+            let (cached, stored) = cached_and_stored(&store, Some(*id)).await;
+            assert_eq!(cached.len(), 50);
+            assert_eq!(cached, stored);
+            let trace = store.get_trace_or_load(*id).await.unwrap();
+            assert_eq!(trace.stats.span_count, 50);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn insert_batches_race_clear() {
+        let store = buffered_store().await;
+        let mut tasks = Vec::new();
+        for task in 0..4u128 {
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..10 {
+                    let spans = (0..10)
+                        .map(|i| span(TraceId::from_u128(task * 10 + i), "step"))
+                        .collect();
+                    store.insert_batch(spans).await.unwrap();
+                }
+            }));
+        }
+        let clearer = store.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..5 {
+                clearer.clear(ClearScope::All).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        }));
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let (cached, stored) = cached_and_stored(&store, None).await;
+        assert_eq!(cached, stored);
+    }
 }
@@ -0,0 +1,154 @@
+//! `cargo bench -p storage-turbopuffer`: the storage harness against an
+//! in-process mock of the Turbopuffer v2 API, so the numbers cover the
+//! client (batching, serialization, overlay) and not the network. See
+//! `storage::bench` for settings.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use storage::bench::{self, BenchConfig};
+use storage_turbopuffer::{TurbopufferBackend, TurbopufferConfig};
+
+/// Rows by namespace, then by id.
+type Namespaces = Arc<Mutex<HashMap<String, HashMap<String, Value>>>>;
+
+fn compare(row: Option<&Value>, op: &str, value: &Value) -> bool {
+    let Some(row) = row else {
+        return op == "NotEq";
+    };
+    let ordering = match (row, value) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (a, b) => a
+            .as_f64()
+            .zip(b.as_f64())
+            .and_then(|(a, b)| a.partial_cmp(&b)),
+    };
+    match op {
+        "Eq" => row == value,
+        "NotEq" => row != value,
+        "Gt" => ordering.is_some_and(|o| o.is_gt()),
+        "Gte" => ordering.is_some_and(|o| o.is_ge()),
+        "Lt" => ordering.is_some_and(|o| o.is_lt()),
+        "Lte" => ordering.is_some_and(|o| o.is_le()),
+        "In" => value.as_array().is_some_and(|vs| vs.contains(row)),
+        "Glob" => match (row.as_str(), value.as_str()) {
+            (Some(s), Some(pattern)) => s.contains(pattern.trim_matches('*')),
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/// Evaluate the subset of Turbopuffer filters the backend sends.
+fn matches(row: &Value, filter: &Value) -> bool {
+    let Some(parts) = filter.as_array() else {
+        return true;
+    };
+    match (parts.first().and_then(Value::as_str), parts.get(1)) {
+        (Some("And"), Some(Value::Array(all))) => all.iter().all(|f| matches(row, f)),
+        (Some("Or"), Some(Value::Array(any))) => any.iter().any(|f| matches(row, f)),
+        (Some("Not"), Some(inner)) => !matches(row, inner),
+        (Some(attr), Some(Value::String(op))) => {
+            compare(row.get(attr), op, parts.get(2).unwrap_or(&Value::Null))
+        }
+        _ => true,
+    }
+}
+
+async fn write(
+    State(namespaces): State<Namespaces>,
+    Path(ns): Path<String>,
+    Json(req): Json<Value>,
+) -> Json<Value> {
+    let mut namespaces = namespaces.lock().unwrap();
+    let rows = namespaces.entry(ns).or_default();
+    let mut affected = 0;
+    for row in req["upsert_rows"].as_array().into_iter().flatten() {
+        if let Some(id) = row["id"].as_str() {
+            rows.insert(id.to_string(), row.clone());
+            affected += 1;
+        }
+    }
+    for id in req["deletes"].as_array().into_iter().flatten() {
+        if let Some(id) = id.as_str() {
+            affected += usize::from(rows.remove(id).is_some());
+        }
+    }
+    if let Some(filter) = req.get("delete_by_filter") {
+        let before = rows.len();
+        rows.retain(|_, row| !matches(row, filter));
+        affected += before - rows.len();
+    }
+    Json(json!({ "rows_affected": affected }))
+}
+
+async fn query(
+    State(namespaces): State<Namespaces>,
+    Path(ns): Path<String>,
+    Json(req): Json<Value>,
+) -> Json<Value> {
+    let namespaces = namespaces.lock().unwrap();
+    let empty = HashMap::new();
+    let rows = namespaces.get(&ns).unwrap_or(&empty);
+    let filter = req.get("filters").cloned().unwrap_or(Value::Null);
+    let mut hits: Vec<&Value> = rows.values().filter(|r| matches(r, &filter)).collect();
+    if let Some(attr) = req["rank_by"][0].as_str() {
+        hits.sort_by(|a, b| {
+            let ord = match (a[attr].as_str(), b[attr].as_str()) {
+                (Some(x), Some(y)) => x.cmp(y),
+                _ => a[attr]
+                    .as_f64()
+                    .partial_cmp(&b[attr].as_f64())
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            };
+            if req["rank_by"][1] == "desc" {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+    }
+    let top_k = req["top_k"].as_u64().map_or(hits.len(), |k| k as usize);
+    hits.truncate(top_k);
+    Json(json!({ "rows": hits }))
+}
+
+/// Serve the mock on a local port and return its base URL.
+async fn spawn_mock() -> String {
+    let app = Router::new()
+        .route("/v2/namespaces/:ns", post(write))
+        .route("/v2/namespaces/:ns/query", post(query))
+        .with_state(Namespaces::default());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind mock Turbopuffer");
+    let addr = listener.local_addr().expect("mock has no address");
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::main]
+async fn main() {
+    let config = BenchConfig::from_env();
+    let base_url = spawn_mock().await;
+
+    let mut runs = 0;
+    let results = bench::run_backend("turbopuffer-mock", &config, || {
+        runs += 1;
+        let tp = TurbopufferConfig::new("bench", format!("bench{runs}")).with_base_url(&base_url);
+        async move { Ok(TurbopufferBackend::new(tp)?) }
+    })
+    .await
+    .expect("turbopuffer-mock benchmark failed");
+
+    let regressions = bench::report(&results, &config).expect("failed to report results");
+    if !regressions.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,234 @@
+//! Write coalescing for Turbopuffer upserts.
+//!
+//! Upserts are buffered per collection and sent as one request once the
+//! batch is `max_rows` long or its oldest row is `max_delay` old. Rows with
+//! the same id within a batch are coalesced (last write wins).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Batching window. A `max_rows` of 0 or 1, or a zero `max_delay`, disables
+/// batching and every upsert is sent immediately.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    pub max_rows: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: 500,
+            max_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn disabled() -> Self {
+        Self {
+            max_rows: 0,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_rows > 1 && !self.max_delay.is_zero()
+    }
+}
+
+/// Cumulative batching counters for one backend.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct BatchStats {
+    /// Upsert requests sent.
+    pub flushes: u64,
+    /// Rows sent across all flushes.
+    pub rows_flushed: u64,
+    /// Rows dropped because a later write to the same id superseded them.
+    pub rows_coalesced: u64,
+    pub max_batch_rows: u64,
+    /// Failed flushes. Their rows are re-queued for the next flush.
+    pub flush_errors: u64,
+    pub flush_ms_total: u64,
+    pub flush_ms_max: u64,
+    /// Rows currently buffered.
+    pub pending_rows: u64,
+}
+
+/// Rows waiting to be written to one collection.
+#[derive(Default)]
+pub(crate) struct Batch {
+    pub rows: Vec<serde_json::Value>,
+    pub schema: Option<serde_json::Value>,
+    index: HashMap<String, usize>,
+    opened_at: Option<Instant>,
+}
+
+impl Batch {
+    /// Add a row, replacing any pending row with the same id. Returns true if
+    /// a pending row was replaced.
+    fn push(&mut self, row: serde_json::Value, schema: Option<serde_json::Value>) -> bool {
+        self.opened_at.get_or_insert_with(Instant::now);
+        if schema.is_some() {
+            self.schema = schema;
+        }
+        let id = row.get("id").and_then(|v| v.as_str()).map(str::to_owned);
+        if let Some(&pos) = id.as_ref().and_then(|id| self.index.get(id)) {
+            self.rows[pos] = row;
+            return true;
+        }
+        if let Some(id) = id {
+            self.index.insert(id, self.rows.len());
+        }
+        self.rows.push(row);
+        false
+    }
+
+    fn is_due(&self, max_delay: Duration) -> bool {
+        self.opened_at.is_some_and(|t| t.elapsed() >= max_delay)
+    }
+}
+
+pub(crate) struct Batcher {
+    config: BatchConfig,
+    pending: Mutex<HashMap<String, Batch>>,
+    stats: Mutex<BatchStats>,
+}
+
+impl Batcher {
+    pub fn new(config: BatchConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::new(HashMap::new()),
+            stats: Mutex::new(BatchStats::default()),
+        }
+    }
+
+    /// Buffer a row. Returns the collection's batch if it is now full and
+    /// should be flushed by the caller.
+    pub fn push(
+        &self,
+        collection: &str,
+        row: serde_json::Value,
+        schema: Option<serde_json::Value>,
+    ) -> Option<Batch> {
+        let mut pending = self.pending.lock().unwrap();
+        let batch = pending.entry(collection.to_string()).or_default();
+        if batch.push(row, schema) {
+            self.stats.lock().unwrap().rows_coalesced += 1;
+        }
+        if batch.rows.len() >= self.config.max_rows {
+            pending.remove(collection)
+        } else {
+            None
+        }
+    }
+
+    /// Take the pending batch for one collection.
+    pub fn take(&self, collection: &str) -> Option<Batch> {
+        self.pending.lock().unwrap().remove(collection)
+    }
+
+    /// Take every batch whose window has elapsed.
+    pub fn take_due(&self) -> Vec<(String, Batch)> {
+        let mut pending = self.pending.lock().unwrap();
+        let due: Vec<String> = pending
+            .iter()
+            .filter(|(_, b)| b.is_due(self.config.max_delay))
+            .map(|(c, _)| c.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|c| pending.remove(&c).map(|b| (c, b)))
+            .collect()
+    }
+
+    /// Take every pending batch.
+    pub fn take_all(&self) -> Vec<(String, Batch)> {
+        self.pending.lock().unwrap().drain().collect()
+    }
+
+    /// Put a failed batch back in front of anything written since, so newer
+    /// writes to the same id still win.
+    pub fn requeue(&self, collection: &str, failed: Batch) {
+        let mut pending = self.pending.lock().unwrap();
+        let newer = pending.remove(collection);
+        let mut merged = Batch::default();
+        for row in failed.rows {
+            merged.push(row, None);
+        }
+        merged.schema = failed.schema;
+        if let Some(newer) = newer {
+            for row in newer.rows {
+                merged.push(row, newer.schema.clone());
+            }
+        }
+        pending.insert(collection.to_string(), merged);
+    }
+
+    pub fn record_flush(&self, rows: usize, elapsed: Duration, ok: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let ms = elapsed.as_millis() as u64;
+        stats.flushes += 1;
+        stats.flush_ms_total += ms;
+        stats.flush_ms_max = stats.flush_ms_max.max(ms);
+        if ok {
+            stats.rows_flushed += rows as u64;
+            stats.max_batch_rows = stats.max_batch_rows.max(rows as u64);
+        } else {
+            stats.flush_errors += 1;
+        }
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        let pending_rows = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|b| b.rows.len() as u64)
+            .sum();
+        BatchStats {
+            pending_rows,
+            ..*self.stats.lock().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: &str, v: u32) -> serde_json::Value {
+        serde_json::json!({ "id": id, "v": v })
+    }
+
+    #[test]
+    fn coalesces_same_id_and_flushes_when_full() {
+        let batcher = Batcher::new(BatchConfig {
+            max_rows: 3,
+            max_delay: Duration::from_secs(60),
+        });
+        assert!(batcher.push("spans", row("a", 1), None).is_none());
+        assert!(batcher.push("spans", row("a", 2), None).is_none());
+        assert!(batcher.push("spans", row("b", 1), None).is_none());
+        let full = batcher.push("spans", row("c", 1), None).unwrap();
+        assert_eq!(full.rows, vec![row("a", 2), row("b", 1), row("c", 1)]);
+        assert_eq!(batcher.stats().rows_coalesced, 1);
+        assert!(batcher.take_due().is_empty());
+    }
+
+    #[test]
+    fn requeue_keeps_newer_writes() {
+        let batcher = Batcher::new(BatchConfig::default());
+        batcher.push("spans", row("a", 1), None);
+        batcher.push("spans", row("b", 1), None);
+        let failed = batcher.take("spans").unwrap();
+        batcher.push("spans", row("a", 2), None);
+        batcher.requeue("spans", failed);
+        let batch = batcher.take("spans").unwrap();
+        assert_eq!(batch.rows, vec![row("a", 2), row("b", 1)]);
+    }
+}
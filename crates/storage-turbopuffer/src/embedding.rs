@@ -0,0 +1,176 @@
+//! Span embeddings for semantic search.
+//!
+//! Finished spans are embedded from their name and input/output text and the
+//! vector is stored on the span's row. Queries are embedded with the same
+//! model and ranked by ANN distance.
+
+use reqwest::Client;
+use serde::Deserialize;
+use trace::{Span, SpanKind};
+
+use crate::TurbopufferError;
+
+/// Where embeddings come from.
+#[derive(Debug, Clone)]
+pub enum EmbeddingProvider {
+    /// OpenAI, or any server exposing an OpenAI-compatible `/v1/embeddings`.
+    OpenAi {
+        api_key: String,
+        base_url: String,
+        model: String,
+    },
+    /// A local Ollama server (`/api/embed`). Span text never leaves the host.
+    Ollama { base_url: String, model: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    pub provider: EmbeddingProvider,
+    /// Span text is truncated to this many characters before embedding.
+    pub max_input_chars: usize,
+}
+
+impl EmbeddingConfig {
+    pub fn new(provider: EmbeddingProvider) -> Self {
+        Self {
+            provider,
+            max_input_chars: 8_000,
+        }
+    }
+
+    /// Read `TRACEWAY_EMBEDDING_PROVIDER` (`openai`, `ollama`, or `none`).
+    /// Returns `None` when unset or `none`.
+    pub fn from_env() -> Result<Option<Self>, TurbopufferError> {
+        let provider = std::env::var("TRACEWAY_EMBEDDING_PROVIDER")
+            .unwrap_or_else(|_| "none".to_string())
+            .to_ascii_lowercase();
+        let model = std::env::var("TRACEWAY_EMBEDDING_MODEL").ok();
+
+        let provider = match provider.as_str() {
+            "none" | "" => return Ok(None),
+            "openai" => EmbeddingProvider::OpenAi {
+                api_key: std::env::var("OPENAI_API_KEY").map_err(|_| {
+                    TurbopufferError::Config(
+                        "OPENAI_API_KEY must be set for the openai embedding provider".to_string(),
+                    )
+                })?,
+                base_url: std::env::var("TRACEWAY_EMBEDDING_URL")
+                    .unwrap_or_else(|_| "https://api.openai.com".to_string()),
+                model: model.unwrap_or_else(|| "text-embedding-3-small".to_string()),
+            },
+            "ollama" => EmbeddingProvider::Ollama {
+                base_url: std::env::var("TRACEWAY_EMBEDDING_URL")
+                    .unwrap_or_else(|_| "http://localhost:11434".to_string()),
+                model: model.unwrap_or_else(|| "nomic-embed-text".to_string()),
+            },
+            other => {
+                return Err(TurbopufferError::Config(format!(
+                    "unknown embedding provider '{other}' (expected openai, ollama, or none)"
+                )))
+            }
+        };
+
+        let mut config = Self::new(provider);
+        if let Some(chars) = std::env::var("TRACEWAY_EMBEDDING_MAX_CHARS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            config.max_input_chars = chars;
+        }
+        Ok(Some(config))
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Turns text into vectors with the configured provider.
+pub struct Embedder {
+    client: Client,
+    config: EmbeddingConfig,
+}
+
+impl Embedder {
+    pub fn new(client: Client, config: EmbeddingConfig) -> Self {
+        Self { client, config }
+    }
+
+    /// Embed one piece of text.
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>, TurbopufferError> {
+        let request = match &self.config.provider {
+            EmbeddingProvider::OpenAi {
+                api_key,
+                base_url,
+                model,
+            } => self
+                .client
+                .post(format!("{}/v1/embeddings", base_url.trim_end_matches('/')))
+                .bearer_auth(api_key)
+                .json(&serde_json::json!({ "model": model, "input": [text] })),
+            EmbeddingProvider::Ollama { base_url, model } => self
+                .client
+                .post(format!("{}/api/embed", base_url.trim_end_matches('/')))
+                .json(&serde_json::json!({ "model": model, "input": [text] })),
+        };
+
+        let resp = request.send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status().as_u16();
+            let message = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(TurbopufferError::Api { status, message });
+        }
+
+        let vector = match self.config.provider {
+            EmbeddingProvider::OpenAi { .. } => resp
+                .json::<OpenAiResponse>()
+                .await?
+                .data
+                .into_iter()
+                .next()
+                .map(|e| e.embedding),
+            EmbeddingProvider::Ollama { .. } => {
+                resp.json::<OllamaResponse>().await?.embeddings.into_iter().next()
+            }
+        };
+        vector.ok_or_else(|| TurbopufferError::Api {
+            status: 200,
+            message: "embedding response contained no vectors".to_string(),
+        })
+    }
+
+    /// Text to embed for a finished span: its name plus input and output,
+    /// preferring the LLM previews when captured. `None` while running.
+    pub fn span_text(&self, span: &Span) -> Option<String> {
+        span.ended_at()?;
+        let mut parts = vec![span.name().to_string()];
+        let (input_preview, output_preview) = match span.kind() {
+            SpanKind::LlmCall {
+                input_preview,
+                output_preview,
+                ..
+            } => (input_preview.clone(), output_preview.clone()),
+            _ => (None, None),
+        };
+        parts.extend(input_preview.or_else(|| span.input().map(|v| v.to_string())));
+        parts.extend(output_preview.or_else(|| span.output().map(|v| v.to_string())));
+        Some(
+            parts
+                .join("\n")
+                .chars()
+                .take(self.config.max_input_chars)
+                .collect(),
+        )
+    }
+}
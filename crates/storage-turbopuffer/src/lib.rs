@@ -16,25 +16,56 @@
 //! - `type`: Entity type for filtering within namespace
 //! - `data`: Full JSON-serialized entity data
 //! - Additional indexed attributes for filtering (trace_id, status, etc.)
+//! - `vector`: embedding of a finished span's text, when an embedding
+//!   provider is configured (spans namespace only)
+//!
+//! Turbopuffer queries are eventually consistent. Recent writes and deletes
+//! are overlaid on reads for `recent_writes_ttl` so a row can be read back
+//! right after it is written; see [`recent`].
+
+mod batch;
+mod embedding;
+mod recent;
+mod retry;
+mod schema;
+
+pub use batch::{BatchConfig, BatchStats};
+pub use embedding::{Embedder, EmbeddingConfig, EmbeddingProvider};
+pub use retry::{RetryConfig, RetryStats};
+pub use schema::{SchemaDiff, COLLECTIONS};
 
 use async_trait::async_trait;
 use base64::Engine;
+use chrono::{DateTime, Utc};
 
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use storage::error::StorageError;
-use storage::filter::{SpanFilter, TraceFilter};
-use storage::StorageBackend;
+use std::collections::HashMap;
+use storage::filter::{
+    self, AnnotationFilter, AuditFilter, CursorPosition, SortValue, SpanFilter, TraceFilter,
+    TrashScope,
+};
+use storage::{ScoredSpan, StorageBackend};
 use thiserror::Error;
+
+use batch::{Batch, Batcher};
+use recent::{Recent, RecentWrites};
+use retry::RetryCounters;
 use trace::{
-    CaptureRule, CaptureRuleId, Datapoint, DatapointId, Dataset, DatasetId, EvalResult,
-    EvalResultId, EvalRun, EvalRunId, FileVersion, ProviderConnection, ProviderConnectionId,
-    QueueItem, QueueItemId, Span, SpanId, Trace, TraceId,
+    Annotation, AnnotationId, AuditEvent, CaptureRule, CaptureRuleId, Datapoint, DatapointId,
+    Dataset, DatasetId, EvalResult, EvalResultId, EvalRun, EvalRunId, FileVersion, Machine,
+    ProviderConnection, ProviderConnectionId, QueueItem, QueueItemId, QueueSubmission, SavedView,
+    SavedViewId, Session, Span, SpanId, SpanKindDefinition, Trace, TraceId, Webhook,
+    WebhookDelivery, WebhookId,
 };
 use tracing::{debug, info, instrument, warn};
 
 const QUERY_PAGE_SIZE: usize = 10_000;
+const DEFAULT_RECENT_WRITES_TTL: Duration = Duration::from_secs(10);
 
 /// Turbopuffer-specific errors
 #[derive(Debug, Error)]
@@ -55,6 +86,20 @@ pub enum TurbopufferError {
     Config(String),
 }
 
+impl TurbopufferError {
+    /// Whether the request may succeed if sent again: timeouts, connection
+    /// failures, throttling and server errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TurbopufferError::Http(e) => e.is_timeout() || e.is_connect(),
+            TurbopufferError::Api { status, .. } => {
+                matches!(status, 408 | 429) || (500..600).contains(status)
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<TurbopufferError> for StorageError {
     fn from(e: TurbopufferError) -> Self {
         match e {
@@ -77,6 +122,16 @@ pub struct TurbopufferConfig {
     pub namespace: String,
     /// Request timeout in seconds
     pub timeout_secs: u64,
+    /// Upsert batching window. Batched writes return before they reach
+    /// Turbopuffer; reads and deletes on a collection flush it first.
+    pub batch: BatchConfig,
+    /// Embedding provider for span vectors. `None` disables semantic search.
+    pub embedding: Option<EmbeddingConfig>,
+    /// How long written and deleted rows override query results. Zero
+    /// disables the overlay.
+    pub recent_writes_ttl: Duration,
+    /// Retries of failed requests and hedging of slow queries.
+    pub retry: RetryConfig,
 }
 
 impl TurbopufferConfig {
@@ -95,11 +150,53 @@ impl TurbopufferConfig {
             .and_then(|s| s.parse().ok())
             .unwrap_or(30);
 
+        let mut batch = BatchConfig::default();
+        if let Some(rows) = std::env::var("TURBOPUFFER_BATCH_MAX_ROWS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            batch.max_rows = rows;
+        }
+        if let Some(ms) = std::env::var("TURBOPUFFER_BATCH_MAX_DELAY_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            batch.max_delay = Duration::from_millis(ms);
+        }
+
+        let recent_writes_ttl = std::env::var("TURBOPUFFER_RECENT_WRITES_TTL_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_RECENT_WRITES_TTL);
+
+        let mut retry = RetryConfig::default();
+        if let Some(attempts) = std::env::var("TURBOPUFFER_RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            retry.max_attempts = attempts;
+        }
+        if let Some(ms) = std::env::var("TURBOPUFFER_RETRY_BASE_DELAY_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            retry.base_delay = Duration::from_millis(ms);
+        }
+        retry.hedge_after = std::env::var("TURBOPUFFER_HEDGE_AFTER_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_millis);
+
         Ok(Self {
             api_key,
             base_url,
             namespace,
             timeout_secs,
+            batch,
+            embedding: EmbeddingConfig::from_env()?,
+            recent_writes_ttl,
+            retry,
         })
     }
 
@@ -109,6 +206,10 @@ impl TurbopufferConfig {
             base_url: "https://gcp-us-central1.turbopuffer.com".to_string(),
             namespace: namespace.into(),
             timeout_secs: 30,
+            batch: BatchConfig::default(),
+            embedding: None,
+            recent_writes_ttl: DEFAULT_RECENT_WRITES_TTL,
+            retry: RetryConfig::default(),
         }
     }
 
@@ -122,6 +223,26 @@ impl TurbopufferConfig {
         self
     }
 
+    pub fn with_batch(mut self, batch: BatchConfig) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    pub fn with_embedding(mut self, embedding: EmbeddingConfig) -> Self {
+        self.embedding = Some(embedding);
+        self
+    }
+
+    pub fn with_recent_writes_ttl(mut self, ttl: Duration) -> Self {
+        self.recent_writes_ttl = ttl;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
     /// Create a new config with a different namespace prefix (for per-org isolation)
     pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
         self.namespace = namespace.into();
@@ -137,18 +258,25 @@ impl TurbopufferConfig {
             base_url: self.base_url.clone(),
             namespace: format!("tw_{}", org_short),
             timeout_secs: self.timeout_secs,
+            batch: self.batch,
+            embedding: self.embedding.clone(),
+            recent_writes_ttl: self.recent_writes_ttl,
+            retry: self.retry,
         }
     }
 }
 
 /// Row-based upsert request for Turbopuffer v2 API
 #[derive(Debug, Serialize)]
-struct UpsertRequest {
-    upsert_rows: Vec<serde_json::Value>,
+struct UpsertRequest<'a> {
+    upsert_rows: &'a [serde_json::Value],
     #[serde(skip_serializing_if = "Option::is_none")]
     distance_metric: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    schema: Option<serde_json::Value>,
+    schema: Option<&'a serde_json::Value>,
+    /// Only rows whose stored copy matches this filter are written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upsert_condition: Option<serde_json::Value>,
 }
 
 /// Query request for Turbopuffer v2 API
@@ -176,53 +304,156 @@ struct DeleteRequest {
     deletes: Vec<String>,
 }
 
-/// Turbopuffer storage backend implementation
-pub struct TurbopufferBackend {
-    client: Client,
-    config: Arc<TurbopufferConfig>,
+/// Filtered delete request for Turbopuffer v2 API
+#[derive(Debug, Serialize)]
+struct DeleteByFilterRequest {
+    delete_by_filter: serde_json::Value,
 }
 
-impl TurbopufferBackend {
-    /// Create a new Turbopuffer backend with the given configuration
-    pub fn new(config: TurbopufferConfig) -> Result<Self, TurbopufferError> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(config.timeout_secs))
-            .gzip(true)
-            .build()?;
-
-        info!(namespace = %config.namespace, "Initialized Turbopuffer backend");
-
-        Ok(Self {
-            client,
-            config: Arc::new(config),
-        })
-    }
+#[derive(Debug, Deserialize)]
+struct WriteResponse {
+    #[serde(default)]
+    rows_affected: Option<usize>,
+}
 
-    /// Create a backend from environment variables
-    pub fn from_env() -> Result<Self, TurbopufferError> {
-        let config = TurbopufferConfig::from_env()?;
-        Self::new(config)
-    }
+/// HTTP client and config shared by the backend and its batch flusher.
+#[derive(Clone)]
+struct Transport {
+    client: Client,
+    config: Arc<TurbopufferConfig>,
+    retries: Arc<RetryCounters>,
+}
 
+impl Transport {
     /// Get the full namespace name for a collection type
     fn namespace(&self, collection: &str) -> String {
         format!("{}_{}", self.config.namespace, collection)
     }
 
-    /// Make an authenticated POST request to Turbopuffer
+    /// Make an authenticated POST request to Turbopuffer, retrying
+    /// retryable failures. See [`retry`].
     async fn post<T: Serialize, R: for<'de> Deserialize<'de>>(
         &self,
         path: &str,
         body: &T,
+    ) -> Result<R, TurbopufferError> {
+        self.post_retrying(path, body, false).await
+    }
+
+    /// `post` for a query, hedged when `hedge_after` is set.
+    async fn post_query<T: Serialize, R: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<R, TurbopufferError> {
+        self.post_retrying(path, body, true).await
+    }
+
+    async fn post_retrying<T: Serialize, R: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: &T,
+        hedge: bool,
     ) -> Result<R, TurbopufferError> {
         let url = format!("{}{}", self.config.base_url, path);
-        let resp = self
-            .client
-            .post(&url)
+        self.retrying(path, hedge, || self.send(self.client.post(&url).json(body)))
+            .await
+    }
+
+    /// How the schema of `collection`'s namespace differs from its
+    /// declaration, or `None` if the namespace doesn't exist.
+    async fn schema_diff(&self, collection: &str) -> Result<Option<SchemaDiff>, TurbopufferError> {
+        let path = format!("/v1/namespaces/{}/schema", self.namespace(collection));
+        match self.get::<serde_json::Value>(&path).await {
+            Ok(live) => Ok(Some(schema::diff(&schema::declared(collection), &live))),
+            Err(TurbopufferError::Api { status: 404, .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Make an authenticated GET request to Turbopuffer, retrying
+    /// retryable failures.
+    async fn get<R: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<R, TurbopufferError> {
+        let url = format!("{}{}", self.config.base_url, path);
+        self.retrying(path, false, || self.send(self.client.get(&url)))
+            .await
+    }
+
+    async fn retrying<R, F, Fut>(
+        &self,
+        path: &str,
+        hedge: bool,
+        request: F,
+    ) -> Result<R, TurbopufferError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<R, TurbopufferError>>,
+    {
+        let policy = &self.config.retry;
+        let mut attempt = 1;
+        loop {
+            let result = match policy.hedge_after {
+                Some(after) if hedge => self.send_hedged(&request, after).await,
+                _ => request().await,
+            };
+            match result {
+                Err(e) if e.is_retryable() && attempt < policy.max_attempts => {
+                    let delay = policy.backoff(attempt);
+                    warn!(path, attempt, error = %e, delay_ms = delay.as_millis() as u64, "Turbopuffer request failed; retrying");
+                    self.retries.retry();
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    if attempt > 1 && e.is_retryable() {
+                        self.retries.exhausted();
+                    }
+                    return Err(e);
+                }
+                Ok(resp) => {
+                    if attempt > 1 {
+                        self.retries.recovered();
+                    }
+                    return Ok(resp);
+                }
+            }
+        }
+    }
+
+    /// Send the request, and a second copy if the first hasn't answered
+    /// within `after`; the first answer wins.
+    async fn send_hedged<R, Fut>(
+        &self,
+        request: impl Fn() -> Fut,
+        after: Duration,
+    ) -> Result<R, TurbopufferError>
+    where
+        Fut: Future<Output = Result<R, TurbopufferError>>,
+    {
+        let first = request();
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(after) => {}
+        }
+        self.retries.hedge();
+        tokio::select! {
+            result = &mut first => result,
+            result = request() => {
+                self.retries.hedge_win();
+                result
+            }
+        }
+    }
+
+    async fn send<R: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<R, TurbopufferError> {
+        let resp = request
             .header("Authorization", format!("Bearer {}", self.config.api_key))
             .header("Content-Type", "application/json")
             .header("Accept", "application/json")
-            .json(body)
             .send()
             .await?;
 
@@ -235,12 +466,14 @@ impl TurbopufferBackend {
         Ok(resp.json().await?)
     }
 
-    /// Upsert documents to a namespace
-    #[instrument(skip(self, rows), fields(count = rows.len()))]
+    /// Send one upsert request, optionally with an explicit schema (e.g. to
+    /// mark attributes as non-filterable)
+    #[instrument(skip(self, rows, schema), fields(count = rows.len()))]
     async fn upsert(
         &self,
         collection: &str,
-        rows: Vec<serde_json::Value>,
+        rows: &[serde_json::Value],
+        schema: Option<&serde_json::Value>,
     ) -> Result<(), TurbopufferError> {
         if rows.is_empty() {
             return Ok(());
@@ -251,67 +484,484 @@ impl TurbopufferBackend {
 
         debug!(namespace = %ns, count = rows.len(), "Upserting documents");
 
-        // For non-vector namespaces, we don't need distance_metric
+        // Only writes that carry vectors need a distance metric
         let req = UpsertRequest {
             upsert_rows: rows,
-            distance_metric: None,
-            schema: None,
+            distance_metric: rows
+                .iter()
+                .any(|r| r.get("vector").is_some())
+                .then(|| "cosine_distance".to_string()),
+            schema,
+            upsert_condition: None,
         };
 
         let _: serde_json::Value = self.post(&path, &req).await?;
         Ok(())
     }
 
-    /// Upsert documents with an explicit schema (e.g. to mark attributes as non-filterable)
-    #[instrument(skip(self, rows, schema), fields(count = rows.len()))]
-    async fn upsert_with_schema(
+    /// Upsert `row` only if its stored copy matches `condition`. Returns
+    /// whether it was written.
+    async fn upsert_if(
         &self,
         collection: &str,
-        rows: Vec<serde_json::Value>,
-        schema: serde_json::Value,
+        row: &serde_json::Value,
+        condition: serde_json::Value,
+        schema: Option<&serde_json::Value>,
+    ) -> Result<bool, TurbopufferError> {
+        let path = format!("/v2/namespaces/{}", self.namespace(collection));
+        let req = UpsertRequest {
+            upsert_rows: std::slice::from_ref(row),
+            distance_metric: None,
+            schema,
+            upsert_condition: Some(condition),
+        };
+        let resp: WriteResponse = self.post(&path, &req).await?;
+        Ok(resp.rows_affected.unwrap_or(0) > 0)
+    }
+
+    /// Write a batch, recording stats. A failed batch is re-queued so the
+    /// next flush retries it.
+    async fn flush_batch(
+        &self,
+        batcher: &Batcher,
+        collection: &str,
+        batch: Batch,
     ) -> Result<(), TurbopufferError> {
-        if rows.is_empty() {
+        let start = Instant::now();
+        let result = self
+            .upsert(collection, &batch.rows, batch.schema.as_ref())
+            .await;
+        batcher.record_flush(batch.rows.len(), start.elapsed(), result.is_ok());
+        if let Err(ref e) = result {
+            warn!(collection, rows = batch.rows.len(), error = %e, "Turbopuffer batch flush failed; re-queued");
+            batcher.requeue(collection, batch);
+        }
+        result
+    }
+}
+
+/// Periodically flush batches whose window has elapsed. Stops once the
+/// backend (and with it the batcher) is dropped.
+/// Upsert rows through the batcher when one is given, flushing a batch
+/// inline once it is full.
+async fn write_rows_via(
+    transport: &Transport,
+    batcher: Option<&Batcher>,
+    collection: &str,
+    rows: Vec<serde_json::Value>,
+    schema: Option<serde_json::Value>,
+) -> Result<(), TurbopufferError> {
+    let Some(batcher) = batcher else {
+        return transport.upsert(collection, &rows, schema.as_ref()).await;
+    };
+    // Full batches are written inline, so a sustained write burst is
+    // throttled by Turbopuffer latency rather than buffering without bound.
+    for row in rows {
+        if let Some(full) = batcher.push(collection, row, schema.clone()) {
+            transport.flush_batch(batcher, collection, full).await?;
+        }
+    }
+    Ok(())
+}
+
+fn spawn_flusher(transport: Transport, batcher: Weak<Batcher>, max_delay: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval((max_delay / 2).max(Duration::from_millis(1)));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let Some(batcher) = batcher.upgrade() else {
+                break;
+            };
+            for (collection, batch) in batcher.take_due() {
+                let _ = transport.flush_batch(&batcher, &collection, batch).await;
+            }
+        }
+    });
+}
+
+/// Turbopuffer storage backend implementation
+pub struct TurbopufferBackend {
+    transport: Transport,
+    batcher: Option<Arc<Batcher>>,
+    embedder: Option<Arc<Embedder>>,
+    recent: Option<RecentWrites>,
+}
+
+impl TurbopufferBackend {
+    /// Create a new Turbopuffer backend with the given configuration.
+    /// Batching requires a Tokio runtime; without one, writes are sent
+    /// immediately.
+    pub fn new(config: TurbopufferConfig) -> Result<Self, TurbopufferError> {
+        let client = Client::builder()
+            .timeout(std::time::Duration::from_secs(config.timeout_secs))
+            .gzip(true)
+            .build()?;
+
+        info!(namespace = %config.namespace, "Initialized Turbopuffer backend");
+
+        let batch = config.batch;
+        let recent = (!config.recent_writes_ttl.is_zero())
+            .then(|| RecentWrites::new(config.recent_writes_ttl));
+        let embedder = config
+            .embedding
+            .clone()
+            .map(|e| Arc::new(Embedder::new(client.clone(), e)));
+        let transport = Transport {
+            client,
+            config: Arc::new(config),
+            retries: Arc::default(),
+        };
+        let batcher = (batch.is_enabled() && tokio::runtime::Handle::try_current().is_ok())
+            .then(|| Arc::new(Batcher::new(batch)));
+        if let Some(ref b) = batcher {
+            spawn_flusher(transport.clone(), Arc::downgrade(b), batch.max_delay);
+        }
+
+        Ok(Self {
+            transport,
+            batcher,
+            embedder,
+            recent,
+        })
+    }
+
+    /// Create a backend from environment variables
+    pub fn from_env() -> Result<Self, TurbopufferError> {
+        let config = TurbopufferConfig::from_env()?;
+        Self::new(config)
+    }
+
+    /// Batching counters, or `None` if batching is disabled.
+    pub fn batch_stats(&self) -> Option<BatchStats> {
+        self.batcher.as_ref().map(|b| b.stats())
+    }
+
+    pub fn retry_stats(&self) -> RetryStats {
+        self.transport.retries.stats()
+    }
+
+    /// How each existing namespace's schema differs from its declaration,
+    /// in `COLLECTIONS` order. Namespaces that don't exist yet are left
+    /// out; their first write declares the schema.
+    pub async fn verify_schemas(&self) -> Result<Vec<(&'static str, SchemaDiff)>, TurbopufferError> {
+        let mut checks = tokio::task::JoinSet::new();
+        for (i, collection) in COLLECTIONS.iter().enumerate() {
+            let transport = self.transport.clone();
+            checks.spawn(async move { (i, transport.schema_diff(collection).await) });
+        }
+        let mut diffs = Vec::new();
+        while let Some(joined) = checks.join_next().await {
+            let (i, diff) = joined.expect("schema check panicked");
+            if let Some(diff) = diff? {
+                diffs.push((i, diff));
+            }
+        }
+        diffs.sort_by_key(|(i, _)| *i);
+        Ok(diffs.into_iter().map(|(i, d)| (COLLECTIONS[i], d)).collect())
+    }
+
+    /// `verify_schemas`, applying index setting changes. Type conflicts
+    /// are logged; they need the namespace rebuilt.
+    pub async fn ensure_schemas(&self) -> Result<Vec<(&'static str, SchemaDiff)>, TurbopufferError> {
+        let diffs = self.verify_schemas().await?;
+        for (collection, diff) in &diffs {
+            let ns = self.namespace(collection);
+            if !diff.changed.is_empty() {
+                let declared = schema::declared(collection);
+                let update: serde_json::Map<String, serde_json::Value> = diff
+                    .changed
+                    .iter()
+                    .map(|name| (name.clone(), declared[name].clone()))
+                    .collect();
+                let _: serde_json::Value = self
+                    .post(&format!("/v1/namespaces/{ns}/schema"), &update)
+                    .await?;
+                info!(namespace = %ns, attributes = ?diff.changed, "Updated Turbopuffer schema");
+            }
+            if !diff.conflicts.is_empty() {
+                warn!(namespace = %ns, attributes = ?diff.conflicts, "Turbopuffer attributes have a different type than declared");
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// `ensure_schemas`, then save every row of each collection with
+    /// missing attributes again, so rows written before the attribute was
+    /// declared carry it. Returns the rows rewritten per collection.
+    pub async fn migrate(&self) -> Result<Vec<(&'static str, usize)>, StorageError> {
+        let mut rewritten = Vec::new();
+        for (collection, diff) in self.ensure_schemas().await? {
+            if !diff.missing.is_empty() {
+                rewritten.push((collection, self.rewrite(collection).await?));
+            }
+        }
+        self.flush_writes().await?;
+        Ok(rewritten)
+    }
+
+    /// Save every row of `collection` again through its current writer.
+    async fn rewrite(&self, collection: &str) -> Result<usize, StorageError> {
+        macro_rules! resave {
+            ($list:expr, $save:ident) => {{
+                let all = $list.await?;
+                for item in &all {
+                    self.$save(item).await?;
+                }
+                all.len()
+            }};
+        }
+        Ok(match collection {
+            "traces" => resave!(
+                self.list_traces(&TraceFilter {
+                    trash: TrashScope::All,
+                    ..Default::default()
+                }),
+                save_trace
+            ),
+            "spans" => {
+                let all = self.list_spans(&SpanFilter::default()).await?;
+                for chunk in all.chunks(500) {
+                    self.save_spans_batch(chunk).await?;
+                }
+                all.len()
+            }
+            "datasets" => resave!(self.list_datasets(), save_dataset),
+            "datapoints" => {
+                let all = self.list_datapoints_all().await?;
+                for chunk in all.chunks(500) {
+                    self.save_datapoints_batch(chunk).await?;
+                }
+                all.len()
+            }
+            "queue_items" => resave!(self.list_queue_items_all(), save_queue_item),
+            "eval_runs" => resave!(self.list_eval_runs_all(), save_eval_run),
+            "eval_results" => resave!(self.list_eval_results_all(), save_eval_result),
+            "capture_rules" => resave!(self.list_capture_rules_all(), save_capture_rule),
+            "provider_connections" => {
+                resave!(self.list_provider_connections(), save_provider_connection)
+            }
+            "span_kinds" => resave!(self.list_span_kinds(), save_span_kind),
+            "machines" => resave!(self.list_machines(), save_machine),
+            "webhooks" => resave!(self.list_webhooks(), save_webhook),
+            "file_versions" => resave!(self.list_file_versions(), save_file_version),
+            _ => {
+                warn!(collection, "Rows can't be rewritten; older rows keep missing attributes unset");
+                0
+            }
+        })
+    }
+
+    /// Namespace prefixes (`{prefix}_{collection}`) of every backend
+    /// stored under this API key whose prefix starts with `prefix`.
+    pub async fn namespace_prefixes(&self, prefix: &str) -> Result<Vec<String>, TurbopufferError> {
+        #[derive(Deserialize)]
+        struct Namespace {
+            id: String,
+        }
+        #[derive(Deserialize)]
+        struct Namespaces {
+            #[serde(default)]
+            namespaces: Vec<Namespace>,
+            next_cursor: Option<String>,
+        }
+
+        let mut prefixes = std::collections::BTreeSet::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut path = format!("/v1/namespaces?prefix={prefix}&page_size=1000");
+            if let Some(ref c) = cursor {
+                path.push_str(&format!("&cursor={c}"));
+            }
+            let page: Namespaces = self.transport.get(&path).await?;
+            for ns in page.namespaces {
+                if let Some(p) = COLLECTIONS
+                    .iter()
+                    .find_map(|c| ns.id.strip_suffix(c)?.strip_suffix('_'))
+                {
+                    prefixes.insert(p.to_string());
+                }
+            }
+            match page.next_cursor {
+                Some(next) if cursor.as_ref() != Some(&next) => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(prefixes.into_iter().collect())
+    }
+
+    /// Write every buffered row now.
+    pub async fn flush_writes(&self) -> Result<(), TurbopufferError> {
+        let Some(ref batcher) = self.batcher else {
             return Ok(());
+        };
+        let mut result = Ok(());
+        for (collection, batch) in batcher.take_all() {
+            if let Err(e) = self.transport.flush_batch(batcher, &collection, batch).await {
+                result = Err(e);
+            }
         }
+        result
+    }
 
-        let ns = self.namespace(collection);
-        let path = format!("/v2/namespaces/{}", ns);
+    /// Write buffered rows for one collection so reads and deletes see them.
+    async fn flush_collection(&self, collection: &str) -> Result<(), TurbopufferError> {
+        let Some(ref batcher) = self.batcher else {
+            return Ok(());
+        };
+        match batcher.take(collection) {
+            Some(batch) => self.transport.flush_batch(batcher, collection, batch).await,
+            None => Ok(()),
+        }
+    }
 
-        debug!(namespace = %ns, count = rows.len(), "Upserting documents with schema");
+    fn namespace(&self, collection: &str) -> String {
+        self.transport.namespace(collection)
+    }
 
-        let req = UpsertRequest {
-            upsert_rows: rows,
-            distance_metric: None,
-            schema: Some(schema),
+    async fn post<T: Serialize, R: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<R, TurbopufferError> {
+        self.transport.post(path, body).await
+    }
+
+    /// Upsert documents to a namespace with the collection's declared
+    /// schema, through the batcher when enabled
+    async fn upsert(
+        &self,
+        collection: &str,
+        rows: Vec<serde_json::Value>,
+    ) -> Result<(), TurbopufferError> {
+        if let Some(ref recent) = self.recent {
+            recent.record_rows(collection, &rows);
+        }
+        let schema = schema::declared(collection);
+        write_rows_via(&self.transport, self.batcher.as_deref(), collection, rows, Some(schema))
+            .await
+    }
+
+    /// Rewrite a row carrying a `version` attribute, but only while the
+    /// stored row is still at `expected`. Skips the batcher, after flushing
+    /// it so the condition sees every earlier write.
+    async fn upsert_versioned(
+        &self,
+        collection: &str,
+        id: &str,
+        row: serde_json::Value,
+        expected: u64,
+    ) -> Result<bool, TurbopufferError> {
+        self.flush_collection(collection).await?;
+        // A conditional upsert of an id with no stored row is written
+        // unconditionally, which would resurrect a deleted row.
+        if self.get_by_id(collection, id).await?.is_none() {
+            return Ok(false);
+        }
+        let condition = if expected == 0 {
+            // Rows saved before versions were tracked have none.
+            serde_json::json!(["Or", [["version", "Eq", 0], ["version", "Eq", null]]])
+        } else {
+            serde_json::json!(["version", "Eq", expected])
         };
+        let schema = schema::declared(collection);
+        let written = self
+            .transport
+            .upsert_if(collection, &row, condition, Some(&schema))
+            .await?;
+        if written {
+            if let Some(ref recent) = self.recent {
+                recent.record_rows(collection, std::slice::from_ref(&row));
+            }
+        }
+        Ok(written)
+    }
 
-        let _: serde_json::Value = self.post(&path, &req).await?;
-        Ok(())
+    /// Embed a finished span off the write path and rewrite its row with
+    /// the vector. Failures are logged; the span stays stored without one.
+    fn spawn_embed(&self, span: &Span, row: serde_json::Value) {
+        let Some(embedder) = self.embedder.clone() else {
+            return;
+        };
+        let Some(text) = embedder.span_text(span) else {
+            return;
+        };
+        let transport = self.transport.clone();
+        let batcher = self.batcher.clone();
+        let span_id = span.id();
+        tokio::spawn(async move {
+            let vector = match embedder.embed(&text).await {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!(%span_id, error = %e, "Span embedding failed");
+                    return;
+                }
+            };
+            let mut row = row;
+            row["vector"] = serde_json::json!(vector);
+            let schema = schema::declared("spans");
+            if let Err(e) =
+                write_rows_via(&transport, batcher.as_deref(), "spans", vec![row], Some(schema))
+                    .await
+            {
+                warn!(%span_id, error = %e, "Failed to store span embedding");
+            }
+        });
     }
 
-    /// Query documents from a namespace.
+    /// Query documents from a namespace, ordered by id.
     /// Returns an empty vec if the namespace does not exist yet (404).
-    #[instrument(skip(self, filters))]
     async fn query(
         &self,
         collection: &str,
         filters: Option<serde_json::Value>,
         limit: usize,
     ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        // Order by id for consistent ordering when not using vectors
+        self.query_ranked(collection, filters, serde_json::json!(["id", "asc"]), limit)
+            .await
+    }
+
+    /// Query documents ordered by `rank_by` (e.g. `["started_at", "desc"]`).
+    /// Returns an empty vec if the namespace does not exist yet (404).
+    #[instrument(skip(self, filters, rank_by))]
+    async fn query_ranked(
+        &self,
+        collection: &str,
+        filters: Option<serde_json::Value>,
+        rank_by: serde_json::Value,
+        limit: usize,
+    ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        let rows = self
+            .query_ranked_raw(collection, filters, rank_by, limit, serde_json::json!(true))
+            .await?;
+        Ok(self.overlay_recent(collection, rows))
+    }
+
+    /// `query_ranked` without the recent-writes overlay, returning only
+    /// `include_attributes`.
+    async fn query_ranked_raw(
+        &self,
+        collection: &str,
+        filters: Option<serde_json::Value>,
+        rank_by: serde_json::Value,
+        limit: usize,
+        include_attributes: serde_json::Value,
+    ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        self.flush_collection(collection).await?;
         let ns = self.namespace(collection);
         let path = format!("/v2/namespaces/{}/query", ns);
 
-        // Order by id for consistent ordering when not using vectors
         let req = QueryRequest {
-            rank_by: Some(serde_json::json!(["id", "asc"])),
+            rank_by: Some(rank_by),
             filters,
             top_k: Some(limit),
-            include_attributes: serde_json::json!(true),
+            include_attributes,
         };
 
         debug!(namespace = %ns, limit, "Querying documents");
 
-        match self.post(&path, &req).await {
+        match self.transport.post_query(&path, &req).await {
             Ok(resp) => {
                 let resp: QueryResponse = resp;
                 Ok(resp.rows)
@@ -333,60 +983,135 @@ impl TurbopufferBackend {
         collection: &str,
         filters: Option<serde_json::Value>,
     ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
-        let mut rows = Vec::new();
-        let mut last_id: Option<String> = None;
+        self.query_all_with(collection, filters, serde_json::json!(true))
+            .await
+    }
 
-        loop {
-            let page_filters = match (&filters, &last_id) {
-                (None, None) => None,
-                (Some(base), None) => Some(base.clone()),
-                (None, Some(id)) => Some(serde_json::json!(["id", "Gt", id])),
-                (Some(base), Some(id)) => {
-                    Some(serde_json::json!(["And", [base.clone(), ["id", "Gt", id]]]))
+    /// `query_all`, returning only `include_attributes`.
+    async fn query_all_with(
+        &self,
+        collection: &str,
+        filters: Option<serde_json::Value>,
+        include_attributes: serde_json::Value,
+    ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        // Overlay once at the end so dropped rows don't end pagination early.
+        let rows = self
+            .query_pages(
+                collection,
+                filters,
+                "id",
+                false,
+                usize::MAX,
+                include_attributes,
+            )
+            .await?;
+        Ok(self.overlay_recent(collection, rows))
+    }
+
+    /// Up to `limit` documents ordered by `attribute`, paged like `query_all`.
+    async fn query_sorted(
+        &self,
+        collection: &str,
+        filters: Option<serde_json::Value>,
+        attribute: &str,
+        desc: bool,
+        limit: usize,
+    ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        let rows = self
+            .query_pages(
+                collection,
+                filters,
+                attribute,
+                desc,
+                limit,
+                serde_json::json!(true),
+            )
+            .await?;
+        Ok(self.overlay_recent(collection, rows))
+    }
+
+    /// Up to `limit` rows ordered by `attribute`, fetched `QUERY_PAGE_SIZE`
+    /// at a time so limits beyond Turbopuffer's `top_k` cap aren't
+    /// truncated. Each page continues after the previous page's last value;
+    /// `attribute` must be among `include_attributes`.
+    async fn query_pages(
+        &self,
+        collection: &str,
+        filters: Option<serde_json::Value>,
+        attribute: &str,
+        desc: bool,
+        limit: usize,
+        include_attributes: serde_json::Value,
+    ) -> Result<Vec<serde_json::Value>, TurbopufferError> {
+        let rank_by = serde_json::json!([attribute, if desc { "desc" } else { "asc" }]);
+        let mut rows = Vec::new();
+        let mut after: Option<(serde_json::Value, Vec<String>)> = None;
+
+        while rows.len() < limit {
+            let want = (limit - rows.len()).min(QUERY_PAGE_SIZE);
+            let page_filters = match &after {
+                None => filters.clone(),
+                Some((value, ties)) => {
+                    let rest = continue_after(attribute, value, ties, desc);
+                    Some(match &filters {
+                        Some(base) => serde_json::json!(["And", [base.clone(), rest]]),
+                        None => rest,
+                    })
                 }
             };
-
             let page = self
-                .query(collection, page_filters, QUERY_PAGE_SIZE)
+                .query_ranked_raw(
+                    collection,
+                    page_filters,
+                    rank_by.clone(),
+                    want,
+                    include_attributes.clone(),
+                )
                 .await?;
 
-            if page.is_empty() {
+            let full = page.len() == want;
+            let Some(last) = page.last() else {
                 break;
-            }
-
-            let page_len = page.len();
-            let next_last_id = page
-                .last()
-                .and_then(|row| row.get("id"))
-                .and_then(|v| v.as_str())
-                .map(ToOwned::to_owned);
-
+            };
+            let Some(value) = last.get(attribute).cloned() else {
+                warn!(collection, attribute, "missing sort attribute; stopping pagination early");
+                rows.extend(page);
+                break;
+            };
+            // Rows sharing the last value are told apart by id, carried over
+            // while the value spans several pages.
+            let mut ties = match after {
+                Some((previous, ties)) if previous == value => ties,
+                _ => Vec::new(),
+            };
+            ties.extend(
+                page.iter()
+                    .filter(|row| row.get(attribute) == Some(&value))
+                    .filter_map(|row| row.get("id")?.as_str().map(ToOwned::to_owned)),
+            );
             rows.extend(page);
 
-            if page_len < QUERY_PAGE_SIZE {
+            if !full {
                 break;
             }
-
-            warn!(collection, page_size = QUERY_PAGE_SIZE, "query page reached top_k; continuing pagination");
-
-            match next_last_id {
-                Some(id) => {
-                    if last_id.as_deref() == Some(id.as_str()) {
-                        warn!(collection, "pagination cursor did not advance; stopping to avoid loop");
-                        break;
-                    }
-                    last_id = Some(id);
-                }
-                None => {
-                    warn!(collection, "missing id in query row; stopping pagination early");
-                    break;
-                }
-            }
+            debug!(collection, page_size = QUERY_PAGE_SIZE, "query page reached top_k; continuing");
+            after = Some((value, ties));
         }
 
         Ok(rows)
     }
 
+    fn overlay_recent(
+        &self,
+        collection: &str,
+        rows: Vec<serde_json::Value>,
+    ) -> Vec<serde_json::Value> {
+        match self.recent {
+            Some(ref recent) => recent.overlay(collection, rows),
+            None => rows,
+        }
+    }
+
     /// Delete documents by ID.
     /// Returns 0 if the namespace does not exist yet (404).
     #[instrument(skip(self, ids))]
@@ -399,9 +1124,13 @@ impl TurbopufferBackend {
             return Ok(0);
         }
 
+        self.flush_collection(collection).await?;
         let ns = self.namespace(collection);
         let path = format!("/v2/namespaces/{}", ns);
         let count = ids.len();
+        if let Some(ref recent) = self.recent {
+            recent.record_deletes(collection, &ids);
+        }
 
         let req = DeleteRequest { deletes: ids };
 
@@ -417,6 +1146,39 @@ impl TurbopufferBackend {
         }
     }
 
+    /// Delete every document matching `filter` (all documents if `None`) in
+    /// one request. Returns 0 if the namespace does not exist yet (404).
+    #[instrument(skip(self, filter))]
+    async fn delete_by_filter(
+        &self,
+        collection: &str,
+        filter: Option<serde_json::Value>,
+    ) -> Result<usize, TurbopufferError> {
+        self.flush_collection(collection).await?;
+        if let Some(ref recent) = self.recent {
+            recent.forget_collection(collection);
+        }
+        let ns = self.namespace(collection);
+        let path = format!("/v2/namespaces/{}", ns);
+
+        // Every document has a non-empty id, so this matches all of them.
+        let filter = filter.unwrap_or_else(|| serde_json::json!(["id", "NotEq", ""]));
+        let req = DeleteByFilterRequest {
+            delete_by_filter: filter,
+        };
+
+        debug!(namespace = %ns, "Deleting documents by filter");
+
+        match self.post::<_, WriteResponse>(&path, &req).await {
+            Ok(resp) => Ok(resp.rows_affected.unwrap_or(0)),
+            Err(TurbopufferError::Api { status: 404, .. }) => {
+                debug!(namespace = %ns, "Namespace not found on delete, returning 0");
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get a single document by ID.
     /// Returns None if the namespace does not exist yet.
     async fn get_by_id(
@@ -424,6 +1186,11 @@ impl TurbopufferBackend {
         collection: &str,
         id: &str,
     ) -> Result<Option<serde_json::Value>, TurbopufferError> {
+        match self.recent.as_ref().and_then(|r| r.get(collection, id)) {
+            Some(Recent::Written(row)) => return Ok(Some(row)),
+            Some(Recent::Deleted) => return Ok(None),
+            None => {}
+        }
         let filter = serde_json::json!(["id", "Eq", id]);
         let results = self.query(collection, Some(filter), 1).await?;
         Ok(results.into_iter().next())
@@ -447,12 +1214,164 @@ impl TurbopufferBackend {
     }
 }
 
+/// Combine filter conditions with `And`.
+fn combine_conditions(mut conditions: Vec<serde_json::Value>) -> Option<serde_json::Value> {
+    match conditions.len() {
+        0 => None,
+        1 => Some(conditions.remove(0)),
+        _ => Some(serde_json::json!(["And", conditions])),
+    }
+}
+
+/// Keyset condition for rows after `after` in `(started_at, id)` order.
+/// Turbopuffer ranks by a single attribute, so rows sharing a start time
+/// with the cursor row are disambiguated by id only approximately.
+fn started_at_keyset(after: &CursorPosition, desc: bool) -> serde_json::Value {
+    let op = if desc { "Lt" } else { "Gt" };
+    let value = match &after.0 {
+        SortValue::Text(s) => s.clone(),
+        SortValue::Number(n) => n.to_string(),
+    };
+    serde_json::json!([
+        "Or",
+        [
+            ["started_at", op, value],
+            ["And", [["started_at", "Eq", value], ["id", op, after.1]]]
+        ]
+    ])
+}
+
+/// Condition for rows after `value` in `attribute` order, excluding the
+/// already-read rows `ties` that share it.
+fn continue_after(
+    attribute: &str,
+    value: &serde_json::Value,
+    ties: &[String],
+    desc: bool,
+) -> serde_json::Value {
+    let op = if desc { "Lt" } else { "Gt" };
+    serde_json::json!([
+        "Or",
+        [
+            [attribute, op, value],
+            ["And", [[attribute, "Eq", value], ["id", "NotIn", ties]]]
+        ]
+    ])
+}
+
+/// Turbopuffer conditions for the indexed predicates of a span filter.
+/// Anything reported by `has_unindexed_span_predicates` is left out.
+fn span_conditions(filter: &SpanFilter) -> Vec<serde_json::Value> {
+    let mut conditions = Vec::new();
+
+    if let Some(ref trace_id) = filter.trace_id {
+        conditions.push(serde_json::json!(["trace_id", "Eq", trace_id.to_string()]));
+    }
+    if let Some(ref status) = filter.status {
+        conditions.push(serde_json::json!(["status", "Eq", status]));
+    }
+    if let Some(ref kind) = filter.kind {
+        conditions.push(serde_json::json!(["kind", "Eq", kind]));
+    }
+    if let Some(ref model) = filter.model {
+        conditions.push(serde_json::json!(["model", "Eq", model]));
+    }
+    if let Some(ref provider) = filter.provider {
+        conditions.push(serde_json::json!(["provider", "Eq", provider]));
+    }
+    if let Some(ref name) = filter.name_contains {
+        conditions.push(serde_json::json!(["name", "Glob", format!("*{}*", name)]));
+    }
+    if let Some(since) = filter.since {
+        conditions.push(serde_json::json!(["started_at", "Gte", since.to_rfc3339()]));
+    }
+    if let Some(until) = filter.until {
+        conditions.push(serde_json::json!(["started_at", "Lte", until.to_rfc3339()]));
+    }
+    if let Some(ref ids) = filter.span_ids {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        conditions.push(serde_json::json!(["id", "In", ids]));
+    }
+    conditions
+}
+
+/// Turbopuffer conditions for a trace filter. Tags aren't indexed and are
+/// left out.
+fn trace_conditions(filter: &TraceFilter) -> Vec<serde_json::Value> {
+    let mut conditions = Vec::new();
+
+    if let Some(ref name) = filter.name_contains {
+        // Use Glob for partial matching
+        conditions.push(serde_json::json!(["name", "Glob", format!("*{}*", name)]));
+    }
+    if let Some(since) = filter.since {
+        conditions.push(serde_json::json!(["started_at", "Gte", since.to_rfc3339()]));
+    }
+    if let Some(until) = filter.until {
+        conditions.push(serde_json::json!(["started_at", "Lte", until.to_rfc3339()]));
+    }
+    if let Some(ref machine_id) = filter.machine_id {
+        conditions.push(serde_json::json!(["machine_id", "Eq", machine_id]));
+    }
+    if let Some(ref session_id) = filter.session_id {
+        conditions.push(serde_json::json!(["session_id", "Eq", session_id]));
+    }
+    if let Some(ref git_commit) = filter.git_commit {
+        conditions.push(serde_json::json!(["git_commit", "Eq", git_commit]));
+    }
+    if let Some(ref git_branch) = filter.git_branch {
+        conditions.push(serde_json::json!(["git_branch", "Eq", git_branch]));
+    }
+    if let Some(ref repo) = filter.repo {
+        conditions.push(serde_json::json!(["repo", "Eq", repo]));
+    }
+    // Rows saved before `tags` was declared lack it and won't match until
+    // `migrate` rewrites them
+    for tag in filter.tags.iter().flatten() {
+        conditions.push(serde_json::json!(["tags", "Contains", tag]));
+    }
+    // Rows saved before `trashed` was declared lack it, so live traces
+    // are matched as not trashed rather than as `false`
+    match filter.trash {
+        TrashScope::Live => conditions.push(serde_json::json!(["trashed", "NotEq", true])),
+        TrashScope::Trashed => conditions.push(serde_json::json!(["trashed", "Eq", true])),
+        TrashScope::All => {}
+    }
+    conditions
+}
+
+/// Whether the span filter uses predicates that aren't indexed attributes
+/// and must be evaluated in memory.
+fn has_unindexed_span_predicates(filter: &SpanFilter) -> bool {
+    filter.path.is_some()
+        || filter.tool.is_some()
+        || filter.index.is_some()
+        || filter.verdict.is_some()
+        || filter.duration_min.is_some()
+        || filter.duration_max.is_some()
+        || filter.tokens_min.is_some()
+        || filter.cost_min.is_some()
+        || filter.text_contains.is_some()
+        || filter.input_contains.is_some()
+        || filter.output_contains.is_some()
+}
+
 #[async_trait]
 impl StorageBackend for TurbopufferBackend {
     fn backend_type(&self) -> &'static str {
         "turbopuffer"
     }
 
+    async fn flush(&self) -> Result<(), StorageError> {
+        Ok(self.flush_writes().await?)
+    }
+
+    /// A one-row query, which also succeeds before the namespace exists.
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.query("traces", None, 1).await?;
+        Ok(())
+    }
+
     // --- Trace operations ---
 
     async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
@@ -462,6 +1381,13 @@ impl StorageBackend for TurbopufferBackend {
             "name": trace.name,
             "started_at": trace.started_at.to_rfc3339(),
             "ended_at": trace.ended_at.map(|t| t.to_rfc3339()),
+            "machine_id": trace.machine_id,
+            "session_id": trace.session_id,
+            "git_commit": trace.git_commit,
+            "git_branch": trace.git_branch,
+            "repo": trace.repo,
+            "trashed": trace.deleted_at.is_some(),
+            "tags": trace.tags,
         });
 
         self.upsert("traces", vec![row]).await?;
@@ -476,41 +1402,76 @@ impl StorageBackend for TurbopufferBackend {
     }
 
     async fn list_traces(&self, filter: &TraceFilter) -> Result<Vec<Trace>, StorageError> {
-        let mut conditions = Vec::new();
-
-        if let Some(ref name) = filter.name_contains {
-            // Use Glob for partial matching
-            conditions.push(serde_json::json!(["name", "Glob", format!("*{}*", name)]));
-        }
-        if let Some(since) = filter.since {
-            conditions.push(serde_json::json!(["started_at", "Gte", since.to_rfc3339()]));
-        }
-        if let Some(until) = filter.until {
-            conditions.push(serde_json::json!(["started_at", "Lte", until.to_rfc3339()]));
-        }
+        let mut conditions = trace_conditions(filter);
 
-        let filters = if conditions.is_empty() {
-            None
-        } else if conditions.len() == 1 {
-            Some(conditions.remove(0))
-        } else {
-            Some(serde_json::json!(["And", conditions]))
-        };
+        let desc = filter.sort_desc();
+        let field = filter.sort_field();
+        let after = filter.cursor_position()?;
 
-        let results = if let Some(limit) = filter.limit {
-            self.query("traces", filters, limit).await?
-        } else {
-            self.query_all("traces", filters).await?
-        };
+        // Page in Turbopuffer when ranking by start time; anything else
+        // (other sort fields, unbounded reads) is sorted in memory.
+        if let (Some(limit), "started_at") = (filter.limit, field) {
+            if let Some(ref pos) = after {
+                conditions.push(started_at_keyset(pos, desc));
+            }
+            let offset = filter.offset.unwrap_or(0);
+            let results = self
+                .query_sorted(
+                    "traces",
+                    combine_conditions(conditions),
+                    "started_at",
+                    desc,
+                    offset + limit,
+                )
+                .await?;
+            return Ok(results
+                .iter()
+                .skip(offset)
+                .filter_map(Self::extract_data::<Trace>)
+                .collect());
+        }
 
-        let mut traces = Vec::new();
-        for row in results {
-            if let Some(trace) = Self::extract_data::<Trace>(&row) {
-                traces.push(trace);
+        let results = self.query_all("traces", combine_conditions(conditions)).await?;
+        let traces: Vec<Trace> = results
+            .iter()
+            .filter_map(Self::extract_data::<Trace>)
+            .filter(|t| filter.matches(t))
+            .collect();
+
+        let mut costs: HashMap<TraceId, f64> = HashMap::new();
+        if field == "cost" {
+            for row in self.query_all("spans", None).await? {
+                if let Some(span) = Self::extract_data::<Span>(&row) {
+                    *costs.entry(span.trace_id()).or_default() += span.kind().cost().unwrap_or(0.0);
+                }
             }
         }
 
-        Ok(traces)
+        Ok(filter::sort_and_page(
+            traces,
+            |t| {
+                let cost = costs.get(&t.id).copied().unwrap_or(0.0);
+                (filter::trace_sort_value(t, field, cost), t.id.to_string())
+            },
+            desc,
+            after.as_ref(),
+            filter.offset.unwrap_or(0),
+            filter.limit,
+        ))
+    }
+
+    async fn list_sessions(&self, filter: &TraceFilter) -> Result<Vec<Session>, StorageError> {
+        let mut conditions = trace_conditions(filter);
+        conditions.push(serde_json::json!(["session_id", "NotEq", null]));
+        let results = self
+            .query_all("traces", combine_conditions(conditions))
+            .await?;
+        let traces: Vec<Trace> = results
+            .iter()
+            .filter_map(Self::extract_data::<Trace>)
+            .filter(|t| filter.matches(t))
+            .collect();
+        Ok(storage::sessions::sessions_over(&traces))
     }
 
     async fn delete_trace(&self, id: TraceId) -> Result<bool, StorageError> {
@@ -518,6 +1479,26 @@ impl StorageBackend for TurbopufferBackend {
         Ok(count > 0)
     }
 
+    async fn delete_traces_by_filter(&self, filter: &TraceFilter) -> Result<usize, StorageError> {
+        let rows = self
+            .query_all("traces", combine_conditions(trace_conditions(filter)))
+            .await?;
+        let ids: Vec<String> = rows
+            .iter()
+            .filter_map(Self::extract_data::<Trace>)
+            .filter(|t| filter.matches(t))
+            .map(|t| t.id.to_string())
+            .collect();
+
+        // Delete requests are bounded, so remove spans and traces in chunks
+        for chunk in ids.chunks(1000) {
+            self.delete_by_filter("spans", Some(serde_json::json!(["trace_id", "In", chunk])))
+                .await?;
+            self.delete_ids("traces", chunk.to_vec()).await?;
+        }
+        Ok(ids.len())
+    }
+
     // --- Span operations ---
 
     async fn save_span(&self, span: &Span) -> Result<(), StorageError> {
@@ -534,12 +1515,8 @@ impl StorageBackend for TurbopufferBackend {
             "ended_at": span.ended_at().map(|t| t.to_rfc3339()),
         });
 
-        // Mark `data` as non-filterable since it can be large (LLM outputs)
-        // and we only read it back, never filter on it. This also gives a 50% storage discount.
-        let schema = serde_json::json!({
-            "data": {"type": "string", "filterable": false}
-        });
-        self.upsert_with_schema("spans", vec![row], schema).await?;
+        self.upsert("spans", vec![row.clone()]).await?;
+        self.spawn_embed(span, row);
         Ok(())
     }
 
@@ -551,55 +1528,52 @@ impl StorageBackend for TurbopufferBackend {
     }
 
     async fn list_spans(&self, filter: &SpanFilter) -> Result<Vec<Span>, StorageError> {
-        let mut conditions = Vec::new();
-
-        if let Some(ref trace_id) = filter.trace_id {
-            conditions.push(serde_json::json!(["trace_id", "Eq", trace_id.to_string()]));
-        }
-        if let Some(ref status) = filter.status {
-            conditions.push(serde_json::json!(["status", "Eq", status]));
-        }
-        if let Some(ref kind) = filter.kind {
-            conditions.push(serde_json::json!(["kind", "Eq", kind]));
-        }
-        if let Some(ref model) = filter.model {
-            conditions.push(serde_json::json!(["model", "Eq", model]));
-        }
-        if let Some(ref provider) = filter.provider {
-            conditions.push(serde_json::json!(["provider", "Eq", provider]));
-        }
-        if let Some(ref name) = filter.name_contains {
-            conditions.push(serde_json::json!(["name", "Glob", format!("*{}*", name)]));
-        }
-        if let Some(since) = filter.since {
-            conditions.push(serde_json::json!(["started_at", "Gte", since.to_rfc3339()]));
-        }
-        if let Some(until) = filter.until {
-            conditions.push(serde_json::json!(["started_at", "Lte", until.to_rfc3339()]));
-        }
-
-        let filters = if conditions.is_empty() {
-            None
-        } else if conditions.len() == 1 {
-            Some(conditions.remove(0))
-        } else {
-            Some(serde_json::json!(["And", conditions]))
-        };
-
-        let results = if let Some(limit) = filter.limit {
-            self.query("spans", filters, limit).await?
-        } else {
-            self.query_all("spans", filters).await?
-        };
-
-        let mut spans = Vec::new();
-        for row in results {
-            if let Some(span) = Self::extract_data::<Span>(&row) {
-                spans.push(span);
+        let mut conditions = span_conditions(filter);
+
+        let desc = filter.sort_desc();
+        let field = filter.sort_field();
+        let after = filter.cursor_position()?;
+
+        // Page in Turbopuffer when ranking by start time and every predicate
+        // is an indexed attribute; otherwise filter and sort in memory.
+        if let (Some(limit), "started_at", false) =
+            (filter.limit, field, has_unindexed_span_predicates(filter))
+        {
+            if let Some(ref pos) = after {
+                conditions.push(started_at_keyset(pos, desc));
             }
+            let offset = filter.offset.unwrap_or(0);
+            let results = self
+                .query_sorted(
+                    "spans",
+                    combine_conditions(conditions),
+                    "started_at",
+                    desc,
+                    offset + limit,
+                )
+                .await?;
+            return Ok(results
+                .iter()
+                .skip(offset)
+                .filter_map(Self::extract_data::<Span>)
+                .collect());
         }
 
-        Ok(spans)
+        let results = self.query_all("spans", combine_conditions(conditions)).await?;
+        let spans: Vec<Span> = results
+            .iter()
+            .filter_map(Self::extract_data::<Span>)
+            .filter(|s| filter.matches(s))
+            .collect();
+
+        Ok(filter::sort_and_page(
+            spans,
+            |s| (filter::span_sort_value(s, field), s.id().to_string()),
+            desc,
+            after.as_ref(),
+            filter.offset.unwrap_or(0),
+            filter.limit,
+        ))
     }
 
     async fn delete_span(&self, id: SpanId) -> Result<bool, StorageError> {
@@ -608,34 +1582,70 @@ impl StorageBackend for TurbopufferBackend {
     }
 
     async fn delete_trace_spans(&self, trace_id: TraceId) -> Result<usize, StorageError> {
-        let filter = SpanFilter {
-            trace_id: Some(trace_id),
-            ..Default::default()
-        };
-        let spans = self.list_spans(&filter).await?;
-        let ids: Vec<String> = spans.iter().map(|s| s.id().to_string()).collect();
-        let count = ids.len();
+        let filter = serde_json::json!(["trace_id", "Eq", trace_id.to_string()]);
+        Ok(self.delete_by_filter("spans", Some(filter)).await?)
+    }
 
-        if !ids.is_empty() {
-            self.delete_ids("spans", ids).await?;
+    async fn delete_spans_by_filter(&self, filter: &SpanFilter) -> Result<usize, StorageError> {
+        if !has_unindexed_span_predicates(filter) {
+            let conditions = combine_conditions(span_conditions(filter));
+            return Ok(self.delete_by_filter("spans", conditions).await?);
         }
 
+        // Some predicates are only evaluated in memory, so resolve ids first
+        let rows = self
+            .query_all("spans", combine_conditions(span_conditions(filter)))
+            .await?;
+        let ids: Vec<String> = rows
+            .iter()
+            .filter_map(Self::extract_data::<Span>)
+            .filter(|s| filter.matches(s))
+            .map(|s| s.id().to_string())
+            .collect();
+
+        let mut count = 0;
+        for chunk in ids.chunks(1000) {
+            count += self.delete_ids("spans", chunk.to_vec()).await?;
+        }
         Ok(count)
     }
 
     async fn clear_spans(&self) -> Result<(), StorageError> {
-        // Query all spans and delete them
-        let spans = self.list_spans(&SpanFilter::default()).await?;
-        let ids: Vec<String> = spans.iter().map(|s| s.id().to_string()).collect();
+        self.delete_by_filter("spans", None).await?;
+        Ok(())
+    }
 
-        if !ids.is_empty() {
-            // Delete in batches to avoid request size limits
-            for chunk in ids.chunks(1000) {
-                self.delete_ids("spans", chunk.to_vec()).await?;
-            }
-        }
+    // --- Search ---
 
-        Ok(())
+    async fn semantic_search(
+        &self,
+        query: &str,
+        filter: &SpanFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredSpan>, StorageError> {
+        let Some(ref embedder) = self.embedder else {
+            return Err(StorageError::Unsupported(
+                "semantic search requires an embedding provider (TRACEWAY_EMBEDDING_PROVIDER)"
+                    .to_string(),
+            ));
+        };
+        let vector = embedder.embed(query).await?;
+        let rank_by = serde_json::json!(["vector", "ANN", vector]);
+        let rows = self
+            .query_ranked("spans", combine_conditions(span_conditions(filter)), rank_by, limit)
+            .await?;
+
+        // Unindexed predicates are applied to the ranked rows, so a filtered
+        // search can return fewer than `limit` spans.
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let span = Self::extract_data::<Span>(row)?;
+                let distance = row.get("$dist").and_then(|d| d.as_f64()).unwrap_or(0.0);
+                Some(ScoredSpan { span, distance })
+            })
+            .filter(|scored| filter.matches(&scored.span))
+            .collect())
     }
 
     // --- Dataset operations ---
@@ -647,6 +1657,7 @@ impl StorageBackend for TurbopufferBackend {
             "name": dataset.name,
             "created_at": dataset.created_at.to_rfc3339(),
             "updated_at": dataset.updated_at.to_rfc3339(),
+            "version": dataset.version,
         });
 
         self.upsert("datasets", vec![row]).await?;
@@ -681,6 +1692,20 @@ impl StorageBackend for TurbopufferBackend {
         Ok(count > 0)
     }
 
+    async fn update_dataset(&self, dataset: &Dataset, expected: u64) -> Result<bool, StorageError> {
+        let id = dataset.id.to_string();
+        let row = serde_json::json!({
+            "id": id,
+            "data": serde_json::to_string(dataset)?,
+            "name": dataset.name,
+            "created_at": dataset.created_at.to_rfc3339(),
+            "updated_at": dataset.updated_at.to_rfc3339(),
+            "version": dataset.version,
+        });
+
+        Ok(self.upsert_versioned("datasets", &id, row, expected).await?)
+    }
+
     // --- Datapoint operations ---
 
     async fn save_datapoint(&self, dp: &Datapoint) -> Result<(), StorageError> {
@@ -690,10 +1715,10 @@ impl StorageBackend for TurbopufferBackend {
             "dataset_id": dp.dataset_id.to_string(),
             "source": format!("{:?}", dp.source),
             "created_at": dp.created_at.to_rfc3339(),
+            "version": dp.version,
         });
 
-        let schema = serde_json::json!({"data": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("datapoints", vec![row], schema).await?;
+        self.upsert("datapoints", vec![row]).await?;
         Ok(())
     }
 
@@ -736,6 +1761,20 @@ impl StorageBackend for TurbopufferBackend {
         Ok(count > 0)
     }
 
+    async fn update_datapoint(&self, dp: &Datapoint, expected: u64) -> Result<bool, StorageError> {
+        let id = dp.id.to_string();
+        let row = serde_json::json!({
+            "id": id,
+            "data": serde_json::to_string(dp)?,
+            "dataset_id": dp.dataset_id.to_string(),
+            "source": format!("{:?}", dp.source),
+            "created_at": dp.created_at.to_rfc3339(),
+            "version": dp.version,
+        });
+
+        Ok(self.upsert_versioned("datapoints", &id, row, expected).await?)
+    }
+
     async fn delete_dataset_datapoints(
         &self,
         dataset_id: DatasetId,
@@ -764,8 +1803,7 @@ impl StorageBackend for TurbopufferBackend {
             "created_at": item.created_at.to_rfc3339(),
         });
 
-        let schema = serde_json::json!({"data": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("queue_items", vec![row], schema).await?;
+        self.upsert("queue_items", vec![row]).await?;
         Ok(())
     }
 
@@ -807,12 +1845,45 @@ impl StorageBackend for TurbopufferBackend {
     }
 
     async fn delete_queue_item(&self, id: QueueItemId) -> Result<bool, StorageError> {
+        let filter = serde_json::json!(["queue_item_id", "Eq", id.to_string()]);
+        self.delete_by_filter("queue_submissions", Some(filter))
+            .await?;
         let count = self
             .delete_ids("queue_items", vec![id.to_string()])
             .await?;
         Ok(count > 0)
     }
 
+    async fn save_queue_submission(
+        &self,
+        submission: &QueueSubmission,
+    ) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": submission.id.to_string(),
+            "data": serde_json::to_string(submission)?,
+            "queue_item_id": submission.queue_item_id.to_string(),
+            "reviewer": submission.reviewer,
+            "created_at": submission.created_at.to_rfc3339(),
+        });
+        self.upsert("queue_submissions", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_queue_submissions(
+        &self,
+        queue_item_id: QueueItemId,
+    ) -> Result<Vec<QueueSubmission>, StorageError> {
+        let filter = serde_json::json!(["queue_item_id", "Eq", queue_item_id.to_string()]);
+        let mut submissions: Vec<QueueSubmission> = self
+            .query_all("queue_submissions", Some(filter))
+            .await?
+            .iter()
+            .filter_map(Self::extract_data)
+            .collect();
+        submissions.sort_by_key(|s| s.created_at);
+        Ok(submissions)
+    }
+
     // --- Eval Run operations ---
 
     async fn save_eval_run(&self, run: &EvalRun) -> Result<(), StorageError> {
@@ -874,8 +1945,7 @@ impl StorageBackend for TurbopufferBackend {
             "datapoint_id": result.datapoint_id.to_string(),
             "status": result.status.as_str(),
         });
-        let schema = serde_json::json!({"data": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("eval_results", vec![row], schema).await?;
+        self.upsert("eval_results", vec![row]).await?;
         Ok(())
     }
 
@@ -1006,6 +2076,333 @@ impl StorageBackend for TurbopufferBackend {
         Ok(count > 0)
     }
 
+    // --- Span Kind Definition operations ---
+
+    async fn save_span_kind(&self, def: &SpanKindDefinition) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": def.name,
+            "data": serde_json::to_string(def)?,
+            "updated_at": def.updated_at.to_rfc3339(),
+        });
+        self.upsert("span_kinds", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError> {
+        let results = self.query_all("span_kinds", None).await?;
+        let mut defs: Vec<SpanKindDefinition> = results
+            .iter()
+            .filter_map(Self::extract_data::<SpanKindDefinition>)
+            .collect();
+        defs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(defs)
+    }
+
+    async fn delete_span_kind(&self, name: &str) -> Result<bool, StorageError> {
+        let count = self.delete_ids("span_kinds", vec![name.to_string()]).await?;
+        Ok(count > 0)
+    }
+
+    // --- Machine registry ---
+
+    async fn save_machine(&self, machine: &Machine) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": machine.id,
+            "data": serde_json::to_string(machine)?,
+            "last_seen": machine.last_seen.to_rfc3339(),
+        });
+        self.upsert("machines", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_machines(&self) -> Result<Vec<Machine>, StorageError> {
+        let results = self.query_all("machines", None).await?;
+        let mut machines: Vec<Machine> = results
+            .iter()
+            .filter_map(Self::extract_data::<Machine>)
+            .collect();
+        machines.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(machines)
+    }
+
+    async fn delete_machine(&self, id: &str) -> Result<bool, StorageError> {
+        let count = self.delete_ids("machines", vec![id.to_string()]).await?;
+        Ok(count > 0)
+    }
+
+    // --- Settings ---
+
+    async fn get_setting(&self, key: &str) -> Result<Option<serde_json::Value>, StorageError> {
+        Ok(self
+            .get_by_id("settings", key)
+            .await?
+            .as_ref()
+            .and_then(Self::extract_data))
+    }
+
+    async fn save_setting(&self, key: &str, value: &serde_json::Value) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": key,
+            "data": value.to_string(),
+        });
+        self.upsert("settings", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn delete_settings(&self, keys: &[String]) -> Result<(), StorageError> {
+        self.delete_ids("settings", keys.to_vec()).await?;
+        Ok(())
+    }
+
+    async fn update_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, StorageError> {
+        self.flush_collection("settings").await?;
+        let stored = self.get_by_id("settings", key).await?;
+        if stored.as_ref().and_then(Self::extract_data).as_ref() != expected {
+            return Ok(false);
+        }
+        // Settings saved without a version count as version 0
+        let version = stored
+            .as_ref()
+            .and_then(|row| row.get("version"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        let row = serde_json::json!({
+            "id": key,
+            "data": value.to_string(),
+            "version": version + 1,
+        });
+        if stored.is_none() {
+            // A conditional upsert can't require a row to be missing, so
+            // the first write is as racy as `save_setting`
+            self.upsert("settings", vec![row]).await?;
+            return Ok(true);
+        }
+        Ok(self.upsert_versioned("settings", key, row, version).await?)
+    }
+
+    // --- Webhooks ---
+
+    async fn save_webhook(&self, webhook: &Webhook) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": webhook.id.to_string(),
+            "data": serde_json::to_string(webhook)?,
+        });
+        self.upsert("webhooks", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, StorageError> {
+        let results = self.query_all("webhooks", None).await?;
+        let mut webhooks: Vec<Webhook> = results
+            .iter()
+            .filter_map(Self::extract_data::<Webhook>)
+            .collect();
+        webhooks.sort_by_key(|w| w.created_at);
+        Ok(webhooks)
+    }
+
+    async fn delete_webhook(&self, id: WebhookId) -> Result<bool, StorageError> {
+        let filter = serde_json::json!(["webhook_id", "Eq", id.to_string()]);
+        self.delete_by_filter("webhook_deliveries", Some(filter)).await?;
+        let count = self.delete_ids("webhooks", vec![id.to_string()]).await?;
+        Ok(count > 0)
+    }
+
+    async fn save_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": delivery.id.to_string(),
+            "data": serde_json::to_string(delivery)?,
+            "webhook_id": delivery.webhook_id.to_string(),
+            "created_at": delivery.created_at.to_rfc3339(),
+        });
+        self.upsert("webhook_deliveries", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: WebhookId,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, StorageError> {
+        let filter = serde_json::json!(["webhook_id", "Eq", webhook_id.to_string()]);
+        let rows = self
+            .query_sorted(
+                "webhook_deliveries",
+                Some(filter),
+                "created_at",
+                true,
+                limit,
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(Self::extract_data::<WebhookDelivery>)
+            .collect())
+    }
+
+    // --- Audit log ---
+
+    async fn save_audit_event(&self, event: &AuditEvent) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": event.id.to_string(),
+            "data": serde_json::to_string(event)?,
+            "actor": event.actor,
+            "action": event.action,
+            "created_at": event.created_at.to_rfc3339(),
+        });
+        self.upsert("audit_events", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, StorageError> {
+        let mut conditions = Vec::new();
+        if let Some(ref actor) = filter.actor {
+            conditions.push(serde_json::json!(["actor", "Eq", actor]));
+        }
+        match filter.action.as_deref() {
+            Some(prefix) if prefix.ends_with('.') => {
+                conditions.push(serde_json::json!(["action", "Glob", format!("{prefix}*")]));
+            }
+            Some(action) => conditions.push(serde_json::json!(["action", "Eq", action])),
+            None => {}
+        }
+        if let Some(since) = filter.since {
+            conditions.push(serde_json::json!(["created_at", "Gte", since.to_rfc3339()]));
+        }
+        if let Some(until) = filter.until {
+            conditions.push(serde_json::json!(["created_at", "Lte", until.to_rfc3339()]));
+        }
+        let filters = match conditions.len() {
+            0 => None,
+            1 => conditions.pop(),
+            _ => Some(serde_json::json!(["And", conditions])),
+        };
+        let rows = self
+            .query_sorted(
+                "audit_events",
+                filters,
+                "created_at",
+                true,
+                filter.limit.unwrap_or(filter::DEFAULT_PAGE_LIMIT),
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(Self::extract_data::<AuditEvent>)
+            .collect())
+    }
+
+    async fn delete_audit_events_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError> {
+        let filter = serde_json::json!(["created_at", "Lt", cutoff.to_rfc3339()]);
+        Ok(self.delete_by_filter("audit_events", Some(filter)).await?)
+    }
+
+    // --- Annotations ---
+
+    async fn save_annotation(&self, annotation: &Annotation) -> Result<(), StorageError> {
+        let mut row = serde_json::json!({
+            "id": annotation.id.to_string(),
+            "data": serde_json::to_string(annotation)?,
+            "span_id": annotation.span_id.to_string(),
+            "trace_id": annotation.trace_id.to_string(),
+            "created_by": annotation.created_by,
+            "created_at": annotation.created_at.to_rfc3339(),
+        });
+        if let Some(score) = annotation.score {
+            row["score"] = serde_json::json!(score);
+        }
+        if let Some(ref label) = annotation.label {
+            row["label"] = serde_json::json!(label);
+        }
+        self.upsert("annotations", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_annotations(
+        &self,
+        filter: &AnnotationFilter,
+    ) -> Result<Vec<Annotation>, StorageError> {
+        let mut conditions = Vec::new();
+        if let Some(span_id) = filter.span_id {
+            conditions.push(serde_json::json!(["span_id", "Eq", span_id.to_string()]));
+        }
+        if let Some(trace_id) = filter.trace_id {
+            conditions.push(serde_json::json!(["trace_id", "Eq", trace_id.to_string()]));
+        }
+        if let Some(ref label) = filter.label {
+            conditions.push(serde_json::json!(["label", "Eq", label]));
+        }
+        if let Some(min) = filter.score_min {
+            conditions.push(serde_json::json!(["score", "Gte", min]));
+        }
+        if let Some(max) = filter.score_max {
+            conditions.push(serde_json::json!(["score", "Lte", max]));
+        }
+        if let Some(ref created_by) = filter.created_by {
+            conditions.push(serde_json::json!(["created_by", "Eq", created_by]));
+        }
+        let filters = match conditions.len() {
+            0 => None,
+            1 => conditions.pop(),
+            _ => Some(serde_json::json!(["And", conditions])),
+        };
+        let rows = self
+            .query_sorted(
+                "annotations",
+                filters,
+                "created_at",
+                true,
+                filter.limit.unwrap_or(usize::MAX),
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(Self::extract_data::<Annotation>)
+            .collect())
+    }
+
+    async fn delete_annotation(&self, id: AnnotationId) -> Result<bool, StorageError> {
+        let count = self.delete_ids("annotations", vec![id.to_string()]).await?;
+        Ok(count > 0)
+    }
+
+    // --- Saved views ---
+
+    async fn save_view(&self, view: &SavedView) -> Result<(), StorageError> {
+        let row = serde_json::json!({
+            "id": view.id.to_string(),
+            "data": serde_json::to_string(view)?,
+        });
+        self.upsert("saved_views", vec![row]).await?;
+        Ok(())
+    }
+
+    async fn list_views(&self) -> Result<Vec<SavedView>, StorageError> {
+        let results = self.query_all("saved_views", None).await?;
+        let mut views: Vec<SavedView> = results
+            .iter()
+            .filter_map(Self::extract_data::<SavedView>)
+            .collect();
+        views.sort_by_key(|v| v.created_at);
+        Ok(views)
+    }
+
+    async fn delete_view(&self, id: SavedViewId) -> Result<bool, StorageError> {
+        let count = self.delete_ids("saved_views", vec![id.to_string()]).await?;
+        Ok(count > 0)
+    }
+
     // --- File operations ---
 
     async fn save_file_version(&self, version: &FileVersion) -> Result<(), StorageError> {
@@ -1037,6 +2434,14 @@ impl StorageBackend for TurbopufferBackend {
         Ok(versions)
     }
 
+    async fn delete_file_versions_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError> {
+        let filter = serde_json::json!(["created_at", "Lte", cutoff.to_rfc3339()]);
+        Ok(self.delete_by_filter("file_versions", Some(filter)).await?)
+    }
+
     async fn save_file_content(&self, hash: &str, content: &[u8]) -> Result<(), StorageError> {
         let encoded = base64::engine::general_purpose::STANDARD.encode(content);
         let row = serde_json::json!({
@@ -1045,8 +2450,7 @@ impl StorageBackend for TurbopufferBackend {
             "size": content.len(),
         });
 
-        let schema = serde_json::json!({"content_base64": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("file_contents", vec![row], schema).await?;
+        self.upsert("file_contents", vec![row]).await?;
         Ok(())
     }
 
@@ -1066,6 +2470,25 @@ impl StorageBackend for TurbopufferBackend {
         }
     }
 
+    async fn list_file_contents(&self) -> Result<Vec<(String, u64)>, StorageError> {
+        // Sizes only; the content itself isn't needed
+        let rows = self
+            .query_all_with("file_contents", None, serde_json::json!(["size"]))
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let hash = row.get("id")?.as_str()?.to_string();
+                let size = row.get("size").and_then(|v| v.as_u64()).unwrap_or(0);
+                Some((hash, size))
+            })
+            .collect())
+    }
+
+    async fn delete_file_contents(&self, hashes: &[String]) -> Result<usize, StorageError> {
+        Ok(self.delete_ids("file_contents", hashes.to_vec()).await?)
+    }
+
     // --- Batch operations (optimized for cloud) ---
 
     async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError> {
@@ -1091,8 +2514,7 @@ impl StorageBackend for TurbopufferBackend {
             })
             .collect::<Result<Vec<_>, serde_json::Error>>();
 
-        let schema = serde_json::json!({"data": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("spans", rows?, schema).await?;
+        self.upsert("spans", rows?).await?;
         Ok(())
     }
 
@@ -1110,12 +2532,12 @@ impl StorageBackend for TurbopufferBackend {
                     "dataset_id": dp.dataset_id.to_string(),
                     "source": format!("{:?}", dp.source),
                     "created_at": dp.created_at.to_rfc3339(),
+                    "version": dp.version,
                 }))
             })
             .collect::<Result<Vec<_>, serde_json::Error>>();
 
-        let schema = serde_json::json!({"data": {"type": "string", "filterable": false}});
-        self.upsert_with_schema("datapoints", rows?, schema).await?;
+        self.upsert("datapoints", rows?).await?;
         Ok(())
     }
 }
@@ -1141,4 +2563,47 @@ mod tests {
         assert_eq!(config.base_url, "http://localhost:8080");
         assert_eq!(config.timeout_secs, 60);
     }
+
+    #[test]
+    fn only_transient_api_errors_are_retryable() {
+        let api = |status| TurbopufferError::Api {
+            status,
+            message: String::new(),
+        };
+        assert!(api(503).is_retryable());
+        assert!(api(429).is_retryable());
+        assert!(!api(400).is_retryable());
+        assert!(!api(404).is_retryable());
+        assert!(!TurbopufferError::Config("no key".into()).is_retryable());
+    }
+
+    #[test]
+    fn continuation_skips_rows_already_read() {
+        let value = serde_json::json!("2024-01-01T00:00:00+00:00");
+        let ties = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            continue_after("started_at", &value, &ties, true),
+            serde_json::json!([
+                "Or",
+                [
+                    ["started_at", "Lt", value],
+                    [
+                        "And",
+                        [["started_at", "Eq", value], ["id", "NotIn", ["a", "b"]]]
+                    ]
+                ]
+            ])
+        );
+    }
+
+    #[test]
+    fn trace_tags_are_filtered_in_turbopuffer() {
+        let filter = TraceFilter {
+            tags: Some(vec!["prod".into(), "checkout".into()]),
+            ..Default::default()
+        };
+        let conditions = trace_conditions(&filter);
+        assert!(conditions.contains(&serde_json::json!(["tags", "Contains", "prod"])));
+        assert!(conditions.contains(&serde_json::json!(["tags", "Contains", "checkout"])));
+    }
 }
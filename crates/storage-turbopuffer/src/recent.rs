@@ -0,0 +1,176 @@
+//! Read-your-writes overlay for Turbopuffer.
+//!
+//! Turbopuffer makes upserts visible to queries eventually, so a row read
+//! right after it was written can come back stale or missing. Rows written
+//! and ids deleted through this backend are remembered for a short window,
+//! and reads consult them first: by-id reads see the latest write, and list
+//! reads have rows replaced or dropped accordingly. Rows a list query would
+//! newly match are not added, since Turbopuffer filters can't be evaluated
+//! here; lists catch up once the window has passed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Remembered rows beyond which the oldest are forgotten early, to bound
+/// memory under a sustained write burst.
+const MAX_ENTRIES: usize = 100_000;
+
+type Key = (String, String);
+
+struct Entry {
+    /// `None` once deleted.
+    row: Option<serde_json::Value>,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    /// Write order, for expiry. Superseded writes stay queued and are
+    /// skipped when they reach the front.
+    order: VecDeque<(Key, Instant)>,
+}
+
+pub(crate) struct RecentWrites {
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+/// What a read should use in place of Turbopuffer's answer for one id.
+pub(crate) enum Recent {
+    Written(serde_json::Value),
+    Deleted,
+}
+
+impl RecentWrites {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn record_rows(&self, collection: &str, rows: &[serde_json::Value]) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        for row in rows {
+            if let Some(id) = row.get("id").and_then(|v| v.as_str()) {
+                state.insert(
+                    (collection.to_string(), id.to_string()),
+                    Some(row.clone()),
+                    now,
+                );
+            }
+        }
+        state.expire(now, self.ttl);
+    }
+
+    pub fn record_deletes(&self, collection: &str, ids: &[String]) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        for id in ids {
+            state.insert((collection.to_string(), id.clone()), None, now);
+        }
+        state.expire(now, self.ttl);
+    }
+
+    /// Forget a collection after a delete whose ids aren't known. Its reads
+    /// go straight to Turbopuffer again.
+    pub fn forget_collection(&self, collection: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.retain(|(c, _), _| c != collection);
+    }
+
+    pub fn get(&self, collection: &str, id: &str) -> Option<Recent> {
+        let state = self.state.lock().unwrap();
+        let entry = state
+            .entries
+            .get(&(collection.to_string(), id.to_string()))
+            .filter(|e| e.at.elapsed() < self.ttl)?;
+        Some(match &entry.row {
+            Some(row) => Recent::Written(row.clone()),
+            None => Recent::Deleted,
+        })
+    }
+
+    /// Apply remembered writes and deletes to rows read from `collection`.
+    pub fn overlay(
+        &self,
+        collection: &str,
+        rows: Vec<serde_json::Value>,
+    ) -> Vec<serde_json::Value> {
+        rows.into_iter()
+            .filter_map(|row| {
+                let Some(id) = row.get("id").and_then(|v| v.as_str()) else {
+                    return Some(row);
+                };
+                match self.get(collection, id) {
+                    Some(Recent::Written(newer)) => Some(newer),
+                    Some(Recent::Deleted) => None,
+                    None => Some(row),
+                }
+            })
+            .collect()
+    }
+}
+
+impl State {
+    fn insert(&mut self, key: Key, row: Option<serde_json::Value>, at: Instant) {
+        self.order.push_back((key.clone(), at));
+        self.entries.insert(key, Entry { row, at });
+    }
+
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while let Some((key, at)) = self.order.front() {
+            if now.duration_since(*at) < ttl && self.entries.len() <= MAX_ENTRIES {
+                break;
+            }
+            if self.entries.get(key).is_some_and(|e| e.at == *at) {
+                self.entries.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ids(rows: &[serde_json::Value]) -> Vec<&str> {
+        rows.iter().map(|r| r["id"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn reads_see_recent_writes_and_deletes() {
+        let recent = RecentWrites::new(Duration::from_secs(60));
+        recent.record_rows("spans", &[json!({"id": "a", "v": 2}), json!({"id": "b"})]);
+        recent.record_deletes("spans", &["c".to_string()]);
+
+        assert!(matches!(recent.get("spans", "a"), Some(Recent::Written(r)) if r["v"] == 2));
+        assert!(matches!(recent.get("spans", "c"), Some(Recent::Deleted)));
+        assert!(recent.get("traces", "a").is_none());
+
+        let stale = vec![
+            json!({"id": "a", "v": 1}),
+            json!({"id": "c"}),
+            json!({"id": "d"}),
+        ];
+        let rows = recent.overlay("spans", stale);
+        assert_eq!(ids(&rows), ["a", "d"]);
+        assert_eq!(rows[0]["v"], 2);
+
+        recent.forget_collection("spans");
+        assert!(recent.get("spans", "a").is_none());
+    }
+
+    #[test]
+    fn entries_expire() {
+        let recent = RecentWrites::new(Duration::ZERO);
+        recent.record_rows("spans", &[json!({"id": "a"})]);
+        assert!(recent.get("spans", "a").is_none());
+        assert!(recent.state.lock().unwrap().entries.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! Retries and hedging for Turbopuffer requests.
+//!
+//! A request that fails with a network error, 408, 429 or 5xx is retried up
+//! to `max_attempts` times in all, sleeping a jittered exponential backoff
+//! between attempts. Every request the backend sends is idempotent (upserts
+//! are keyed by id, deletes by id or filter, queries only read), so all are
+//! retried; a non-idempotent call must not go through the retrying path.
+//!
+//! Queries can also be hedged: if one hasn't answered within `hedge_after`,
+//! an identical query is sent and whichever answers first is used.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    /// Attempts per request, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    /// Backoff before the first retry, doubling for each one after.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Send a second copy of a query that hasn't answered after this long.
+    pub hedge_after: Option<Duration>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            hedge_after: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            hedge_after: None,
+            ..Default::default()
+        }
+    }
+
+    /// Sleep before retry number `retry` (1-based): uniformly random up to
+    /// the exponential backoff, so clients that failed together don't retry
+    /// together.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        exp.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Cumulative retry counters for one backend.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RetryStats {
+    /// Retries sent after a retryable failure.
+    pub retries: u64,
+    /// Requests that succeeded after at least one retry.
+    pub recovered: u64,
+    /// Requests that still failed with a retryable error after the last
+    /// attempt.
+    pub exhausted: u64,
+    /// Hedged copies sent for slow queries.
+    pub hedges: u64,
+    /// Queries answered by their hedged copy.
+    pub hedge_wins: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct RetryCounters {
+    retries: AtomicU64,
+    recovered: AtomicU64,
+    exhausted: AtomicU64,
+    hedges: AtomicU64,
+    hedge_wins: AtomicU64,
+}
+
+impl RetryCounters {
+    pub fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn recovered(&self) {
+        self.recovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn exhausted(&self) {
+        self.exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hedge(&self) {
+        self.hedges.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hedge_win(&self) {
+        self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.retries.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+            hedges: self.hedges.load(Ordering::Relaxed),
+            hedge_wins: self.hedge_wins.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            ..Default::default()
+        };
+        for _ in 0..50 {
+            assert!(config.backoff(1) <= Duration::from_millis(100));
+            assert!(config.backoff(2) <= Duration::from_millis(200));
+            assert!(config.backoff(3) <= Duration::from_millis(350));
+            assert!(config.backoff(40) <= Duration::from_millis(350));
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Declared attribute schemas of the Turbopuffer collections.
+//!
+//! Every upsert sends its collection's schema, so a namespace is created
+//! with these types and index settings instead of ones inferred from its
+//! first row. `TurbopufferBackend::ensure_schemas` checks live namespaces
+//! against the declarations on startup and applies index setting changes;
+//! `TurbopufferBackend::migrate` also rewrites rows saved before an
+//! attribute was declared, so they carry it.
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// Every collection the backend writes.
+pub const COLLECTIONS: &[&str] = &[
+    "traces",
+    "spans",
+    "datasets",
+    "datapoints",
+    "queue_items",
+    "queue_submissions",
+    "eval_runs",
+    "eval_results",
+    "capture_rules",
+    "provider_connections",
+    "span_kinds",
+    "machines",
+    "settings",
+    "webhooks",
+    "webhook_deliveries",
+    "audit_events",
+    "annotations",
+    "saved_views",
+    "file_versions",
+    "file_contents",
+];
+
+enum Attr {
+    /// Filterable string.
+    Str,
+    /// String with a BM25 full-text index, also filterable.
+    Text,
+    /// String that is only read back, never filtered on. Unindexed
+    /// attributes are cheaper to store.
+    Stored,
+    Float,
+    Uint,
+    Bool,
+    /// Filterable list of strings, matched with `Contains`.
+    StrList,
+}
+
+fn attributes(collection: &str) -> &'static [(&'static str, Attr)] {
+    use Attr::*;
+    match collection {
+        "traces" => &[
+            ("data", Stored),
+            ("name", Text),
+            ("started_at", Str),
+            ("ended_at", Str),
+            ("machine_id", Str),
+            ("session_id", Str),
+            ("git_commit", Str),
+            ("git_branch", Str),
+            ("repo", Str),
+            ("trashed", Bool),
+            ("tags", StrList),
+        ],
+        "spans" => &[
+            ("data", Stored),
+            ("trace_id", Str),
+            ("name", Text),
+            ("kind", Str),
+            ("status", Str),
+            ("model", Str),
+            ("provider", Str),
+            ("started_at", Str),
+            ("ended_at", Str),
+        ],
+        "datasets" => &[
+            ("data", Stored),
+            ("name", Text),
+            ("created_at", Str),
+            ("updated_at", Str),
+            ("version", Uint),
+        ],
+        "datapoints" => &[
+            ("data", Stored),
+            ("dataset_id", Str),
+            ("source", Str),
+            ("created_at", Str),
+            ("version", Uint),
+        ],
+        "queue_items" => &[
+            ("data", Stored),
+            ("dataset_id", Str),
+            ("datapoint_id", Str),
+            ("status", Str),
+            ("claimed_by", Str),
+            ("created_at", Str),
+        ],
+        "queue_submissions" => &[
+            ("data", Stored),
+            ("queue_item_id", Str),
+            ("reviewer", Str),
+            ("created_at", Str),
+        ],
+        "eval_runs" => &[
+            ("data", Stored),
+            ("dataset_id", Str),
+            ("status", Str),
+            ("created_at", Str),
+        ],
+        "eval_results" => &[
+            ("data", Stored),
+            ("run_id", Str),
+            ("datapoint_id", Str),
+            ("status", Str),
+        ],
+        "capture_rules" => &[
+            ("data", Stored),
+            ("dataset_id", Str),
+            ("enabled", Bool),
+            ("created_at", Str),
+        ],
+        "provider_connections" => &[
+            ("data", Stored),
+            ("name", Str),
+            ("provider", Str),
+            ("created_at", Str),
+            ("updated_at", Str),
+        ],
+        "span_kinds" => &[("data", Stored), ("updated_at", Str)],
+        "machines" => &[("data", Stored), ("last_seen", Str)],
+        "settings" => &[("data", Stored), ("version", Uint)],
+        "webhooks" | "saved_views" => &[("data", Stored)],
+        "webhook_deliveries" => &[("data", Stored), ("webhook_id", Str), ("created_at", Str)],
+        "audit_events" => &[
+            ("data", Stored),
+            ("actor", Str),
+            ("action", Str),
+            ("created_at", Str),
+        ],
+        "annotations" => &[
+            ("data", Stored),
+            ("span_id", Str),
+            ("trace_id", Str),
+            ("score", Float),
+            ("label", Str),
+            ("created_by", Str),
+            ("created_at", Str),
+        ],
+        "file_versions" => &[
+            ("data", Stored),
+            ("path", Str),
+            ("hash", Str),
+            ("created_at", Str),
+        ],
+        "file_contents" => &[("content_base64", Stored)],
+        _ => &[],
+    }
+}
+
+/// The schema sent with writes to `collection`, in Turbopuffer's format.
+pub fn declared(collection: &str) -> Value {
+    let schema: Map<String, Value> = attributes(collection)
+        .iter()
+        .map(|(name, attr)| {
+            let spec = match attr {
+                Attr::Str => json!({"type": "string"}),
+                Attr::Text => json!({"type": "string", "full_text_search": true}),
+                Attr::Stored => json!({"type": "string", "filterable": false}),
+                Attr::Float => json!({"type": "float"}),
+                Attr::Uint => json!({"type": "uint"}),
+                Attr::Bool => json!({"type": "bool"}),
+                Attr::StrList => json!({"type": "[]string"}),
+            };
+            (name.to_string(), spec)
+        })
+        .collect();
+    Value::Object(schema)
+}
+
+/// How a namespace's live schema differs from its declaration.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SchemaDiff {
+    /// Declared attributes no row has yet. Rows written before the
+    /// declaration lack them until rewritten.
+    pub missing: Vec<String>,
+    /// Attributes whose index settings differ; fixable in place.
+    pub changed: Vec<String>,
+    /// Attributes stored with a different type, which Turbopuffer can't
+    /// convert. Writes to them fail until the namespace is rebuilt.
+    pub conflicts: Vec<String>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// Compare a declared schema against a namespace's live one. Attributes
+/// that aren't declared are ignored.
+pub fn diff(declared: &Value, live: &Value) -> SchemaDiff {
+    let mut diff = SchemaDiff::default();
+    let Some(declared) = declared.as_object() else {
+        return diff;
+    };
+    for (name, want) in declared {
+        let Some(have) = live.get(name) else {
+            diff.missing.push(name.clone());
+            continue;
+        };
+        if attr_type(want) != attr_type(have) {
+            diff.conflicts.push(name.clone());
+        } else if filterable(want) != filterable(have) || full_text(want) != full_text(have) {
+            diff.changed.push(name.clone());
+        }
+    }
+    diff
+}
+
+fn attr_type(spec: &Value) -> Option<&str> {
+    match spec {
+        Value::String(t) => Some(t),
+        _ => spec.get("type")?.as_str(),
+    }
+}
+
+fn filterable(spec: &Value) -> bool {
+    spec.get("filterable")
+        .and_then(Value::as_bool)
+        .unwrap_or(true)
+}
+
+/// `full_text_search` may be `true` or an object of tokenizer options.
+fn full_text(spec: &Value) -> bool {
+    match spec.get("full_text_search") {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_collection_is_declared() {
+        for collection in COLLECTIONS {
+            assert!(
+                !declared(collection).as_object().unwrap().is_empty(),
+                "{collection} has no declared schema"
+            );
+        }
+    }
+
+    #[test]
+    fn diff_sorts_out_missing_changed_and_conflicting() {
+        let live = json!({
+            "data": {"type": "string", "filterable": true},
+            "trace_id": {"type": "string", "filterable": true},
+            "name": {"type": "string", "full_text_search": {"language": "english"}},
+            "status": {"type": "int"},
+            "extra": {"type": "string"},
+        });
+        let d = diff(&declared("spans"), &live);
+        assert_eq!(d.changed, ["data"]);
+        assert_eq!(d.conflicts, ["status"]);
+        assert_eq!(
+            d.missing,
+            ["ended_at", "kind", "model", "provider", "started_at"]
+        );
+        assert!(diff(&declared("spans"), &declared("spans")).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! Analytical store: a SQL mirror of span summary columns.
+//!
+//! `PersistentStore::with_analytical` copies each span's summary (ids,
+//! kind, model, status, timing, cost and tokens, but no payloads) into an
+//! [`AnalyticalStore`] as the span is written, and drops it when the span
+//! is deleted. `/api/analytics` and timeseries queries then aggregate in
+//! SQL instead of loading every matching span. Metrics that need the spans
+//! themselves (percentiles, concurrency, queue gaps) are still computed in
+//! memory, as is grouping by trace tag, which can change after the span
+//! is mirrored; see [`supports`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use trace::{
+    AnalyticsFilter, AnalyticsGroup, AnalyticsInterval, AnalyticsMetric, AnalyticsQuery,
+    AnalyticsResponse, GroupByField, MetricValues, Span, SpanId, SpanKind, TimeSeries,
+    TimeseriesQuery, TimeseriesResponse, TraceId,
+};
+
+use crate::analytics::bucket_count;
+use crate::error::StorageError;
+
+/// The columns mirrored for one span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRow {
+    pub id: SpanId,
+    pub trace_id: TraceId,
+    /// Normalized where rules apply, as grouped by `name`.
+    pub name: String,
+    pub kind: String,
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub tool: Option<String>,
+    pub index: Option<String>,
+    pub status: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: Option<i64>,
+    pub cost: Option<f64>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    pub total_tokens: Option<u64>,
+    /// Custom span attributes as text, as grouped by `attribute`.
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl From<&Span> for SpanRow {
+    fn from(span: &Span) -> Self {
+        let kind = span.kind();
+        Self {
+            id: span.id(),
+            trace_id: span.trace_id(),
+            name: span.group_name().to_string(),
+            kind: kind.kind_name().to_string(),
+            model: kind.model().map(str::to_string),
+            provider: kind.provider().map(str::to_string),
+            tool: kind.tool_name().map(str::to_string),
+            index: kind.index().map(str::to_string),
+            status: span.status().as_str().to_string(),
+            started_at: span.started_at(),
+            duration_ms: span.duration_ms(),
+            cost: kind.cost(),
+            input_tokens: kind.input_tokens(),
+            output_tokens: kind.output_tokens(),
+            total_tokens: kind.total_tokens(),
+            attributes: match kind {
+                SpanKind::Custom { attributes, .. } => attributes
+                    .keys()
+                    .filter_map(|k| Some((k.clone(), kind.attribute(k)?)))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+        }
+    }
+}
+
+/// What to aggregate: spans matching `filter`, grouped by `group_by` and,
+/// for timeseries, by bucket.
+#[derive(Debug, Clone)]
+pub struct Aggregation {
+    pub filter: AnalyticsFilter,
+    pub group_by: Vec<GroupByField>,
+    pub buckets: Option<Buckets>,
+}
+
+/// Fixed-width buckets from the one containing `since` up to `until`.
+/// Spans starting outside `[since, until]` are left out.
+#[derive(Debug, Clone, Copy)]
+pub struct Buckets {
+    pub interval: AnalyticsInterval,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+}
+
+impl Buckets {
+    /// Start of the first bucket, in Unix milliseconds.
+    pub fn first_ms(&self) -> i64 {
+        self.interval.bucket_start(self.since).timestamp_millis()
+    }
+
+    pub fn width_ms(&self) -> i64 {
+        self.interval.duration().num_milliseconds()
+    }
+}
+
+/// Sums for one group, or one group in one bucket.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aggregate {
+    pub bucket: Option<usize>,
+    /// `(field, value)` pairs, sorted by field, with `unknown` for spans
+    /// that lack the field.
+    pub key: Vec<(String, String)>,
+    pub span_count: u64,
+    pub error_count: u64,
+    pub cost: f64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+    pub latency_sum_ms: f64,
+    pub latency_count: u64,
+}
+
+impl Aggregate {
+    fn add(&mut self, other: &Aggregate) {
+        self.span_count += other.span_count;
+        self.error_count += other.error_count;
+        self.cost += other.cost;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.total_tokens += other.total_tokens;
+        self.latency_sum_ms += other.latency_sum_ms;
+        self.latency_count += other.latency_count;
+    }
+
+    fn to_metrics(&self, requested: &[AnalyticsMetric]) -> MetricValues {
+        let mut mv = MetricValues::default();
+        for m in requested {
+            match m {
+                AnalyticsMetric::TotalCost => mv.total_cost = Some(self.cost),
+                AnalyticsMetric::TotalInputTokens => {
+                    mv.total_input_tokens = Some(self.input_tokens)
+                }
+                AnalyticsMetric::TotalOutputTokens => {
+                    mv.total_output_tokens = Some(self.output_tokens)
+                }
+                AnalyticsMetric::TotalTokens => mv.total_tokens = Some(self.total_tokens),
+                AnalyticsMetric::AvgLatencyMs => {
+                    mv.avg_latency_ms = Some(if self.latency_count > 0 {
+                        self.latency_sum_ms / self.latency_count as f64
+                    } else {
+                        0.0
+                    })
+                }
+                AnalyticsMetric::SpanCount => mv.span_count = Some(self.span_count),
+                AnalyticsMetric::ErrorCount => mv.error_count = Some(self.error_count),
+                // Never asked for; see `supports`
+                _ => {}
+            }
+        }
+        mv
+    }
+}
+
+/// An embedded analytical database holding [`SpanRow`]s.
+#[async_trait]
+pub trait AnalyticalStore: Send + Sync {
+    /// Name of the engine, for logs and health output.
+    fn engine(&self) -> &'static str;
+
+    /// Insert or replace rows by span id.
+    async fn upsert(&self, rows: &[SpanRow]) -> Result<(), StorageError>;
+
+    async fn delete(&self, ids: &[SpanId]) -> Result<(), StorageError>;
+
+    async fn delete_traces(&self, ids: &[TraceId]) -> Result<(), StorageError>;
+
+    async fn clear(&self) -> Result<(), StorageError>;
+
+    /// Start of the earliest span matching `filter`.
+    async fn earliest(
+        &self,
+        filter: &AnalyticsFilter,
+    ) -> Result<Option<DateTime<Utc>>, StorageError>;
+
+    /// One [`Aggregate`] per non-empty group (and bucket).
+    async fn aggregate(&self, query: &Aggregation) -> Result<Vec<Aggregate>, StorageError>;
+}
+
+/// Whether every metric and grouping can be computed from an
+/// [`AnalyticalStore`].
+pub fn supports(metrics: &[AnalyticsMetric], group_by: &[GroupByField]) -> bool {
+    !group_by.contains(&GroupByField::Tag)
+        && metrics.iter().all(|m| {
+            matches!(
+                m,
+                AnalyticsMetric::TotalCost
+                    | AnalyticsMetric::TotalInputTokens
+                    | AnalyticsMetric::TotalOutputTokens
+                    | AnalyticsMetric::TotalTokens
+                    | AnalyticsMetric::AvgLatencyMs
+                    | AnalyticsMetric::SpanCount
+                    | AnalyticsMetric::ErrorCount
+            )
+        })
+}
+
+/// Name of a group-by field in response keys, as `compute_analytics` uses.
+pub fn field_name(field: &GroupByField) -> String {
+    match field {
+        GroupByField::Attribute(name) => format!("attribute.{name}"),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+/// Answer `query` with the store.
+pub async fn analytics(
+    store: &dyn AnalyticalStore,
+    query: &AnalyticsQuery,
+) -> Result<AnalyticsResponse, StorageError> {
+    let aggregates = store
+        .aggregate(&Aggregation {
+            filter: query.filter.clone(),
+            group_by: query.group_by.clone(),
+            buckets: None,
+        })
+        .await?;
+    let mut totals = Aggregate::default();
+    let mut groups = Vec::new();
+    for agg in &aggregates {
+        totals.add(agg);
+        if !query.group_by.is_empty() {
+            groups.push(AnalyticsGroup {
+                key: agg.key.iter().cloned().collect(),
+                metrics: agg.to_metrics(&query.metrics),
+            });
+        }
+    }
+    Ok(AnalyticsResponse {
+        groups,
+        totals: totals.to_metrics(&query.metrics),
+    })
+}
+
+/// Answer `query` over `[since, until]` with the store, zero-filling
+/// empty buckets.
+pub async fn timeseries(
+    store: &dyn AnalyticalStore,
+    query: &TimeseriesQuery,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Result<TimeseriesResponse, StorageError> {
+    let buckets = Buckets {
+        interval: query.interval,
+        since,
+        until,
+    };
+    let aggregates = store
+        .aggregate(&Aggregation {
+            filter: query.filter.clone(),
+            group_by: query.group_by.clone(),
+            buckets: Some(buckets),
+        })
+        .await?;
+
+    let count = bucket_count(query.interval, since, until);
+    let mut totals = vec![Aggregate::default(); count];
+    let mut series: BTreeMap<Vec<(String, String)>, Vec<Aggregate>> = BTreeMap::new();
+    for agg in &aggregates {
+        let Some(i) = agg.bucket.filter(|&i| i < count) else {
+            continue;
+        };
+        totals[i].add(agg);
+        if !query.group_by.is_empty() {
+            series
+                .entry(agg.key.clone())
+                .or_insert_with(|| vec![Aggregate::default(); count])[i]
+                .add(agg);
+        }
+    }
+
+    let first = query.interval.bucket_start(since);
+    let width = query.interval.duration();
+    let metrics = |values: &[Aggregate]| -> Vec<MetricValues> {
+        values
+            .iter()
+            .map(|a| a.to_metrics(&query.metrics))
+            .collect()
+    };
+    Ok(TimeseriesResponse {
+        interval: query.interval,
+        buckets: (0..count as i32).map(|i| first + width * i).collect(),
+        totals: metrics(&totals),
+        series: series
+            .iter()
+            .map(|(key, values)| TimeSeries {
+                key: key.iter().cloned().collect::<HashMap<_, _>>(),
+                values: metrics(values),
+            })
+            .collect(),
+    })
+}
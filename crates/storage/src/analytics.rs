@@ -1,12 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use chrono::{DateTime, Utc};
 use trace::{
-    AnalyticsGroup, AnalyticsMetric, AnalyticsQuery, AnalyticsResponse, AnalyticsSummary,
-    GroupByField, MetricValues, ModelCost, ModelTokens, Span, SpanStatus,
+    AnalyticsGroup, AnalyticsInterval, AnalyticsMetric, AnalyticsQuery, AnalyticsResponse,
+    AnalyticsSummary, CohortMetrics, CommitDeltas, CommitMetrics, CompareDeltas, CompareResponse,
+    ConcurrencyBucket, ConcurrencyResponse, GroupByField, HistogramBucket, LatencyPercentiles,
+    MetricDelta, MetricValues, ModelCost, ModelTokens, Span, SpanId, SpanStatus, TimeSeries,
+    TimeseriesQuery, TimeseriesResponse, Trace, TraceConcurrency, TraceId, LATENCY_BUCKETS_MS,
 };
 
+use crate::analytical::field_name;
+
 /// Compute analytics from a set of spans according to the query.
-pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsResponse {
+/// `trace_tags` maps trace ids to their tags for grouping by `tag`; spans
+/// whose trace has no tags, or isn't in the map, group as `unknown`.
+pub fn compute_analytics(
+    spans: &[&Span],
+    trace_tags: &HashMap<TraceId, Vec<String>>,
+    query: &AnalyticsQuery,
+) -> AnalyticsResponse {
     // Accumulator per group
     struct Acc {
         cost: f64,
@@ -17,6 +29,10 @@ pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsRe
         latency_count: u64,
         span_count: u64,
         error_count: u64,
+        intervals: Vec<(i64, i64)>,
+        gaps: GapStats,
+        /// Finished span latencies, kept only for percentiles and histograms.
+        latencies: Vec<f64>,
     }
 
     impl Acc {
@@ -30,17 +46,29 @@ pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsRe
                 latency_count: 0,
                 span_count: 0,
                 error_count: 0,
+                intervals: Vec::new(),
+                gaps: GapStats::default(),
+                latencies: Vec::new(),
             }
         }
 
-        fn accumulate(&mut self, span: &Span) {
+        fn accumulate(&mut self, span: &Span, ctx: &SpanContext) {
             self.span_count += 1;
+            if ctx.track_intervals {
+                self.intervals.push(span_interval(span, ctx.now));
+            }
+            if let Some(gap) = ctx.queue_gap_ms(span) {
+                self.gaps.add(gap);
+            }
             if matches!(span.status(), SpanStatus::Failed { .. }) {
                 self.error_count += 1;
             }
             if let Some(ms) = span.duration_ms() {
                 self.latency_sum_ms += ms as f64;
                 self.latency_count += 1;
+                if ctx.track_latencies {
+                    self.latencies.push(ms as f64);
+                }
             }
             if let Some(c) = span.kind().cost() {
                 self.cost += c;
@@ -57,6 +85,8 @@ pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsRe
         }
 
         fn to_metrics(&self, requested: &[AnalyticsMetric]) -> MetricValues {
+            let mut latencies = self.latencies.clone();
+            latencies.sort_by(f64::total_cmp);
             let mut mv = MetricValues::default();
             for m in requested {
                 match m {
@@ -77,44 +107,119 @@ pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsRe
                     }
                     AnalyticsMetric::SpanCount => mv.span_count = Some(self.span_count),
                     AnalyticsMetric::ErrorCount => mv.error_count = Some(self.error_count),
+                    AnalyticsMetric::MaxConcurrency => {
+                        mv.max_concurrency = Some(max_concurrency(&self.intervals))
+                    }
+                    AnalyticsMetric::AvgQueueGapMs => mv.avg_queue_gap_ms = self.gaps.avg(),
+                    AnalyticsMetric::MaxQueueGapMs => mv.max_queue_gap_ms = self.gaps.max(),
+                    AnalyticsMetric::P50LatencyMs => {
+                        mv.p50_latency_ms = percentile(&latencies, 0.50)
+                    }
+                    AnalyticsMetric::P95LatencyMs => {
+                        mv.p95_latency_ms = percentile(&latencies, 0.95)
+                    }
+                    AnalyticsMetric::P99LatencyMs => {
+                        mv.p99_latency_ms = percentile(&latencies, 0.99)
+                    }
+                    AnalyticsMetric::LatencyHistogram => {
+                        mv.latency_histogram = Some(latency_histogram(&latencies))
+                    }
                 }
             }
             mv
         }
     }
 
-    fn group_key(span: &Span, fields: &[GroupByField]) -> HashMap<String, String> {
-        let mut key = HashMap::new();
+    fn group_value(span: &Span, field: &GroupByField) -> String {
+        match field {
+            GroupByField::Model => span.kind().model().unwrap_or("unknown").to_string(),
+            GroupByField::Provider => span.kind().provider().unwrap_or("unknown").to_string(),
+            GroupByField::Kind => span.kind().kind_name().to_string(),
+            GroupByField::Status => span.status().as_str().to_string(),
+            GroupByField::Trace => span.trace_id().to_string(),
+            GroupByField::Day => span.started_at().format("%Y-%m-%d").to_string(),
+            GroupByField::Hour => span.started_at().format("%Y-%m-%dT%H:00").to_string(),
+            GroupByField::Name => span.group_name().to_string(),
+            GroupByField::Tool => span.kind().tool_name().unwrap_or("unknown").to_string(),
+            GroupByField::Index => span.kind().index().unwrap_or("unknown").to_string(),
+            GroupByField::Attribute(name) => span
+                .kind()
+                .attribute(name)
+                .unwrap_or_else(|| "unknown".to_string()),
+            // Multi-valued; expanded by `group_keys`
+            GroupByField::Tag => unreachable!(),
+        }
+    }
+
+    /// Every key `span` groups under, sorted by field. One, unless grouping
+    /// by tag and the span's trace has several.
+    fn group_keys(
+        span: &Span,
+        fields: &[GroupByField],
+        trace_tags: &HashMap<TraceId, Vec<String>>,
+    ) -> Vec<Vec<(String, String)>> {
+        let mut keys = vec![Vec::new()];
         for field in fields {
-            let val = match field {
-                GroupByField::Model => span.kind().model().unwrap_or("unknown").to_string(),
-                GroupByField::Provider => span.kind().provider().unwrap_or("unknown").to_string(),
-                GroupByField::Kind => span.kind().kind_name().to_string(),
-                GroupByField::Status => span.status().as_str().to_string(),
-                GroupByField::Trace => span.trace_id().to_string(),
-                GroupByField::Day => span.started_at().format("%Y-%m-%d").to_string(),
-                GroupByField::Hour => span.started_at().format("%Y-%m-%dT%H:00").to_string(),
+            let values = match field {
+                GroupByField::Tag => match trace_tags.get(&span.trace_id()) {
+                    Some(tags) if !tags.is_empty() => tags.clone(),
+                    _ => vec!["unknown".to_string()],
+                },
+                field => vec![group_value(span, field)],
             };
-            key.insert(format!("{:?}", field).to_lowercase(), val);
+            let name = field_name(field);
+            let mut expanded = Vec::with_capacity(keys.len() * values.len());
+            for key in &keys {
+                for value in &values {
+                    let mut key = key.clone();
+                    key.push((name.clone(), value.clone()));
+                    expanded.push(key);
+                }
+            }
+            keys = expanded;
+        }
+        for key in &mut keys {
+            key.sort_by(|a, b| a.0.cmp(&b.0));
+            key.dedup_by(|a, b| a.0 == b.0);
         }
-        key
+        keys
     }
 
+    let ctx = SpanContext::new(
+        spans,
+        Utc::now(),
+        query.metrics.contains(&AnalyticsMetric::MaxConcurrency),
+        query.metrics.iter().any(|m| {
+            matches!(
+                m,
+                AnalyticsMetric::AvgQueueGapMs | AnalyticsMetric::MaxQueueGapMs
+            )
+        }),
+        query.metrics.iter().any(|m| {
+            matches!(
+                m,
+                AnalyticsMetric::P50LatencyMs
+                    | AnalyticsMetric::P95LatencyMs
+                    | AnalyticsMetric::P99LatencyMs
+                    | AnalyticsMetric::LatencyHistogram
+            )
+        }),
+    );
+
     // Single pass: accumulate into groups + totals
     let mut groups: HashMap<Vec<(String, String)>, Acc> = HashMap::new();
     let mut totals = Acc::new();
 
     for span in spans {
-        totals.accumulate(span);
+        totals.accumulate(span, &ctx);
 
         if !query.group_by.is_empty() {
-            let key_map = group_key(span, &query.group_by);
-            let mut sorted_key: Vec<(String, String)> = key_map.into_iter().collect();
-            sorted_key.sort_by(|a, b| a.0.cmp(&b.0));
-            groups
-                .entry(sorted_key)
-                .or_insert_with(Acc::new)
-                .accumulate(span);
+            for key in group_keys(span, &query.group_by, trace_tags) {
+                groups
+                    .entry(key)
+                    .or_insert_with(Acc::new)
+                    .accumulate(span, &ctx);
+            }
         }
     }
 
@@ -132,6 +237,391 @@ pub fn compute_analytics(spans: &[&Span], query: &AnalyticsQuery) -> AnalyticsRe
     }
 }
 
+/// Counts of ascending `sorted` latencies per `LATENCY_BUCKETS_MS` bucket.
+/// Every bucket is returned, so groups line up in charts.
+fn latency_histogram(sorted: &[f64]) -> Vec<HistogramBucket> {
+    let mut rest = sorted;
+    let mut min_ms = 0.0;
+    let mut buckets = Vec::with_capacity(LATENCY_BUCKETS_MS.len() + 1);
+    for &max_ms in LATENCY_BUCKETS_MS {
+        let n = rest.partition_point(|&ms| ms <= max_ms);
+        buckets.push(HistogramBucket {
+            min_ms,
+            max_ms: Some(max_ms),
+            count: n as u64,
+        });
+        rest = &rest[n..];
+        min_ms = max_ms;
+    }
+    buckets.push(HistogramBucket {
+        min_ms,
+        max_ms: None,
+        count: rest.len() as u64,
+    });
+    buckets
+}
+
+// --- Time series ---
+
+/// `query`'s metrics per fixed-width bucket between `since` and `until`,
+/// grouped when `group_by` is set. Spans starting outside the range are
+/// ignored; concurrency and queue gaps only see spans in the same bucket.
+pub fn compute_timeseries(
+    spans: &[&Span],
+    trace_tags: &HashMap<TraceId, Vec<String>>,
+    query: &TimeseriesQuery,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> TimeseriesResponse {
+    let interval = query.interval;
+    let width = interval.duration();
+    let first = interval.bucket_start(since);
+    let count = bucket_count(interval, since, until);
+
+    let mut by_bucket: Vec<Vec<&Span>> = vec![Vec::new(); count];
+    for span in spans {
+        let t = span.started_at();
+        if t < since || t > until {
+            continue;
+        }
+        let idx = ((t - first).num_milliseconds() / width.num_milliseconds()) as usize;
+        if let Some(bucket) = by_bucket.get_mut(idx) {
+            bucket.push(span);
+        }
+    }
+
+    let per_bucket = AnalyticsQuery {
+        metrics: query.metrics.clone(),
+        group_by: query.group_by.clone(),
+        filter: Default::default(),
+    };
+    let zero = compute_analytics(&[], trace_tags, &per_bucket).totals;
+    let mut totals = Vec::with_capacity(count);
+    let mut series: BTreeMap<Vec<(String, String)>, Vec<MetricValues>> = BTreeMap::new();
+    for (i, bucket) in by_bucket.iter().enumerate() {
+        let result = compute_analytics(bucket, trace_tags, &per_bucket);
+        totals.push(result.totals);
+        for group in result.groups {
+            let mut key: Vec<(String, String)> = group.key.into_iter().collect();
+            key.sort();
+            series
+                .entry(key)
+                .or_insert_with(|| vec![zero.clone(); count])[i] = group.metrics;
+        }
+    }
+
+    TimeseriesResponse {
+        interval,
+        buckets: (0..count as i32).map(|i| first + width * i).collect(),
+        totals,
+        series: series
+            .into_iter()
+            .map(|(key, values)| TimeSeries {
+                key: key.into_iter().collect(),
+                values,
+            })
+            .collect(),
+    }
+}
+
+// --- Concurrency ---
+
+/// Shared inputs for per-span concurrency and queue-gap accounting.
+struct SpanContext {
+    now: DateTime<Utc>,
+    track_intervals: bool,
+    track_latencies: bool,
+    /// Start time of every input span, for parent lookups. Empty unless
+    /// queue gaps were requested.
+    starts: HashMap<SpanId, DateTime<Utc>>,
+}
+
+impl SpanContext {
+    fn new(
+        spans: &[&Span],
+        now: DateTime<Utc>,
+        track_intervals: bool,
+        track_gaps: bool,
+        track_latencies: bool,
+    ) -> Self {
+        let starts = if track_gaps {
+            spans.iter().map(|s| (s.id(), s.started_at())).collect()
+        } else {
+            HashMap::new()
+        };
+        Self {
+            now,
+            track_intervals,
+            track_latencies,
+            starts,
+        }
+    }
+
+    /// Time from the parent's start to this span's start. `None` for roots
+    /// and for spans whose parent isn't in the input set.
+    fn queue_gap_ms(&self, span: &Span) -> Option<f64> {
+        let parent_start = self.starts.get(&span.parent_id()?)?;
+        Some(((span.started_at() - *parent_start).num_milliseconds()).max(0) as f64)
+    }
+}
+
+#[derive(Default)]
+struct GapStats {
+    sum_ms: f64,
+    max_ms: f64,
+    count: u64,
+}
+
+impl GapStats {
+    fn add(&mut self, gap_ms: f64) {
+        self.sum_ms += gap_ms;
+        self.max_ms = self.max_ms.max(gap_ms);
+        self.count += 1;
+    }
+
+    fn avg(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum_ms / self.count as f64)
+    }
+
+    fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max_ms)
+    }
+}
+
+/// A span's busy interval in epoch milliseconds. Running spans are busy
+/// until `now`.
+fn span_interval(span: &Span, now: DateTime<Utc>) -> (i64, i64) {
+    let start = span.started_at().timestamp_millis();
+    let end = span.ended_at().unwrap_or(now).timestamp_millis();
+    (start, end.max(start))
+}
+
+/// Start (+1) and end (-1) events, sorted so that at equal timestamps ends
+/// come first: back-to-back spans don't count as overlapping.
+fn sweep_events(intervals: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut events: Vec<(i64, i64)> = intervals
+        .iter()
+        .flat_map(|&(start, end)| [(start, 1), (end, -1)])
+        .collect();
+    events.sort_unstable();
+    events
+}
+
+fn max_concurrency(intervals: &[(i64, i64)]) -> u64 {
+    let mut level = 0_i64;
+    let mut max = 0_i64;
+    for (_, delta) in sweep_events(intervals) {
+        level += delta;
+        max = max.max(level);
+    }
+    max as u64
+}
+
+/// Concurrency per fixed-width bucket between `since` and `until`. The
+/// range is widened to whole buckets.
+fn concurrency_buckets(
+    intervals: &[(i64, i64)],
+    interval: AnalyticsInterval,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Vec<ConcurrencyBucket> {
+    let width = interval.duration().num_milliseconds();
+    let first = interval.bucket_start(since).timestamp_millis();
+    let last = until.timestamp_millis();
+    let events = sweep_events(intervals);
+
+    let mut level = 0_i64;
+    let mut idx = 0;
+    // Spans already running when the range opens.
+    while idx < events.len() && events[idx].0 < first {
+        level += events[idx].1;
+        idx += 1;
+    }
+
+    let mut buckets = Vec::new();
+    let mut bucket_start = first;
+    while bucket_start <= last {
+        let bucket_end = bucket_start + width;
+        // Spans ending exactly on the boundary belong to the previous bucket.
+        while idx < events.len() && events[idx].0 == bucket_start && events[idx].1 < 0 {
+            level += events[idx].1;
+            idx += 1;
+        }
+        let mut max = level;
+        let mut busy_ms = 0_i64;
+        let mut cursor = bucket_start;
+        let mut started = 0_u64;
+        while idx < events.len() && events[idx].0 < bucket_end {
+            let (t, delta) = events[idx];
+            busy_ms += level * (t - cursor);
+            cursor = t;
+            level += delta;
+            if delta > 0 {
+                started += 1;
+            }
+            max = max.max(level);
+            idx += 1;
+        }
+        busy_ms += level * (bucket_end - cursor);
+        buckets.push(ConcurrencyBucket {
+            start: DateTime::from_timestamp_millis(bucket_start).unwrap_or(since),
+            max_concurrency: max as u64,
+            avg_concurrency: busy_ms as f64 / width as f64,
+            spans_started: started,
+        });
+        bucket_start = bucket_end;
+    }
+    buckets
+}
+
+/// Number of buckets `compute_concurrency` would return for the range.
+pub fn bucket_count(
+    interval: AnalyticsInterval,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> usize {
+    let width = interval.duration().num_milliseconds();
+    let first = interval.bucket_start(since).timestamp_millis();
+    ((until.timestamp_millis() - first).max(0) / width + 1) as usize
+}
+
+/// Span concurrency over time for the whole set, plus max concurrency and
+/// parent-to-child queue gaps per trace (busiest `trace_limit` traces).
+/// `since`/`until` default to the spans' own time range.
+pub fn compute_concurrency(
+    spans: &[&Span],
+    interval: AnalyticsInterval,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    trace_limit: usize,
+    now: DateTime<Utc>,
+) -> ConcurrencyResponse {
+    let ctx = SpanContext::new(spans, now, true, true, false);
+    let intervals: Vec<(i64, i64)> = spans.iter().map(|s| span_interval(s, now)).collect();
+
+    let mut gaps = GapStats::default();
+    let mut by_trace: HashMap<TraceId, (Vec<(i64, i64)>, GapStats)> = HashMap::new();
+    for (span, interval) in spans.iter().zip(&intervals) {
+        let entry = by_trace.entry(span.trace_id()).or_default();
+        entry.0.push(*interval);
+        if let Some(gap) = ctx.queue_gap_ms(span) {
+            gaps.add(gap);
+            entry.1.add(gap);
+        }
+    }
+
+    let mut traces: Vec<TraceConcurrency> = by_trace
+        .into_iter()
+        .map(|(trace_id, (intervals, gaps))| TraceConcurrency {
+            trace_id,
+            span_count: intervals.len() as u64,
+            max_concurrency: max_concurrency(&intervals),
+            avg_queue_gap_ms: gaps.avg(),
+            max_queue_gap_ms: gaps.max(),
+        })
+        .collect();
+    traces.sort_by(|a, b| {
+        b.max_concurrency
+            .cmp(&a.max_concurrency)
+            .then(b.span_count.cmp(&a.span_count))
+    });
+    traces.truncate(trace_limit);
+
+    let range_start = since.or_else(|| spans.iter().map(|s| s.started_at()).min());
+    let range_end = until.or_else(|| {
+        intervals
+            .iter()
+            .map(|&(_, end)| end)
+            .max()
+            .and_then(DateTime::from_timestamp_millis)
+    });
+    let buckets = match (range_start, range_end) {
+        (Some(start), Some(end)) if start <= end => {
+            concurrency_buckets(&intervals, interval, start, end)
+        }
+        _ => Vec::new(),
+    };
+
+    ConcurrencyResponse {
+        interval,
+        max_concurrency: max_concurrency(&intervals),
+        avg_queue_gap_ms: gaps.avg(),
+        max_queue_gap_ms: gaps.max(),
+        buckets,
+        traces,
+    }
+}
+
+// --- Experiment comparison ---
+
+/// Nearest-rank percentile of ascending `sorted` values, `q` in `[0, 1]`.
+pub fn percentile(sorted: &[f64], q: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Cost, token, error, and latency metrics for one comparison cohort.
+pub fn compute_cohort(spans: &[&Span], label: Option<String>) -> CohortMetrics {
+    let mut m = CohortMetrics {
+        label,
+        span_count: spans.len() as u64,
+        ..Default::default()
+    };
+    let mut latencies = Vec::with_capacity(spans.len());
+    for span in spans {
+        if matches!(span.status(), SpanStatus::Failed { .. }) {
+            m.error_count += 1;
+        }
+        if let Some(ms) = span.duration_ms() {
+            latencies.push(ms as f64);
+        }
+        m.total_cost += span.kind().cost().unwrap_or(0.0);
+        m.total_input_tokens += span.kind().input_tokens().unwrap_or(0);
+        m.total_output_tokens += span.kind().output_tokens().unwrap_or(0);
+    }
+    if !spans.is_empty() {
+        let n = spans.len() as f64;
+        m.error_rate = m.error_count as f64 / n;
+        m.avg_cost = m.total_cost / n;
+        m.avg_tokens = (m.total_input_tokens + m.total_output_tokens) as f64 / n;
+    }
+    latencies.sort_by(f64::total_cmp);
+    m.latency_ms = latency_percentiles(&latencies);
+    m
+}
+
+fn latency_percentiles(sorted: &[f64]) -> LatencyPercentiles {
+    LatencyPercentiles {
+        avg: (!sorted.is_empty()).then(|| sorted.iter().sum::<f64>() / sorted.len() as f64),
+        p50: percentile(sorted, 0.50),
+        p90: percentile(sorted, 0.90),
+        p95: percentile(sorted, 0.95),
+        p99: percentile(sorted, 0.99),
+    }
+}
+
+/// Side-by-side metrics for two cohorts with the deltas from `a` to `b`.
+pub fn compare_cohorts(a: CohortMetrics, b: CohortMetrics) -> CompareResponse {
+    let latency = |f: fn(&LatencyPercentiles) -> Option<f64>| {
+        Some(MetricDelta::between(f(&a.latency_ms)?, f(&b.latency_ms)?))
+    };
+    let delta = CompareDeltas {
+        span_count: MetricDelta::between(a.span_count as f64, b.span_count as f64),
+        error_rate: MetricDelta::between(a.error_rate, b.error_rate),
+        total_cost: MetricDelta::between(a.total_cost, b.total_cost),
+        avg_cost: MetricDelta::between(a.avg_cost, b.avg_cost),
+        avg_tokens: MetricDelta::between(a.avg_tokens, b.avg_tokens),
+        avg_latency_ms: latency(|l| l.avg),
+        p50_latency_ms: latency(|l| l.p50),
+        p95_latency_ms: latency(|l| l.p95),
+        p99_latency_ms: latency(|l| l.p99),
+    };
+    CompareResponse { a, b, delta }
+}
+
 /// Compute a summary suitable for a quick dashboard view.
 pub fn compute_summary(spans: &[&Span], trace_count: usize) -> AnalyticsSummary {
     let mut total_cost = 0.0_f64;
@@ -215,3 +705,293 @@ pub fn compute_summary(spans: &[&Span], trace_count: usize) -> AnalyticsSummary
         tokens_by_model,
     }
 }
+
+// --- Commit breakdown ---
+
+/// Metrics per `git_commit` over `traces`, newest commit first. Traces
+/// without a commit are skipped. Each commit is compared with the one first
+/// seen before it in the same repository.
+pub fn compute_by_commit(traces: &[Trace]) -> Vec<CommitMetrics> {
+    let mut by_commit: HashMap<&str, Vec<&Trace>> = HashMap::new();
+    for trace in traces {
+        if let Some(commit) = trace.git_commit.as_deref() {
+            by_commit.entry(commit).or_default().push(trace);
+        }
+    }
+
+    let mut commits: Vec<CommitMetrics> = by_commit
+        .into_iter()
+        .map(|(commit, mut traces)| {
+            traces.sort_by_key(|t| t.started_at);
+            let latest = traces[traces.len() - 1];
+            let n = traces.len() as f64;
+            let mut latencies: Vec<f64> = traces
+                .iter()
+                .filter_map(|t| t.stats.duration_ms())
+                .map(|ms| ms as f64)
+                .collect();
+            latencies.sort_by(f64::total_cmp);
+            let error_count = traces.iter().filter(|t| t.stats.error_count > 0).count() as u64;
+            let total_cost: f64 = traces.iter().map(|t| t.stats.total_cost).sum();
+            CommitMetrics {
+                git_commit: commit.to_string(),
+                git_branch: latest.git_branch.clone(),
+                repo: latest.repo.clone(),
+                trace_count: traces.len() as u64,
+                span_count: traces.iter().map(|t| t.stats.span_count).sum(),
+                error_count,
+                error_rate: error_count as f64 / n,
+                total_cost,
+                avg_cost: total_cost / n,
+                latency_ms: latency_percentiles(&latencies),
+                first_seen: traces[0].started_at,
+                last_seen: latest.started_at,
+                delta: None,
+            }
+        })
+        .collect();
+
+    commits.sort_by(|a, b| {
+        a.first_seen
+            .cmp(&b.first_seen)
+            .then_with(|| a.git_commit.cmp(&b.git_commit))
+    });
+    let mut previous: HashMap<Option<String>, usize> = HashMap::new();
+    for i in 0..commits.len() {
+        if let Some(&p) = previous.get(&commits[i].repo) {
+            let (a, b) = (&commits[p], &commits[i]);
+            let latency = |f: fn(&LatencyPercentiles) -> Option<f64>| {
+                Some(MetricDelta::between(f(&a.latency_ms)?, f(&b.latency_ms)?))
+            };
+            let delta = CommitDeltas {
+                previous_commit: a.git_commit.clone(),
+                error_rate: MetricDelta::between(a.error_rate, b.error_rate),
+                avg_cost: MetricDelta::between(a.avg_cost, b.avg_cost),
+                p50_latency_ms: latency(|l| l.p50),
+                p95_latency_ms: latency(|l| l.p95),
+            };
+            commits[i].delta = Some(delta);
+        }
+        previous.insert(commits[i].repo.clone(), i);
+    }
+    commits.reverse();
+    commits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(ms).unwrap()
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 0.5), Some(50.0));
+        assert_eq!(percentile(&values, 0.99), Some(99.0));
+        assert_eq!(percentile(&values, 1.0), Some(100.0));
+        assert_eq!(percentile(&[7.0], 0.0), Some(7.0));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn histogram_buckets_are_upper_inclusive() {
+        let buckets = latency_histogram(&[5.0, 10.0, 11.0, 900.0, 120_000.0]);
+        assert_eq!(buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+        assert_eq!(buckets[0].count, 2);
+        assert_eq!(buckets[1].min_ms, 10.0);
+        assert_eq!(buckets[1].count, 1);
+        assert_eq!(buckets[6].count, 1);
+        let last = buckets.last().unwrap();
+        assert_eq!((last.max_ms, last.count), (None, 1));
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<u64>(), 5);
+    }
+
+    #[test]
+    fn compare_reports_deltas_from_a_to_b() {
+        let cohort = |cost: f64, p50: Option<f64>| CohortMetrics {
+            span_count: 10,
+            total_cost: cost,
+            latency_ms: LatencyPercentiles {
+                p50,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let r = compare_cohorts(cohort(2.0, Some(100.0)), cohort(3.0, Some(80.0)));
+        assert_eq!(r.delta.total_cost.absolute, 1.0);
+        assert_eq!(r.delta.total_cost.relative, Some(0.5));
+        assert_eq!(r.delta.p50_latency_ms.unwrap().absolute, -20.0);
+        assert_eq!(r.delta.error_rate.relative, None);
+        assert!(r.delta.p95_latency_ms.is_none());
+    }
+
+    #[test]
+    fn timeseries_zero_fills_every_series() {
+        let trace_id = TraceId::now_v7();
+        let span = |model: &str| {
+            trace::SpanBuilder::new(
+                trace_id,
+                "call",
+                trace::SpanKind::LlmCall {
+                    model: model.to_string(),
+                    provider: None,
+                    input_tokens: Some(10),
+                    output_tokens: None,
+                    cost: None,
+                    input_preview: None,
+                    output_preview: None,
+                },
+            )
+            .build()
+        };
+        let spans = [span("gpt-4o"), span("gpt-4o"), span("llama3")];
+        let refs: Vec<&Span> = spans.iter().collect();
+        let now = Utc::now();
+        let query = TimeseriesQuery {
+            interval: AnalyticsInterval::Hour,
+            metrics: vec![
+                AnalyticsMetric::SpanCount,
+                AnalyticsMetric::TotalInputTokens,
+            ],
+            group_by: vec![GroupByField::Model],
+            filter: Default::default(),
+        };
+        let r = compute_timeseries(
+            &refs,
+            &HashMap::new(),
+            &query,
+            now - chrono::Duration::hours(2),
+            now,
+        );
+        assert_eq!(r.buckets.len(), 3);
+        assert_eq!(r.buckets[1] - r.buckets[0], chrono::Duration::hours(1));
+        let counts: Vec<_> = r.totals.iter().map(|m| m.span_count).collect();
+        assert_eq!(counts, [Some(0), Some(0), Some(3)]);
+        assert_eq!(r.series.len(), 2);
+        let gpt = r
+            .series
+            .iter()
+            .find(|s| s.key["model"] == "gpt-4o")
+            .unwrap();
+        assert_eq!(gpt.values.len(), 3);
+        assert_eq!(gpt.values[0].total_input_tokens, Some(0));
+        assert_eq!(gpt.values[2].total_input_tokens, Some(20));
+    }
+
+    #[test]
+    fn groups_by_trace_tag_and_custom_attribute() {
+        let (tagged, untagged) = (TraceId::now_v7(), TraceId::now_v7());
+        let span = |trace_id, env: serde_json::Value, cost: f64| {
+            trace::SpanBuilder::new(
+                trace_id,
+                "rerank",
+                trace::SpanKind::Custom {
+                    kind: "rerank".into(),
+                    attributes: HashMap::from([
+                        ("env".to_string(), env),
+                        ("cost".to_string(), cost.into()),
+                    ]),
+                },
+            )
+            .build()
+        };
+        let spans = [
+            span(tagged, "prod".into(), 1.0),
+            span(tagged, "staging".into(), 2.0),
+            span(untagged, 3.into(), 4.0),
+        ];
+        let refs: Vec<&Span> = spans.iter().collect();
+        let trace_tags = HashMap::from([(tagged, vec!["search".into(), "beta".into()])]);
+        let cost = |group_by: Vec<GroupByField>| -> BTreeMap<String, f64> {
+            let query = AnalyticsQuery {
+                metrics: vec![AnalyticsMetric::TotalCost],
+                group_by,
+                filter: Default::default(),
+            };
+            compute_analytics(&refs, &trace_tags, &query)
+                .groups
+                .into_iter()
+                .map(|g| {
+                    let key = g.key.into_values().collect::<Vec<_>>().join("/");
+                    (key, g.metrics.total_cost.unwrap())
+                })
+                .collect()
+        };
+
+        let by_tag = cost(vec![GroupByField::Tag]);
+        assert_eq!(by_tag["search"], 3.0);
+        assert_eq!(by_tag["beta"], 3.0);
+        assert_eq!(by_tag["unknown"], 4.0);
+
+        let by_env = cost(vec![GroupByField::Attribute("env".into())]);
+        assert_eq!(by_env["prod"], 1.0);
+        assert_eq!(by_env["3"], 4.0);
+
+        let both = cost(vec![
+            GroupByField::Tag,
+            GroupByField::Attribute("env".into()),
+        ]);
+        assert_eq!(both.len(), 5);
+    }
+
+    #[test]
+    fn back_to_back_spans_do_not_overlap() {
+        assert_eq!(max_concurrency(&[(0, 10), (10, 20)]), 1);
+        assert_eq!(max_concurrency(&[(0, 10), (5, 20), (6, 7)]), 3);
+        assert_eq!(max_concurrency(&[]), 0);
+    }
+
+    #[test]
+    fn buckets_carry_spans_across_boundaries() {
+        // Two spans busy for the whole first minute, one of them into the second.
+        let intervals = [(0, 60_000), (0, 90_000), (120_000, 150_000)];
+        let buckets =
+            concurrency_buckets(&intervals, AnalyticsInterval::Minute, at(0), at(150_000));
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets[0].max_concurrency, 2);
+        assert_eq!(buckets[0].avg_concurrency, 2.0);
+        assert_eq!(buckets[0].spans_started, 2);
+        assert_eq!(buckets[1].max_concurrency, 1);
+        assert_eq!(buckets[1].avg_concurrency, 0.5);
+        assert_eq!(buckets[1].spans_started, 0);
+        assert_eq!(buckets[2].start, at(120_000));
+        assert_eq!(buckets[2].spans_started, 1);
+        assert_eq!(
+            bucket_count(AnalyticsInterval::Minute, at(0), at(150_000)),
+            3
+        );
+    }
+
+    #[test]
+    fn commits_are_compared_with_the_previous_deploy() {
+        let trace = |commit: Option<&str>, start: i64, duration: i64, cost: f64| {
+            let mut t = Trace::new(None);
+            t.git_commit = commit.map(String::from);
+            t.started_at = at(start);
+            t.stats.span_count = 1;
+            t.stats.total_cost = cost;
+            t.stats.first_started_at = Some(at(start));
+            t.stats.last_ended_at = Some(at(start + duration));
+            t
+        };
+        let traces = [
+            trace(Some("aaa"), 0, 100, 1.0),
+            trace(Some("aaa"), 1_000, 300, 1.0),
+            trace(Some("bbb"), 2_000, 400, 3.0),
+            trace(None, 3_000, 1, 0.0),
+        ];
+        let commits = compute_by_commit(&traces);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].git_commit, "bbb");
+        assert_eq!(commits[1].trace_count, 2);
+        assert_eq!(commits[1].latency_ms.p50, Some(100.0));
+        assert!(commits[1].delta.is_none());
+        let delta = commits[0].delta.as_ref().unwrap();
+        assert_eq!(delta.previous_commit, "aaa");
+        assert_eq!(delta.avg_cost.absolute, 2.0);
+        assert_eq!(delta.p50_latency_ms.unwrap().absolute, 300.0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Archival of old spans into compressed segment files.
+//!
+//! `PersistentStore::archive_before` moves finished spans older than a
+//! cutoff out of the backend into gzipped JSONL segments held by a
+//! [`SegmentStore`], and records a [`SegmentInfo`] pointer for each segment
+//! in the `archive_segments` setting. Traces stay in the backend with their
+//! rollups intact. Lookups that miss the backend (by span id, by trace, or
+//! time-bounded span queries) read the matching segments back.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use trace::{Span, SpanId, TraceId};
+
+use crate::error::StorageError;
+
+/// Setting holding the list of `SegmentInfo` pointers.
+pub const SEGMENTS_SETTING: &str = "archive_segments";
+/// Spans written to one segment at most.
+pub const MAX_SEGMENT_SPANS: usize = 50_000;
+
+/// Where segment files are kept, e.g. a local directory or an S3 bucket.
+#[async_trait]
+pub trait SegmentStore: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError>;
+
+    /// `None` when no segment has this key.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Deleting a missing segment is not an error.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+/// Segments stored as files under a directory.
+pub struct LocalSegments {
+    dir: PathBuf,
+}
+
+impl LocalSegments {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SegmentStore for LocalSegments {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.dir.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Written aside and renamed so a crash never leaves half a segment
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match tokio::fs::read(self.dir.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Pointer to one archived segment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub key: String,
+    /// Start time of the segment's earliest span.
+    pub from: DateTime<Utc>,
+    /// Start time of the segment's latest span.
+    pub to: DateTime<Utc>,
+    pub span_count: usize,
+    /// Compressed size.
+    pub bytes: usize,
+    pub trace_ids: Vec<TraceId>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SegmentInfo {
+    /// Whether spans started in `[since, until]` may be in this segment.
+    pub fn overlaps(&self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> bool {
+        since.is_none_or(|since| self.to >= since) && until.is_none_or(|until| self.from <= until)
+    }
+
+    /// Whether the span may be in this segment, judged by the creation
+    /// time in a v7 id. Other ids may be in any segment.
+    pub fn may_contain(&self, id: SpanId) -> bool {
+        let Some(ts) = id.get_timestamp() else {
+            return true;
+        };
+        let (secs, nanos) = ts.to_unix();
+        DateTime::from_timestamp(secs as i64, nanos).is_none_or(|at| {
+            // Ids are minted when a span is built, which may be a little
+            // before or after its recorded start
+            let slack = chrono::Duration::minutes(5);
+            at >= self.from - slack && at <= self.to + slack
+        })
+    }
+}
+
+/// What an archival pass moved, or would move on a dry run.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveReport {
+    pub cutoff: DateTime<Utc>,
+    pub dry_run: bool,
+    pub spans: usize,
+    pub segments: Vec<SegmentInfo>,
+}
+
+/// Key for a new segment, grouped by the day of its earliest span and
+/// named after that span, which is only ever archived once.
+pub fn segment_key(from: DateTime<Utc>, first: SpanId) -> String {
+    format!("spans/{}/{}.jsonl.gz", from.format("%Y/%m/%d"), first)
+}
+
+/// Gzipped JSON lines, one span per line.
+pub fn encode(spans: &[Span]) -> Result<Vec<u8>, StorageError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for span in spans {
+        serde_json::to_writer(&mut encoder, span)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        encoder.write_all(b"\n")?;
+    }
+    Ok(encoder.finish()?)
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<Span>, StorageError> {
+    let mut spans = Vec::new();
+    for line in BufReader::new(GzDecoder::new(data)).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        spans.push(
+            serde_json::from_str(&line).map_err(|e| StorageError::Serialization(e.to_string()))?,
+        );
+    }
+    Ok(spans)
+}
@@ -1,12 +1,24 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use trace::{
-    CaptureRule, CaptureRuleId, Datapoint, DatapointId, Dataset, DatasetId, EvalResult,
-    EvalResultId, EvalRun, EvalRunId, FileVersion, ProviderConnection, ProviderConnectionId,
-    QueueItem, QueueItemId, Span, SpanId, Trace, TraceId,
+    Annotation, AnnotationId, AuditEvent, CaptureRule, CaptureRuleId, Datapoint, DatapointId,
+    Dataset, DatasetId, EvalResult, EvalResultId, EvalRun, EvalRunId, FileVersion, Machine,
+    ProviderConnection, ProviderConnectionId, QueueItem, QueueItemId, QueueSubmission, SavedView,
+    SavedViewId, Session, Span, SpanId, SpanKindDefinition, Trace, TraceId, Webhook,
+    WebhookDelivery, WebhookId,
 };
 
 use crate::error::StorageError;
-use crate::filter::{SpanFilter, TraceFilter};
+use crate::filter::{AnnotationFilter, AuditFilter, SpanFilter, TraceFilter};
+
+/// A span returned by semantic search, with its distance from the query
+/// (smaller is closer).
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoredSpan {
+    pub span: Span,
+    pub distance: f64,
+}
 
 /// Trait for pluggable storage backends.
 ///
@@ -25,9 +37,28 @@ pub trait StorageBackend: Send + Sync {
     /// List traces matching the filter.
     async fn list_traces(&self, filter: &TraceFilter) -> Result<Vec<Trace>, StorageError>;
 
+    /// Sessions among the traces matching the filter, most recently active
+    /// first. Sorting and paging fields are ignored.
+    async fn list_sessions(&self, filter: &TraceFilter) -> Result<Vec<Session>, StorageError> {
+        let traces = self
+            .list_traces(&TraceFilter {
+                limit: None,
+                offset: None,
+                cursor: None,
+                ..filter.clone()
+            })
+            .await?;
+        Ok(crate::sessions::sessions_over(&traces))
+    }
+
     /// Delete a trace by ID. Returns true if deleted.
     async fn delete_trace(&self, id: TraceId) -> Result<bool, StorageError>;
 
+    /// Delete every trace matching the filter, along with its spans, in one
+    /// operation. Sorting and paging fields are ignored. Returns count of
+    /// deleted traces.
+    async fn delete_traces_by_filter(&self, filter: &TraceFilter) -> Result<usize, StorageError>;
+
     // --- Span operations ---
 
     /// Save or update a span.
@@ -45,6 +76,10 @@ pub trait StorageBackend: Send + Sync {
     /// Delete all spans for a trace. Returns count of deleted spans.
     async fn delete_trace_spans(&self, trace_id: TraceId) -> Result<usize, StorageError>;
 
+    /// Delete every span matching the filter in one operation. Sorting and
+    /// paging fields are ignored. Returns count of deleted spans.
+    async fn delete_spans_by_filter(&self, filter: &SpanFilter) -> Result<usize, StorageError>;
+
     /// Clear all spans.
     async fn clear_spans(&self) -> Result<(), StorageError>;
 
@@ -62,6 +97,21 @@ pub trait StorageBackend: Send + Sync {
     /// Delete a dataset by ID. Returns true if deleted.
     async fn delete_dataset(&self, id: DatasetId) -> Result<bool, StorageError>;
 
+    /// Save `dataset` only if the stored copy is still at version
+    /// `expected`. Returns false, writing nothing, if it has moved on or
+    /// is gone. The default reads then writes, which only holds up with a
+    /// single writer; backends shared between instances override it with
+    /// an atomic conditional write.
+    async fn update_dataset(&self, dataset: &Dataset, expected: u64) -> Result<bool, StorageError> {
+        match self.get_dataset(dataset.id).await? {
+            Some(current) if current.version == expected => {
+                self.save_dataset(dataset).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     // --- Datapoint operations ---
 
     /// Save or update a datapoint.
@@ -76,6 +126,18 @@ pub trait StorageBackend: Send + Sync {
     /// Delete a datapoint by ID. Returns true if deleted.
     async fn delete_datapoint(&self, id: DatapointId) -> Result<bool, StorageError>;
 
+    /// Save `dp` only if the stored copy is still at version `expected`;
+    /// see [`StorageBackend::update_dataset`].
+    async fn update_datapoint(&self, dp: &Datapoint, expected: u64) -> Result<bool, StorageError> {
+        match self.get_datapoint(dp.id).await? {
+            Some(current) if current.version == expected => {
+                self.save_datapoint(dp).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     /// Delete all datapoints for a dataset. Returns count of deleted.
     async fn delete_dataset_datapoints(&self, dataset_id: DatasetId)
         -> Result<usize, StorageError>;
@@ -95,6 +157,16 @@ pub trait StorageBackend: Send + Sync {
     /// Delete a queue item by ID. Returns true if deleted.
     async fn delete_queue_item(&self, id: QueueItemId) -> Result<bool, StorageError>;
 
+    /// Save or replace a reviewer's submission for a queue item.
+    async fn save_queue_submission(&self, submission: &QueueSubmission)
+        -> Result<(), StorageError>;
+
+    /// A queue item's submissions, oldest first.
+    async fn list_queue_submissions(
+        &self,
+        queue_item_id: QueueItemId,
+    ) -> Result<Vec<QueueSubmission>, StorageError>;
+
     // --- Eval Run operations ---
 
     /// Save or update an eval run.
@@ -151,12 +223,25 @@ pub trait StorageBackend: Send + Sync {
     /// List all file versions.
     async fn list_file_versions(&self) -> Result<Vec<FileVersion>, StorageError>;
 
+    /// Delete file version records created at or before `cutoff`. Content
+    /// blobs are left in place. Returns the number of records deleted.
+    async fn delete_file_versions_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError>;
+
     /// Save file content by hash.
     async fn save_file_content(&self, hash: &str, content: &[u8]) -> Result<(), StorageError>;
 
     /// Load file content by hash.
     async fn load_file_content(&self, hash: &str) -> Result<Vec<u8>, StorageError>;
 
+    /// Hash and size in bytes of every stored content blob.
+    async fn list_file_contents(&self) -> Result<Vec<(String, u64)>, StorageError>;
+
+    /// Delete content blobs by hash. Returns the number deleted.
+    async fn delete_file_contents(&self, hashes: &[String]) -> Result<usize, StorageError>;
+
     // --- Batch operations (for cloud efficiency) ---
 
     /// Save multiple spans in a batch.
@@ -265,6 +350,160 @@ pub trait StorageBackend: Send + Sync {
         self.list_provider_connections().await
     }
 
+    // --- Span Kind Definition operations ---
+
+    /// Save or update a custom span kind definition, keyed by name.
+    async fn save_span_kind(&self, def: &SpanKindDefinition) -> Result<(), StorageError>;
+
+    /// List all custom span kind definitions.
+    async fn list_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError>;
+
+    /// Delete a custom span kind definition by name. Returns true if deleted.
+    async fn delete_span_kind(&self, name: &str) -> Result<bool, StorageError>;
+
+    /// Load all span kind definitions. Used during store initialization.
+    async fn load_all_span_kinds(&self) -> Result<Vec<SpanKindDefinition>, StorageError> {
+        self.list_span_kinds().await
+    }
+
+    // --- Machine registry ---
+
+    /// Save or update a registered machine, keyed by id.
+    async fn save_machine(&self, machine: &Machine) -> Result<(), StorageError>;
+
+    /// List all registered machines.
+    async fn list_machines(&self) -> Result<Vec<Machine>, StorageError>;
+
+    /// Delete a machine by id. Returns true if deleted.
+    async fn delete_machine(&self, id: &str) -> Result<bool, StorageError>;
+
+    // --- Settings ---
+
+    /// A stored setting, e.g. an org's redaction rules.
+    async fn get_setting(&self, key: &str) -> Result<Option<serde_json::Value>, StorageError>;
+
+    /// Save a setting, replacing any previous value.
+    async fn save_setting(&self, key: &str, value: &serde_json::Value) -> Result<(), StorageError>;
+
+    /// Delete the settings under `keys`; missing ones are skipped.
+    async fn delete_settings(&self, keys: &[String]) -> Result<(), StorageError>;
+
+    /// Save a setting only if its stored value is still `expected`, or
+    /// there is none when `expected` is `None`. Returns false, writing
+    /// nothing, otherwise. The default reads then writes; see
+    /// [`StorageBackend::update_dataset`].
+    async fn update_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, StorageError> {
+        if self.get_setting(key).await?.as_ref() != expected {
+            return Ok(false);
+        }
+        self.save_setting(key, value).await?;
+        Ok(true)
+    }
+
+    // --- Webhooks ---
+
+    /// Save or update a webhook subscription.
+    async fn save_webhook(&self, webhook: &Webhook) -> Result<(), StorageError>;
+
+    /// List all webhook subscriptions.
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, StorageError>;
+
+    /// Delete a webhook and its delivery log. Returns true if deleted.
+    async fn delete_webhook(&self, id: WebhookId) -> Result<bool, StorageError>;
+
+    /// Record a delivery attempt.
+    async fn save_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), StorageError>;
+
+    /// A webhook's most recent deliveries, newest first.
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: WebhookId,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, StorageError>;
+
+    // --- Audit log ---
+
+    /// Append an audit event.
+    async fn save_audit_event(&self, event: &AuditEvent) -> Result<(), StorageError>;
+
+    /// Audit events matching `filter`, newest first.
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, StorageError>;
+
+    /// Delete audit events older than `cutoff`. Returns the number deleted.
+    async fn delete_audit_events_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, StorageError>;
+
+    // --- Annotations ---
+
+    /// Save or update an annotation.
+    async fn save_annotation(&self, annotation: &Annotation) -> Result<(), StorageError>;
+
+    /// Annotations matching `filter`, newest first.
+    async fn list_annotations(
+        &self,
+        filter: &AnnotationFilter,
+    ) -> Result<Vec<Annotation>, StorageError>;
+
+    /// Delete an annotation. Returns true if deleted.
+    async fn delete_annotation(&self, id: AnnotationId) -> Result<bool, StorageError>;
+
+    // --- Saved views ---
+
+    /// Save or update a saved view.
+    async fn save_view(&self, view: &SavedView) -> Result<(), StorageError>;
+
+    /// List all saved views.
+    async fn list_views(&self) -> Result<Vec<SavedView>, StorageError>;
+
+    /// Delete a saved view. Returns true if deleted.
+    async fn delete_view(&self, id: SavedViewId) -> Result<bool, StorageError>;
+
+    // --- Search ---
+
+    /// Rank spans matching `filter` by semantic similarity to `query`,
+    /// closest first. Sorting and paging fields are ignored. Backends
+    /// without embeddings return `StorageError::Unsupported`.
+    async fn semantic_search(
+        &self,
+        _query: &str,
+        _filter: &SpanFilter,
+        _limit: usize,
+    ) -> Result<Vec<ScoredSpan>, StorageError> {
+        Err(StorageError::Unsupported(format!(
+            "semantic search is not available on the {} backend",
+            self.backend_type()
+        )))
+    }
+
+    // --- Durability ---
+
+    /// Write any buffered data through to storage. Backends that write
+    /// synchronously have nothing to do.
+    async fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// The cheapest round trip that proves storage is reachable, for
+    /// health checks. By default, reads one trace.
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.list_traces(&TraceFilter {
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+        .map(|_| ())
+    }
+
     // --- Metadata ---
 
     /// Returns the type of this backend (e.g., "sqlite", "turbopuffer").
@@ -0,0 +1,327 @@
+//! Benchmark harness for storage backends.
+//!
+//! Backend crates call [`run_backend`] from `cargo bench` targets. Each run
+//! seeds a fresh backend with a fixed number of spans and times single
+//! saves, batch saves, filtered lists, and deletes against it. Results are
+//! printed as JSON so CI can keep them and compare against a baseline.
+//!
+//! Environment:
+//! - `BENCH_SIZES`: comma-separated seeded span counts (default `1000,10000`)
+//! - `BENCH_SAMPLES`: timed calls per operation (default 200)
+//! - `BENCH_OUTPUT`: also write the results to this file
+//! - `BENCH_BASELINE`: results of an earlier run; operations whose mean is
+//!   more than `BENCH_MAX_REGRESSION` (default 1.5) times the baseline's
+//!   are reported as regressions
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use trace::{Span, SpanBuilder, SpanKind, TraceId};
+
+use crate::{SpanFilter, StorageBackend, StorageError};
+
+const MODELS: &[&str] = &["gpt-4o", "gpt-4o-mini", "claude-sonnet", "llama3"];
+/// Spans per trace in seeded data.
+const SPANS_PER_TRACE: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub sizes: Vec<usize>,
+    pub samples: usize,
+    /// Spans per `save_spans_batch` call.
+    pub batch_size: usize,
+    pub output: Option<PathBuf>,
+    pub baseline: Option<PathBuf>,
+    pub max_regression: f64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![1_000, 10_000],
+            samples: 200,
+            batch_size: 500,
+            output: None,
+            baseline: None,
+            max_regression: 1.5,
+        }
+    }
+}
+
+impl BenchConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        Self {
+            sizes: var("BENCH_SIZES")
+                .map(|s| s.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+                .filter(|sizes: &Vec<usize>| !sizes.is_empty())
+                .unwrap_or(defaults.sizes),
+            samples: var("BENCH_SAMPLES")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.samples),
+            batch_size: defaults.batch_size,
+            output: var("BENCH_OUTPUT").map(PathBuf::from),
+            baseline: var("BENCH_BASELINE").map(PathBuf::from),
+            max_regression: var("BENCH_MAX_REGRESSION")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.max_regression),
+        }
+    }
+}
+
+/// Timings for one operation on one backend at one seeded size.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub backend: String,
+    pub op: String,
+    /// Spans in the backend when the operation was timed.
+    pub size: usize,
+    pub samples: usize,
+    /// Spans written or read per call.
+    pub items_per_op: usize,
+    pub mean_us: f64,
+    pub p50_us: f64,
+    pub p95_us: f64,
+    pub ops_per_sec: f64,
+}
+
+impl BenchResult {
+    fn new(
+        backend: &str,
+        op: &str,
+        size: usize,
+        items_per_op: usize,
+        mut times: Vec<Duration>,
+    ) -> Self {
+        times.sort();
+        let micros = |d: Duration| d.as_secs_f64() * 1e6;
+        let total: Duration = times.iter().sum();
+        let pick = |q: f64| {
+            let i = ((times.len() as f64 * q).ceil() as usize).clamp(1, times.len().max(1));
+            times.get(i - 1).copied().map_or(0.0, micros)
+        };
+        Self {
+            backend: backend.to_string(),
+            op: op.to_string(),
+            size,
+            samples: times.len(),
+            items_per_op,
+            mean_us: micros(total) / times.len().max(1) as f64,
+            p50_us: pick(0.5),
+            p95_us: pick(0.95),
+            ops_per_sec: times.len() as f64 / total.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+
+    fn key(&self) -> (&str, &str, usize) {
+        (&self.backend, &self.op, self.size)
+    }
+}
+
+/// An operation that got slower than the baseline allows.
+#[derive(Debug, Clone, Serialize)]
+pub struct Regression {
+    pub backend: String,
+    pub op: String,
+    pub size: usize,
+    pub baseline_us: f64,
+    pub mean_us: f64,
+    pub ratio: f64,
+}
+
+fn seeded_span(trace_id: TraceId, i: usize) -> Span {
+    let model = MODELS[i % MODELS.len()];
+    SpanBuilder::new(
+        trace_id,
+        format!("call-{}", i % 50),
+        SpanKind::LlmCall {
+            model: model.to_string(),
+            provider: Some("openai".to_string()),
+            input_tokens: Some(100 + (i % 900) as u64),
+            output_tokens: Some(50 + (i % 400) as u64),
+            cost: Some(0.001 * (i % 10) as f64),
+            input_preview: None,
+            output_preview: None,
+        },
+    )
+    .input(
+        serde_json::json!({ "messages": [{ "role": "user", "content": format!("prompt {i}") }] }),
+    )
+    .build()
+    .complete(Some(
+        serde_json::json!({ "content": format!("answer {i}") }),
+    ))
+}
+
+fn seeded_spans(count: usize) -> Vec<Span> {
+    let mut trace_id = TraceId::now_v7();
+    (0..count)
+        .map(|i| {
+            if i % SPANS_PER_TRACE == 0 {
+                trace_id = TraceId::now_v7();
+            }
+            seeded_span(trace_id, i)
+        })
+        .collect()
+}
+
+/// Seed a backend from `open` for each configured size and time every
+/// operation against it. `open` must return an empty backend each call.
+pub async fn run_backend<B, F, Fut>(
+    name: &str,
+    config: &BenchConfig,
+    mut open: F,
+) -> Result<Vec<BenchResult>, StorageError>
+where
+    B: StorageBackend,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<B, StorageError>>,
+{
+    let mut results = Vec::new();
+    for &size in &config.sizes {
+        let backend = open().await?;
+
+        let spans = seeded_spans(size);
+        let mut times = Vec::new();
+        for chunk in spans.chunks(config.batch_size) {
+            let start = Instant::now();
+            backend.save_spans_batch(chunk).await?;
+            times.push(start.elapsed());
+        }
+        backend.flush().await?;
+        let per_batch = config.batch_size.min(size);
+        results.push(BenchResult::new(
+            name,
+            "save_spans_batch",
+            size,
+            per_batch,
+            times,
+        ));
+
+        let extra = seeded_spans(config.samples);
+        let mut times = Vec::with_capacity(extra.len());
+        for span in &extra {
+            let start = Instant::now();
+            backend.save_span(span).await?;
+            times.push(start.elapsed());
+        }
+        backend.flush().await?;
+        results.push(BenchResult::new(name, "save_span", size, 1, times));
+
+        let filter = SpanFilter {
+            model: Some(MODELS[0].to_string()),
+            limit: Some(50),
+            ..Default::default()
+        };
+        let mut times = Vec::with_capacity(config.samples);
+        let mut listed = 0;
+        for _ in 0..config.samples {
+            let start = Instant::now();
+            listed = backend.list_spans(&filter).await?.len();
+            times.push(start.elapsed());
+        }
+        results.push(BenchResult::new(
+            name,
+            "list_spans_filtered",
+            size,
+            listed,
+            times,
+        ));
+
+        let mut times = Vec::with_capacity(config.samples);
+        for span in spans.iter().take(config.samples) {
+            let start = Instant::now();
+            backend.delete_span(span.id()).await?;
+            times.push(start.elapsed());
+        }
+        results.push(BenchResult::new(name, "delete_span", size, 1, times));
+    }
+    Ok(results)
+}
+
+/// Compare `results` with a baseline run.
+pub fn regressions(
+    results: &[BenchResult],
+    baseline: &[BenchResult],
+    max_ratio: f64,
+) -> Vec<Regression> {
+    results
+        .iter()
+        .filter_map(|r| {
+            let base = baseline.iter().find(|b| b.key() == r.key())?;
+            let ratio = r.mean_us / base.mean_us.max(f64::EPSILON);
+            (ratio > max_ratio).then(|| Regression {
+                backend: r.backend.clone(),
+                op: r.op.clone(),
+                size: r.size,
+                baseline_us: base.mean_us,
+                mean_us: r.mean_us,
+                ratio,
+            })
+        })
+        .collect()
+}
+
+/// Print `results` as JSON, write them to `BENCH_OUTPUT`, and check them
+/// against `BENCH_BASELINE`. Returns the regressions found.
+pub fn report(
+    results: &[BenchResult],
+    config: &BenchConfig,
+) -> Result<Vec<Regression>, StorageError> {
+    let json = serde_json::to_string_pretty(results)?;
+    println!("{json}");
+    if let Some(ref path) = config.output {
+        std::fs::write(path, &json)?;
+    }
+    let Some(ref path) = config.baseline else {
+        return Ok(Vec::new());
+    };
+    let baseline: Vec<BenchResult> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let found = regressions(results, &baseline, config.max_regression);
+    for r in &found {
+        eprintln!(
+            "regression: {} {} at {} spans: {:.1}us -> {:.1}us ({:.2}x)",
+            r.backend, r.op, r.size, r.baseline_us, r.mean_us, r.ratio
+        );
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(op: &str, mean_us: f64) -> BenchResult {
+        BenchResult {
+            mean_us,
+            ..BenchResult::new("sqlite", op, 1000, 1, vec![Duration::from_micros(10)])
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let times = (1..=100).map(Duration::from_micros).collect();
+        let r = BenchResult::new("sqlite", "save_span", 10, 1, times);
+        assert_eq!(r.samples, 100);
+        assert_eq!(r.p50_us, 50.0);
+        assert_eq!(r.p95_us, 95.0);
+        assert!((r.mean_us - 50.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flags_only_slower_matching_ops() {
+        let baseline = vec![result("save_span", 100.0), result("delete_span", 100.0)];
+        let now = vec![
+            result("save_span", 140.0),
+            result("delete_span", 200.0),
+            result("list_spans_filtered", 900.0),
+        ];
+        let found = regressions(&now, &baseline, 1.5);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].op, "delete_span");
+        assert!((found[0].ratio - 2.0).abs() < 1e-9);
+    }
+}
@@ -22,6 +22,12 @@ pub enum StorageError {
 
     #[error("backend error: {0}")]
     Backend(String),
+
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
 }
 
 impl From<serde_json::Error> for StorageError {
@@ -36,3 +42,10 @@ impl From<rusqlite::Error> for StorageError {
         StorageError::Database(e.to_string())
     }
 }
+
+#[cfg(feature = "duckdb")]
+impl From<duckdb::Error> for StorageError {
+    fn from(e: duckdb::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
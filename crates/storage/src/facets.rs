@@ -0,0 +1,277 @@
+//! Per-trace search facets computed from in-memory spans and trace metadata.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use trace::{FacetCount, Span, SpanStatus, Trace, TraceFacets};
+
+use crate::filter::TraceFilter;
+
+const MAX_FINGERPRINT_LEN: usize = 120;
+
+/// A trace's metadata (if it was recorded) together with its spans.
+pub struct TraceGroup<'a> {
+    pub trace: Option<&'a Trace>,
+    pub spans: Vec<&'a Span>,
+}
+
+impl TraceGroup<'_> {
+    /// Trace start time: the recorded trace start, or the earliest span start.
+    pub fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.trace
+            .map(|t| t.started_at)
+            .or_else(|| self.spans.iter().map(|s| s.started_at()).min())
+    }
+
+    /// Whether this trace satisfies the trace-level filter (limit is ignored).
+    pub fn matches(&self, filter: &TraceFilter) -> bool {
+        if let Some(ref name) = filter.name_contains {
+            match self.trace.and_then(|t| t.name.as_deref()) {
+                Some(n) if n.contains(name.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref tags) = filter.tags {
+            let trace_tags = self.trace.map(|t| t.tags.as_slice()).unwrap_or(&[]);
+            if !tags.iter().all(|tag| trace_tags.contains(tag)) {
+                return false;
+            }
+        }
+        if filter.since.is_some() || filter.until.is_some() {
+            let Some(started) = self.started_at() else {
+                return false;
+            };
+            if filter.since.is_some_and(|since| started < since) {
+                return false;
+            }
+            if filter.until.is_some_and(|until| started > until) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Rolled-up trace status: failed if any span failed, running if any span is
+/// still running, otherwise completed.
+pub fn trace_status(spans: &[&Span]) -> &'static str {
+    if spans
+        .iter()
+        .any(|s| matches!(s.status(), SpanStatus::Failed { .. }))
+    {
+        "failed"
+    } else if spans.iter().any(|s| !s.status().is_terminal()) {
+        "running"
+    } else {
+        "completed"
+    }
+}
+
+/// Normalize an error message so that errors differing only in IDs, hashes,
+/// or large numbers group together. Short numbers (e.g. HTTP status codes)
+/// are kept since they usually distinguish error classes.
+pub fn error_fingerprint(error: &str) -> String {
+    let normalized: Vec<String> = error
+        .split_whitespace()
+        .map(|token| {
+            let core = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '-');
+            if core.parse::<trace::SpanId>().is_ok() {
+                return token.replace(core, "<id>");
+            }
+            if core.len() >= 8
+                && core.chars().all(|c| c.is_ascii_hexdigit())
+                && core.chars().any(|c| c.is_ascii_digit())
+            {
+                return token.replace(core, "<hex>");
+            }
+            replace_long_numbers(token)
+        })
+        .collect();
+    let mut fingerprint = normalized.join(" ");
+    if fingerprint.chars().count() > MAX_FINGERPRINT_LEN {
+        fingerprint = fingerprint.chars().take(MAX_FINGERPRINT_LEN).collect();
+    }
+    fingerprint
+}
+
+fn replace_long_numbers(token: &str) -> String {
+    let mut out = String::with_capacity(token.len());
+    let mut digits = String::new();
+    let flush = |digits: &mut String, out: &mut String| {
+        if digits.len() > 3 {
+            out.push_str("<n>");
+        } else {
+            out.push_str(digits);
+        }
+        digits.clear();
+    };
+    for c in token.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+        } else {
+            flush(&mut digits, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut digits, &mut out);
+    out
+}
+
+/// Count facet values across trace groups. Each value is counted at most once
+/// per trace.
+pub fn compute_trace_facets(groups: &[TraceGroup]) -> TraceFacets {
+    let mut models: HashMap<String, usize> = HashMap::new();
+    let mut providers: HashMap<String, usize> = HashMap::new();
+    let mut statuses: HashMap<String, usize> = HashMap::new();
+    let mut tags: HashMap<String, usize> = HashMap::new();
+    let mut fingerprints: HashMap<String, usize> = HashMap::new();
+
+    for group in groups {
+        let mut trace_models = BTreeSet::new();
+        let mut trace_providers = BTreeSet::new();
+        let mut trace_fingerprints = BTreeSet::new();
+        for span in &group.spans {
+            if let Some(m) = span.kind().model() {
+                trace_models.insert(m.to_string());
+            }
+            if let Some(p) = span.kind().provider() {
+                trace_providers.insert(p.to_string());
+            }
+            if let SpanStatus::Failed { error } = span.status() {
+                trace_fingerprints.insert(error_fingerprint(error));
+            }
+        }
+        for m in trace_models {
+            *models.entry(m).or_default() += 1;
+        }
+        for p in trace_providers {
+            *providers.entry(p).or_default() += 1;
+        }
+        for f in trace_fingerprints {
+            *fingerprints.entry(f).or_default() += 1;
+        }
+        if let Some(trace) = group.trace {
+            let trace_tags: BTreeSet<&String> = trace.tags.iter().collect();
+            for t in trace_tags {
+                *tags.entry(t.clone()).or_default() += 1;
+            }
+        }
+        *statuses
+            .entry(trace_status(&group.spans).to_string())
+            .or_default() += 1;
+    }
+
+    TraceFacets {
+        total_traces: groups.len(),
+        models: into_sorted(models),
+        providers: into_sorted(providers),
+        statuses: into_sorted(statuses),
+        tags: into_sorted(tags),
+        error_fingerprints: into_sorted(fingerprints),
+    }
+}
+
+/// How many traces carry each tag, most used first.
+pub fn count_tags<'a>(traces: impl IntoIterator<Item = &'a Trace>) -> Vec<FacetCount> {
+    let mut tags: HashMap<String, usize> = HashMap::new();
+    for trace in traces {
+        let trace_tags: BTreeSet<&String> = trace.tags.iter().collect();
+        for t in trace_tags {
+            *tags.entry(t.clone()).or_default() += 1;
+        }
+    }
+    into_sorted(tags)
+}
+
+/// Sort facet values by count descending, then value ascending.
+fn into_sorted(counts: HashMap<String, usize>) -> Vec<FacetCount> {
+    let mut facets: Vec<FacetCount> = counts
+        .into_iter()
+        .map(|(value, count)| FacetCount { value, count })
+        .collect();
+    facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    facets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trace::{SpanBuilder, SpanKind};
+
+    fn llm_span(trace_id: trace::TraceId, model: &str) -> Span {
+        SpanBuilder::new(
+            trace_id,
+            "call",
+            SpanKind::LlmCall {
+                model: model.to_string(),
+                provider: Some("openai".to_string()),
+                input_tokens: None,
+                output_tokens: None,
+                cost: None,
+                input_preview: None,
+                output_preview: None,
+            },
+        )
+        .build()
+    }
+
+    #[test]
+    fn fingerprint_strips_ids() {
+        let a = error_fingerprint("span 0192f4a8-7c1e-7b3a-9a1e-2f0c6d8e4b11 timed out after 30000ms");
+        let b = error_fingerprint("span 0192f4a8-0000-7b3a-9a1e-2f0c6d8e4b11 timed out after 45000ms");
+        assert_eq!(a, b);
+        assert_eq!(a, "span <id> timed out after <n>ms");
+        assert_eq!(error_fingerprint("HTTP 429"), "HTTP 429");
+        assert_eq!(error_fingerprint("bad hash deadbeef12"), "bad hash <hex>");
+    }
+
+    #[test]
+    fn facets_count_once_per_trace() {
+        let t1 = Trace::new(Some("a".into())).with_tags(vec!["prod".into()]);
+        let t2 = Trace::new(Some("b".into()));
+        let s1 = llm_span(t1.id, "gpt-4o");
+        let s2 = llm_span(t1.id, "gpt-4o");
+        let s3 = llm_span(t2.id, "gpt-4o-mini").fail("HTTP 500");
+        let groups = vec![
+            TraceGroup {
+                trace: Some(&t1),
+                spans: vec![&s1, &s2],
+            },
+            TraceGroup {
+                trace: Some(&t2),
+                spans: vec![&s3],
+            },
+        ];
+        let facets = compute_trace_facets(&groups);
+        assert_eq!(facets.total_traces, 2);
+        assert_eq!(
+            facets.models,
+            vec![
+                FacetCount { value: "gpt-4o".into(), count: 1 },
+                FacetCount { value: "gpt-4o-mini".into(), count: 1 },
+            ]
+        );
+        assert_eq!(facets.providers[0].count, 2);
+        assert_eq!(facets.tags, vec![FacetCount { value: "prod".into(), count: 1 }]);
+        assert_eq!(facets.error_fingerprints[0].value, "HTTP 500");
+        assert!(facets.statuses.iter().any(|f| f.value == "failed" && f.count == 1));
+        assert!(facets.statuses.iter().any(|f| f.value == "running" && f.count == 1));
+    }
+
+    #[test]
+    fn trace_filter_by_tag_and_name() {
+        let t = Trace::new(Some("checkout flow".into())).with_tags(vec!["prod".into()]);
+        let group = TraceGroup {
+            trace: Some(&t),
+            spans: vec![],
+        };
+        let mut filter = TraceFilter {
+            name_contains: Some("checkout".into()),
+            tags: Some(vec!["prod".into()]),
+            ..Default::default()
+        };
+        assert!(group.matches(&filter));
+        filter.tags = Some(vec!["staging".into()]);
+        assert!(!group.matches(&filter));
+    }
+}
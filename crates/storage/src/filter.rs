@@ -1,7 +1,10 @@
+use std::cmp::Ordering;
+
 use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use trace::{DatasetId, TraceId};
+use trace::{AnalyticsFilter, Annotation, DatasetId, Span, SpanId, Trace, TraceId};
+use utoipa::ToSchema;
 
 use crate::StorageError;
 
@@ -24,7 +27,7 @@ impl Default for SortOrder {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Page<T> {
     pub items: Vec<T>,
     pub total: Option<usize>,
@@ -46,7 +49,100 @@ pub struct TraceFilter {
     pub tags: Option<Vec<String>>,
     pub since: Option<DateTime<Utc>>,
     pub until: Option<DateTime<Utc>>,
+    /// Exact `Trace::machine_id`
+    pub machine_id: Option<String>,
+    /// Exact `Trace::session_id`
+    pub session_id: Option<String>,
+    /// Exact `Trace::git_commit`
+    pub git_commit: Option<String>,
+    /// Exact `Trace::git_branch`
+    pub git_branch: Option<String>,
+    /// Exact `Trace::repo`
+    pub repo: Option<String>,
     pub limit: Option<usize>,
+    /// Number of matching traces to skip (applied after `cursor`)
+    pub offset: Option<usize>,
+    /// Opaque cursor from a previous page's `next_cursor`
+    pub cursor: Option<String>,
+    /// Field to sort by: "started_at", "duration", "cost", "name"
+    pub sort_by: Option<String>,
+    /// Sort direction: "asc" or "desc" (default: "desc")
+    pub sort_order: Option<String>,
+    /// Whether trashed traces are left out (the default), selected alone,
+    /// or included
+    pub trash: TrashScope,
+}
+
+/// Which traces a `TraceFilter` sees with respect to the trash.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrashScope {
+    #[default]
+    Live,
+    Trashed,
+    All,
+}
+
+impl TrashScope {
+    pub fn admits(self, trashed: bool) -> bool {
+        match self {
+            TrashScope::Live => !trashed,
+            TrashScope::Trashed => trashed,
+            TrashScope::All => true,
+        }
+    }
+}
+
+/// Filter for querying the audit log. Results are newest first.
+#[derive(Debug, Default, Clone)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    /// Exact action, or a prefix ending in `.` (e.g. `webhook.`)
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+/// Filter for querying annotations. Results are newest first.
+#[derive(Debug, Default, Clone)]
+pub struct AnnotationFilter {
+    pub span_id: Option<SpanId>,
+    pub trace_id: Option<TraceId>,
+    pub label: Option<String>,
+    /// Minimum score (inclusive); annotations without a score never match.
+    pub score_min: Option<f64>,
+    /// Maximum score (inclusive)
+    pub score_max: Option<f64>,
+    pub created_by: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AnnotationFilter {
+    pub fn matches(&self, annotation: &Annotation) -> bool {
+        if self.span_id.is_some_and(|id| annotation.span_id != id) {
+            return false;
+        }
+        if self.trace_id.is_some_and(|id| annotation.trace_id != id) {
+            return false;
+        }
+        if self.label.is_some() && annotation.label != self.label {
+            return false;
+        }
+        if self.created_by.is_some() && Some(&annotation.created_by) != self.created_by.as_ref() {
+            return false;
+        }
+        if let Some(min) = self.score_min {
+            if !annotation.score.is_some_and(|s| s >= min) {
+                return false;
+            }
+        }
+        if let Some(max) = self.score_max {
+            if !annotation.score.is_some_and(|s| s <= max) {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 /// Filter for querying spans.
@@ -60,8 +156,18 @@ pub struct SpanFilter {
     pub until: Option<DateTime<Utc>>,
     pub name_contains: Option<String>,
     pub path: Option<String>,
+    /// Tool name of `tool_call` spans.
+    pub tool: Option<String>,
+    /// Index of `retrieval` spans.
+    pub index: Option<String>,
+    /// Verdict of `guardrail` spans: `pass`, `fail` or `flag`.
+    pub verdict: Option<String>,
     pub trace_id: Option<TraceId>,
     pub limit: Option<usize>,
+    /// Number of matching spans to skip (applied after `cursor`)
+    pub offset: Option<usize>,
+    /// Opaque cursor from a previous page's `next_cursor`
+    pub cursor: Option<String>,
     /// Minimum duration in milliseconds (inclusive)
     pub duration_min: Option<i64>,
     /// Maximum duration in milliseconds (inclusive)
@@ -76,6 +182,20 @@ pub struct SpanFilter {
     pub input_contains: Option<String>,
     /// Full-text search within span output content only (case-insensitive)
     pub output_contains: Option<String>,
+    /// Minimum annotation score (inclusive): spans with an annotation
+    /// scored at least this
+    pub score_min: Option<f64>,
+    /// Maximum annotation score (inclusive)
+    pub score_max: Option<f64>,
+    /// Spans with an annotation carrying this label
+    pub label: Option<String>,
+    /// Only these spans. `PersistentStore` sets this from the annotation
+    /// predicates above, which backends and `matches` don't read.
+    pub span_ids: Option<Vec<SpanId>>,
+    /// Leave `input` and `output` unread, for callers that don't return
+    /// them. Backends that keep each span as one document may still read
+    /// them.
+    pub skip_payloads: bool,
     /// Field to sort by: "started_at", "duration", "tokens", "cost", "name"
     pub sort_by: Option<String>,
     /// Sort direction: "asc" or "desc" (default: "desc")
@@ -101,6 +221,418 @@ pub struct DatapointFilter {
     pub limit: Option<usize>,
 }
 
+/// Default page size for paged queries that don't specify `limit`.
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+
+/// Fields spans can be sorted by.
+pub const SPAN_SORT_FIELDS: &[&str] = &["started_at", "duration", "tokens", "cost", "name"];
+const TRACE_SORT_FIELDS: &[&str] = &["started_at", "duration", "cost", "name"];
+const NUMERIC_SORT_FIELDS: &[&str] = &["duration", "tokens", "cost"];
+
+/// A span or trace's value for the active sort field, as stored in cursors.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Number(f64),
+    Text(String),
+}
+
+impl SortValue {
+    fn parse(sort_field: &str, value: &str) -> Result<Self, StorageError> {
+        if NUMERIC_SORT_FIELDS.contains(&sort_field) {
+            value
+                .parse()
+                .map(SortValue::Number)
+                .map_err(|e| StorageError::Serialization(format!("invalid cursor value: {e}")))
+        } else {
+            Ok(SortValue::Text(value.to_string()))
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortValue::Number(a), SortValue::Number(b)) => {
+                a.partial_cmp(b).unwrap_or(Ordering::Equal)
+            }
+            (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+            (SortValue::Number(_), SortValue::Text(_)) => Ordering::Less,
+            (SortValue::Text(_), SortValue::Number(_)) => Ordering::Greater,
+        }
+    }
+
+    fn to_cursor_value(&self) -> String {
+        match self {
+            SortValue::Number(n) => n.to_string(),
+            SortValue::Text(s) => s.clone(),
+        }
+    }
+}
+
+/// Position of the last item of a page: its sort value and id.
+pub type CursorPosition = (SortValue, String);
+
+fn normalize_sort<'a>(sort_by: Option<&'a str>, allowed: &[&str]) -> &'a str {
+    match sort_by {
+        Some(field) if allowed.contains(&field) => field,
+        _ => "started_at",
+    }
+}
+
+fn decode_position(cursor: Option<&str>, sort_field: &str) -> Result<Option<CursorPosition>, StorageError> {
+    let Some(cursor) = cursor else {
+        return Ok(None);
+    };
+    let inner = decode_cursor(cursor)?;
+    if inner.sort_field != sort_field {
+        return Err(StorageError::Serialization(format!(
+            "cursor was issued for sort '{}', not '{}'",
+            inner.sort_field, sort_field
+        )));
+    }
+    Ok(Some((SortValue::parse(sort_field, &inner.last_value)?, inner.last_id)))
+}
+
+fn json_contains(value: Option<&serde_json::Value>, needle: &str) -> bool {
+    value
+        .map(|v| {
+            serde_json::to_string(v)
+                .unwrap_or_default()
+                .to_lowercase()
+                .contains(needle)
+        })
+        .unwrap_or(false)
+}
+
+impl SpanFilter {
+    /// Whether `span` satisfies every predicate in this filter (sorting and
+    /// paging fields are ignored).
+    pub fn matches(&self, span: &Span) -> bool {
+        if let Some(ref kind) = self.kind {
+            if span.kind().kind_name() != kind {
+                return false;
+            }
+        }
+
+        if let Some(ref model) = self.model {
+            match span.kind().model() {
+                Some(m) if m == model => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref provider) = self.provider {
+            match span.kind().provider() {
+                Some(p) if p == provider => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref status) = self.status {
+            if span.status().as_str() != status {
+                return false;
+            }
+        }
+
+        if let Some(since) = self.since {
+            if span.started_at() < since {
+                return false;
+            }
+        }
+
+        if let Some(until) = self.until {
+            if span.started_at() > until {
+                return false;
+            }
+        }
+
+        if let Some(ref name_contains) = self.name_contains {
+            if !span.name().contains(name_contains) {
+                return false;
+            }
+        }
+
+        if let Some(ref path) = self.path {
+            match span.kind().path() {
+                Some(p) if p == path => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref tool) = self.tool {
+            match span.kind().tool_name() {
+                Some(t) if t == tool => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref index) = self.index {
+            match span.kind().index() {
+                Some(i) if i == index => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref verdict) = self.verdict {
+            match span.kind().verdict() {
+                Some(v) if v.as_str() == verdict => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(trace_id) = self.trace_id {
+            if span.trace_id() != trace_id {
+                return false;
+            }
+        }
+
+        if let Some(ref ids) = self.span_ids {
+            if !ids.contains(&span.id()) {
+                return false;
+            }
+        }
+
+        // Running spans have no duration and never match duration bounds
+        if let Some(min_ms) = self.duration_min {
+            match span.duration_ms() {
+                Some(d) if d >= min_ms => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(max_ms) = self.duration_max {
+            match span.duration_ms() {
+                Some(d) if d <= max_ms => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(min_tokens) = self.tokens_min {
+            match span.kind().total_tokens() {
+                Some(t) if t >= min_tokens => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(min_cost) = self.cost_min {
+            match span.kind().cost() {
+                Some(c) if c >= min_cost => {}
+                _ => return false,
+            }
+        }
+
+        // Full-text search: case-insensitive contains on serialized input/output
+        if let Some(ref text) = self.text_contains {
+            let needle = text.to_lowercase();
+            if !json_contains(span.input(), &needle)
+                && !json_contains(span.output(), &needle)
+                && !span.name().to_lowercase().contains(&needle)
+            {
+                return false;
+            }
+        }
+
+        if let Some(ref text) = self.input_contains {
+            if !json_contains(span.input(), &text.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if let Some(ref text) = self.output_contains {
+            if !json_contains(span.output(), &text.to_lowercase()) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Whether this filters on annotations, which is resolved to
+    /// `span_ids` before reaching a backend.
+    pub fn has_annotation_predicates(&self) -> bool {
+        self.score_min.is_some() || self.score_max.is_some() || self.label.is_some()
+    }
+
+    /// The annotations whose spans the annotation predicates select.
+    pub fn annotation_filter(&self) -> AnnotationFilter {
+        AnnotationFilter {
+            label: self.label.clone(),
+            score_min: self.score_min,
+            score_max: self.score_max,
+            trace_id: self.trace_id,
+            ..Default::default()
+        }
+    }
+
+    /// The effective sort field; unknown values fall back to "started_at".
+    pub fn sort_field(&self) -> &str {
+        normalize_sort(self.sort_by.as_deref(), SPAN_SORT_FIELDS)
+    }
+
+    pub fn sort_desc(&self) -> bool {
+        self.sort_order.as_deref() != Some("asc")
+    }
+
+    /// Decode `cursor`, checking that it was issued for the same sort field.
+    pub fn cursor_position(&self) -> Result<Option<CursorPosition>, StorageError> {
+        decode_position(self.cursor.as_deref(), self.sort_field())
+    }
+}
+
+impl From<&AnalyticsFilter> for SpanFilter {
+    fn from(f: &AnalyticsFilter) -> Self {
+        SpanFilter {
+            kind: f.kind.clone(),
+            model: f.model.clone(),
+            provider: f.provider.clone(),
+            status: f.status.clone(),
+            since: f.since,
+            until: f.until,
+            trace_id: f.trace_id,
+            ..Default::default()
+        }
+    }
+}
+
+impl TraceFilter {
+    /// Whether `trace` satisfies every predicate in this filter (sorting and
+    /// paging fields are ignored).
+    pub fn matches(&self, trace: &Trace) -> bool {
+        if let Some(ref name) = self.name_contains {
+            match trace.name.as_deref() {
+                Some(n) if n.contains(name.as_str()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(ref tags) = self.tags {
+            if !tags.iter().all(|tag| trace.tags.contains(tag)) {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| trace.started_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| trace.started_at > until) {
+            return false;
+        }
+        if self.machine_id.is_some() && trace.machine_id != self.machine_id {
+            return false;
+        }
+        if self.session_id.is_some() && trace.session_id != self.session_id {
+            return false;
+        }
+        if self.git_commit.is_some() && trace.git_commit != self.git_commit {
+            return false;
+        }
+        if self.git_branch.is_some() && trace.git_branch != self.git_branch {
+            return false;
+        }
+        if self.repo.is_some() && trace.repo != self.repo {
+            return false;
+        }
+        self.trash.admits(trace.deleted_at.is_some())
+    }
+
+    /// The effective sort field; unknown values fall back to "started_at".
+    pub fn sort_field(&self) -> &str {
+        normalize_sort(self.sort_by.as_deref(), TRACE_SORT_FIELDS)
+    }
+
+    pub fn sort_desc(&self) -> bool {
+        self.sort_order.as_deref() != Some("asc")
+    }
+
+    /// Decode `cursor`, checking that it was issued for the same sort field.
+    pub fn cursor_position(&self) -> Result<Option<CursorPosition>, StorageError> {
+        decode_position(self.cursor.as_deref(), self.sort_field())
+    }
+}
+
+/// Sort value of `span` for a span sort field.
+pub fn span_sort_value(span: &Span, sort_field: &str) -> SortValue {
+    match sort_field {
+        "duration" => SortValue::Number(span.duration_ms().unwrap_or(0) as f64),
+        "tokens" => SortValue::Number(span.kind().total_tokens().unwrap_or(0) as f64),
+        "cost" => SortValue::Number(span.kind().cost().unwrap_or(0.0)),
+        "name" => SortValue::Text(span.name().to_string()),
+        _ => SortValue::Text(span.started_at().to_rfc3339()),
+    }
+}
+
+/// Sort value of `trace` for a trace sort field. `cost` is the summed cost
+/// of the trace's spans and is only used when sorting by cost.
+pub fn trace_sort_value(trace: &Trace, sort_field: &str, cost: f64) -> SortValue {
+    match sort_field {
+        "duration" => SortValue::Number(
+            trace
+                .ended_at
+                .map(|end| (end - trace.started_at).num_milliseconds())
+                .unwrap_or(0) as f64,
+        ),
+        "cost" => SortValue::Number(cost),
+        "name" => SortValue::Text(trace.name.clone().unwrap_or_default()),
+        _ => SortValue::Text(trace.started_at.to_rfc3339()),
+    }
+}
+
+/// Sort `items` by `(sort value, id)`, drop everything up to and including
+/// `after`, then apply `offset` and `limit`. Used by backends that page in
+/// memory rather than in the query engine.
+pub fn sort_and_page<T>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> CursorPosition,
+    desc: bool,
+    after: Option<&CursorPosition>,
+    offset: usize,
+    limit: Option<usize>,
+) -> Vec<T> {
+    let cmp = |a: &CursorPosition, b: &CursorPosition| {
+        let ord = a.0.compare(&b.0).then_with(|| a.1.cmp(&b.1));
+        if desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    };
+    let mut keyed: Vec<(CursorPosition, T)> = items.into_iter().map(|t| (key(&t), t)).collect();
+    keyed.sort_by(|a, b| cmp(&a.0, &b.0));
+    keyed
+        .into_iter()
+        .filter(|(k, _)| after.is_none_or(|pos| cmp(k, pos) == Ordering::Greater))
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|(_, t)| t)
+        .collect()
+}
+
+/// Build a page from up to `limit + 1` sorted items; the extra item only
+/// signals that another page exists.
+pub fn into_page<T>(
+    mut items: Vec<T>,
+    limit: usize,
+    sort_field: &str,
+    key: impl Fn(&T) -> CursorPosition,
+) -> Page<T> {
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+    let next_cursor = if has_more {
+        items.last().map(|last| {
+            let (value, id) = key(last);
+            encode_cursor(&CursorInner {
+                sort_field: sort_field.to_string(),
+                last_value: value.to_cursor_value(),
+                last_id: id,
+            })
+        })
+    } else {
+        None
+    };
+    Page {
+        items,
+        total: None,
+        next_cursor,
+        has_more,
+    }
+}
+
 pub fn encode_cursor(inner: &CursorInner) -> String {
     let json = serde_json::to_string(inner).expect("CursorInner is always serializable");
     STANDARD.encode(json.as_bytes())
@@ -140,4 +672,62 @@ mod tests {
         let not_json = STANDARD.encode(b"not json");
         assert!(decode_cursor(&not_json).is_err());
     }
+
+    #[test]
+    fn verdict_matches_guardrail_spans() {
+        let kind = trace::SpanKind::Guardrail {
+            name: "toxicity".into(),
+            verdict: trace::GuardrailVerdict::Fail,
+            categories: vec!["harassment".into()],
+        };
+        let span = trace::SpanBuilder::new(Trace::new(None).id, "moderate", kind).build();
+        let filter = |verdict: &str| SpanFilter {
+            verdict: Some(verdict.into()),
+            ..Default::default()
+        };
+        assert!(filter("fail").matches(&span));
+        assert!(!filter("pass").matches(&span));
+    }
+
+    #[test]
+    fn pages_follow_cursor_without_gaps() {
+        let key = |n: &u32| (SortValue::Number(f64::from(n % 3)), n.to_string());
+        let items: Vec<u32> = (0..7).collect();
+        let filter = SpanFilter {
+            sort_by: Some("cost".into()),
+            ..Default::default()
+        };
+
+        let mut seen = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let f = SpanFilter {
+                cursor: cursor.clone(),
+                ..filter.clone()
+            };
+            let after = f.cursor_position().unwrap();
+            let batch = sort_and_page(items.clone(), key, f.sort_desc(), after.as_ref(), 0, Some(3));
+            let page = into_page(batch, 2, f.sort_field(), key);
+            seen.extend(page.items);
+            match page.next_cursor {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![5, 2, 4, 1, 6, 3, 0]);
+    }
+
+    #[test]
+    fn cursor_must_match_sort_field() {
+        let cursor = encode_cursor(&CursorInner {
+            sort_field: "cost".into(),
+            last_value: "1.5".into(),
+            last_id: "x".into(),
+        });
+        let filter = SpanFilter {
+            cursor: Some(cursor),
+            ..Default::default()
+        };
+        assert!(filter.cursor_position().is_err());
+    }
 }
@@ -1,28 +1,81 @@
+pub mod analytical;
 pub mod analytics;
+pub mod archive;
 pub mod backend;
+#[cfg(feature = "bench")]
+pub mod bench;
 pub mod error;
+pub mod facets;
 pub mod filter;
-
-use std::collections::HashMap;
-
+pub mod normalize;
+pub mod payloads;
+pub mod query;
+pub mod redact;
+pub mod sessions;
+pub mod spill;
+pub mod write_behind;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use dashmap::DashMap;
 use lru::LruCache;
+use serde::Serialize;
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+use trace::consensus::Resolution;
 use trace::{
-    CaptureRule, CaptureRuleId, Datapoint, DatapointId, Dataset, DatasetId, EvalResult,
-    EvalResultId, EvalRun, EvalRunId, FileVersion, ProviderConnection, ProviderConnectionId,
-    QueueItem, QueueItemId, QueueItemStatus, Span, SpanId, SpanKind, Trace, TraceId,
+    Annotation, AnnotationId, AuditEvent, CaptureRule, CaptureRuleId, Datapoint, DatapointId,
+    Dataset, DatasetId, EvalResult, EvalResultId, EvalRun, EvalRunId, FacetCount, FileVersion,
+    Machine, ProviderConnection, ProviderConnectionId, QueueItem, QueueItemId, QueueItemStatus,
+    QueueSubmission, ReviewPolicy, SavedView, SavedViewId, Session, Span, SpanId, SpanKind,
+    SpanKindDefinition, SpanStatus, Trace, TraceFacets, TraceId, TraceStats, Webhook,
+    WebhookDelivery, WebhookId,
 };
 
-pub use backend::StorageBackend;
+pub use analytical::{AnalyticalStore, SpanRow};
+pub use archive::{ArchiveReport, LocalSegments, SegmentInfo, SegmentStore};
+pub use backend::{ScoredSpan, StorageBackend};
 pub use error::StorageError;
 pub use filter::{
-    decode_cursor, encode_cursor, CursorInner, DatapointFilter, FileFilter, Page, Pagination,
-    SortOrder, SpanFilter, TraceFilter,
+    decode_cursor, encode_cursor, AnnotationFilter, AuditFilter, CursorInner, DatapointFilter,
+    FileFilter, Page, Pagination, SortOrder, SortValue, SpanFilter, TraceFilter, TrashScope,
+    DEFAULT_PAGE_LIMIT,
 };
+pub use normalize::{NameNormalizer, NameRule};
+pub use payloads::{PayloadField, PayloadOffloadConfig, PayloadRef};
+pub use query::parse_span_query;
+pub use redact::{Detector, RedactionConfig, RedactionRule, Redactor};
+pub use spill::{SpillConfig, SpillStatus};
+pub use write_behind::WriteBehindConfig;
+
+use archive::{MAX_SEGMENT_SPANS, SEGMENTS_SETTING};
+use spill::{SpillQueue, Spilled};
+use write_behind::WriteBehind;
 
 const DEFAULT_MAX_SPANS: usize = 50_000;
+/// Decoded archive segments kept in memory.
+const SEGMENT_CACHE_SIZE: std::num::NonZero<usize> = std::num::NonZero::new(4).unwrap();
+/// Spans or traces read per backend page by full scans such as
+/// `rebuild_analytical` and `scan_spans`.
+const REBUILD_PAGE_SIZE: usize = 1_000;
 const DEFAULT_MAX_TRACES: usize = 10_000;
 const DEFAULT_MAX_DATASETS: usize = 5_000;
 const DEFAULT_MAX_DATAPOINTS: usize = 5_000;
+/// Tries at moving a dataset into or out of the trash while other writers
+/// keep changing it.
+const DATASET_MOVE_ATTEMPTS: usize = 3;
+
+/// Name of the per-trace setting holding the daemon's summary of a trace.
+pub const TRACE_SUMMARY_SETTING: &str = "trace_summary";
+/// Settings kept per trace under [`trace_setting_key`]. They are deleted
+/// along with their trace.
+pub const TRACE_SETTINGS: &[&str] = &[TRACE_SUMMARY_SETTING];
+
+/// Key trace `id`'s `name` setting is saved under.
+pub fn trace_setting_key(name: &str, id: TraceId) -> String {
+    format!("{name}:{id}")
+}
 
 fn get_cache_size(env_var: &str, default: usize) -> usize {
     std::env::var(env_var)
@@ -79,8 +132,12 @@ impl Default for SpanStore {
 
 impl SpanStore {
     pub fn new() -> Self {
+        Self::with_capacity(max_spans())
+    }
+
+    pub fn with_capacity(capacity: std::num::NonZero<usize>) -> Self {
         Self {
-            spans: LruCache::new(max_spans()),
+            spans: LruCache::new(capacity),
             traces: HashMap::new(),
         }
     }
@@ -97,6 +154,11 @@ impl SpanStore {
         self.spans.get(&id)
     }
 
+    /// Like `get`, without touching LRU order.
+    pub fn peek(&self, id: SpanId) -> Option<&Span> {
+        self.spans.peek(&id)
+    }
+
     pub fn remove(&mut self, id: SpanId) -> Option<Span> {
         self.spans.pop(&id)
     }
@@ -161,262 +223,239 @@ impl SpanStore {
         self.traces.clear();
     }
 
+    /// Spans matching `filter`, sorted and paged per its `sort_by`, `cursor`,
+    /// `offset`, and `limit`. An invalid cursor is ignored.
     pub fn filter_spans(&self, filter: &SpanFilter) -> Vec<&Span> {
-        let mut results: Vec<&Span> = self
-            .spans
-            .iter()
-            .map(|(_, span)| span)
-            .filter(|span| {
-                if let Some(ref kind) = filter.kind {
-                    if span.kind().kind_name() != kind {
-                        return false;
-                    }
-                }
-
-                if let Some(ref model) = filter.model {
-                    match span.kind().model() {
-                        Some(m) if m == model => {}
-                        _ => return false,
-                    }
-                }
-
-                if let Some(ref provider) = filter.provider {
-                    match span.kind().provider() {
-                        Some(p) if p == provider => {}
-                        _ => return false,
-                    }
-                }
-
-                if let Some(ref status) = filter.status {
-                    if span.status().as_str() != status {
-                        return false;
-                    }
-                }
-
-                if let Some(since) = filter.since {
-                    if span.started_at() < since {
-                        return false;
-                    }
-                }
-
-                if let Some(until) = filter.until {
-                    if span.started_at() > until {
-                        return false;
-                    }
-                }
+        page_spans(self.all_spans(), filter)
+    }
+}
 
-                if let Some(ref name_contains) = filter.name_contains {
-                    if !span.name().contains(name_contains) {
-                        return false;
-                    }
-                }
+fn page_spans<'a>(spans: impl Iterator<Item = &'a Span>, filter: &SpanFilter) -> Vec<&'a Span> {
+    let results: Vec<&Span> = spans.filter(|span| filter.matches(span)).collect();
+    let field = filter.sort_field();
+    let after = filter.cursor_position().ok().flatten();
+    filter::sort_and_page(
+        results,
+        |s| (filter::span_sort_value(s, field), s.id().to_string()),
+        filter.sort_desc(),
+        after.as_ref(),
+        filter.offset.unwrap_or(0),
+        filter.limit,
+    )
+}
 
-                if let Some(ref path) = filter.path {
-                    match span.kind().path() {
-                        Some(p) if p == path => {}
-                        _ => return false,
-                    }
-                }
+// --- Persistent store ---
 
-                if let Some(trace_id) = filter.trace_id {
-                    if span.trace_id() != trace_id {
-                        return false;
-                    }
-                }
+/// What a retention prune removed, or would remove on a dry run. `spans`
+/// excludes spans deleted along with their trace.
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneReport {
+    pub cutoff: chrono::DateTime<chrono::Utc>,
+    pub dry_run: bool,
+    pub traces: usize,
+    pub spans: usize,
+    pub file_versions: usize,
+    /// Archive segments holding only spans past the cutoff.
+    pub segments: usize,
+}
 
-                if let Some(min_ms) = filter.duration_min {
-                    match span.duration_ms() {
-                        Some(d) if d >= min_ms => {}
-                        Some(_) => return false,
-                        None => return false, // running spans have no duration
-                    }
-                }
+/// What a file content GC removed, or would remove on a dry run.
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Content blobs still referenced by a file version or a span.
+    pub blobs_kept: usize,
+    pub blobs_deleted: usize,
+    pub bytes_reclaimed: u64,
+}
 
-                if let Some(max_ms) = filter.duration_max {
-                    match span.duration_ms() {
-                        Some(d) if d <= max_ms => {}
-                        Some(_) => return false,
-                        None => return false,
-                    }
-                }
+/// What `PersistentStore::clear` removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearScope {
+    /// Every span. Traces are kept with their rollups reset.
+    Spans,
+    /// Traces started at or before the cutoff, with their spans.
+    TracesBefore(chrono::DateTime<chrono::Utc>),
+    /// Every trace and span.
+    All,
+}
 
-                if let Some(min_tokens) = filter.tokens_min {
-                    match span.kind().total_tokens() {
-                        Some(t) if t >= min_tokens => {}
-                        _ => return false,
-                    }
-                }
+/// What a trash purge deleted for good.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PurgeReport {
+    pub traces: usize,
+    pub datasets: usize,
+}
 
-                if let Some(min_cost) = filter.cost_min {
-                    match span.kind().cost() {
-                        Some(c) if c >= min_cost => {}
-                        _ => return false,
-                    }
-                }
+/// Outcome of a versioned update.
+#[derive(Debug, Clone)]
+pub enum Versioned<T> {
+    /// Written; holds the saved copy at its new version.
+    Updated(T),
+    /// The stored copy had moved on; holds it as it now is.
+    Conflict(T),
+    NotFound,
+}
 
-                // Full-text search: case-insensitive contains on serialized input/output
-                if let Some(ref text) = filter.text_contains {
-                    let needle = text.to_lowercase();
-                    let in_input = span
-                        .input()
-                        .map(|v| {
-                            serde_json::to_string(v)
-                                .unwrap_or_default()
-                                .to_lowercase()
-                                .contains(&needle)
-                        })
-                        .unwrap_or(false);
-                    let in_output = span
-                        .output()
-                        .map(|v| {
-                            serde_json::to_string(v)
-                                .unwrap_or_default()
-                                .to_lowercase()
-                                .contains(&needle)
-                        })
-                        .unwrap_or(false);
-                    let in_name = span.name().to_lowercase().contains(&needle);
-                    if !in_input && !in_output && !in_name {
-                        return false;
-                    }
-                }
+/// Counts removed by a clear, or that would be removed for a preview.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClearReport {
+    pub traces: usize,
+    pub spans: usize,
+}
 
-                if let Some(ref text) = filter.input_contains {
-                    let needle = text.to_lowercase();
-                    let found = span
-                        .input()
-                        .map(|v| {
-                            serde_json::to_string(v)
-                                .unwrap_or_default()
-                                .to_lowercase()
-                                .contains(&needle)
-                        })
-                        .unwrap_or(false);
-                    if !found {
-                        return false;
-                    }
-                }
+/// A span's state before and after a write, for trace rollups.
+type SpanChange<'a> = (Option<&'a Span>, Option<&'a Span>);
 
-                if let Some(ref text) = filter.output_contains {
-                    let needle = text.to_lowercase();
-                    let found = span
-                        .output()
-                        .map(|v| {
-                            serde_json::to_string(v)
-                                .unwrap_or_default()
-                                .to_lowercase()
-                                .contains(&needle)
-                        })
-                        .unwrap_or(false);
-                    if !found {
-                        return false;
-                    }
-                }
+/// Span cache shards. Spans are sharded by trace, so a trace's spans and its
+/// index entry always live in the same shard.
+const SPAN_SHARDS: usize = 16;
 
-                true
-            })
-            .collect();
+fn shard_of(trace_id: TraceId) -> usize {
+    (trace_id.as_u128() % SPAN_SHARDS as u128) as usize
+}
 
-        // Apply sorting
-        if let Some(ref sort_by) = filter.sort_by {
-            let desc = filter.sort_order.as_deref() != Some("asc");
-            match sort_by.as_str() {
-                "started_at" => {
-                    results.sort_by(|a, b| {
-                        let cmp = a.started_at().cmp(&b.started_at());
-                        if desc {
-                            cmp.reverse()
-                        } else {
-                            cmp
-                        }
-                    });
-                }
-                "duration" => {
-                    results.sort_by(|a, b| {
-                        let cmp = a
-                            .duration_ms()
-                            .unwrap_or(0)
-                            .cmp(&b.duration_ms().unwrap_or(0));
-                        if desc {
-                            cmp.reverse()
-                        } else {
-                            cmp
-                        }
-                    });
-                }
-                "tokens" => {
-                    results.sort_by(|a, b| {
-                        let cmp = a
-                            .kind()
-                            .total_tokens()
-                            .unwrap_or(0)
-                            .cmp(&b.kind().total_tokens().unwrap_or(0));
-                        if desc {
-                            cmp.reverse()
-                        } else {
-                            cmp
-                        }
-                    });
-                }
-                "cost" => {
-                    results.sort_by(|a, b| {
-                        let ca = a.kind().cost().unwrap_or(0.0);
-                        let cb = b.kind().cost().unwrap_or(0.0);
-                        let cmp = ca.partial_cmp(&cb).unwrap_or(std::cmp::Ordering::Equal);
-                        if desc {
-                            cmp.reverse()
-                        } else {
-                            cmp
-                        }
-                    });
-                }
-                "name" => {
-                    results.sort_by(|a, b| {
-                        let cmp = a.name().cmp(b.name());
-                        if desc {
-                            cmp.reverse()
-                        } else {
-                            cmp
-                        }
-                    });
-                }
-                _ => {
-                    // Default: newest first
-                    results.sort_by(|a, b| b.started_at().cmp(&a.started_at()));
-                }
-            }
-        } else {
-            // Default sort: newest first
-            results.sort_by(|a, b| b.started_at().cmp(&a.started_at()));
+/// Facet counts over `traces` and `spans`, grouped by trace. Spans whose
+/// trace wasn't recorded form groups of their own.
+fn facets_over<'a>(
+    traces: impl Iterator<Item = &'a Trace>,
+    spans: impl Iterator<Item = &'a Span>,
+    filter: &TraceFilter,
+    span_filter: Option<&SpanFilter>,
+) -> TraceFacets {
+    let mut by_trace: HashMap<TraceId, Vec<&Span>> = HashMap::new();
+    let mut span_matches: HashSet<TraceId> = HashSet::new();
+    for span in spans {
+        if span_filter.is_some_and(|f| f.matches(span)) {
+            span_matches.insert(span.trace_id());
         }
+        by_trace.entry(span.trace_id()).or_default().push(span);
+    }
+    let metas: HashMap<TraceId, &Trace> = traces.map(|t| (t.id, t)).collect();
+
+    let mut ids: HashSet<TraceId> = by_trace.keys().copied().collect();
+    ids.extend(metas.keys().copied());
+    let groups: Vec<facets::TraceGroup> = ids
+        .into_iter()
+        .filter(|id| span_filter.is_none() || span_matches.contains(id))
+        .map(|id| facets::TraceGroup {
+            trace: metas.get(&id).copied(),
+            spans: by_trace.remove(&id).unwrap_or_default(),
+        })
+        .filter(|g| g.matches(filter))
+        .collect();
+    facets::compute_trace_facets(&groups)
+}
 
-        // Apply limit
-        if let Some(limit) = filter.limit {
-            results.truncate(limit);
-        }
+/// Run a bulk load, or skip it and load nothing for a lazily opened store.
+async fn unless_lazy<T>(
+    lazy: bool,
+    load: impl std::future::Future<Output = Result<Vec<T>, StorageError>>,
+) -> Result<Vec<T>, StorageError> {
+    if lazy {
+        Ok(Vec::new())
+    } else {
+        load.await
+    }
+}
 
-        results
+/// Apply a tag edit in place, keeping the existing order and adding new
+/// tags at the end. Returns whether anything changed.
+fn retag(tags: &mut Vec<String>, add: &[String], remove: &[String]) -> bool {
+    let before = tags.len();
+    tags.retain(|t| !remove.contains(t));
+    let mut changed = tags.len() != before;
+    for tag in add {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+            changed = true;
+        }
     }
+    changed
 }
 
-// --- Persistent store ---
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
 
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Write-through cache over a `StorageBackend`, shared as
+/// `Arc<PersistentStore<_>>`.
+///
+/// Cached state sits behind synchronous locks that are never held across
+/// backend I/O, so readers only ever wait on in-memory updates. Reads don't
+/// touch LRU order and take shared locks. Span and trace writes are
+/// serialized per trace shard so that rollups are updated consistently;
+/// bulk deletes take every shard.
 pub struct PersistentStore<B: StorageBackend> {
-    memory: SpanStore,
-    trace_meta: LruCache<TraceId, Trace>,
-    file_versions: Vec<FileVersion>,
-    datasets: LruCache<DatasetId, Dataset>,
-    datapoints: LruCache<DatapointId, Datapoint>,
-    queue_items: HashMap<QueueItemId, QueueItem>,
-    eval_runs: HashMap<EvalRunId, EvalRun>,
-    eval_results: HashMap<EvalResultId, EvalResult>,
-    capture_rules: HashMap<CaptureRuleId, CaptureRule>,
-    provider_connections: HashMap<ProviderConnectionId, ProviderConnection>,
-    backend: B,
+    spans: Box<[RwLock<SpanStore>]>,
+    /// Held across span and trace writes, indexed like `spans`.
+    trace_locks: Box<[AsyncMutex<()>]>,
+    trace_meta: RwLock<LruCache<TraceId, Trace>>,
+    /// Traces in the trash. Their spans stay in the backend but are kept
+    /// out of the cache and query results.
+    trashed: RwLock<HashSet<TraceId>>,
+    file_versions: RwLock<Vec<FileVersion>>,
+    /// Hashes of content saved since opening that no file version refers
+    /// to yet. GC keeps them, as their version may be about to be saved.
+    unversioned: RwLock<HashSet<String>>,
+    datasets: RwLock<LruCache<DatasetId, Dataset>>,
+    datapoints: RwLock<LruCache<DatapointId, Datapoint>>,
+    queue_items: DashMap<QueueItemId, QueueItem>,
+    eval_runs: DashMap<EvalRunId, EvalRun>,
+    eval_results: DashMap<EvalResultId, EvalResult>,
+    capture_rules: DashMap<CaptureRuleId, CaptureRule>,
+    provider_connections: DashMap<ProviderConnectionId, ProviderConnection>,
+    span_kinds: DashMap<String, SpanKindDefinition>,
+    backend: Arc<B>,
+    writer: Option<WriteBehind>,
+    /// Holds span and trace writes while the backend is failing.
+    spill: Option<Arc<SpillQueue>>,
+    /// Opened with `open_lazy`: the caches start empty and fill on demand.
+    lazy: bool,
+    names: Option<Arc<NameNormalizer>>,
+    /// Masks payloads before spans are persisted; swapped when an org
+    /// changes its settings.
+    redactor: RwLock<Option<Arc<Redactor>>>,
+    /// Stamped on saved traces that don't name a machine.
+    machine_id: Option<String>,
+    /// Holds spans moved out of the backend by `archive_before`.
+    archive: Option<Arc<dyn SegmentStore>>,
+    /// Recently read segments, by key.
+    segment_cache: RwLock<LruCache<String, Arc<Vec<Span>>>>,
+    /// Held while deletes rewrite segments and their pointers, which a
+    /// single trace lock doesn't serialize.
+    segments_lock: AsyncMutex<()>,
+    /// Mirrors span summaries for SQL analytics.
+    analytical: Option<Arc<dyn AnalyticalStore>>,
+    payload_offload: Option<PayloadOffloadConfig>,
+    /// Bumped after every span or trace write and delete.
+    revision: AtomicU64,
 }
 
 impl<B: StorageBackend> PersistentStore<B> {
+    /// Open the store, loading every span, trace, datapoint and queue item
+    /// into memory (subject to the cache limits).
     pub async fn open(backend: B) -> Result<Self, StorageError> {
+        Self::open_with(backend, false).await
+    }
+
+    /// Open the store without loading spans, traces, datapoints or queue
+    /// items. They are cached as they are written or read, and lookups that
+    /// miss the cache go to the backend. Suited to databases too large to
+    /// hold in memory.
+    pub async fn open_lazy(backend: B) -> Result<Self, StorageError> {
+        Self::open_with(backend, true).await
+    }
+
+    async fn open_with(backend: B, lazy: bool) -> Result<Self, StorageError> {
+        let trashed_filter = TraceFilter {
+            trash: TrashScope::Trashed,
+            ..Default::default()
+        };
         let (
             spans,
             traces_list,
@@ -428,23 +467,34 @@ impl<B: StorageBackend> PersistentStore<B> {
             eres_list,
             cr_list,
             pc_list,
+            sk_list,
+            trashed,
         ) = tokio::try_join!(
-            backend.load_all_spans(),
-            backend.load_all_traces(),
+            unless_lazy(lazy, backend.load_all_spans()),
+            unless_lazy(lazy, backend.load_all_traces()),
             backend.load_all_files(),
             backend.load_all_datasets(),
-            backend.load_all_datapoints(),
-            backend.load_all_queue_items(),
+            unless_lazy(lazy, backend.load_all_datapoints()),
+            unless_lazy(lazy, backend.load_all_queue_items()),
             backend.load_all_eval_runs(),
             backend.load_all_eval_results(),
             backend.load_all_capture_rules(),
             backend.load_all_provider_connections(),
+            backend.load_all_span_kinds(),
+            backend.list_traces(&trashed_filter),
         )?;
+        let trashed: HashSet<TraceId> = trashed.into_iter().map(|t| t.id).collect();
 
-        let mut memory = SpanStore::new();
+        let shard_capacity = std::num::NonZero::new(max_spans().get().div_ceil(SPAN_SHARDS))
+            .unwrap_or(std::num::NonZero::new(1).unwrap());
+        let mut shards: Vec<SpanStore> = (0..SPAN_SHARDS)
+            .map(|_| SpanStore::with_capacity(shard_capacity))
+            .collect();
         let span_count = spans.len();
         for span in spans {
-            memory.insert(span);
+            if !trashed.contains(&span.trace_id()) {
+                shards[shard_of(span.trace_id())].insert(span);
+            }
         }
         if span_count > 0 {
             tracing::info!(count = span_count, "loaded spans from storage backend");
@@ -462,63 +512,422 @@ impl<B: StorageBackend> PersistentStore<B> {
         for d in dp_list {
             datapoints.put(d.id, d);
         }
-        let queue_items: HashMap<_, _> = qi_list.into_iter().map(|q| (q.id, q)).collect();
-        let eval_runs: HashMap<_, _> = er_list.into_iter().map(|r| (r.id, r)).collect();
-        let eval_results: HashMap<_, _> = eres_list.into_iter().map(|r| (r.id, r)).collect();
-        let capture_rules: HashMap<_, _> = cr_list.into_iter().map(|r| (r.id, r)).collect();
-        let provider_connections: HashMap<_, _> = pc_list.into_iter().map(|p| (p.id, p)).collect();
 
         Ok(Self {
-            memory,
-            trace_meta,
-            file_versions,
-            datasets,
-            datapoints,
-            queue_items,
-            eval_runs,
-            eval_results,
-            capture_rules,
-            provider_connections,
-            backend,
+            spans: shards.into_iter().map(RwLock::new).collect(),
+            trace_locks: (0..SPAN_SHARDS).map(|_| AsyncMutex::new(())).collect(),
+            trace_meta: RwLock::new(trace_meta),
+            trashed: RwLock::new(trashed),
+            file_versions: RwLock::new(file_versions),
+            unversioned: RwLock::new(HashSet::new()),
+            datasets: RwLock::new(datasets),
+            datapoints: RwLock::new(datapoints),
+            queue_items: qi_list.into_iter().map(|q| (q.id, q)).collect(),
+            eval_runs: er_list.into_iter().map(|r| (r.id, r)).collect(),
+            eval_results: eres_list.into_iter().map(|r| (r.id, r)).collect(),
+            capture_rules: cr_list.into_iter().map(|r| (r.id, r)).collect(),
+            provider_connections: pc_list.into_iter().map(|p| (p.id, p)).collect(),
+            span_kinds: sk_list.into_iter().map(|d| (d.name.clone(), d)).collect(),
+            backend: Arc::new(backend),
+            writer: None,
+            spill: None,
+            lazy,
+            names: None,
+            redactor: RwLock::new(None),
+            machine_id: None,
+            archive: None,
+            segment_cache: RwLock::new(LruCache::new(SEGMENT_CACHE_SIZE)),
+            segments_lock: AsyncMutex::new(()),
+            analytical: None,
+            payload_offload: None,
+            // Seeded with the open time so revisions aren't reused across
+            // restarts
+            revision: AtomicU64::new(chrono::Utc::now().timestamp_millis() as u64),
         })
     }
 
+    /// Buffer span and trace writes and persist them from a background
+    /// task. Reads that go to the backend, and deletes, flush first.
+    pub fn with_write_behind(mut self, config: WriteBehindConfig) -> Self
+    where
+        B: 'static,
+    {
+        self.writer = Some(WriteBehind::spawn(
+            self.backend.clone(),
+            config,
+            self.spill.clone(),
+        ));
+        self
+    }
+
+    /// Queue span and trace writes the backend fails on in `config.dir`
+    /// and replay them once it recovers. Call before `with_write_behind`,
+    /// whose flusher spills through the same queue.
+    pub fn with_spill(mut self, config: &SpillConfig) -> Result<Self, StorageError>
+    where
+        B: 'static,
+    {
+        let queue = Arc::new(SpillQueue::open(&config.dir)?);
+        spill::spawn_replayer(
+            Arc::downgrade(&queue),
+            self.backend.clone(),
+            config.replay_interval,
+        );
+        self.spill = Some(queue);
+        Ok(self)
+    }
+
+    /// `None` without a spill queue.
+    pub fn spill_status(&self) -> Option<SpillStatus> {
+        self.spill.as_ref().map(|q| q.status())
+    }
+
+    /// Normalize span names on ingest with `names`.
+    pub fn with_name_normalizer(mut self, names: Arc<NameNormalizer>) -> Self {
+        self.names = (!names.is_empty()).then_some(names);
+        self
+    }
+
+    /// Redact span payloads on ingest with `redactor`.
+    pub fn with_redactor(self, redactor: Option<Arc<Redactor>>) -> Self {
+        self.set_redactor(redactor);
+        self
+    }
+
+    pub fn set_redactor(&self, redactor: Option<Arc<Redactor>>) {
+        *write(&self.redactor) = redactor;
+    }
+
+    /// Record traces saved without a `machine_id` as coming from `id`.
+    pub fn with_machine_id(mut self, id: impl Into<String>) -> Self {
+        self.machine_id = Some(id.into());
+        self
+    }
+
+    /// Archive old spans to `segments` and read them back from there.
+    pub fn with_archive(mut self, segments: Arc<dyn SegmentStore>) -> Self {
+        self.archive = Some(segments);
+        self
+    }
+
+    /// Mirror span summaries to `analytical` as spans are written and
+    /// deleted. Archived spans keep their rows.
+    pub fn with_analytical(mut self, analytical: Arc<dyn AnalyticalStore>) -> Self {
+        self.analytical = Some(analytical);
+        self
+    }
+
+    /// Save payloads over `config.threshold_bytes` as separate blobs,
+    /// keeping a preview inline.
+    pub fn with_payload_offload(mut self, config: PayloadOffloadConfig) -> Self {
+        self.payload_offload = Some(config);
+        self
+    }
+
+    pub fn analytical(&self) -> Option<&dyn AnalyticalStore> {
+        self.analytical.as_deref()
+    }
+
+    async fn clear_analytical(&self) {
+        if let Some(analytical) = &self.analytical {
+            if let Err(e) = analytical.clear().await {
+                tracing::warn!("failed to clear analytics: {}", e);
+            }
+        }
+    }
+
+    /// Refill the analytical store from the backend, e.g. after enabling it
+    /// on an existing database. Returns the number of spans mirrored.
+    pub async fn rebuild_analytical(&self) -> Result<usize, StorageError> {
+        let analytical = self.analytical.as_ref().ok_or_else(|| {
+            StorageError::Configuration("no analytical store is configured".into())
+        })?;
+        self.flush_writes().await?;
+        analytical.clear().await?;
+        let mut mirrored = 0;
+        loop {
+            let page = self
+                .backend
+                .list_spans(&SpanFilter {
+                    limit: Some(REBUILD_PAGE_SIZE),
+                    offset: Some(mirrored),
+                    ..Default::default()
+                })
+                .await?;
+            let rows: Vec<SpanRow> = page.iter().map(SpanRow::from).collect();
+            analytical.upsert(&rows).await?;
+            mirrored += rows.len();
+            if rows.len() < REBUILD_PAGE_SIZE {
+                return Ok(mirrored);
+            }
+        }
+    }
+
     /// Get a reference to the underlying backend
     pub fn backend(&self) -> &B {
         &self.backend
     }
 
+    /// Write any data the store or backend has buffered. Call before shutdown.
+    pub async fn flush(&self) -> Result<(), StorageError> {
+        self.flush_writes().await?;
+        self.backend.flush().await
+    }
+
+    async fn flush_writes(&self) -> Result<(), StorageError> {
+        match &self.writer {
+            Some(writer) => writer.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Flush buffered writes and replay spilled ones, so a delete can't be
+    /// undone by a write that was queued before it.
+    async fn flush_before_delete(&self) -> Result<(), StorageError> {
+        self.flush_writes().await?;
+        match &self.spill {
+            Some(spill) if !spill.is_empty() => spill.replay(&*self.backend).await.map(drop),
+            _ => Ok(()),
+        }
+    }
+
+    async fn persist_span(&self, span: &Span) -> Result<(), StorageError> {
+        match &self.writer {
+            Some(writer) => writer.span(span.clone()).await?,
+            None => {
+                self.write_through(|| vec![Spilled::span(span)], self.backend.save_span(span))
+                    .await?
+            }
+        }
+        self.mirror(std::slice::from_ref(span)).await;
+        self.bump_revision();
+        Ok(())
+    }
+
+    /// Run a backend write, through the spill queue when there is one.
+    async fn write_through(
+        &self,
+        entries: impl FnOnce() -> Vec<Spilled>,
+        write: impl std::future::Future<Output = Result<(), StorageError>>,
+    ) -> Result<(), StorageError> {
+        match &self.spill {
+            Some(spill) => spill.write_or_spill(entries, write).await,
+            None => write.await,
+        }
+    }
+
+    /// Copy span summaries to the analytical store. Failures are logged
+    /// rather than failing the write; `rebuild_analytical` repairs drift.
+    async fn mirror(&self, spans: &[Span]) {
+        let Some(analytical) = &self.analytical else {
+            return;
+        };
+        let rows: Vec<SpanRow> = spans.iter().map(SpanRow::from).collect();
+        if let Err(e) = analytical.upsert(&rows).await {
+            tracing::warn!(count = rows.len(), "failed to mirror spans: {}", e);
+        }
+    }
+
+    async fn unmirror(&self, spans: &[SpanId], traces: &[TraceId]) {
+        let Some(analytical) = &self.analytical else {
+            return;
+        };
+        let result = match (spans.is_empty(), traces.is_empty()) {
+            (false, _) => analytical.delete(spans).await,
+            (true, false) => analytical.delete_traces(traces).await,
+            (true, true) => Ok(()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("failed to remove spans from analytics: {}", e);
+        }
+    }
+
+    async fn persist_trace(&self, trace: &Trace) -> Result<(), StorageError> {
+        match &self.writer {
+            Some(writer) => writer.trace(trace.clone()).await?,
+            None => {
+                self.write_through(
+                    || vec![Spilled::trace(trace)],
+                    self.backend.save_trace(trace),
+                )
+                .await?
+            }
+        }
+        self.bump_revision();
+        Ok(())
+    }
+
+    /// Changes whenever spans or traces are written or deleted, so equal
+    /// revisions mean unchanged span and trace data.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    fn bump_revision(&self) {
+        self.revision.fetch_add(1, Ordering::AcqRel);
+    }
+
     /// Get the backend type
     pub fn backend_type(&self) -> &'static str {
         self.backend.backend_type()
     }
 
+    /// Check that the backend is reachable.
+    pub async fn ping(&self) -> Result<(), StorageError> {
+        self.backend.ping().await
+    }
+
+    // --- Span cache helpers ---
+
+    fn shard(&self, trace_id: TraceId) -> &RwLock<SpanStore> {
+        &self.spans[shard_of(trace_id)]
+    }
+
+    async fn lock_trace(&self, trace_id: TraceId) -> AsyncMutexGuard<'_, ()> {
+        self.trace_locks[shard_of(trace_id)].lock().await
+    }
+
+    /// Take every trace lock, in shard order, for writes spanning many traces.
+    async fn lock_all_traces(&self) -> Vec<AsyncMutexGuard<'_, ()>> {
+        let mut guards = Vec::with_capacity(SPAN_SHARDS);
+        for lock in self.trace_locks.iter() {
+            guards.push(lock.lock().await);
+        }
+        guards
+    }
+
+    fn cached_span(&self, id: SpanId) -> Option<Span> {
+        self.spans
+            .iter()
+            .find_map(|shard| read(shard).peek(id).cloned())
+    }
+
+    fn cache_span(&self, span: Span) {
+        if self.is_trashed(span.trace_id()) {
+            return;
+        }
+        let mut shard = write(self.shard(span.trace_id()));
+        if shard.peek(span.id()).is_some() {
+            shard.replace(span);
+        } else {
+            shard.insert(span);
+        }
+    }
+
+    /// Cache a span read from the backend, unless a copy is already cached:
+    /// the cached one is at least as recent.
+    fn cache_loaded_span(&self, span: Span) -> bool {
+        if self.is_trashed(span.trace_id()) {
+            return false;
+        }
+        let mut shard = write(self.shard(span.trace_id()));
+        if shard.peek(span.id()).is_some() {
+            return false;
+        }
+        shard.insert(span);
+        true
+    }
+
     // --- Span methods ---
 
-    pub async fn insert(&mut self, span: Span) -> Result<SpanId, StorageError> {
-        self.backend.save_span(&span).await?;
-        let id = self.memory.insert(span);
+    pub async fn insert(&self, span: Span) -> Result<SpanId, StorageError> {
+        // Payloads are offloaded under the lock, so `gc_file_contents` never
+        // sees a blob whose span is still to be written
+        let _guard = self.lock_trace(span.trace_id()).await;
+        let span = self.offload_payloads(self.prepare(span)).await?;
+        let span = self.resolve_file_version(span).await?;
+        self.persist_span(&span).await?;
+        let previous = read(self.shard(span.trace_id())).peek(span.id()).cloned();
+        self.update_trace_stats(span.trace_id(), previous.as_ref(), Some(&span))
+            .await?;
+        let id = span.id();
+        self.cache_span(span);
         Ok(id)
     }
 
-    pub fn get(&mut self, id: SpanId) -> Option<&Span> {
-        self.memory.get(id)
+    /// Insert many spans with one backend write. Trace rollups are updated
+    /// once per trace rather than once per span.
+    pub async fn insert_batch(&self, spans: Vec<Span>) -> Result<Vec<Span>, StorageError> {
+        let shards: BTreeSet<usize> = spans.iter().map(|s| shard_of(s.trace_id())).collect();
+        let mut guards = Vec::with_capacity(shards.len());
+        for i in shards {
+            guards.push(self.trace_locks[i].lock().await);
+        }
+        let mut resolved = Vec::with_capacity(spans.len());
+        for span in spans {
+            let span = self.offload_payloads(self.prepare(span)).await?;
+            resolved.push(self.resolve_file_version(span).await?);
+        }
+        match &self.writer {
+            Some(writer) => {
+                for span in &resolved {
+                    writer.span(span.clone()).await?;
+                }
+            }
+            None => {
+                self.write_through(
+                    || resolved.iter().map(Spilled::span).collect(),
+                    self.backend.save_spans_batch(&resolved),
+                )
+                .await?
+            }
+        }
+        self.mirror(&resolved).await;
+        self.bump_revision();
+
+        let previous: Vec<Option<Span>> = resolved
+            .iter()
+            .map(|span| read(self.shard(span.trace_id())).peek(span.id()).cloned())
+            .collect();
+        let mut by_trace: HashMap<TraceId, Vec<SpanChange<'_>>> = HashMap::new();
+        for (span, prev) in resolved.iter().zip(&previous) {
+            by_trace
+                .entry(span.trace_id())
+                .or_default()
+                .push((prev.as_ref(), Some(span)));
+        }
+        for (trace_id, changes) in by_trace {
+            self.apply_trace_stats(trace_id, &changes).await?;
+        }
+        for span in &resolved {
+            self.cache_span(span.clone());
+        }
+        Ok(resolved)
+    }
+
+    pub fn get(&self, id: SpanId) -> Option<Span> {
+        self.cached_span(id)
     }
 
     /// Get a span by ID, falling back to the storage backend if not in memory.
     /// If found in the backend, the span is cached in memory for subsequent access.
-    pub async fn get_or_load(&mut self, id: SpanId) -> Option<&Span> {
-        if self.memory.get(id).is_some() {
-            return self.memory.get(id);
+    pub async fn get_or_load(&self, id: SpanId) -> Option<Span> {
+        if let Some(span) = self.cached_span(id) {
+            return Some(span);
         }
         // Try loading from backend
         match self.backend.get_span(id).await {
             Ok(Some(span)) => {
                 tracing::debug!(%id, "loaded span from backend (not in memory)");
-                self.memory.insert(span);
-                self.memory.get(id)
+                self.cache_loaded_span(span.clone());
+                Some(span)
+            }
+            Ok(None) => {
+                let archived = self
+                    .archived_spans(|s| s.may_contain(id), |s| s.id() == id)
+                    .await;
+                match archived {
+                    Ok(spans) => {
+                        let span = spans.into_iter().next()?;
+                        self.cache_loaded_span(span.clone());
+                        Some(span)
+                    }
+                    Err(e) => {
+                        tracing::warn!(%id, "failed to read span from archive: {}", e);
+                        None
+                    }
+                }
             }
-            Ok(None) => None,
             Err(e) => {
                 tracing::warn!(%id, "failed to load span from backend: {}", e);
                 None
@@ -526,47 +935,63 @@ impl<B: StorageBackend> PersistentStore<B> {
         }
     }
 
-    pub fn spans_for_trace(&self, trace_id: TraceId) -> &[SpanId] {
-        self.memory.spans_for_trace(trace_id)
+    pub fn spans_for_trace(&self, trace_id: TraceId) -> Vec<SpanId> {
+        read(self.shard(trace_id))
+            .spans_for_trace(trace_id)
+            .to_vec()
     }
 
     /// Get spans for a trace, falling back to the storage backend if none in memory.
     /// If found in the backend, spans are cached in memory for subsequent access.
-    pub async fn spans_for_trace_or_load(&mut self, trace_id: TraceId) -> &[SpanId] {
-        if !self.memory.spans_for_trace(trace_id).is_empty() {
-            return self.memory.spans_for_trace(trace_id);
+    /// A lazily opened store always asks the backend, since it may have cached
+    /// only some of the trace's spans.
+    pub async fn spans_for_trace_or_load(&self, trace_id: TraceId) -> Vec<SpanId> {
+        let cached = self.spans_for_trace(trace_id);
+        if !cached.is_empty() && !self.lazy {
+            return cached;
         }
         // Try loading from backend
         let filter = SpanFilter {
             trace_id: Some(trace_id),
             ..Default::default()
         };
-        match self.backend.list_spans(&filter).await {
-            Ok(spans) if !spans.is_empty() => {
-                tracing::debug!(%trace_id, count = spans.len(), "loaded trace spans from backend");
-                for span in spans {
-                    self.memory.insert(span);
-                }
-                self.memory.spans_for_trace(trace_id)
-            }
-            Ok(_) => &[],
+        let spans = match self.backend.list_spans(&filter).await {
+            Ok(spans) => spans,
             Err(e) => {
                 tracing::warn!(%trace_id, "failed to load trace spans from backend: {}", e);
-                &[]
+                return cached;
             }
+        };
+        // Part or all of the trace may have been archived
+        let archived = self
+            .archived_spans(
+                |s| s.trace_ids.contains(&trace_id),
+                |s| s.trace_id() == trace_id,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(%trace_id, "failed to read trace spans from archive: {}", e);
+                Vec::new()
+            });
+        if spans.is_empty() && archived.is_empty() {
+            return cached;
         }
+        tracing::debug!(%trace_id, count = spans.len(), "loaded trace spans from backend");
+        for span in archived.into_iter().chain(spans) {
+            self.cache_loaded_span(span);
+        }
+        self.spans_for_trace(trace_id)
     }
 
     /// Sync spans and traces from the storage backend into memory.
     /// Merges new data without removing existing in-memory state.
     /// Used to keep multi-instance deployments consistent.
-    pub async fn sync_from_backend(&mut self) {
+    pub async fn sync_from_backend(&self) {
         match self.backend.load_all_spans().await {
             Ok(spans) => {
                 let mut loaded = 0;
                 for span in spans {
-                    if self.memory.get(span.id()).is_none() {
-                        self.memory.insert(span);
+                    if self.cache_loaded_span(span) {
                         loaded += 1;
                     }
                 }
@@ -581,9 +1006,10 @@ impl<B: StorageBackend> PersistentStore<B> {
         match self.backend.load_all_traces().await {
             Ok(traces) => {
                 let mut loaded = 0;
+                let mut trace_meta = write(&self.trace_meta);
                 for trace in traces {
-                    if !self.trace_meta.contains(&trace.id) {
-                        self.trace_meta.put(trace.id, trace);
+                    if !trace_meta.contains(&trace.id) {
+                        trace_meta.put(trace.id, trace);
                         loaded += 1;
                     }
                 }
@@ -597,84 +1023,384 @@ impl<B: StorageBackend> PersistentStore<B> {
         }
     }
 
-    pub fn span_trace_ids(&self) -> impl Iterator<Item = &TraceId> {
-        self.memory.trace_ids()
+    pub fn span_trace_ids(&self) -> Vec<TraceId> {
+        self.spans
+            .iter()
+            .flat_map(|shard| read(shard).trace_ids().copied().collect::<Vec<_>>())
+            .collect()
     }
 
-    pub fn all_spans(&self) -> impl Iterator<Item = &Span> {
-        self.memory.all_spans()
+    pub fn all_spans(&self) -> Vec<Span> {
+        self.spans
+            .iter()
+            .flat_map(|shard| read(shard).all_spans().cloned().collect::<Vec<_>>())
+            .collect()
     }
 
+    /// Spans held in memory. For a lazily opened store that is only those
+    /// written or read since it was opened.
     pub fn span_count(&self) -> usize {
-        self.memory.span_count()
+        self.spans
+            .iter()
+            .map(|shard| read(shard).span_count())
+            .sum()
     }
 
     pub fn trace_count(&self) -> usize {
-        if self.trace_meta.is_empty() {
-            self.memory.trace_count()
+        let traces = read(&self.trace_meta).len();
+        if traces > 0 {
+            traces
         } else {
-            self.trace_meta.len()
+            self.spans
+                .iter()
+                .map(|shard| read(shard).trace_count())
+                .sum()
         }
     }
 
-    pub fn filter_spans(&self, filter: &SpanFilter) -> Vec<&Span> {
-        self.memory.filter_spans(filter)
+    pub fn filter_spans(&self, filter: &SpanFilter) -> Vec<Span> {
+        let shards: Vec<_> = self.spans.iter().map(read).collect();
+        page_spans(shards.iter().flat_map(|s| s.all_spans()), filter)
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
-    /// Complete a span (immutable transition: Running -> Completed).
-    /// Falls back to the storage backend if the span is not in memory
-    /// (e.g. when running multiple instances behind a load balancer).
-    pub async fn complete_span(
-        &mut self,
+    /// One page of spans matching `filter`, read from the storage backend so
+    /// that spans evicted from the in-memory cache are included. Filters
+    /// bounded by time or trace also read archived segments they overlap.
+    pub async fn query_spans(&self, filter: &SpanFilter) -> Result<Page<Span>, StorageError> {
+        self.flush_writes().await?;
+        filter.cursor_position()?;
+        let filter = &self.resolve_annotation_predicates(filter).await?;
+        let limit = filter.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        let page = SpanFilter {
+            limit: Some(limit + 1),
+            ..filter.clone()
+        };
+        let mut spans = self.backend.list_spans(&page).await?;
+        let historical =
+            filter.since.is_some() || filter.until.is_some() || filter.trace_id.is_some();
+        if historical {
+            let archived = self
+                .archived_spans(
+                    |s| {
+                        s.overlaps(filter.since, filter.until)
+                            && filter.trace_id.is_none_or(|t| s.trace_ids.contains(&t))
+                    },
+                    |s| filter.matches(s),
+                )
+                .await?;
+            if !archived.is_empty() {
+                // A span archived during a failed pass may also still be in
+                // the backend
+                let mut seen = HashSet::new();
+                spans.extend(archived);
+                spans.retain(|s| seen.insert(s.id()));
+                let merged = SpanFilter {
+                    offset: None,
+                    ..page
+                };
+                spans = page_spans(spans.iter(), &merged)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+            }
+        }
+        let spans = spans
+            .into_iter()
+            .map(|s| self.attach_file_version(s))
+            .collect();
+        let field = filter.sort_field();
+        let mut page = filter::into_page(spans, limit, field, |s| {
+            (filter::span_sort_value(s, field), s.id().to_string())
+        });
+        // Dropped after paging, so the cursor still follows the backend
+        page.items.retain(|s| !self.is_trashed(s.trace_id()));
+        Ok(page)
+    }
+
+    /// Call `visit` with every span matching `filter`, reading a page at a
+    /// time as `query_spans` does, so spans evicted from the cache and
+    /// archived spans are included. The filter's paging fields are ignored.
+    /// Returns the number of spans visited.
+    pub async fn scan_spans(
+        &self,
+        filter: &SpanFilter,
+        mut visit: impl FnMut(&Span),
+    ) -> Result<usize, StorageError> {
+        let mut page_filter = SpanFilter {
+            limit: Some(REBUILD_PAGE_SIZE),
+            offset: None,
+            cursor: None,
+            ..filter.clone()
+        };
+        let mut visited = 0;
+        loop {
+            let page = self.query_spans(&page_filter).await?;
+            page.items.iter().for_each(&mut visit);
+            visited += page.items.len();
+            match page.next_cursor {
+                Some(cursor) if page.has_more => page_filter.cursor = Some(cursor),
+                _ => return Ok(visited),
+            }
+        }
+    }
+
+    /// `filter` with its annotation predicates resolved to the spans they
+    /// select, which every backend can filter on.
+    async fn resolve_annotation_predicates(
+        &self,
+        filter: &SpanFilter,
+    ) -> Result<SpanFilter, StorageError> {
+        let mut resolved = filter.clone();
+        if !filter.has_annotation_predicates() {
+            return Ok(resolved);
+        }
+        let annotated: HashSet<SpanId> = self
+            .backend
+            .list_annotations(&filter.annotation_filter())
+            .await?
+            .into_iter()
+            .map(|a| a.span_id)
+            .collect();
+        resolved.span_ids = Some(match &filter.span_ids {
+            Some(ids) => ids.iter().filter(|id| annotated.contains(id)).copied().collect(),
+            None => annotated.into_iter().collect(),
+        });
+        Ok(resolved)
+    }
+
+    /// Every span in a trace, in start order, read from the backend and
+    /// the archive rather than the cache. With `skip_payloads` the backend
+    /// may leave `input` and `output` unread.
+    pub async fn trace_spans(
+        &self,
+        trace_id: TraceId,
+        skip_payloads: bool,
+    ) -> Result<Vec<Span>, StorageError> {
+        if self.is_trashed(trace_id) {
+            return Ok(Vec::new());
+        }
+        self.flush_writes().await?;
+        let filter = SpanFilter {
+            trace_id: Some(trace_id),
+            skip_payloads,
+            sort_by: Some("started_at".into()),
+            sort_order: Some("asc".into()),
+            ..Default::default()
+        };
+        let mut spans = self.backend.list_spans(&filter).await?;
+        let archived = self
+            .archived_spans(
+                |s| s.trace_ids.contains(&trace_id),
+                |s| s.trace_id() == trace_id,
+            )
+            .await?;
+        if !archived.is_empty() {
+            let mut seen: HashSet<SpanId> = spans.iter().map(Span::id).collect();
+            spans.extend(archived.into_iter().filter(|s| seen.insert(s.id())));
+            spans.sort_by_key(|s| (s.started_at(), s.id()));
+        }
+        Ok(spans
+            .into_iter()
+            .map(|s| self.attach_file_version(s))
+            .collect())
+    }
+
+    /// Spans ranked by semantic similarity to `query`, read from the backend.
+    pub async fn semantic_search(
+        &self,
+        query: &str,
+        filter: &SpanFilter,
+        limit: usize,
+    ) -> Result<Vec<ScoredSpan>, StorageError> {
+        self.flush_writes().await?;
+        let filter = self.resolve_annotation_predicates(filter).await?;
+        let mut results = self.backend.semantic_search(query, &filter, limit).await?;
+        results.retain(|r| !self.is_trashed(r.span.trace_id()));
+        Ok(results)
+    }
+
+    /// One page of traces matching `filter`, read from the storage backend.
+    pub async fn query_traces(&self, filter: &TraceFilter) -> Result<Page<Trace>, StorageError> {
+        self.flush_writes().await?;
+        filter.cursor_position()?;
+        let limit = filter.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        let traces = self
+            .backend
+            .list_traces(&TraceFilter {
+                limit: Some(limit + 1),
+                ..filter.clone()
+            })
+            .await?;
+        let field = filter.sort_field();
+        // Only the last item of a full page feeds the cursor, so the cost sum
+        // is computed for that trace alone.
+        let last_cost = match traces.get(limit.saturating_sub(1)) {
+            Some(last) if field == "cost" && traces.len() > limit => self
+                .backend
+                .list_spans(&SpanFilter {
+                    trace_id: Some(last.id),
+                    ..Default::default()
+                })
+                .await?
+                .iter()
+                .filter_map(|s| s.kind().cost())
+                .sum(),
+            _ => 0.0,
+        };
+        Ok(filter::into_page(traces, limit, field, |t| {
+            (filter::trace_sort_value(t, field, last_cost), t.id.to_string())
+        }))
+    }
+
+    /// Call `visit` with every trace matching `filter`, a page at a time as
+    /// `scan_spans` does.
+    pub async fn scan_traces(
+        &self,
+        filter: &TraceFilter,
+        mut visit: impl FnMut(&Trace),
+    ) -> Result<usize, StorageError> {
+        let mut page_filter = TraceFilter {
+            limit: Some(REBUILD_PAGE_SIZE),
+            offset: None,
+            cursor: None,
+            ..filter.clone()
+        };
+        let mut visited = 0;
+        loop {
+            let page = self.query_traces(&page_filter).await?;
+            page.items.iter().for_each(&mut visit);
+            visited += page.items.len();
+            match page.next_cursor {
+                Some(cursor) if page.has_more => page_filter.cursor = Some(cursor),
+                _ => return Ok(visited),
+            }
+        }
+    }
+
+    /// Sessions among the traces matching `filter`, read from the backend.
+    pub async fn list_sessions(&self, filter: &TraceFilter) -> Result<Vec<Session>, StorageError> {
+        self.flush_writes().await?;
+        self.backend.list_sessions(filter).await
+    }
+
+    /// Facet counts (model, provider, status, tag, error fingerprint) over the
+    /// cached traces matching `filter`. When `span_filter` is given, only
+    /// traces with at least one matching span are counted.
+    pub fn trace_facets(
+        &self,
+        filter: &TraceFilter,
+        span_filter: Option<&SpanFilter>,
+    ) -> TraceFacets {
+        let shards: Vec<_> = self.spans.iter().map(read).collect();
+        let trace_meta = read(&self.trace_meta);
+        facets_over(
+            trace_meta.iter().map(|(_, t)| t),
+            shards.iter().flat_map(|s| s.all_spans()),
+            filter,
+            span_filter,
+        )
+    }
+
+    /// `trace_facets`, computed from the storage backend for a lazily opened
+    /// store, whose cache holds only part of the data.
+    pub async fn query_trace_facets(
+        &self,
+        filter: &TraceFilter,
+        span_filter: Option<&SpanFilter>,
+    ) -> Result<TraceFacets, StorageError> {
+        if !self.lazy {
+            return Ok(self.trace_facets(filter, span_filter));
+        }
+        self.flush_writes().await?;
+        let traces = self
+            .backend
+            .list_traces(&TraceFilter {
+                limit: None,
+                offset: None,
+                cursor: None,
+                ..filter.clone()
+            })
+            .await?;
+        // A trace's spans start no earlier than the trace itself.
+        let spans = self
+            .backend
+            .list_spans(&SpanFilter {
+                since: filter.since,
+                ..Default::default()
+            })
+            .await?;
+        Ok(facets_over(
+            traces.iter(),
+            spans.iter(),
+            filter,
+            span_filter,
+        ))
+    }
+
+    /// Move a running span to a terminal state with `transition`. Returns
+    /// `None` if the span doesn't exist, is already terminal, or
+    /// `transition` declines. Falls back to the storage backend if the span
+    /// is not in memory (e.g. when running multiple instances behind a load
+    /// balancer).
+    async fn finish_span(
+        &self,
         id: SpanId,
-        output: Option<serde_json::Value>,
+        transition: impl FnOnce(Span) -> Option<Span>,
     ) -> Result<Option<Span>, StorageError> {
-        // Try memory first, then fall back to backend
-        let span = match self.memory.remove(id) {
+        let found = match self.cached_span(id) {
             Some(s) => s,
             None => match self.backend.get_span(id).await {
                 Ok(Some(s)) => {
-                    tracing::debug!(%id, "complete_span: loaded span from backend");
+                    tracing::debug!(%id, "finish_span: loaded span from backend");
                     s
                 }
                 _ => return Ok(None),
             },
         };
+        let trace_id = found.trace_id();
+        let _guard = self.lock_trace(trace_id).await;
+        // Another writer may have finished it while we waited for the lock
+        let span = read(self.shard(trace_id))
+            .peek(id)
+            .cloned()
+            .unwrap_or(found);
         if span.status().is_terminal() {
-            self.memory.replace(span);
             return Ok(None);
         }
-        let completed = span.complete(output);
-        self.backend.save_span(&completed).await?;
-        self.memory.replace(completed.clone());
-        Ok(Some(completed))
+        let Some(finished) = transition(span.clone()) else {
+            return Ok(None);
+        };
+        let finished = self.offload_payloads(self.redact(finished)).await?;
+        self.persist_span(&finished).await?;
+        self.update_trace_stats(trace_id, Some(&span), Some(&finished))
+            .await?;
+        self.cache_span(finished.clone());
+        Ok(Some(finished))
+    }
+
+    /// Complete a span (immutable transition: Running -> Completed).
+    pub async fn complete_span(
+        &self,
+        id: SpanId,
+        output: Option<serde_json::Value>,
+    ) -> Result<Option<Span>, StorageError> {
+        self.finish_span(id, |span| Some(span.complete(output)))
+            .await
     }
 
     /// Complete a span with an updated SpanKind (e.g. to populate token counts).
     /// Uses serde JSON round-trip to reconstruct with new kind, same pattern as SqliteBackend::deserialize_span.
-    /// Falls back to the storage backend if the span is not in memory.
     pub async fn complete_span_with_kind(
-        &mut self,
+        &self,
         id: SpanId,
         kind: SpanKind,
         output: Option<serde_json::Value>,
     ) -> Result<Option<Span>, StorageError> {
-        let span = match self.memory.remove(id) {
-            Some(s) => s,
-            None => match self.backend.get_span(id).await {
-                Ok(Some(s)) => {
-                    tracing::debug!(%id, "complete_span_with_kind: loaded span from backend");
-                    s
-                }
-                _ => return Ok(None),
-            },
-        };
-        if span.status().is_terminal() {
-            self.memory.replace(span);
-            return Ok(None);
-        }
-        // Serialize the span to JSON, patch in the new kind, then deserialize back
-        let completed: Option<Span> = (|| {
+        self.finish_span(id, |span| {
+            // Serialize the span to JSON, patch in the new kind, then deserialize back
             let mut json = serde_json::to_value(&span).ok()?;
             let kind_json = serde_json::to_value(&kind).ok()?;
             let obj = json.as_object_mut()?;
@@ -691,90 +1417,330 @@ impl<B: StorageBackend> PersistentStore<B> {
                 obj.insert("output".to_string(), out.clone());
             }
             serde_json::from_value(json).ok()
-        })();
-        let Some(completed) = completed else {
-            self.memory.replace(span);
-            return Ok(None);
-        };
-        self.backend.save_span(&completed).await?;
-        self.memory.replace(completed.clone());
-        Ok(Some(completed))
+        })
+        .await
     }
 
     /// Fail a span (immutable transition: Running -> Failed).
-    /// Falls back to the storage backend if the span is not in memory.
     pub async fn fail_span(
-        &mut self,
+        &self,
         id: SpanId,
         error: impl Into<String>,
     ) -> Result<Option<Span>, StorageError> {
-        let span = match self.memory.remove(id) {
-            Some(s) => s,
-            None => match self.backend.get_span(id).await {
-                Ok(Some(s)) => {
-                    tracing::debug!(%id, "fail_span: loaded span from backend");
-                    s
-                }
-                _ => return Ok(None),
-            },
-        };
-        if span.status().is_terminal() {
-            self.memory.replace(span);
-            return Ok(None);
-        }
-        let failed = span.fail(error);
-        self.backend.save_span(&failed).await?;
-        self.memory.replace(failed.clone());
-        Ok(Some(failed))
+        let error = error.into();
+        self.finish_span(id, |span| Some(span.fail(error))).await
     }
 
-    pub async fn delete_span(&mut self, id: SpanId) -> Result<bool, StorageError> {
+    /// Fail every span still running that started at or before `cutoff`,
+    /// returning the spans that were failed.
+    pub async fn fail_stale_spans(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+        error: &str,
+    ) -> Result<Vec<Span>, StorageError> {
+        self.flush_writes().await?;
+        let stale = self
+            .backend
+            .list_spans(&SpanFilter {
+                status: Some(SpanStatus::Running.as_str().to_string()),
+                until: Some(cutoff),
+                ..Default::default()
+            })
+            .await?;
+        let mut failed = Vec::new();
+        for span in stale {
+            if let Some(span) = self.fail_span(span.id(), error).await? {
+                failed.push(span);
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Traces started at or after `started_since` that completed (see
+    /// `Trace::completed_at`) after `from` and at or before `to`.
+    pub async fn traces_completed_between(
+        &self,
+        started_since: chrono::DateTime<chrono::Utc>,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<Trace>, StorageError> {
+        self.flush_writes().await?;
+        let traces = self
+            .backend
+            .list_traces(&TraceFilter {
+                since: Some(started_since),
+                until: Some(to),
+                ..Default::default()
+            })
+            .await?;
+        Ok(traces
+            .into_iter()
+            .filter(|t| t.completed_at().is_some_and(|at| at > from && at <= to))
+            .collect())
+    }
+
+    pub async fn delete_span(&self, id: SpanId) -> Result<bool, StorageError> {
+        // Lock the trace before flushing, so no write to it can be queued
+        // between the flush and the delete
+        let trace_id = match self.cached_span(id) {
+            Some(s) => Some(s.trace_id()),
+            None => self.backend.get_span(id).await?.map(|s| s.trace_id()),
+        };
+        let _guard = match trace_id {
+            Some(trace_id) => Some(self.lock_trace(trace_id).await),
+            None => None,
+        };
+        self.flush_before_delete().await?;
+        let existing = match self.cached_span(id) {
+            Some(s) => Some(s),
+            None => self.backend.get_span(id).await?,
+        };
         // Delete from backend first, then cache
         self.backend.delete_span(id).await?;
-        self.memory.delete_span(id);
+        self.unmirror(&[id], &[]).await;
+        if let Some(span) = &existing {
+            self.update_trace_stats(span.trace_id(), Some(span), None)
+                .await?;
+            write(self.shard(span.trace_id())).delete_span(id);
+        }
+        self.bump_revision();
         Ok(true)
     }
 
-    pub async fn delete_trace(&mut self, trace_id: TraceId) -> Result<usize, StorageError> {
+    pub async fn delete_trace(&self, trace_id: TraceId) -> Result<usize, StorageError> {
+        let _guard = self.lock_trace(trace_id).await;
+        self.flush_before_delete().await?;
         // Delete from backend first, then cache
         self.backend.delete_trace_spans(trace_id).await?;
         self.backend.delete_trace(trace_id).await?;
-        let count = self.memory.delete_trace(trace_id);
-        self.trace_meta.pop(&trace_id);
+        self.unmirror(&[], &[trace_id]).await;
+        self.delete_archived_traces(&HashSet::from([trace_id]))
+            .await?;
+        self.delete_trace_settings(&[trace_id]).await?;
+        let count = write(self.shard(trace_id)).delete_trace(trace_id);
+        write(&self.trace_meta).pop(&trace_id);
+        write(&self.trashed).remove(&trace_id);
+        self.bump_revision();
         Ok(count)
     }
 
-    /// Delete all spans started before the given cutoff time.
-    /// Returns the number of spans deleted.
-    pub async fn delete_spans_before(
-        &mut self,
+    /// Move a trace to the trash. Its spans stay in the backend, out of the
+    /// cache, analytics and query results, until it is restored or purged.
+    /// Returns false if there is no such trace outside the trash.
+    pub async fn trash_trace(&self, trace_id: TraceId) -> Result<bool, StorageError> {
+        let _guard = self.lock_trace(trace_id).await;
+        self.flush_before_delete().await?;
+        let mut trace = match self.backend.get_trace(trace_id).await? {
+            Some(trace) if trace.deleted_at.is_none() => trace,
+            _ => return Ok(false),
+        };
+        trace.deleted_at = Some(chrono::Utc::now());
+        self.backend.save_trace(&trace).await?;
+        write(&self.trashed).insert(trace_id);
+        self.unmirror(&[], &[trace_id]).await;
+        write(self.shard(trace_id)).delete_trace(trace_id);
+        write(&self.trace_meta).pop(&trace_id);
+        self.bump_revision();
+        Ok(true)
+    }
+
+    /// Take a trace out of the trash. Returns `None` if it isn't there.
+    pub async fn restore_trace(&self, trace_id: TraceId) -> Result<Option<Trace>, StorageError> {
+        let _guard = self.lock_trace(trace_id).await;
+        let mut trace = match self.backend.get_trace(trace_id).await? {
+            Some(trace) if trace.deleted_at.is_some() => trace,
+            _ => return Ok(None),
+        };
+        trace.deleted_at = None;
+        self.backend.save_trace(&trace).await?;
+        write(&self.trashed).remove(&trace_id);
+        let spans = self
+            .backend
+            .list_spans(&SpanFilter {
+                trace_id: Some(trace_id),
+                ..Default::default()
+            })
+            .await?;
+        self.mirror(&spans).await;
+        if !self.lazy {
+            for span in spans {
+                self.cache_loaded_span(span);
+            }
+        }
+        write(&self.trace_meta).put(trace_id, trace.clone());
+        self.bump_revision();
+        Ok(Some(trace))
+    }
+
+    pub fn is_trashed(&self, trace_id: TraceId) -> bool {
+        read(&self.trashed).contains(&trace_id)
+    }
+
+    /// Traces in the trash, most recently started first.
+    pub async fn trashed_traces(&self) -> Result<Vec<Trace>, StorageError> {
+        self.flush_writes().await?;
+        self.backend
+            .list_traces(&TraceFilter {
+                trash: TrashScope::Trashed,
+                ..Default::default()
+            })
+            .await
+    }
+
+    /// Permanently delete traces and datasets trashed before `cutoff`.
+    pub async fn purge_trash(
+        &self,
         cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<PurgeReport, StorageError> {
+        let mut report = PurgeReport::default();
+        for trace in self.trashed_traces().await? {
+            if trace.deleted_at.is_some_and(|at| at < cutoff) {
+                self.delete_trace(trace.id).await?;
+                report.traces += 1;
+            }
+        }
+        for dataset in self.trashed_datasets() {
+            if dataset.deleted_at.is_some_and(|at| at < cutoff) {
+                self.delete_dataset(dataset.id).await?;
+                report.datasets += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Delete every span matching `filter`, ignoring its sort and paging
+    /// fields. Returns the number of spans deleted from the backend.
+    pub async fn delete_spans_by_filter(&self, filter: &SpanFilter) -> Result<usize, StorageError> {
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        let filter = self.resolve_annotation_predicates(filter).await?;
+        self.delete_matching_spans(&filter).await
+    }
+
+    /// `delete_spans_by_filter` with every trace lock already held.
+    async fn delete_matching_spans(&self, filter: &SpanFilter) -> Result<usize, StorageError> {
+        let mirrored: Vec<SpanId> = match &self.analytical {
+            Some(_) => {
+                let all = SpanFilter {
+                    limit: None,
+                    offset: None,
+                    cursor: None,
+                    ..filter.clone()
+                };
+                let spans = self.backend.list_spans(&all).await?;
+                spans.iter().map(Span::id).collect()
+            }
+            None => Vec::new(),
+        };
+        // Delete from backend first, then cache
+        let count = self.backend.delete_spans_by_filter(filter).await?;
+        self.unmirror(&mirrored, &[]).await;
+        let cached: Vec<Span> = self
+            .spans
+            .iter()
+            .flat_map(|shard| {
+                read(shard)
+                    .all_spans()
+                    .filter(|s| filter.matches(s))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        for span in cached {
+            self.update_trace_stats(span.trace_id(), Some(&span), None)
+                .await?;
+            write(self.shard(span.trace_id())).delete_span(span.id());
+        }
+        self.bump_revision();
+        Ok(count)
+    }
+
+    /// Delete every trace matching `filter` along with its spans. Returns the
+    /// number of traces deleted from the backend.
+    pub async fn delete_traces_by_filter(
+        &self,
+        filter: &TraceFilter,
     ) -> Result<usize, StorageError> {
-        let expired_ids: Vec<SpanId> = self
-            .memory
-            .all_spans()
-            .filter(|s| s.started_at() < cutoff)
-            .map(|s| s.id())
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        self.delete_matching_traces(filter).await
+    }
+
+    /// `delete_traces_by_filter` with every trace lock already held.
+    async fn delete_matching_traces(&self, filter: &TraceFilter) -> Result<usize, StorageError> {
+        let all = TraceFilter {
+            limit: None,
+            offset: None,
+            cursor: None,
+            ..filter.clone()
+        };
+        let matching: Vec<TraceId> = self
+            .backend
+            .list_traces(&all)
+            .await?
+            .iter()
+            .map(|t| t.id)
             .collect();
+        let count = self.backend.delete_traces_by_filter(filter).await?;
+        self.unmirror(&[], &matching).await;
+        self.delete_trace_settings(&matching).await?;
+        self.delete_archived_traces(&matching.into_iter().collect())
+            .await?;
+        let mut trace_meta = write(&self.trace_meta);
+        let cached: Vec<TraceId> = trace_meta
+            .iter()
+            .filter(|(_, t)| filter.matches(t))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in cached {
+            write(self.shard(id)).delete_trace(id);
+            trace_meta.pop(&id);
+        }
+        drop(trace_meta);
+        self.bump_revision();
+        Ok(count)
+    }
 
-        let count = expired_ids.len();
-        for id in &expired_ids {
-            self.backend.delete_span(*id).await?;
-            self.memory.delete_span(*id);
+    /// Delete the settings kept for traces `ids`; see [`TRACE_SETTINGS`].
+    async fn delete_trace_settings(&self, ids: &[TraceId]) -> Result<(), StorageError> {
+        if ids.is_empty() {
+            return Ok(());
         }
+        let keys: Vec<String> = ids
+            .iter()
+            .flat_map(|&id| TRACE_SETTINGS.iter().map(move |name| trace_setting_key(name, id)))
+            .collect();
+        self.backend.delete_settings(&keys).await
+    }
+
+    /// Delete all spans started at or before the given cutoff time.
+    /// Returns the number of spans deleted.
+    pub async fn delete_spans_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize, StorageError> {
+        let filter = SpanFilter {
+            until: Some(cutoff),
+            ..Default::default()
+        };
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        let count = self.delete_matching_spans(&filter).await?;
 
-        // Also clean up traces that now have zero spans
-        let empty_traces: Vec<TraceId> = self
-            .trace_meta
+        // Also clean up traces that now have zero spans. A lazy store may not
+        // have cached a trace's spans, so it also requires an empty rollup.
+        let empty_traces: Vec<TraceId> = read(&self.trace_meta)
             .iter()
-            .map(|(tid, _)| tid)
-            .filter(|tid| self.memory.spans_for_trace(**tid).is_empty())
-            .cloned()
+            .filter(|(_, t)| !self.lazy || t.stats.span_count == 0)
+            .map(|(tid, _)| *tid)
+            .filter(|tid| read(self.shard(*tid)).spans_for_trace(*tid).is_empty())
             .collect();
-        for tid in empty_traces {
+        for &tid in &empty_traces {
             self.backend.delete_trace(tid).await?;
-            self.trace_meta.pop(&tid);
+            write(&self.trace_meta).pop(&tid);
         }
+        self.delete_trace_settings(&empty_traces).await?;
+        self.bump_revision();
 
         if count > 0 {
             tracing::info!(count, "retention cleanup: deleted expired spans");
@@ -782,57 +1748,721 @@ impl<B: StorageBackend> PersistentStore<B> {
         Ok(count)
     }
 
-    pub async fn clear(&mut self) -> Result<(), StorageError> {
-        // Clear backend first, then cache
-        self.backend.clear_spans().await?;
-        self.memory.clear();
-        self.trace_meta.clear();
-        self.file_versions.clear();
-        self.datasets.clear();
-        self.datapoints.clear();
-        self.queue_items.clear();
-        self.eval_runs.clear();
-        self.eval_results.clear();
-        self.capture_rules.clear();
-        // Note: provider_connections are NOT cleared on "clear all data" — they are org settings, not trace data.
-        Ok(())
+    /// Retention pruning. Deletes traces started at or before `cutoff` (with
+    /// all of their spans), any other spans started at or before it, and file
+    /// version records created at or before it. With `dry_run`, only counts
+    /// what would be deleted.
+    pub async fn prune_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+        dry_run: bool,
+    ) -> Result<PruneReport, StorageError> {
+        let trace_filter = TraceFilter {
+            until: Some(cutoff),
+            ..Default::default()
+        };
+        let span_filter = SpanFilter {
+            until: Some(cutoff),
+            ..Default::default()
+        };
+        let mut report = PruneReport {
+            cutoff,
+            dry_run,
+            traces: 0,
+            spans: 0,
+            file_versions: 0,
+            segments: 0,
+        };
+        if dry_run {
+            self.flush_writes().await?;
+            report.traces = self.backend.list_traces(&trace_filter).await?.len();
+            report.spans = self.backend.list_spans(&span_filter).await?.len();
+            report.file_versions = read(&self.file_versions)
+                .iter()
+                .filter(|fv| fv.created_at <= cutoff)
+                .count();
+            return Ok(report);
+        }
+
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        report.traces = self.delete_matching_traces(&trace_filter).await?;
+        report.spans = self.delete_matching_spans(&span_filter).await?;
+        report.file_versions = self.backend.delete_file_versions_before(cutoff).await?;
+        write(&self.file_versions).retain(|fv| fv.created_at > cutoff);
+        report.segments = self.delete_segments_before(cutoff).await?;
+        Ok(report)
+    }
+
+    // --- Archive ---
+
+    /// Pointers to the archived segments, oldest first.
+    pub async fn archive_segments(&self) -> Result<Vec<SegmentInfo>, StorageError> {
+        match self.backend.get_setting(SEGMENTS_SETTING).await? {
+            Some(value) => serde_json::from_value(value)
+                .map_err(|e| StorageError::Serialization(e.to_string())),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn save_archive_segments(&self, segments: &[SegmentInfo]) -> Result<(), StorageError> {
+        let value = serde_json::to_value(segments)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        self.backend.save_setting(SEGMENTS_SETTING, &value).await
+    }
+
+    /// Move spans started at or before `cutoff` into compressed segments
+    /// and delete them from the backend and the cache. Trace rollups are
+    /// left as they are, since the spans still exist. With `dry_run`, only
+    /// counts what would be moved.
+    pub async fn archive_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+        dry_run: bool,
+    ) -> Result<ArchiveReport, StorageError> {
+        let segments = self
+            .archive
+            .clone()
+            .ok_or_else(|| StorageError::Configuration("no archive is configured".into()))?;
+        let filter = SpanFilter {
+            until: Some(cutoff),
+            ..Default::default()
+        };
+        let mut report = ArchiveReport {
+            cutoff,
+            dry_run,
+            spans: 0,
+            segments: Vec::new(),
+        };
+
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        let mut spans = self.backend.list_spans(&filter).await?;
+        let listed = spans.len();
+        // Spans still running stay put until they finish or go stale
+        spans.retain(|s| s.status().is_terminal());
+        report.spans = spans.len();
+        if dry_run || spans.is_empty() {
+            return Ok(report);
+        }
+        spans.sort_by_key(|s| (s.started_at(), s.id()));
+
+        // Segments are written before their pointers, and both before any
+        // span is deleted, so a failure part way leaves spans readable
+        for chunk in spans.chunks(MAX_SEGMENT_SPANS) {
+            let (first, last) = (&chunk[0], &chunk[chunk.len() - 1]);
+            let data = archive::encode(chunk)?;
+            let trace_ids: BTreeSet<TraceId> = chunk.iter().map(Span::trace_id).collect();
+            let info = SegmentInfo {
+                key: archive::segment_key(first.started_at(), first.id()),
+                from: first.started_at(),
+                to: last.started_at(),
+                span_count: chunk.len(),
+                bytes: data.len(),
+                trace_ids: trace_ids.into_iter().collect(),
+                created_at: chrono::Utc::now(),
+            };
+            segments.put(&info.key, data).await?;
+            report.segments.push(info);
+        }
+        let mut saved = self.archive_segments().await?;
+        saved.extend(report.segments.iter().cloned());
+        self.save_archive_segments(&saved).await?;
+
+        if spans.len() == listed {
+            self.backend.delete_spans_by_filter(&filter).await?;
+        } else {
+            for span in &spans {
+                self.backend.delete_span(span.id()).await?;
+            }
+        }
+        for span in &spans {
+            write(self.shard(span.trace_id())).delete_span(span.id());
+        }
+        self.bump_revision();
+        tracing::info!(
+            spans = report.spans,
+            segments = report.segments.len(),
+            "archived spans"
+        );
+        Ok(report)
+    }
+
+    /// Drop segments whose every span started at or before `cutoff`.
+    async fn delete_segments_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize, StorageError> {
+        let Some(store) = &self.archive else {
+            return Ok(0);
+        };
+        let (expired, kept): (Vec<_>, Vec<_>) = self
+            .archive_segments()
+            .await?
+            .into_iter()
+            .partition(|s| s.to <= cutoff);
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        self.save_archive_segments(&kept).await?;
+        for segment in &expired {
+            store.delete(&segment.key).await?;
+            write(&self.segment_cache).pop(&segment.key);
+        }
+        Ok(expired.len())
+    }
+
+    /// Remove the archived spans of `traces`, rewriting the segments that
+    /// hold them and dropping the ones left empty. Returns the number of
+    /// spans removed.
+    async fn delete_archived_traces(
+        &self,
+        traces: &HashSet<TraceId>,
+    ) -> Result<usize, StorageError> {
+        let Some(store) = &self.archive else {
+            return Ok(0);
+        };
+        if traces.is_empty() {
+            return Ok(0);
+        }
+        let _segments = self.segments_lock.lock().await;
+        let segments = self.archive_segments().await?;
+        let holds = |s: &SegmentInfo| s.trace_ids.iter().any(|t| traces.contains(t));
+        if !segments.iter().any(holds) {
+            return Ok(0);
+        }
+
+        // Rewritten segments keep their keys and are written before their
+        // pointers, and emptied ones are deleted after, so a failure part
+        // way never points at a missing segment
+        let mut kept = Vec::with_capacity(segments.len());
+        let mut emptied = Vec::new();
+        let mut removed = 0;
+        for segment in segments {
+            if !holds(&segment) {
+                kept.push(segment);
+                continue;
+            }
+            let spans: Vec<Span> = self
+                .read_segment(&segment)
+                .await?
+                .iter()
+                .filter(|s| !traces.contains(&s.trace_id()))
+                .cloned()
+                .collect();
+            removed += segment.span_count - spans.len();
+            let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+                emptied.push(segment.key);
+                continue;
+            };
+            let data = archive::encode(&spans)?;
+            let trace_ids: BTreeSet<TraceId> = spans.iter().map(Span::trace_id).collect();
+            let info = SegmentInfo {
+                from: first.started_at(),
+                to: last.started_at(),
+                span_count: spans.len(),
+                bytes: data.len(),
+                trace_ids: trace_ids.into_iter().collect(),
+                ..segment
+            };
+            store.put(&info.key, data).await?;
+            write(&self.segment_cache).put(info.key.clone(), Arc::new(spans));
+            kept.push(info);
+        }
+        self.save_archive_segments(&kept).await?;
+        for key in &emptied {
+            store.delete(key).await?;
+            write(&self.segment_cache).pop(key);
+        }
+        Ok(removed)
+    }
+
+    /// Delete every archived segment. Returns the number of spans removed.
+    async fn delete_archive(&self) -> Result<usize, StorageError> {
+        let Some(store) = &self.archive else {
+            return Ok(0);
+        };
+        let _segments = self.segments_lock.lock().await;
+        let segments = self.archive_segments().await?;
+        if segments.is_empty() {
+            return Ok(0);
+        }
+        self.save_archive_segments(&[]).await?;
+        for segment in &segments {
+            store.delete(&segment.key).await?;
+        }
+        write(&self.segment_cache).clear();
+        Ok(segments.iter().map(|s| s.span_count).sum())
+    }
+
+    async fn read_segment(&self, segment: &SegmentInfo) -> Result<Arc<Vec<Span>>, StorageError> {
+        if let Some(spans) = write(&self.segment_cache).get(&segment.key) {
+            return Ok(spans.clone());
+        }
+        let store = self
+            .archive
+            .as_ref()
+            .ok_or_else(|| StorageError::Configuration("no archive is configured".into()))?;
+        let data = store.get(&segment.key).await?.ok_or_else(|| {
+            StorageError::Backend(format!("archive segment {} is missing", segment.key))
+        })?;
+        let spans = Arc::new(archive::decode(&data)?);
+        write(&self.segment_cache).put(segment.key.clone(), spans.clone());
+        Ok(spans)
+    }
+
+    /// Archived spans in the segments `select` picks that match `keep`.
+    async fn archived_spans(
+        &self,
+        select: impl Fn(&SegmentInfo) -> bool,
+        keep: impl Fn(&Span) -> bool,
+    ) -> Result<Vec<Span>, StorageError> {
+        if self.archive.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut found = Vec::new();
+        for segment in self.archive_segments().await?.iter().filter(|s| select(s)) {
+            let spans = self.read_segment(segment).await?;
+            found.extend(spans.iter().filter(|s| keep(s)).cloned());
+        }
+        Ok(found)
+    }
+
+    /// Count what `clear` would remove without deleting anything.
+    pub async fn clear_preview(&self, scope: ClearScope) -> Result<ClearReport, StorageError> {
+        self.flush_writes().await?;
+        let all_spans = SpanFilter::default();
+        Ok(match scope {
+            ClearScope::Spans => ClearReport {
+                traces: 0,
+                spans: self.backend.list_spans(&all_spans).await?.len(),
+            },
+            ClearScope::TracesBefore(cutoff) => {
+                let traces = self
+                    .backend
+                    .list_traces(&TraceFilter {
+                        until: Some(cutoff),
+                        trash: TrashScope::All,
+                        ..Default::default()
+                    })
+                    .await?;
+                ClearReport {
+                    traces: traces.len(),
+                    spans: traces.iter().map(|t| t.stats.span_count as usize).sum(),
+                }
+            }
+            ClearScope::All => ClearReport {
+                traces: self
+                    .backend
+                    .list_traces(&TraceFilter {
+                        trash: TrashScope::All,
+                        ..Default::default()
+                    })
+                    .await?
+                    .len(),
+                spans: self.backend.list_spans(&all_spans).await?.len(),
+            },
+        })
+    }
+
+    /// Delete trace data in `scope` from the backend, the archive and the
+    /// cache alike, along with payloads offloaded from the removed spans and
+    /// the settings of removed traces. Datasets, files, eval data, and org
+    /// settings are never touched.
+    pub async fn clear(&self, scope: ClearScope) -> Result<ClearReport, StorageError> {
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        let report = self.clear_locked(scope).await;
+        self.bump_revision();
+        // Payloads can hold what the clear was meant to remove
+        self.delete_orphaned_payloads().await?;
+        report
+    }
+
+    async fn clear_locked(&self, scope: ClearScope) -> Result<ClearReport, StorageError> {
+        match scope {
+            ClearScope::Spans => {
+                let spans = self
+                    .backend
+                    .delete_spans_by_filter(&SpanFilter::default())
+                    .await?;
+                self.clear_analytical().await;
+                self.delete_archive().await?;
+                let every_trace = TraceFilter {
+                    trash: TrashScope::All,
+                    ..Default::default()
+                };
+                for mut trace in self.backend.list_traces(&every_trace).await? {
+                    trace.stats = TraceStats::default();
+                    self.backend.save_trace(&trace).await?;
+                    let mut trace_meta = write(&self.trace_meta);
+                    if trace_meta.contains(&trace.id) {
+                        trace_meta.put(trace.id, trace);
+                    }
+                }
+                for shard in self.spans.iter() {
+                    write(shard).clear();
+                }
+                Ok(ClearReport { traces: 0, spans })
+            }
+            ClearScope::TracesBefore(cutoff) => {
+                let filter = TraceFilter {
+                    until: Some(cutoff),
+                    trash: TrashScope::All,
+                    ..Default::default()
+                };
+                let matching = self.backend.list_traces(&filter).await?;
+                let spans = matching.iter().map(|t| t.stats.span_count as usize).sum();
+                let traces = self.delete_matching_traces(&filter).await?;
+                let mut trashed = write(&self.trashed);
+                for trace in &matching {
+                    trashed.remove(&trace.id);
+                }
+                Ok(ClearReport { traces, spans })
+            }
+            ClearScope::All => {
+                let spans = self
+                    .backend
+                    .delete_spans_by_filter(&SpanFilter::default())
+                    .await?;
+                self.clear_analytical().await;
+                self.delete_archive().await?;
+                let every_trace = TraceFilter {
+                    trash: TrashScope::All,
+                    ..Default::default()
+                };
+                let ids: Vec<TraceId> = self
+                    .backend
+                    .list_traces(&every_trace)
+                    .await?
+                    .iter()
+                    .map(|t| t.id)
+                    .collect();
+                let traces = self.backend.delete_traces_by_filter(&every_trace).await?;
+                self.delete_trace_settings(&ids).await?;
+                for shard in self.spans.iter() {
+                    write(shard).clear();
+                }
+                write(&self.trace_meta).clear();
+                write(&self.trashed).clear();
+                Ok(ClearReport { traces, spans })
+            }
+        }
     }
 
     // --- Trace methods ---
 
-    pub async fn save_trace(&mut self, trace: Trace) -> Result<(), StorageError> {
-        self.backend.save_trace(&trace).await?;
-        self.trace_meta.put(trace.id, trace);
+    /// Save trace metadata. `stats` and `deleted_at` on the incoming trace
+    /// are ignored: they are carried over from the stored trace, or for a
+    /// new one computed from cached spans and left unset.
+    pub async fn save_trace(&self, mut trace: Trace) -> Result<(), StorageError> {
+        if trace.machine_id.is_none() {
+            trace.machine_id = self.machine_id.clone();
+        }
+        let _guard = self.lock_trace(trace.id).await;
+        let cached = read(&self.trace_meta)
+            .peek(&trace.id)
+            .map(|t| t.stats.clone());
+        (trace.stats, trace.deleted_at) = match cached {
+            Some(stats) => (stats, None),
+            None => match self.backend.get_trace(trace.id).await? {
+                // Saving doesn't take a trace out of the trash
+                Some(existing) => (existing.stats, existing.deleted_at),
+                None => {
+                    let shard = read(self.shard(trace.id));
+                    let ids: HashSet<SpanId> =
+                        shard.spans_for_trace(trace.id).iter().copied().collect();
+                    let stats =
+                        TraceStats::from_spans(ids.into_iter().filter_map(|id| shard.peek(id)));
+                    (stats, None)
+                }
+            },
+        };
+        self.persist_trace(&trace).await?;
+        self.cache_trace(trace);
         Ok(())
     }
 
-    pub fn get_trace(&mut self, id: TraceId) -> Option<&Trace> {
-        self.trace_meta.get(&id)
+    /// Cache a trace unless it is in the trash.
+    fn cache_trace(&self, trace: Trace) {
+        if trace.deleted_at.is_none() {
+            write(&self.trace_meta).put(trace.id, trace);
+        }
+    }
+
+    /// Swap `previous` for `current` in the trace's rollup and persist it.
+    /// Spans whose trace hasn't been saved yet are picked up by `save_trace`.
+    async fn update_trace_stats(
+        &self,
+        trace_id: TraceId,
+        previous: Option<&Span>,
+        current: Option<&Span>,
+    ) -> Result<(), StorageError> {
+        self.apply_trace_stats(trace_id, &[(previous, current)])
+            .await
+    }
+
+    /// Apply a set of `(previous, current)` span changes to one trace's
+    /// rollup with a single write. The caller holds the trace's lock.
+    async fn apply_trace_stats(
+        &self,
+        trace_id: TraceId,
+        changes: &[SpanChange<'_>],
+    ) -> Result<(), StorageError> {
+        let cached = read(&self.trace_meta).peek(&trace_id).cloned();
+        let mut trace = match cached {
+            Some(t) => t,
+            None => match self.backend.get_trace(trace_id).await? {
+                Some(t) => t,
+                None => return Ok(()),
+            },
+        };
+        for (previous, current) in changes {
+            if let Some(span) = previous {
+                trace.stats.remove(span);
+            }
+            if let Some(span) = current {
+                trace.stats.add(span);
+            }
+        }
+        self.persist_trace(&trace).await?;
+        self.cache_trace(trace);
+        Ok(())
     }
 
-    pub fn all_traces(&self) -> impl Iterator<Item = &Trace> {
-        self.trace_meta.iter().map(|(_, t)| t)
+    pub fn get_trace(&self, id: TraceId) -> Option<Trace> {
+        read(&self.trace_meta).peek(&id).cloned()
+    }
+
+    /// Get a trace, falling back to the storage backend if not in memory.
+    pub async fn get_trace_or_load(&self, id: TraceId) -> Option<Trace> {
+        if let Some(trace) = self.get_trace(id) {
+            return Some(trace);
+        }
+        match self.backend.get_trace(id).await {
+            Ok(Some(trace)) if trace.deleted_at.is_none() => {
+                write(&self.trace_meta).get_or_insert(id, || trace.clone());
+                Some(trace)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!(%id, "failed to load trace from backend: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn all_traces(&self) -> Vec<Trace> {
+        read(&self.trace_meta)
+            .iter()
+            .map(|(_, t)| t.clone())
+            .collect()
+    }
+
+    // --- Tag methods ---
+
+    /// Add `add` to a trace's tags and drop `remove` from them. Returns the
+    /// trace as saved, or `None` if there is no such trace outside the
+    /// trash.
+    pub async fn retag_trace(
+        &self,
+        trace_id: TraceId,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<Option<Trace>, StorageError> {
+        let _guard = self.lock_trace(trace_id).await;
+        let cached = read(&self.trace_meta).peek(&trace_id).cloned();
+        let mut trace = match cached {
+            Some(t) => t,
+            None => match self.backend.get_trace(trace_id).await? {
+                Some(t) if t.deleted_at.is_none() => t,
+                _ => return Ok(None),
+            },
+        };
+        if retag(&mut trace.tags, add, remove) {
+            self.persist_trace(&trace).await?;
+            self.cache_trace(trace.clone());
+        }
+        Ok(Some(trace))
+    }
+
+    /// How many traces outside the trash carry each tag, most used first.
+    pub async fn tag_counts(&self) -> Result<Vec<FacetCount>, StorageError> {
+        if !self.lazy {
+            return Ok(facets::count_tags(read(&self.trace_meta).iter().map(|(_, t)| t)));
+        }
+        self.flush_writes().await?;
+        let traces = self.backend.list_traces(&TraceFilter::default()).await?;
+        Ok(facets::count_tags(&traces))
+    }
+
+    /// Rename tag `from` to `to` on every trace carrying it, trashed ones
+    /// included. A trace that already has `to` just loses `from`. Returns
+    /// the number of traces rewritten.
+    pub async fn rename_tag(&self, from: &str, to: &str) -> Result<usize, StorageError> {
+        self.flush_writes().await?;
+        let tagged = self
+            .backend
+            .list_traces(&TraceFilter {
+                tags: Some(vec![from.to_string()]),
+                trash: TrashScope::All,
+                ..Default::default()
+            })
+            .await?;
+        let (add, remove) = ([to.to_string()], [from.to_string()]);
+        let mut renamed = 0;
+        for trace in tagged {
+            let _guard = self.lock_trace(trace.id).await;
+            // Re-read under the lock; stats may have moved on since the
+            // listing.
+            let cached = read(&self.trace_meta).peek(&trace.id).cloned();
+            let mut trace = match cached {
+                Some(t) => t,
+                None => match self.backend.get_trace(trace.id).await? {
+                    Some(t) => t,
+                    None => continue,
+                },
+            };
+            if retag(&mut trace.tags, &add, &remove) {
+                self.persist_trace(&trace).await?;
+                self.cache_trace(trace);
+                renamed += 1;
+            }
+        }
+        Ok(renamed)
     }
 
     // --- File methods ---
 
-    pub async fn save_file_version(&mut self, version: FileVersion) -> Result<(), StorageError> {
+    /// Record a file version. Recording the same path and hash again only
+    /// rewrites it in the backend.
+    pub async fn save_file_version(&self, version: FileVersion) -> Result<(), StorageError> {
         self.backend.save_file_version(&version).await?;
-        self.file_versions.push(version);
+        let hash = version.hash.clone();
+        {
+            let mut file_versions = write(&self.file_versions);
+            if !file_versions
+                .iter()
+                .any(|fv| fv.hash == version.hash && fv.path == version.path)
+            {
+                file_versions.push(version);
+            }
+        }
+        // Only once the version is cached, for `gc_file_contents`
+        write(&self.unversioned).remove(&hash);
         Ok(())
     }
 
     pub async fn save_file_content(&self, hash: &str, content: &[u8]) -> Result<(), StorageError> {
+        write(&self.unversioned).insert(hash.to_string());
         self.backend.save_file_content(hash, content).await?;
         Ok(())
     }
 
+    /// Delete stored file content that no file version refers to, and
+    /// offloaded payloads no span refers to, such as content left behind by
+    /// retention pruning or deleted traces. With `dry_run`, only reports
+    /// what would be deleted.
+    pub async fn gc_file_contents(&self, dry_run: bool) -> Result<GcReport, StorageError> {
+        // Spans are written under their trace lock right after their
+        // payloads are offloaded, so with every lock held and writes flushed
+        // each payload blob's span can be found
+        let _guards = self.lock_all_traces().await;
+        self.flush_before_delete().await?;
+        let mut referenced: HashSet<String> = self
+            .backend
+            .list_file_versions()
+            .await?
+            .into_iter()
+            .map(|v| v.hash)
+            .collect();
+        let blobs = self.backend.list_file_contents().await?;
+        // Read after listing, so anything saved meanwhile is covered: a
+        // hash leaves `unversioned` only after its version is cached
+        referenced.extend(read(&self.unversioned).iter().cloned());
+        referenced.extend(read(&self.file_versions).iter().map(|v| v.hash.clone()));
+        // Offloaded payloads are referenced by spans, archived ones included
+        if blobs
+            .iter()
+            .any(|(hash, _)| hash.starts_with(payloads::BLOB_PREFIX))
+        {
+            referenced.extend(self.referenced_payloads().await?);
+        }
+
+        let (garbage, kept): (Vec<_>, Vec<_>) = blobs
+            .into_iter()
+            .partition(|(hash, _)| !referenced.contains(hash));
+        let mut report = GcReport {
+            dry_run,
+            blobs_kept: kept.len(),
+            blobs_deleted: garbage.len(),
+            bytes_reclaimed: garbage.iter().map(|(_, size)| size).sum(),
+        };
+        if !dry_run && !garbage.is_empty() {
+            let hashes: Vec<String> = garbage.into_iter().map(|(hash, _)| hash).collect();
+            report.blobs_deleted = self.backend.delete_file_contents(&hashes).await?;
+        }
+        Ok(report)
+    }
+
+    /// Delete offloaded payloads no span refers to. Call with every trace
+    /// lock held and writes flushed.
+    async fn delete_orphaned_payloads(&self) -> Result<usize, StorageError> {
+        let payloads: Vec<String> = self
+            .backend
+            .list_file_contents()
+            .await?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .filter(|hash| hash.starts_with(payloads::BLOB_PREFIX))
+            .collect();
+        if payloads.is_empty() {
+            return Ok(0);
+        }
+        let referenced = self.referenced_payloads().await?;
+        let orphans: Vec<String> = payloads
+            .into_iter()
+            .filter(|hash| !referenced.contains(hash))
+            .collect();
+        if orphans.is_empty() {
+            return Ok(0);
+        }
+        self.backend.delete_file_contents(&orphans).await
+    }
+
+    /// Blob keys of the payloads offloaded from spans in the backend or the
+    /// archive.
+    async fn referenced_payloads(&self) -> Result<HashSet<String>, StorageError> {
+        let mut keys = HashSet::new();
+        let mut offset = 0;
+        loop {
+            let page = self
+                .backend
+                .list_spans(&SpanFilter {
+                    limit: Some(REBUILD_PAGE_SIZE),
+                    offset: Some(offset),
+                    ..Default::default()
+                })
+                .await?;
+            keys.extend(page.iter().flat_map(payloads::blob_keys));
+            offset += page.len();
+            if page.len() < REBUILD_PAGE_SIZE {
+                break;
+            }
+        }
+        for segment in self.archive_segments().await? {
+            let spans = self.read_segment(&segment).await?;
+            keys.extend(spans.iter().flat_map(payloads::blob_keys));
+        }
+        Ok(keys)
+    }
+
     pub async fn load_file_content(&self, hash: &str) -> Result<Vec<u8>, StorageError> {
         self.backend.load_file_content(hash).await
     }
 
-    pub fn list_files(&self, filter: &FileFilter) -> Vec<&FileVersion> {
-        self.file_versions
+    pub fn list_files(&self, filter: &FileFilter) -> Vec<FileVersion> {
+        read(&self.file_versions)
             .iter()
             .filter(|fv| {
                 if let Some(ref prefix) = filter.path_prefix {
@@ -852,146 +2482,402 @@ impl<B: StorageBackend> PersistentStore<B> {
                 }
                 true
             })
+            .cloned()
             .collect()
     }
 
-    pub fn get_file_versions(&self, path: &str) -> Vec<&FileVersion> {
-        self.file_versions
+    fn find_file_version(&self, path: &str, hash: &str) -> Option<FileVersion> {
+        read(&self.file_versions)
+            .iter()
+            .find(|fv| fv.hash == hash && fv.path == path)
+            .cloned()
+    }
+
+    /// Apply ingest-time rewrites: name normalization and redaction.
+    fn prepare(&self, span: Span) -> Span {
+        self.redact(self.normalize_name(span))
+    }
+
+    /// Move payloads over the offload threshold into blobs.
+    async fn offload_payloads(&self, span: Span) -> Result<Span, StorageError> {
+        let Some(config) = self.payload_offload else {
+            return Ok(span);
+        };
+        let mut blobs = Vec::new();
+        let span = span.map_payloads(|value| blobs.extend(payloads::offload(value, &config)));
+        for (reference, data) in blobs {
+            self.backend
+                .save_file_content(&reference.blob_key(), &data)
+                .await?;
+        }
+        Ok(span)
+    }
+
+    /// The full body of a payload, reading it back from its blob when it
+    /// was offloaded.
+    pub async fn resolve_payload(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<serde_json::Value, StorageError> {
+        match PayloadRef::from_value(value) {
+            Some(reference) => {
+                let data = self
+                    .backend
+                    .load_file_content(&reference.blob_key())
+                    .await?;
+                serde_json::from_slice(&data)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            }
+            None => Ok(value.clone()),
+        }
+    }
+
+    fn redact(&self, span: Span) -> Span {
+        let redactor = read(&self.redactor).clone();
+        match redactor {
+            Some(redactor) => redactor.redact_span(span),
+            None => span,
+        }
+    }
+
+    fn normalize_name(&self, span: Span) -> Span {
+        match &self.names {
+            Some(names) => {
+                let normalized = names.normalize(span.name());
+                span.with_name_normalized(normalized)
+            }
+            None => span,
+        }
+    }
+
+    /// Validate an fs span's `file_version` and attach the matching stored
+    /// version, creating it from the span when none exists yet.
+    async fn resolve_file_version(&self, span: Span) -> Result<Span, StorageError> {
+        let (path, hash, size, written) = match span.kind() {
+            SpanKind::FsWrite {
+                path,
+                file_version,
+                bytes_written,
+            } => (path, file_version, *bytes_written, true),
+            SpanKind::FsRead {
+                path,
+                file_version: Some(file_version),
+                bytes_read,
+            } => (path, file_version, *bytes_read, false),
+            _ => return Ok(span),
+        };
+        if !trace::is_content_hash(hash) {
+            return Err(StorageError::InvalidInput(format!(
+                "file_version '{hash}' is not a SHA-256 hex digest"
+            )));
+        }
+
+        let version = match self.find_file_version(path, hash) {
+            // Same content at the same path must be the same size.
+            Some(existing) if written && existing.size != size => {
+                return Err(StorageError::InvalidInput(format!(
+                    "file_version {hash} for {path} is {} bytes, span wrote {size}",
+                    existing.size
+                )));
+            }
+            Some(existing) => existing,
+            None => {
+                let version = FileVersion {
+                    hash: hash.clone(),
+                    path: path.clone(),
+                    size,
+                    created_at: span.started_at(),
+                    created_by_span: written.then(|| span.id()),
+                };
+                self.save_file_version(version.clone()).await?;
+                version
+            }
+        };
+        Ok(span.with_file(version))
+    }
+
+    fn attach_file_version(&self, span: Span) -> Span {
+        let version = match span.kind() {
+            SpanKind::FsWrite {
+                path, file_version, ..
+            }
+            | SpanKind::FsRead {
+                path,
+                file_version: Some(file_version),
+                ..
+            } => self.find_file_version(path, file_version),
+            _ => None,
+        };
+        match version {
+            Some(v) => span.with_file(v),
+            None => span,
+        }
+    }
+
+    pub fn get_file_versions(&self, path: &str) -> Vec<FileVersion> {
+        read(&self.file_versions)
             .iter()
             .filter(|fv| fv.path == path)
+            .cloned()
             .collect()
     }
 
     // --- Dataset methods ---
 
-    pub async fn save_dataset(&mut self, dataset: Dataset) -> Result<(), StorageError> {
+    pub async fn save_dataset(&self, dataset: Dataset) -> Result<(), StorageError> {
         self.backend.save_dataset(&dataset).await?;
-        self.datasets.put(dataset.id, dataset);
+        write(&self.datasets).put(dataset.id, dataset);
         Ok(())
     }
 
-    pub fn get_dataset(&mut self, id: DatasetId) -> Option<&Dataset> {
-        self.datasets.get(&id)
+    /// A dataset outside the trash.
+    pub fn get_dataset(&self, id: DatasetId) -> Option<Dataset> {
+        read(&self.datasets)
+            .peek(&id)
+            .filter(|d| d.deleted_at.is_none())
+            .cloned()
     }
 
     pub fn contains_dataset(&self, id: DatasetId) -> bool {
-        self.datasets.contains(&id)
+        read(&self.datasets)
+            .peek(&id)
+            .is_some_and(|d| d.deleted_at.is_none())
     }
 
     /// Get a dataset, falling back to the storage backend if not in memory.
     /// Loads and caches the dataset in memory on fallback hit.
-    pub async fn get_dataset_or_load(&mut self, id: DatasetId) -> Option<&Dataset> {
-        if self.datasets.contains(&id) {
-            return self.datasets.get(&id);
+    pub async fn get_dataset_or_load(&self, id: DatasetId) -> Option<Dataset> {
+        if let Some(ds) = self.get_dataset(id) {
+            return Some(ds);
         }
         match self.backend.get_dataset(id).await {
             Ok(Some(ds)) => {
                 tracing::debug!(%id, "get_dataset_or_load: loaded from backend");
-                self.datasets.put(id, ds);
-                self.datasets.get(&id)
+                write(&self.datasets).put(id, ds.clone());
+                Some(ds).filter(|d| d.deleted_at.is_none())
             }
             _ => None,
         }
     }
 
-    pub fn all_datasets(&self) -> impl Iterator<Item = &Dataset> {
-        self.datasets.iter().map(|(_, d)| d)
+    /// Datasets outside the trash.
+    pub fn all_datasets(&self) -> Vec<Dataset> {
+        read(&self.datasets)
+            .iter()
+            .map(|(_, d)| d)
+            .filter(|d| d.deleted_at.is_none())
+            .cloned()
+            .collect()
     }
 
-    pub async fn delete_dataset(&mut self, id: DatasetId) -> Result<bool, StorageError> {
-        if !self.datasets.contains(&id) {
+    pub fn trashed_datasets(&self) -> Vec<Dataset> {
+        read(&self.datasets)
+            .iter()
+            .map(|(_, d)| d)
+            .filter(|d| d.deleted_at.is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Read a dataset from the backend, refreshing the cached copy, which
+    /// may be stale if another instance has written it since. `None` if it
+    /// is gone or in the trash.
+    pub async fn reload_dataset(&self, id: DatasetId) -> Result<Option<Dataset>, StorageError> {
+        let dataset = self.backend.get_dataset(id).await?;
+        let mut datasets = write(&self.datasets);
+        match &dataset {
+            Some(ds) => {
+                datasets.put(id, ds.clone());
+            }
+            None => {
+                datasets.pop(&id);
+            }
+        }
+        Ok(dataset.filter(|d| d.deleted_at.is_none()))
+    }
+
+    /// Save `dataset` if the stored copy is still at `dataset.version`,
+    /// bumping its version and `updated_at`. Unlike `save_dataset`, this
+    /// is safe against other instances writing the same dataset.
+    pub async fn update_dataset(
+        &self,
+        mut dataset: Dataset,
+    ) -> Result<Versioned<Dataset>, StorageError> {
+        let expected = dataset.version;
+        dataset.version += 1;
+        dataset.updated_at = chrono::Utc::now();
+        if self.backend.update_dataset(&dataset, expected).await? {
+            write(&self.datasets).put(dataset.id, dataset.clone());
+            return Ok(Versioned::Updated(dataset));
+        }
+        Ok(match self.backend.get_dataset(dataset.id).await? {
+            Some(current) => {
+                write(&self.datasets).put(current.id, current.clone());
+                Versioned::Conflict(current)
+            }
+            None => {
+                write(&self.datasets).pop(&dataset.id);
+                Versioned::NotFound
+            }
+        })
+    }
+
+    /// Move a dataset to the trash. It keeps its datapoints, queue items and
+    /// eval runs, but is hidden and its capture rules stop firing until it
+    /// is restored. `NotFound` if there is no such dataset outside the
+    /// trash.
+    pub async fn trash_dataset(&self, id: DatasetId) -> Result<Versioned<Dataset>, StorageError> {
+        self.move_dataset(id, true).await
+    }
+
+    /// Take a dataset out of the trash. `NotFound` if it isn't there.
+    pub async fn restore_dataset(&self, id: DatasetId) -> Result<Versioned<Dataset>, StorageError> {
+        self.move_dataset(id, false).await
+    }
+
+    /// Move a dataset into or out of the trash, reading it from the backend
+    /// and writing it back only if its version hasn't moved, so a stale
+    /// cached copy can't overwrite newer edits. Rereads and tries again when
+    /// it has, up to `DATASET_MOVE_ATTEMPTS` times.
+    async fn move_dataset(
+        &self,
+        id: DatasetId,
+        to_trash: bool,
+    ) -> Result<Versioned<Dataset>, StorageError> {
+        for _ in 0..DATASET_MOVE_ATTEMPTS {
+            let Some(mut dataset) = self.backend.get_dataset(id).await? else {
+                write(&self.datasets).pop(&id);
+                return Ok(Versioned::NotFound);
+            };
+            if dataset.deleted_at.is_some() == to_trash {
+                write(&self.datasets).put(id, dataset);
+                return Ok(Versioned::NotFound);
+            }
+            let expected = dataset.version;
+            dataset.deleted_at = to_trash.then(chrono::Utc::now);
+            // Fails any update racing with the move.
+            dataset.version += 1;
+            if self.backend.update_dataset(&dataset, expected).await? {
+                write(&self.datasets).put(id, dataset.clone());
+                return Ok(Versioned::Updated(dataset));
+            }
+        }
+        Ok(match self.backend.get_dataset(id).await? {
+            Some(stored) => {
+                write(&self.datasets).put(id, stored.clone());
+                Versioned::Conflict(stored)
+            }
+            None => {
+                write(&self.datasets).pop(&id);
+                Versioned::NotFound
+            }
+        })
+    }
+
+    /// Delete a dataset and everything in it, whether or not it is in the
+    /// trash.
+    pub async fn delete_dataset(&self, id: DatasetId) -> Result<bool, StorageError> {
+        if !read(&self.datasets).contains(&id) {
             return Ok(false);
         }
         // Delete from backend first (cascade handled by FK in SQLite)
         self.backend.delete_dataset(id).await?;
         // Then clean up cache
-        self.datasets.pop(&id);
+        write(&self.datasets).pop(&id);
         // Remove associated datapoints from memory
-        let dp_ids: Vec<DatapointId> = self
-            .datapoints
-            .iter()
-            .map(|(_, dp)| dp)
-            .filter(|dp| dp.dataset_id == id)
-            .map(|dp| dp.id)
-            .collect();
-        for dp_id in &dp_ids {
-            self.datapoints.pop(dp_id);
-        }
-        // Remove associated queue items from memory
-        let qi_ids: Vec<QueueItemId> = self
-            .queue_items
-            .values()
-            .filter(|qi| qi.dataset_id == id)
-            .map(|qi| qi.id)
-            .collect();
-        for qi_id in &qi_ids {
-            self.queue_items.remove(qi_id);
-        }
-        // Remove associated eval runs and their results from memory
-        let run_ids: Vec<EvalRunId> = self
-            .eval_runs
-            .values()
-            .filter(|r| r.dataset_id == id)
-            .map(|r| r.id)
-            .collect();
-        for rid in &run_ids {
-            self.eval_runs.remove(rid);
-        }
-        let result_ids: Vec<EvalResultId> = self
-            .eval_results
-            .values()
-            .filter(|r| r.run_id == id)
-            .map(|r| r.id)
-            .collect();
-        for rid in &result_ids {
-            self.eval_results.remove(rid);
-        }
-        // Remove associated capture rules from memory
-        let rule_ids: Vec<CaptureRuleId> = self
-            .capture_rules
-            .values()
-            .filter(|r| r.dataset_id == id)
-            .map(|r| r.id)
-            .collect();
-        for rid in &rule_ids {
-            self.capture_rules.remove(rid);
+        {
+            let mut datapoints = write(&self.datapoints);
+            let dp_ids: Vec<DatapointId> = datapoints
+                .iter()
+                .map(|(_, dp)| dp)
+                .filter(|dp| dp.dataset_id == id)
+                .map(|dp| dp.id)
+                .collect();
+            for dp_id in &dp_ids {
+                datapoints.pop(dp_id);
+            }
         }
+        // Remove associated queue items, eval runs and their results, and
+        // capture rules from memory
+        self.queue_items.retain(|_, qi| qi.dataset_id != id);
+        self.eval_runs.retain(|_, r| r.dataset_id != id);
+        self.eval_results.retain(|_, r| r.run_id != id);
+        self.capture_rules.retain(|_, r| r.dataset_id != id);
         Ok(true)
     }
 
     pub fn dataset_count(&self) -> usize {
-        self.datasets.len()
+        read(&self.datasets)
+            .iter()
+            .filter(|(_, d)| d.deleted_at.is_none())
+            .count()
     }
 
     // --- Datapoint methods ---
 
-    pub async fn save_datapoint(&mut self, dp: Datapoint) -> Result<(), StorageError> {
+    pub async fn save_datapoint(&self, dp: Datapoint) -> Result<(), StorageError> {
         self.backend.save_datapoint(&dp).await?;
-        self.datapoints.put(dp.id, dp);
+        write(&self.datapoints).put(dp.id, dp);
         Ok(())
     }
 
-    pub fn get_datapoint(&mut self, id: DatapointId) -> Option<&Datapoint> {
-        self.datapoints.get(&id)
+    pub fn get_datapoint(&self, id: DatapointId) -> Option<Datapoint> {
+        read(&self.datapoints).peek(&id).cloned()
     }
 
-    pub fn datapoints_for_dataset(&self, dataset_id: DatasetId) -> Vec<&Datapoint> {
-        self.datapoints
+    /// Read a datapoint from the backend, refreshing the cached copy.
+    pub async fn reload_datapoint(&self, id: DatapointId) -> Result<Option<Datapoint>, StorageError> {
+        let dp = self.backend.get_datapoint(id).await?;
+        let mut datapoints = write(&self.datapoints);
+        match &dp {
+            Some(dp) => {
+                datapoints.put(id, dp.clone());
+            }
+            None => {
+                datapoints.pop(&id);
+            }
+        }
+        Ok(dp)
+    }
+
+    /// Save a datapoint's `kind` if the stored copy is still at
+    /// `dp.version`; see [`PersistentStore::update_dataset`]. Its dataset
+    /// and source don't change.
+    pub async fn update_datapoint(
+        &self,
+        mut dp: Datapoint,
+    ) -> Result<Versioned<Datapoint>, StorageError> {
+        let expected = dp.version;
+        dp.version += 1;
+        if self.backend.update_datapoint(&dp, expected).await? {
+            write(&self.datapoints).put(dp.id, dp.clone());
+            return Ok(Versioned::Updated(dp));
+        }
+        Ok(match self.reload_datapoint(dp.id).await? {
+            Some(current) => Versioned::Conflict(current),
+            None => Versioned::NotFound,
+        })
+    }
+
+    pub fn datapoints_for_dataset(&self, dataset_id: DatasetId) -> Vec<Datapoint> {
+        read(&self.datapoints)
             .iter()
             .map(|(_, dp)| dp)
             .filter(|dp| dp.dataset_id == dataset_id)
+            .cloned()
             .collect()
     }
 
     /// Load datapoints for a dataset from the storage backend and merge into memory.
     /// Used for multi-instance consistency — ensures datapoints created on other
     /// instances are available locally.
-    pub async fn sync_datapoints_for_dataset(&mut self, dataset_id: DatasetId) {
+    pub async fn sync_datapoints_for_dataset(&self, dataset_id: DatasetId) {
         match self.backend.list_datapoints(dataset_id).await {
             Ok(dps) => {
                 let count = dps.len();
+                let mut datapoints = write(&self.datapoints);
                 for dp in dps {
-                    self.datapoints.put(dp.id, dp);
+                    datapoints.put(dp.id, dp);
                 }
                 tracing::debug!(%dataset_id, count, "synced datapoints from backend");
             }
@@ -1002,114 +2888,261 @@ impl<B: StorageBackend> PersistentStore<B> {
     }
 
     pub fn datapoint_count_for_dataset(&self, dataset_id: DatasetId) -> usize {
-        self.datapoints
+        read(&self.datapoints)
             .iter()
             .map(|(_, dp)| dp)
             .filter(|dp| dp.dataset_id == dataset_id)
             .count()
     }
 
-    pub async fn delete_datapoint(&mut self, id: DatapointId) -> Result<bool, StorageError> {
-        if !self.datapoints.contains(&id) {
+    pub async fn delete_datapoint(&self, id: DatapointId) -> Result<bool, StorageError> {
+        if !read(&self.datapoints).contains(&id) {
             return Ok(false);
         }
         // Delete from backend first
         self.backend.delete_datapoint(id).await?;
         // Then clean up cache
-        self.datapoints.pop(&id);
-        let qi_ids: Vec<QueueItemId> = self
-            .queue_items
-            .values()
-            .filter(|qi| qi.datapoint_id == id)
-            .map(|qi| qi.id)
-            .collect();
-        for qi_id in &qi_ids {
-            self.queue_items.remove(qi_id);
-        }
+        write(&self.datapoints).pop(&id);
+        self.queue_items.retain(|_, qi| qi.datapoint_id != id);
         Ok(true)
     }
 
     // --- Queue methods ---
 
-    pub async fn save_queue_item(&mut self, item: QueueItem) -> Result<(), StorageError> {
+    pub async fn save_queue_item(&self, item: QueueItem) -> Result<(), StorageError> {
         self.backend.save_queue_item(&item).await?;
         self.queue_items.insert(item.id, item);
         Ok(())
     }
 
-    pub fn get_queue_item(&self, id: QueueItemId) -> Option<&QueueItem> {
-        self.queue_items.get(&id)
+    pub fn get_queue_item(&self, id: QueueItemId) -> Option<QueueItem> {
+        self.queue_items.get(&id).map(|qi| qi.clone())
     }
 
-    pub fn queue_items_for_dataset(&self, dataset_id: DatasetId) -> Vec<&QueueItem> {
+    pub fn queue_items_for_dataset(&self, dataset_id: DatasetId) -> Vec<QueueItem> {
         self.queue_items
-            .values()
+            .iter()
             .filter(|qi| qi.dataset_id == dataset_id)
+            .map(|qi| qi.clone())
             .collect()
     }
 
-    pub fn all_queue_items(&self) -> Vec<&QueueItem> {
-        self.queue_items.values().collect()
+    /// Load a dataset's queue items from the storage backend into memory,
+    /// keeping cached copies, which are at least as recent.
+    pub async fn sync_queue_items_for_dataset(
+        &self,
+        dataset_id: DatasetId,
+    ) -> Result<(), StorageError> {
+        for item in self.backend.list_queue_items(dataset_id).await? {
+            self.queue_items.entry(item.id).or_insert(item);
+        }
+        Ok(())
     }
 
-    pub async fn claim_queue_item(
-        &mut self,
+    pub fn all_queue_items(&self) -> Vec<QueueItem> {
+        self.queue_items.iter().map(|qi| qi.clone()).collect()
+    }
+
+    /// A queue item, read through from the backend when the store is lazy.
+    pub async fn load_queue_item(
+        &self,
         id: QueueItemId,
-        claimed_by: impl Into<String>,
     ) -> Result<Option<QueueItem>, StorageError> {
-        let item = match self.queue_items.remove(&id) {
-            Some(i) => i,
-            None => return Ok(None),
-        };
-        if item.status != QueueItemStatus::Pending {
-            self.queue_items.insert(id, item);
+        if let Some(item) = self.get_queue_item(id) {
+            return Ok(Some(item));
+        }
+        if !self.lazy {
+            return Ok(None);
+        }
+        let item = self.backend.get_queue_item(id).await?;
+        if let Some(item) = &item {
+            self.queue_items.entry(id).or_insert_with(|| item.clone());
+        }
+        Ok(item)
+    }
+
+    /// Move a queue item from `from` to the state `transition` returns, in
+    /// memory first so concurrent callers can't both win, then in the
+    /// backend. The cached item is restored if the backend write fails.
+    async fn transition_queue_item(
+        &self,
+        id: QueueItemId,
+        from: QueueItemStatus,
+        transition: impl FnOnce(QueueItem) -> QueueItem,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        if self.load_queue_item(id).await?.is_none() {
             return Ok(None);
         }
-        let claimed = item.claim(claimed_by);
-        self.backend.save_queue_item(&claimed).await?;
-        self.queue_items.insert(id, claimed.clone());
-        Ok(Some(claimed))
+        let (previous, next) = {
+            let Some(mut item) = self.queue_items.get_mut(&id) else {
+                return Ok(None);
+            };
+            if item.status != from {
+                return Ok(None);
+            }
+            let next = transition(item.clone());
+            (std::mem::replace(&mut *item, next.clone()), next)
+        };
+        if let Err(e) = self.backend.save_queue_item(&next).await {
+            self.queue_items.insert(id, previous);
+            return Err(e);
+        }
+        Ok(Some(next))
+    }
+
+    pub async fn claim_queue_item(
+        &self,
+        id: QueueItemId,
+        claimed_by: impl Into<String>,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        let claimed_by = claimed_by.into();
+        self.transition_queue_item(id, QueueItemStatus::Pending, |item| item.claim(claimed_by))
+            .await
     }
 
     pub async fn complete_queue_item(
-        &mut self,
+        &self,
         id: QueueItemId,
         edited_data: Option<serde_json::Value>,
     ) -> Result<Option<QueueItem>, StorageError> {
-        let item = match self.queue_items.remove(&id) {
-            Some(i) => i,
-            None => return Ok(None),
-        };
-        if item.status != QueueItemStatus::Claimed {
-            self.queue_items.insert(id, item);
+        self.transition_queue_item(id, QueueItemStatus::Claimed, |item| {
+            item.complete(edited_data)
+        })
+        .await
+    }
+
+    /// Put a claimed item back in the queue.
+    pub async fn release_queue_item(
+        &self,
+        id: QueueItemId,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        self.transition_queue_item(id, QueueItemStatus::Claimed, QueueItem::release)
+            .await
+    }
+
+    pub async fn reject_queue_item(
+        &self,
+        id: QueueItemId,
+        reason: impl Into<String>,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        let reason = reason.into();
+        self.transition_queue_item(id, QueueItemStatus::Claimed, |item| item.reject(reason))
+            .await
+    }
+
+    /// Move a pending item into review by `review.reviewers`.
+    pub async fn assign_queue_reviewers(
+        &self,
+        id: QueueItemId,
+        review: ReviewPolicy,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        self.transition_queue_item(id, QueueItemStatus::Pending, |item| {
+            item.assign_reviewers(review)
+        })
+        .await
+    }
+
+    pub async fn save_queue_submission(
+        &self,
+        submission: &QueueSubmission,
+    ) -> Result<(), StorageError> {
+        self.backend.save_queue_submission(submission).await
+    }
+
+    pub async fn queue_submissions(
+        &self,
+        queue_item_id: QueueItemId,
+    ) -> Result<Vec<QueueSubmission>, StorageError> {
+        self.backend.list_queue_submissions(queue_item_id).await
+    }
+
+    /// Apply the item's review policy to its submissions, completing or
+    /// escalating it once every reviewer has submitted. Returns the item
+    /// if it changed.
+    pub async fn resolve_queue_review(
+        &self,
+        id: QueueItemId,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        let Some(review) = self.load_queue_item(id).await?.and_then(|item| item.review) else {
             return Ok(None);
+        };
+        let submissions = self.queue_submissions(id).await?;
+        match review.resolve(&submissions) {
+            Resolution::Waiting => Ok(None),
+            Resolution::Agreed(data) => {
+                self.transition_queue_item(id, QueueItemStatus::InReview, |item| {
+                    item.complete(data)
+                })
+                .await
+            }
+            Resolution::Disputed => {
+                self.transition_queue_item(id, QueueItemStatus::InReview, QueueItem::escalate)
+                    .await
+            }
+        }
+    }
+
+    /// Settle a disputed item with the adjudicator's data.
+    pub async fn adjudicate_queue_item(
+        &self,
+        id: QueueItemId,
+        edited_data: Option<serde_json::Value>,
+    ) -> Result<Option<QueueItem>, StorageError> {
+        self.transition_queue_item(id, QueueItemStatus::Adjudication, |item| {
+            item.complete(edited_data)
+        })
+        .await
+    }
+
+    /// Every queue item, read from the backend when the store is lazy.
+    pub async fn load_queue_items(&self) -> Result<Vec<QueueItem>, StorageError> {
+        if self.lazy {
+            self.backend.list_queue_items_all().await
+        } else {
+            Ok(self.all_queue_items())
         }
-        let completed = item.complete(edited_data);
-        self.backend.save_queue_item(&completed).await?;
-        self.queue_items.insert(id, completed.clone());
-        Ok(Some(completed))
+    }
+
+    /// Release items claimed before `cutoff`. Returns the released items.
+    pub async fn release_expired_queue_items(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<QueueItem>, StorageError> {
+        let mut released = Vec::new();
+        for item in self.load_queue_items().await? {
+            let expired = item.status == QueueItemStatus::Claimed
+                && item.claimed_at.is_some_and(|t| t < cutoff);
+            if !expired {
+                continue;
+            }
+            if let Some(item) = self.release_queue_item(item.id).await? {
+                released.push(item);
+            }
+        }
+        Ok(released)
     }
 
     // --- Eval Run methods ---
 
-    pub async fn save_eval_run(&mut self, run: EvalRun) -> Result<(), StorageError> {
+    pub async fn save_eval_run(&self, run: EvalRun) -> Result<(), StorageError> {
         self.backend.save_eval_run(&run).await?;
         self.eval_runs.insert(run.id, run);
         Ok(())
     }
 
-    pub fn get_eval_run(&self, id: EvalRunId) -> Option<&EvalRun> {
-        self.eval_runs.get(&id)
+    pub fn get_eval_run(&self, id: EvalRunId) -> Option<EvalRun> {
+        self.eval_runs.get(&id).map(|r| r.clone())
     }
 
-    pub fn eval_runs_for_dataset(&self, dataset_id: DatasetId) -> Vec<&EvalRun> {
+    pub fn eval_runs_for_dataset(&self, dataset_id: DatasetId) -> Vec<EvalRun> {
         self.eval_runs
-            .values()
+            .iter()
             .filter(|r| r.dataset_id == dataset_id)
+            .map(|r| r.clone())
             .collect()
     }
 
-    pub async fn delete_eval_run(&mut self, id: EvalRunId) -> Result<bool, StorageError> {
+    pub async fn delete_eval_run(&self, id: EvalRunId) -> Result<bool, StorageError> {
         if !self.eval_runs.contains_key(&id) {
             return Ok(false);
         }
@@ -1118,61 +3151,74 @@ impl<B: StorageBackend> PersistentStore<B> {
         self.backend.delete_eval_run(id).await?;
         // Then clean up cache
         self.eval_runs.remove(&id);
-        let result_ids: Vec<EvalResultId> = self
-            .eval_results
-            .values()
-            .filter(|r| r.run_id == id)
-            .map(|r| r.id)
-            .collect();
-        for rid in result_ids {
-            self.eval_results.remove(&rid);
-        }
+        self.eval_results.retain(|_, r| r.run_id != id);
         Ok(true)
     }
 
     // --- Eval Result methods ---
 
-    pub async fn save_eval_result(&mut self, result: EvalResult) -> Result<(), StorageError> {
+    pub async fn save_eval_result(&self, result: EvalResult) -> Result<(), StorageError> {
         self.backend.save_eval_result(&result).await?;
         self.eval_results.insert(result.id, result);
         Ok(())
     }
 
-    pub fn get_eval_result(&self, id: EvalResultId) -> Option<&EvalResult> {
-        self.eval_results.get(&id)
+    pub fn get_eval_result(&self, id: EvalResultId) -> Option<EvalResult> {
+        self.eval_results.get(&id).map(|r| r.clone())
     }
 
-    pub fn eval_results_for_run(&self, run_id: EvalRunId) -> Vec<&EvalResult> {
+    pub fn eval_results_for_run(&self, run_id: EvalRunId) -> Vec<EvalResult> {
         self.eval_results
-            .values()
+            .iter()
             .filter(|r| r.run_id == run_id)
+            .map(|r| r.clone())
+            .collect()
+    }
+
+    /// Eval results recorded against a span, from any run.
+    pub fn eval_results_for_span(&self, span_id: SpanId) -> Vec<EvalResult> {
+        self.eval_results
+            .iter()
+            .filter(|r| r.span_id == Some(span_id))
+            .map(|r| r.clone())
             .collect()
     }
 
     // --- Capture Rule methods ---
 
-    pub async fn save_capture_rule(&mut self, rule: CaptureRule) -> Result<(), StorageError> {
+    pub async fn save_capture_rule(&self, rule: CaptureRule) -> Result<(), StorageError> {
         self.backend.save_capture_rule(&rule).await?;
         self.capture_rules.insert(rule.id, rule);
         Ok(())
     }
 
-    pub fn get_capture_rule(&self, id: CaptureRuleId) -> Option<&CaptureRule> {
-        self.capture_rules.get(&id)
+    pub fn get_capture_rule(&self, id: CaptureRuleId) -> Option<CaptureRule> {
+        self.capture_rules.get(&id).map(|r| r.clone())
     }
 
-    pub fn capture_rules_for_dataset(&self, dataset_id: DatasetId) -> Vec<&CaptureRule> {
+    pub fn capture_rules_for_dataset(&self, dataset_id: DatasetId) -> Vec<CaptureRule> {
         self.capture_rules
-            .values()
+            .iter()
             .filter(|r| r.dataset_id == dataset_id)
+            .map(|r| r.clone())
             .collect()
     }
 
-    pub fn all_enabled_capture_rules(&self) -> Vec<&CaptureRule> {
-        self.capture_rules.values().filter(|r| r.enabled).collect()
+    /// Enabled rules of datasets outside the trash.
+    pub fn all_enabled_capture_rules(&self) -> Vec<CaptureRule> {
+        self.capture_rules
+            .iter()
+            .filter(|r| r.enabled)
+            .filter(|r| {
+                read(&self.datasets)
+                    .peek(&r.dataset_id)
+                    .is_none_or(|d| d.deleted_at.is_none())
+            })
+            .map(|r| r.clone())
+            .collect()
     }
 
-    pub async fn delete_capture_rule(&mut self, id: CaptureRuleId) -> Result<bool, StorageError> {
+    pub async fn delete_capture_rule(&self, id: CaptureRuleId) -> Result<bool, StorageError> {
         if !self.capture_rules.contains_key(&id) {
             return Ok(false);
         }
@@ -1184,7 +3230,7 @@ impl<B: StorageBackend> PersistentStore<B> {
     // --- Provider Connection operations ---
 
     pub async fn save_provider_connection(
-        &mut self,
+        &self,
         conn: ProviderConnection,
     ) -> Result<(), StorageError> {
         self.backend.save_provider_connection(&conn).await?;
@@ -1192,16 +3238,19 @@ impl<B: StorageBackend> PersistentStore<B> {
         Ok(())
     }
 
-    pub fn get_provider_connection(&self, id: ProviderConnectionId) -> Option<&ProviderConnection> {
-        self.provider_connections.get(&id)
+    pub fn get_provider_connection(&self, id: ProviderConnectionId) -> Option<ProviderConnection> {
+        self.provider_connections.get(&id).map(|c| c.clone())
     }
 
-    pub fn list_provider_connections(&self) -> Vec<&ProviderConnection> {
-        self.provider_connections.values().collect()
+    pub fn list_provider_connections(&self) -> Vec<ProviderConnection> {
+        self.provider_connections
+            .iter()
+            .map(|c| c.clone())
+            .collect()
     }
 
     pub async fn delete_provider_connection(
-        &mut self,
+        &self,
         id: ProviderConnectionId,
     ) -> Result<bool, StorageError> {
         if !self.provider_connections.contains_key(&id) {
@@ -1211,4 +3260,161 @@ impl<B: StorageBackend> PersistentStore<B> {
         self.provider_connections.remove(&id);
         Ok(true)
     }
+
+    // --- Span Kind Definition operations ---
+
+    pub async fn save_span_kind(&self, def: SpanKindDefinition) -> Result<(), StorageError> {
+        self.backend.save_span_kind(&def).await?;
+        self.span_kinds.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    pub fn get_span_kind(&self, name: &str) -> Option<SpanKindDefinition> {
+        self.span_kinds.get(name).map(|d| d.clone())
+    }
+
+    pub fn list_span_kinds(&self) -> Vec<SpanKindDefinition> {
+        let mut defs: Vec<_> = self.span_kinds.iter().map(|d| d.clone()).collect();
+        defs.sort_by(|a, b| a.name.cmp(&b.name));
+        defs
+    }
+
+    pub async fn delete_span_kind(&self, name: &str) -> Result<bool, StorageError> {
+        if !self.span_kinds.contains_key(name) {
+            return Ok(false);
+        }
+        self.backend.delete_span_kind(name).await?;
+        self.span_kinds.remove(name);
+        Ok(true)
+    }
+
+    // --- Machine registry ---
+    //
+    // Not cached: heartbeats may land on any instance, and health depends
+    // on the latest `last_seen`.
+
+    /// Record a heartbeat from `machine`, keeping its original `first_seen`.
+    pub async fn register_machine(&self, mut machine: Machine) -> Result<Machine, StorageError> {
+        let existing = self.backend.list_machines().await?;
+        if let Some(prev) = existing.iter().find(|m| m.id == machine.id) {
+            machine.first_seen = prev.first_seen;
+        }
+        self.backend.save_machine(&machine).await?;
+        Ok(machine)
+    }
+
+    pub async fn list_machines(&self) -> Result<Vec<Machine>, StorageError> {
+        self.backend.list_machines().await
+    }
+
+    pub async fn delete_machine(&self, id: &str) -> Result<bool, StorageError> {
+        self.backend.delete_machine(id).await
+    }
+
+    // --- Webhooks ---
+    //
+    // Not cached here; the daemon's dispatcher keeps its own short-lived
+    // copy of the subscriptions.
+
+    pub async fn get_setting(&self, key: &str) -> Result<Option<serde_json::Value>, StorageError> {
+        self.backend.get_setting(key).await
+    }
+
+    pub async fn save_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), StorageError> {
+        self.backend.save_setting(key, value).await
+    }
+
+    /// Save a setting only if it is still `expected`; see
+    /// [`StorageBackend::update_setting`]. Safe against other instances
+    /// updating the same setting.
+    pub async fn update_setting(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        expected: Option<&serde_json::Value>,
+    ) -> Result<bool, StorageError> {
+        self.backend.update_setting(key, value, expected).await
+    }
+
+    pub async fn save_webhook(&self, webhook: &Webhook) -> Result<(), StorageError> {
+        self.backend.save_webhook(webhook).await
+    }
+
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>, StorageError> {
+        self.backend.list_webhooks().await
+    }
+
+    pub async fn delete_webhook(&self, id: WebhookId) -> Result<bool, StorageError> {
+        self.backend.delete_webhook(id).await
+    }
+
+    pub async fn save_webhook_delivery(
+        &self,
+        delivery: &WebhookDelivery,
+    ) -> Result<(), StorageError> {
+        self.backend.save_webhook_delivery(delivery).await
+    }
+
+    pub async fn list_webhook_deliveries(
+        &self,
+        webhook_id: WebhookId,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>, StorageError> {
+        self.backend.list_webhook_deliveries(webhook_id, limit).await
+    }
+
+    // --- Audit log ---
+
+    pub async fn save_audit_event(&self, event: &AuditEvent) -> Result<(), StorageError> {
+        self.backend.save_audit_event(event).await
+    }
+
+    pub async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEvent>, StorageError> {
+        self.backend.list_audit_events(filter).await
+    }
+
+    pub async fn delete_audit_events_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize, StorageError> {
+        self.backend.delete_audit_events_before(cutoff).await
+    }
+
+    // --- Annotations ---
+
+    pub async fn save_annotation(&self, annotation: &Annotation) -> Result<(), StorageError> {
+        self.backend.save_annotation(annotation).await
+    }
+
+    pub async fn list_annotations(
+        &self,
+        filter: &AnnotationFilter,
+    ) -> Result<Vec<Annotation>, StorageError> {
+        self.backend.list_annotations(filter).await
+    }
+
+    pub async fn delete_annotation(&self, id: AnnotationId) -> Result<bool, StorageError> {
+        self.backend.delete_annotation(id).await
+    }
+
+    // --- Saved views ---
+
+    pub async fn save_view(&self, view: &SavedView) -> Result<(), StorageError> {
+        self.backend.save_view(view).await
+    }
+
+    pub async fn list_views(&self) -> Result<Vec<SavedView>, StorageError> {
+        self.backend.list_views().await
+    }
+
+    pub async fn delete_view(&self, id: SavedViewId) -> Result<bool, StorageError> {
+        self.backend.delete_view(id).await
+    }
 }
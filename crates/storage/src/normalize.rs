@@ -0,0 +1,130 @@
+//! Span name normalization.
+//!
+//! Names that embed ids (`fetch_user_4812`) split what is one operation into
+//! many groups. Rules rewrite the name on ingest into `name_normalized`,
+//! which analytics group by; the raw name is kept. Rules run in order, each
+//! on the previous one's output.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// One normalization rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NameRule {
+    /// Replace every match of `pattern`. `replace` may refer to capture
+    /// groups (`$1`, `${name}`).
+    Regex { pattern: String, replace: String },
+    /// Rename any name matching the template to the template itself. Each
+    /// `{placeholder}` matches one or more characters.
+    Template { template: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct NameNormalizer {
+    rules: Vec<(Regex, String)>,
+}
+
+impl NameNormalizer {
+    pub fn new(rules: &[NameRule]) -> Result<Self, regex::Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                NameRule::Regex { pattern, replace } => Ok((Regex::new(pattern)?, replace.clone())),
+                NameRule::Template { template } => {
+                    // The replacement is literal: `$` in a template means `$`.
+                    Ok((template_regex(template)?, template.replace('$', "$$")))
+                }
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The normalized name, or `None` if no rule changed it.
+    pub fn normalize(&self, name: &str) -> Option<String> {
+        let mut out = name.to_string();
+        for (pattern, replace) in &self.rules {
+            out = pattern.replace_all(&out, replace.as_str()).into_owned();
+        }
+        (out != name).then_some(out)
+    }
+}
+
+/// A regex matching whole names that fit `template`.
+fn template_regex(template: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(len) = rest[open..].find('}') else {
+            break;
+        };
+        pattern.push_str(&regex::escape(&rest[..open]));
+        pattern.push_str(".+?");
+        rest = &rest[open + len + 1..];
+    }
+    pattern.push_str(&regex::escape(rest));
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(rules: &str) -> NameNormalizer {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<NameRule>,
+        }
+        let rules: Rules = serde_json::from_str(rules).unwrap();
+        NameNormalizer::new(&rules.rules).unwrap()
+    }
+
+    #[test]
+    fn rules_apply_in_order() {
+        let n = normalizer(
+            r#"{"rules": [
+                {"template": "fetch_user_{id}"},
+                {"pattern": "\\d{3,}", "replace": "{n}"},
+                {"pattern": "^GET /(\\w+)/.*", "replace": "GET /$1/:id"}
+            ]}"#,
+        );
+        assert_eq!(
+            n.normalize("fetch_user_4812").as_deref(),
+            Some("fetch_user_{id}")
+        );
+        assert_eq!(
+            n.normalize("job 12345 retry").as_deref(),
+            Some("job {n} retry")
+        );
+        assert_eq!(
+            n.normalize("GET /orders/abc").as_deref(),
+            Some("GET /orders/:id")
+        );
+        assert_eq!(n.normalize("chat"), None);
+    }
+
+    #[test]
+    fn templates_match_whole_names() {
+        let n = normalizer(r#"{"rules": [{"template": "load {table}.csv ($)"}]}"#);
+        assert_eq!(
+            n.normalize("load users.csv ($)").as_deref(),
+            Some("load {table}.csv ($)")
+        );
+        assert_eq!(n.normalize("reload users.csv ($)"), None);
+        assert_eq!(n.normalize("load users_csv ($)"), None);
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let rules = [NameRule::Regex {
+            pattern: "(".to_string(),
+            replace: String::new(),
+        }];
+        assert!(NameNormalizer::new(&rules).is_err());
+    }
+}
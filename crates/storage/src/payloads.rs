@@ -0,0 +1,136 @@
+//! Offloading of large span payloads.
+//!
+//! With `PersistentStore::with_payload_offload`, a span `input` or `output`
+//! whose JSON is larger than the threshold is saved as a content blob keyed
+//! by its SHA-256 hash, and replaced inline by a [`PayloadRef`] holding a
+//! short preview. List queries and the span cache then only carry the
+//! preview; `PersistentStore::resolve_payload` reads the full body back.
+//! Payload filters such as `input_contains` only see the preview. Blobs are
+//! shared by spans with the same payload, so they are left in place when a
+//! span is deleted and collected by `PersistentStore::gc_file_contents` once
+//! no span refers to them.
+
+use serde::{Deserialize, Serialize};
+use trace::Span;
+use utoipa::ToSchema;
+
+/// Key of the single field in an offloaded payload's inline stand-in.
+pub const OFFLOADED_KEY: &str = "$offloaded";
+/// Prefix of offloaded payload blobs among the backend's file contents.
+pub const BLOB_PREFIX: &str = "payload:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadOffloadConfig {
+    /// Payloads whose JSON is larger than this many bytes are offloaded.
+    pub threshold_bytes: usize,
+    /// Characters of the payload's JSON kept inline.
+    pub preview_chars: usize,
+}
+
+impl Default for PayloadOffloadConfig {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: 64 * 1024,
+            preview_chars: 1024,
+        }
+    }
+}
+
+/// Which payload of a span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadField {
+    Input,
+    Output,
+}
+
+/// Inline stand-in for an offloaded payload, stored as
+/// `{"$offloaded": {"hash": ..., "bytes": ..., "preview": ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayloadRef {
+    /// Hex SHA-256 of the payload's JSON.
+    pub hash: String,
+    pub bytes: usize,
+    /// The start of the payload's JSON, which may not parse on its own.
+    pub preview: String,
+}
+
+impl PayloadRef {
+    /// The reference `value` stands in for, if it is one.
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        let object = value.as_object().filter(|o| o.len() == 1)?;
+        serde_json::from_value(object.get(OFFLOADED_KEY)?.clone()).ok()
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::json!({ OFFLOADED_KEY: self })
+    }
+
+    /// Key of the blob among the backend's file contents.
+    pub fn blob_key(&self) -> String {
+        format!("{BLOB_PREFIX}{}", self.hash)
+    }
+}
+
+/// Blob keys of the payloads offloaded from `span`.
+pub fn blob_keys(span: &Span) -> impl Iterator<Item = String> + '_ {
+    [span.input(), span.output()]
+        .into_iter()
+        .flatten()
+        .filter_map(PayloadRef::from_value)
+        .map(|reference| reference.blob_key())
+}
+
+/// Replace `value` by a reference when its JSON is over the threshold,
+/// returning the reference and the bytes to save under it.
+pub fn offload(
+    value: &mut serde_json::Value,
+    config: &PayloadOffloadConfig,
+) -> Option<(PayloadRef, Vec<u8>)> {
+    if PayloadRef::from_value(value).is_some() {
+        return None;
+    }
+    let data = serde_json::to_vec(value).ok()?;
+    if data.len() <= config.threshold_bytes {
+        return None;
+    }
+    let reference = PayloadRef {
+        hash: trace::content_hash(&data),
+        bytes: data.len(),
+        preview: String::from_utf8_lossy(&data)
+            .chars()
+            .take(config.preview_chars)
+            .collect(),
+    };
+    *value = reference.to_value();
+    Some((reference, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offloads_only_large_payloads() {
+        let config = PayloadOffloadConfig {
+            threshold_bytes: 32,
+            preview_chars: 8,
+        };
+        let mut small = serde_json::json!({ "prompt": "hi" });
+        assert!(offload(&mut small, &config).is_none());
+        assert_eq!(small, serde_json::json!({ "prompt": "hi" }));
+
+        let original = serde_json::json!({ "prompt": "x".repeat(100) });
+        let mut large = original.clone();
+        let (reference, data) = offload(&mut large, &config).unwrap();
+        assert_eq!(PayloadRef::from_value(&large), Some(reference.clone()));
+        assert_eq!(reference.preview, "{\"prompt");
+        assert_eq!(reference.bytes, data.len());
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&data).unwrap(),
+            original
+        );
+        // Already offloaded
+        assert!(offload(&mut large, &config).is_none());
+    }
+}
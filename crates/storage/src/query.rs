@@ -0,0 +1,214 @@
+//! The span query language used by the search bar and the CLI.
+//!
+//! A query is space-separated `key:value` terms plus bare words:
+//!
+//! ```text
+//! kind:llm_call model:gpt-4o status:failed since:1h tool:search
+//! index:docs verdict:fail
+//! duration:>500ms duration:1s-5s tokens:>1000 cost:>0.01
+//! name:"tool call" sort:duration order:desc
+//! score:>0.5 label:hallucination
+//! ```
+//!
+//! `score` and `label` match spans by their annotations. Bare words and
+//! unknown keys search span names. `since`/`until` take an
+//! RFC 3339 timestamp or a relative `30m`, `2h`, `7d`.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{SpanFilter, StorageError};
+
+fn invalid(key: &str, value: &str) -> StorageError {
+    StorageError::InvalidInput(format!("invalid value for {key}: '{value}'"))
+}
+
+/// Split on spaces, keeping double-quoted runs together (quotes removed).
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            _ => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// `500ms`, `1.5s`, or a bare number of milliseconds.
+fn parse_duration_ms(value: &str) -> Option<i64> {
+    let ms = if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse::<f64>().ok()?
+    } else if let Some(secs) = value.strip_suffix('s') {
+        secs.parse::<f64>().ok()? * 1000.0
+    } else {
+        value.parse::<f64>().ok()?
+    };
+    (ms >= 0.0).then(|| ms.round() as i64)
+}
+
+fn parse_time(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(value) {
+        return Some(ts.with_timezone(&Utc));
+    }
+    let unit = value.chars().last()?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    let ago = match unit {
+        'm' => Duration::minutes(amount),
+        'h' => Duration::hours(amount),
+        'd' => Duration::days(amount),
+        _ => return None,
+    };
+    Some(now - ago)
+}
+
+/// Parse a query into a span filter, resolving relative times against now.
+pub fn parse_span_query(input: &str) -> Result<SpanFilter, StorageError> {
+    parse_span_query_at(input, Utc::now())
+}
+
+fn parse_span_query_at(input: &str, now: DateTime<Utc>) -> Result<SpanFilter, StorageError> {
+    let mut filter = SpanFilter::default();
+    let mut words: Vec<String> = Vec::new();
+
+    for token in tokenize(input.trim()) {
+        let Some((key, value)) = token
+            .split_once(':')
+            .filter(|(k, v)| !k.is_empty() && !v.is_empty())
+        else {
+            words.push(token);
+            continue;
+        };
+        let value = value.to_string();
+        match key {
+            "kind" => filter.kind = Some(value),
+            "model" => filter.model = Some(value),
+            "provider" => filter.provider = Some(value),
+            "status" => filter.status = Some(value),
+            "name" => words.push(value),
+            "path" => filter.path = Some(value),
+            "tool" => filter.tool = Some(value),
+            "index" => filter.index = Some(value),
+            "verdict" => filter.verdict = Some(value),
+            "trace" => {
+                filter.trace_id = Some(value.parse().map_err(|_| invalid(key, &value))?);
+            }
+            "text" => filter.text_contains = Some(value),
+            "input" => filter.input_contains = Some(value),
+            "output" => filter.output_contains = Some(value),
+            "since" => {
+                filter.since = Some(parse_time(&value, now).ok_or_else(|| invalid(key, &value))?)
+            }
+            "until" => {
+                filter.until = Some(parse_time(&value, now).ok_or_else(|| invalid(key, &value))?)
+            }
+            "sort" => filter.sort_by = Some(value),
+            "order" => filter.sort_order = Some(value),
+            "duration" => {
+                let ms = |v: &str| parse_duration_ms(v).ok_or_else(|| invalid(key, &value));
+                if let Some(max) = value.strip_prefix('<') {
+                    filter.duration_max = Some(ms(max)?);
+                } else if let Some(min) = value.strip_prefix('>') {
+                    filter.duration_min = Some(ms(min)?);
+                } else if let Some((min, max)) = value.split_once('-') {
+                    filter.duration_min = Some(ms(min)?);
+                    filter.duration_max = Some(ms(max)?);
+                } else {
+                    filter.duration_min = Some(ms(&value)?);
+                }
+            }
+            "tokens" => {
+                let n = value.strip_prefix('>').unwrap_or(&value);
+                filter.tokens_min = Some(n.parse().map_err(|_| invalid(key, &value))?);
+            }
+            "cost" => {
+                let n = value.strip_prefix('>').unwrap_or(&value);
+                filter.cost_min = Some(n.parse().map_err(|_| invalid(key, &value))?);
+            }
+            "score" => {
+                let n = |v: &str| v.parse::<f64>().map_err(|_| invalid(key, &value));
+                if let Some(max) = value.strip_prefix('<') {
+                    filter.score_max = Some(n(max)?);
+                } else if let Some(min) = value.strip_prefix('>') {
+                    filter.score_min = Some(n(min)?);
+                } else if let Some((min, max)) = value.split_once('-') {
+                    filter.score_min = Some(n(min)?);
+                    filter.score_max = Some(n(max)?);
+                } else {
+                    filter.score_min = Some(n(&value)?);
+                }
+            }
+            "label" => filter.label = Some(value),
+            _ => words.push(token),
+        }
+    }
+
+    if !words.is_empty() {
+        filter.name_contains = Some(words.join(" "));
+    }
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_terms_and_bare_words() {
+        let now = Utc::now();
+        let f = parse_span_query_at(
+            r#"kind:llm_call model:gpt-4o status:failed since:1h name:"tool call" retry"#,
+            now,
+        )
+        .unwrap();
+        assert_eq!(f.kind.as_deref(), Some("llm_call"));
+        assert_eq!(f.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(f.status.as_deref(), Some("failed"));
+        assert_eq!(f.since, Some(now - Duration::hours(1)));
+        assert_eq!(f.name_contains.as_deref(), Some("tool call retry"));
+    }
+
+    #[test]
+    fn parses_numeric_bounds() {
+        let f = parse_span_query("duration:1s-2500ms tokens:>1000 cost:0.01").unwrap();
+        assert_eq!(f.duration_min, Some(1000));
+        assert_eq!(f.duration_max, Some(2500));
+        assert_eq!(f.tokens_min, Some(1000));
+        assert_eq!(f.cost_min, Some(0.01));
+
+        let f = parse_span_query("duration:<1.5s").unwrap();
+        assert_eq!((f.duration_min, f.duration_max), (None, Some(1500)));
+
+        let f = parse_span_query("score:>0.5 label:hallucination").unwrap();
+        assert_eq!((f.score_min, f.score_max), (Some(0.5), None));
+        assert_eq!(f.label.as_deref(), Some("hallucination"));
+        assert!(f.has_annotation_predicates());
+
+        let f = parse_span_query("score:0.2-0.8").unwrap();
+        assert_eq!((f.score_min, f.score_max), (Some(0.2), Some(0.8)));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert!(parse_span_query("duration:>fast").is_err());
+        assert!(parse_span_query("since:yesterday").is_err());
+        assert!(parse_span_query("trace:nope").is_err());
+        assert!(parse_span_query("score:>high").is_err());
+        assert_eq!(
+            parse_span_query("foo:bar")
+                .unwrap()
+                .name_contains
+                .as_deref(),
+            Some("foo:bar")
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! PII redaction for captured payloads.
+//!
+//! The store masks matches in span `input`, `output`, and LLM previews
+//! before anything is persisted. Built-in detectors cover emails, phone
+//! numbers, card numbers, and API keys; custom rules add regexes. Masks
+//! look like `[REDACTED:email]`.
+
+use std::borrow::Cow;
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use trace::Span;
+
+/// A built-in detector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    Email,
+    Phone,
+    CreditCard,
+    ApiKey,
+}
+
+impl Detector {
+    /// Cards and keys run before phones, whose pattern could otherwise
+    /// claim part of a longer number.
+    pub const ALL: [Detector; 4] = [
+        Detector::Email,
+        Detector::ApiKey,
+        Detector::CreditCard,
+        Detector::Phone,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Detector::Email => "email",
+            Detector::Phone => "phone",
+            Detector::CreditCard => "credit_card",
+            Detector::ApiKey => "api_key",
+        }
+    }
+
+    fn pattern(self) -> &'static str {
+        match self {
+            Detector::Email => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+            // Separators are required so ids and timestamps don't match
+            Detector::Phone => concat!(
+                r"\+\d{1,3}[ .-]?\(?\d{1,4}\)?(?:[ .-]?\d{2,4}){2,3}\b",
+                r"|(?:\(\d{3}\) ?|\b\d{3}[ .-])\d{3}[ .-]\d{4}\b",
+            ),
+            Detector::CreditCard => r"\b\d(?:[ -]?\d){12,18}\b",
+            Detector::ApiKey => concat!(
+                r"\b(?:sk|pk|rk)-[A-Za-z0-9_-]{16,}",
+                r"|\bgh[pousr]_[A-Za-z0-9]{36,}",
+                r"|\bgithub_pat_[A-Za-z0-9_]{22,}",
+                r"|\bAKIA[0-9A-Z]{16}\b",
+                r"|\bxox[abprs]-[A-Za-z0-9-]{10,}",
+                r"|\bAIza[0-9A-Za-z_-]{35}",
+                r"|\bwhsec_[A-Za-z0-9]{24,}",
+            ),
+        }
+    }
+}
+
+/// A custom redaction pattern.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedactionRule {
+    /// Shown in the mask: `[REDACTED:<name>]`.
+    pub name: String,
+    pub pattern: String,
+    /// Replacement instead of the mask; may refer to capture groups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace: Option<String>,
+}
+
+/// Redaction settings, as configured for the daemon or saved for an org.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    pub detectors: Vec<Detector>,
+    pub rules: Vec<RedactionRule>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            detectors: Detector::ALL.to_vec(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Check {
+    None,
+    Luhn,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    regex: Regex,
+    replace: String,
+    check: Check,
+}
+
+/// Compiled redaction rules. Detectors run first, then custom rules, each
+/// on the previous one's output.
+#[derive(Debug)]
+pub struct Redactor {
+    patterns: Vec<Pattern>,
+}
+
+/// Whether `digits` pass the Luhn checksum card numbers carry.
+fn luhn_valid(digits: &str) -> bool {
+    let digits: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, _) => d,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+impl Redactor {
+    /// Compile `config`, or `None` when redaction is disabled or has
+    /// nothing to apply.
+    pub fn new(config: &RedactionConfig) -> Result<Option<Self>, regex::Error> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let mut patterns = Vec::new();
+        for detector in &config.detectors {
+            patterns.push(Pattern {
+                regex: Regex::new(detector.pattern())?,
+                replace: format!("[REDACTED:{}]", detector.name()),
+                check: match detector {
+                    Detector::CreditCard => Check::Luhn,
+                    _ => Check::None,
+                },
+            });
+        }
+        for rule in &config.rules {
+            patterns.push(Pattern {
+                regex: Regex::new(&rule.pattern)?,
+                replace: match &rule.replace {
+                    Some(replace) => replace.clone(),
+                    None => format!("[REDACTED:{}]", rule.name).replace('$', "$$"),
+                },
+                check: Check::None,
+            });
+        }
+        Ok((!patterns.is_empty()).then_some(Self { patterns }))
+    }
+
+    /// `text` with every match masked.
+    pub fn redact_str<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut out = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            let replaced = match pattern.check {
+                Check::None => pattern.regex.replace_all(&out, pattern.replace.as_str()),
+                Check::Luhn => pattern.regex.replace_all(&out, |caps: &Captures| {
+                    if luhn_valid(&caps[0]) {
+                        pattern.replace.clone()
+                    } else {
+                        caps[0].to_string()
+                    }
+                }),
+            };
+            if let Cow::Owned(replaced) = replaced {
+                out = Cow::Owned(replaced);
+            }
+        }
+        out
+    }
+
+    /// Mask every string in `value`. Object keys are left alone.
+    pub fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact_str(s) {
+                    *s = redacted;
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_value(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact_value(v)),
+            _ => {}
+        }
+    }
+
+    pub fn redact_span(&self, span: Span) -> Span {
+        span.map_payloads(|value| self.redact_value(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(rules: Vec<RedactionRule>) -> Redactor {
+        Redactor::new(&RedactionConfig {
+            enabled: true,
+            rules,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn masks_builtin_detectors() {
+        let r = redactor(Vec::new());
+        assert_eq!(
+            r.redact_str("mail jane.doe@example.co.uk or call +1 415-555-0123"),
+            "mail [REDACTED:email] or call [REDACTED:phone]"
+        );
+        assert_eq!(
+            r.redact_str("card 4111 1111 1111 1111, order 4111111111111112"),
+            "card [REDACTED:credit_card], order 4111111111111112"
+        );
+        assert_eq!(
+            r.redact_str("key=sk-proj-abcdefghijklmnop1234 ts=1700000000123"),
+            "key=[REDACTED:api_key] ts=1700000000123"
+        );
+        assert_eq!(r.redact_str("(415) 555-0123"), "[REDACTED:phone]");
+        assert!(matches!(r.redact_str("nothing here"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn custom_rules_and_nested_values() {
+        let r = redactor(vec![
+            RedactionRule {
+                name: "ssn".to_string(),
+                pattern: r"\b\d{3}-\d{2}-\d{4}\b".to_string(),
+                replace: None,
+            },
+            RedactionRule {
+                name: "account".to_string(),
+                pattern: r"acct-(\d+)".to_string(),
+                replace: Some("acct-***".to_string()),
+            },
+        ]);
+        let mut value = serde_json::json!({
+            "messages": [{"role": "user", "content": "ssn 123-45-6789, acct-998877"}],
+            "user@example.com": 3
+        });
+        r.redact_value(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "messages": [{"role": "user", "content": "ssn [REDACTED:ssn], acct-***"}],
+                "user@example.com": 3
+            })
+        );
+    }
+
+    #[test]
+    fn disabled_or_empty_compiles_to_none() {
+        assert!(Redactor::new(&RedactionConfig::default())
+            .unwrap()
+            .is_none());
+        let empty = RedactionConfig {
+            enabled: true,
+            detectors: Vec::new(),
+            rules: Vec::new(),
+        };
+        assert!(Redactor::new(&empty).unwrap().is_none());
+        let bad = RedactionConfig {
+            enabled: true,
+            rules: vec![RedactionRule {
+                name: "x".to_string(),
+                pattern: "(".to_string(),
+                replace: None,
+            }],
+            ..Default::default()
+        };
+        assert!(Redactor::new(&bad).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Rolling traces up into sessions by `session_id`.
+
+use std::collections::HashMap;
+
+use trace::{Session, Trace};
+
+/// One session per distinct `session_id` among `traces`, most recently
+/// active first. Traces without a session are skipped.
+pub fn sessions_over<'a>(traces: impl IntoIterator<Item = &'a Trace>) -> Vec<Session> {
+    let mut sessions: HashMap<&str, Session> = HashMap::new();
+    for trace in traces {
+        let Some(id) = trace.session_id.as_deref() else {
+            continue;
+        };
+        let last_activity = trace.ended_at.unwrap_or(trace.started_at);
+        let session = sessions.entry(id).or_insert_with(|| Session {
+            id: id.to_string(),
+            user_id: None,
+            trace_count: 0,
+            span_count: 0,
+            error_count: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            total_cost: 0.0,
+            started_at: trace.started_at,
+            last_activity_at: last_activity,
+            duration_ms: 0,
+        });
+        session.trace_count += 1;
+        session.span_count += trace.stats.span_count;
+        session.error_count += trace.stats.error_count;
+        session.input_tokens += trace.stats.input_tokens;
+        session.output_tokens += trace.stats.output_tokens;
+        session.total_cost += trace.stats.total_cost;
+        session.started_at = session.started_at.min(trace.started_at);
+        session.last_activity_at = session.last_activity_at.max(last_activity);
+        if session.user_id.is_none() {
+            session.user_id = trace.user_id.clone();
+        }
+    }
+    let mut sessions: Vec<Session> = sessions
+        .into_values()
+        .map(|mut s| {
+            s.duration_ms = (s.last_activity_at - s.started_at).num_milliseconds();
+            s
+        })
+        .collect();
+    sessions.sort_by(|a, b| {
+        b.last_activity_at
+            .cmp(&a.last_activity_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+
+    fn trace(session: Option<&str>, start_secs: i64, end_secs: Option<i64>, cost: f64) -> Trace {
+        let base = Utc::now();
+        let mut trace = Trace::new(Some("t".into()));
+        trace.session_id = session.map(String::from);
+        trace.started_at = base + Duration::seconds(start_secs);
+        trace.ended_at = end_secs.map(|s| base + Duration::seconds(s));
+        trace.stats.span_count = 2;
+        trace.stats.total_cost = cost;
+        trace
+    }
+
+    #[test]
+    fn groups_by_session_and_orders_by_activity() {
+        let traces = [
+            trace(Some("a"), 0, Some(10), 0.5),
+            trace(Some("a"), 20, None, 0.25),
+            trace(Some("b"), 5, Some(60), 1.0),
+            trace(None, 0, Some(100), 9.0),
+        ];
+        let sessions = sessions_over(&traces);
+        let ids: Vec<_> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        let a = &sessions[1];
+        assert_eq!((a.trace_count, a.span_count, a.total_cost), (2, 4, 0.75));
+        // A running trace counts up to its start
+        assert_eq!(a.duration_ms, 20_000);
+    }
+}
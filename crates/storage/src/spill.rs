@@ -0,0 +1,472 @@
+//! Local spill queue for span and trace writes the backend rejects.
+//!
+//! With `PersistentStore::with_spill`, a write that fails with a backend,
+//! network or database error is appended to a JSON-lines file in the spill
+//! directory and reported as saved. While the queue holds anything, later
+//! writes are appended behind it rather than sent, so the backend sees
+//! writes in their original order. A background task replays the queue
+//! once the backend accepts writes again: it moves the file aside, writes
+//! its entries in order, and deletes it. A queue left over from a previous
+//! run is replayed the same way. Entries the backend rejects as invalid are
+//! dropped one by one, like the write-behind flusher does, so a bad span
+//! can't hold the queue open. Deletes replay the queue first, so a queued
+//! write can't bring back what they remove.
+
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex as AsyncMutex;
+use trace::{Span, Trace};
+use utoipa::ToSchema;
+
+use crate::write_behind::BatchWriter;
+use crate::StorageError;
+
+const QUEUE_FILE: &str = "spill.jsonl";
+/// The queue while it is being replayed.
+const REPLAY_FILE: &str = "spill.replaying.jsonl";
+/// Spans per `save_spans_batch` call during replay.
+const REPLAY_BATCH: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct SpillConfig {
+    pub dir: PathBuf,
+    /// How often the backend is retried while writes are queued.
+    pub replay_interval: Duration,
+}
+
+impl SpillConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            replay_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// The same settings, spilling to `sub` under this directory.
+    pub fn nested(&self, sub: impl AsRef<Path>) -> Self {
+        Self {
+            dir: self.dir.join(sub),
+            replay_interval: self.replay_interval,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct SpillStatus {
+    /// Writes waiting to be replayed.
+    pub pending: u64,
+    /// Writes replayed into the backend since opening.
+    pub replayed: u64,
+    /// Replayed writes the backend rejected as invalid, which were dropped.
+    pub dropped: u64,
+    /// Why the last replay attempt failed, until one succeeds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data", rename_all = "lowercase")]
+pub(crate) enum Spilled {
+    Span(Box<Span>),
+    Trace(Box<Trace>),
+}
+
+impl Spilled {
+    pub fn span(span: &Span) -> Self {
+        Spilled::Span(Box::new(span.clone()))
+    }
+
+    pub fn trace(trace: &Trace) -> Self {
+        Spilled::Trace(Box::new(trace.clone()))
+    }
+}
+
+pub(crate) struct SpillQueue {
+    dir: PathBuf,
+    /// Append handle of the queue file, reopened after replay moves it.
+    file: Mutex<Option<File>>,
+    pending: AtomicU64,
+    replayed: AtomicU64,
+    dropped: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    /// Held while replaying, so the background task and a delete don't
+    /// replay the same file twice.
+    replaying: AsyncMutex<()>,
+}
+
+impl SpillQueue {
+    pub fn open(dir: &Path) -> Result<Self, StorageError> {
+        fs::create_dir_all(dir)?;
+        let pending = count_lines(&dir.join(REPLAY_FILE))? + count_lines(&dir.join(QUEUE_FILE))?;
+        if pending > 0 {
+            tracing::warn!(dir = %dir.display(), pending, "replaying writes spilled by a previous run");
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file: Mutex::new(None),
+            pending: AtomicU64::new(pending),
+            replayed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            replaying: AsyncMutex::new(()),
+        })
+    }
+
+    pub fn status(&self) -> SpillStatus {
+        SpillStatus {
+            pending: self.pending.load(Ordering::Acquire),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    /// Whether no writes are waiting to be replayed.
+    pub fn is_empty(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+
+    /// Run `write`, or queue `entries` if earlier writes are still queued
+    /// or `write` fails in a way the backend may recover from.
+    pub async fn write_or_spill(
+        &self,
+        entries: impl FnOnce() -> Vec<Spilled>,
+        write: impl Future<Output = Result<(), StorageError>>,
+    ) -> Result<(), StorageError> {
+        if self.is_empty() {
+            match write.await {
+                Err(e) if is_transient(&e) => {
+                    tracing::warn!("backend write failed, spilling to disk: {e}");
+                }
+                result => return result,
+            }
+        }
+        self.append(&entries())
+    }
+
+    fn append(&self, entries: &[Spilled]) -> Result<(), StorageError> {
+        let mut data = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(QUEUE_FILE))?,
+            );
+        }
+        let handle = file.as_mut().expect("opened above");
+        handle.write_all(&data)?;
+        handle.sync_data()?;
+        self.pending
+            .fetch_add(entries.len() as u64, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Move the queue file aside for replay, unless an earlier replay's
+    /// file is still there. Returns the file to replay, if any.
+    fn take(&self) -> Result<Option<PathBuf>, StorageError> {
+        let replay = self.dir.join(REPLAY_FILE);
+        if replay.exists() {
+            return Ok(Some(replay));
+        }
+        let mut file = self.file.lock().unwrap();
+        *file = None;
+        match fs::rename(self.dir.join(QUEUE_FILE), &replay) {
+            Ok(()) => Ok(Some(replay)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write every queued entry to `backend`, oldest first, dropping the
+    /// ones it rejects as invalid. Stops at the first transient failure,
+    /// leaving the rest queued for the next attempt.
+    pub async fn replay<B: BatchWriter>(&self, backend: &B) -> Result<u64, StorageError> {
+        let _replaying = self.replaying.lock().await;
+        let mut total = 0;
+        while let Some(path) = self.take()? {
+            let (entries, lines) = read_entries(&path)?;
+            let dropped = match replay_entries(backend, entries).await {
+                Ok(dropped) => dropped,
+                Err(e) => {
+                    *self.last_error.lock().unwrap() = Some(e.to_string());
+                    return Err(e);
+                }
+            };
+            fs::remove_file(&path)?;
+            self.pending.fetch_sub(lines, Ordering::AcqRel);
+            self.replayed.fetch_add(lines - dropped, Ordering::Relaxed);
+            self.dropped.fetch_add(dropped, Ordering::Relaxed);
+            total += lines - dropped;
+        }
+        *self.last_error.lock().unwrap() = None;
+        Ok(total)
+    }
+}
+
+/// Replay `queue` into `backend` every `interval` while it holds writes.
+/// Stops once the queue is dropped.
+pub(crate) fn spawn_replayer<B: BatchWriter + 'static>(
+    queue: Weak<SpillQueue>,
+    backend: Arc<B>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let Some(queue) = queue.upgrade() else {
+                return;
+            };
+            if queue.is_empty() {
+                continue;
+            }
+            match queue.replay(&*backend).await {
+                Ok(count) => tracing::info!(count, "replayed spilled writes"),
+                Err(e) => tracing::warn!("spill replay failed, will retry: {e}"),
+            }
+        }
+    });
+}
+
+/// Errors the backend may recover from, as opposed to bad input.
+pub(crate) fn is_transient(e: &StorageError) -> bool {
+    matches!(
+        e,
+        StorageError::Network(_) | StorageError::Backend(_) | StorageError::Database(_)
+    )
+}
+
+fn count_lines(path: &Path) -> Result<u64, StorageError> {
+    match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file).lines().count() as u64),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The entries in `path` and its line count. A line that doesn't parse,
+/// such as one cut short by a crash, is skipped.
+fn read_entries(path: &Path) -> Result<(Vec<Spilled>, u64), StorageError> {
+    let mut entries = Vec::new();
+    let mut lines = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        lines += 1;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), "skipping unreadable spilled write: {e}")
+            }
+        }
+    }
+    Ok((entries, lines))
+}
+
+/// Write `entries` in order, batching runs of spans. Returns how many
+/// were rejected as invalid and dropped.
+async fn replay_entries<B: BatchWriter>(
+    backend: &B,
+    entries: Vec<Spilled>,
+) -> Result<u64, StorageError> {
+    let mut dropped = 0;
+    let mut spans = Vec::new();
+    for entry in entries {
+        match entry {
+            Spilled::Span(span) => {
+                spans.push(*span);
+                if spans.len() >= REPLAY_BATCH {
+                    dropped += replay_spans(backend, &std::mem::take(&mut spans)).await?;
+                }
+            }
+            Spilled::Trace(trace) => {
+                if !spans.is_empty() {
+                    dropped += replay_spans(backend, &std::mem::take(&mut spans)).await?;
+                }
+                match backend.save_trace(&trace).await {
+                    Err(e) if !is_transient(&e) => {
+                        tracing::error!(trace_id = %trace.id, "dropping spilled trace write: {e}");
+                        dropped += 1;
+                    }
+                    result => result?,
+                }
+            }
+        }
+    }
+    if !spans.is_empty() {
+        dropped += replay_spans(backend, &spans).await?;
+    }
+    Ok(dropped)
+}
+
+/// Write a batch of spilled spans. If the backend rejects it as invalid,
+/// write them one at a time and drop the ones at fault.
+async fn replay_spans<B: BatchWriter>(backend: &B, spans: &[Span]) -> Result<u64, StorageError> {
+    match backend.save_spans_batch(spans).await {
+        Err(e) if !is_transient(&e) => {
+            tracing::warn!("spilled spans rejected, replaying one at a time: {e}");
+        }
+        result => return result.map(|()| 0),
+    }
+    let mut dropped = 0;
+    for span in spans {
+        match backend.save_spans_batch(std::slice::from_ref(span)).await {
+            Err(e) if !is_transient(&e) => {
+                tracing::error!(span_id = %span.id(), "dropping spilled span write: {e}");
+                dropped += 1;
+            }
+            result => result?,
+        }
+    }
+    Ok(dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use trace::{SpanBuilder, SpanKind, TraceId};
+
+    use super::*;
+
+    /// Records span writes; spans named "bad" are rejected, and every write
+    /// fails as if the backend were down while `down` is set.
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<String>>,
+        traces: Mutex<Vec<TraceId>>,
+        down: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl BatchWriter for Recorder {
+        async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(StorageError::Network("unreachable".into()));
+            }
+            if spans.iter().any(|s| s.name() == "bad") {
+                return Err(StorageError::InvalidInput("bad span".into()));
+            }
+            let names = spans.iter().map(|s| s.name().to_string());
+            self.spans.lock().unwrap().extend(names);
+            Ok(())
+        }
+
+        async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(StorageError::Network("unreachable".into()));
+            }
+            self.traces.lock().unwrap().push(trace.id);
+            Ok(())
+        }
+    }
+
+    fn span(name: &str) -> Span {
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        SpanBuilder::new(TraceId::new_v4(), name, kind).build()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("traceway-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn spilled_writes_replay_in_order_once_the_backend_recovers() {
+        let dir = temp_dir("spill-replay");
+        let queue = SpillQueue::open(&dir).unwrap();
+        let backend = Recorder::default();
+        let trace = Trace::new(Some("t".into()));
+
+        backend.down.store(true, Ordering::Relaxed);
+        let first = [span("first")];
+        let write = backend.save_spans_batch(&first);
+        queue
+            .write_or_spill(|| vec![Spilled::span(&first[0])], write)
+            .await
+            .unwrap();
+        // Queued behind the first without being sent
+        let write = backend.save_trace(&trace);
+        queue
+            .write_or_spill(|| vec![Spilled::trace(&trace)], write)
+            .await
+            .unwrap();
+        assert_eq!(queue.status().pending, 2);
+        assert!(queue.replay(&backend).await.is_err());
+        assert_eq!(queue.status().pending, 2);
+        assert!(queue.status().last_error.is_some());
+
+        backend.down.store(false, Ordering::Relaxed);
+        assert_eq!(queue.replay(&backend).await.unwrap(), 2);
+        let status = queue.status();
+        assert_eq!((status.pending, status.replayed), (0, 2));
+        assert!(status.last_error.is_none());
+        assert_eq!(*backend.spans.lock().unwrap(), ["first"]);
+        assert_eq!(*backend.traces.lock().unwrap(), [trace.id]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_spilled_writes_are_dropped() {
+        let dir = temp_dir("spill-poison");
+        let queue = SpillQueue::open(&dir).unwrap();
+        let backend = Recorder::default();
+        let entries: Vec<Spilled> = ["a", "bad", "b"]
+            .iter()
+            .map(|n| Spilled::span(&span(n)))
+            .collect();
+        queue.append(&entries).unwrap();
+
+        assert_eq!(queue.replay(&backend).await.unwrap(), 2);
+        let status = queue.status();
+        assert_eq!((status.pending, status.replayed, status.dropped), (0, 2, 1));
+        assert_eq!(*backend.spans.lock().unwrap(), ["a", "b"]);
+
+        // With the queue empty, writes go straight to the backend again
+        let next = [span("c")];
+        let write = backend.save_spans_batch(&next);
+        queue.write_or_spill(Vec::new, write).await.unwrap();
+        assert_eq!(queue.status().pending, 0);
+        assert_eq!(backend.spans.lock().unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn queued_writes_survive_reopening() {
+        let dir = std::env::temp_dir().join(format!("traceway-spill-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let trace = Trace::new(Some("t".into()));
+
+        let queue = SpillQueue::open(&dir).unwrap();
+        queue.append(&[Spilled::trace(&trace)]).unwrap();
+        queue.append(&[Spilled::trace(&trace)]).unwrap();
+        assert_eq!(queue.status().pending, 2);
+        let path = queue.take().unwrap().unwrap();
+        // New writes queue behind the file being replayed
+        queue.append(&[Spilled::trace(&trace)]).unwrap();
+        drop(queue);
+
+        let reopened = SpillQueue::open(&dir).unwrap();
+        assert_eq!(reopened.status().pending, 3);
+        assert_eq!(reopened.take().unwrap(), Some(path.clone()));
+        let (entries, lines) = read_entries(&path).unwrap();
+        assert_eq!(lines, 2);
+        assert!(matches!(&entries[0], Spilled::Trace(t) if t.id == trace.id));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
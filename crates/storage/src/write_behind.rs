@@ -0,0 +1,440 @@
+//! Write-behind buffering for span and trace writes.
+//!
+//! Writes are queued on a bounded channel and a background task persists
+//! them in batches, so callers return as soon as the cache is updated. A full
+//! queue applies backpressure instead of dropping writes. Repeated writes to
+//! the same span or trace within a batch are coalesced (last write wins).
+//! A failing batch is retried with backoff up to `max_attempts` times, and
+//! writes the backend rejects as invalid are dropped one by one rather than
+//! holding up the rest.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use trace::{Span, SpanId, Trace, TraceId};
+
+use crate::spill::{is_transient, SpillQueue, Spilled};
+use crate::{StorageBackend, StorageError};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriteBehindConfig {
+    /// Queued writes before callers wait for the flusher.
+    pub capacity: usize,
+    /// Spans written per `save_spans_batch` call.
+    pub max_batch: usize,
+    /// Longest a write waits in the buffer.
+    pub max_delay: Duration,
+    /// Tries at a failing batch before its writes are dropped.
+    pub max_attempts: u32,
+}
+
+impl Default for WriteBehindConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            max_batch: 500,
+            max_delay: Duration::from_millis(50),
+            max_attempts: 8,
+        }
+    }
+}
+
+enum WriteOp {
+    Span(Box<Span>),
+    Trace(Box<Trace>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// The backend writes the flusher makes.
+#[async_trait]
+pub(crate) trait BatchWriter: Send + Sync {
+    async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError>;
+    async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError>;
+}
+
+#[async_trait]
+impl<B: StorageBackend> BatchWriter for B {
+    async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError> {
+        StorageBackend::save_spans_batch(self, spans).await
+    }
+
+    async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
+        StorageBackend::save_trace(self, trace).await
+    }
+}
+
+pub(crate) struct WriteBehind {
+    tx: mpsc::Sender<WriteOp>,
+}
+
+impl WriteBehind {
+    pub fn spawn<B: BatchWriter + 'static>(
+        backend: Arc<B>,
+        config: WriteBehindConfig,
+        spill: Option<Arc<SpillQueue>>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.capacity.max(1));
+        tokio::spawn(run(backend, config, spill, rx));
+        Self { tx }
+    }
+
+    pub async fn span(&self, span: Span) -> Result<(), StorageError> {
+        self.send(WriteOp::Span(Box::new(span))).await
+    }
+
+    pub async fn trace(&self, trace: Trace) -> Result<(), StorageError> {
+        self.send(WriteOp::Trace(Box::new(trace))).await
+    }
+
+    /// Wait until every write queued before this call is persisted.
+    pub async fn flush(&self) -> Result<(), StorageError> {
+        let (reply, done) = oneshot::channel();
+        self.send(WriteOp::Flush(reply)).await?;
+        done.await.map_err(|_| closed())
+    }
+
+    async fn send(&self, op: WriteOp) -> Result<(), StorageError> {
+        self.tx.send(op).await.map_err(|_| closed())
+    }
+}
+
+fn closed() -> StorageError {
+    StorageError::Backend("write-behind flusher has stopped".to_string())
+}
+
+#[derive(Default)]
+struct Pending {
+    spans: Vec<Span>,
+    span_index: HashMap<SpanId, usize>,
+    traces: HashMap<TraceId, Trace>,
+    opened_at: Option<Instant>,
+}
+
+impl Pending {
+    fn push_span(&mut self, span: Span) {
+        self.opened_at.get_or_insert_with(Instant::now);
+        match self.span_index.get(&span.id()) {
+            Some(&pos) => self.spans[pos] = span,
+            None => {
+                self.span_index.insert(span.id(), self.spans.len());
+                self.spans.push(span);
+            }
+        }
+    }
+
+    fn push_trace(&mut self, trace: Trace) {
+        self.opened_at.get_or_insert_with(Instant::now);
+        self.traces.insert(trace.id, trace);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.spans.is_empty() && self.traces.is_empty()
+    }
+
+    /// The writes in the order `write_once` sends them.
+    fn entries(&self) -> Vec<Spilled> {
+        let spans = self.spans.iter().map(Spilled::span);
+        spans
+            .chain(self.traces.values().map(Spilled::trace))
+            .collect()
+    }
+}
+
+async fn run<B: BatchWriter>(
+    backend: Arc<B>,
+    config: WriteBehindConfig,
+    spill: Option<Arc<SpillQueue>>,
+    mut rx: mpsc::Receiver<WriteOp>,
+) {
+    let spill = spill.as_deref();
+    let mut pending = Pending::default();
+    loop {
+        let deadline = pending.opened_at.map(|t| t + config.max_delay);
+        let op = tokio::select! {
+            op = rx.recv() => op,
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                write(&*backend, spill, config.max_attempts, &mut pending).await;
+                continue;
+            }
+        };
+        match op {
+            Some(WriteOp::Span(span)) => {
+                pending.push_span(*span);
+                if pending.spans.len() >= config.max_batch {
+                    write(&*backend, spill, config.max_attempts, &mut pending).await;
+                }
+            }
+            Some(WriteOp::Trace(trace)) => pending.push_trace(*trace),
+            Some(WriteOp::Flush(reply)) => {
+                write(&*backend, spill, config.max_attempts, &mut pending).await;
+                let _ = reply.send(());
+            }
+            None => {
+                write(&*backend, spill, config.max_attempts, &mut pending).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Persist everything pending, retrying with backoff until the backend or
+/// the spill queue accepts it or `max_attempts` tries have failed. New writes
+/// queue up behind a failing batch.
+async fn write<B: BatchWriter>(
+    backend: &B,
+    spill: Option<&SpillQueue>,
+    max_attempts: u32,
+    pending: &mut Pending,
+) {
+    if pending.is_empty() {
+        return;
+    }
+    let batch = std::mem::take(pending);
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        let result = match spill {
+            Some(spill) => {
+                spill
+                    .write_or_spill(|| batch.entries(), write_once(backend, &batch))
+                    .await
+            }
+            None => write_once(backend, &batch).await,
+        };
+        let e = match result {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if !is_transient(&e) {
+            // Retrying won't help; find the writes at fault and keep the rest
+            tracing::warn!("write-behind flush rejected, writing one at a time: {e}");
+            write_each(backend, &batch).await;
+            return;
+        }
+        if attempt >= max_attempts {
+            tracing::error!(
+                spans = batch.spans.len(),
+                traces = batch.traces.len(),
+                "write-behind flush failed {attempt} times, dropping its writes: {e}"
+            );
+            return;
+        }
+        tracing::error!(
+            spans = batch.spans.len(),
+            traces = batch.traces.len(),
+            "write-behind flush failed, retrying in {delay:?}: {e}"
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+        attempt += 1;
+    }
+}
+
+/// Write a batch one span or trace at a time, dropping the ones that fail.
+async fn write_each<B: BatchWriter>(backend: &B, batch: &Pending) {
+    for span in &batch.spans {
+        if let Err(e) = backend.save_spans_batch(std::slice::from_ref(span)).await {
+            tracing::error!(span_id = %span.id(), "dropping write-behind span write: {e}");
+        }
+    }
+    for trace in batch.traces.values() {
+        if let Err(e) = backend.save_trace(trace).await {
+            tracing::error!(trace_id = %trace.id, "dropping write-behind trace write: {e}");
+        }
+    }
+}
+
+async fn write_once<B: BatchWriter>(backend: &B, batch: &Pending) -> Result<(), StorageError> {
+    if !batch.spans.is_empty() {
+        backend.save_spans_batch(&batch.spans).await?;
+    }
+    for trace in batch.traces.values() {
+        backend.save_trace(trace).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use trace::{SpanBuilder, SpanKind, SpanStatus};
+
+    use super::*;
+
+    /// Records each write; spans named "bad" are rejected, and the first
+    /// `outages` writes fail as if the backend were down.
+    #[derive(Default)]
+    struct Recorder {
+        batches: Mutex<Vec<Vec<Span>>>,
+        traces: Mutex<Vec<TraceId>>,
+        outages: Mutex<u32>,
+        attempts: Mutex<u32>,
+    }
+
+    impl Recorder {
+        fn batches(&self) -> Vec<Vec<Span>> {
+            self.batches.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl BatchWriter for Recorder {
+        async fn save_spans_batch(&self, spans: &[Span]) -> Result<(), StorageError> {
+            *self.attempts.lock().unwrap() += 1;
+            let mut outages = self.outages.lock().unwrap();
+            if *outages > 0 {
+                *outages -= 1;
+                return Err(StorageError::Network("unreachable".into()));
+            }
+            if spans.iter().any(|s| s.name() == "bad") {
+                return Err(StorageError::InvalidInput("bad span".into()));
+            }
+            self.batches.lock().unwrap().push(spans.to_vec());
+            Ok(())
+        }
+
+        async fn save_trace(&self, trace: &Trace) -> Result<(), StorageError> {
+            self.traces.lock().unwrap().push(trace.id);
+            Ok(())
+        }
+    }
+
+    fn config(max_batch: usize, max_delay: Duration) -> WriteBehindConfig {
+        WriteBehindConfig {
+            capacity: 100,
+            max_batch,
+            max_delay,
+            max_attempts: 3,
+        }
+    }
+
+    fn span(trace_id: TraceId, name: &str) -> Span {
+        let kind = SpanKind::Custom {
+            kind: "step".into(),
+            attributes: Default::default(),
+        };
+        SpanBuilder::new(trace_id, name, kind).build()
+    }
+
+    /// Wait up to a second for the flusher to write `n` batches.
+    async fn wait_for_batches(recorder: &Recorder, n: usize) {
+        for _ in 0..100 {
+            if recorder.batches().len() >= n {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {n} batches, got {}", recorder.batches().len());
+    }
+
+    #[tokio::test]
+    async fn repeated_writes_coalesce() {
+        let recorder = Arc::new(Recorder::default());
+        let writer =
+            WriteBehind::spawn(recorder.clone(), config(100, Duration::from_secs(60)), None);
+        let trace = Trace::new(Some("run".into()));
+        let a = span(trace.id, "a");
+        let b = span(trace.id, "b");
+        writer.span(a.clone()).await.unwrap();
+        writer.span(b.clone()).await.unwrap();
+        writer.span(a.clone().complete(None)).await.unwrap();
+        writer.trace(trace.clone()).await.unwrap();
+        writer.trace(trace.clone()).await.unwrap();
+        writer.flush().await.unwrap();
+
+        let batches = recorder.batches();
+        assert_eq!(batches.len(), 1);
+        let ids: Vec<SpanId> = batches[0].iter().map(Span::id).collect();
+        assert_eq!(ids, vec![a.id(), b.id()]);
+        assert!(matches!(batches[0][0].status(), SpanStatus::Completed));
+        assert_eq!(*recorder.traces.lock().unwrap(), vec![trace.id]);
+    }
+
+    #[tokio::test]
+    async fn full_batches_flush_without_waiting() {
+        let recorder = Arc::new(Recorder::default());
+        let writer = WriteBehind::spawn(recorder.clone(), config(2, Duration::from_secs(60)), None);
+        let trace_id = Trace::new(None).id;
+        for name in ["a", "b", "c"] {
+            writer.span(span(trace_id, name)).await.unwrap();
+        }
+        wait_for_batches(&recorder, 1).await;
+        assert_eq!(recorder.batches()[0].len(), 2);
+
+        writer.flush().await.unwrap();
+        assert_eq!(recorder.batches()[1].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pending_writes_flush_after_max_delay() {
+        let recorder = Arc::new(Recorder::default());
+        let writer = WriteBehind::spawn(
+            recorder.clone(),
+            config(100, Duration::from_millis(20)),
+            None,
+        );
+        writer.span(span(Trace::new(None).id, "a")).await.unwrap();
+        wait_for_batches(&recorder, 1).await;
+        assert_eq!(recorder.batches()[0].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn flush_persists_writes_in_order() {
+        let recorder = Arc::new(Recorder::default());
+        let writer =
+            WriteBehind::spawn(recorder.clone(), config(100, Duration::from_secs(60)), None);
+        let a = span(Trace::new(None).id, "a");
+        writer.span(a.clone()).await.unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(recorder.batches().len(), 1);
+
+        writer.span(a.clone().complete(None)).await.unwrap();
+        writer.flush().await.unwrap();
+        let batches = recorder.batches();
+        assert_eq!(batches.len(), 2);
+        assert!(matches!(batches[0][0].status(), SpanStatus::Running));
+        assert!(matches!(batches[1][0].status(), SpanStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn failing_batches_are_dropped_after_max_attempts() {
+        let recorder = Arc::new(Recorder {
+            outages: Mutex::new(u32::MAX),
+            ..Default::default()
+        });
+        let writer =
+            WriteBehind::spawn(recorder.clone(), config(100, Duration::from_secs(60)), None);
+        writer.span(span(Trace::new(None).id, "a")).await.unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(*recorder.attempts.lock().unwrap(), 3);
+
+        // The flusher moves on to later writes once the backend recovers
+        *recorder.outages.lock().unwrap() = 0;
+        writer.span(span(Trace::new(None).id, "b")).await.unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(recorder.batches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_writes_are_dropped_alone() {
+        let recorder = Arc::new(Recorder::default());
+        let writer =
+            WriteBehind::spawn(recorder.clone(), config(100, Duration::from_secs(60)), None);
+        let trace_id = Trace::new(None).id;
+        let good = span(trace_id, "good");
+        writer.span(good.clone()).await.unwrap();
+        writer.span(span(trace_id, "bad")).await.unwrap();
+        writer.flush().await.unwrap();
+
+        let batches = recorder.batches();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0][0].id(), good.id());
+    }
+}
@@ -0,0 +1,102 @@
+//! Deciding a multi-reviewer queue item from its submissions.
+//!
+//! Nothing is decided until every assigned reviewer has submitted.
+//! Submissions agree when their `edited_data` is equal as JSON.
+
+use crate::{ConsensusPolicy, QueueSubmission, ReviewPolicy};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    /// Some reviewers haven't submitted yet.
+    Waiting,
+    /// The policy picked this data.
+    Agreed(Option<serde_json::Value>),
+    /// The item needs an adjudicator.
+    Disputed,
+}
+
+impl ReviewPolicy {
+    /// Apply the policy to `submissions`. Submissions from anyone not
+    /// assigned are ignored.
+    pub fn resolve(&self, submissions: &[QueueSubmission]) -> Resolution {
+        let mut votes: Vec<(&Option<serde_json::Value>, usize)> = Vec::new();
+        let mut submitted = 0;
+        for reviewer in &self.reviewers {
+            let Some(s) = submissions.iter().find(|s| &s.reviewer == reviewer) else {
+                continue;
+            };
+            submitted += 1;
+            match votes.iter_mut().find(|(data, _)| *data == &s.edited_data) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((&s.edited_data, 1)),
+            }
+        }
+        if submitted < self.reviewers.len() {
+            return Resolution::Waiting;
+        }
+        let Some((data, count)) = votes.into_iter().max_by_key(|(_, count)| *count) else {
+            return Resolution::Disputed;
+        };
+        let agreed = match self.consensus {
+            ConsensusPolicy::Majority => count * 2 > submitted,
+            ConsensusPolicy::Unanimous => count == submitted,
+            ConsensusPolicy::Adjudication => false,
+        };
+        if agreed {
+            Resolution::Agreed(data.clone())
+        } else {
+            Resolution::Disputed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn policy(consensus: ConsensusPolicy) -> ReviewPolicy {
+        ReviewPolicy {
+            reviewers: vec!["a".into(), "b".into(), "c".into()],
+            consensus,
+        }
+    }
+
+    fn submit(reviewer: &str, label: &str) -> QueueSubmission {
+        QueueSubmission::new(Uuid::nil(), reviewer, Some(json!({ "label": label })))
+    }
+
+    #[test]
+    fn waits_for_every_reviewer() {
+        let subs = [submit("a", "x"), submit("b", "x"), submit("z", "x")];
+        assert_eq!(
+            policy(ConsensusPolicy::Majority).resolve(&subs),
+            Resolution::Waiting
+        );
+    }
+
+    #[test]
+    fn policies_decide_split_and_unanimous_votes() {
+        let split = [submit("a", "x"), submit("b", "y"), submit("c", "x")];
+        let same = [submit("a", "x"), submit("b", "x"), submit("c", "x")];
+        let agreed = Resolution::Agreed(Some(json!({ "label": "x" })));
+        assert_eq!(policy(ConsensusPolicy::Majority).resolve(&split), agreed);
+        assert_eq!(
+            policy(ConsensusPolicy::Unanimous).resolve(&split),
+            Resolution::Disputed
+        );
+        assert_eq!(policy(ConsensusPolicy::Unanimous).resolve(&same), agreed);
+        assert_eq!(
+            policy(ConsensusPolicy::Adjudication).resolve(&same),
+            Resolution::Disputed
+        );
+
+        let three_ways = [submit("a", "x"), submit("b", "y"), submit("c", "z")];
+        assert_eq!(
+            policy(ConsensusPolicy::Majority).resolve(&three_ways),
+            Resolution::Disputed
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! Differences between two spans, for comparing a replay with its original
+//! or two versions of a prompt.
+//!
+//! Chat payloads (requests with `messages`, OpenAI and Anthropic
+//! completions) are compared message by message; anything else is compared
+//! line by line as text, with JSON pretty-printed first.
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{Span, SpanId, SpanStatus};
+
+/// Past this many cells in the comparison table, the part of two payloads
+/// between their common prefix and suffix is reported as replaced
+/// outright rather than diffed.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    /// Only in `a`.
+    Removed,
+    /// Only in `b`.
+    Added,
+    /// A message in the same place and with the same role in both, whose
+    /// content differs.
+    Changed,
+}
+
+/// Consecutive lines with the same op.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct TextHunk {
+    pub op: DiffOp,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MessageDiff {
+    pub op: DiffOp,
+    pub role: String,
+    /// Position in `a`'s messages; `None` when added.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a_index: Option<usize>,
+    /// Position in `b`'s messages; `None` when removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b_index: Option<usize>,
+    /// The message's content, line by line. A single hunk unless `changed`.
+    pub content: Vec<TextHunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PayloadDiff {
+    Messages { messages: Vec<MessageDiff> },
+    Text { hunks: Vec<TextHunk> },
+}
+
+/// One compared field: the span's kind, status, or duration, or an
+/// attribute of its kind such as `model` or `input_tokens`.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldDiff {
+    pub field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b: Option<Value>,
+    pub changed: bool,
+    /// `b - a` when both are numbers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SpanDiff {
+    #[schema(value_type = String)]
+    pub a: SpanId,
+    #[schema(value_type = String)]
+    pub b: SpanId,
+    /// Kind, status, duration, then kind attributes, `a`'s first.
+    pub fields: Vec<FieldDiff>,
+    /// `None` when neither span has an input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<PayloadDiff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PayloadDiff>,
+}
+
+/// A span and the payloads to compare it by, which may have been loaded
+/// from blob storage rather than read off the span.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffSide<'a> {
+    pub span: &'a Span,
+    pub input: Option<&'a Value>,
+    pub output: Option<&'a Value>,
+}
+
+impl<'a> DiffSide<'a> {
+    /// `span` with the payloads it carries.
+    pub fn new(span: &'a Span) -> Self {
+        Self {
+            span,
+            input: span.input(),
+            output: span.output(),
+        }
+    }
+}
+
+impl SpanDiff {
+    pub fn between(a: DiffSide<'_>, b: DiffSide<'_>) -> Self {
+        Self {
+            a: a.span.id(),
+            b: b.span.id(),
+            fields: diff_fields(&fields(a.span), &fields(b.span)),
+            input: diff_payloads(a.input, b.input),
+            output: diff_payloads(a.output, b.output),
+        }
+    }
+}
+
+/// Fields compared between spans, in order. Previews are left out: they
+/// repeat the payloads, which are diffed in full.
+fn fields(span: &Span) -> Vec<(String, Value)> {
+    let mut out = vec![
+        ("kind".to_string(), Value::from(span.kind().kind_name())),
+        ("status".to_string(), Value::from(span.status().as_str())),
+    ];
+    if let SpanStatus::Failed { error } = span.status() {
+        out.push(("error".to_string(), Value::from(error.as_str())));
+    }
+    if let Some(ms) = span.duration_ms() {
+        out.push(("duration_ms".to_string(), Value::from(ms)));
+    }
+    if let Ok(Value::Object(kind)) = serde_json::to_value(span.kind()) {
+        for (key, value) in kind {
+            match value {
+                // `kind` is already in; custom spans also name it in `kind`
+                _ if key == "type" || key == "kind" || key.ends_with("_preview") => {}
+                Value::Object(attributes) if key == "attributes" => out.extend(
+                    attributes
+                        .into_iter()
+                        .map(|(name, v)| (format!("attributes.{name}"), v)),
+                ),
+                value => out.push((key, value)),
+            }
+        }
+    }
+    if let Some(total) = span.kind().total_tokens() {
+        out.push(("total_tokens".to_string(), Value::from(total)));
+    }
+    out
+}
+
+fn diff_fields(a: &[(String, Value)], b: &[(String, Value)]) -> Vec<FieldDiff> {
+    let mut names: Vec<&str> = a.iter().map(|(n, _)| n.as_str()).collect();
+    for (name, _) in b {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    let get = |side: &[(String, Value)], name: &str| {
+        side.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let (a_value, b_value) = (get(a, name), get(b, name));
+            let number = |v: &Option<Value>| v.as_ref().and_then(Value::as_f64);
+            let delta = match (number(&a_value), number(&b_value)) {
+                (Some(x), Some(y)) => Some(y - x),
+                _ => None,
+            };
+            FieldDiff {
+                field: name.to_string(),
+                changed: a_value != b_value,
+                a: a_value,
+                b: b_value,
+                delta,
+            }
+        })
+        .collect()
+}
+
+fn diff_payloads(a: Option<&Value>, b: Option<&Value>) -> Option<PayloadDiff> {
+    if a.is_none() && b.is_none() {
+        return None;
+    }
+    if let (Some(ma), Some(mb)) = (a.and_then(chat_messages), b.and_then(chat_messages)) {
+        return Some(PayloadDiff::Messages {
+            messages: diff_messages(&ma, &mb),
+        });
+    }
+    let lines = |v: Option<&Value>| v.map(|v| text_lines(&payload_text(v))).unwrap_or_default();
+    Some(PayloadDiff::Text {
+        hunks: diff_lines(&lines(a), &lines(b)),
+    })
+}
+
+fn payload_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => serde_json::to_string_pretty(other).unwrap_or_default(),
+    }
+}
+
+fn text_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+/// `(role, content)` of a message. Content blocks are joined by newlines,
+/// text as-is and others (images, tool use) as JSON; tool calls are
+/// appended as JSON.
+fn message(value: &Value) -> Option<(String, String)> {
+    let role = value.get("role")?.as_str()?.to_string();
+    let mut parts = Vec::new();
+    match value.get("content") {
+        Some(Value::String(s)) => parts.push(s.clone()),
+        Some(Value::Array(blocks)) => {
+            parts.extend(blocks.iter().map(
+                |block| match block.get("text").and_then(Value::as_str) {
+                    Some(text) => text.to_string(),
+                    None => block.to_string(),
+                },
+            ))
+        }
+        _ => {}
+    }
+    if let Some(calls) = value.get("tool_calls").filter(|c| !c.is_null()) {
+        parts.push(format!("tool_calls: {calls}"));
+    }
+    Some((role, parts.join("\n")))
+}
+
+/// The messages of a chat request (with an Anthropic `system` prompt
+/// first) or completion. `None` when `value` isn't one.
+fn chat_messages(value: &Value) -> Option<Vec<(String, String)>> {
+    if let Some(messages) = value.get("messages").and_then(Value::as_array) {
+        let system = match value.get("system") {
+            Some(Value::String(s)) => Some(("system".to_string(), s.clone())),
+            Some(blocks @ Value::Array(_)) => message(&serde_json::json!({
+                "role": "system",
+                "content": blocks,
+            })),
+            _ => None,
+        };
+        return system
+            .into_iter()
+            .map(Some)
+            .chain(messages.iter().map(message))
+            .collect();
+    }
+    if let Some(choices) = value.get("choices").and_then(Value::as_array) {
+        return choices.iter().map(|c| message(c.get("message")?)).collect();
+    }
+    if value.get("role").is_some() && value.get("content").is_some() {
+        return message(value).map(|m| vec![m]);
+    }
+    None
+}
+
+fn diff_messages(a: &[(String, String)], b: &[(String, String)]) -> Vec<MessageDiff> {
+    let whole = |op, text: &str| {
+        vec![TextHunk {
+            op,
+            lines: text_lines(text),
+        }]
+    };
+    let mut out = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    // Removals and additions between two matched messages are paired up
+    // in order; pairs with the same role become `changed`.
+    let flush = |removed: &mut Vec<usize>, added: &mut Vec<usize>, out: &mut Vec<MessageDiff>| {
+        for (k, &i) in removed.iter().enumerate() {
+            match added.get(k).filter(|&&j| a[i].0 == b[j].0) {
+                Some(&j) => out.push(MessageDiff {
+                    op: DiffOp::Changed,
+                    role: a[i].0.clone(),
+                    a_index: Some(i),
+                    b_index: Some(j),
+                    content: diff_lines(&text_lines(&a[i].1), &text_lines(&b[j].1)),
+                }),
+                None => out.push(MessageDiff {
+                    op: DiffOp::Removed,
+                    role: a[i].0.clone(),
+                    a_index: Some(i),
+                    b_index: None,
+                    content: whole(DiffOp::Removed, &a[i].1),
+                }),
+            }
+        }
+        for (k, &j) in added.iter().enumerate() {
+            let was_paired = removed.get(k).is_some_and(|&i| a[i].0 == b[j].0);
+            if !was_paired {
+                out.push(MessageDiff {
+                    op: DiffOp::Added,
+                    role: b[j].0.clone(),
+                    a_index: None,
+                    b_index: Some(j),
+                    content: whole(DiffOp::Added, &b[j].1),
+                });
+            }
+        }
+        removed.clear();
+        added.clear();
+    };
+    for edit in edit_script(a, b) {
+        match edit {
+            Edit::Equal(i, j) => {
+                flush(&mut removed, &mut added, &mut out);
+                out.push(MessageDiff {
+                    op: DiffOp::Equal,
+                    role: a[i].0.clone(),
+                    a_index: Some(i),
+                    b_index: Some(j),
+                    content: whole(DiffOp::Equal, &a[i].1),
+                });
+            }
+            Edit::Removed(i) => removed.push(i),
+            Edit::Added(j) => added.push(j),
+        }
+    }
+    flush(&mut removed, &mut added, &mut out);
+    out
+}
+
+fn diff_lines(a: &[String], b: &[String]) -> Vec<TextHunk> {
+    let mut hunks: Vec<TextHunk> = Vec::new();
+    for edit in edit_script(a, b) {
+        let (op, line) = match edit {
+            Edit::Equal(i, _) => (DiffOp::Equal, &a[i]),
+            Edit::Removed(i) => (DiffOp::Removed, &a[i]),
+            Edit::Added(j) => (DiffOp::Added, &b[j]),
+        };
+        match hunks.last_mut() {
+            Some(hunk) if hunk.op == op => hunk.lines.push(line.clone()),
+            _ => hunks.push(TextHunk {
+                op,
+                lines: vec![line.clone()],
+            }),
+        }
+    }
+    hunks
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// A shortest edit script from `a` to `b` by longest common subsequence,
+/// with removals ahead of additions wherever both fall between the same
+/// pair of matches.
+fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut script: Vec<Edit> = (0..prefix).map(|i| Edit::Equal(i, i)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        script.extend((0..n).map(|i| Edit::Removed(prefix + i)));
+        script.extend((0..m).map(|j| Edit::Added(prefix + j)));
+    } else {
+        // lcs[i][j]: length of the LCS of a_mid[i..] and b_mid[j..]
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * (m + 1) + j] = if a_mid[i] == b_mid[j] {
+                    lcs[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        let mut added = Vec::new();
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                script.append(&mut added);
+                script.push(Edit::Equal(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if j < m && (i == n || lcs[i * (m + 1) + j + 1] > lcs[(i + 1) * (m + 1) + j]) {
+                added.push(Edit::Added(prefix + j));
+                j += 1;
+            } else {
+                script.push(Edit::Removed(prefix + i));
+                i += 1;
+            }
+        }
+        script.append(&mut added);
+    }
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+    script.extend((0..suffix).map(|k| Edit::Equal(a_end + k, b_end + k)));
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::SpanKind;
+
+    fn llm_span(
+        model: &str,
+        output_tokens: u64,
+        latency_ms: i64,
+        input: Value,
+        output: Value,
+    ) -> Span {
+        let start = Utc::now();
+        Span::from_parts(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            None,
+            None,
+            "chat".into(),
+            SpanKind::LlmCall {
+                model: model.into(),
+                provider: Some("openai".into()),
+                input_tokens: Some(100),
+                output_tokens: Some(output_tokens),
+                cost: None,
+                input_preview: None,
+                output_preview: None,
+            },
+            SpanStatus::Completed,
+            start,
+            Some(start + Duration::milliseconds(latency_ms)),
+            Some(input),
+            Some(output),
+        )
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text_lines(text)
+    }
+
+    #[test]
+    fn line_diffs_group_runs_and_put_removals_first() {
+        let hunks = diff_lines(&lines("a\nb\nc\nd"), &lines("a\nx\ny\nd\ne"));
+        let ops: Vec<(DiffOp, usize)> = hunks.iter().map(|h| (h.op, h.lines.len())).collect();
+        assert_eq!(
+            ops,
+            [
+                (DiffOp::Equal, 1),
+                (DiffOp::Removed, 2),
+                (DiffOp::Added, 2),
+                (DiffOp::Equal, 1),
+                (DiffOp::Added, 1),
+            ]
+        );
+        assert_eq!(hunks[2].lines, ["x", "y"]);
+        assert!(diff_lines(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn chat_spans_diff_by_message_and_field() {
+        let a = llm_span(
+            "gpt-4o",
+            20,
+            800,
+            json!({ "messages": [
+                { "role": "system", "content": "Be terse." },
+                { "role": "user", "content": "Summarize:\nthe report" },
+            ]}),
+            json!({ "choices": [{ "message": { "role": "assistant", "content": "Fine." } }] }),
+        );
+        let b = llm_span(
+            "gpt-4o-mini",
+            35,
+            500,
+            json!({ "messages": [
+                { "role": "system", "content": "Be terse." },
+                { "role": "user", "content": "Summarize:\nthe memo" },
+                { "role": "user", "content": "In French." },
+            ]}),
+            json!({ "choices": [{ "message": { "role": "assistant", "content": "Bien." } }] }),
+        );
+        let diff = SpanDiff::between(DiffSide::new(&a), DiffSide::new(&b));
+
+        let Some(PayloadDiff::Messages { messages }) = &diff.input else {
+            panic!("input not diffed by message: {:?}", diff.input);
+        };
+        let ops: Vec<DiffOp> = messages.iter().map(|m| m.op).collect();
+        assert_eq!(ops, [DiffOp::Equal, DiffOp::Changed, DiffOp::Added]);
+        assert_eq!(messages[1].content[0].lines, ["Summarize:"]);
+        assert_eq!(messages[1].content[1].op, DiffOp::Removed);
+        assert_eq!(messages[2].b_index, Some(2));
+        assert!(
+            matches!(&diff.output, Some(PayloadDiff::Messages { messages }) if messages[0].op == DiffOp::Changed)
+        );
+
+        let field = |name: &str| diff.fields.iter().find(|f| f.field == name).unwrap();
+        assert!(!field("status").changed);
+        assert_eq!(field("duration_ms").delta, Some(-300.0));
+        assert_eq!(field("model").b, Some(json!("gpt-4o-mini")));
+        assert_eq!(field("output_tokens").delta, Some(15.0));
+        assert!(!field("provider").changed);
+        assert_eq!(field("kind").a, Some(json!("llm_call")));
+    }
+
+    #[test]
+    fn other_payloads_diff_as_pretty_json() {
+        let hunks = match diff_payloads(
+            Some(&json!({ "x": 1, "y": 2 })),
+            Some(&json!({ "x": 1, "y": 3 })),
+        ) {
+            Some(PayloadDiff::Text { hunks }) => hunks,
+            other => panic!("not a text diff: {other:?}"),
+        };
+        assert_eq!(hunks[1].lines, ["  \"y\": 2"]);
+        assert_eq!(hunks[2].lines, ["  \"y\": 3"]);
+        assert_eq!(diff_payloads(None, None), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Source control metadata attached to traces.
+
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Trace;
+
+/// The commit, branch, and repository a trace's code came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitInfo {
+    pub commit: Option<String>,
+    pub branch: Option<String>,
+    pub repo: Option<String>,
+}
+
+impl GitInfo {
+    /// Ask `git` about the checkout containing `dir`. Everything is `None`
+    /// outside a repository or without `git` installed. A detached HEAD has
+    /// no branch.
+    pub fn detect(dir: &Path) -> Self {
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+            (!value.is_empty() && value != "HEAD").then_some(value)
+        };
+        Self {
+            commit: git(&["rev-parse", "HEAD"]),
+            branch: git(&["rev-parse", "--abbrev-ref", "HEAD"]),
+            repo: git(&["config", "--get", "remote.origin.url"]),
+        }
+    }
+
+    /// Detect from the process's working directory.
+    pub fn from_current_dir() -> Self {
+        std::env::current_dir()
+            .map(|dir| Self::detect(&dir))
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commit.is_none() && self.branch.is_none() && self.repo.is_none()
+    }
+
+    /// Fill in whichever of `trace`'s git fields are unset.
+    pub fn apply(&self, trace: &mut Trace) {
+        if trace.git_commit.is_none() {
+            trace.git_commit = self.commit.clone();
+        }
+        if trace.git_branch.is_none() {
+            trace.git_branch = self.branch.clone();
+        }
+        if trace.repo.is_none() {
+            trace.repo = self.repo.clone();
+        }
+    }
+}
@@ -1,22 +1,32 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+pub mod consensus;
+pub mod diff;
+pub mod git;
 pub mod pricing;
+pub mod tree;
 
 pub type SpanId = Uuid;
 pub type TraceId = Uuid;
 pub type DatasetId = Uuid;
 pub type DatapointId = Uuid;
 pub type QueueItemId = Uuid;
+pub type QueueSubmissionId = Uuid;
 pub type EvalRunId = Uuid;
 pub type EvalResultId = Uuid;
 pub type CaptureRuleId = Uuid;
 pub type ProviderConnectionId = Uuid;
 pub type OrgId = Uuid;
+pub type WebhookId = Uuid;
+pub type WebhookDeliveryId = Uuid;
+pub type AuditEventId = Uuid;
+pub type AnnotationId = Uuid;
+pub type SavedViewId = Uuid;
 
 // --- SpanKind: typed span variants ---
 
@@ -50,6 +60,44 @@ pub enum SpanKind {
         #[serde(skip_serializing_if = "Option::is_none")]
         output_preview: Option<String>,
     },
+    /// A tool the model asked for, usually a child of that `LlmCall`.
+    ToolCall {
+        tool_name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        arguments: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result_preview: Option<String>,
+    },
+    /// One step of an agent loop, such as "plan", "act" or "observe".
+    AgentStep {
+        step_type: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        iteration: Option<u32>,
+    },
+    /// A search over an index, such as the retrieval step of a RAG pipeline.
+    Retrieval {
+        index: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        query_preview: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        top_k: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        num_results: Option<u64>,
+    },
+    Embedding {
+        model: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input_count: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dimensions: Option<u64>,
+    },
+    /// A moderation or validation check, e.g. on model output.
+    Guardrail {
+        name: String,
+        verdict: GuardrailVerdict,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        categories: Vec<String>,
+    },
     Custom {
         kind: String,
         #[serde(default)]
@@ -63,13 +111,39 @@ impl SpanKind {
             SpanKind::FsRead { .. } => "fs_read",
             SpanKind::FsWrite { .. } => "fs_write",
             SpanKind::LlmCall { .. } => "llm_call",
+            SpanKind::ToolCall { .. } => "tool_call",
+            SpanKind::AgentStep { .. } => "agent_step",
+            SpanKind::Retrieval { .. } => "retrieval",
+            SpanKind::Embedding { .. } => "embedding",
+            SpanKind::Guardrail { .. } => "guardrail",
             SpanKind::Custom { kind, .. } => kind,
         }
     }
 
+    pub fn tool_name(&self) -> Option<&str> {
+        match self {
+            SpanKind::ToolCall { tool_name, .. } => Some(tool_name),
+            _ => None,
+        }
+    }
+
+    pub fn index(&self) -> Option<&str> {
+        match self {
+            SpanKind::Retrieval { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+
+    pub fn verdict(&self) -> Option<GuardrailVerdict> {
+        match self {
+            SpanKind::Guardrail { verdict, .. } => Some(*verdict),
+            _ => None,
+        }
+    }
+
     pub fn model(&self) -> Option<&str> {
         match self {
-            SpanKind::LlmCall { model, .. } => Some(model),
+            SpanKind::LlmCall { model, .. } | SpanKind::Embedding { model, .. } => Some(model),
             _ => None,
         }
     }
@@ -111,9 +185,26 @@ impl SpanKind {
         }
     }
 
+    /// A custom span's attribute, as text: strings as-is, other values as
+    /// JSON.
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        match self {
+            SpanKind::Custom { attributes, .. } => attributes.get(name).map(|v| match v {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Cost in USD. Custom spans carry cost in their `cost` attribute
+    /// (filled in at ingestion from the org's registered cost formula).
     pub fn cost(&self) -> Option<f64> {
         match self {
             SpanKind::LlmCall { cost, .. } => *cost,
+            SpanKind::Custom { attributes, .. } => {
+                attributes.get("cost").and_then(|v| v.as_f64())
+            }
             _ => None,
         }
     }
@@ -121,6 +212,12 @@ impl SpanKind {
     /// If this is an LlmCall with token counts but no cost, estimate cost
     /// from the model pricing table and fill it in. Returns self (mutated).
     pub fn with_estimated_cost(self) -> Self {
+        self.with_cost_from(&pricing::PricingTable::default())
+    }
+
+    /// Like [`SpanKind::with_estimated_cost`], but prices against the given
+    /// table (built-in defaults plus any configured overrides).
+    pub fn with_cost_from(self, table: &pricing::PricingTable) -> Self {
         match self {
             SpanKind::LlmCall {
                 model,
@@ -131,8 +228,9 @@ impl SpanKind {
                 input_preview,
                 output_preview,
             } => {
-                let final_cost =
-                    cost.or_else(|| pricing::estimate_cost(&model, input_tokens, output_tokens));
+                let final_cost = cost.or_else(|| {
+                    table.estimate(&model, provider.as_deref(), input_tokens, output_tokens)
+                });
                 SpanKind::LlmCall {
                     model,
                     provider,
@@ -148,6 +246,25 @@ impl SpanKind {
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardrailVerdict {
+    Pass,
+    Fail,
+    /// Let through, but marked for review.
+    Flag,
+}
+
+impl GuardrailVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GuardrailVerdict::Pass => "pass",
+            GuardrailVerdict::Fail => "fail",
+            GuardrailVerdict::Flag => "flag",
+        }
+    }
+}
+
 // --- SpanStatus: simplified (timestamps live on Span) ---
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
@@ -186,6 +303,10 @@ pub struct Span {
     #[schema(value_type = Option<String>)]
     parent_id: Option<SpanId>,
     name: String,
+    /// `name` rewritten by the org's normalization rules, when they changed
+    /// it. Set by the store on ingest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name_normalized: Option<String>,
     kind: SpanKind,
     status: SpanStatus,
     started_at: DateTime<Utc>,
@@ -194,6 +315,10 @@ pub struct Span {
     input: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     output: Option<serde_json::Value>,
+    /// The stored version named by an fs span's `file_version`. Attached by
+    /// the store on ingest and read; not persisted with the span.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<FileVersion>,
 }
 
 impl Span {
@@ -218,13 +343,48 @@ impl Span {
             org_id,
             parent_id,
             name,
+            name_normalized: None,
             kind,
             status,
             started_at,
             ended_at,
             input,
             output,
+            file: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: FileVersion) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    pub fn with_name_normalized(mut self, name: Option<String>) -> Self {
+        self.name_normalized = name;
+        self
+    }
+
+    /// Apply `f` to the captured payloads: `input`, `output`, and an LLM
+    /// call's previews (passed as JSON strings).
+    pub fn map_payloads(mut self, mut f: impl FnMut(&mut serde_json::Value)) -> Self {
+        for payload in [&mut self.input, &mut self.output].into_iter().flatten() {
+            f(payload);
+        }
+        if let SpanKind::LlmCall {
+            input_preview,
+            output_preview,
+            ..
+        } = &mut self.kind
+        {
+            for preview in [input_preview, output_preview].into_iter().flatten() {
+                let mut value = serde_json::Value::String(std::mem::take(preview));
+                f(&mut value);
+                if let serde_json::Value::String(text) = value {
+                    *preview = text;
+                }
+            }
         }
+        self
     }
 }
 
@@ -250,6 +410,15 @@ impl Span {
         &self.name
     }
 
+    pub fn name_normalized(&self) -> Option<&str> {
+        self.name_normalized.as_deref()
+    }
+
+    /// The name spans are grouped by: normalized if rules applied, else raw.
+    pub fn group_name(&self) -> &str {
+        self.name_normalized.as_deref().unwrap_or(&self.name)
+    }
+
     pub fn kind(&self) -> &SpanKind {
         &self.kind
     }
@@ -274,6 +443,10 @@ impl Span {
         self.output.as_ref()
     }
 
+    pub fn file(&self) -> Option<&FileVersion> {
+        self.file.as_ref()
+    }
+
     pub fn duration_ms(&self) -> Option<i64> {
         self.ended_at
             .map(|end| (end - self.started_at).num_milliseconds())
@@ -352,12 +525,14 @@ impl SpanBuilder {
             org_id: self.org_id,
             parent_id: self.parent_id,
             name: self.name,
+            name_normalized: None,
             kind: self.kind,
             status: SpanStatus::Running,
             started_at: Utc::now(),
             ended_at: None,
             input: self.input,
             output: None,
+            file: None,
         }
     }
 }
@@ -380,6 +555,27 @@ pub struct Trace {
     pub ended_at: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub machine_id: Option<String>,
+    /// Session or conversation the trace belongs to, as named by the caller.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The caller's end user, for attribution.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Commit the traced code was built from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_branch: Option<String>,
+    /// Repository the commit belongs to, usually its remote URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Rollup of the trace's spans, maintained by the store.
+    #[serde(default)]
+    pub stats: TraceStats,
+    /// When the trace was moved to the trash; trashed traces are hidden
+    /// from queries until restored or purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Trace {
@@ -392,6 +588,13 @@ impl Trace {
             started_at: Utc::now(),
             ended_at: None,
             machine_id: None,
+            session_id: None,
+            user_id: None,
+            git_commit: None,
+            git_branch: None,
+            repo: None,
+            stats: TraceStats::default(),
+            deleted_at: None,
         }
     }
 
@@ -409,6 +612,86 @@ impl Trace {
         self.ended_at = Some(Utc::now());
         self
     }
+
+    /// When the trace finished: its `ended_at`, or else the last span end
+    /// once it has spans and none are running.
+    pub fn completed_at(&self) -> Option<DateTime<Utc>> {
+        if self.ended_at.is_some() {
+            return self.ended_at;
+        }
+        let stats = &self.stats;
+        if stats.span_count > 0 && stats.running_count == 0 {
+            stats.last_ended_at
+        } else {
+            None
+        }
+    }
+}
+
+/// Span counts, tokens, cost, and time bounds for one trace. Updated
+/// incrementally: a span write removes its previous version and adds the
+/// new one. Time bounds only widen, since a removed span's start or end
+/// can't be un-merged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TraceStats {
+    pub span_count: u64,
+    pub running_count: u64,
+    pub error_count: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_cost: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_ended_at: Option<DateTime<Utc>>,
+}
+
+impl TraceStats {
+    pub fn from_spans<'a>(spans: impl IntoIterator<Item = &'a Span>) -> Self {
+        let mut stats = Self::default();
+        for span in spans {
+            stats.add(span);
+        }
+        stats
+    }
+
+    pub fn add(&mut self, span: &Span) {
+        self.span_count += 1;
+        match span.status() {
+            SpanStatus::Running => self.running_count += 1,
+            SpanStatus::Failed { .. } => self.error_count += 1,
+            SpanStatus::Completed => {}
+        }
+        self.input_tokens += span.kind().input_tokens().unwrap_or(0);
+        self.output_tokens += span.kind().output_tokens().unwrap_or(0);
+        self.total_cost += span.kind().cost().unwrap_or(0.0);
+        let start = span.started_at();
+        self.first_started_at = Some(self.first_started_at.map_or(start, |t| t.min(start)));
+        if let Some(end) = span.ended_at() {
+            self.last_ended_at = Some(self.last_ended_at.map_or(end, |t| t.max(end)));
+        }
+    }
+
+    pub fn remove(&mut self, span: &Span) {
+        self.span_count = self.span_count.saturating_sub(1);
+        match span.status() {
+            SpanStatus::Running => self.running_count = self.running_count.saturating_sub(1),
+            SpanStatus::Failed { .. } => self.error_count = self.error_count.saturating_sub(1),
+            SpanStatus::Completed => {}
+        }
+        self.input_tokens = self
+            .input_tokens
+            .saturating_sub(span.kind().input_tokens().unwrap_or(0));
+        self.output_tokens = self
+            .output_tokens
+            .saturating_sub(span.kind().output_tokens().unwrap_or(0));
+        self.total_cost = (self.total_cost - span.kind().cost().unwrap_or(0.0)).max(0.0);
+    }
+
+    /// Wall-clock time from the first span start to the last span end.
+    pub fn duration_ms(&self) -> Option<i64> {
+        Some((self.last_ended_at? - self.first_started_at?).num_milliseconds())
+    }
 }
 
 // --- File model types ---
@@ -440,6 +723,11 @@ pub fn content_hash(bytes: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Whether `s` has the shape `content_hash` produces: 64 lowercase hex digits.
+pub fn is_content_hash(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
 // --- Dataset types ---
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -491,6 +779,13 @@ pub struct Dataset {
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// When the dataset was moved to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Incremented by every update; conditional writes compare against it
+    /// so concurrent edits can't silently overwrite each other.
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Dataset {
@@ -503,6 +798,8 @@ impl Dataset {
             description,
             created_at: now,
             updated_at: now,
+            deleted_at: None,
+            version: 0,
         }
     }
 
@@ -524,6 +821,9 @@ pub struct Datapoint {
     #[schema(value_type = Option<String>)]
     pub source_span_id: Option<SpanId>,
     pub created_at: DateTime<Utc>,
+    /// Incremented by every update, like [`Dataset::version`].
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Datapoint {
@@ -535,6 +835,7 @@ impl Datapoint {
             source,
             source_span_id: None,
             created_at: Utc::now(),
+            version: 0,
         }
     }
 
@@ -550,6 +851,11 @@ pub enum QueueItemStatus {
     Pending,
     Claimed,
     Completed,
+    Rejected,
+    /// Waiting on submissions from the assigned reviewers.
+    InReview,
+    /// Reviewers disagreed; waiting on an adjudicator.
+    Adjudication,
 }
 
 impl QueueItemStatus {
@@ -558,6 +864,9 @@ impl QueueItemStatus {
             QueueItemStatus::Pending => "pending",
             QueueItemStatus::Claimed => "claimed",
             QueueItemStatus::Completed => "completed",
+            QueueItemStatus::Rejected => "rejected",
+            QueueItemStatus::InReview => "in_review",
+            QueueItemStatus::Adjudication => "adjudication",
         }
     }
 }
@@ -579,6 +888,16 @@ pub struct QueueItem {
     pub original_data: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub edited_data: Option<serde_json::Value>,
+    /// Why the labeler rejected the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_reason: Option<String>,
+    /// When the item was completed or rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Set when the item is reviewed by several people instead of claimed
+    /// by one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewPolicy>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -597,6 +916,9 @@ impl QueueItem {
             claimed_at: None,
             original_data,
             edited_data: None,
+            rejected_reason: None,
+            resolved_at: None,
+            review: None,
             created_at: Utc::now(),
         }
     }
@@ -611,8 +933,88 @@ impl QueueItem {
     pub fn complete(mut self, edited_data: Option<serde_json::Value>) -> Self {
         self.status = QueueItemStatus::Completed;
         self.edited_data = edited_data;
+        self.resolved_at = Some(Utc::now());
         self
     }
+
+    /// Put a claimed item back in the queue for anyone to claim.
+    pub fn release(mut self) -> Self {
+        self.status = QueueItemStatus::Pending;
+        self.claimed_by = None;
+        self.claimed_at = None;
+        self
+    }
+
+    pub fn reject(mut self, reason: impl Into<String>) -> Self {
+        self.status = QueueItemStatus::Rejected;
+        self.rejected_reason = Some(reason.into());
+        self.resolved_at = Some(Utc::now());
+        self
+    }
+
+    pub fn assign_reviewers(mut self, review: ReviewPolicy) -> Self {
+        self.status = QueueItemStatus::InReview;
+        self.review = Some(review);
+        self
+    }
+
+    /// Hand a disputed item to an adjudicator.
+    pub fn escalate(mut self) -> Self {
+        self.status = QueueItemStatus::Adjudication;
+        self
+    }
+}
+
+/// How the final data is chosen from reviewers' submissions.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusPolicy {
+    /// More than half the reviewers submitted the same data.
+    #[default]
+    Majority,
+    /// Every reviewer submitted the same data.
+    Unanimous,
+    /// An adjudicator always picks the final data.
+    Adjudication,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ReviewPolicy {
+    pub reviewers: Vec<String>,
+    #[serde(default)]
+    pub consensus: ConsensusPolicy,
+}
+
+/// One reviewer's submission for a queue item under review.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueueSubmission {
+    #[schema(value_type = String)]
+    pub id: QueueSubmissionId,
+    #[schema(value_type = String)]
+    pub queue_item_id: QueueItemId,
+    pub reviewer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_data: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl QueueSubmission {
+    /// The id is derived from the item and reviewer, so a reviewer has at
+    /// most one submission per item.
+    pub fn new(
+        queue_item_id: QueueItemId,
+        reviewer: impl Into<String>,
+        edited_data: Option<serde_json::Value>,
+    ) -> Self {
+        let reviewer = reviewer.into();
+        Self {
+            id: Uuid::new_v5(&queue_item_id, reviewer.as_bytes()),
+            queue_item_id,
+            reviewer,
+            edited_data,
+            created_at: Utc::now(),
+        }
+    }
 }
 
 // --- Analytics types ---
@@ -636,9 +1038,35 @@ pub enum AnalyticsMetric {
     AvgLatencyMs,
     SpanCount,
     ErrorCount,
+    /// Most spans running at the same instant.
+    MaxConcurrency,
+    /// Delay from a parent span's start to each child's start.
+    AvgQueueGapMs,
+    MaxQueueGapMs,
+    P50LatencyMs,
+    P95LatencyMs,
+    P99LatencyMs,
+    /// Span counts per latency bucket (see `LATENCY_BUCKETS_MS`).
+    LatencyHistogram,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+/// Upper bounds, in milliseconds, of the latency histogram buckets. A last
+/// bucket with no upper bound catches the rest.
+pub const LATENCY_BUCKETS_MS: &[f64] = &[
+    10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1_000.0, 2_500.0, 5_000.0, 10_000.0, 30_000.0, 60_000.0,
+];
+
+/// Finished spans with a latency in `(min_ms, max_ms]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HistogramBucket {
+    pub min_ms: f64,
+    /// Absent for the last, unbounded bucket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ms: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum GroupByField {
     Model,
@@ -648,6 +1076,18 @@ pub enum GroupByField {
     Trace,
     Day,
     Hour,
+    /// Span name, normalized where rules apply.
+    Name,
+    /// Tool name of `tool_call` spans.
+    Tool,
+    /// Index of `retrieval` spans.
+    Index,
+    /// Tags of the span's trace. A span counts once under each tag, so
+    /// groups can sum to more than the totals.
+    Tag,
+    /// A custom span attribute, by name (`{"attribute": "env"}`). Values
+    /// that aren't strings group by their JSON text.
+    Attribute(String),
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
@@ -697,6 +1137,20 @@ pub struct MetricValues {
     pub span_count: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_queue_gap_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queue_gap_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_histogram: Option<Vec<HistogramBucket>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -729,6 +1183,291 @@ pub struct ModelTokens {
     pub total_tokens: u64,
 }
 
+/// Fixed bucket width for time-bucketed analytics.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub enum AnalyticsInterval {
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[default]
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl AnalyticsInterval {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            Self::Minute => chrono::Duration::minutes(1),
+            Self::FiveMinutes => chrono::Duration::minutes(5),
+            Self::Hour => chrono::Duration::hours(1),
+            Self::Day => chrono::Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket containing `t`, aligned to the Unix epoch.
+    pub fn bucket_start(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let width = self.duration().num_milliseconds();
+        let ms = t.timestamp_millis();
+        DateTime::from_timestamp_millis(ms - ms.rem_euclid(width)).unwrap_or(t)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConcurrencyQuery {
+    #[serde(default)]
+    pub interval: AnalyticsInterval,
+    #[serde(default)]
+    pub filter: AnalyticsFilter,
+    /// Number of traces to return, busiest first. Defaults to 20.
+    #[serde(default)]
+    pub trace_limit: Option<usize>,
+}
+
+/// Span concurrency over one time bucket. Running spans count as busy up
+/// to the time of the query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ConcurrencyBucket {
+    pub start: DateTime<Utc>,
+    pub max_concurrency: u64,
+    /// Time-weighted mean number of running spans.
+    pub avg_concurrency: f64,
+    pub spans_started: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TraceConcurrency {
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    pub span_count: u64,
+    pub max_concurrency: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_queue_gap_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queue_gap_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConcurrencyResponse {
+    pub interval: AnalyticsInterval,
+    pub max_concurrency: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_queue_gap_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queue_gap_ms: Option<f64>,
+    pub buckets: Vec<ConcurrencyBucket>,
+    pub traces: Vec<TraceConcurrency>,
+}
+
+/// Body for `POST /api/analytics/timeseries`. `filter.since`/`until` set
+/// the range; it is widened to whole buckets.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TimeseriesQuery {
+    #[serde(default)]
+    pub interval: AnalyticsInterval,
+    pub metrics: Vec<AnalyticsMetric>,
+    #[serde(default)]
+    pub group_by: Vec<GroupByField>,
+    #[serde(default)]
+    pub filter: AnalyticsFilter,
+}
+
+/// Metric values for one group, one entry per bucket.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TimeSeries {
+    pub key: HashMap<String, String>,
+    pub values: Vec<MetricValues>,
+}
+
+/// Spans are bucketed by start time. Buckets with no spans hold zeroed
+/// metrics, so every series has the same length as `buckets`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TimeseriesResponse {
+    pub interval: AnalyticsInterval,
+    /// Start of each bucket.
+    pub buckets: Vec<DateTime<Utc>>,
+    pub totals: Vec<MetricValues>,
+    /// One series per group; empty without `group_by`.
+    pub series: Vec<TimeSeries>,
+}
+
+/// One side of an experiment comparison: the spans matching `filter` and
+/// `name_contains`, limited to traces carrying every tag in `trace_tags`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct Cohort {
+    /// Shown in place of `a`/`b` by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub filter: AnalyticsFilter,
+    /// Substring of the span name, e.g. a prompt version suffix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace_tags: Vec<String>,
+}
+
+/// Body for `POST /api/analytics/compare`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompareQuery {
+    pub a: Cohort,
+    pub b: Cohort,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct LatencyPercentiles {
+    pub avg: Option<f64>,
+    pub p50: Option<f64>,
+    pub p90: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+/// Metrics for one cohort. Averages are per span; latency covers finished
+/// spans only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct CohortMetrics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub span_count: u64,
+    pub error_count: u64,
+    pub error_rate: f64,
+    pub total_cost: f64,
+    pub avg_cost: f64,
+    pub total_input_tokens: u64,
+    pub total_output_tokens: u64,
+    pub avg_tokens: f64,
+    pub latency_ms: LatencyPercentiles,
+}
+
+/// Change from cohort `a` to cohort `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MetricDelta {
+    /// `b - a`.
+    pub absolute: f64,
+    /// `(b - a) / a`; absent when `a` is zero.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative: Option<f64>,
+}
+
+impl MetricDelta {
+    pub fn between(a: f64, b: f64) -> Self {
+        Self {
+            absolute: b - a,
+            relative: (a != 0.0).then(|| (b - a) / a),
+        }
+    }
+}
+
+/// Deltas for the comparable metrics. Latency deltas are absent when either
+/// cohort has no finished spans.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompareDeltas {
+    pub span_count: MetricDelta,
+    pub error_rate: MetricDelta,
+    pub total_cost: MetricDelta,
+    pub avg_cost: MetricDelta,
+    pub avg_tokens: MetricDelta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<MetricDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_latency_ms: Option<MetricDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_latency_ms: Option<MetricDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_latency_ms: Option<MetricDelta>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompareResponse {
+    pub a: CohortMetrics,
+    pub b: CohortMetrics,
+    pub delta: CompareDeltas,
+}
+
+// --- Commit breakdown types ---
+
+/// Trace metrics for one commit. Averages are per trace; latency covers
+/// finished traces only.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CommitMetrics {
+    pub git_commit: String,
+    /// Branch of the commit's most recent trace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub trace_count: u64,
+    pub span_count: u64,
+    /// Traces with at least one failed span.
+    pub error_count: u64,
+    pub error_rate: f64,
+    pub total_cost: f64,
+    pub avg_cost: f64,
+    pub latency_ms: LatencyPercentiles,
+    /// When the commit's first trace started; commits are ordered by it.
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Change from the commit seen before this one in the same repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<CommitDeltas>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CommitDeltas {
+    /// The commit compared against.
+    pub previous_commit: String,
+    pub error_rate: MetricDelta,
+    pub avg_cost: MetricDelta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_latency_ms: Option<MetricDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_latency_ms: Option<MetricDelta>,
+}
+
+// --- Search facet types ---
+
+/// Number of traces that have a given facet value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Traces sharing a `session_id`, e.g. one conversation with an agent,
+/// rolled up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Session {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    pub trace_count: u64,
+    pub span_count: u64,
+    pub error_count: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_cost: f64,
+    /// Start of the earliest trace.
+    pub started_at: DateTime<Utc>,
+    /// The latest trace end, or start for traces still running.
+    pub last_activity_at: DateTime<Utc>,
+    pub duration_ms: i64,
+}
+
+/// Per-trace facet counts for a trace filter. Each count is the number of
+/// matching traces containing at least one span (or tag) with that value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TraceFacets {
+    pub total_traces: usize,
+    pub models: Vec<FacetCount>,
+    pub providers: Vec<FacetCount>,
+    pub statuses: Vec<FacetCount>,
+    pub tags: Vec<FacetCount>,
+    pub error_fingerprints: Vec<FacetCount>,
+}
+
 // --- Eval Pipeline types ---
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
@@ -789,6 +1528,8 @@ pub enum ScoringStrategy {
     ExactMatch,
     Contains,
     LlmJudge,
+    /// Scored by the scorers in each result's `scores`.
+    Scorers,
     None,
 }
 
@@ -798,6 +1539,7 @@ impl ScoringStrategy {
             ScoringStrategy::ExactMatch => "exact_match",
             ScoringStrategy::Contains => "contains",
             ScoringStrategy::LlmJudge => "llm_judge",
+            ScoringStrategy::Scorers => "scorers",
             ScoringStrategy::None => "none",
         }
     }
@@ -823,6 +1565,76 @@ pub struct EvalRunResults {
     pub completed: usize,
     pub failed: usize,
     pub scores: ScoreSummary,
+    /// Per-scorer summaries, keyed by scorer name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_scorer: BTreeMap<String, ScoreSummary>,
+}
+
+fn default_similarity_threshold() -> f64 {
+    0.8
+}
+
+fn default_judge_threshold() -> f64 {
+    0.5
+}
+
+/// A scorer to apply to eval results. Each compares a result's actual
+/// output with the datapoint's expected output and yields a score in
+/// `[0, 1]`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScorerSpec {
+    /// 1 if the output text equals the expected text, else 0.
+    ExactMatch {
+        #[serde(default)]
+        ignore_case: bool,
+    },
+    /// 1 if the output text matches `pattern`, else 0. Ignores the
+    /// expected output.
+    Regex { pattern: String },
+    /// Fraction of the expected JSON's leaf values present at the same
+    /// paths in the output. Passes when all are.
+    JsonSubset,
+    /// Cosine similarity of the two texts' embeddings.
+    EmbeddingSimilarity {
+        #[serde(default = "default_similarity_threshold")]
+        threshold: f64,
+    },
+    /// A model grades the output against the expected output, called
+    /// through the daemon's proxy so the judge calls are traced too.
+    LlmJudge {
+        model: String,
+        /// What the judge should grade on. Defaults to correctness.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        criteria: Option<String>,
+        #[serde(default = "default_judge_threshold")]
+        threshold: f64,
+        /// Env var holding the provider API key. Defaults to `OPENAI_API_KEY`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key_env: Option<String>,
+    },
+}
+
+impl ScorerSpec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScorerSpec::ExactMatch { .. } => "exact_match",
+            ScorerSpec::Regex { .. } => "regex",
+            ScorerSpec::JsonSubset => "json_subset",
+            ScorerSpec::EmbeddingSimilarity { .. } => "embedding_similarity",
+            ScorerSpec::LlmJudge { .. } => "llm_judge",
+        }
+    }
+}
+
+/// One scorer's verdict on one eval result.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ScorerScore {
+    pub scorer: String,
+    pub score: f64,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -914,6 +1726,8 @@ pub struct EvalResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(value_type = Option<String>)]
     pub span_id: Option<SpanId>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<ScorerScore>,
 }
 
 impl EvalResult {
@@ -931,6 +1745,7 @@ impl EvalResult {
             output_tokens: None,
             error: None,
             span_id: None,
+            scores: Vec::new(),
         }
     }
 }
@@ -1119,3 +1934,383 @@ fn mask_key(key: &str) -> String {
     let prefix = &key[..8];
     format!("{}...{}", prefix, &key[key.len() - 4..])
 }
+
+// --- Custom span kind definitions ---
+
+/// Expected type of a custom span attribute.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeType {
+    String,
+    Number,
+    Bool,
+    #[default]
+    Any,
+}
+
+impl AttributeType {
+    pub fn matches(&self, value: &serde_json::Value) -> bool {
+        match self {
+            AttributeType::String => value.is_string(),
+            AttributeType::Number => value.is_number(),
+            AttributeType::Bool => value.is_boolean(),
+            AttributeType::Any => true,
+        }
+    }
+}
+
+/// An attribute a custom span kind is expected to carry.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SpanAttributeDef {
+    pub name: String,
+    #[serde(default)]
+    pub value_type: AttributeType,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Hints for how the UI should render spans of a custom kind.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SpanKindDisplay {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Attributes to surface in span lists, in order.
+    #[serde(default)]
+    pub summary_attributes: Vec<String>,
+}
+
+/// Linear cost formula: `per_call + sum(attribute_value * rate)` in USD.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct CostFormula {
+    #[serde(default)]
+    pub per_call: f64,
+    /// Rate in USD per unit of a numeric attribute, keyed by attribute name.
+    #[serde(default)]
+    pub per_unit: HashMap<String, f64>,
+}
+
+impl CostFormula {
+    pub fn evaluate(&self, attributes: &HashMap<String, serde_json::Value>) -> f64 {
+        self.per_call
+            + self
+                .per_unit
+                .iter()
+                .filter_map(|(attr, rate)| {
+                    attributes.get(attr).and_then(|v| v.as_f64()).map(|v| v * rate)
+                })
+                .sum::<f64>()
+    }
+}
+
+/// An org-registered definition for `SpanKind::Custom` spans with a given kind name.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SpanKindDefinition {
+    /// Matches `SpanKind::Custom { kind, .. }`.
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub attributes: Vec<SpanAttributeDef>,
+    #[serde(default)]
+    pub display: SpanKindDisplay,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostFormula>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Attribute prefixes added by ingestion itself, never reported as unknown.
+const RESERVED_ATTRIBUTE_PREFIXES: &[&str] = &["resource.", "traceway."];
+
+impl SpanKindDefinition {
+    pub fn new(name: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            name: name.into(),
+            description: None,
+            attributes: Vec::new(),
+            display: SpanKindDisplay::default(),
+            cost: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Check span attributes against this definition. Returns human-readable
+    /// warnings for unknown attributes, missing required attributes, and
+    /// type mismatches. Never rejects the span.
+    pub fn validate(&self, attributes: &HashMap<String, serde_json::Value>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for def in &self.attributes {
+            match attributes.get(&def.name) {
+                None if def.required => {
+                    warnings.push(format!("missing required attribute '{}'", def.name))
+                }
+                Some(v) if !def.value_type.matches(v) => warnings.push(format!(
+                    "attribute '{}' should be {:?}",
+                    def.name, def.value_type
+                )),
+                _ => {}
+            }
+        }
+        let mut unknown: Vec<&String> = attributes
+            .keys()
+            .filter(|k| k.as_str() != "cost")
+            .filter(|k| !RESERVED_ATTRIBUTE_PREFIXES.iter().any(|p| k.starts_with(p)))
+            .filter(|k| !self.attributes.iter().any(|d| &d.name == *k))
+            .collect();
+        unknown.sort();
+        for k in unknown {
+            warnings.push(format!("unknown attribute '{}'", k));
+        }
+        warnings
+    }
+
+    /// Fill in the `cost` attribute from the cost formula, unless the span
+    /// already reports one. Spans of other kinds are returned unchanged.
+    pub fn apply_cost(&self, kind: SpanKind) -> SpanKind {
+        match (kind, &self.cost) {
+            (SpanKind::Custom { kind, mut attributes }, Some(formula)) if kind == self.name => {
+                if !attributes.contains_key("cost") {
+                    let cost = formula.evaluate(&attributes);
+                    if let Some(n) = serde_json::Number::from_f64(cost) {
+                        attributes.insert("cost".to_string(), serde_json::Value::Number(n));
+                    }
+                }
+                SpanKind::Custom { kind, attributes }
+            }
+            (kind, _) => kind,
+        }
+    }
+}
+
+// --- Machine registry ---
+
+/// A daemon that reports traces, as registered by its heartbeat. `id` is
+/// what its traces carry in `Trace::machine_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Machine {
+    pub id: String,
+    pub hostname: String,
+    pub os: String,
+    pub daemon_version: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MachineStatus {
+    /// Heard from within the last few heartbeats.
+    Online,
+    /// Missed heartbeats, but seen within the last day.
+    Stale,
+    Offline,
+}
+
+impl Machine {
+    /// Seconds since `last_seen` within which a machine counts as online.
+    pub const ONLINE_WINDOW_SECS: i64 = 300;
+    /// Seconds since `last_seen` within which a machine counts as stale
+    /// rather than offline.
+    pub const STALE_WINDOW_SECS: i64 = 86_400;
+
+    pub fn status_at(&self, now: DateTime<Utc>) -> MachineStatus {
+        let silent = (now - self.last_seen).num_seconds();
+        if silent <= Self::ONLINE_WINDOW_SECS {
+            MachineStatus::Online
+        } else if silent <= Self::STALE_WINDOW_SECS {
+            MachineStatus::Stale
+        } else {
+            MachineStatus::Offline
+        }
+    }
+}
+
+// --- Webhooks ---
+
+/// An outbound webhook subscribed to system events.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    #[schema(value_type = String)]
+    pub id: WebhookId,
+    pub url: String,
+    /// HMAC-SHA256 key for the `X-Traceway-Signature` header.
+    pub secret: String,
+    /// Event types to deliver (e.g. `span_failed`); empty means all.
+    #[serde(default)]
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn new(url: String, secret: String, events: Vec<String>) -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            url,
+            secret,
+            events,
+            enabled: true,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn wants(&self, event_type: &str) -> bool {
+        self.enabled && (self.events.is_empty() || self.events.iter().any(|e| e == event_type))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Succeeded,
+    /// Every attempt failed.
+    Failed,
+}
+
+/// The outcome of delivering one event to one webhook, after retries.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    #[schema(value_type = String)]
+    pub id: WebhookDeliveryId,
+    #[schema(value_type = String)]
+    pub webhook_id: WebhookId,
+    pub event_type: String,
+    /// Journal sequence of the event.
+    pub sequence: u64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// HTTP status of the last attempt, if it got a response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+}
+
+// --- Audit log ---
+
+/// A destructive or administrative action, as recorded in the org's audit
+/// log.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEvent {
+    #[schema(value_type = String)]
+    pub id: AuditEventId,
+    /// Who acted: `user:<id>`, `api_key:<prefix>`, or `local`.
+    pub actor: String,
+    /// What they did, e.g. `webhook.delete` or `config.update`.
+    pub action: String,
+    /// What it was done to, e.g. the webhook's id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    pub fn new(
+        actor: String,
+        action: &str,
+        target: Option<String>,
+        details: serde_json::Value,
+    ) -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            actor,
+            action: action.to_string(),
+            target,
+            details,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+// --- Annotations ---
+
+/// Feedback on a span's output, from a reviewer or a program.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Annotation {
+    #[schema(value_type = String)]
+    pub id: AnnotationId,
+    #[schema(value_type = String)]
+    pub span_id: SpanId,
+    #[schema(value_type = String)]
+    pub trace_id: TraceId,
+    /// Usually in `[0, 1]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// A category such as `hallucination` or `helpful`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Who annotated: `user:<id>`, `api_key:<prefix>`, or `local`.
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Annotation {
+    pub fn new(span_id: SpanId, trace_id: TraceId, created_by: String) -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            span_id,
+            trace_id,
+            score: None,
+            label: None,
+            comment: None,
+            created_by,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+// --- Saved views ---
+
+/// A named span query with how to display its results, shared across an
+/// org.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavedView {
+    #[schema(value_type = String)]
+    pub id: SavedViewId,
+    pub name: String,
+    /// In the span query language, e.g. `model:gpt-4o status:failed since:24h`.
+    pub query: String,
+    /// Columns to show, in order; empty means the default columns.
+    #[serde(default)]
+    pub columns: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_by: Option<String>,
+    /// `asc` or `desc`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<String>,
+    /// Who saved it: `user:<id>`, `api_key:<prefix>`, or `local`.
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SavedView {
+    pub fn new(name: String, query: String, created_by: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            name,
+            query,
+            columns: Vec::new(),
+            sort_by: None,
+            sort_order: None,
+            created_by,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
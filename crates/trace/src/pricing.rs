@@ -4,9 +4,14 @@
 //! When a model isn't found, we try prefix matching (e.g. "gpt-4o-2024-08-06"
 //! matches the "gpt-4o" entry). Returns None if no match is found so the caller
 //! can decide whether to leave cost as None or use a fallback.
+//!
+//! [`PricingTable`] layers user-configured overrides on top of the built-in
+//! table and knows which providers (e.g. Ollama) run models locally for free.
+
+use serde::{Deserialize, Serialize};
 
 /// Per-million-token pricing for a model.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ModelPricing {
     /// Price per 1M input tokens in USD
     pub input_per_mtok: f64,
@@ -384,6 +389,91 @@ pub fn lookup_pricing(model: &str) -> Option<ModelPricing> {
     None
 }
 
+/// Providers that serve models locally and never bill per token.
+const FREE_PROVIDERS: &[&str] = &["ollama"];
+
+/// Returns true if calls through this provider are always free.
+pub fn is_free_provider(provider: &str) -> bool {
+    FREE_PROVIDERS
+        .iter()
+        .any(|p| p.eq_ignore_ascii_case(provider))
+}
+
+/// Pricing table with per-model overrides layered over the built-in defaults.
+///
+/// Overrides use the same exact-then-prefix matching as the built-in table,
+/// and always win over it. An empty table behaves like [`estimate_cost`]
+/// except that free providers are priced at zero.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PricingTable {
+    #[serde(default)]
+    overrides: Vec<(String, ModelPricing)>,
+}
+
+impl PricingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace an override for a model name (or name prefix).
+    pub fn with_override(mut self, model: impl Into<String>, pricing: ModelPricing) -> Self {
+        self.set_override(model, pricing);
+        self
+    }
+
+    /// Add or replace an override for a model name (or name prefix).
+    pub fn set_override(&mut self, model: impl Into<String>, pricing: ModelPricing) {
+        let model = model.into().to_lowercase();
+        self.overrides.retain(|(m, _)| *m != model);
+        self.overrides.push((model, pricing));
+        // Keep longest prefixes first so prefix matching picks the most specific entry.
+        self.overrides.sort_by_key(|(m, _)| std::cmp::Reverse(m.len()));
+    }
+
+    /// Configured overrides, longest prefix first.
+    pub fn overrides(&self) -> &[(String, ModelPricing)] {
+        &self.overrides
+    }
+
+    /// Look up pricing for a model, checking overrides before the built-in table.
+    pub fn lookup(&self, model: &str) -> Option<ModelPricing> {
+        let model_lower = model.to_lowercase();
+        if let Some((_, pricing)) = self.overrides.iter().find(|(m, _)| *m == model_lower) {
+            return Some(*pricing);
+        }
+        if let Some((_, pricing)) = self
+            .overrides
+            .iter()
+            .find(|(m, _)| model_lower.starts_with(m.as_str()))
+        {
+            return Some(*pricing);
+        }
+        lookup_pricing(&model_lower)
+    }
+
+    /// Estimate cost in USD for a call. Free providers always cost 0.0 when
+    /// any token counts are known. Returns None if the model is unknown or no
+    /// tokens are provided.
+    pub fn estimate(
+        &self,
+        model: &str,
+        provider: Option<&str>,
+        input_tokens: Option<u64>,
+        output_tokens: Option<u64>,
+    ) -> Option<f64> {
+        let inp = input_tokens.unwrap_or(0) as f64;
+        let out = output_tokens.unwrap_or(0) as f64;
+        if inp == 0.0 && out == 0.0 {
+            return None;
+        }
+        if provider.is_some_and(is_free_provider) {
+            return Some(0.0);
+        }
+        let pricing = self.lookup(model)?;
+        Some((inp * pricing.input_per_mtok + out * pricing.output_per_mtok) / 1_000_000.0)
+    }
+}
+
 /// Estimate cost in USD from model name and token counts.
 /// Returns None if the model is not in the pricing table or no tokens are provided.
 pub fn estimate_cost(
@@ -450,6 +540,41 @@ mod tests {
         assert!(estimate_cost("gpt-4o", Some(0), Some(0)).is_none());
     }
 
+    #[test]
+    fn test_table_override_wins() {
+        let table = PricingTable::new().with_override(
+            "gpt-4o",
+            ModelPricing {
+                input_per_mtok: 1.0,
+                output_per_mtok: 2.0,
+            },
+        );
+        assert_eq!(table.lookup("gpt-4o-2024-08-06").unwrap().input_per_mtok, 1.0);
+        // Built-in entries that aren't overridden still resolve
+        assert_eq!(table.lookup("claude-3-haiku").unwrap().input_per_mtok, 0.25);
+    }
+
+    #[test]
+    fn test_table_custom_model() {
+        let table = PricingTable::new().with_override(
+            "My-Finetune",
+            ModelPricing {
+                input_per_mtok: 4.0,
+                output_per_mtok: 8.0,
+            },
+        );
+        let cost = table.estimate("my-finetune-v2", None, Some(1_000_000), Some(0)).unwrap();
+        assert!((cost - 4.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_table_free_provider() {
+        let table = PricingTable::new();
+        assert_eq!(table.estimate("llama3.2", Some("ollama"), Some(100), Some(50)), Some(0.0));
+        assert_eq!(table.estimate("llama3.2", Some("ollama"), None, None), None);
+        assert_eq!(table.estimate("llama3.2", Some("openai"), Some(100), Some(50)), None);
+    }
+
     #[test]
     fn test_case_insensitive() {
         assert!(lookup_pricing("GPT-4o").is_some());
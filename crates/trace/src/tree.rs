@@ -0,0 +1,473 @@
+//! A trace's spans arranged by `parent_id`, for waterfall views.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{Span, SpanId};
+
+/// A span with its children, ordered by start time.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SpanNode {
+    #[serde(flatten)]
+    pub span: Span,
+    /// 0 for roots.
+    pub depth: usize,
+    /// Duration not covered by any child. `None` while the span runs.
+    pub self_time_ms: Option<i64>,
+    /// The span's parent isn't in the trace (or its parents form a cycle),
+    /// so it was placed at the root.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub orphaned: bool,
+    #[schema(no_recursion)]
+    pub children: Vec<SpanNode>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct TraceTree {
+    pub roots: Vec<SpanNode>,
+    /// Spans placed at the root because their parent couldn't be found.
+    #[schema(value_type = Vec<String>)]
+    pub orphans: Vec<SpanId>,
+    pub span_count: usize,
+    pub max_depth: usize,
+}
+
+/// Timing summary of a trace's waterfall. Spans still running are measured
+/// up to when the summary was taken.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WaterfallStats {
+    /// First span start to last span end.
+    pub wall_time_ms: i64,
+    /// Length of the critical path: the wall time less any gaps where no
+    /// span was running.
+    pub critical_path_ms: i64,
+    /// Spans on the critical path, earliest first.
+    pub critical_path: Vec<PathSpan>,
+    /// Self time per span kind, most first. Sums to the total self time of
+    /// the trace, so nested spans aren't counted twice.
+    pub time_by_kind: Vec<KindTime>,
+    /// Total self time over critical path time: 1.0 when spans run one at
+    /// a time, higher the more they overlap. `None` when no time elapsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<f64>,
+    /// Longest spans, slowest first.
+    pub slowest: Vec<SlowSpan>,
+}
+
+/// A span on the critical path.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PathSpan {
+    #[schema(value_type = String)]
+    pub span_id: SpanId,
+    pub name: String,
+    pub kind: String,
+    /// Time the span itself adds to the path, outside the children the
+    /// path passes through.
+    pub time_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct KindTime {
+    pub kind: String,
+    pub span_count: usize,
+    pub self_time_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SlowSpan {
+    #[schema(value_type = String)]
+    pub span_id: SpanId,
+    pub name: String,
+    pub kind: String,
+    pub duration_ms: i64,
+    pub self_time_ms: i64,
+    pub running: bool,
+}
+
+impl TraceTree {
+    pub fn build(spans: impl IntoIterator<Item = Span>) -> Self {
+        let mut by_id: HashMap<SpanId, Span> = HashMap::new();
+        for span in spans {
+            by_id.insert(span.id(), span);
+        }
+        let mut children: HashMap<SpanId, Vec<SpanId>> = HashMap::new();
+        let mut roots = Vec::new();
+        let mut orphans = Vec::new();
+        for span in by_id.values() {
+            match span.parent_id() {
+                None => roots.push(span.id()),
+                Some(parent) if parent != span.id() && by_id.contains_key(&parent) => {
+                    children.entry(parent).or_default().push(span.id());
+                }
+                Some(_) => orphans.push(span.id()),
+            }
+        }
+        for ids in children.values_mut() {
+            sort_by_start(ids, &by_id);
+        }
+        roots.extend(orphans.iter().copied());
+        sort_by_start(&mut roots, &by_id);
+
+        // Spans whose parents loop never reach a root; cut each cycle at
+        // its earliest span.
+        let mut reached = HashSet::new();
+        let mut stack = roots.clone();
+        while let Some(id) = stack.pop() {
+            if reached.insert(id) {
+                stack.extend(children.get(&id).into_iter().flatten().copied());
+            }
+        }
+        let mut unreached: Vec<SpanId> = by_id
+            .keys()
+            .filter(|id| !reached.contains(*id))
+            .copied()
+            .collect();
+        sort_by_start(&mut unreached, &by_id);
+        for id in unreached {
+            if reached.contains(&id) {
+                continue;
+            }
+            let mut stack = vec![id];
+            while let Some(id) = stack.pop() {
+                if reached.insert(id) {
+                    stack.extend(children.get(&id).into_iter().flatten().copied());
+                }
+            }
+            // Detach from the cycle so the walk below terminates
+            for ids in children.values_mut() {
+                ids.retain(|child| *child != id);
+            }
+            orphans.push(id);
+            roots.push(id);
+        }
+        sort_by_start(&mut roots, &by_id);
+        sort_by_start(&mut orphans, &by_id);
+
+        let orphan_set: HashSet<SpanId> = orphans.iter().copied().collect();
+        let mut tree = TraceTree {
+            span_count: by_id.len(),
+            ..Default::default()
+        };
+        for id in roots {
+            let node = build_node(
+                id,
+                0,
+                &mut by_id,
+                &children,
+                &orphan_set,
+                &mut tree.max_depth,
+            );
+            tree.roots.extend(node);
+        }
+        tree.orphans = orphans;
+        tree
+    }
+}
+
+impl TraceTree {
+    /// Critical path, time per kind, parallelism, and the `slowest` longest
+    /// spans. Running spans count as ending at `now`.
+    pub fn stats(&self, now: DateTime<Utc>, slowest: usize) -> WaterfallStats {
+        let mut nodes: Vec<&SpanNode> = Vec::with_capacity(self.span_count);
+        let mut stack: Vec<&SpanNode> = self.roots.iter().collect();
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(&node.children);
+        }
+
+        let mut by_kind: HashMap<&str, KindTime> = HashMap::new();
+        let mut slow = Vec::with_capacity(nodes.len());
+        let mut self_total = 0;
+        for node in &nodes {
+            let span = &node.span;
+            let (start, end) = (span.started_at(), span_end(span, now));
+            // Not `node.self_time_ms`, which runs children to their
+            // parent's end rather than to `now`
+            let self_time = uncovered_ms(start, end, &node.children, now);
+            self_total += self_time;
+            let kind = by_kind
+                .entry(span.kind().kind_name())
+                .or_insert_with(|| KindTime {
+                    kind: span.kind().kind_name().to_string(),
+                    span_count: 0,
+                    self_time_ms: 0,
+                });
+            kind.span_count += 1;
+            kind.self_time_ms += self_time;
+            slow.push(SlowSpan {
+                span_id: span.id(),
+                name: span.name().to_string(),
+                kind: span.kind().kind_name().to_string(),
+                duration_ms: (end - start).num_milliseconds(),
+                self_time_ms: self_time,
+                running: span.ended_at().is_none(),
+            });
+        }
+        let mut time_by_kind: Vec<KindTime> = by_kind.into_values().collect();
+        time_by_kind.sort_by(|a, b| {
+            b.self_time_ms
+                .cmp(&a.self_time_ms)
+                .then_with(|| a.kind.cmp(&b.kind))
+        });
+        slow.sort_by_key(|s| (Reverse(s.duration_ms), s.span_id));
+        slow.truncate(slowest);
+
+        let first = nodes.iter().map(|n| n.span.started_at()).min();
+        let last = nodes.iter().map(|n| span_end(&n.span, now)).max();
+        let (wall_time_ms, critical_path) = match (first, last) {
+            (Some(first), Some(last)) => {
+                let mut path = Vec::new();
+                walk_critical_path(None, &self.roots, first, last, now, &mut path);
+                path.sort_by_key(|(span, _)| (span.started_at(), span.id()));
+                let path = path
+                    .into_iter()
+                    .map(|(span, time_ms)| PathSpan {
+                        span_id: span.id(),
+                        name: span.name().to_string(),
+                        kind: span.kind().kind_name().to_string(),
+                        time_ms,
+                    })
+                    .collect();
+                ((last - first).num_milliseconds(), path)
+            }
+            _ => (0, Vec::new()),
+        };
+        let critical_path_ms: i64 = critical_path.iter().map(|s| s.time_ms).sum();
+
+        WaterfallStats {
+            wall_time_ms,
+            critical_path_ms,
+            critical_path,
+            time_by_kind,
+            parallelism: (critical_path_ms > 0)
+                .then(|| self_total as f64 / critical_path_ms as f64),
+            slowest: slow,
+        }
+    }
+}
+
+/// When `span` ended, or `now` while it runs. Never before it started.
+fn span_end(span: &Span, now: DateTime<Utc>) -> DateTime<Utc> {
+    span.ended_at().unwrap_or(now).max(span.started_at())
+}
+
+/// Walk back from `end` to `start` through `children`, each step into the
+/// child that finishes last before the cursor, and credit the time no child
+/// covers to `owner`. With no owner (the trace itself) that time is idle
+/// and left off the path.
+fn walk_critical_path<'a>(
+    owner: Option<&'a Span>,
+    children: &'a [SpanNode],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    now: DateTime<Utc>,
+    path: &mut Vec<(&'a Span, i64)>,
+) {
+    let mut latest: Vec<&SpanNode> = children.iter().collect();
+    latest.sort_by_key(|c| Reverse(span_end(&c.span, now)));
+    let mut cursor = end;
+    let mut own = chrono::Duration::zero();
+    for child in latest {
+        let child_start = child.span.started_at().max(start);
+        if child_start >= cursor {
+            continue;
+        }
+        let child_end = span_end(&child.span, now).min(cursor);
+        own += cursor - child_end;
+        walk_critical_path(
+            Some(&child.span),
+            &child.children,
+            child_start,
+            child_end,
+            now,
+            path,
+        );
+        cursor = child_start;
+    }
+    own += cursor - start;
+    if let Some(span) = owner {
+        path.push((span, own.num_milliseconds().max(0)));
+    }
+}
+
+fn sort_by_start(ids: &mut [SpanId], spans: &HashMap<SpanId, Span>) {
+    ids.sort_by_key(|id| (spans.get(id).map(|s| s.started_at()), *id));
+}
+
+fn build_node(
+    id: SpanId,
+    depth: usize,
+    spans: &mut HashMap<SpanId, Span>,
+    children: &HashMap<SpanId, Vec<SpanId>>,
+    orphans: &HashSet<SpanId>,
+    max_depth: &mut usize,
+) -> Option<SpanNode> {
+    let span = spans.remove(&id)?;
+    *max_depth = (*max_depth).max(depth);
+    let children: Vec<SpanNode> = children
+        .get(&id)
+        .into_iter()
+        .flatten()
+        .filter_map(|child| build_node(*child, depth + 1, spans, children, orphans, max_depth))
+        .collect();
+    Some(SpanNode {
+        self_time_ms: self_time_ms(&span, &children),
+        orphaned: orphans.contains(&id),
+        depth,
+        span,
+        children,
+    })
+}
+
+/// The span's duration minus the union of its children's, each clipped to
+/// the span. Children still running count until the span's end.
+fn self_time_ms(span: &Span, children: &[SpanNode]) -> Option<i64> {
+    let end = span.ended_at()?;
+    Some(uncovered_ms(span.started_at(), end, children, end))
+}
+
+/// Time in `[start, end]` not covered by any of `children`, taking running
+/// children to end at `running_end`.
+fn uncovered_ms(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    children: &[SpanNode],
+    running_end: DateTime<Utc>,
+) -> i64 {
+    let mut intervals: Vec<(DateTime<Utc>, DateTime<Utc>)> = children
+        .iter()
+        .map(|c| {
+            let child_end = c.span.ended_at().unwrap_or(running_end);
+            (c.span.started_at().max(start), child_end.min(end))
+        })
+        .filter(|(s, e)| s < e)
+        .collect();
+    intervals.sort();
+    let mut covered = chrono::Duration::zero();
+    let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    for (s, e) in intervals {
+        match &mut current {
+            Some((_, cur_end)) if s <= *cur_end => *cur_end = (*cur_end).max(e),
+            _ => {
+                if let Some((cs, ce)) = current.replace((s, e)) {
+                    covered += ce - cs;
+                }
+            }
+        }
+    }
+    if let Some((cs, ce)) = current {
+        covered += ce - cs;
+    }
+    ((end - start) - covered).num_milliseconds().max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{SpanKind, SpanStatus};
+
+    fn span(id: u128, parent: Option<u128>, start_ms: i64, end_ms: Option<i64>) -> Span {
+        let at = |ms| Utc.timestamp_millis_opt(ms).unwrap();
+        Span::from_parts(
+            Uuid::from_u128(id),
+            Uuid::nil(),
+            None,
+            parent.map(Uuid::from_u128),
+            format!("s{id}"),
+            SpanKind::Custom {
+                kind: "step".to_string(),
+                attributes: Default::default(),
+            },
+            match end_ms {
+                Some(_) => SpanStatus::Completed,
+                None => SpanStatus::Running,
+            },
+            at(start_ms),
+            end_ms.map(at),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn nests_by_parent_and_computes_self_time() {
+        let tree = TraceTree::build(vec![
+            span(3, Some(1), 600, Some(900)),
+            span(1, None, 0, Some(1000)),
+            span(2, Some(1), 100, Some(700)),
+            span(4, Some(2), 200, None),
+        ]);
+        assert_eq!((tree.span_count, tree.max_depth), (4, 2));
+        assert!(tree.orphans.is_empty());
+        let root = &tree.roots[0];
+        let order: Vec<_> = root.children.iter().map(|c| c.span.name()).collect();
+        assert_eq!(order, ["s2", "s3"]);
+        // Children overlap 100..900, leaving 200ms of the root's own time
+        assert_eq!(root.self_time_ms, Some(200));
+        // A running child covers its parent to the parent's end
+        assert_eq!(root.children[0].self_time_ms, Some(100));
+        assert_eq!(root.children[0].children[0].depth, 2);
+        assert_eq!(root.children[0].children[0].self_time_ms, None);
+    }
+
+    #[test]
+    fn stats_follow_the_critical_path() {
+        // s1 fans out to s2 and s3 in parallel; s4 runs after s3 and is
+        // still going. s5 is a second root after a 100ms gap.
+        let tree = TraceTree::build(vec![
+            span(1, None, 0, Some(1000)),
+            span(2, Some(1), 100, Some(400)),
+            span(3, Some(1), 100, Some(600)),
+            span(4, Some(1), 650, None),
+            span(5, None, 1100, Some(1200)),
+        ]);
+        let now = Utc.timestamp_millis_opt(900).unwrap();
+        let stats = tree.stats(now, 2);
+        assert_eq!(stats.wall_time_ms, 1200);
+        assert_eq!(stats.critical_path_ms, 1100);
+        let path: Vec<_> = stats
+            .critical_path
+            .iter()
+            .map(|s| (s.name.as_str(), s.time_ms))
+            .collect();
+        // s4 runs to `now`, so s1 owns its last 100ms
+        assert_eq!(path, [("s1", 250), ("s3", 500), ("s4", 250), ("s5", 100)]);
+        assert_eq!(stats.time_by_kind.len(), 1);
+        assert_eq!(stats.time_by_kind[0].span_count, 5);
+        // s1 has 250ms to itself; the others have no children
+        assert_eq!(
+            stats.time_by_kind[0].self_time_ms,
+            250 + 300 + 500 + 250 + 100
+        );
+        assert_eq!(stats.parallelism, Some(1400.0 / 1100.0));
+        let slowest: Vec<_> = stats.slowest.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(slowest, ["s1", "s3"]);
+        assert!(!stats.slowest[0].running);
+    }
+
+    #[test]
+    fn missing_parents_and_cycles_become_orphan_roots() {
+        let tree = TraceTree::build(vec![
+            span(1, None, 0, Some(10)),
+            span(2, Some(99), 5, Some(8)),
+            span(3, Some(4), 20, Some(30)),
+            span(4, Some(3), 21, Some(25)),
+        ]);
+        let roots: Vec<_> = tree
+            .roots
+            .iter()
+            .map(|n| (n.span.name(), n.orphaned))
+            .collect();
+        assert_eq!(roots, [("s1", false), ("s2", true), ("s3", true)]);
+        assert_eq!(tree.orphans, [Uuid::from_u128(2), Uuid::from_u128(3)]);
+        assert_eq!(tree.roots[2].children[0].span.name(), "s4");
+        assert_eq!(tree.span_count, 4);
+    }
+}
@@ -253,8 +253,10 @@ export type SpanEvent =
 	| { type: 'trace_deleted'; trace_id: string }
 	| { type: 'file_version_created'; file: FileVersion }
 	| { type: 'dataset_created'; dataset: Dataset }
+	| { type: 'dataset_updated'; dataset: Dataset }
 	| { type: 'dataset_deleted'; dataset_id: string }
 	| { type: 'datapoint_created'; datapoint: Datapoint }
+	| { type: 'datapoint_updated'; datapoint: Datapoint }
 	| { type: 'queue_item_updated'; item: QueueItem }
 	| { type: 'eval_run_created'; run: EvalRun }
 	| { type: 'eval_run_updated'; run: EvalRun }
@@ -664,7 +666,8 @@ export type Scope =
 	| 'datasets_read'
 	| 'datasets_write'
 	| 'analytics_read'
-	| 'admin';
+	| 'admin'
+	| 'ingest';
 
 export interface AuthConfig {
 	mode: 'local' | 'cloud';